@@ -0,0 +1,433 @@
+// Read-only, no_std access to Fordex order book accounts
+//
+// A program that only needs a market's best bid and ask (a lending protocol
+// pricing collateral, say) can read them with this crate alone: it links
+// neither std nor solana-program, allocates nothing and logs nothing.
+// `OrderBookView` reads fields and resting orders straight from the account
+// data, so a reader pays for the handful of orders it inspects.
+//
+// The crate holds its own copy of the order book layout in `layout`, in
+// plain integers. The Fordex program's tests check the copy against the
+// program's layout, so a layout change that misses it fails them. Checking
+// the account's owner is left to the caller, whose account types this crate
+// does not know; the program's `reader::with_order_book` does it for
+// `AccountInfo`s.
+
+#![no_std]
+
+use arrayref::array_ref;
+
+// Copy of the parts of the order book and order layouts the reader uses
+pub mod layout {
+    // Layout version the copy matches, bumped with the program's
+    pub const LAYOUT_VERSION: u8 = 22;
+    pub const MAX_ORDERS: usize = 64;
+    pub const ORDER_BOOK_LEN: usize = 52_351;
+
+    pub const ORDER_BOOK_IS_INITIALIZED_OFFSET: usize = 0;
+    pub const ORDER_BOOK_BASE_MINT_OFFSET: usize = 1;
+    pub const ORDER_BOOK_QUOTE_MINT_OFFSET: usize = 33;
+    pub const ORDER_BOOK_TICK_SIZE_OFFSET: usize = 97;
+    pub const ORDER_BOOK_LOT_SIZE_OFFSET: usize = 105;
+    pub const ORDER_BOOK_BUY_COUNT_OFFSET: usize = 113;
+    pub const ORDER_BOOK_SELL_COUNT_OFFSET: usize = 117;
+    pub const ORDER_BOOK_LAST_TRADE_PRICE_OFFSET: usize = 419;
+    pub const ORDER_BOOK_DARK_POOL_OFFSET: usize = 431;
+    pub const ORDER_BOOK_SEQ_NUM_OFFSET: usize = 469;
+    pub const ORDER_BOOK_BOOK_CHECKSUM_OFFSET: usize = 1_019;
+    pub const ORDER_BOOK_BUY_ORDERS_OFFSET: usize = 1_039;
+    pub const ORDER_BOOK_SELL_ORDERS_OFFSET: usize = 17_167;
+    pub const ORDER_BOOK_BUY_LEVEL_COUNT_OFFSET: usize = 49_783;
+    pub const ORDER_BOOK_SELL_LEVEL_COUNT_OFFSET: usize = 49_787;
+    pub const ORDER_BOOK_BUY_LEVELS_OFFSET: usize = 49_791;
+    pub const ORDER_BOOK_SELL_LEVELS_OFFSET: usize = 51_071;
+
+    pub const ORDER_LEN: usize = 252;
+    pub const ORDER_TRADER_OFFSET: usize = 0;
+    pub const ORDER_AMOUNT_OFFSET: usize = 32;
+    pub const ORDER_PRICE_OFFSET: usize = 40;
+    pub const ORDER_TYPE_OFFSET: usize = 48;
+    pub const ORDER_UNIX_TIMESTAMP_OFFSET: usize = 57;
+    pub const ORDER_ID_OFFSET: usize = 65;
+    pub const ORDER_EXPIRES_AT_OFFSET: usize = 73;
+    pub const ORDER_PEG_OFFSET: usize = 89;
+    pub const ORDER_PEG_TICKS_OFFSET: usize = 90;
+    pub const ORDER_DECAY_START_PRICE_OFFSET: usize = 179;
+    pub const ORDER_DECAY_END_OFFSET: usize = 187;
+    pub const ORDER_EXPIRY_UNIT_OFFSET: usize = 203;
+
+    pub const PRICE_LEVEL_LEN: usize = 20;
+}
+
+use layout::*;
+
+// Define the ways an account fails to read as an order book
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReaderError {
+    // Written with a newer layout than this reader's copy
+    UnsupportedLayoutVersion,
+    // Written with an older layout of another size
+    OutdatedLayoutVersion,
+    // Too small to hold an order book
+    AccountDataTooSmall,
+    // The market is not initialized
+    UninitializedAccount,
+    // An order or price level count or an order's field is out of range
+    InvalidAccountData,
+    // No resting order at the given index
+    OrderNotFound,
+}
+
+// Define the side of an order, as the program's `OrderType`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+// Define the reference prices a pegged order can float against, as the
+// program's `PegType`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Peg {
+    None,
+    BestBid,
+    BestAsk,
+    Midpoint,
+}
+
+// Define the slot and time orders are read at, from the Clock sysvar
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Now {
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+// Define the resting orders of a side at one price, as the program's
+// `PriceLevel`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Level {
+    pub price: u64,
+    pub amount: u64,
+    pub order_count: u32,
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(*array_ref![data, offset, 8])
+}
+
+fn i64_at(data: &[u8], offset: usize) -> i64 {
+    i64::from_le_bytes(*array_ref![data, offset, 8])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(*array_ref![data, offset, 4])
+}
+
+// Define a borrowed view of a resting order's data
+#[derive(Clone, Copy, Debug)]
+pub struct OrderView<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> OrderView<'a> {
+    // Check the data holds an order with valid side, peg and expiry unit
+    pub fn new(data: &'a [u8]) -> Result<Self, ReaderError> {
+        if data.len() < ORDER_LEN
+            || data[ORDER_TYPE_OFFSET] > 1
+            || data[ORDER_PEG_OFFSET] > 3
+            || data[ORDER_EXPIRY_UNIT_OFFSET] > 1
+        {
+            return Err(ReaderError::InvalidAccountData);
+        }
+        Ok(OrderView { data })
+    }
+
+    pub fn trader(&self) -> [u8; 32] {
+        *array_ref![self.data, ORDER_TRADER_OFFSET, 32]
+    }
+
+    pub fn amount(&self) -> u64 {
+        u64_at(self.data, ORDER_AMOUNT_OFFSET)
+    }
+
+    pub fn price(&self) -> u64 {
+        u64_at(self.data, ORDER_PRICE_OFFSET)
+    }
+
+    pub fn side(&self) -> Side {
+        match self.data[ORDER_TYPE_OFFSET] {
+            0 => Side::Buy,
+            _ => Side::Sell,
+        }
+    }
+
+    pub fn order_id(&self) -> u64 {
+        u64_at(self.data, ORDER_ID_OFFSET)
+    }
+
+    pub fn peg(&self) -> Peg {
+        match self.data[ORDER_PEG_OFFSET] {
+            0 => Peg::None,
+            1 => Peg::BestBid,
+            2 => Peg::BestAsk,
+            _ => Peg::Midpoint,
+        }
+    }
+
+    pub fn peg_ticks(&self) -> i64 {
+        i64_at(self.data, ORDER_PEG_TICKS_OFFSET)
+    }
+
+    // Whether the order has expired at `now`, by time or by slot
+    pub fn is_expired(&self, now: Now) -> bool {
+        let expires_at = i64_at(self.data, ORDER_EXPIRES_AT_OFFSET);
+        expires_at != 0
+            && match self.data[ORDER_EXPIRY_UNIT_OFFSET] {
+                0 => now.unix_timestamp >= expires_at,
+                _ => now.slot >= expires_at as u64,
+            }
+    }
+
+    // Price a fixed-price order is listed at. See `Order::current_price`.
+    pub fn current_price(&self, now: Now, tick_size: u64) -> u64 {
+        let decay_start_price = u64_at(self.data, ORDER_DECAY_START_PRICE_OFFSET);
+        let decay_end = i64_at(self.data, ORDER_DECAY_END_OFFSET);
+        if decay_start_price == 0 || now.unix_timestamp >= decay_end {
+            return self.price();
+        }
+        let placed_at = i64_at(self.data, ORDER_UNIX_TIMESTAMP_OFFSET);
+        let elapsed = now.unix_timestamp.saturating_sub(placed_at).max(0) as u128;
+        let duration = (decay_end - placed_at).max(1) as u128;
+        let range = decay_start_price.saturating_sub(self.price()) as u128;
+        let decayed = decay_start_price - (range * elapsed / duration) as u64;
+        decayed.div_ceil(tick_size) * tick_size
+    }
+
+    // Price the order trades at given the best fixed bid and ask of its
+    // book. See `OrderBook::effective_price`.
+    pub fn effective_price(
+        &self,
+        (best_bid, best_ask): (Option<u64>, Option<u64>),
+        tick_size: u64,
+        now: Now,
+    ) -> Option<u64> {
+        let reference = match self.peg() {
+            Peg::None if u64_at(self.data, ORDER_DECAY_START_PRICE_OFFSET) > 0 => {
+                let floor = best_bid.map_or(0, |bid| bid + tick_size);
+                return Some(self.current_price(now, tick_size).max(floor));
+            }
+            Peg::None => return Some(self.price()),
+            Peg::BestBid => best_bid? as i128,
+            Peg::BestAsk => best_ask? as i128,
+            Peg::Midpoint => (best_bid? as i128 + best_ask? as i128) / 2,
+        };
+        let tick = tick_size as i128;
+        let pegged = reference + self.peg_ticks() as i128 * tick;
+        let price = match self.side() {
+            Side::Buy => {
+                let ceiling = best_ask.map_or(i128::MAX, |ask| ask as i128 - tick);
+                (pegged.div_euclid(tick) * tick)
+                    .min(self.price() as i128)
+                    .min(ceiling)
+            }
+            Side::Sell => {
+                let floor = best_bid.map_or(0, |bid| bid as i128 + tick);
+                ((pegged + tick - 1).div_euclid(tick) * tick)
+                    .max(self.price() as i128)
+                    .max(floor)
+            }
+        };
+        u64::try_from(price).ok().filter(|price| *price > 0)
+    }
+}
+
+// Define a borrowed view of an initialized order book account's data
+#[derive(Clone, Copy, Debug)]
+pub struct OrderBookView<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> OrderBookView<'a> {
+    // Check the data holds an initialized order book of the copied layout,
+    // reading the version before the size as the program does
+    pub fn new(data: &'a [u8]) -> Result<Self, ReaderError> {
+        match data.get(ORDER_BOOK_IS_INITIALIZED_OFFSET).copied() {
+            Some(version) if version > LAYOUT_VERSION => {
+                return Err(ReaderError::UnsupportedLayoutVersion);
+            }
+            Some(version)
+                if version != 0 && version < LAYOUT_VERSION && data.len() != ORDER_BOOK_LEN =>
+            {
+                return Err(ReaderError::OutdatedLayoutVersion);
+            }
+            _ => {}
+        }
+        if data.len() < ORDER_BOOK_LEN {
+            return Err(ReaderError::AccountDataTooSmall);
+        }
+        let view = OrderBookView { data };
+        if view.layout_version() == 0 {
+            return Err(ReaderError::UninitializedAccount);
+        }
+        if view.order_count(Side::Buy) > MAX_ORDERS
+            || view.order_count(Side::Sell) > MAX_ORDERS
+            || view.level_count(Side::Buy) > MAX_ORDERS
+            || view.level_count(Side::Sell) > MAX_ORDERS
+        {
+            return Err(ReaderError::InvalidAccountData);
+        }
+        Ok(view)
+    }
+
+    // Layout version the account was last written with
+    pub fn layout_version(&self) -> u8 {
+        self.data[ORDER_BOOK_IS_INITIALIZED_OFFSET]
+    }
+
+    pub fn base_mint(&self) -> [u8; 32] {
+        *array_ref![self.data, ORDER_BOOK_BASE_MINT_OFFSET, 32]
+    }
+
+    pub fn quote_mint(&self) -> [u8; 32] {
+        *array_ref![self.data, ORDER_BOOK_QUOTE_MINT_OFFSET, 32]
+    }
+
+    pub fn tick_size(&self) -> u64 {
+        u64_at(self.data, ORDER_BOOK_TICK_SIZE_OFFSET)
+    }
+
+    pub fn lot_size(&self) -> u64 {
+        u64_at(self.data, ORDER_BOOK_LOT_SIZE_OFFSET)
+    }
+
+    pub fn last_trade_price(&self) -> u64 {
+        u64_at(self.data, ORDER_BOOK_LAST_TRADE_PRICE_OFFSET)
+    }
+
+    pub fn seq_num(&self) -> u64 {
+        u64_at(self.data, ORDER_BOOK_SEQ_NUM_OFFSET)
+    }
+
+    pub fn book_checksum(&self) -> u64 {
+        u64_at(self.data, ORDER_BOOK_BOOK_CHECKSUM_OFFSET)
+    }
+
+    pub fn dark_pool(&self) -> bool {
+        self.data[ORDER_BOOK_DARK_POOL_OFFSET] != 0
+    }
+
+    // Number of resting orders on a side
+    pub fn order_count(&self, side: Side) -> usize {
+        let offset = match side {
+            Side::Buy => ORDER_BOOK_BUY_COUNT_OFFSET,
+            Side::Sell => ORDER_BOOK_SELL_COUNT_OFFSET,
+        };
+        u32_at(self.data, offset) as usize
+    }
+
+    // Offset of the resting order at `index` of a side
+    pub fn order_offset(side: Side, index: usize) -> usize {
+        let side_offset = match side {
+            Side::Buy => ORDER_BOOK_BUY_ORDERS_OFFSET,
+            Side::Sell => ORDER_BOOK_SELL_ORDERS_OFFSET,
+        };
+        side_offset + index * ORDER_LEN
+    }
+
+    // View the resting order at `index` of a side
+    pub fn order(&self, side: Side, index: usize) -> Result<OrderView<'a>, ReaderError> {
+        if index >= self.order_count(side) {
+            return Err(ReaderError::OrderNotFound);
+        }
+        OrderView::new(&self.data[Self::order_offset(side, index)..])
+    }
+
+    // View the resting orders of a side one at a time, in book order
+    pub fn orders(
+        &self,
+        side: Side,
+    ) -> impl Iterator<Item = Result<OrderView<'a>, ReaderError>> + 'a {
+        let view = *self;
+        (0..self.order_count(side)).map(move |index| view.order(side, index))
+    }
+
+    // Number of price levels in the index of a side
+    pub fn level_count(&self, side: Side) -> usize {
+        let offset = match side {
+            Side::Buy => ORDER_BOOK_BUY_LEVEL_COUNT_OFFSET,
+            Side::Sell => ORDER_BOOK_SELL_LEVEL_COUNT_OFFSET,
+        };
+        u32_at(self.data, offset) as usize
+    }
+
+    // Read the price levels of a side from the index, best first
+    pub fn levels(&self, side: Side) -> impl Iterator<Item = Level> + 'a {
+        let offset = match side {
+            Side::Buy => ORDER_BOOK_BUY_LEVELS_OFFSET,
+            Side::Sell => ORDER_BOOK_SELL_LEVELS_OFFSET,
+        };
+        let data = self.data;
+        (0..self.level_count(side)).map(move |i| {
+            let start = offset + i * PRICE_LEVEL_LEN;
+            Level {
+                price: u64_at(data, start),
+                amount: u64_at(data, start + 8),
+                order_count: u32_at(data, start + 16),
+            }
+        })
+    }
+
+    // Best live bid: the highest price a resting buy order trades at, pegged
+    // orders and Dutch auctions priced as the matcher prices them. A dark
+    // pool market reports no best orders.
+    pub fn best_bid(&self, now: Now) -> Result<Option<u64>, ReaderError> {
+        self.best_price(Side::Buy, now)
+    }
+
+    // Best live ask, the lowest price a resting sell order trades at
+    pub fn best_ask(&self, now: Now) -> Result<Option<u64>, ReaderError> {
+        self.best_price(Side::Sell, now)
+    }
+
+    fn best_price(&self, side: Side, now: Now) -> Result<Option<u64>, ReaderError> {
+        if self.dark_pool() {
+            return Ok(None);
+        }
+        let references = (
+            self.best_fixed_price(Side::Buy, now)?,
+            self.best_fixed_price(Side::Sell, now)?,
+        );
+        let mut best = None;
+        for order in self.orders(side) {
+            let order = order?;
+            if order.is_expired(now) {
+                continue;
+            }
+            if let Some(price) = order.effective_price(references, self.tick_size(), now) {
+                best = Some(better_price(side, best, price));
+            }
+        }
+        Ok(best)
+    }
+
+    // Best live fixed-price order on a side, the reference of pegged orders
+    fn best_fixed_price(&self, side: Side, now: Now) -> Result<Option<u64>, ReaderError> {
+        let mut best = None;
+        for order in self.orders(side) {
+            let order = order?;
+            if order.peg() == Peg::None && !order.is_expired(now) {
+                let price = order.current_price(now, self.tick_size());
+                best = Some(better_price(side, best, price));
+            }
+        }
+        Ok(best)
+    }
+}
+
+fn better_price(side: Side, best: Option<u64>, price: u64) -> u64 {
+    match (side, best) {
+        (_, None) => price,
+        (Side::Buy, Some(best)) => best.max(price),
+        (Side::Sell, Some(best)) => best.min(price),
+    }
+}
@@ -0,0 +1,81 @@
+// Start solana-test-validator with Fordex deployed and seed a market on it
+//
+//     cargo run --features localnet --bin fordex-localnet -- \
+//         target/deploy/fordex.so [fixtures/localnet.txt]
+//
+// The validator keeps running, with its ledger reset on every start, until
+// the command is interrupted.
+
+use fordex::localnet::{self, Fixture};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{commitment_config::CommitmentConfig, signer::Signer};
+use std::{
+    env,
+    process::{self, Command},
+    thread,
+    time::Duration,
+};
+
+const RPC_URL: &str = "http://127.0.0.1:8899";
+const DEFAULT_FIXTURE: &str = "fixtures/localnet.txt";
+const STARTUP_ATTEMPTS: u32 = 120;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some(program) = args.first() else {
+        eprintln!("usage: fordex-localnet <program.so> [fixture]");
+        process::exit(2);
+    };
+    let fixture_path = args.get(1).map_or(DEFAULT_FIXTURE, String::as_str);
+    let fixture = std::fs::read_to_string(fixture_path)
+        .map_err(|err| err.to_string())
+        .and_then(|source| Fixture::parse(&source))
+        .unwrap_or_else(|err| {
+            eprintln!("invalid fixture {}: {}", fixture_path, err);
+            process::exit(1);
+        });
+
+    let program_id = fordex::id();
+    let mut validator = Command::new("solana-test-validator")
+        .args(["--reset", "--quiet", "--upgradeable-program"])
+        .arg(program_id.to_string())
+        .arg(program)
+        .arg(localnet::authority().pubkey().to_string())
+        .spawn()
+        .unwrap_or_else(|err| {
+            eprintln!("failed to start solana-test-validator: {}", err);
+            process::exit(1);
+        });
+
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL, CommitmentConfig::confirmed());
+    let mut attempts = 0;
+    while rpc_client.get_health().is_err() {
+        attempts += 1;
+        if attempts == STARTUP_ATTEMPTS {
+            eprintln!("solana-test-validator did not become healthy");
+            let _ = validator.kill();
+            process::exit(1);
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+
+    let market = localnet::seed_market(&rpc_client, &program_id, &fixture).unwrap_or_else(|err| {
+        eprintln!("failed to seed the market: {}", err);
+        let _ = validator.kill();
+        process::exit(1);
+    });
+    println!("rpc         {}", RPC_URL);
+    println!("program     {}", program_id);
+    println!("market      {}", market.order_book);
+    println!("base mint   {}", market.base_mint);
+    println!("quote mint  {}", market.quote_mint);
+    for trader in &market.traders {
+        println!("trader      {} {}", trader.name, trader.wallet);
+    }
+    println!(
+        "{} orders resting; press Ctrl-C to stop",
+        fixture.orders.len()
+    );
+
+    let _ = validator.wait();
+}
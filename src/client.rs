@@ -0,0 +1,1991 @@
+// Typed instruction builders for clients and programs calling Fordex
+//
+// Each instruction takes its accounts as a struct naming every account in
+// the order the processor reads them, so callers never assemble AccountMeta
+// vectors by hand. Programs calling Fordex through CPI pass the built
+// Instruction to `invoke` along with the matching AccountInfos.
+//
+// The program reads the Clock and Rent sysvars from the runtime wherever an
+// instruction's account list allows it, and the builders leave those sysvar
+// accounts out. `required_sysvars` names the sysvar accounts an instruction
+// still takes.
+//
+// Transactions that match against several makers reference many accounts.
+// `market_lookup_addresses` lists a market's static accounts for an address
+// lookup table, and `versioned_transaction` builds v0 transactions loading
+// them from it.
+//
+// Large orders can exceed the default compute unit limit. `ComputeBudget`
+// estimates the units of an instruction that matches orders and builds the
+// ComputeBudget instructions to prepend, as `place_order_with_budget` does.
+//
+// Trade feeds subscribed to the program's logs turn them into FordexEvent
+// values with `decode_log_events`.
+//
+// PlaceOrder returns a FillPreview of its outcome, so `preview_place_order`
+// quotes an order, fees included, by simulating it. `dry_run` wraps any
+// instruction so it rolls back even if sent, and `simulate_dry_run` returns
+// what the wrapped instruction would return or the error it would fail with.
+//
+// Chunked queries return one page per call. `export_book_orders` and
+// `open_orders_of` stream the whole result, simulating the query once per
+// page and following the cursor until the last page.
+
+use crate::{
+    instruction::OrderBookInstruction,
+    math::RoundingPolicy,
+    state::{
+        Blocklist, CandleSeries, Config, FillPreview, FillReceipt, FordexEvent, KeeperBond,
+        LaunchAuction, ListingRequirements, MarketRegistryEntry, OpenOrders, OracleSource, Order,
+        OrderBook, OrderType, PriceOracle, TradeHistory,
+    },
+};
+use solana_program::{
+    address_lookup_table::{self, AddressLookupTableAccount},
+    clock::{Slot, UnixTimestamp},
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    message::{v0, CompileError, VersionedMessage},
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+use spl_associated_token_account::get_associated_token_address;
+#[cfg(not(target_os = "solana"))]
+use {
+    crate::{
+        error::OrderBookError,
+        state::{Page, PageItem},
+    },
+    base64::{prelude::BASE64_STANDARD, Engine},
+    futures::{stream, Stream, TryStreamExt},
+    solana_client::{
+        client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+        nonblocking::rpc_client::RpcClient as NonblockingRpcClient,
+        rpc_client::RpcClient,
+        rpc_config::RpcSimulateTransactionConfig,
+        rpc_response::RpcSimulateTransactionResult,
+    },
+    solana_program::{message::Message, program_error::ProgramError},
+    solana_sdk::{
+        compute_budget::ComputeBudgetInstruction,
+        instruction::InstructionError,
+        signer::{Signer, SignerError},
+        transaction::{Transaction, TransactionError, VersionedTransaction},
+    },
+};
+
+fn instruction(
+    program_id: &Pubkey,
+    accounts: Vec<AccountMeta>,
+    data: OrderBookInstruction,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.pack(),
+    }
+}
+
+fn vault_signer(program_id: &Pubkey, order_book: &Pubkey) -> Pubkey {
+    OrderBook::find_vault_signer(program_id, order_book).0
+}
+
+// Sysvar accounts the instruction still requires in its account list, in
+// order
+pub fn required_sysvars(instruction: &OrderBookInstruction) -> Vec<Pubkey> {
+    instruction
+        .expected_accounts()
+        .iter()
+        .filter_map(|spec| match spec.name {
+            "clock" => Some(sysvar::clock::id()),
+            "rent" => Some(sysvar::rent::id()),
+            _ => None,
+        })
+        .collect()
+}
+
+// Define the trailing accounts of the instructions that match orders: the
+// market's TradeHistory and CandleSeries PDAs, followed by the OpenOrders
+// accounts of the makers the order fills. The Config PDA, which tells whether
+// the protocol is paused, precedes them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MatchingAccounts {
+    pub trade_history: Option<Pubkey>,
+    pub candle_series: Vec<Pubkey>,
+    pub maker_open_orders: Vec<Pubkey>,
+}
+
+impl MatchingAccounts {
+    // Trailing accounts for a market, taking its feeds from the order book
+    pub fn new(order_book: &OrderBook, maker_open_orders: Vec<Pubkey>) -> Self {
+        MatchingAccounts {
+            trade_history: Some(order_book.trade_history).filter(|key| key != &Pubkey::default()),
+            candle_series: order_book.candle_series.clone(),
+            maker_open_orders,
+        }
+    }
+
+    fn metas(&self, program_id: &Pubkey) -> impl Iterator<Item = AccountMeta> + '_ {
+        let config = AccountMeta::new_readonly(Config::find_address(program_id).0, false);
+        std::iter::once(config).chain(self.market_metas())
+    }
+
+    // The feeds and makers, without the Config PDA
+    fn market_metas(&self) -> impl Iterator<Item = AccountMeta> + '_ {
+        self.trade_history
+            .iter()
+            .chain(&self.candle_series)
+            .chain(&self.maker_open_orders)
+            .map(|key| AccountMeta::new(*key, false))
+    }
+}
+
+// Define the ComputeBudget settings of a transaction
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ComputeBudget {
+    // Compute unit limit, where 0 keeps the runtime default
+    pub unit_limit: u32,
+    // Priority fee in micro-lamports per compute unit
+    pub unit_price: u64,
+}
+
+impl ComputeBudget {
+    // Compute units an order uses before matching and per maker it fills
+    pub const BASE_UNITS: u32 = 30_000;
+    pub const UNITS_PER_FILL: u32 = 20_000;
+    // Compute units of recording one fill in a trade history or candle feed
+    pub const UNITS_PER_FEED_RECORD: u32 = 2_500;
+    pub const MAX_UNIT_LIMIT: u32 = 1_400_000;
+
+    // Budget for an instruction that may fill against every maker in
+    // `matching`, with a quarter of headroom over the estimate
+    pub fn for_matching(matching: &MatchingAccounts, unit_price: u64) -> Self {
+        let fills = matching.maker_open_orders.len().max(1) as u32;
+        let feeds = matching.trade_history.iter().count() + matching.candle_series.len();
+        let per_fill = Self::UNITS_PER_FILL + feeds as u32 * Self::UNITS_PER_FEED_RECORD;
+        let estimate = Self::BASE_UNITS.saturating_add(fills.saturating_mul(per_fill));
+        ComputeBudget {
+            unit_limit: estimate
+                .saturating_add(estimate / 4)
+                .min(Self::MAX_UNIT_LIMIT),
+            unit_price,
+        }
+    }
+
+    // ComputeBudget instructions applying these settings
+    #[cfg(not(target_os = "solana"))]
+    pub fn instructions(&self) -> Vec<Instruction> {
+        let mut instructions = Vec::with_capacity(2);
+        if self.unit_limit > 0 {
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(
+                self.unit_limit,
+            ));
+        }
+        if self.unit_price > 0 {
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_price(
+                self.unit_price,
+            ));
+        }
+        instructions
+    }
+
+    // Prefix `instruction` with the ComputeBudget instructions
+    #[cfg(not(target_os = "solana"))]
+    pub fn prepend(&self, instruction: Instruction) -> Vec<Instruction> {
+        let mut instructions = self.instructions();
+        instructions.push(instruction);
+        instructions
+    }
+}
+
+// Define the accounts of PlaceOrder; `trader_token` and `vault` are the
+// trader's token account and the market vault of the currency the order
+// locks: quote for a bid, base for an ask. `order_gate_signer` is the gate
+// program's signer PDA a gated market requires, and `blocklist` the
+// Blocklist of a market that enforces one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlaceOrderAccounts {
+    pub order_book: Pubkey,
+    pub trader: Pubkey,
+    pub open_orders: Pubkey,
+    pub trader_token: Pubkey,
+    pub vault: Pubkey,
+    pub order_gate_signer: Option<Pubkey>,
+    pub blocklist: Option<Pubkey>,
+    pub matching: MatchingAccounts,
+}
+
+pub fn place_order(
+    program_id: &Pubkey,
+    accounts: &PlaceOrderAccounts,
+    order: Order,
+) -> Instruction {
+    let mut metas = vec![
+        AccountMeta::new(accounts.order_book, false),
+        AccountMeta::new(accounts.trader, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new(accounts.open_orders, false),
+        AccountMeta::new(accounts.trader_token, false),
+        AccountMeta::new(accounts.vault, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(Config::find_address(program_id).0, false),
+    ];
+    if let Some(order_gate_signer) = accounts.order_gate_signer {
+        metas.push(AccountMeta::new_readonly(order_gate_signer, true));
+    }
+    if let Some(blocklist) = accounts.blocklist {
+        metas.push(AccountMeta::new_readonly(blocklist, false));
+    }
+    metas.extend(accounts.matching.market_metas());
+    instruction(program_id, metas, OrderBookInstruction::PlaceOrder(order))
+}
+
+// Build a PlaceOrder preceded by ComputeBudget instructions sized for the
+// makers it may fill, paying `unit_price` micro-lamports per compute unit
+#[cfg(not(target_os = "solana"))]
+pub fn place_order_with_budget(
+    program_id: &Pubkey,
+    accounts: &PlaceOrderAccounts,
+    order: Order,
+    unit_price: u64,
+) -> Vec<Instruction> {
+    ComputeBudget::for_matching(&accounts.matching, unit_price)
+        .prepend(place_order(program_id, accounts, order))
+}
+
+// Define a market of a RouteOrder: its order book, its vault of the currency
+// the order locks and the trailing accounts of its matching
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteMarket {
+    pub order_book: Pubkey,
+    pub vault: Pubkey,
+    pub matching: MatchingAccounts,
+}
+
+// Define the accounts of RouteOrder; `trader_token` is the trader's token
+// account of the currency the order locks
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteOrderAccounts {
+    pub trader: Pubkey,
+    pub open_orders: Pubkey,
+    pub trader_token: Pubkey,
+    pub markets: Vec<RouteMarket>,
+}
+
+// Fill up to `amount` across the markets, best price first, at or through
+// `limit_price`, failing unless at least `min_fill_amount` fills
+pub fn route_order(
+    program_id: &Pubkey,
+    accounts: &RouteOrderAccounts,
+    side: OrderType,
+    amount: u64,
+    limit_price: u64,
+    min_fill_amount: u64,
+) -> Instruction {
+    let mut metas = vec![
+        AccountMeta::new(accounts.trader, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new(accounts.open_orders, false),
+        AccountMeta::new(accounts.trader_token, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(Config::find_address(program_id).0, false),
+    ];
+    let mut market_account_counts = Vec::with_capacity(accounts.markets.len());
+    for market in &accounts.markets {
+        let start = metas.len();
+        metas.push(AccountMeta::new(market.order_book, false));
+        metas.push(AccountMeta::new(market.vault, false));
+        metas.extend(market.matching.market_metas());
+        market_account_counts.push((metas.len() - start) as u8);
+    }
+    instruction(
+        program_id,
+        metas,
+        OrderBookInstruction::RouteOrder {
+            side,
+            amount,
+            limit_price,
+            min_fill_amount,
+            market_account_counts,
+        },
+    )
+}
+
+// Simulate a PlaceOrder against the cluster's latest blockhash, without
+// signatures, and decode the FillPreview it returns as a pre-trade quote
+#[cfg(not(target_os = "solana"))]
+#[allow(clippy::result_large_err)]
+pub fn preview_place_order(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey,
+    accounts: &PlaceOrderAccounts,
+    order: Order,
+) -> ClientResult<FillPreview> {
+    let transaction = Transaction::new_unsigned(Message::new(
+        &[place_order(program_id, accounts, order)],
+        Some(&accounts.trader),
+    ));
+    let config = RpcSimulateTransactionConfig {
+        sig_verify: false,
+        replace_recent_blockhash: true,
+        ..RpcSimulateTransactionConfig::default()
+    };
+    let simulation = rpc_client
+        .simulate_transaction_with_config(&transaction, config)?
+        .value;
+    let data = return_data(program_id, simulation, "PlaceOrder")?;
+    FillPreview::unpack(&data).map_err(|err| ClientErrorKind::Custom(err.to_string()).into())
+}
+
+// Wrap an instruction in a DryRun, which runs it and then rolls it back
+pub fn dry_run(mut instruction: Instruction) -> Instruction {
+    instruction
+        .data
+        .insert(0, OrderBookInstruction::DRY_RUN_TAG);
+    instruction
+}
+
+// Simulate a dry run of `instruction` with fee payer `payer`, returning the
+// return data it set, or the error it failed with
+#[cfg(not(target_os = "solana"))]
+#[allow(clippy::result_large_err)]
+pub fn simulate_dry_run(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    instruction: Instruction,
+) -> ClientResult<Option<Vec<u8>>> {
+    let transaction = Transaction::new_unsigned(Message::new(&[dry_run(instruction)], Some(payer)));
+    let config = RpcSimulateTransactionConfig {
+        sig_verify: false,
+        replace_recent_blockhash: true,
+        ..RpcSimulateTransactionConfig::default()
+    };
+    let mut simulation = rpc_client
+        .simulate_transaction_with_config(&transaction, config)?
+        .value;
+    match simulation.err.take() {
+        Some(TransactionError::InstructionError(_, InstructionError::Custom(code)))
+            if OrderBookError::from_code(code) == Some(OrderBookError::DryRunComplete) => {}
+        Some(err) => return Err(err.into()),
+        None => return Err(ClientErrorKind::Custom("DryRun did not roll back".to_string()).into()),
+    }
+    if simulation.return_data.is_none() {
+        return Ok(None);
+    }
+    return_data(program_id, simulation, "DryRun").map(Some)
+}
+
+// Decode the return data `program_id` set in a simulation of `name`
+#[cfg(not(target_os = "solana"))]
+#[allow(clippy::result_large_err)]
+fn return_data(
+    program_id: &Pubkey,
+    simulation: RpcSimulateTransactionResult,
+    name: &str,
+) -> ClientResult<Vec<u8>> {
+    if let Some(err) = simulation.err {
+        return Err(err.into());
+    }
+    let return_data = simulation
+        .return_data
+        .filter(|return_data| return_data.program_id == program_id.to_string())
+        .ok_or_else(|| ClientErrorKind::Custom(format!("{} set no return data", name)))?;
+    Ok(BASE64_STANDARD
+        .decode(&return_data.data.0)
+        .map_err(|err| ClientErrorKind::Custom(err.to_string()))?)
+}
+
+// Simulate one page of a chunked query with fee payer `payer`
+#[cfg(not(target_os = "solana"))]
+pub async fn query_page<T: PageItem>(
+    rpc_client: &NonblockingRpcClient,
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    query: Instruction,
+) -> ClientResult<Page<T>> {
+    let transaction = Transaction::new_unsigned(Message::new(&[query], Some(payer)));
+    let config = RpcSimulateTransactionConfig {
+        sig_verify: false,
+        replace_recent_blockhash: true,
+        ..RpcSimulateTransactionConfig::default()
+    };
+    let simulation = rpc_client
+        .simulate_transaction_with_config(&transaction, config)
+        .await?
+        .value;
+    let data = return_data(program_id, simulation, "Query")?;
+    Page::unpack(&data).map_err(|err| ClientErrorKind::Custom(err.to_string()).into())
+}
+
+// Stream every item of a chunked query, simulating the instruction `query`
+// builds for each cursor until the last page
+#[cfg(not(target_os = "solana"))]
+pub fn query_pages<'a, T: PageItem + 'a>(
+    rpc_client: &'a NonblockingRpcClient,
+    program_id: &'a Pubkey,
+    payer: &'a Pubkey,
+    query: impl Fn(u32) -> Instruction + 'a,
+) -> impl Stream<Item = ClientResult<T>> + 'a {
+    stream::try_unfold(Some(0), move |cursor| {
+        let page =
+            cursor.map(|cursor| query_page::<T>(rpc_client, program_id, payer, query(cursor)));
+        async move {
+            let Some(page) = page else {
+                return Ok(None);
+            };
+            let page = page.await?;
+            Ok::<_, ClientError>(Some((
+                stream::iter(page.items.into_iter().map(Ok)),
+                page.next_cursor,
+            )))
+        }
+    })
+    .try_flatten()
+}
+
+// Stream the resting orders of a market through ExportBook
+#[cfg(not(target_os = "solana"))]
+pub fn export_book_orders<'a>(
+    rpc_client: &'a NonblockingRpcClient,
+    program_id: &'a Pubkey,
+    order_book: &'a Pubkey,
+    payer: &'a Pubkey,
+) -> impl Stream<Item = ClientResult<Order>> + 'a {
+    query_pages(rpc_client, program_id, payer, move |cursor| {
+        export_book(program_id, order_book, cursor)
+    })
+}
+
+// Stream the orders of `trader` on a market through GetOpenOrders
+#[cfg(not(target_os = "solana"))]
+pub fn open_orders_of<'a>(
+    rpc_client: &'a NonblockingRpcClient,
+    program_id: &'a Pubkey,
+    order_book: &'a Pubkey,
+    trader: &'a Pubkey,
+) -> impl Stream<Item = ClientResult<Order>> + 'a {
+    query_pages(rpc_client, program_id, trader, move |cursor| {
+        get_open_orders(program_id, order_book, trader, cursor)
+    })
+}
+
+pub fn get_best_buy_order(program_id: &Pubkey, order_book: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        vec![AccountMeta::new_readonly(*order_book, false)],
+        OrderBookInstruction::GetBestBuyOrder,
+    )
+}
+
+pub fn get_best_sell_order(program_id: &Pubkey, order_book: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        vec![AccountMeta::new_readonly(*order_book, false)],
+        OrderBookInstruction::GetBestSellOrder,
+    )
+}
+
+pub fn export_book(program_id: &Pubkey, order_book: &Pubkey, cursor: u32) -> Instruction {
+    instruction(
+        program_id,
+        vec![AccountMeta::new_readonly(*order_book, false)],
+        OrderBookInstruction::ExportBook { cursor },
+    )
+}
+
+pub fn get_depth(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    max_levels: u8,
+    cursor: u32,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![AccountMeta::new_readonly(*order_book, false)],
+        OrderBookInstruction::GetDepth { max_levels, cursor },
+    )
+}
+
+pub fn get_open_orders(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    trader: &Pubkey,
+    cursor: u32,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![AccountMeta::new_readonly(*order_book, false)],
+        OrderBookInstruction::GetOpenOrders {
+            trader: *trader,
+            cursor,
+        },
+    )
+}
+
+pub fn get_inventory(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    open_orders: &Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new_readonly(*order_book, false),
+            AccountMeta::new_readonly(*open_orders, false),
+        ],
+        OrderBookInstruction::GetInventory,
+    )
+}
+
+pub fn verify_market(program_id: &Pubkey, order_book: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        vec![AccountMeta::new_readonly(*order_book, false)],
+        OrderBookInstruction::VerifyMarket,
+    )
+}
+
+pub fn get_order_statuses(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    order_ids: &[u64],
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![AccountMeta::new_readonly(*order_book, false)],
+        OrderBookInstruction::GetOrderStatuses {
+            order_ids: order_ids.to_vec(),
+        },
+    )
+}
+
+// Write the FillReceipt PDA of a completely filled order of `owner`
+pub fn write_fill_receipt(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    owner: &Pubkey,
+    order_id: u64,
+) -> Instruction {
+    let (fill_receipt, _) = FillReceipt::find_address(program_id, order_book, order_id);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(fill_receipt, false),
+            AccountMeta::new_readonly(*order_book, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        OrderBookInstruction::WriteFillReceipt { order_id },
+    )
+}
+
+// Close the FillReceipt PDA of an order, returning its rent to `owner`
+pub fn close_fill_receipt(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    owner: &Pubkey,
+    order_id: u64,
+) -> Instruction {
+    let (fill_receipt, _) = FillReceipt::find_address(program_id, order_book, order_id);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(fill_receipt, false),
+            AccountMeta::new(*owner, true),
+        ],
+        OrderBookInstruction::CloseFillReceipt,
+    )
+}
+
+// Define the accounts of CancelOrder and CancelOrdersByPrice
+#[derive(Clone, Debug, PartialEq)]
+pub struct CancelOrderAccounts {
+    pub order_book: Pubkey,
+    pub trader: Pubkey,
+    pub open_orders: Pubkey,
+}
+
+pub fn cancel_order(
+    program_id: &Pubkey,
+    accounts: &CancelOrderAccounts,
+    order_id: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(accounts.order_book, false),
+            AccountMeta::new(accounts.trader, true),
+            AccountMeta::new(accounts.open_orders, false),
+        ],
+        OrderBookInstruction::CancelOrder { order_id },
+    )
+}
+
+pub fn cancel_orders_by_price(
+    program_id: &Pubkey,
+    accounts: &CancelOrderAccounts,
+    side: OrderType,
+    limit_price: u64,
+    max_count: u16,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(accounts.order_book, false),
+            AccountMeta::new(accounts.trader, true),
+            AccountMeta::new(accounts.open_orders, false),
+        ],
+        OrderBookInstruction::CancelOrdersByPrice {
+            side,
+            limit_price,
+            max_count,
+        },
+    )
+}
+
+// Define the accounts of PruneOrder; `open_orders` belongs to the order's
+// owner
+#[derive(Clone, Debug, PartialEq)]
+pub struct PruneOrderAccounts {
+    pub order_book: Pubkey,
+    pub pruner: Pubkey,
+    pub open_orders: Pubkey,
+}
+
+pub fn prune_order(
+    program_id: &Pubkey,
+    accounts: &PruneOrderAccounts,
+    order_id: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(accounts.order_book, false),
+            AccountMeta::new(accounts.pruner, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new(accounts.open_orders, false),
+        ],
+        OrderBookInstruction::PruneOrder { order_id },
+    )
+}
+
+// Define the accounts of the keeper instructions CancelExpiredOrder,
+// TriggerStopOrder and SettleAuction; `open_orders` belongs to the order's
+// owner
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeeperAccounts {
+    pub order_book: Pubkey,
+    pub keeper: Pubkey,
+    pub open_orders: Pubkey,
+}
+
+pub fn cancel_expired_order(
+    program_id: &Pubkey,
+    accounts: &KeeperAccounts,
+    order_id: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(accounts.order_book, false),
+            AccountMeta::new(accounts.keeper, false),
+            AccountMeta::new(accounts.open_orders, false),
+        ],
+        OrderBookInstruction::CancelExpiredOrder { order_id },
+    )
+}
+
+// Cancel up to `max_orders` expired resting orders; `owner_open_orders`
+// lists the OpenOrders accounts of their owners, as
+// `OrderBook::expired_order_ids` finds them. A `bonded` keeper passes its
+// KeeperBond for priority during a backlog.
+pub fn prune_expired_orders(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    keeper: &Pubkey,
+    bonded: bool,
+    owner_open_orders: &[Pubkey],
+    max_orders: u8,
+) -> Instruction {
+    let mut metas = vec![
+        AccountMeta::new(*order_book, false),
+        AccountMeta::new(*keeper, false),
+    ];
+    if bonded {
+        let (keeper_bond, _) = KeeperBond::find_address(program_id, keeper);
+        metas.push(AccountMeta::new_readonly(keeper_bond, false));
+    }
+    metas.extend(
+        owner_open_orders
+            .iter()
+            .map(|open_orders| AccountMeta::new(*open_orders, false)),
+    );
+    instruction(
+        program_id,
+        metas,
+        OrderBookInstruction::PruneExpiredOrders { max_orders },
+    )
+}
+
+fn keeper_matching_metas(
+    program_id: &Pubkey,
+    accounts: &KeeperAccounts,
+    matching: &MatchingAccounts,
+) -> Vec<AccountMeta> {
+    let mut metas = vec![
+        AccountMeta::new(accounts.order_book, false),
+        AccountMeta::new(accounts.keeper, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new(accounts.open_orders, false),
+    ];
+    metas.extend(matching.metas(program_id));
+    metas
+}
+
+pub fn trigger_stop_order(
+    program_id: &Pubkey,
+    accounts: &KeeperAccounts,
+    matching: &MatchingAccounts,
+    order_id: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        keeper_matching_metas(program_id, accounts, matching),
+        OrderBookInstruction::TriggerStopOrder { order_id },
+    )
+}
+
+pub fn settle_auction(
+    program_id: &Pubkey,
+    accounts: &KeeperAccounts,
+    matching: &MatchingAccounts,
+    order_id: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        keeper_matching_metas(program_id, accounts, matching),
+        OrderBookInstruction::SettleAuction { order_id },
+    )
+}
+
+// Heal a crossed book; `matching.maker_open_orders` lists the OpenOrders
+// accounts of the owners of the crossed orders on both sides
+pub fn heal_book(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    matching: &MatchingAccounts,
+    max_fills: u16,
+) -> Instruction {
+    let mut metas = vec![AccountMeta::new(*order_book, false)];
+    metas.extend(matching.metas(program_id));
+    instruction(
+        program_id,
+        metas,
+        OrderBookInstruction::HealBook { max_fills },
+    )
+}
+
+// Create the Config PDA; `admin` must be the program's upgrade authority,
+// recorded in its upgradeable loader `program_data` account
+pub fn initialize_config(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    program_data: &Pubkey,
+    default_tick_size: u64,
+    default_lot_size: u64,
+    unique_markets: bool,
+    protocol_fee_bps: u16,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(Config::find_address(program_id).0, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        OrderBookInstruction::InitializeConfig {
+            default_tick_size,
+            default_lot_size,
+            unique_markets,
+            protocol_fee_bps,
+        },
+    )
+}
+
+// Define the accounts of InitializeMarket; the vaults are token accounts of
+// the market mints owned by the market's vault signer. `canonical` is the
+// market's CanonicalMarket PDA, required when the Config enforces unique
+// markets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InitializeMarketAccounts {
+    pub order_book: Pubkey,
+    pub creator: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub base_vault: Pubkey,
+    pub quote_vault: Pubkey,
+    pub canonical: Option<Pubkey>,
+}
+
+// Define the market parameters of InitializeMarket, documented on
+// `OrderBookInstruction::InitializeMarket`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MarketParams {
+    pub tick_size: u64,
+    pub lot_size: u64,
+    pub buy_taker_fee_bps: u16,
+    pub sell_taker_fee_bps: u16,
+    pub backstop_discount_bps: u16,
+    pub dark_pool: bool,
+    pub oracle_source: OracleSource,
+    pub oracle: Pubkey,
+    pub whole_units: bool,
+}
+
+pub fn initialize_market(
+    program_id: &Pubkey,
+    accounts: &InitializeMarketAccounts,
+    params: &MarketParams,
+) -> Instruction {
+    let registry = MarketRegistryEntry::find_address(program_id, &accounts.order_book).0;
+    let mut metas = vec![
+        AccountMeta::new(accounts.order_book, false),
+        AccountMeta::new(accounts.creator, true),
+        AccountMeta::new_readonly(accounts.base_mint, false),
+        AccountMeta::new_readonly(accounts.quote_mint, false),
+        AccountMeta::new_readonly(Config::find_address(program_id).0, false),
+        AccountMeta::new(registry, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(accounts.base_vault, false),
+        AccountMeta::new_readonly(accounts.quote_vault, false),
+    ];
+    metas.extend(accounts.canonical.map(|key| AccountMeta::new(key, false)));
+    instruction(
+        program_id,
+        metas,
+        OrderBookInstruction::InitializeMarket {
+            tick_size: params.tick_size,
+            lot_size: params.lot_size,
+            buy_taker_fee_bps: params.buy_taker_fee_bps,
+            sell_taker_fee_bps: params.sell_taker_fee_bps,
+            backstop_discount_bps: params.backstop_discount_bps,
+            dark_pool: params.dark_pool,
+            oracle_source: params.oracle_source,
+            oracle: params.oracle,
+            whole_units: params.whole_units,
+        },
+    )
+}
+
+pub fn set_canonical_market(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    order_book: &Pubkey,
+    canonical: bool,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new_readonly(Config::find_address(program_id).0, false),
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(
+                MarketRegistryEntry::find_address(program_id, order_book).0,
+                false,
+            ),
+        ],
+        OrderBookInstruction::SetCanonicalMarket { canonical },
+    )
+}
+
+// Set the guardians that may pause the protocol; signed by the Config admin
+pub fn set_guardians(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    pause_threshold: u8,
+    guardians: &[Pubkey],
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(Config::find_address(program_id).0, false),
+            AccountMeta::new_readonly(*admin, true),
+        ],
+        OrderBookInstruction::SetGuardians {
+            pause_threshold,
+            guardians: guardians.to_vec(),
+        },
+    )
+}
+
+pub fn set_listing_requirements(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    listing: ListingRequirements,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(Config::find_address(program_id).0, false),
+            AccountMeta::new_readonly(*admin, true),
+        ],
+        OrderBookInstruction::SetListingRequirements(listing),
+    )
+}
+
+// Pause or resume trading across the protocol, signed by `guardians`
+pub fn set_paused(program_id: &Pubkey, guardians: &[Pubkey], paused: bool) -> Instruction {
+    let mut metas = vec![AccountMeta::new(Config::find_address(program_id).0, false)];
+    metas.extend(
+        guardians
+            .iter()
+            .map(|guardian| AccountMeta::new_readonly(*guardian, true)),
+    );
+    instruction(
+        program_id,
+        metas,
+        OrderBookInstruction::SetPaused { paused },
+    )
+}
+
+// Create the OpenOrders PDA of `owner` for markets quoted in `quote_mint`
+pub fn init_open_orders(program_id: &Pubkey, owner: &Pubkey, quote_mint: &Pubkey) -> Instruction {
+    let (open_orders, _) = OpenOrders::find_address(program_id, owner, quote_mint);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(open_orders, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(*quote_mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        OrderBookInstruction::InitOpenOrders,
+    )
+}
+
+// Define the accounts of SettleFunds. `owner` is the OpenOrders account's
+// withdraw authority, the owner unless it handed withdrawals to another key.
+// With `create_missing_accounts` set, its associated token accounts are
+// created when they do not exist yet. `blocklist` is the Blocklist of a
+// market that enforces one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SettleFundsAccounts {
+    pub order_book: Pubkey,
+    pub owner: Pubkey,
+    pub open_orders: Pubkey,
+    pub base_vault: Pubkey,
+    pub quote_vault: Pubkey,
+    pub owner_base: Pubkey,
+    pub owner_quote: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub blocklist: Option<Pubkey>,
+    pub create_missing_accounts: bool,
+}
+
+pub fn settle_funds(program_id: &Pubkey, accounts: &SettleFundsAccounts) -> Instruction {
+    settle_funds_to(program_id, accounts, u64::MAX, u64::MAX, None)
+}
+
+// SettleFunds of at most `max_base` and `max_quote`. With a `recipient`,
+// `owner_base` and `owner_quote` are the recipient's token accounts, which
+// must already exist.
+pub fn settle_funds_to(
+    program_id: &Pubkey,
+    accounts: &SettleFundsAccounts,
+    max_base: u64,
+    max_quote: u64,
+    recipient: Option<Pubkey>,
+) -> Instruction {
+    let mut metas = vec![
+        AccountMeta::new_readonly(accounts.order_book, false),
+        AccountMeta::new(accounts.owner, true),
+        AccountMeta::new(accounts.open_orders, false),
+        AccountMeta::new(accounts.base_vault, false),
+        AccountMeta::new(accounts.quote_vault, false),
+        AccountMeta::new(accounts.owner_base, false),
+        AccountMeta::new(accounts.owner_quote, false),
+        AccountMeta::new_readonly(vault_signer(program_id, &accounts.order_book), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    if let Some(blocklist) = accounts.blocklist {
+        metas.push(AccountMeta::new_readonly(blocklist, false));
+    }
+    if accounts.create_missing_accounts {
+        metas.extend([
+            AccountMeta::new_readonly(accounts.base_mint, false),
+            AccountMeta::new_readonly(accounts.quote_mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        ]);
+    }
+    instruction(
+        program_id,
+        metas,
+        OrderBookInstruction::SettleFunds {
+            max_base,
+            max_quote,
+            recipient,
+        },
+    )
+}
+
+// Define one market settled by SettleAndWithdrawAll; `owner_base` is the
+// owner's associated token account of the base mint
+#[derive(Clone, Debug, PartialEq)]
+pub struct SettledMarketAccounts {
+    pub order_book: Pubkey,
+    pub base_vault: Pubkey,
+    pub quote_vault: Pubkey,
+    pub base_mint: Pubkey,
+    pub owner_base: Pubkey,
+}
+
+// Define the accounts of SettleAndWithdrawAll; `owner_quote` is the owner's
+// associated token account of the quote mint
+#[derive(Clone, Debug, PartialEq)]
+pub struct SettleAndWithdrawAllAccounts {
+    pub owner: Pubkey,
+    pub open_orders: Pubkey,
+    pub quote_mint: Pubkey,
+    pub owner_quote: Pubkey,
+    pub markets: Vec<SettledMarketAccounts>,
+}
+
+pub fn settle_and_withdraw_all(
+    program_id: &Pubkey,
+    accounts: &SettleAndWithdrawAllAccounts,
+) -> Instruction {
+    let mut metas = vec![
+        AccountMeta::new(accounts.owner, true),
+        AccountMeta::new(accounts.open_orders, false),
+        AccountMeta::new_readonly(accounts.quote_mint, false),
+        AccountMeta::new(accounts.owner_quote, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    for market in &accounts.markets {
+        metas.extend([
+            AccountMeta::new_readonly(market.order_book, false),
+            AccountMeta::new(market.base_vault, false),
+            AccountMeta::new(market.quote_vault, false),
+            AccountMeta::new_readonly(vault_signer(program_id, &market.order_book), false),
+            AccountMeta::new_readonly(market.base_mint, false),
+            AccountMeta::new(market.owner_base, false),
+        ]);
+    }
+    instruction(
+        program_id,
+        metas,
+        OrderBookInstruction::SettleAndWithdrawAll,
+    )
+}
+
+// Settle `owner`'s funds on every market given, keyed by address, into its
+// associated token accounts
+//
+// Markets are grouped by quote mint, as each quote mint has its own
+// OpenOrders account, and each group is settled `markets_per_instruction`
+// markets at a time so every instruction fits in a transaction of its own.
+pub fn settle_many(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    markets: &[(Pubkey, OrderBook)],
+    markets_per_instruction: usize,
+) -> Vec<Instruction> {
+    let mut quote_mints: Vec<Pubkey> = vec![];
+    for (_, order_book) in markets {
+        if !quote_mints.contains(&order_book.quote_mint) {
+            quote_mints.push(order_book.quote_mint);
+        }
+    }
+    let mut instructions = vec![];
+    for quote_mint in quote_mints {
+        let settled: Vec<SettledMarketAccounts> = markets
+            .iter()
+            .filter(|(_, order_book)| order_book.quote_mint == quote_mint)
+            .map(|(key, order_book)| SettledMarketAccounts {
+                order_book: *key,
+                base_vault: order_book.base_vault,
+                quote_vault: order_book.quote_vault,
+                base_mint: order_book.base_mint,
+                owner_base: get_associated_token_address(owner, &order_book.base_mint),
+            })
+            .collect();
+        for batch in settled.chunks(markets_per_instruction.max(1)) {
+            let accounts = SettleAndWithdrawAllAccounts {
+                owner: *owner,
+                open_orders: OpenOrders::find_address(program_id, owner, &quote_mint).0,
+                quote_mint,
+                owner_quote: get_associated_token_address(owner, &quote_mint),
+                markets: batch.to_vec(),
+            };
+            instructions.push(settle_and_withdraw_all(program_id, &accounts));
+        }
+    }
+    instructions
+}
+
+// Define the accounts of SweepFees and SweepProtocolFees; `destination` is a
+// quote token account of the fee collector or the Config treasury
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepFeesAccounts {
+    pub order_book: Pubkey,
+    pub quote_vault: Pubkey,
+    pub destination: Pubkey,
+}
+
+fn sweep_metas(program_id: &Pubkey, accounts: &SweepFeesAccounts) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(accounts.order_book, false),
+        AccountMeta::new(accounts.quote_vault, false),
+        AccountMeta::new(accounts.destination, false),
+        AccountMeta::new_readonly(vault_signer(program_id, &accounts.order_book), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ]
+}
+
+pub fn sweep_fees(program_id: &Pubkey, accounts: &SweepFeesAccounts) -> Instruction {
+    instruction(
+        program_id,
+        sweep_metas(program_id, accounts),
+        OrderBookInstruction::SweepFees,
+    )
+}
+
+pub fn sweep_protocol_fees(program_id: &Pubkey, accounts: &SweepFeesAccounts) -> Instruction {
+    let mut metas = sweep_metas(program_id, accounts);
+    metas.push(AccountMeta::new_readonly(
+        Config::find_address(program_id).0,
+        false,
+    ));
+    instruction(program_id, metas, OrderBookInstruction::SweepProtocolFees)
+}
+
+// Accounts of the instructions signed by the market creator that only change
+// the order book
+fn creator_metas(order_book: &Pubkey, creator: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*order_book, false),
+        AccountMeta::new_readonly(*creator, true),
+    ]
+}
+
+pub fn set_backstop_maker(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    maker: Pubkey,
+    backstop: bool,
+) -> Instruction {
+    instruction(
+        program_id,
+        creator_metas(order_book, creator),
+        OrderBookInstruction::SetBackstopMaker { maker, backstop },
+    )
+}
+
+pub fn set_oracle(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    oracle_source: OracleSource,
+    oracle: Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        creator_metas(order_book, creator),
+        OrderBookInstruction::SetOracle {
+            oracle_source,
+            oracle,
+        },
+    )
+}
+
+pub fn set_market_features(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    disabled_features: u8,
+) -> Instruction {
+    instruction(
+        program_id,
+        creator_metas(order_book, creator),
+        OrderBookInstruction::SetMarketFeatures { disabled_features },
+    )
+}
+
+pub fn set_placement_fee(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    lamports: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        creator_metas(order_book, creator),
+        OrderBookInstruction::SetPlacementFee { lamports },
+    )
+}
+
+pub fn set_prune_after(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    slots: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        creator_metas(order_book, creator),
+        OrderBookInstruction::SetPruneAfter { slots },
+    )
+}
+
+pub fn set_order_rate_limit(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    max_orders_per_slot: u16,
+) -> Instruction {
+    instruction(
+        program_id,
+        creator_metas(order_book, creator),
+        OrderBookInstruction::SetOrderRateLimit {
+            max_orders_per_slot,
+        },
+    )
+}
+
+pub fn set_oracle_confidence(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    max_conf_bps: u16,
+) -> Instruction {
+    instruction(
+        program_id,
+        creator_metas(order_book, creator),
+        OrderBookInstruction::SetOracleConfidence { max_conf_bps },
+    )
+}
+
+pub fn set_order_gate(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    order_gate: Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        creator_metas(order_book, creator),
+        OrderBookInstruction::SetOrderGate { order_gate },
+    )
+}
+
+pub fn set_blocklist(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    blocklist: Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        creator_metas(order_book, creator),
+        OrderBookInstruction::SetBlocklist { blocklist },
+    )
+}
+
+pub fn init_blocklist(program_id: &Pubkey, authority: &Pubkey) -> Instruction {
+    let (blocklist, _) = Blocklist::find_address(program_id, authority);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(blocklist, false),
+            AccountMeta::new(*authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        OrderBookInstruction::InitBlocklist,
+    )
+}
+
+pub fn update_blocklist(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    address: &Pubkey,
+    blocked: bool,
+) -> Instruction {
+    let (blocklist, _) = Blocklist::find_address(program_id, authority);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(blocklist, false),
+            AccountMeta::new_readonly(*authority, true),
+        ],
+        OrderBookInstruction::UpdateBlocklist {
+            address: *address,
+            blocked,
+        },
+    )
+}
+
+pub fn bond_keeper(program_id: &Pubkey, keeper: &Pubkey, lamports: u64) -> Instruction {
+    let (keeper_bond, _) = KeeperBond::find_address(program_id, keeper);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(keeper_bond, false),
+            AccountMeta::new(*keeper, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        OrderBookInstruction::BondKeeper { lamports },
+    )
+}
+
+pub fn unbond_keeper(program_id: &Pubkey, keeper: &Pubkey, lamports: u64) -> Instruction {
+    let (keeper_bond, _) = KeeperBond::find_address(program_id, keeper);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(keeper_bond, false),
+            AccountMeta::new(*keeper, true),
+        ],
+        OrderBookInstruction::UnbondKeeper { lamports },
+    )
+}
+
+pub fn set_priority_auction(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    enabled: bool,
+) -> Instruction {
+    instruction(
+        program_id,
+        creator_metas(order_book, creator),
+        OrderBookInstruction::SetPriorityAuction { enabled },
+    )
+}
+
+pub fn set_rounding_policy(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    policy: RoundingPolicy,
+) -> Instruction {
+    instruction(
+        program_id,
+        creator_metas(order_book, creator),
+        OrderBookInstruction::SetRoundingPolicy { policy },
+    )
+}
+
+// Define the accounts of MigrateLiquidity; `owner_open_orders` lists the
+// OpenOrders accounts of the owners of the deprecated market's orders and
+// balances
+#[derive(Clone, Debug, PartialEq)]
+pub struct MigrateLiquidityAccounts {
+    pub order_book: Pubkey,
+    pub successor: Pubkey,
+    pub creator: Pubkey,
+    pub base_vault: Pubkey,
+    pub quote_vault: Pubkey,
+    pub successor_base_vault: Pubkey,
+    pub successor_quote_vault: Pubkey,
+    pub owner_open_orders: Vec<Pubkey>,
+}
+
+pub fn migrate_liquidity(
+    program_id: &Pubkey,
+    accounts: &MigrateLiquidityAccounts,
+    max_orders: u8,
+) -> Instruction {
+    let mut metas = vec![
+        AccountMeta::new(accounts.order_book, false),
+        AccountMeta::new(accounts.successor, false),
+        AccountMeta::new_readonly(accounts.creator, true),
+        AccountMeta::new(accounts.base_vault, false),
+        AccountMeta::new(accounts.quote_vault, false),
+        AccountMeta::new(accounts.successor_base_vault, false),
+        AccountMeta::new(accounts.successor_quote_vault, false),
+        AccountMeta::new_readonly(vault_signer(program_id, &accounts.order_book), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    metas.extend(
+        accounts
+            .owner_open_orders
+            .iter()
+            .map(|open_orders| AccountMeta::new(*open_orders, false)),
+    );
+    instruction(
+        program_id,
+        metas,
+        OrderBookInstruction::MigrateLiquidity { max_orders },
+    )
+}
+
+// Define the accounts of TransferFreeBalance, moving free balances of the
+// owner's OpenOrders account from `order_book` to `destination`.
+// `blocklists` are the Blocklists of those of the two markets that enforce
+// one, the source market's first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferFreeBalanceAccounts {
+    pub order_book: Pubkey,
+    pub destination: Pubkey,
+    pub owner: Pubkey,
+    pub quote_mint: Pubkey,
+    pub base_vault: Pubkey,
+    pub quote_vault: Pubkey,
+    pub destination_base_vault: Pubkey,
+    pub destination_quote_vault: Pubkey,
+    pub blocklists: Vec<Pubkey>,
+}
+
+pub fn transfer_free_balance(
+    program_id: &Pubkey,
+    accounts: &TransferFreeBalanceAccounts,
+    base_amount: u64,
+    quote_amount: u64,
+) -> Instruction {
+    let (open_orders, _) =
+        OpenOrders::find_address(program_id, &accounts.owner, &accounts.quote_mint);
+    let mut metas = vec![
+        AccountMeta::new_readonly(accounts.order_book, false),
+        AccountMeta::new_readonly(accounts.destination, false),
+        AccountMeta::new_readonly(accounts.owner, true),
+        AccountMeta::new(open_orders, false),
+        AccountMeta::new(accounts.base_vault, false),
+        AccountMeta::new(accounts.quote_vault, false),
+        AccountMeta::new(accounts.destination_base_vault, false),
+        AccountMeta::new(accounts.destination_quote_vault, false),
+        AccountMeta::new_readonly(vault_signer(program_id, &accounts.order_book), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    metas.extend(
+        accounts
+            .blocklists
+            .iter()
+            .map(|blocklist| AccountMeta::new_readonly(*blocklist, false)),
+    );
+    instruction(
+        program_id,
+        metas,
+        OrderBookInstruction::TransferFreeBalance {
+            base_amount,
+            quote_amount,
+        },
+    )
+}
+
+// Define the accounts of FundIncentives
+#[derive(Clone, Debug, PartialEq)]
+pub struct FundIncentivesAccounts {
+    pub order_book: Pubkey,
+    pub funder: Pubkey,
+    pub funder_token: Pubkey,
+    pub quote_vault: Pubkey,
+}
+
+pub fn fund_incentives(
+    program_id: &Pubkey,
+    accounts: &FundIncentivesAccounts,
+    amount: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(accounts.order_book, false),
+            AccountMeta::new_readonly(accounts.funder, true),
+            AccountMeta::new(accounts.funder_token, false),
+            AccountMeta::new(accounts.quote_vault, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        OrderBookInstruction::FundIncentives { amount },
+    )
+}
+
+// FundEpochRewards takes the same accounts as FundIncentives
+pub fn fund_epoch_rewards(
+    program_id: &Pubkey,
+    accounts: &FundIncentivesAccounts,
+    amount: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(accounts.order_book, false),
+            AccountMeta::new_readonly(accounts.funder, true),
+            AccountMeta::new(accounts.funder_token, false),
+            AccountMeta::new(accounts.quote_vault, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        OrderBookInstruction::FundEpochRewards { amount },
+    )
+}
+
+pub fn claim_epoch_rewards(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    owner: &Pubkey,
+    open_orders: &Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(*order_book, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new(*open_orders, false),
+        ],
+        OrderBookInstruction::ClaimEpochRewards,
+    )
+}
+
+pub fn set_beneficial_owner(
+    program_id: &Pubkey,
+    open_orders: &Pubkey,
+    owner: &Pubkey,
+    beneficial_owner: Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(*open_orders, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new_readonly(beneficial_owner, true),
+        ],
+        OrderBookInstruction::SetBeneficialOwner { beneficial_owner },
+    )
+}
+
+pub fn set_withdraw_authority(
+    program_id: &Pubkey,
+    open_orders: &Pubkey,
+    withdraw_authority: &Pubkey,
+    new_withdraw_authority: Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(*open_orders, false),
+            AccountMeta::new_readonly(*withdraw_authority, true),
+            AccountMeta::new_readonly(new_withdraw_authority, true),
+        ],
+        OrderBookInstruction::SetWithdrawAuthority {
+            withdraw_authority: new_withdraw_authority,
+        },
+    )
+}
+
+pub fn settle_reference_orders(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    keeper: &Pubkey,
+    owner_open_orders: &[Pubkey],
+    settle_at: UnixTimestamp,
+) -> Instruction {
+    let mut metas = vec![
+        AccountMeta::new(*order_book, false),
+        AccountMeta::new(*keeper, false),
+        AccountMeta::new_readonly(PriceOracle::find_address(program_id, order_book).0, false),
+        AccountMeta::new_readonly(Config::find_address(program_id).0, false),
+    ];
+    metas.extend(
+        owner_open_orders
+            .iter()
+            .map(|open_orders| AccountMeta::new(*open_orders, false)),
+    );
+    instruction(
+        program_id,
+        metas,
+        OrderBookInstruction::SettleReferenceOrders { settle_at },
+    )
+}
+
+pub fn cancel_all_after(
+    program_id: &Pubkey,
+    open_orders: &Pubkey,
+    owner: &Pubkey,
+    slot: Slot,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(*open_orders, false),
+            AccountMeta::new_readonly(*owner, true),
+        ],
+        OrderBookInstruction::CancelAllAfter { slot },
+    )
+}
+
+pub fn init_price_oracle(program_id: &Pubkey, order_book: &Pubkey, payer: &Pubkey) -> Instruction {
+    let (price_oracle, _) = PriceOracle::find_address(program_id, order_book);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(price_oracle, false),
+            AccountMeta::new_readonly(*order_book, false),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        OrderBookInstruction::InitPriceOracle,
+    )
+}
+
+pub fn update_price_oracle(program_id: &Pubkey, order_book: &Pubkey) -> Instruction {
+    let (price_oracle, _) = PriceOracle::find_address(program_id, order_book);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(price_oracle, false),
+            AccountMeta::new_readonly(*order_book, false),
+        ],
+        OrderBookInstruction::UpdatePriceOracle,
+    )
+}
+
+// Launch a sealed-bid auction of `supply` base atoms from the creator's
+// `creator_base` token account
+#[allow(clippy::too_many_arguments)]
+pub fn init_launch_auction(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    creator_base: &Pubkey,
+    base_vault: &Pubkey,
+    supply: u64,
+    reserve_price: u64,
+    bid_end: UnixTimestamp,
+    reveal_end: UnixTimestamp,
+) -> Instruction {
+    let (launch_auction, _) = LaunchAuction::find_address(program_id, order_book);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(launch_auction, false),
+            AccountMeta::new_readonly(*order_book, false),
+            AccountMeta::new(*creator, true),
+            AccountMeta::new(*creator_base, false),
+            AccountMeta::new(*base_vault, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        OrderBookInstruction::InitLaunchAuction {
+            supply,
+            reserve_price,
+            bid_end,
+            reveal_end,
+        },
+    )
+}
+
+// Commit a sealed bid for `quantity` base atoms, its price hidden behind
+// `LaunchAuction::commitment`, locking `deposit` quote atoms
+#[allow(clippy::too_many_arguments)]
+pub fn commit_launch_bid(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    bidder: &Pubkey,
+    open_orders: &Pubkey,
+    bidder_quote: &Pubkey,
+    quote_vault: &Pubkey,
+    quantity: u64,
+    deposit: u64,
+    commitment: [u8; 32],
+) -> Instruction {
+    let (launch_auction, _) = LaunchAuction::find_address(program_id, order_book);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(launch_auction, false),
+            AccountMeta::new_readonly(*order_book, false),
+            AccountMeta::new_readonly(*bidder, true),
+            AccountMeta::new(*open_orders, false),
+            AccountMeta::new(*bidder_quote, false),
+            AccountMeta::new(*quote_vault, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        OrderBookInstruction::CommitLaunchBid {
+            quantity,
+            deposit,
+            commitment,
+        },
+    )
+}
+
+pub fn reveal_launch_bid(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    bidder: &Pubkey,
+    price: u64,
+    salt: [u8; 32],
+) -> Instruction {
+    let (launch_auction, _) = LaunchAuction::find_address(program_id, order_book);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(launch_auction, false),
+            AccountMeta::new_readonly(*order_book, false),
+            AccountMeta::new_readonly(*bidder, true),
+        ],
+        OrderBookInstruction::RevealLaunchBid { price, salt },
+    )
+}
+
+// Clear the auction, crediting the seller's `open_orders` account with the
+// proceeds and unsold supply
+pub fn clear_launch_auction(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    open_orders: &Pubkey,
+) -> Instruction {
+    let (launch_auction, _) = LaunchAuction::find_address(program_id, order_book);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(launch_auction, false),
+            AccountMeta::new_readonly(*order_book, false),
+            AccountMeta::new(*open_orders, false),
+        ],
+        OrderBookInstruction::ClearLaunchAuction,
+    )
+}
+
+// Credit the owner of `open_orders` with the allocations and change of its
+// bids in a cleared auction
+pub fn settle_launch_bids(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    open_orders: &Pubkey,
+) -> Instruction {
+    let (launch_auction, _) = LaunchAuction::find_address(program_id, order_book);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(launch_auction, false),
+            AccountMeta::new_readonly(*order_book, false),
+            AccountMeta::new(*open_orders, false),
+        ],
+        OrderBookInstruction::SettleLaunchBids,
+    )
+}
+
+// Accounts of the instructions creating a market feed PDA paid by the
+// market creator
+fn init_feed_metas(feed: Pubkey, order_book: &Pubkey, creator: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(feed, false),
+        AccountMeta::new(*order_book, false),
+        AccountMeta::new(*creator, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ]
+}
+
+pub fn init_trade_history(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    capacity: u32,
+) -> Instruction {
+    let (trade_history, _) = TradeHistory::find_address(program_id, order_book);
+    instruction(
+        program_id,
+        init_feed_metas(trade_history, order_book, creator),
+        OrderBookInstruction::InitTradeHistory { capacity },
+    )
+}
+
+pub fn init_candle_series(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    creator: &Pubkey,
+    interval: u32,
+    capacity: u32,
+) -> Instruction {
+    let (candle_series, _) = CandleSeries::find_address(program_id, order_book, interval);
+    instruction(
+        program_id,
+        init_feed_metas(candle_series, order_book, creator),
+        OrderBookInstruction::InitCandleSeries { interval, capacity },
+    )
+}
+
+pub fn get_candles(
+    program_id: &Pubkey,
+    order_book: &Pubkey,
+    candle_series: &Pubkey,
+    start_time: UnixTimestamp,
+    end_time: UnixTimestamp,
+) -> Instruction {
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new_readonly(*order_book, false),
+            AccountMeta::new_readonly(*candle_series, false),
+        ],
+        OrderBookInstruction::GetCandles {
+            start_time,
+            end_time,
+        },
+    )
+}
+
+pub fn get_market_stats(program_id: &Pubkey, order_book: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        vec![AccountMeta::new_readonly(*order_book, false)],
+        OrderBookInstruction::GetMarketStats,
+    )
+}
+
+pub fn get_version(program_id: &Pubkey) -> Instruction {
+    instruction(program_id, vec![], OrderBookInstruction::GetVersion)
+}
+
+// Addresses each address lookup table extension adds, keeping the extend
+// transaction within the packet size
+pub const LOOKUP_TABLE_EXTEND_CHUNK: usize = 20;
+
+// Static accounts that a market's matching and settling transactions
+// reference, for an address lookup table. The program id itself is left out
+// because invoked programs cannot be loaded from a lookup table.
+pub fn market_lookup_addresses(
+    program_id: &Pubkey,
+    order_book_key: &Pubkey,
+    order_book: &OrderBook,
+) -> Vec<Pubkey> {
+    let mut addresses = vec![
+        *order_book_key,
+        order_book.base_mint,
+        order_book.quote_mint,
+        order_book.base_vault,
+        order_book.quote_vault,
+        vault_signer(program_id, order_book_key),
+        sysvar::clock::id(),
+        sysvar::rent::id(),
+        system_program::id(),
+        spl_token::id(),
+        spl_associated_token_account::id(),
+    ];
+    let matching = MatchingAccounts::new(order_book, vec![]);
+    addresses.extend(matching.trade_history);
+    addresses.extend(matching.candle_series);
+    addresses
+}
+
+// Create an address lookup table holding `addresses`, returning its address
+// and the instructions creating and extending it. The table can be used once
+// the slot after the last extension has passed.
+pub fn create_lookup_table(
+    authority: &Pubkey,
+    payer: &Pubkey,
+    recent_slot: Slot,
+    addresses: &[Pubkey],
+) -> (Pubkey, Vec<Instruction>) {
+    let (create, lookup_table) =
+        address_lookup_table::instruction::create_lookup_table(*authority, *payer, recent_slot);
+    let mut instructions = vec![create];
+    instructions.extend(extend_lookup_table(
+        &lookup_table,
+        authority,
+        payer,
+        addresses,
+    ));
+    (lookup_table, instructions)
+}
+
+// Add `addresses` to an existing lookup table, one instruction per
+// `LOOKUP_TABLE_EXTEND_CHUNK` addresses
+pub fn extend_lookup_table(
+    lookup_table: &Pubkey,
+    authority: &Pubkey,
+    payer: &Pubkey,
+    addresses: &[Pubkey],
+) -> Vec<Instruction> {
+    addresses
+        .chunks(LOOKUP_TABLE_EXTEND_CHUNK)
+        .map(|chunk| {
+            address_lookup_table::instruction::extend_lookup_table(
+                *lookup_table,
+                *authority,
+                Some(*payer),
+                chunk.to_vec(),
+            )
+        })
+        .collect()
+}
+
+// Compile instructions into a v0 message that loads every account it can
+// from the given lookup tables
+pub fn versioned_message(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    recent_blockhash: Hash,
+) -> Result<VersionedMessage, CompileError> {
+    v0::Message::try_compile(payer, instructions, lookup_tables, recent_blockhash)
+        .map(VersionedMessage::V0)
+}
+
+// Build and sign a versioned transaction that loads accounts from the given
+// lookup tables
+#[cfg(not(target_os = "solana"))]
+pub fn versioned_transaction(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    recent_blockhash: Hash,
+    signers: &[&dyn Signer],
+) -> Result<VersionedTransaction, SignerError> {
+    let message = versioned_message(payer, instructions, lookup_tables, recent_blockhash)
+        .map_err(|err| SignerError::Custom(err.to_string()))?;
+    VersionedTransaction::try_new(message, signers)
+}
+
+// Decode the events Fordex logged in a transaction's log messages, as a
+// websocket logs subscription or a fetched transaction reports them
+//
+// Only `Program data:` lines logged while `program_id` itself executes are
+// decoded, so data logged by programs it calls or that call it is skipped.
+#[cfg(not(target_os = "solana"))]
+pub fn decode_log_events<S: AsRef<str>>(
+    program_id: &Pubkey,
+    logs: &[S],
+) -> Result<Vec<FordexEvent>, ProgramError> {
+    let program = program_id.to_string();
+    let mut invoked = vec![];
+    let mut events = vec![];
+    for line in logs.iter().map(AsRef::as_ref) {
+        if let Some(data) = line.strip_prefix("Program data: ") {
+            if invoked.last() == Some(&program.as_str()) {
+                for field in data.split_whitespace() {
+                    events.push(decode_log_data(field)?);
+                }
+            }
+        } else if let Some(rest) = line.strip_prefix("Program ") {
+            let mut words = rest.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some(id), Some("invoke")) => invoked.push(id),
+                (Some(_), Some("success" | "failed:")) => {
+                    invoked.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(events)
+}
+
+// Decode one base64 field of a `Program data:` log line
+#[cfg(not(target_os = "solana"))]
+pub fn decode_log_data(field: &str) -> Result<FordexEvent, ProgramError> {
+    let data = BASE64_STANDARD
+        .decode(field)
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    FordexEvent::unpack(&data)
+}
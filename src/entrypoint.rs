@@ -0,0 +1,5 @@
+// Program entrypoint, routing every instruction to the processor
+
+use crate::processor::process_instruction;
+
+solana_program::entrypoint!(process_instruction);
@@ -0,0 +1,237 @@
+// Errors returned by the order book program
+
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use std::fmt;
+
+// Define the custom errors returned by the order book program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderBookError {
+    // The instruction tag belongs to a newer program version
+    UnsupportedInstructionVersion,
+    // No resting order has the given order id
+    OrderNotFound,
+    // The order has not rested long enough to be pruned
+    OrderNotAbandoned,
+    // The OpenOrders account of a maker filled by the order was not passed to
+    // PlaceOrder
+    MakerAccountMissing,
+    // The order has no expiry or has not expired yet
+    OrderNotExpired,
+    // The price or amount is not a multiple of the market tick or lot size
+    InvalidOrderSize,
+    // The OpenOrders account already holds balances for its maximum number of
+    // markets
+    OpenOrdersFull,
+    // The market already has its maximum number of backstop makers
+    BackstopMakersFull,
+    // The last trade price has not reached the stop order's trigger price
+    StopNotTriggered,
+    // The order's price improvement window has not ended yet
+    AuctionNotEnded,
+    // The market has no oracle, or its oracle account holds no usable price
+    InvalidOraclePrice,
+    // The order uses a capability disabled on the market
+    FeatureDisabled,
+    // An account the instruction requires to sign did not sign
+    MissingRequiredSigner,
+    // An account the instruction writes to was passed read-only
+    AccountNotWritable,
+    // An account's data length differs from the length of its layout
+    InvalidAccountSize,
+    // The launch auction is not in the phase the instruction acts in
+    WrongAuctionPhase,
+    // The protocol is paused, so orders may not be placed or matched
+    ProtocolPaused,
+    // The account was written by a newer program version whose layout this
+    // one cannot read
+    UnsupportedLayoutVersion,
+    // Less than the routed order's minimum fill amount filled across the
+    // markets it was routed to
+    RouteUnderfilled,
+    // The market is deprecated in favor of its successor and takes no new
+    // orders
+    MarketDeprecated,
+    // The owner has no maker volume in a closed fee epoch to claim rewards
+    // for
+    NoEpochRewards,
+    // The market only takes orders through CPI from its gate program
+    OrderGateRequired,
+    // A waiting taker with priority in the market's priority auction must
+    // be cleared first
+    PriorityTakerWaiting,
+    // The reference price orders trade at settlement at is not fixed yet
+    ReferencePriceNotFixed,
+    // The same account was passed in two roles that must be distinct
+    DuplicateAccount,
+    // A token transfer moved a different amount than the instruction
+    // requested
+    TokenBalanceMismatch,
+    // A vault account is not the market's vault for the funds it moves
+    WrongVault,
+    // A token account holds a different mint than the market trades
+    MintMismatch,
+    // The address is on the blocklist the market opted into
+    AddressBlocked,
+    // The blocklist holds its maximum number of addresses
+    BlocklistFull,
+    // The expired orders are reserved to keepers with larger bonds for now
+    KeeperPriorityWindow,
+    // A DryRun ran its instruction successfully and rolled it back
+    DryRunComplete,
+    // The market does not meet the Config's listing requirements
+    ListingRequirementsNotMet,
+    // The account was written by an older program version with a layout of
+    // a different size, which this one cannot read
+    OutdatedLayoutVersion,
+    // The OpenOrders account already placed the market's limit of orders in
+    // this slot
+    OrderRateLimited,
+    // The oracle's confidence interval is wider than the market accepts
+    OracleConfidenceTooWide,
+}
+
+impl OrderBookError {
+    // Every error, indexed by its code
+    pub const ALL: [OrderBookError; 36] = [
+        OrderBookError::UnsupportedInstructionVersion,
+        OrderBookError::OrderNotFound,
+        OrderBookError::OrderNotAbandoned,
+        OrderBookError::MakerAccountMissing,
+        OrderBookError::OrderNotExpired,
+        OrderBookError::InvalidOrderSize,
+        OrderBookError::OpenOrdersFull,
+        OrderBookError::BackstopMakersFull,
+        OrderBookError::StopNotTriggered,
+        OrderBookError::AuctionNotEnded,
+        OrderBookError::InvalidOraclePrice,
+        OrderBookError::FeatureDisabled,
+        OrderBookError::MissingRequiredSigner,
+        OrderBookError::AccountNotWritable,
+        OrderBookError::InvalidAccountSize,
+        OrderBookError::WrongAuctionPhase,
+        OrderBookError::ProtocolPaused,
+        OrderBookError::UnsupportedLayoutVersion,
+        OrderBookError::RouteUnderfilled,
+        OrderBookError::MarketDeprecated,
+        OrderBookError::NoEpochRewards,
+        OrderBookError::OrderGateRequired,
+        OrderBookError::PriorityTakerWaiting,
+        OrderBookError::ReferencePriceNotFixed,
+        OrderBookError::DuplicateAccount,
+        OrderBookError::TokenBalanceMismatch,
+        OrderBookError::WrongVault,
+        OrderBookError::MintMismatch,
+        OrderBookError::AddressBlocked,
+        OrderBookError::BlocklistFull,
+        OrderBookError::KeeperPriorityWindow,
+        OrderBookError::DryRunComplete,
+        OrderBookError::ListingRequirementsNotMet,
+        OrderBookError::OutdatedLayoutVersion,
+        OrderBookError::OrderRateLimited,
+        OrderBookError::OracleConfidenceTooWide,
+    ];
+
+    // Decode the code of a `ProgramError::Custom` returned by the program
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.get(code as usize).copied()
+    }
+
+    // Decode an error returned by the program, or `None` for errors that
+    // are not custom order book errors
+    pub fn from_program_error(err: &ProgramError) -> Option<Self> {
+        match err {
+            ProgramError::Custom(code) => Self::from_code(*code),
+            _ => None,
+        }
+    }
+
+    // Find the error of a failed transaction in its logs, where the runtime
+    // reports it as "Program <id> failed: custom program error: 0x<code>"
+    pub fn from_logs(program_id: &Pubkey, logs: &[String]) -> Option<Self> {
+        let prefix = format!("Program {} failed: custom program error: 0x", program_id);
+        logs.iter().rev().find_map(|log| {
+            let code = log.strip_prefix(&prefix)?;
+            Self::from_code(u32::from_str_radix(code, 16).ok()?)
+        })
+    }
+
+    // Description of the error for display to users
+    pub fn description(&self) -> &'static str {
+        match self {
+            OrderBookError::UnsupportedInstructionVersion => {
+                "Instruction not supported by this program version"
+            }
+            OrderBookError::OrderNotFound => "Order not found",
+            OrderBookError::OrderNotAbandoned => "Order has not rested long enough to be pruned",
+            OrderBookError::MakerAccountMissing => "OpenOrders account of a filled maker missing",
+            OrderBookError::OrderNotExpired => "Order has not expired",
+            OrderBookError::InvalidOrderSize => {
+                "Order price not a multiple of tick size or amount not a multiple of lot size"
+            }
+            OrderBookError::OpenOrdersFull => {
+                "OpenOrders account holds the maximum number of markets"
+            }
+            OrderBookError::BackstopMakersFull => {
+                "Market has the maximum number of backstop makers"
+            }
+            OrderBookError::StopNotTriggered => "Stop order trigger price not reached",
+            OrderBookError::AuctionNotEnded => "Price improvement window has not ended",
+            OrderBookError::InvalidOraclePrice => "Market oracle holds no usable price",
+            OrderBookError::FeatureDisabled => "Order type disabled on this market",
+            OrderBookError::MissingRequiredSigner => "Required signature missing",
+            OrderBookError::AccountNotWritable => "Account must be writable",
+            OrderBookError::InvalidAccountSize => "Account data has the wrong size",
+            OrderBookError::WrongAuctionPhase => "Launch auction is not in the required phase",
+            OrderBookError::ProtocolPaused => "Trading is paused across the protocol",
+            OrderBookError::UnsupportedLayoutVersion => {
+                "Account written by a newer program version"
+            }
+            OrderBookError::RouteUnderfilled => "Routed order filled less than its minimum",
+            OrderBookError::MarketDeprecated => "Market is deprecated and takes no new orders",
+            OrderBookError::NoEpochRewards => "No maker volume in a closed fee epoch to claim",
+            OrderBookError::OrderGateRequired => {
+                "Market only takes orders through its gate program"
+            }
+            OrderBookError::PriorityTakerWaiting => {
+                "A taker with higher priority must be cleared first"
+            }
+            OrderBookError::ReferencePriceNotFixed => "Reference price is not fixed yet",
+            OrderBookError::DuplicateAccount => "Account passed in two distinct roles",
+            OrderBookError::TokenBalanceMismatch => {
+                "Token transfer moved a different amount than requested"
+            }
+            OrderBookError::WrongVault => "Account is not the market vault",
+            OrderBookError::MintMismatch => "Token account holds the wrong mint",
+            OrderBookError::AddressBlocked => "Address is blocked on this market",
+            OrderBookError::BlocklistFull => "Blocklist holds the maximum number of addresses",
+            OrderBookError::KeeperPriorityWindow => {
+                "Expired orders are reserved to bonded keepers for now"
+            }
+            OrderBookError::DryRunComplete => "Dry run succeeded and was rolled back",
+            OrderBookError::ListingRequirementsNotMet => {
+                "Market does not meet the listing requirements"
+            }
+            OrderBookError::OutdatedLayoutVersion => {
+                "Account written by an older program version with another layout"
+            }
+            OrderBookError::OrderRateLimited => "Too many orders placed in this slot",
+            OrderBookError::OracleConfidenceTooWide => {
+                "Oracle confidence interval is too wide for the market"
+            }
+        }
+    }
+}
+
+impl fmt::Display for OrderBookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl std::error::Error for OrderBookError {}
+
+impl From<OrderBookError> for ProgramError {
+    fn from(err: OrderBookError) -> Self {
+        ProgramError::Custom(err as u32)
+    }
+}
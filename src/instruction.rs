@@ -0,0 +1,1513 @@
+// Instructions of the order book program and their wire format
+
+use crate::{
+    error::OrderBookError,
+    math::RoundingPolicy,
+    state::{ListingRequirements, OracleSource, Order, OrderType},
+};
+use arrayref::array_ref;
+use solana_program::{
+    clock::{Slot, UnixTimestamp},
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+// Define the possible instructions for the order book
+//
+// Instruction data is a one-byte tag followed by the instruction payload.
+// Tags are assigned sequentially and never reused, and payloads only evolve
+// by appending fields. Older program versions ignore the fields appended
+// after theirs, and a payload without them, from a client built before they
+// were appended, unpacks with their defaults. A tag above
+// `LATEST_TAG` was introduced by a later program version and is rejected
+// with `OrderBookError::UnsupportedInstructionVersion`.
+#[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum OrderBookInstruction {
+    // Match an order against the book and rest any remainder, returning a
+    // FillPreview of the outcome
+    PlaceOrder(Order),
+    GetBestBuyOrder,
+    GetBestSellOrder,
+    // Return one page of resting orders starting at `cursor`, which counts
+    // buy orders first and then sell orders
+    ExportBook {
+        cursor: u32,
+    },
+    // Check the order book invariants and return a VerifyMarketReport
+    VerifyMarket,
+    // Cancel a resting order, releasing its funds in the owner's OpenOrders
+    // account and refunding its deposit to the owner
+    CancelOrder {
+        order_id: u64,
+    },
+    // Remove an order that has rested for the market's `prune_after_slots`,
+    // releasing its funds in the owner's OpenOrders account and paying its
+    // deposit and bounty to the pruner; refused on markets that have not
+    // opted into pruning with SetPruneAfter
+    PruneOrder {
+        order_id: u64,
+    },
+    // Remove an expired order, or one placed after its owner's CancelAllAfter
+    // cutoff, paying its bounty and a reward from the crank incentives to the
+    // keeper and its deposit back to the owner's OpenOrders account
+    CancelExpiredOrder {
+        order_id: u64,
+    },
+    // Create the Config PDA holding protocol-level market defaults; must be
+    // signed by the program upgrade authority. With `unique_markets` set,
+    // only one market may exist per (base, quote, tick, lot) combination.
+    // `protocol_fee_bps` of every taker fee goes to the protocol treasury,
+    // which is the admin.
+    InitializeConfig {
+        default_tick_size: u64,
+        default_lot_size: u64,
+        unique_markets: bool,
+        protocol_fee_bps: u16,
+    },
+    // Initialize a market in a pre-created order book account and create its
+    // registry entry. Anyone may create a market; zero sizes take the Config
+    // defaults. The creator collects the taker fees, charged at separate rates
+    // for buying and selling takers; the sell rate defaults to the buy rate.
+    // Fills against backstop makers have `backstop_discount_bps` of their fee
+    // paid from the market's incentives instead. A `dark_pool` market does not
+    // display its resting orders. The market's `oracle` account is read as
+    // `oracle_source` dictates; it defaults to none. A `whole_units` market
+    // trades an indivisible base mint without decimals in lots of one unit.
+    InitializeMarket {
+        tick_size: u64,
+        lot_size: u64,
+        buy_taker_fee_bps: u16,
+        sell_taker_fee_bps: u16,
+        backstop_discount_bps: u16,
+        dark_pool: bool,
+        oracle_source: OracleSource,
+        oracle: Pubkey,
+        whole_units: bool,
+    },
+    // Mark a market's registry entry as canonical or not; signed by the
+    // Config admin
+    SetCanonicalMarket {
+        canonical: bool,
+    },
+    // Create the caller's OpenOrders PDA for markets quoted in the given mint
+    InitOpenOrders,
+    // Transfer up to `max_base` and `max_quote` of the free balances of one
+    // market in an OpenOrders account to token accounts of its withdraw
+    // authority, who signs, or of `recipient` when one is named, and any
+    // refunded order deposits to the withdraw authority. Its missing
+    // associated token accounts are created, paid by it, when the mints and
+    // programs are passed as optional trailing accounts. Instruction data
+    // holding only the tag settles everything to the withdraw authority.
+    SettleFunds {
+        max_base: u64,
+        max_quote: u64,
+        recipient: Option<Pubkey>,
+    },
+    // Settle every market passed, like SettleFunds, into the withdraw
+    // authority's associated token accounts, creating them when they do not
+    // exist yet
+    SettleAndWithdrawAll,
+    // Transfer a market's outstanding fees to a quote token account of its
+    // fee collector; anyone may crank it
+    SweepFees,
+    // Transfer a market's outstanding protocol fees to a quote token account
+    // of the Config treasury; anyone may crank it
+    SweepProtocolFees,
+    // Register or remove a backstop maker; signed by the market creator
+    SetBackstopMaker {
+        maker: Pubkey,
+        backstop: bool,
+    },
+    // Deposit quote tokens into the market's incentives, which subsidize the
+    // taker fees of fills against backstop makers
+    FundIncentives {
+        amount: u64,
+    },
+    // Fire a trailing stop whose trigger price the last trade reached,
+    // matching it as a limit order and paying its bounty to the keeper
+    TriggerStopOrder {
+        order_id: u64,
+    },
+    // Match an order whose price improvement window has ended, paying its
+    // bounty to the keeper
+    SettleAuction {
+        order_id: u64,
+    },
+    // Group an OpenOrders account under a beneficial owner, whose accounts
+    // never trade against each other; signed by the account owner and the
+    // beneficial owner
+    SetBeneficialOwner {
+        beneficial_owner: Pubkey,
+    },
+    // Change the oracle a market reads prices from; signed by the market
+    // creator
+    SetOracle {
+        oracle_source: OracleSource,
+        oracle: Pubkey,
+    },
+    // Create a market's PriceOracle PDA, paid by the signer; anyone may
+    // create it
+    InitPriceOracle,
+    // Refresh a market's PriceOracle PDA from the order book; anyone may
+    // crank it
+    UpdatePriceOracle,
+    // Create a market's TradeHistory PDA holding its last `capacity` fills;
+    // signed by the market creator, who pays for it. Instructions that match
+    // orders then take the account first among their trailing accounts.
+    InitTradeHistory {
+        capacity: u32,
+    },
+    // Create a CandleSeries PDA aggregating a market's fills into candles of
+    // `interval` seconds, keeping the last `capacity`; signed by the market
+    // creator, who pays for it. A market keeps up to
+    // `OrderBook::MAX_CANDLE_SERIES` intervals, whose accounts instructions
+    // that match orders take after the TradeHistory account, in the order
+    // they were created.
+    InitCandleSeries {
+        interval: u32,
+        capacity: u32,
+    },
+    // Return the candles of a CandleSeries starting in
+    // [`start_time`, `end_time`) as a CandlePage
+    GetCandles {
+        start_time: UnixTimestamp,
+        end_time: UnixTimestamp,
+    },
+    // Return the market's open interest and unique traders of the current
+    // epoch as MarketStats
+    GetMarketStats,
+    // Return the program and account layout versions and the enabled
+    // features as a ProgramVersion
+    GetVersion,
+    // Set the bitmask of `OrderBook::FEATURE_*` capabilities disabled on a
+    // market; signed by the market creator
+    SetMarketFeatures {
+        disabled_features: u8,
+    },
+    // Match the crossed portion of a book, making at most `max_fills` fills,
+    // as `OrderBook::uncross` describes. The fills charge no taker fee, as
+    // neither side chose to take. Anyone may crank it, passing the OpenOrders
+    // accounts of the crossed orders' owners after the market's feeds.
+    HealBook {
+        max_fills: u16,
+    },
+    // Create a market's LaunchAuction PDA and deposit the `supply` of base
+    // it sells into the base vault; signed by the market creator, who sells
+    // and pays for it. Bids are committed until `bid_end` and revealed until
+    // `reveal_end`, and reveal no price below `reserve_price`.
+    InitLaunchAuction {
+        supply: u64,
+        reserve_price: u64,
+        bid_end: UnixTimestamp,
+        reveal_end: UnixTimestamp,
+    },
+    // Commit a sealed bid for `quantity` base, locking `deposit` quote in
+    // the bidder's OpenOrders account. `commitment` is
+    // `LaunchAuction::commitment` of the bid price and a secret salt.
+    CommitLaunchBid {
+        quantity: u64,
+        deposit: u64,
+        commitment: [u8; 32],
+    },
+    // Reveal the price of the bidder's sealed bid committed to `price` and
+    // `salt`; the deposit must cover the bid's quantity at that price
+    RevealLaunchBid {
+        price: u64,
+        salt: [u8; 32],
+    },
+    // Clear a launch auction whose reveal window has ended, crediting the
+    // proceeds and unsold supply to the seller's OpenOrders account; anyone
+    // may crank it
+    ClearLaunchAuction,
+    // Credit the allocations and unspent deposits of the cleared bids of an
+    // OpenOrders account's owner; anyone may crank it
+    SettleLaunchBids,
+    // Cancel up to `max_count` of the trader's resting orders on `side` at
+    // or through `limit_price`, bids at or above it and asks at or below it,
+    // most aggressive first, as CancelOrder does for each
+    CancelOrdersByPrice {
+        side: OrderType,
+        limit_price: u64,
+        max_count: u16,
+    },
+    // Return the status of each of up to `OrderStatusReport::MAX_ORDERS`
+    // order ids as an OrderStatusReport
+    GetOrderStatuses {
+        order_ids: Vec<u64>,
+    },
+    // Create the FillReceipt PDA of an order of the signer that filled
+    // completely, paying its rent. The order must still be among the closed
+    // orders the market remembers.
+    WriteFillReceipt {
+        order_id: u64,
+    },
+    // Close a FillReceipt PDA, returning its rent to the owner who signs
+    CloseFillReceipt,
+    // Set the lamports every PlaceOrder pays into the market's crank
+    // incentives, at most `OrderBook::MAX_PLACEMENT_FEE_LAMPORTS`; signed by
+    // the market creator
+    SetPlacementFee {
+        lamports: u64,
+    },
+    // Set the guardians that may pause the protocol and how many of them must
+    // sign SetPaused; signed by the Config admin
+    SetGuardians {
+        pause_threshold: u8,
+        guardians: Vec<Pubkey>,
+    },
+    // Pause or resume placing and matching orders on every market; signed by
+    // the Config's threshold of guardians, passed after the Config
+    SetPaused {
+        paused: bool,
+    },
+    // Cancel up to `max_orders` expired resting orders found through the
+    // book's expiration wheel, soonest expiring first, as CancelExpiredOrder
+    // cancels each. Anyone may crank it, passing the keeper's KeeperBond, if
+    // it has one, then the OpenOrders accounts of the expired orders'
+    // owners. During a backlog the keeper's bond decides how soon after
+    // expiring it may prune an order; see `KeeperBond`.
+    PruneExpiredOrders {
+        max_orders: u8,
+    },
+    // Return one MarketDepth page of up to `max_levels` price levels of each
+    // side starting at `cursor`, read from the book's price level index. The
+    // cursor was appended and defaults to 0 when absent.
+    GetDepth {
+        max_levels: u8,
+        cursor: u32,
+    },
+    // Set the market's fee rounding policy; signed by the market creator and
+    // refused while orders wait off the book with fees reserved under the
+    // current one
+    SetRoundingPolicy {
+        policy: RoundingPolicy,
+    },
+    // Fill a taker order across up to `MAX_ROUTE_MARKETS` markets of the same
+    // pair, best price first, and cancel whatever does not fill at or
+    // through `limit_price`. Fails unless at least `min_fill_amount` fills.
+    // The accounts after the Config come in one group per market, of
+    // `market_account_counts` accounts each: the order book, its vault for
+    // the side, its feeds and the OpenOrders accounts of the makers filled.
+    RouteOrder {
+        side: OrderType,
+        amount: u64,
+        limit_price: u64,
+        min_fill_amount: u64,
+        market_account_counts: Vec<u8>,
+    },
+    // Move the liquidity of a deprecated market to its successor, a market
+    // of the same pair and creator, who signs. The first call names the
+    // successor, after which the deprecated market takes no new orders.
+    // Each call cancels up to `max_orders` of its orders, refunding their
+    // deposits to the owners' OpenOrders accounts, passed after the fixed
+    // accounts. Once no orders remain, it moves the free balances of the
+    // passed OpenOrders accounts, and the tokens backing them, to the
+    // successor.
+    MigrateLiquidity {
+        max_orders: u8,
+    },
+    // Deposit quote tokens into the rewards of the market's current fee
+    // epoch, shared by its makers by volume once the epoch closes
+    FundEpochRewards {
+        amount: u64,
+    },
+    // Credit the owner's share of the rewards of the closed fee epoch it
+    // made volume in to its free quote balance
+    ClaimEpochRewards,
+    // Hand the right to withdraw from an OpenOrders account to another key;
+    // signed by the current withdraw authority and the new one
+    SetWithdrawAuthority {
+        withdraw_authority: Pubkey,
+    },
+    // Require PlaceOrder on the market to come through CPI from the
+    // `order_gate` program, or lift the requirement with the default key;
+    // signed by the market creator
+    SetOrderGate {
+        order_gate: Pubkey,
+    },
+    // Switch the market into priority auction mode, where takers of a slot
+    // wait and are matched by their priority bids, or back to arrival
+    // order; signed by the market creator
+    SetPriorityAuction {
+        enabled: bool,
+    },
+    // Set the cancel cutoff of an OpenOrders account, after which any of the
+    // owner's orders placed in a later slot can be canceled by anyone with
+    // CancelExpiredOrder, or lift it with zero; signed by the owner
+    CancelAllAfter {
+        slot: Slot,
+    },
+    // Cross the orders trading at settlement at `settle_at` at the market's
+    // reference price, paying their bounties to the keeper, or cancel them
+    // once the reference was missed. Anyone may crank it, passing the
+    // OpenOrders accounts of the orders' owners.
+    SettleReferenceOrders {
+        settle_at: UnixTimestamp,
+    },
+    // Return one OrderPage of `trader`'s orders starting at `cursor`, which
+    // counts resting buy and sell orders, then stop, pending and reference
+    // orders
+    GetOpenOrders {
+        trader: Pubkey,
+        cursor: u32,
+    },
+    // Create the Blocklist PDA of the signing authority
+    InitBlocklist,
+    // List or delist `address` on a Blocklist; signed by its authority
+    UpdateBlocklist {
+        address: Pubkey,
+        blocked: bool,
+    },
+    // Opt the market into the `blocklist` Blocklist, or out of any with the
+    // default key; signed by the market creator
+    SetBlocklist {
+        blocklist: Pubkey,
+    },
+    // Bond `lamports` in the signing keeper's KeeperBond PDA, creating it on
+    // the first bond, for priority in PruneExpiredOrders during a backlog
+    BondKeeper {
+        lamports: u64,
+    },
+    // Return `lamports` bonded in the signing keeper's KeeperBond PDA once
+    // its lock has passed
+    UnbondKeeper {
+        lamports: u64,
+    },
+    // Run the wrapped instruction, with its accounts, then fail with
+    // `OrderBookError::DryRunComplete` so the runtime rolls back its state
+    // changes and CPIs. Simulating it shows the instruction's exact fills,
+    // fees, logs and return data, or the error it would fail with, under
+    // the current state. Dry runs do not nest.
+    DryRun(Box<OrderBookInstruction>),
+    // Return an InventoryReport of the passed OpenOrders account's orders
+    // and balances on the market
+    GetInventory,
+    // Set the requirements InitializeMarket enforces on new markets; signed
+    // by the Config admin
+    SetListingRequirements(ListingRequirements),
+    // Move `base_amount` and `quote_amount` of the free balances of one
+    // market in an OpenOrders account to another market in the same
+    // account, with the tokens backing them, vault to vault. The owner
+    // signs; the funds never leave the account, so its withdraw authority
+    // need not. Base only moves between markets of the same base mint. The
+    // Blocklists of markets that enforce one follow, and a listed owner,
+    // beneficial owner or withdraw authority is refused.
+    TransferFreeBalance {
+        base_amount: u64,
+        quote_amount: u64,
+    },
+    // Set the age after which anyone may prune a resting order with
+    // PruneOrder, at least `OrderBook::MIN_PRUNE_AFTER_SLOTS`, or zero to
+    // turn pruning off; signed by the market creator
+    SetPruneAfter {
+        slots: Slot,
+    },
+    // Set the most orders each OpenOrders account may place in the market
+    // per slot, or zero to lift the limit; signed by the market creator
+    SetOrderRateLimit {
+        max_orders_per_slot: u16,
+    },
+    // Set the widest oracle confidence interval the market accepts, in basis
+    // points of the oracle price, or zero to accept any; signed by the market
+    // creator
+    SetOracleConfidence {
+        max_conf_bps: u16,
+    },
+}
+
+// Define what an instruction requires of one of its leading accounts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AccountSpec {
+    pub name: &'static str,
+    pub writable: bool,
+    pub signer: bool,
+}
+
+impl AccountSpec {
+    const fn new(name: &'static str, writable: bool, signer: bool) -> Self {
+        AccountSpec {
+            name,
+            writable,
+            signer,
+        }
+    }
+
+    const fn readonly(name: &'static str) -> Self {
+        Self::new(name, false, false)
+    }
+
+    const fn writable(name: &'static str) -> Self {
+        Self::new(name, true, false)
+    }
+
+    const fn signer(name: &'static str) -> Self {
+        Self::new(name, false, true)
+    }
+
+    const fn writable_signer(name: &'static str) -> Self {
+        Self::new(name, true, true)
+    }
+}
+
+impl OrderBookInstruction {
+    // Highest instruction tag understood by this program version
+    pub const LATEST_TAG: u8 = 67;
+    // Tag prefixed to an instruction's data to dry-run it
+    pub const DRY_RUN_TAG: u8 = 61;
+
+    // Most markets a RouteOrder may be routed across
+    pub const MAX_ROUTE_MARKETS: usize = 4;
+
+    // Pack the instruction data into a byte array
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            OrderBookInstruction::PlaceOrder(order) => {
+                buf.push(0);
+                buf.extend_from_slice(&order.pack());
+            }
+            OrderBookInstruction::GetBestBuyOrder => {
+                buf.push(1);
+            }
+            OrderBookInstruction::GetBestSellOrder => {
+                buf.push(2);
+            }
+            OrderBookInstruction::ExportBook { cursor } => {
+                buf.push(3);
+                buf.extend_from_slice(&cursor.to_le_bytes());
+            }
+            OrderBookInstruction::VerifyMarket => {
+                buf.push(4);
+            }
+            OrderBookInstruction::CancelOrder { order_id } => {
+                buf.push(5);
+                buf.extend_from_slice(&order_id.to_le_bytes());
+            }
+            OrderBookInstruction::PruneOrder { order_id } => {
+                buf.push(6);
+                buf.extend_from_slice(&order_id.to_le_bytes());
+            }
+            OrderBookInstruction::CancelExpiredOrder { order_id } => {
+                buf.push(7);
+                buf.extend_from_slice(&order_id.to_le_bytes());
+            }
+            OrderBookInstruction::InitializeConfig {
+                default_tick_size,
+                default_lot_size,
+                unique_markets,
+                protocol_fee_bps,
+            } => {
+                buf.push(8);
+                buf.extend_from_slice(&default_tick_size.to_le_bytes());
+                buf.extend_from_slice(&default_lot_size.to_le_bytes());
+                buf.push(*unique_markets as u8);
+                buf.extend_from_slice(&protocol_fee_bps.to_le_bytes());
+            }
+            OrderBookInstruction::InitializeMarket {
+                tick_size,
+                lot_size,
+                buy_taker_fee_bps,
+                sell_taker_fee_bps,
+                backstop_discount_bps,
+                dark_pool,
+                oracle_source,
+                oracle,
+                whole_units,
+            } => {
+                buf.push(9);
+                buf.extend_from_slice(&tick_size.to_le_bytes());
+                buf.extend_from_slice(&lot_size.to_le_bytes());
+                buf.extend_from_slice(&buy_taker_fee_bps.to_le_bytes());
+                buf.extend_from_slice(&sell_taker_fee_bps.to_le_bytes());
+                buf.extend_from_slice(&backstop_discount_bps.to_le_bytes());
+                buf.push(*dark_pool as u8);
+                buf.push(*oracle_source as u8);
+                buf.extend_from_slice(&oracle.to_bytes());
+                buf.push(*whole_units as u8);
+            }
+            OrderBookInstruction::SetCanonicalMarket { canonical } => {
+                buf.push(10);
+                buf.push(*canonical as u8);
+            }
+            OrderBookInstruction::InitOpenOrders => {
+                buf.push(11);
+            }
+            OrderBookInstruction::SettleFunds {
+                max_base,
+                max_quote,
+                recipient,
+            } => {
+                buf.push(12);
+                buf.extend_from_slice(&max_base.to_le_bytes());
+                buf.extend_from_slice(&max_quote.to_le_bytes());
+                buf.push(recipient.is_some() as u8);
+                if let Some(recipient) = recipient {
+                    buf.extend_from_slice(recipient.as_ref());
+                }
+            }
+            OrderBookInstruction::SettleAndWithdrawAll => {
+                buf.push(13);
+            }
+            OrderBookInstruction::SweepFees => {
+                buf.push(14);
+            }
+            OrderBookInstruction::SweepProtocolFees => {
+                buf.push(15);
+            }
+            OrderBookInstruction::SetBackstopMaker { maker, backstop } => {
+                buf.push(16);
+                buf.extend_from_slice(maker.as_ref());
+                buf.push(*backstop as u8);
+            }
+            OrderBookInstruction::FundIncentives { amount } => {
+                buf.push(17);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            OrderBookInstruction::TriggerStopOrder { order_id } => {
+                buf.push(18);
+                buf.extend_from_slice(&order_id.to_le_bytes());
+            }
+            OrderBookInstruction::SettleAuction { order_id } => {
+                buf.push(19);
+                buf.extend_from_slice(&order_id.to_le_bytes());
+            }
+            OrderBookInstruction::SetBeneficialOwner { beneficial_owner } => {
+                buf.push(20);
+                buf.extend_from_slice(&beneficial_owner.to_bytes());
+            }
+            OrderBookInstruction::SetOracle {
+                oracle_source,
+                oracle,
+            } => {
+                buf.push(21);
+                buf.push(*oracle_source as u8);
+                buf.extend_from_slice(&oracle.to_bytes());
+            }
+            OrderBookInstruction::InitPriceOracle => buf.push(22),
+            OrderBookInstruction::UpdatePriceOracle => buf.push(23),
+            OrderBookInstruction::InitTradeHistory { capacity } => {
+                buf.push(24);
+                buf.extend_from_slice(&capacity.to_le_bytes());
+            }
+            OrderBookInstruction::InitCandleSeries { interval, capacity } => {
+                buf.push(25);
+                buf.extend_from_slice(&interval.to_le_bytes());
+                buf.extend_from_slice(&capacity.to_le_bytes());
+            }
+            OrderBookInstruction::GetCandles {
+                start_time,
+                end_time,
+            } => {
+                buf.push(26);
+                buf.extend_from_slice(&start_time.to_le_bytes());
+                buf.extend_from_slice(&end_time.to_le_bytes());
+            }
+            OrderBookInstruction::GetMarketStats => buf.push(27),
+            OrderBookInstruction::GetVersion => buf.push(28),
+            OrderBookInstruction::SetMarketFeatures { disabled_features } => {
+                buf.push(29);
+                buf.push(*disabled_features);
+            }
+            OrderBookInstruction::HealBook { max_fills } => {
+                buf.push(30);
+                buf.extend_from_slice(&max_fills.to_le_bytes());
+            }
+            OrderBookInstruction::InitLaunchAuction {
+                supply,
+                reserve_price,
+                bid_end,
+                reveal_end,
+            } => {
+                buf.push(31);
+                buf.extend_from_slice(&supply.to_le_bytes());
+                buf.extend_from_slice(&reserve_price.to_le_bytes());
+                buf.extend_from_slice(&bid_end.to_le_bytes());
+                buf.extend_from_slice(&reveal_end.to_le_bytes());
+            }
+            OrderBookInstruction::CommitLaunchBid {
+                quantity,
+                deposit,
+                commitment,
+            } => {
+                buf.push(32);
+                buf.extend_from_slice(&quantity.to_le_bytes());
+                buf.extend_from_slice(&deposit.to_le_bytes());
+                buf.extend_from_slice(commitment);
+            }
+            OrderBookInstruction::RevealLaunchBid { price, salt } => {
+                buf.push(33);
+                buf.extend_from_slice(&price.to_le_bytes());
+                buf.extend_from_slice(salt);
+            }
+            OrderBookInstruction::ClearLaunchAuction => buf.push(34),
+            OrderBookInstruction::SettleLaunchBids => buf.push(35),
+            OrderBookInstruction::CancelOrdersByPrice {
+                side,
+                limit_price,
+                max_count,
+            } => {
+                buf.push(36);
+                buf.push(*side as u8);
+                buf.extend_from_slice(&limit_price.to_le_bytes());
+                buf.extend_from_slice(&max_count.to_le_bytes());
+            }
+            OrderBookInstruction::GetOrderStatuses { order_ids } => {
+                buf.push(37);
+                buf.push(order_ids.len() as u8);
+                for order_id in order_ids {
+                    buf.extend_from_slice(&order_id.to_le_bytes());
+                }
+            }
+            OrderBookInstruction::WriteFillReceipt { order_id } => {
+                buf.push(38);
+                buf.extend_from_slice(&order_id.to_le_bytes());
+            }
+            OrderBookInstruction::CloseFillReceipt => buf.push(39),
+            OrderBookInstruction::SetPlacementFee { lamports } => {
+                buf.push(40);
+                buf.extend_from_slice(&lamports.to_le_bytes());
+            }
+            OrderBookInstruction::SetGuardians {
+                pause_threshold,
+                guardians,
+            } => {
+                buf.push(41);
+                buf.push(*pause_threshold);
+                buf.push(guardians.len() as u8);
+                for guardian in guardians {
+                    buf.extend_from_slice(guardian.as_ref());
+                }
+            }
+            OrderBookInstruction::SetPaused { paused } => {
+                buf.push(42);
+                buf.push(*paused as u8);
+            }
+            OrderBookInstruction::PruneExpiredOrders { max_orders } => {
+                buf.push(43);
+                buf.push(*max_orders);
+            }
+            OrderBookInstruction::GetDepth { max_levels, cursor } => {
+                buf.push(44);
+                buf.push(*max_levels);
+                buf.extend_from_slice(&cursor.to_le_bytes());
+            }
+            OrderBookInstruction::SetRoundingPolicy { policy } => {
+                buf.push(45);
+                buf.push(*policy as u8);
+            }
+            OrderBookInstruction::RouteOrder {
+                side,
+                amount,
+                limit_price,
+                min_fill_amount,
+                market_account_counts,
+            } => {
+                buf.push(46);
+                buf.push(*side as u8);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&limit_price.to_le_bytes());
+                buf.extend_from_slice(&min_fill_amount.to_le_bytes());
+                buf.push(market_account_counts.len() as u8);
+                buf.extend_from_slice(market_account_counts);
+            }
+            OrderBookInstruction::MigrateLiquidity { max_orders } => {
+                buf.push(47);
+                buf.push(*max_orders);
+            }
+            OrderBookInstruction::FundEpochRewards { amount } => {
+                buf.push(48);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            OrderBookInstruction::ClaimEpochRewards => {
+                buf.push(49);
+            }
+            OrderBookInstruction::SetWithdrawAuthority { withdraw_authority } => {
+                buf.push(50);
+                buf.extend_from_slice(&withdraw_authority.to_bytes());
+            }
+            OrderBookInstruction::SetOrderGate { order_gate } => {
+                buf.push(51);
+                buf.extend_from_slice(&order_gate.to_bytes());
+            }
+            OrderBookInstruction::SetPriorityAuction { enabled } => {
+                buf.push(52);
+                buf.push(*enabled as u8);
+            }
+            OrderBookInstruction::CancelAllAfter { slot } => {
+                buf.push(53);
+                buf.extend_from_slice(&slot.to_le_bytes());
+            }
+            OrderBookInstruction::SettleReferenceOrders { settle_at } => {
+                buf.push(54);
+                buf.extend_from_slice(&settle_at.to_le_bytes());
+            }
+            OrderBookInstruction::GetOpenOrders { trader, cursor } => {
+                buf.push(55);
+                buf.extend_from_slice(trader.as_ref());
+                buf.extend_from_slice(&cursor.to_le_bytes());
+            }
+            OrderBookInstruction::InitBlocklist => {
+                buf.push(56);
+            }
+            OrderBookInstruction::UpdateBlocklist { address, blocked } => {
+                buf.push(57);
+                buf.extend_from_slice(address.as_ref());
+                buf.push(*blocked as u8);
+            }
+            OrderBookInstruction::SetBlocklist { blocklist } => {
+                buf.push(58);
+                buf.extend_from_slice(blocklist.as_ref());
+            }
+            OrderBookInstruction::BondKeeper { lamports } => {
+                buf.push(59);
+                buf.extend_from_slice(&lamports.to_le_bytes());
+            }
+            OrderBookInstruction::UnbondKeeper { lamports } => {
+                buf.push(60);
+                buf.extend_from_slice(&lamports.to_le_bytes());
+            }
+            OrderBookInstruction::DryRun(instruction) => {
+                buf.push(Self::DRY_RUN_TAG);
+                buf.extend_from_slice(&instruction.pack());
+            }
+            OrderBookInstruction::GetInventory => buf.push(62),
+            OrderBookInstruction::SetListingRequirements(listing) => {
+                buf.push(63);
+                buf.extend_from_slice(&listing.pack());
+            }
+            OrderBookInstruction::TransferFreeBalance {
+                base_amount,
+                quote_amount,
+            } => {
+                buf.push(64);
+                buf.extend_from_slice(&base_amount.to_le_bytes());
+                buf.extend_from_slice(&quote_amount.to_le_bytes());
+            }
+            OrderBookInstruction::SetPruneAfter { slots } => {
+                buf.push(65);
+                buf.extend_from_slice(&slots.to_le_bytes());
+            }
+            OrderBookInstruction::SetOrderRateLimit {
+                max_orders_per_slot,
+            } => {
+                buf.push(66);
+                buf.extend_from_slice(&max_orders_per_slot.to_le_bytes());
+            }
+            OrderBookInstruction::SetOracleConfidence { max_conf_bps } => {
+                buf.push(67);
+                buf.extend_from_slice(&max_conf_bps.to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    // Unpack the instruction data from a byte array
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let (tag, rest) = data
+            .split_first()
+            .ok_or(ProgramError::InvalidInstructionData)?;
+        match tag {
+            0 => {
+                let order = Order::unpack_payload(rest).map_err(|err| {
+                    msg!("Failed to unpack PlaceOrder instruction data: {:?}", err);
+                    ProgramError::InvalidInstructionData
+                })?;
+                Ok(OrderBookInstruction::PlaceOrder(order))
+            }
+            1 => Ok(OrderBookInstruction::GetBestBuyOrder),
+            2 => Ok(OrderBookInstruction::GetBestSellOrder),
+            3 => {
+                let cursor = rest
+                    .get(..4)
+                    .map(|bytes| u32::from_le_bytes(*array_ref![bytes, 0, 4]))
+                    .ok_or(ProgramError::InvalidInstructionData)?;
+                Ok(OrderBookInstruction::ExportBook { cursor })
+            }
+            4 => Ok(OrderBookInstruction::VerifyMarket),
+            5 => Ok(OrderBookInstruction::CancelOrder {
+                order_id: Self::unpack_u64(rest)?,
+            }),
+            6 => Ok(OrderBookInstruction::PruneOrder {
+                order_id: Self::unpack_u64(rest)?,
+            }),
+            7 => Ok(OrderBookInstruction::CancelExpiredOrder {
+                order_id: Self::unpack_u64(rest)?,
+            }),
+            8 => Ok(OrderBookInstruction::InitializeConfig {
+                default_tick_size: Self::unpack_u64(rest)?,
+                default_lot_size: Self::unpack_u64(rest.get(8..).unwrap_or_default())?,
+                unique_markets: rest.get(16).is_some_and(|flag| *flag != 0),
+                protocol_fee_bps: rest
+                    .get(17..19)
+                    .map_or(0, |bytes| u16::from_le_bytes(*array_ref![bytes, 0, 2])),
+            }),
+            9 => {
+                let buy_taker_fee_bps = rest
+                    .get(16..18)
+                    .map_or(0, |bytes| u16::from_le_bytes(*array_ref![bytes, 0, 2]));
+                Ok(OrderBookInstruction::InitializeMarket {
+                    tick_size: Self::unpack_u64(rest)?,
+                    lot_size: Self::unpack_u64(rest.get(8..).unwrap_or_default())?,
+                    buy_taker_fee_bps,
+                    sell_taker_fee_bps: rest.get(18..20).map_or(buy_taker_fee_bps, |bytes| {
+                        u16::from_le_bytes(*array_ref![bytes, 0, 2])
+                    }),
+                    backstop_discount_bps: rest
+                        .get(20..22)
+                        .map_or(0, |bytes| u16::from_le_bytes(*array_ref![bytes, 0, 2])),
+                    dark_pool: rest.get(22).is_some_and(|flag| *flag != 0),
+                    oracle_source: rest
+                        .get(23)
+                        .map_or(Ok(OracleSource::None), |tag| OracleSource::from_u8(*tag))?,
+                    oracle: rest.get(24..56).map_or(Pubkey::default(), |bytes| {
+                        Pubkey::new_from_array(*array_ref![bytes, 0, 32])
+                    }),
+                    whole_units: rest.get(56).is_some_and(|flag| *flag != 0),
+                })
+            }
+            10 => Ok(OrderBookInstruction::SetCanonicalMarket {
+                canonical: *rest.first().ok_or(ProgramError::InvalidInstructionData)? != 0,
+            }),
+            11 => Ok(OrderBookInstruction::InitOpenOrders),
+            12 if rest.is_empty() => Ok(OrderBookInstruction::SettleFunds {
+                max_base: u64::MAX,
+                max_quote: u64::MAX,
+                recipient: None,
+            }),
+            12 => Ok(OrderBookInstruction::SettleFunds {
+                max_base: Self::unpack_u64(rest)?,
+                max_quote: Self::unpack_u64(rest.get(8..).unwrap_or_default())?,
+                recipient: match rest.get(16) {
+                    Some(0) => None,
+                    Some(_) => Some(
+                        rest.get(17..49)
+                            .map(|bytes| Pubkey::new_from_array(*array_ref![bytes, 0, 32]))
+                            .ok_or(ProgramError::InvalidInstructionData)?,
+                    ),
+                    None => return Err(ProgramError::InvalidInstructionData),
+                },
+            }),
+            13 => Ok(OrderBookInstruction::SettleAndWithdrawAll),
+            14 => Ok(OrderBookInstruction::SweepFees),
+            15 => Ok(OrderBookInstruction::SweepProtocolFees),
+            16 => Ok(OrderBookInstruction::SetBackstopMaker {
+                maker: rest
+                    .get(..32)
+                    .map(|bytes| Pubkey::new_from_array(*array_ref![bytes, 0, 32]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+                backstop: *rest.get(32).ok_or(ProgramError::InvalidInstructionData)? != 0,
+            }),
+            17 => Ok(OrderBookInstruction::FundIncentives {
+                amount: Self::unpack_u64(rest)?,
+            }),
+            18 => Ok(OrderBookInstruction::TriggerStopOrder {
+                order_id: Self::unpack_u64(rest)?,
+            }),
+            19 => Ok(OrderBookInstruction::SettleAuction {
+                order_id: Self::unpack_u64(rest)?,
+            }),
+            20 => Ok(OrderBookInstruction::SetBeneficialOwner {
+                beneficial_owner: rest
+                    .get(..32)
+                    .map(|bytes| Pubkey::new_from_array(*array_ref![bytes, 0, 32]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            21 => Ok(OrderBookInstruction::SetOracle {
+                oracle_source: OracleSource::from_u8(
+                    *rest.first().ok_or(ProgramError::InvalidInstructionData)?,
+                )?,
+                oracle: rest
+                    .get(1..33)
+                    .map(|bytes| Pubkey::new_from_array(*array_ref![bytes, 0, 32]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            22 => Ok(OrderBookInstruction::InitPriceOracle),
+            23 => Ok(OrderBookInstruction::UpdatePriceOracle),
+            24 => Ok(OrderBookInstruction::InitTradeHistory {
+                capacity: rest
+                    .get(..4)
+                    .map(|bytes| u32::from_le_bytes(*array_ref![bytes, 0, 4]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            25 => {
+                let u32_at = |offset: usize| {
+                    rest.get(offset..offset + 4)
+                        .map(|bytes| u32::from_le_bytes(*array_ref![bytes, 0, 4]))
+                        .ok_or(ProgramError::InvalidInstructionData)
+                };
+                Ok(OrderBookInstruction::InitCandleSeries {
+                    interval: u32_at(0)?,
+                    capacity: u32_at(4)?,
+                })
+            }
+            26 => Ok(OrderBookInstruction::GetCandles {
+                start_time: Self::unpack_u64(rest)? as i64,
+                end_time: Self::unpack_u64(rest.get(8..).unwrap_or_default())? as i64,
+            }),
+            27 => Ok(OrderBookInstruction::GetMarketStats),
+            28 => Ok(OrderBookInstruction::GetVersion),
+            29 => Ok(OrderBookInstruction::SetMarketFeatures {
+                disabled_features: *rest.first().ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            30 => Ok(OrderBookInstruction::HealBook {
+                max_fills: rest
+                    .get(..2)
+                    .map(|bytes| u16::from_le_bytes(*array_ref![bytes, 0, 2]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            31 => {
+                let u64_at =
+                    |offset: usize| Self::unpack_u64(rest.get(offset..).unwrap_or_default());
+                Ok(OrderBookInstruction::InitLaunchAuction {
+                    supply: u64_at(0)?,
+                    reserve_price: u64_at(8)?,
+                    bid_end: u64_at(16)? as i64,
+                    reveal_end: u64_at(24)? as i64,
+                })
+            }
+            32 => Ok(OrderBookInstruction::CommitLaunchBid {
+                quantity: Self::unpack_u64(rest)?,
+                deposit: Self::unpack_u64(rest.get(8..).unwrap_or_default())?,
+                commitment: Self::unpack_hash(rest.get(16..).unwrap_or_default())?,
+            }),
+            33 => Ok(OrderBookInstruction::RevealLaunchBid {
+                price: Self::unpack_u64(rest)?,
+                salt: Self::unpack_hash(rest.get(8..).unwrap_or_default())?,
+            }),
+            34 => Ok(OrderBookInstruction::ClearLaunchAuction),
+            35 => Ok(OrderBookInstruction::SettleLaunchBids),
+            36 => {
+                let side = match rest.first() {
+                    Some(0) => OrderType::Buy,
+                    Some(1) => OrderType::Sell,
+                    _ => return Err(ProgramError::InvalidInstructionData),
+                };
+                Ok(OrderBookInstruction::CancelOrdersByPrice {
+                    side,
+                    limit_price: Self::unpack_u64(rest.get(1..).unwrap_or_default())?,
+                    max_count: rest
+                        .get(9..11)
+                        .map(|bytes| u16::from_le_bytes(*array_ref![bytes, 0, 2]))
+                        .ok_or(ProgramError::InvalidInstructionData)?,
+                })
+            }
+            37 => {
+                let (count, ids) = rest
+                    .split_first()
+                    .ok_or(ProgramError::InvalidInstructionData)?;
+                let order_ids = (0..*count as usize)
+                    .map(|i| Self::unpack_u64(ids.get(i * 8..).unwrap_or_default()))
+                    .collect::<Result<_, _>>()?;
+                Ok(OrderBookInstruction::GetOrderStatuses { order_ids })
+            }
+            38 => Ok(OrderBookInstruction::WriteFillReceipt {
+                order_id: Self::unpack_u64(rest)?,
+            }),
+            39 => Ok(OrderBookInstruction::CloseFillReceipt),
+            40 => Ok(OrderBookInstruction::SetPlacementFee {
+                lamports: Self::unpack_u64(rest)?,
+            }),
+            41 => {
+                let [pause_threshold, count, keys @ ..] = rest else {
+                    return Err(ProgramError::InvalidInstructionData);
+                };
+                let guardians = (0..*count as usize)
+                    .map(|i| {
+                        keys.get(i * 32..i * 32 + 32)
+                            .map(|bytes| Pubkey::new_from_array(*array_ref![bytes, 0, 32]))
+                            .ok_or(ProgramError::InvalidInstructionData)
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(OrderBookInstruction::SetGuardians {
+                    pause_threshold: *pause_threshold,
+                    guardians,
+                })
+            }
+            42 => Ok(OrderBookInstruction::SetPaused {
+                paused: *rest.first().ok_or(ProgramError::InvalidInstructionData)? != 0,
+            }),
+            43 => Ok(OrderBookInstruction::PruneExpiredOrders {
+                max_orders: *rest.first().ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            44 => Ok(OrderBookInstruction::GetDepth {
+                max_levels: *rest.first().ok_or(ProgramError::InvalidInstructionData)?,
+                cursor: rest
+                    .get(1..5)
+                    .map(|bytes| u32::from_le_bytes(*array_ref![bytes, 0, 4]))
+                    .unwrap_or_default(),
+            }),
+            45 => Ok(OrderBookInstruction::SetRoundingPolicy {
+                policy: RoundingPolicy::from_u8(
+                    *rest.first().ok_or(ProgramError::InvalidInstructionData)?,
+                )?,
+            }),
+            46 => {
+                let side = match rest.first() {
+                    Some(0) => OrderType::Buy,
+                    Some(1) => OrderType::Sell,
+                    _ => return Err(ProgramError::InvalidInstructionData),
+                };
+                let u64_at =
+                    |offset: usize| Self::unpack_u64(rest.get(offset..).unwrap_or_default());
+                let (count, counts) = rest
+                    .get(25..)
+                    .and_then(|bytes| bytes.split_first())
+                    .ok_or(ProgramError::InvalidInstructionData)?;
+                Ok(OrderBookInstruction::RouteOrder {
+                    side,
+                    amount: u64_at(1)?,
+                    limit_price: u64_at(9)?,
+                    min_fill_amount: u64_at(17)?,
+                    market_account_counts: counts
+                        .get(..*count as usize)
+                        .ok_or(ProgramError::InvalidInstructionData)?
+                        .to_vec(),
+                })
+            }
+            47 => Ok(OrderBookInstruction::MigrateLiquidity {
+                max_orders: *rest.first().ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            48 => Ok(OrderBookInstruction::FundEpochRewards {
+                amount: Self::unpack_u64(rest)?,
+            }),
+            49 => Ok(OrderBookInstruction::ClaimEpochRewards),
+            50 => Ok(OrderBookInstruction::SetWithdrawAuthority {
+                withdraw_authority: rest
+                    .get(..32)
+                    .map(|bytes| Pubkey::new_from_array(*array_ref![bytes, 0, 32]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            51 => Ok(OrderBookInstruction::SetOrderGate {
+                order_gate: rest
+                    .get(..32)
+                    .map(|bytes| Pubkey::new_from_array(*array_ref![bytes, 0, 32]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            52 => Ok(OrderBookInstruction::SetPriorityAuction {
+                enabled: *rest.first().ok_or(ProgramError::InvalidInstructionData)? != 0,
+            }),
+            53 => Ok(OrderBookInstruction::CancelAllAfter {
+                slot: Self::unpack_u64(rest)?,
+            }),
+            54 => Ok(OrderBookInstruction::SettleReferenceOrders {
+                settle_at: Self::unpack_u64(rest)? as i64,
+            }),
+            55 => Ok(OrderBookInstruction::GetOpenOrders {
+                trader: rest
+                    .get(..32)
+                    .map(|bytes| Pubkey::new_from_array(*array_ref![bytes, 0, 32]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+                cursor: rest
+                    .get(32..36)
+                    .map(|bytes| u32::from_le_bytes(*array_ref![bytes, 0, 4]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            56 => Ok(OrderBookInstruction::InitBlocklist),
+            57 => Ok(OrderBookInstruction::UpdateBlocklist {
+                address: rest
+                    .get(..32)
+                    .map(|bytes| Pubkey::new_from_array(*array_ref![bytes, 0, 32]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+                blocked: *rest.get(32).ok_or(ProgramError::InvalidInstructionData)? != 0,
+            }),
+            58 => Ok(OrderBookInstruction::SetBlocklist {
+                blocklist: rest
+                    .get(..32)
+                    .map(|bytes| Pubkey::new_from_array(*array_ref![bytes, 0, 32]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            59 => Ok(OrderBookInstruction::BondKeeper {
+                lamports: Self::unpack_u64(rest)?,
+            }),
+            60 => Ok(OrderBookInstruction::UnbondKeeper {
+                lamports: Self::unpack_u64(rest)?,
+            }),
+            &Self::DRY_RUN_TAG => match Self::unpack(rest)? {
+                OrderBookInstruction::DryRun(_) => {
+                    msg!("Dry runs do not nest");
+                    Err(ProgramError::InvalidInstructionData)
+                }
+                instruction => Ok(OrderBookInstruction::DryRun(Box::new(instruction))),
+            },
+            62 => Ok(OrderBookInstruction::GetInventory),
+            63 => Ok(OrderBookInstruction::SetListingRequirements(
+                ListingRequirements::unpack(rest)?,
+            )),
+            64 => Ok(OrderBookInstruction::TransferFreeBalance {
+                base_amount: Self::unpack_u64(rest)?,
+                quote_amount: Self::unpack_u64(rest.get(8..).unwrap_or_default())?,
+            }),
+            65 => Ok(OrderBookInstruction::SetPruneAfter {
+                slots: Self::unpack_u64(rest)?,
+            }),
+            66 => Ok(OrderBookInstruction::SetOrderRateLimit {
+                max_orders_per_slot: rest
+                    .get(..2)
+                    .map(|bytes| u16::from_le_bytes(*array_ref![bytes, 0, 2]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            67 => Ok(OrderBookInstruction::SetOracleConfidence {
+                max_conf_bps: rest
+                    .get(..2)
+                    .map(|bytes| u16::from_le_bytes(*array_ref![bytes, 0, 2]))
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            _ => {
+                msg!(
+                    "Instruction tag {} is not supported by this program version (latest tag {})",
+                    tag,
+                    Self::LATEST_TAG
+                );
+                Err(OrderBookError::UnsupportedInstructionVersion.into())
+            }
+        }
+    }
+
+    // Return the accounts every invocation of the instruction must pass, in
+    // order. Optional and variable-length trailing accounts, such as the
+    // matching feeds and makers, are not listed. Only instructions that
+    // write the order book lock it writable, so queries, oracle updates and
+    // settlement run in parallel with trading on the market.
+    pub fn expected_accounts(&self) -> &'static [AccountSpec] {
+        const ORDER_BOOK_QUERY_ACCOUNTS: &[AccountSpec] = &[AccountSpec::readonly("order_book")];
+        const CREATOR_SETTING_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::signer("creator"),
+        ];
+        const KEEPER_MATCHING_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::writable("keeper"),
+            AccountSpec::readonly("clock"),
+            AccountSpec::writable("open_orders"),
+            AccountSpec::readonly("config"),
+        ];
+        const INIT_MARKET_FEED_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("feed"),
+            AccountSpec::writable("order_book"),
+            AccountSpec::writable_signer("creator"),
+            AccountSpec::readonly("system_program"),
+        ];
+        const SWEEP_FEES_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::writable("quote_vault"),
+            AccountSpec::writable("destination"),
+            AccountSpec::readonly("vault_signer"),
+            AccountSpec::readonly("token_program"),
+            AccountSpec::readonly("clock"),
+            AccountSpec::readonly("config"),
+        ];
+        const PLACE_ORDER_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::writable_signer("trader"),
+            AccountSpec::readonly("clock"),
+            AccountSpec::readonly("system_program"),
+            AccountSpec::writable("open_orders"),
+            AccountSpec::writable("trader_token"),
+            AccountSpec::writable("vault"),
+            AccountSpec::readonly("token_program"),
+            AccountSpec::readonly("config"),
+        ];
+        const CANCEL_ORDER_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::writable_signer("trader"),
+            AccountSpec::writable("open_orders"),
+        ];
+        const PRUNE_ORDER_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::writable("pruner"),
+            AccountSpec::readonly("clock"),
+            AccountSpec::writable("open_orders"),
+        ];
+        const CANCEL_EXPIRED_ORDER_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::writable("keeper"),
+            AccountSpec::writable("open_orders"),
+        ];
+        const INITIALIZE_CONFIG_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("config"),
+            AccountSpec::writable_signer("admin"),
+            AccountSpec::readonly("program_data"),
+            AccountSpec::readonly("system_program"),
+        ];
+        const INITIALIZE_MARKET_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::writable_signer("creator"),
+            AccountSpec::readonly("base_mint"),
+            AccountSpec::readonly("quote_mint"),
+            AccountSpec::readonly("config"),
+            AccountSpec::writable("registry_entry"),
+            AccountSpec::readonly("system_program"),
+            AccountSpec::readonly("rent"),
+            AccountSpec::readonly("base_vault"),
+            AccountSpec::readonly("quote_vault"),
+        ];
+        const SET_CANONICAL_MARKET_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::readonly("config"),
+            AccountSpec::signer("admin"),
+            AccountSpec::writable("registry_entry"),
+        ];
+        const INIT_OPEN_ORDERS_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("open_orders"),
+            AccountSpec::writable_signer("owner"),
+            AccountSpec::readonly("quote_mint"),
+            AccountSpec::readonly("system_program"),
+        ];
+        const SETTLE_FUNDS_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::readonly("order_book"),
+            AccountSpec::writable_signer("owner"),
+            AccountSpec::writable("open_orders"),
+            AccountSpec::writable("base_vault"),
+            AccountSpec::writable("quote_vault"),
+            AccountSpec::writable("owner_base"),
+            AccountSpec::writable("owner_quote"),
+            AccountSpec::readonly("vault_signer"),
+            AccountSpec::readonly("token_program"),
+            AccountSpec::readonly("rent"),
+        ];
+        const SETTLE_AND_WITHDRAW_ALL_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable_signer("owner"),
+            AccountSpec::writable("open_orders"),
+            AccountSpec::readonly("quote_mint"),
+            AccountSpec::writable("owner_quote"),
+            AccountSpec::readonly("system_program"),
+            AccountSpec::readonly("token_program"),
+            AccountSpec::readonly("associated_token_program"),
+            AccountSpec::readonly("rent"),
+        ];
+        const FUND_INCENTIVES_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::signer("funder"),
+            AccountSpec::writable("funder_token"),
+            AccountSpec::writable("quote_vault"),
+            AccountSpec::readonly("token_program"),
+        ];
+        const CLAIM_EPOCH_REWARDS_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::signer("owner"),
+            AccountSpec::writable("open_orders"),
+        ];
+        const SET_BENEFICIAL_OWNER_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("open_orders"),
+            AccountSpec::signer("owner"),
+            AccountSpec::signer("beneficial_owner"),
+        ];
+        const SET_WITHDRAW_AUTHORITY_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("open_orders"),
+            AccountSpec::signer("withdraw_authority"),
+            AccountSpec::signer("new_withdraw_authority"),
+        ];
+        const SETTLE_REFERENCE_ORDERS_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::writable("keeper"),
+            AccountSpec::readonly("price_oracle"),
+            AccountSpec::readonly("config"),
+        ];
+        const INIT_BLOCKLIST_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("blocklist"),
+            AccountSpec::writable_signer("authority"),
+            AccountSpec::readonly("system_program"),
+        ];
+        const UPDATE_BLOCKLIST_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("blocklist"),
+            AccountSpec::signer("authority"),
+        ];
+        const GET_INVENTORY_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::readonly("order_book"),
+            AccountSpec::readonly("open_orders"),
+        ];
+        const BOND_KEEPER_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("keeper_bond"),
+            AccountSpec::writable_signer("keeper"),
+            AccountSpec::readonly("system_program"),
+        ];
+        const UNBOND_KEEPER_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("keeper_bond"),
+            AccountSpec::writable_signer("keeper"),
+        ];
+        const CANCEL_ALL_AFTER_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("open_orders"),
+            AccountSpec::signer("owner"),
+        ];
+        const INIT_PRICE_ORACLE_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("price_oracle"),
+            AccountSpec::readonly("order_book"),
+            AccountSpec::writable_signer("payer"),
+            AccountSpec::readonly("system_program"),
+        ];
+        const UPDATE_PRICE_ORACLE_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("price_oracle"),
+            AccountSpec::readonly("order_book"),
+        ];
+        const HEAL_BOOK_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::readonly("config"),
+        ];
+        const CONFIG_ADMIN_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("config"),
+            AccountSpec::signer("admin"),
+        ];
+        const SET_PAUSED_ACCOUNTS: &[AccountSpec] = &[AccountSpec::writable("config")];
+        const PRUNE_EXPIRED_ORDERS_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::writable("keeper"),
+        ];
+        const INIT_LAUNCH_AUCTION_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("launch_auction"),
+            AccountSpec::readonly("order_book"),
+            AccountSpec::writable_signer("creator"),
+            AccountSpec::writable("creator_base"),
+            AccountSpec::writable("base_vault"),
+            AccountSpec::readonly("token_program"),
+            AccountSpec::readonly("system_program"),
+        ];
+        const COMMIT_LAUNCH_BID_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("launch_auction"),
+            AccountSpec::readonly("order_book"),
+            AccountSpec::signer("bidder"),
+            AccountSpec::writable("open_orders"),
+            AccountSpec::writable("bidder_quote"),
+            AccountSpec::writable("quote_vault"),
+            AccountSpec::readonly("token_program"),
+        ];
+        const REVEAL_LAUNCH_BID_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("launch_auction"),
+            AccountSpec::readonly("order_book"),
+            AccountSpec::signer("bidder"),
+        ];
+        const LAUNCH_AUCTION_SETTLEMENT_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("launch_auction"),
+            AccountSpec::readonly("order_book"),
+            AccountSpec::writable("open_orders"),
+        ];
+        const WRITE_FILL_RECEIPT_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("fill_receipt"),
+            AccountSpec::readonly("order_book"),
+            AccountSpec::writable_signer("owner"),
+            AccountSpec::readonly("system_program"),
+        ];
+        const CLOSE_FILL_RECEIPT_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("fill_receipt"),
+            AccountSpec::writable_signer("owner"),
+        ];
+        const ROUTE_ORDER_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable_signer("trader"),
+            AccountSpec::readonly("system_program"),
+            AccountSpec::writable("open_orders"),
+            AccountSpec::writable("trader_token"),
+            AccountSpec::readonly("token_program"),
+            AccountSpec::readonly("config"),
+        ];
+        const MIGRATE_LIQUIDITY_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::writable("order_book"),
+            AccountSpec::writable("successor"),
+            AccountSpec::signer("creator"),
+            AccountSpec::writable("base_vault"),
+            AccountSpec::writable("quote_vault"),
+            AccountSpec::writable("successor_base_vault"),
+            AccountSpec::writable("successor_quote_vault"),
+            AccountSpec::readonly("vault_signer"),
+            AccountSpec::readonly("token_program"),
+        ];
+        const TRANSFER_FREE_BALANCE_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::readonly("order_book"),
+            AccountSpec::readonly("destination"),
+            AccountSpec::signer("owner"),
+            AccountSpec::writable("open_orders"),
+            AccountSpec::writable("base_vault"),
+            AccountSpec::writable("quote_vault"),
+            AccountSpec::writable("destination_base_vault"),
+            AccountSpec::writable("destination_quote_vault"),
+            AccountSpec::readonly("vault_signer"),
+            AccountSpec::readonly("token_program"),
+        ];
+        const GET_CANDLES_ACCOUNTS: &[AccountSpec] = &[
+            AccountSpec::readonly("order_book"),
+            AccountSpec::readonly("candle_series"),
+        ];
+        match self {
+            OrderBookInstruction::PlaceOrder(_) => PLACE_ORDER_ACCOUNTS,
+            OrderBookInstruction::GetBestBuyOrder
+            | OrderBookInstruction::GetBestSellOrder
+            | OrderBookInstruction::VerifyMarket
+            | OrderBookInstruction::GetMarketStats
+            | OrderBookInstruction::GetOrderStatuses { .. }
+            | OrderBookInstruction::ExportBook { .. }
+            | OrderBookInstruction::GetOpenOrders { .. }
+            | OrderBookInstruction::GetDepth { .. } => ORDER_BOOK_QUERY_ACCOUNTS,
+            OrderBookInstruction::CancelOrder { .. }
+            | OrderBookInstruction::CancelOrdersByPrice { .. } => CANCEL_ORDER_ACCOUNTS,
+            OrderBookInstruction::PruneOrder { .. } => PRUNE_ORDER_ACCOUNTS,
+            OrderBookInstruction::CancelExpiredOrder { .. } => CANCEL_EXPIRED_ORDER_ACCOUNTS,
+            OrderBookInstruction::InitializeConfig { .. } => INITIALIZE_CONFIG_ACCOUNTS,
+            OrderBookInstruction::InitializeMarket { .. } => INITIALIZE_MARKET_ACCOUNTS,
+            OrderBookInstruction::SetCanonicalMarket { .. } => SET_CANONICAL_MARKET_ACCOUNTS,
+            OrderBookInstruction::InitOpenOrders => INIT_OPEN_ORDERS_ACCOUNTS,
+            OrderBookInstruction::SettleFunds { .. } => SETTLE_FUNDS_ACCOUNTS,
+            OrderBookInstruction::SettleAndWithdrawAll => SETTLE_AND_WITHDRAW_ALL_ACCOUNTS,
+            OrderBookInstruction::SweepFees => &SWEEP_FEES_ACCOUNTS[..6],
+            OrderBookInstruction::SweepProtocolFees => SWEEP_FEES_ACCOUNTS,
+            OrderBookInstruction::SetBackstopMaker { .. }
+            | OrderBookInstruction::SetOracle { .. }
+            | OrderBookInstruction::SetMarketFeatures { .. }
+            | OrderBookInstruction::SetPlacementFee { .. }
+            | OrderBookInstruction::SetPruneAfter { .. }
+            | OrderBookInstruction::SetOrderRateLimit { .. }
+            | OrderBookInstruction::SetOracleConfidence { .. }
+            | OrderBookInstruction::SetOrderGate { .. }
+            | OrderBookInstruction::SetPriorityAuction { .. }
+            | OrderBookInstruction::SetBlocklist { .. }
+            | OrderBookInstruction::SetRoundingPolicy { .. } => CREATOR_SETTING_ACCOUNTS,
+            OrderBookInstruction::HealBook { .. } => HEAL_BOOK_ACCOUNTS,
+            OrderBookInstruction::InitLaunchAuction { .. } => INIT_LAUNCH_AUCTION_ACCOUNTS,
+            OrderBookInstruction::CommitLaunchBid { .. } => COMMIT_LAUNCH_BID_ACCOUNTS,
+            OrderBookInstruction::RevealLaunchBid { .. } => REVEAL_LAUNCH_BID_ACCOUNTS,
+            OrderBookInstruction::ClearLaunchAuction | OrderBookInstruction::SettleLaunchBids => {
+                LAUNCH_AUCTION_SETTLEMENT_ACCOUNTS
+            }
+            OrderBookInstruction::FundIncentives { .. }
+            | OrderBookInstruction::FundEpochRewards { .. } => FUND_INCENTIVES_ACCOUNTS,
+            OrderBookInstruction::ClaimEpochRewards => CLAIM_EPOCH_REWARDS_ACCOUNTS,
+            OrderBookInstruction::TriggerStopOrder { .. }
+            | OrderBookInstruction::SettleAuction { .. } => KEEPER_MATCHING_ACCOUNTS,
+            OrderBookInstruction::SetBeneficialOwner { .. } => SET_BENEFICIAL_OWNER_ACCOUNTS,
+            OrderBookInstruction::SetWithdrawAuthority { .. } => SET_WITHDRAW_AUTHORITY_ACCOUNTS,
+            OrderBookInstruction::CancelAllAfter { .. } => CANCEL_ALL_AFTER_ACCOUNTS,
+            OrderBookInstruction::InitBlocklist => INIT_BLOCKLIST_ACCOUNTS,
+            OrderBookInstruction::UpdateBlocklist { .. } => UPDATE_BLOCKLIST_ACCOUNTS,
+            OrderBookInstruction::BondKeeper { .. } => BOND_KEEPER_ACCOUNTS,
+            OrderBookInstruction::UnbondKeeper { .. } => UNBOND_KEEPER_ACCOUNTS,
+            OrderBookInstruction::DryRun(instruction) => instruction.expected_accounts(),
+            OrderBookInstruction::GetInventory => GET_INVENTORY_ACCOUNTS,
+            OrderBookInstruction::SettleReferenceOrders { .. } => SETTLE_REFERENCE_ORDERS_ACCOUNTS,
+            OrderBookInstruction::SetGuardians { .. }
+            | OrderBookInstruction::SetListingRequirements(_) => CONFIG_ADMIN_ACCOUNTS,
+            OrderBookInstruction::SetPaused { .. } => SET_PAUSED_ACCOUNTS,
+            OrderBookInstruction::PruneExpiredOrders { .. } => PRUNE_EXPIRED_ORDERS_ACCOUNTS,
+            OrderBookInstruction::InitPriceOracle => INIT_PRICE_ORACLE_ACCOUNTS,
+            OrderBookInstruction::UpdatePriceOracle => UPDATE_PRICE_ORACLE_ACCOUNTS,
+            OrderBookInstruction::InitTradeHistory { .. }
+            | OrderBookInstruction::InitCandleSeries { .. } => INIT_MARKET_FEED_ACCOUNTS,
+            OrderBookInstruction::GetCandles { .. } => GET_CANDLES_ACCOUNTS,
+            OrderBookInstruction::WriteFillReceipt { .. } => WRITE_FILL_RECEIPT_ACCOUNTS,
+            OrderBookInstruction::CloseFillReceipt => CLOSE_FILL_RECEIPT_ACCOUNTS,
+            OrderBookInstruction::RouteOrder { .. } => ROUTE_ORDER_ACCOUNTS,
+            OrderBookInstruction::MigrateLiquidity { .. } => MIGRATE_LIQUIDITY_ACCOUNTS,
+            OrderBookInstruction::TransferFreeBalance { .. } => TRANSFER_FREE_BALANCE_ACCOUNTS,
+            OrderBookInstruction::GetVersion => &[],
+        }
+    }
+
+    fn unpack_u64(data: &[u8]) -> Result<u64, ProgramError> {
+        data.get(..8)
+            .map(|bytes| u64::from_le_bytes(*array_ref![bytes, 0, 8]))
+            .ok_or(ProgramError::InvalidInstructionData)
+    }
+
+    fn unpack_hash(data: &[u8]) -> Result<[u8; 32], ProgramError> {
+        data.get(..32)
+            .map(|bytes| *array_ref![bytes, 0, 32])
+            .ok_or(ProgramError::InvalidInstructionData)
+    }
+}
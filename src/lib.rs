@@ -1,370 +1,7050 @@
-use solana_program::{
-    account_info::{next_account_info, AccountInfo},
-    entrypoint,
-    entrypoint::ProgramResult,
-    msg,
-    program_error::ProgramError,
-    pubkey::Pubkey,
-};
-
-entrypoint!(process_instruction);
-
-fn process_instruction<'a>(
-    program_id: &Pubkey,
-    accounts: &'a [AccountInfo<'a>],
-    instruction_data: &[u8],
-) -> ProgramResult {
-    if instruction_data.is_empty() {
-        msg!("No instruction data provided");
-        return Err(ProgramError::InvalidInstructionData);
-    }
-
-    // Parse the instruction data
-    let instruction = match OrderBookInstruction::unpack(instruction_data) {
-        Ok(instruction) => instruction,
-        Err(err) => {
-            msg!("Failed to unpack instruction data: {:?}", err);
-            return Err(err);
-        }
+pub mod client;
+#[cfg(not(feature = "no-entrypoint"))]
+mod entrypoint;
+pub mod error;
+pub mod instruction;
+#[cfg(all(feature = "localnet", not(target_os = "solana")))]
+pub mod localnet;
+mod matching;
+pub mod math;
+pub mod processor;
+#[cfg(kani)]
+mod proofs;
+pub mod reader;
+pub mod replay;
+pub mod serum;
+pub mod state;
+
+pub use error::OrderBookError;
+pub use instruction::OrderBookInstruction;
+pub use processor::process_instruction;
+pub use state::*;
+
+// Declare the program ID
+solana_program::declare_id!("orderbook1111111111111111111111111111111111");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::transfer_lamports;
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use futures::TryStreamExt;
+    use solana_client::{
+        client_error::Result as ClientResult,
+        nonblocking::rpc_client::RpcClient as NonblockingRpcClient, rpc_client::RpcClient,
+        rpc_request::RpcRequest,
+    };
+    use solana_program::{
+        account_info::AccountInfo,
+        address_lookup_table::AddressLookupTableAccount,
+        bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+        clock::{Clock, Slot, UnixTimestamp},
+        entrypoint::ProgramResult,
+        hash::Hash,
+        instruction::{AccountMeta, Instruction},
+        message::VersionedMessage,
+        program::get_return_data,
+        program_error::ProgramError,
+        program_option::COption,
+        program_pack::Pack,
+        program_stubs,
+        pubkey::Pubkey,
+        rent::Rent,
+        system_instruction::SystemInstruction,
+        system_program, sysvar,
+    };
+    use solana_sdk::signature::{Keypair, Signer};
+    use spl_associated_token_account::get_associated_token_address;
+    use spl_token::instruction::TokenInstruction;
+    use std::{
+        cell::{Cell, RefCell},
+        collections::{BTreeMap, VecDeque},
+        sync::Once,
     };
 
-    // Route the instruction to the appropriate handler
-    match instruction {
-        OrderBookInstruction::PlaceOrder(order) => process_place_order(accounts, order),
-        OrderBookInstruction::GetBestBuyOrder => process_get_best_buy_order(accounts),
-        OrderBookInstruction::GetBestSellOrder => process_get_best_sell_order(accounts),
+    thread_local! {
+        static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = const { RefCell::new(None) };
+        static LOGGED_DATA: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+        // Tokens a transfer withholds from its destination, as a token
+        // charging transfer fees would
+        static TRANSFER_FEE: Cell<u64> = const { Cell::new(0) };
+        // Slot and time of the runtime clock instructions read without a
+        // clock account
+        static SLOT: Cell<Slot> = const { Cell::new(0) };
+        static UNIX_TIMESTAMP: Cell<UnixTimestamp> = const { Cell::new(0) };
     }
-}
 
-// Define the possible instructions for the order book
-#[derive(Debug, PartialEq)]
-enum OrderBookInstruction {
-    PlaceOrder(Order),
-    GetBestBuyOrder,
-    GetBestSellOrder,
-}
+    // Syscall stubs that keep return data and logged program data per test
+    // thread and execute
+    // system program and token program transfers and associated token
+    // account creation
+    struct TestSyscallStubs;
 
-impl OrderBookInstruction {
-    // Pack the instruction data into a byte array
-    fn pack(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        match self {
-            OrderBookInstruction::PlaceOrder(order) => {
-                buf.push(0);
-                buf.extend_from_slice(&order.pack());
+    impl program_stubs::SyscallStubs for TestSyscallStubs {
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            let find = |key: &Pubkey| account_infos.iter().find(|info| info.key == key).unwrap();
+            if instruction.program_id == spl_associated_token_account::id() {
+                let payer = find(&instruction.accounts[0].pubkey);
+                let token_account_info = find(&instruction.accounts[1].pubkey);
+                let owner = instruction.accounts[2].pubkey;
+                let mint = instruction.accounts[3].pubkey;
+                assert!(payer.is_signer);
+                assert_eq!(
+                    token_account_info.key,
+                    &get_associated_token_address(&owner, &mint)
+                );
+                token_account_info.assign(&spl_token::id());
+                let token_account = TestAccount::token_account(mint, owner, 0);
+                token_account_info
+                    .try_borrow_mut_data()?
+                    .copy_from_slice(&token_account.data);
+                return transfer_lamports(
+                    payer,
+                    token_account_info,
+                    Rent::default().minimum_balance(spl_token::state::Account::LEN),
+                );
             }
-            OrderBookInstruction::GetBestBuyOrder => {
-                buf.push(1);
+            if instruction.program_id == spl_token::id() {
+                let TokenInstruction::Transfer { amount } =
+                    TokenInstruction::unpack(&instruction.data).unwrap()
+                else {
+                    panic!("unexpected token instruction");
+                };
+                let source_info = find(&instruction.accounts[0].pubkey);
+                let destination_info = find(&instruction.accounts[1].pubkey);
+                let authority_info = find(&instruction.accounts[2].pubkey);
+                let mut source = spl_token::state::Account::unpack(&source_info.data.borrow())?;
+                let mut destination =
+                    spl_token::state::Account::unpack(&destination_info.data.borrow())?;
+                assert_eq!(&source.owner, authority_info.key);
+                assert!(authority_info.is_signer || !signers_seeds.is_empty());
+                assert_eq!(source.mint, destination.mint);
+                source.amount = source
+                    .amount
+                    .checked_sub(amount)
+                    .ok_or(ProgramError::InsufficientFunds)?;
+                destination.amount += amount - TRANSFER_FEE.with(Cell::get);
+                spl_token::state::Account::pack(source, &mut source_info.data.borrow_mut())?;
+                spl_token::state::Account::pack(
+                    destination,
+                    &mut destination_info.data.borrow_mut(),
+                )?;
+                return Ok(());
             }
-            OrderBookInstruction::GetBestSellOrder => {
-                buf.push(2);
+            assert_eq!(instruction.program_id, system_program::id());
+            match bincode::deserialize(&instruction.data).unwrap() {
+                SystemInstruction::Transfer { lamports } => {
+                    let from = find(&instruction.accounts[0].pubkey);
+                    let to = find(&instruction.accounts[1].pubkey);
+                    assert!(from.is_signer);
+                    transfer_lamports(from, to, lamports)
+                }
+                // Test accounts are preallocated, so only check the size
+                SystemInstruction::CreateAccount {
+                    lamports,
+                    space,
+                    owner,
+                } => {
+                    let from = find(&instruction.accounts[0].pubkey);
+                    let to = find(&instruction.accounts[1].pubkey);
+                    assert!(from.is_signer);
+                    assert_eq!(to.data_len(), space as usize);
+                    assert_eq!(to.lamports(), 0);
+                    to.assign(&owner);
+                    transfer_lamports(from, to, lamports)
+                }
+                other => panic!("unexpected system instruction {:?}", other),
             }
         }
-        buf
+
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            let clock = Clock {
+                slot: SLOT.with(Cell::get),
+                unix_timestamp: UNIX_TIMESTAMP.with(Cell::get),
+                ..Clock::default()
+            };
+            unsafe { *(var_addr as *mut Clock) = clock };
+            solana_program::entrypoint::SUCCESS
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            solana_program::entrypoint::SUCCESS
+        }
+
+        fn sol_set_return_data(&self, data: &[u8]) {
+            RETURN_DATA.with(|cell| *cell.borrow_mut() = Some((id(), data.to_vec())));
+        }
+
+        fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+            RETURN_DATA.with(|cell| cell.borrow().clone())
+        }
+
+        fn sol_log_data(&self, fields: &[&[u8]]) {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|field| BASE64_STANDARD.encode(field))
+                .collect();
+            let line = format!("Program data: {}", fields.join(" "));
+            LOGGED_DATA.with(|cell| cell.borrow_mut().push(line));
+        }
+    }
+
+    fn install_syscall_stubs() {
+        static ONCE: Once = Once::new();
+        ONCE.call_once(|| {
+            program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs));
+        });
+    }
+
+    // Set the runtime clock to that of a clock account
+    fn set_runtime_clock(clock: &TestAccount) {
+        let clock: Clock = bincode::deserialize(&clock.data).unwrap();
+        SLOT.with(|slot| slot.set(clock.slot));
+        UNIX_TIMESTAMP.with(|now| now.set(clock.unix_timestamp));
+    }
+
+    fn clock_at(slot: Slot, unix_timestamp: UnixTimestamp) -> Clock {
+        Clock {
+            slot,
+            unix_timestamp,
+            ..Clock::default()
+        }
     }
 
-    // Unpack the instruction data from a byte array
-    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
-        let tag = data.get(0).ok_or(ProgramError::InvalidInstructionData)?;
-        match tag {
-            0 => {
-                let order = Order::unpack(&data[1..]).map_err(|err| {
-                    msg!("Failed to unpack PlaceOrder instruction data: {:?}", err);
-                    ProgramError::InvalidInstructionData
-                })?;
-                Ok(OrderBookInstruction::PlaceOrder(order))
+    // Backing storage for an AccountInfo used in tests
+    #[derive(Clone)]
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+        is_signer: bool,
+        is_writable: bool,
+    }
+
+    impl TestAccount {
+        fn new(key: Pubkey, owner: Pubkey, data: Vec<u8>) -> Self {
+            TestAccount {
+                key,
+                owner,
+                lamports: 0,
+                data,
+                is_signer: false,
+                is_writable: true,
             }
-            1 => Ok(OrderBookInstruction::GetBestBuyOrder),
-            2 => Ok(OrderBookInstruction::GetBestSellOrder),
-            _ => {
-                msg!("Invalid instruction tag");
-                Err(ProgramError::InvalidInstructionData)
+        }
+
+        fn signer(key: Pubkey) -> Self {
+            TestAccount {
+                is_signer: true,
+                lamports: 1_000_000,
+                ..Self::new(key, system_program::id(), vec![])
             }
         }
+
+        fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Self {
+            let account = spl_token::state::Account {
+                mint,
+                owner,
+                amount,
+                state: spl_token::state::AccountState::Initialized,
+                delegate: COption::None,
+                close_authority: COption::None,
+                ..spl_token::state::Account::default()
+            };
+            let mut data = vec![0; spl_token::state::Account::LEN];
+            spl_token::state::Account::pack(account, &mut data).unwrap();
+            Self::new(Pubkey::new_unique(), spl_token::id(), data)
+        }
+
+        // A not yet created associated token account
+        fn associated_token_account(owner: &Pubkey, mint: &Pubkey) -> Self {
+            Self::new(
+                get_associated_token_address(owner, mint),
+                system_program::id(),
+                vec![0; spl_token::state::Account::LEN],
+            )
+        }
+
+        fn token_amount(&self) -> u64 {
+            spl_token::state::Account::unpack(&self.data)
+                .unwrap()
+                .amount
+        }
+
+        fn config(admin: Pubkey, unique_markets: bool) -> Self {
+            let config = Config {
+                is_initialized: true,
+                admin,
+                default_tick_size: 1,
+                default_lot_size: 1,
+                unique_markets,
+                protocol_fee_bps: 0,
+                treasury: admin,
+                ..Config::default()
+            };
+            Self::new(Config::find_address(&id()).0, id(), config.pack())
+        }
+
+        fn rent() -> Self {
+            Self::new(
+                sysvar::rent::id(),
+                sysvar::id(),
+                bincode::serialize(&Rent::default()).unwrap(),
+            )
+        }
+
+        fn clock(slot: Slot, unix_timestamp: UnixTimestamp) -> Self {
+            Self::new(
+                sysvar::clock::id(),
+                sysvar::id(),
+                bincode::serialize(&clock_at(slot, unix_timestamp)).unwrap(),
+            )
+        }
+
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                self.is_signer,
+                self.is_writable,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                false,
+                0,
+            )
+        }
     }
-}
 
-// Define the fields of an order
-#[derive(Clone, Copy, Debug, PartialEq)]
-struct Order {
-    trader: Pubkey,
-    amount: u64,
-    price: u64,
-    order_type: OrderType,
-}
+    // An order book account with its token vaults
+    #[derive(Clone)]
+    struct TestMarket {
+        order_book: TestAccount,
+        config: TestAccount,
+        base_vault: TestAccount,
+        quote_vault: TestAccount,
+        trade_history: Option<TestAccount>,
+        candle_series: Vec<TestAccount>,
+    }
+
+    impl TestMarket {
+        // An initialized market with unit tick and lot sizes
+        fn new() -> Self {
+            Self::with_quote_mint(Pubkey::new_unique())
+        }
 
-impl Order {
-    // Pack the order data into a byte array
-    fn pack(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        buf.extend_from_slice(&self.trader.to_bytes());
-        buf.extend_from_slice(&self.amount.to_le_bytes());
-        buf.extend_from_slice(&self.price.to_le_bytes());
-        buf.push(self.order_type as u8);
-        buf
-    }
-
-    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
-        let trader = Pubkey::new_from_array(*array_ref![data, 0, 32]);
-        let amount = u64::from_le_bytes(*array_ref![data, 32, 8]);
-        let price = u64::from_le_bytes(*array_ref![data, 40, 8]);
-        let order_type = match data.get(48) {
-            Some(0) => OrderType::Buy,
-            Some(1) => OrderType::Sell,
-            _ => {
-                msg!("Invalid order type");
-                return Err(ProgramError::InvalidAccountData);
+        fn with_quote_mint(quote_mint: Pubkey) -> Self {
+            install_syscall_stubs();
+            let key = Pubkey::new_unique();
+            let (vault_signer, vault_signer_bump) = OrderBook::find_vault_signer(&id(), &key);
+            let base_vault = TestAccount::token_account(Pubkey::new_unique(), vault_signer, 0);
+            let quote_vault = TestAccount::token_account(quote_mint, vault_signer, 0);
+            let order_book = OrderBook {
+                is_initialized: true,
+                base_mint: spl_token::state::Account::unpack(&base_vault.data)
+                    .unwrap()
+                    .mint,
+                quote_mint,
+                tick_size: 1,
+                lot_size: 1,
+                base_vault: base_vault.key,
+                quote_vault: quote_vault.key,
+                vault_signer_bump,
+                ..OrderBook::default()
+            };
+            let mut data = vec![0; OrderBook::LEN];
+            order_book.pack_into_slice(&mut data).unwrap();
+            TestMarket {
+                order_book: TestAccount::new(key, id(), data),
+                config: TestAccount::config(Pubkey::new_unique(), false),
+                base_vault,
+                quote_vault,
+                trade_history: None,
+                candle_series: vec![],
             }
-        };
-        Ok(Order {
+        }
+
+        // Another market of the same pair
+        fn sibling(&self) -> Self {
+            let base_mint = self.book().base_mint;
+            let mut market = Self::with_quote_mint(self.book().quote_mint);
+            let mut book = market.book();
+            book.base_mint = base_mint;
+            book.pack_into_slice(&mut market.order_book.data).unwrap();
+            let mut vault = spl_token::state::Account::unpack(&market.base_vault.data).unwrap();
+            vault.mint = base_mint;
+            spl_token::state::Account::pack(vault, &mut market.base_vault.data).unwrap();
+            market
+        }
+
+        fn book(&self) -> OrderBook {
+            OrderBook::unpack(&self.order_book.data).unwrap()
+        }
+
+        // A new trader with `TestTrader::FUNDS` of both market tokens
+        fn trader(&self) -> TestTrader {
+            let book = self.book();
+            let wallet = TestAccount::signer(Pubkey::new_unique());
+            let open_orders = OpenOrders {
+                is_initialized: true,
+                owner: wallet.key,
+                quote_mint: book.quote_mint,
+                markets: vec![],
+                beneficial_owner: wallet.key,
+                withdraw_authority: wallet.key,
+                cancel_after_slot: 0,
+            };
+            let mut open_orders = TestAccount::new(
+                OpenOrders::find_address(&id(), &wallet.key, &book.quote_mint).0,
+                id(),
+                open_orders.pack(),
+            );
+            open_orders.lamports = Rent::default().minimum_balance(OpenOrders::LEN);
+            TestTrader {
+                base_token: TestAccount::token_account(
+                    book.base_mint,
+                    wallet.key,
+                    TestTrader::FUNDS,
+                ),
+                quote_token: TestAccount::token_account(
+                    book.quote_mint,
+                    wallet.key,
+                    TestTrader::FUNDS,
+                ),
+                wallet,
+                open_orders,
+            }
+        }
+    }
+
+    // A trader's wallet, OpenOrders account and token accounts
+    #[derive(Clone)]
+    struct TestTrader {
+        wallet: TestAccount,
+        open_orders: TestAccount,
+        base_token: TestAccount,
+        quote_token: TestAccount,
+    }
+
+    impl TestTrader {
+        const FUNDS: u64 = 1_000_000;
+
+        fn balances(&self, market: &TestMarket) -> OpenOrdersMarket {
+            OpenOrders::unpack(&self.open_orders.data)
+                .unwrap()
+                .market(&market.order_book.key)
+                .copied()
+                .unwrap_or_default()
+        }
+    }
+
+    fn new_order(trader: Pubkey, amount: u64, price: u64, order_type: OrderType) -> Order {
+        Order {
             trader,
             amount,
             price,
             order_type,
-        })
+            slot: 0,
+            unix_timestamp: 0,
+            order_id: 0,
+            expires_at: 0,
+            bounty: 0,
+            peg: PegType::None,
+            peg_ticks: 0,
+            linked_order_id: None,
+            trail_ticks: 0,
+            trigger_price: 0,
+            improvement_slots: 0,
+            avg_price_limit: 0,
+            min_fill_amount: 0,
+            beneficial_owner: trader,
+            decay_start_price: 0,
+            decay_end: 0,
+            quote_budget: 0,
+            expiry_unit: ExpiryUnit::UnixTime,
+            filled_amount: 0,
+            filled_quote: 0,
+            fees_paid: 0,
+            tag: 0,
+            priority_bid: 0,
+            settle_at: 0,
+        }
     }
-}
 
-// Define the two types of orders (buy and sell)
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum OrderType {
-    Buy,
-    Sell,
-}
+    fn place_order(
+        market: &mut TestMarket,
+        trader: &mut TestTrader,
+        clock: &mut TestAccount,
+        order: Order,
+    ) -> ProgramResult {
+        place_order_with_makers(market, trader, clock, &mut [], order)
+    }
 
-// Define the account data for the order book
-#[derive(Default)]
-struct OrderBook {
-    buy_orders: Vec<Order>,
-    sell_orders: Vec<Order>,
-}
+    fn place_order_with_makers(
+        market: &mut TestMarket,
+        trader: &mut TestTrader,
+        clock: &mut TestAccount,
+        makers: &mut [TestTrader],
+        order: Order,
+    ) -> ProgramResult {
+        let data = OrderBookInstruction::PlaceOrder(order).pack();
+        place_order_data(market, trader, clock, makers, order.order_type, &data)
+    }
 
-impl OrderBook {
-    // Add an order to the order book
-    fn add_order(&mut self, order: Order) {
-        match order.order_type {
-            OrderType::Buy => self.buy_orders.push(order),
-            OrderType::Sell => self.sell_orders.push(order),
-        }
+    // Send PlaceOrder instruction data as given, for a `side` order
+    fn place_order_data(
+        market: &mut TestMarket,
+        trader: &mut TestTrader,
+        clock: &mut TestAccount,
+        makers: &mut [TestTrader],
+        side: OrderType,
+        data: &[u8],
+    ) -> ProgramResult {
+        install_syscall_stubs();
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+        let (token, vault) = match side {
+            OrderType::Buy => (&mut trader.quote_token, &mut market.quote_vault),
+            OrderType::Sell => (&mut trader.base_token, &mut market.base_vault),
+        };
+        let mut accounts = vec![
+            market.order_book.info(),
+            trader.wallet.info(),
+            clock.info(),
+            system_program.info(),
+            trader.open_orders.info(),
+            token.info(),
+            vault.info(),
+            token_program.info(),
+            market.config.info(),
+        ];
+        accounts.extend(market.trade_history.iter_mut().map(TestAccount::info));
+        accounts.extend(market.candle_series.iter_mut().map(TestAccount::info));
+        accounts.extend(makers.iter_mut().map(|maker| maker.open_orders.info()));
+        process_instruction(&id(), &accounts, data)
     }
 
-    // Get the best buy order (highest price)
-    fn get_best_buy_order(&self) -> Option<&Order> {
-        self.buy_orders.iter().max_by_key(|order| order.price)
+    fn settle_funds(market: &mut TestMarket, trader: &mut TestTrader) -> ProgramResult {
+        let settle_all = OrderBookInstruction::SettleFunds {
+            max_base: u64::MAX,
+            max_quote: u64::MAX,
+            recipient: None,
+        };
+        settle_funds_with(market, trader, &mut [], settle_all)
     }
 
-    // Get the best sell order (lowest price)
-    fn get_best_sell_order(&self) -> Option<&Order> {
-        self.sell_orders.iter().min_by_key(|order| order.price)
+    // SettleFunds with optional trailing accounts, paying into the trader's
+    // token accounts
+    fn settle_funds_with(
+        market: &mut TestMarket,
+        trader: &mut TestTrader,
+        trailing: &mut [TestAccount],
+        instruction: OrderBookInstruction,
+    ) -> ProgramResult {
+        install_syscall_stubs();
+        let mut vault_signer = TestAccount::new(
+            OrderBook::find_vault_signer(&id(), &market.order_book.key).0,
+            Pubkey::default(),
+            vec![],
+        );
+        let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        let mut accounts = vec![
+            market.order_book.info(),
+            trader.wallet.info(),
+            trader.open_orders.info(),
+            market.base_vault.info(),
+            market.quote_vault.info(),
+            trader.base_token.info(),
+            trader.quote_token.info(),
+            vault_signer.info(),
+            token_program.info(),
+            rent.info(),
+        ];
+        accounts.extend(trailing.iter_mut().map(TestAccount::info));
+        process_instruction(&id(), &accounts, &instruction.pack())
     }
-}
 
-// Process the PlaceOrder instruction
-fn process_place_order<'a>(
-    accounts: &'a [AccountInfo<'a>],
-    order: Order,
-) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let order_book_info = next_account_info(account_info_iter)?;
-    let order_book = &mut OrderBook::from_account_info(order_book_info)?;
+    fn query(order_book: &mut TestAccount, instruction: OrderBookInstruction) -> Vec<u8> {
+        query_at(order_book, &mut TestAccount::clock(0, 0), instruction)
+    }
 
-    // Add the order to the order book
-    order_book.add_order(order);
+    fn query_at(
+        order_book: &mut TestAccount,
+        clock: &mut TestAccount,
+        instruction: OrderBookInstruction,
+    ) -> Vec<u8> {
+        install_syscall_stubs();
+        process_instruction(
+            &id(),
+            &[order_book.info(), clock.info()],
+            &instruction.pack(),
+        )
+        .unwrap();
+        get_return_data().unwrap().1
+    }
 
-    Ok(())
-}
+    #[test]
+    fn test_order_pack_roundtrip() {
+        let order = Order {
+            slot: 42,
+            unix_timestamp: 1_700_000_000,
+            ..new_order(Pubkey::new_unique(), 100, 500, OrderType::Sell)
+        };
+        assert_eq!(order.pack().len(), Order::LEN);
+        assert_eq!(Order::unpack(&order.pack()).unwrap(), order);
+        let ix = OrderBookInstruction::PlaceOrder(order);
+        assert_eq!(OrderBookInstruction::unpack(&ix.pack()).unwrap(), ix);
+    }
 
-// Process the GetBestBuyOrder instruction
-fn process_get_best_buy_order<'a>(accounts: &'a [AccountInfo<'a>]) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let order_book_info = next_account_info(account_info_iter)?;
-    let order_book = &OrderBook::from_account_info(order_book_info)?;
+    #[test]
+    fn test_place_order_short_payload() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
 
-    // Get the best buy order from the order book
-    let best_buy_order = order_book
-        .get_best_buy_order()
-        .ok_or(ProgramError::InvalidAccountData)?;
+        // The first clients sent the trader, amount, price, side and
+        // placement time; the fields appended since take their defaults
+        let order = new_order(trader.wallet.key, 10, 100, OrderType::Buy);
+        let mut data = OrderBookInstruction::PlaceOrder(order).pack();
+        data.truncate(1 + 65);
+        assert_eq!(
+            OrderBookInstruction::unpack(&data),
+            Ok(OrderBookInstruction::PlaceOrder(Order {
+                beneficial_owner: Pubkey::default(),
+                ..order
+            }))
+        );
+        place_order_data(
+            &mut market,
+            &mut trader,
+            &mut clock,
+            &mut [],
+            OrderType::Buy,
+            &data,
+        )
+        .unwrap();
+        assert_eq!(market.book().buy_orders.len(), 1);
+        assert_eq!(trader.balances(&market).quote_locked, 1_000);
 
-    msg!("Best buy order: {:?}", best_buy_order);
+        // A payload may not end inside a field
+        data.truncate(1 + 60);
+        assert_eq!(
+            OrderBookInstruction::unpack(&data),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
 
-    Ok(())
-}
+    #[test]
+    fn test_unknown_instruction_tag() {
+        let tag = OrderBookInstruction::LATEST_TAG + 1;
+        assert_eq!(
+            OrderBookInstruction::unpack(&[tag, 0, 0]),
+            Err(OrderBookError::UnsupportedInstructionVersion.into())
+        );
+        // Payload fields appended by later versions are ignored
+        assert_eq!(
+            OrderBookInstruction::unpack(&[1, 0xff]),
+            Ok(OrderBookInstruction::GetBestBuyOrder)
+        );
+        // Markets created without a sell fee charge both sides alike
+        let mut data = OrderBookInstruction::InitializeMarket {
+            tick_size: 1,
+            lot_size: 1,
+            buy_taker_fee_bps: 30,
+            sell_taker_fee_bps: 10,
+            backstop_discount_bps: 5_000,
+            dark_pool: true,
+            oracle_source: OracleSource::Pyth,
+            oracle: Pubkey::new_unique(),
+            whole_units: true,
+        }
+        .pack();
+        data.truncate(data.len() - 39);
+        assert_eq!(
+            OrderBookInstruction::unpack(&data),
+            Ok(OrderBookInstruction::InitializeMarket {
+                tick_size: 1,
+                lot_size: 1,
+                buy_taker_fee_bps: 30,
+                sell_taker_fee_bps: 30,
+                backstop_discount_bps: 0,
+                dark_pool: false,
+                oracle_source: OracleSource::None,
+                oracle: Pubkey::default(),
+                whole_units: false,
+            })
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_order_json_roundtrip() {
+        let order = new_order(Pubkey::new_unique(), 100, 500, OrderType::Buy);
+        let json = serde_json::to_string(&order).unwrap();
+        assert_eq!(serde_json::from_str::<Order>(&json).unwrap(), order);
+    }
 
-// Process the GetBestSellOrder instruction
-fn process_get_best_sell<'a>(accounts: &'a [AccountInfo<'a>]) -> ProgramResult {
-    let account_info_iter = &mut accounts.iter();
-    let order_book_info = next_account_info(account_info_iter)?;
-    let order_book = &OrderBook::from_account_info(order_book_info)?;
-    // Get the best sell order from the order book
-    let best_sell_order = order_book
-    .get_best_sell_order()
-    .ok_or(ProgramError::InvalidAccountData)?;
+    #[cfg(feature = "localnet")]
+    #[test]
+    fn test_localnet_fixture() {
+        use crate::localnet::{self, Fixture};
+        use solana_sdk::signer::Signer;
 
-    msg!("Best sell order: {:?}", best_sell_order);
+        let fixture = Fixture::parse(include_str!("../fixtures/localnet.txt")).unwrap();
+        assert_eq!(
+            (fixture.params.tick_size, fixture.params.lot_size),
+            (10_000, 1_000_000)
+        );
+        assert_eq!(fixture.traders.len(), 3);
+        assert_eq!(fixture.orders.len(), 10);
+        assert_eq!(fixture.orders[0].side, OrderType::Buy);
+        assert_eq!(
+            localnet::trader_keypair("alice").pubkey(),
+            localnet::trader_keypair("alice").pubkey()
+        );
+        assert_ne!(
+            localnet::trader_keypair("alice").pubkey(),
+            localnet::trader_keypair("bob").pubkey()
+        );
 
-    Ok(())
-}
+        let market = "market tick_size=1 lot_size=1 base_decimals=0 quote_decimals=0\n";
+        assert_eq!(
+            Fixture::parse(&format!("{}bid alice price=1 amount=1", market)),
+            Err("line 2: unknown trader alice".to_string())
+        );
+        assert_eq!(
+            Fixture::parse(&format!(
+                "{}trader alice base=1 quote=1\nbid alice price=2 amount=1\nask alice price=2 amount=1",
+                market
+            )),
+            Err("bid at 2 crosses ask at 2".to_string())
+        );
+        assert!(Fixture::parse("trader alice base=1 quote=1").is_err());
+    }
+
+    #[test]
+    fn test_trader_filter_matches_packed_order() {
+        let order = new_order(Pubkey::new_unique(), 5, 10, OrderType::Sell);
+        let mut order_book = OrderBook::default();
+        order_book
+            .add_order(new_order(Pubkey::new_unique(), 1, 11, OrderType::Sell))
+            .unwrap();
+        order_book.add_order(order).unwrap();
+        let mut data = vec![0; OrderBook::LEN];
+        order_book.pack_into_slice(&mut data).unwrap();
 
-// Define the instruction processor function
-pub fn process_instruction<'a>(
-    program_id: &Pubkey,
-    accounts: &'a [AccountInfo<'a>],
-    instruction_data: &[u8],
-) -> ProgramResult {
-    let instruction = OrderBookInstruction::unpack(instruction_data)?;
+        let filter = OrderBook::trader_filter(OrderType::Sell, 1, &order.trader);
+        assert_eq!(
+            &data[filter.offset..filter.offset + filter.bytes.len()],
+            filter.bytes.as_slice()
+        );
+    }
 
-    match instruction {
-        OrderBookInstruction::PlaceOrder(order) => {
-            msg!("Instruction: PlaceOrder");
-            process_place_order(accounts, order)
+    #[test]
+    fn test_export_book_pages() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(3, 30);
+        let mut placed = vec![];
+        for i in 0..20 {
+            let order_type = if i % 2 == 0 {
+                OrderType::Buy
+            } else {
+                OrderType::Sell
+            };
+            let price = if i % 2 == 0 { 100 - i } else { 200 + i };
+            let mut trader = market.trader();
+            let order = new_order(trader.wallet.key, 1, price, order_type);
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+            placed.push(order);
         }
-        OrderBookInstruction::GetBestBuyOrder => {
-            msg!("Instruction: GetBestBuyOrder");
-            process_get_best_buy_order(accounts)
+
+        let mut exported = vec![];
+        let mut cursor = Some(0);
+        while let Some(next) = cursor {
+            let data = query(
+                &mut market.order_book,
+                OrderBookInstruction::ExportBook { cursor: next },
+            );
+            let page = OrderPage::unpack(&data).unwrap();
+            assert!(page.items.len() <= OrderPage::MAX_ITEMS);
+            exported.extend(page.items);
+            cursor = page.next_cursor;
         }
-        OrderBookInstruction::GetBestSellOrder => {
-            msg!("Instruction: GetBestSellOrder");
-            process_get_best_sell_order(accounts)
+        assert_eq!(exported.len(), placed.len());
+        for order in placed {
+            assert!(exported.iter().any(|o| o.trader == order.trader));
         }
     }
-}
 
-// Declare the program ID
-solana_program::declare_id!("orderbook111111111111111111111111111111111");
+    #[test]
+    fn test_get_inventory() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(3, 30);
+        let mut trader = market.trader();
+        let mut other = market.trader();
+        for (amount, price, side) in [(5, 90, OrderType::Buy), (10, 100, OrderType::Sell)] {
+            let order = new_order(trader.wallet.key, amount, price, side);
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        }
+        let order = new_order(other.wallet.key, 4, 80, OrderType::Buy);
+        place_order(&mut market, &mut other, &mut clock, order).unwrap();
+        let order = new_order(other.wallet.key, 2, 90, OrderType::Sell);
+        place_order_with_makers(
+            &mut market,
+            &mut other,
+            &mut clock,
+            std::slice::from_mut(&mut trader),
+            order,
+        )
+        .unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use solana_program::{
-        instruction::{AccountMeta, Instruction},
-        pubkey::Pubkey,
-        sysvar::rent,
-        system_instruction,
-    };
+        // Only the trader's orders count, with the fill's proceeds free
+        process_instruction(
+            &id(),
+            &[market.order_book.info(), trader.open_orders.info()],
+            &OrderBookInstruction::GetInventory.pack(),
+        )
+        .unwrap();
+        let report = InventoryReport::unpack(&get_return_data().unwrap().1).unwrap();
+        let balances = trader.balances(&market);
+        assert_eq!(
+            report,
+            InventoryReport {
+                owner: trader.wallet.key,
+                bid_count: 1,
+                bid_base: 3,
+                bid_quote: 270,
+                ask_count: 1,
+                ask_base: 10,
+                ask_quote: 1_000,
+                base_locked: 10,
+                quote_locked: balances.quote_locked,
+                base_free: 2,
+                quote_free: balances.quote_free,
+            }
+        );
+        assert_eq!(report.quote_locked, 270);
+    }
 
     #[test]
-    fn test_order_book() {
-        let program_id = solana_program::pubkey::new_rand();
-
-        // Create the order book account
-        let order_book_pubkey = solana_program::pubkey::new_rand();
-        let rent = rent::Rent::default();
-        let mut order_book_account = Account::new(
-            rent.minimum_balance(OrderBook::LEN),
-            OrderBook::LEN,
-            &program_id,
+    fn test_get_open_orders() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(3, 30);
+        let mut trader = market.trader();
+        let mut other = market.trader();
+        for i in 0..6 {
+            let order = new_order(trader.wallet.key, 1, 100 - i, OrderType::Buy);
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        }
+        let order = new_order(other.wallet.key, 1, 200, OrderType::Sell);
+        place_order(&mut market, &mut other, &mut clock, order).unwrap();
+
+        // Pages only hold the trader's orders and end without a cursor
+        let mut orders = vec![];
+        let mut cursor = Some(0);
+        while let Some(next) = cursor {
+            let data = query(
+                &mut market.order_book,
+                OrderBookInstruction::GetOpenOrders {
+                    trader: trader.wallet.key,
+                    cursor: next,
+                },
+            );
+            let page = OrderPage::unpack(&data).unwrap();
+            assert!(page.items.len() <= OrderPage::MAX_ITEMS);
+            orders.extend(page.items);
+            cursor = page.next_cursor;
+        }
+        assert_eq!(orders.len(), 6);
+        assert!(orders.iter().all(|order| order.trader == trader.wallet.key));
+
+        // A cursor past the end returns an empty last page
+        let data = query(
+            &mut market.order_book,
+            OrderBookInstruction::GetOpenOrders {
+                trader: trader.wallet.key,
+                cursor: 6,
+            },
         );
-        OrderBook::pack_into_account(&OrderBook::default(), &mut order_book_account.data);
+        let page = OrderPage::unpack(&data).unwrap();
+        assert!(page.items.is_empty() && !page.has_more());
+    }
 
-        let mut accounts = vec![
-            AccountInfo::new(
-                &solana_program::pubkey::new_rand(),
-                false,
-                true,
-                &mut Account::default(),
+    #[test]
+    fn test_verify_market() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(9, 90);
+        for (price, order_type) in [(100, OrderType::Buy), (101, OrderType::Sell)] {
+            let mut trader = market.trader();
+            let order = new_order(trader.wallet.key, 1, price, order_type);
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        }
+        let data = query(&mut market.order_book, OrderBookInstruction::VerifyMarket);
+        assert_eq!(
+            VerifyMarketReport::unpack(&data).unwrap(),
+            VerifyMarketReport {
+                buy_orders: 1,
+                sell_orders: 1,
+                crossed: false,
+                violations: 0,
+                seq_num: 2,
+                book_checksum: market.book().book_checksum,
+            }
+        );
+
+        let mut book = market.book();
+        book.buy_orders.push(Order {
+            slot: 1,
+            ..new_order(Pubkey::new_unique(), 1, 200, OrderType::Sell)
+        });
+        let report = VerifyMarketReport::verify(&book, &Clock::default());
+        assert!(report.crossed);
+        assert_eq!(
+            report.violations,
+            VerifyMarketReport::SIDE_MISMATCH
+                | VerifyMarketReport::TIME_PRIORITY
+                | VerifyMarketReport::CROSSED
+        );
+    }
+
+    // Process a client-built instruction, passing the test accounts in the
+    // order of its account metas
+    fn process_client_instruction(
+        instruction: &Instruction,
+        accounts: Vec<&mut TestAccount>,
+    ) -> ProgramResult {
+        install_syscall_stubs();
+        let infos: Vec<AccountInfo> = accounts.into_iter().map(TestAccount::info).collect();
+        let ordered: Vec<AccountInfo> = instruction
+            .accounts
+            .iter()
+            .map(|meta| {
+                let info = infos.iter().find(|info| info.key == &meta.pubkey).unwrap();
+                assert!(!meta.is_signer || info.is_signer);
+                info.clone()
+            })
+            .collect();
+        process_instruction(&instruction.program_id, &ordered, &instruction.data)
+    }
+
+    #[test]
+    fn test_client_place_order() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        let order = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+
+        let accounts = client::PlaceOrderAccounts {
+            order_book: market.order_book.key,
+            trader: taker.wallet.key,
+            open_orders: taker.open_orders.key,
+            trader_token: taker.quote_token.key,
+            vault: market.quote_vault.key,
+            order_gate_signer: None,
+            blocklist: None,
+            matching: client::MatchingAccounts::new(&market.book(), vec![maker.open_orders.key]),
+        };
+        let order = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        let instruction = client::place_order(&id(), &accounts, order);
+        assert_eq!(
+            OrderBookInstruction::unpack(&instruction.data),
+            Ok(OrderBookInstruction::PlaceOrder(order))
+        );
+        process_client_instruction(
+            &instruction,
+            vec![
+                &mut market.order_book,
+                &mut taker.wallet,
+                &mut clock,
+                &mut system_program,
+                &mut taker.open_orders,
+                &mut taker.quote_token,
+                &mut market.quote_vault,
+                &mut token_program,
+                &mut market.config,
+                &mut maker.open_orders,
+            ],
+        )
+        .unwrap();
+        assert_eq!(taker.balances(&market).base_free, 10);
+        assert!(market.book().sell_orders.is_empty());
+        let preview = FillPreview::unpack(&get_return_data().unwrap().1).unwrap();
+        assert_eq!(
+            (
+                preview.fill_count,
+                preview.filled_amount,
+                preview.resting_amount
             ),
-            AccountInfo::new(&order_book_pubkey, false, true, &mut order_book_account),
-            AccountInfo::new_readonly(&solana_program::sysvar::rent::id(), false),
-        ];
+            (1, 10, 0)
+        );
+        assert_eq!(preview.average_price(market.book().lot_size), 100);
+        assert_eq!(
+            client::required_sysvars(&OrderBookInstruction::PlaceOrder(order)),
+            vec![sysvar::clock::id()]
+        );
+        assert!(client::required_sysvars(&OrderBookInstruction::GetMarketStats).is_empty());
+    }
 
-        // Place a buy order
-        let trader_pubkey = solana_program::pubkey::new_rand();
-        let order = Order {
-            trader: trader_pubkey,
-            amount: 100,
-            price: 500,
-            order_type: OrderType::Buy,
-        };
-        let mut order_account = Account::new(0, Order::LEN, &program_id);
-        Order::pack_into_account(&order, &mut order_account.data);
-        let order_account_pubkey = solana_program::pubkey::new_rand();
-        accounts.push(AccountInfo::new(
-            &order_account_pubkey,
-            false,
-            true,
-            &mut order_account,
-        ));
-        let place_order_ix = Instruction::new_with_bincode(
-            program_id,
-            &OrderBookInstruction::PlaceOrder(order),
+    #[test]
+    fn test_heal_crossed_book() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut bidder = market.trader();
+        let mut asker = market.trader();
+        let bid = new_order(bidder.wallet.key, 10, 100, OrderType::Buy);
+        place_order(&mut market, &mut bidder, &mut clock, bid).unwrap();
+        let ask = new_order(asker.wallet.key, 6, 105, OrderType::Sell);
+        place_order(&mut market, &mut asker, &mut clock, ask).unwrap();
+
+        // Cross the book as a faulty migration could
+        let mut book = market.book();
+        book.sell_orders[0].price = 99;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let data = query(&mut market.order_book, OrderBookInstruction::VerifyMarket);
+        assert!(VerifyMarketReport::unpack(&data).unwrap().crossed);
+
+        let instruction = client::heal_book(
+            &id(),
+            &market.order_book.key,
+            &client::MatchingAccounts::new(
+                &market.book(),
+                vec![bidder.open_orders.key, asker.open_orders.key],
+            ),
+            8,
+        );
+        let deposit = asker.open_orders.lamports;
+        process_client_instruction(
+            &instruction,
             vec![
-                AccountMeta::new(order_book_pubkey, false),
-                AccountMeta::new_readonly(trader_pubkey, true),
-                AccountMeta::new(order_account_pubkey, false),
+                &mut market.order_book,
+                &mut market.config,
+                &mut bidder.open_orders,
+                &mut asker.open_orders,
             ],
+        )
+        .unwrap();
+
+        // The later ask took from the resting bid at the bid's price
+        let book = market.book();
+        assert!(book.sell_orders.is_empty());
+        assert_eq!(book.buy_orders[0].amount, 4);
+        assert_eq!(book.last_trade_price, 100);
+        let bidder_balances = bidder.balances(&market);
+        assert_eq!(
+            (bidder_balances.base_free, bidder_balances.quote_locked),
+            (6, 400)
         );
-        assert!(solana_program_test::BanksClient::new()
-            .process_instruction(&place_order_ix, accounts.clone())
-            .is_ok());
-
-       // Get the best buy order
-       let get_best_buy_order_ix = Instruction::new_with_bincode(
-        program_id,
-        &OrderBookInstruction::GetBestBuyOrder,
-        vec![AccountMeta::new_readonly(order_book_pubkey, false)],
-    );
-    let result = solana_program_test::BanksClient::new()
-        .process_instruction(&get_best_buy_order_ix, accounts.clone())
-        .unwrap();
-    let best_buy_order = Order::unpack_from_slice(&result.data).unwrap();
-    assert_eq!(
-        best_buy_order,
-        Order {
-            trader: trader_pubkey,
-            amount: 100,
-            price: 500,
-            order_type: OrderType::Buy,
-        }
-    );
-
-    // Place a sell order
-    let trader_pubkey = solana_program::pubkey::new_rand();
-    let order = Order {
-        trader: trader_pubkey,
-        amount: 50,
-        price: 600,
-        order_type: OrderType::Sell,
-    };
-    let mut order_account = Account::new(0, Order::LEN, &program_id);
-    Order::pack_into_account(&order, &mut order_account.data);
-    let order_account_pubkey = solana_program::pubkey::new_rand();
-    accounts.push(AccountInfo::new(
-        &order_account_pubkey,
-        false,
-        true,
-        &mut order_account,
-    ));
-    let place_order_ix = Instruction::new_with_bincode(
-        program_id,
-        &OrderBookInstruction::PlaceOrder(order),
-        vec![
-            AccountMeta::new(order_book_pubkey, false),
-            AccountMeta::new_readonly(trader_pubkey, true),
-            AccountMeta::new(order_account_pubkey, false),
-        ],
-    );
-    assert!(solana_program_test::BanksClient::new()
-        .process_instruction(&place_order_ix, accounts.clone())
-        .is_ok());
-
-    // Get the best sell order
-    let get_best_sell_order_ix = Instruction::new_with_bincode(
-        program_id,
-        &OrderBookInstruction::GetBestSellOrder,
-        vec![AccountMeta::new_readonly(order_book_pubkey, false)],
-    );
-    let result = solana_program_test::BanksClient::new()
-        .process_instruction(&get_best_sell_order_ix, accounts.clone())
-        .unwrap();
-    let best_sell_order = Order::unpack_from_slice(&result.data).unwrap();
-    assert_eq!(
-        best_sell_order,
-        Order {
-            trader: trader_pubkey,
-            amount: 50,
-            price: 600,
-            order_type: OrderType::Sell,
+        let asker_balances = asker.balances(&market);
+        assert_eq!(
+            (
+                asker_balances.quote_free,
+                asker_balances.base_locked,
+                asker_balances.order_count
+            ),
+            (600, 0, 0)
+        );
+        assert_eq!(
+            asker.open_orders.lamports,
+            deposit + OrderBook::ORDER_DEPOSIT_LAMPORTS
+        );
+        let data = query(&mut market.order_book, OrderBookInstruction::VerifyMarket);
+        assert_eq!(VerifyMarketReport::unpack(&data).unwrap().violations, 0);
+
+        // Healing a book that does not cross changes nothing
+        process_client_instruction(
+            &instruction,
+            vec![
+                &mut market.order_book,
+                &mut market.config,
+                &mut bidder.open_orders,
+                &mut asker.open_orders,
+            ],
+        )
+        .unwrap();
+        assert_eq!(market.book(), book);
+    }
+
+    #[test]
+    fn test_launch_auction() {
+        let mut market = TestMarket::new();
+        let mut seller = market.trader();
+        seller.wallet.lamports = Rent::default().minimum_balance(LaunchAuction::LEN);
+        let mut book = market.book();
+        book.creator = seller.wallet.key;
+        book.lot_size = 10;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut launch_auction = TestAccount::new(
+            LaunchAuction::find_address(&id(), &market.order_book.key).0,
+            system_program::id(),
+            vec![0; LaunchAuction::LEN],
+        );
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+        process_client_instruction(
+            &client::init_launch_auction(
+                &id(),
+                &market.order_book.key,
+                &seller.wallet.key,
+                &seller.base_token.key,
+                &market.base_vault.key,
+                100,
+                10,
+                100,
+                200,
+            ),
+            vec![
+                &mut launch_auction,
+                &mut market.order_book,
+                &mut seller.wallet,
+                &mut seller.base_token,
+                &mut market.base_vault,
+                &mut token_program,
+                &mut system_program,
+            ],
+        )
+        .unwrap();
+        assert_eq!(market.base_vault.token_amount(), 100);
+
+        // Pass the test clock as the trailing Clock sysvar
+        let with_clock = |mut instruction: Instruction| {
+            instruction
+                .accounts
+                .push(AccountMeta::new_readonly(sysvar::clock::id(), false));
+            instruction
+        };
+        let mut bidders: Vec<TestTrader> = (0..4).map(|_| market.trader()).collect();
+        let bids = [(60, 20, 200), (30, 15, 100), (50, 15, 100), (10, 30, 50)];
+        let salt = [7; 32];
+        let mut clock = TestAccount::clock(1, 50);
+        for (bidder, (quantity, price, deposit)) in bidders.iter_mut().zip(bids) {
+            let commitment = LaunchAuction::commitment(&bidder.wallet.key, price, &salt);
+            process_client_instruction(
+                &with_clock(client::commit_launch_bid(
+                    &id(),
+                    &market.order_book.key,
+                    &bidder.wallet.key,
+                    &bidder.open_orders.key,
+                    &bidder.quote_token.key,
+                    &market.quote_vault.key,
+                    quantity,
+                    deposit,
+                    commitment,
+                )),
+                vec![
+                    &mut launch_auction,
+                    &mut market.order_book,
+                    &mut bidder.wallet,
+                    &mut bidder.open_orders,
+                    &mut bidder.quote_token,
+                    &mut market.quote_vault,
+                    &mut token_program,
+                    &mut clock,
+                ],
+            )
+            .unwrap();
+            assert_eq!(bidder.balances(&market).quote_locked, deposit);
+        }
+        assert_eq!(market.quote_vault.token_amount(), 450);
+
+        // Prices are revealed only after bidding closes, and only the
+        // committed price opens a bid
+        let reveal = |bidder: &mut TestTrader,
+                      market: &mut TestMarket,
+                      launch_auction: &mut TestAccount,
+                      clock: &mut TestAccount,
+                      price| {
+            process_client_instruction(
+                &with_clock(client::reveal_launch_bid(
+                    &id(),
+                    &market.order_book.key,
+                    &bidder.wallet.key,
+                    price,
+                    salt,
+                )),
+                vec![
+                    launch_auction,
+                    &mut market.order_book,
+                    &mut bidder.wallet,
+                    clock,
+                ],
+            )
+        };
+        assert_eq!(
+            reveal(
+                &mut bidders[0],
+                &mut market,
+                &mut launch_auction,
+                &mut clock,
+                20
+            ),
+            Err(OrderBookError::WrongAuctionPhase.into())
+        );
+        let mut clock = TestAccount::clock(2, 150);
+        assert_eq!(
+            reveal(
+                &mut bidders[0],
+                &mut market,
+                &mut launch_auction,
+                &mut clock,
+                21
+            ),
+            Err(ProgramError::InvalidArgument)
+        );
+        for (bidder, (_, price, _)) in bidders.iter_mut().zip(bids).take(3) {
+            reveal(bidder, &mut market, &mut launch_auction, &mut clock, price).unwrap();
+        }
+
+        // Clearing fills the highest bid in full and splits the rest of the
+        // supply between the bids at the 15 clearing price, in whole lots
+        let clear =
+            client::clear_launch_auction(&id(), &market.order_book.key, &seller.open_orders.key);
+        assert_eq!(
+            process_client_instruction(
+                &with_clock(clear.clone()),
+                vec![
+                    &mut launch_auction,
+                    &mut market.order_book,
+                    &mut seller.open_orders,
+                    &mut clock,
+                ],
+            ),
+            Err(OrderBookError::WrongAuctionPhase.into())
+        );
+        let mut clock = TestAccount::clock(3, 200);
+        process_client_instruction(
+            &with_clock(clear),
+            vec![
+                &mut launch_auction,
+                &mut market.order_book,
+                &mut seller.open_orders,
+                &mut clock,
+            ],
+        )
+        .unwrap();
+        let auction = LaunchAuction::unpack(&launch_auction.data).unwrap();
+        assert_eq!((auction.clearing_price, auction.sold), (15, 90));
+        let allocations: Vec<u64> = auction.bids.iter().map(|bid| bid.allocation).collect();
+        assert_eq!(allocations, vec![60, 10, 20, 0]);
+        let seller_balances = seller.balances(&market);
+        assert_eq!(
+            (seller_balances.quote_free, seller_balances.base_free),
+            (135, 10)
+        );
+
+        // Bidders pay the clearing price and get back the rest of their
+        // deposits, all of it for a bid never revealed
+        for (bidder, expected) in bidders
+            .iter_mut()
+            .zip([(60, 110), (10, 85), (20, 70), (0, 50)])
+        {
+            process_client_instruction(
+                &client::settle_launch_bids(&id(), &market.order_book.key, &bidder.open_orders.key),
+                vec![
+                    &mut launch_auction,
+                    &mut market.order_book,
+                    &mut bidder.open_orders,
+                ],
+            )
+            .unwrap();
+            let balances = bidder.balances(&market);
+            assert_eq!(
+                (
+                    balances.base_free,
+                    balances.quote_free,
+                    balances.quote_locked
+                ),
+                (expected.0, expected.1, 0)
+            );
         }
-    );
+        let auction = LaunchAuction::unpack(&launch_auction.data).unwrap();
+        assert!(auction.bids.iter().all(|bid| bid.settled));
+    }
+
+    #[test]
+    fn test_client_compute_budget() {
+        let matching = client::MatchingAccounts {
+            trade_history: Some(Pubkey::new_unique()),
+            candle_series: vec![],
+            maker_open_orders: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+        };
+        let accounts = client::PlaceOrderAccounts {
+            order_book: Pubkey::new_unique(),
+            trader: Pubkey::new_unique(),
+            open_orders: Pubkey::new_unique(),
+            trader_token: Pubkey::new_unique(),
+            vault: Pubkey::new_unique(),
+            order_gate_signer: None,
+            blocklist: None,
+            matching,
+        };
+        // 30_000 + 2 fills * (20_000 + 2_500), plus a quarter
+        assert_eq!(
+            client::ComputeBudget::for_matching(&accounts.matching, 7),
+            client::ComputeBudget {
+                unit_limit: 93_750,
+                unit_price: 7,
+            }
+        );
+        let order = new_order(accounts.trader, 1, 100, OrderType::Buy);
+        let instructions = client::place_order_with_budget(&id(), &accounts, order, 7);
+        assert_eq!(instructions.len(), 3);
+        assert!(instructions[..2]
+            .iter()
+            .all(|instruction| instruction.program_id == solana_sdk::compute_budget::id()));
+        assert_eq!(
+            instructions[2],
+            client::place_order(&id(), &accounts, order)
+        );
+        assert_eq!(
+            client::ComputeBudget::default().prepend(instructions[2].clone()),
+            vec![instructions[2].clone()]
+        );
+    }
+
+    #[test]
+    fn test_client_preview_place_order() {
+        let accounts = client::PlaceOrderAccounts {
+            order_book: Pubkey::new_unique(),
+            trader: Pubkey::new_unique(),
+            open_orders: Pubkey::new_unique(),
+            trader_token: Pubkey::new_unique(),
+            vault: Pubkey::new_unique(),
+            order_gate_signer: None,
+            blocklist: None,
+            matching: client::MatchingAccounts::default(),
+        };
+        let preview = FillPreview {
+            order_id: 3,
+            fill_count: 2,
+            filled_amount: 7,
+            filled_quote: 700,
+            taker_fee: 2,
+            resting_amount: 1,
+        };
+        let simulation = |program_id: Pubkey| {
+            serde_json::json!({
+                "context": { "slot": 1 },
+                "value": {
+                    "err": null,
+                    "logs": [],
+                    "returnData": {
+                        "programId": program_id.to_string(),
+                        "data": [BASE64_STANDARD.encode(preview.pack()), "base64"],
+                    },
+                },
+            })
+        };
+        let rpc_client = |response| {
+            RpcClient::new_mock_with_mocks(
+                "succeeds",
+                [(RpcRequest::SimulateTransaction, response)].into(),
+            )
+        };
+        let order = new_order(accounts.trader, 8, 100, OrderType::Buy);
+        assert_eq!(
+            client::preview_place_order(&rpc_client(simulation(id())), &id(), &accounts, order)
+                .unwrap(),
+            preview
+        );
+        assert!(client::preview_place_order(
+            &rpc_client(simulation(Pubkey::new_unique())),
+            &id(),
+            &accounts,
+            order
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        let order = new_order(trader.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+
+        // A dry run fails once its instruction succeeds, keeping its return
+        // data, and fails with the instruction's own error otherwise
+        let dry_run = |instruction| OrderBookInstruction::DryRun(Box::new(instruction)).pack();
+        let stats = dry_run(OrderBookInstruction::GetMarketStats);
+        assert_eq!(
+            OrderBookInstruction::unpack(&stats).unwrap(),
+            OrderBookInstruction::DryRun(Box::new(OrderBookInstruction::GetMarketStats))
+        );
+        RETURN_DATA.with(|cell| *cell.borrow_mut() = None);
+        assert_eq!(
+            process_instruction(&id(), &[market.order_book.info(), clock.info()], &stats),
+            Err(OrderBookError::DryRunComplete.into())
+        );
+        let data = get_return_data().unwrap().1;
+        assert_eq!(MarketStats::unpack(&data).unwrap().sell_open_interest, 10);
+        let cancel = dry_run(OrderBookInstruction::CancelOrder { order_id: 7 });
+        assert_eq!(
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    trader.wallet.info(),
+                    trader.open_orders.info(),
+                ],
+                &cancel,
+            ),
+            Err(OrderBookError::OrderNotFound.into())
+        );
+        let mut nested = vec![OrderBookInstruction::DRY_RUN_TAG];
+        nested.extend(stats);
+        assert_eq!(
+            OrderBookInstruction::unpack(&nested),
+            Err(ProgramError::InvalidInstructionData)
+        );
+
+        // The client reads the return data out of the rolled back simulation
+        let query = client::get_market_stats(&id(), &market.order_book.key);
+        assert_eq!(
+            client::dry_run(query.clone()).data,
+            dry_run(OrderBookInstruction::GetMarketStats)
+        );
+        let simulation = |code: OrderBookError| {
+            serde_json::json!({
+                "context": { "slot": 1 },
+                "value": {
+                    "err": { "InstructionError": [0, { "Custom": code as u32 }] },
+                    "logs": [],
+                    "returnData": {
+                        "programId": id().to_string(),
+                        "data": [BASE64_STANDARD.encode(&data), "base64"],
+                    },
+                },
+            })
+        };
+        let rpc_client = |response| {
+            RpcClient::new_mock_with_mocks(
+                "succeeds",
+                [(RpcRequest::SimulateTransaction, response)].into(),
+            )
+        };
+        let payer = Pubkey::new_unique();
+        assert_eq!(
+            client::simulate_dry_run(
+                &rpc_client(simulation(OrderBookError::DryRunComplete)),
+                &id(),
+                &payer,
+                query.clone(),
+            )
+            .unwrap(),
+            Some(data.clone())
+        );
+        assert!(client::simulate_dry_run(
+            &rpc_client(simulation(OrderBookError::OrderNotFound)),
+            &id(),
+            &payer,
+            query,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_client_query_pages() {
+        let market = TestMarket::new();
+        let trader = Pubkey::new_unique();
+        let orders: Vec<Order> = (0..3)
+            .map(|i| new_order(trader, 1, 100 + i, OrderType::Sell))
+            .collect();
+        let page = OrderPage {
+            next_cursor: None,
+            items: orders.clone(),
+        };
+        let simulation = |program_id: Pubkey| {
+            serde_json::json!({
+                "context": { "slot": 1 },
+                "value": {
+                    "err": null,
+                    "logs": [],
+                    "returnData": {
+                        "programId": program_id.to_string(),
+                        "data": [BASE64_STANDARD.encode(page.pack()), "base64"],
+                    },
+                },
+            })
+        };
+        let rpc_client = |response| {
+            NonblockingRpcClient::new_mock_with_mocks(
+                "succeeds".to_string(),
+                [(RpcRequest::SimulateTransaction, response)].into(),
+            )
+        };
+        let program_id = id();
+        let streamed: ClientResult<Vec<Order>> = futures::executor::block_on(
+            client::open_orders_of(
+                &rpc_client(simulation(id())),
+                &program_id,
+                &market.order_book.key,
+                &trader,
+            )
+            .try_collect(),
+        );
+        assert_eq!(streamed.unwrap(), orders);
+
+        // Return data of another program fails the stream
+        let streamed: ClientResult<Vec<Order>> = futures::executor::block_on(
+            client::export_book_orders(
+                &rpc_client(simulation(Pubkey::new_unique())),
+                &program_id,
+                &market.order_book.key,
+                &trader,
+            )
+            .try_collect(),
+        );
+        assert!(streamed.is_err());
+    }
+
+    #[test]
+    fn test_client_lookup_table() {
+        let market = TestMarket::new();
+        let addresses =
+            client::market_lookup_addresses(&id(), &market.order_book.key, &market.book());
+        assert!(addresses.contains(&market.order_book.key));
+        assert!(!addresses.contains(&id()));
+
+        let payer = Keypair::new();
+        let (lookup_table, instructions) =
+            client::create_lookup_table(&payer.pubkey(), &payer.pubkey(), 5, &addresses);
+        assert_eq!(instructions.len(), 2);
+
+        let accounts = client::PlaceOrderAccounts {
+            order_book: market.order_book.key,
+            trader: payer.pubkey(),
+            open_orders: Pubkey::new_unique(),
+            trader_token: Pubkey::new_unique(),
+            vault: market.quote_vault.key,
+            order_gate_signer: None,
+            blocklist: None,
+            matching: client::MatchingAccounts::new(&market.book(), vec![Pubkey::new_unique()]),
+        };
+        let order = new_order(payer.pubkey(), 10, 100, OrderType::Buy);
+        let transaction = client::versioned_transaction(
+            &payer.pubkey(),
+            &[client::place_order(&id(), &accounts, order)],
+            &[AddressLookupTableAccount {
+                key: lookup_table,
+                addresses,
+            }],
+            Hash::default(),
+            &[&payer],
+        )
+        .unwrap();
+        let VersionedMessage::V0(message) = &transaction.message else {
+            panic!("expected a v0 message");
+        };
+        assert_eq!(message.address_table_lookups.len(), 1);
+        assert!(!message.account_keys.contains(&market.order_book.key));
+        assert!(message.account_keys.contains(&id()));
+        assert!(transaction.verify_with_results().iter().all(|ok| *ok));
+    }
+
+    #[test]
+    fn test_protocol_pause() {
+        let mut market = TestMarket::new();
+        let mut admin = TestAccount::signer(Pubkey::new_unique());
+        market.config = TestAccount::config(admin.key, false);
+        let mut guardians: Vec<TestAccount> = (0..3)
+            .map(|_| TestAccount::signer(Pubkey::new_unique()))
+            .collect();
+        let keys: Vec<Pubkey> = guardians.iter().map(|guardian| guardian.key).collect();
+        let set_guardians = |market: &mut TestMarket, admin: &mut TestAccount, threshold| {
+            process_instruction(
+                &id(),
+                &[market.config.info(), admin.info()],
+                &OrderBookInstruction::SetGuardians {
+                    pause_threshold: threshold,
+                    guardians: keys.clone(),
+                }
+                .pack(),
+            )
+        };
+        assert_eq!(
+            set_guardians(&mut market, &mut guardians[0], 2),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            set_guardians(&mut market, &mut admin, 4),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        set_guardians(&mut market, &mut admin, 2).unwrap();
+        let set_paused = |market: &mut TestMarket, signers: &mut [TestAccount], paused| {
+            let mut accounts = vec![market.config.info()];
+            accounts.extend(signers.iter_mut().map(TestAccount::info));
+            process_instruction(
+                &id(),
+                &accounts,
+                &OrderBookInstruction::SetPaused { paused }.pack(),
+            )
+        };
+
+        // One guardian is not enough to pause
+        assert_eq!(
+            set_paused(&mut market, &mut guardians[..1], true),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        let mut clock = TestAccount::clock(1, 1);
+        let mut maker = market.trader();
+        let ask = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, ask).unwrap();
+        set_paused(&mut market, &mut guardians[1..], true).unwrap();
+        assert!(Config::unpack(&market.config.data).unwrap().paused);
+
+        // Placing and matching are blocked while canceling and settling work
+        let mut taker = market.trader();
+        let bid = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        assert_eq!(
+            place_order_with_makers(
+                &mut market,
+                &mut taker,
+                &mut clock,
+                std::slice::from_mut(&mut maker),
+                bid,
+            ),
+            Err(OrderBookError::ProtocolPaused.into())
+        );
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                maker.wallet.info(),
+                maker.open_orders.info(),
+            ],
+            &OrderBookInstruction::CancelOrder { order_id: 0 }.pack(),
+        )
+        .unwrap();
+        settle_funds(&mut market, &mut maker).unwrap();
+        assert_eq!(maker.base_token.token_amount(), TestTrader::FUNDS);
+
+        set_paused(&mut market, &mut guardians[..2], false).unwrap();
+        let bid = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        place_order(&mut market, &mut taker, &mut clock, bid).unwrap();
+    }
+
+    #[test]
+    fn test_placement_fee() {
+        let mut market = TestMarket::new();
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let set_fee = |market: &mut TestMarket, signer: &mut TestAccount, lamports| {
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), signer.info()],
+                &OrderBookInstruction::SetPlacementFee { lamports }.pack(),
+            )
+        };
+        let mut clock = TestAccount::clock(1, 1_000);
+        let mut owner = market.trader();
+        assert_eq!(
+            set_fee(&mut market, &mut owner.wallet, 2_000),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            set_fee(
+                &mut market,
+                &mut creator,
+                OrderBook::MAX_PLACEMENT_FEE_LAMPORTS + 1
+            ),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        set_fee(&mut market, &mut creator, 2_000).unwrap();
+
+        // Every placement pays the fee, whether or not the order rests
+        let order = Order {
+            expires_at: 2_000,
+            ..new_order(owner.wallet.key, 10, 100, OrderType::Sell)
+        };
+        place_order(&mut market, &mut owner, &mut clock, order).unwrap();
+        let mut taker = market.trader();
+        let buy = new_order(taker.wallet.key, 5, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut owner),
+            buy,
+        )
+        .unwrap();
+        assert_eq!(owner.wallet.lamports, 1_000_000 - 12_000);
+        assert_eq!(taker.wallet.lamports, 1_000_000 - 2_000);
+        assert_eq!(market.book().crank_incentive_lamports, 4_000);
+        assert_eq!(
+            market.order_book.lamports,
+            OrderBook::ORDER_DEPOSIT_LAMPORTS + 4_000
+        );
+
+        // A keeper canceling an expired order earns one fee from them
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        let mut expired = TestAccount::clock(2, 2_000);
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                keeper.info(),
+                owner.open_orders.info(),
+                expired.info(),
+            ],
+            &OrderBookInstruction::CancelExpiredOrder { order_id: 0 }.pack(),
+        )
+        .unwrap();
+        assert_eq!(keeper.lamports, 1_002_000);
+        assert_eq!(market.book().crank_incentive_lamports, 2_000);
+        assert_eq!(market.order_book.lamports, 2_000);
+    }
+
+    #[test]
+    fn test_order_rate_limit() {
+        let mut market = TestMarket::new();
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let set_limit = |market: &mut TestMarket, signer: &mut TestAccount, max_orders_per_slot| {
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), signer.info()],
+                &OrderBookInstruction::SetOrderRateLimit {
+                    max_orders_per_slot,
+                }
+                .pack(),
+            )
+        };
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        assert_eq!(
+            set_limit(&mut market, &mut trader.wallet, 2),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        // Unlimited markets leave the counter alone
+        let order = new_order(trader.wallet.key, 1, 90, OrderType::Buy);
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        assert_eq!(trader.balances(&market).slot_order_count, 0);
+
+        // Two orders per slot, then the account waits for the next slot
+        set_limit(&mut market, &mut creator, 2).unwrap();
+        assert_eq!(market.book().max_orders_per_slot, 2);
+        for _ in 0..2 {
+            let order = new_order(trader.wallet.key, 1, 90, OrderType::Buy);
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        }
+        let order = new_order(trader.wallet.key, 1, 90, OrderType::Buy);
+        assert_eq!(
+            place_order(&mut market, &mut trader, &mut clock, order),
+            Err(OrderBookError::OrderRateLimited.into())
+        );
+
+        // Other accounts have their own count
+        let mut other = market.trader();
+        let order = new_order(other.wallet.key, 1, 90, OrderType::Buy);
+        place_order(&mut market, &mut other, &mut clock, order).unwrap();
+
+        // The count restarts in the next slot
+        let mut next_slot = TestAccount::clock(2, 2);
+        let order = new_order(trader.wallet.key, 1, 90, OrderType::Buy);
+        place_order(&mut market, &mut trader, &mut next_slot, order).unwrap();
+        let balances = trader.balances(&market);
+        assert_eq!(
+            (balances.last_order_slot, balances.slot_order_count),
+            (2, 1)
+        );
+
+        // Zero lifts the limit again
+        set_limit(&mut market, &mut creator, 0).unwrap();
+        for _ in 0..3 {
+            let order = new_order(trader.wallet.key, 1, 90, OrderType::Buy);
+            place_order(&mut market, &mut trader, &mut next_slot, order).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_market_features() {
+        let mut market = TestMarket::new();
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        let mut set_features = |market: &mut TestMarket, disabled_features| {
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), creator.info()],
+                &OrderBookInstruction::SetMarketFeatures { disabled_features }.pack(),
+            )
+        };
+        set_features(
+            &mut market,
+            OrderBook::FEATURE_PEGS | OrderBook::FEATURE_LINKED_ORDERS,
+        )
+        .unwrap();
+
+        let pegged = Order {
+            peg: PegType::BestBid,
+            ..new_order(trader.wallet.key, 10, 100, OrderType::Buy)
+        };
+        assert_eq!(
+            place_order(&mut market, &mut trader, &mut clock, pegged),
+            Err(OrderBookError::FeatureDisabled.into())
+        );
+        let order = new_order(trader.wallet.key, 10, 100, OrderType::Buy);
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        let linked = Order {
+            linked_order_id: Some(0),
+            ..new_order(trader.wallet.key, 10, 120, OrderType::Sell)
+        };
+        assert_eq!(
+            place_order(&mut market, &mut trader, &mut clock, linked),
+            Err(OrderBookError::FeatureDisabled.into())
+        );
+
+        // Re-enabling a feature takes effect for the next order
+        set_features(&mut market, 0).unwrap();
+        place_order(&mut market, &mut trader, &mut clock, pegged).unwrap();
+        assert_eq!(market.book().buy_orders.len(), 2);
+    }
+
+    #[test]
+    fn test_get_version() {
+        install_syscall_stubs();
+        process_instruction(&id(), &[], &OrderBookInstruction::GetVersion.pack()).unwrap();
+        let version = ProgramVersion::unpack(&get_return_data().unwrap().1).unwrap();
+        assert_eq!(version, ProgramVersion::current());
+        assert_eq!(version.order_book_layout, OrderBook::LAYOUT_VERSION);
+        assert_eq!(version.latest_tag, OrderBookInstruction::LATEST_TAG);
+        assert_eq!(
+            version.features & ProgramVersion::FEATURE_SERDE != 0,
+            cfg!(feature = "serde")
+        );
+    }
+
+    // FIFO queue of the (order_id, trader, amount) resting at a price
+    type ReferenceLevel = VecDeque<(u64, Pubkey, u64)>;
+
+    // Reference price-time priority book for differential tests
+    #[derive(Default)]
+    struct ReferenceBook {
+        bids: BTreeMap<u64, ReferenceLevel>,
+        asks: BTreeMap<u64, ReferenceLevel>,
+        next_order_id: u64,
+    }
+
+    impl ReferenceBook {
+        fn best(&mut self, side: OrderType) -> Option<(&u64, &mut ReferenceLevel)> {
+            match side {
+                OrderType::Buy => self.bids.iter_mut().next_back(),
+                OrderType::Sell => self.asks.iter_mut().next(),
+            }
+        }
+
+        // Place a limit order, returning its fills as (maker order id,
+        // price, amount) and the amount left resting
+        fn place(
+            &mut self,
+            trader: Pubkey,
+            side: OrderType,
+            price: u64,
+            mut amount: u64,
+        ) -> (Vec<(u64, u64, u64)>, u64) {
+            let order_id = self.next_order_id;
+            self.next_order_id += 1;
+            let crosses = |best: u64| match side {
+                OrderType::Buy => best <= price,
+                OrderType::Sell => best >= price,
+            };
+            let mut fills = vec![];
+            while amount > 0 {
+                let Some((&best, queue)) = self.best(side.opposite()) else {
+                    break;
+                };
+                let maker = queue.front_mut().unwrap();
+                if !crosses(best) || maker.1 == trader {
+                    break;
+                }
+                let filled = maker.2.min(amount);
+                maker.2 -= filled;
+                amount -= filled;
+                fills.push((maker.0, best, filled));
+                if maker.2 == 0 {
+                    queue.pop_front();
+                    if queue.is_empty() {
+                        match side.opposite() {
+                            OrderType::Buy => self.bids.remove(&best),
+                            OrderType::Sell => self.asks.remove(&best),
+                        };
+                    }
+                }
+            }
+            // A remainder stopped by self-trade prevention is canceled
+            if self
+                .best(side.opposite())
+                .is_some_and(|(&best, _)| crosses(best))
+            {
+                amount = 0;
+            }
+            if amount > 0 {
+                let side = match side {
+                    OrderType::Buy => &mut self.bids,
+                    OrderType::Sell => &mut self.asks,
+                };
+                side.entry(price)
+                    .or_default()
+                    .push_back((order_id, trader, amount));
+            }
+            (fills, amount)
+        }
+
+        // Resting orders as (order id, price, amount), by order id
+        fn resting(&self) -> BTreeMap<u64, (u64, u64)> {
+            self.bids
+                .iter()
+                .chain(self.asks.iter())
+                .flat_map(|(&price, queue)| {
+                    queue
+                        .iter()
+                        .map(move |&(order_id, _, amount)| (order_id, (price, amount)))
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_matching_matches_reference_model() {
+        for seed in [1u64, 7, 42, 2024] {
+            let mut market = TestMarket::new();
+            let mut clock = TestAccount::clock(1, 1);
+            let mut traders: Vec<TestTrader> = (0..3).map(|_| market.trader()).collect();
+            let mut model = ReferenceBook {
+                next_order_id: market.book().next_order_id,
+                ..ReferenceBook::default()
+            };
+            let mut state = seed;
+            let mut next = |bound: u64| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state % bound
+            };
+            for _ in 0..60 {
+                let taker = next(3) as usize;
+                let side = if next(2) == 0 {
+                    OrderType::Buy
+                } else {
+                    OrderType::Sell
+                };
+                let price = 95 + next(11);
+                let amount = 1 + next(20);
+                traders.swap(0, taker);
+                let (taker, makers) = traders.split_at_mut(1);
+                let order = new_order(taker[0].wallet.key, amount, price, side);
+                place_order_with_makers(&mut market, &mut taker[0], &mut clock, makers, order)
+                    .unwrap();
+
+                let (fills, resting) = model.place(taker[0].wallet.key, side, price, amount);
+                let preview = FillPreview::unpack(&get_return_data().unwrap().1).unwrap();
+                assert_eq!(
+                    (
+                        preview.fill_count as usize,
+                        preview.filled_amount,
+                        preview.filled_quote,
+                        preview.resting_amount
+                    ),
+                    (
+                        fills.len(),
+                        fills.iter().map(|fill| fill.2).sum(),
+                        fills.iter().map(|fill| fill.1 * fill.2).sum(),
+                        resting
+                    ),
+                    "seed {}",
+                    seed
+                );
+                let book = market.book();
+                let engine: BTreeMap<u64, (u64, u64)> = book
+                    .buy_orders
+                    .iter()
+                    .chain(book.sell_orders.iter())
+                    .map(|order| (order.order_id, (order.price, order.amount)))
+                    .collect();
+                assert_eq!(engine, model.resting(), "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_replay_book_changes() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let now = Clock {
+            slot: 1,
+            unix_timestamp: 1,
+            ..Clock::default()
+        };
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        let mut events = vec![];
+        let mut record = |market: &TestMarket, before: OrderBook| {
+            events.push(replay::MarketEvent::BookChange(BookChangeEvent::new(
+                &market.order_book.key,
+                &before,
+                &market.book(),
+                &now,
+            )));
+        };
+
+        let before = market.book();
+        let order = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        record(&market, before);
+        let before = market.book();
+        let order = new_order(maker.wallet.key, 5, 110, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        record(&market, before);
+        let before = market.book();
+        let order = new_order(taker.wallet.key, 12, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            order,
+        )
+        .unwrap();
+        record(&market, before);
+
+        let replayed = replay::ReplayedMarket::replay(market.order_book.key, &events).unwrap();
+        let book = market.book();
+        assert_eq!(
+            replayed,
+            replay::ReplayedMarket {
+                slot: 1,
+                ..replay::ReplayedMarket::from_order_book(
+                    market.order_book.key,
+                    &book,
+                    &clock_at(0, 1)
+                )
+            }
+        );
+        assert_eq!(
+            replayed.bids.into_iter().collect::<Vec<_>>(),
+            vec![(100, 2)]
+        );
+        assert_eq!(
+            replayed.asks.into_iter().collect::<Vec<_>>(),
+            vec![(110, 5)]
+        );
+        assert_eq!(replayed.resting_orders.len(), 2);
+        assert_ne!(replayed.checksum, 0);
+
+        // A replica that applied a different change no longer follows the
+        // market's checksum
+        let mut tampered = events.clone();
+        if let replay::MarketEvent::BookChange(change) = &mut tampered[1] {
+            change.levels[0].2 += 1;
+        }
+        assert_eq!(
+            replay::ReplayedMarket::replay(market.order_book.key, &tampered),
+            Err(replay::ReplayError::ChecksumMismatch { seq_num: 2 })
+        );
+
+        assert_eq!(
+            replay::ReplayedMarket::replay(market.order_book.key, [&events[0], &events[2]]),
+            Err(replay::ReplayError::SequenceGap {
+                expected: 2,
+                found: 3
+            })
+        );
+        assert_eq!(
+            replay::ReplayedMarket::replay(Pubkey::new_unique(), &events),
+            Err(replay::ReplayError::WrongMarket {
+                event_market: market.order_book.key
+            })
+        );
+    }
+
+    #[test]
+    fn test_book_diff() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let now = clock_at(1, 1);
+        let mut maker = market.trader();
+        for (amount, price) in [(10, 100), (5, 110)] {
+            let order = new_order(maker.wallet.key, amount, price, OrderType::Sell);
+            place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        }
+        let before = market.book();
+        assert!(replay::BookDiff::new(&before, &before, &now).is_empty());
+
+        let mut taker = market.trader();
+        let order = new_order(taker.wallet.key, 12, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            order,
+        )
+        .unwrap();
+        let order = new_order(maker.wallet.key, 3, 110, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        let after = market.book();
+        let diff = replay::BookDiff::new(&before, &after, &now);
+        assert_eq!(diff.seq_nums, (before.seq_num, after.seq_num));
+        assert_eq!(
+            diff.removed
+                .iter()
+                .map(|order| order.order_id)
+                .collect::<Vec<_>>(),
+            vec![0]
+        );
+        assert_eq!(
+            diff.added
+                .iter()
+                .map(|order| (order.order_id, order.amount))
+                .collect::<Vec<_>>(),
+            vec![(2, 2), (3, 3)]
+        );
+        assert!(diff.resized.is_empty());
+        assert_eq!(
+            diff.best_levels,
+            vec![
+                replay::BestLevelChange {
+                    side: OrderType::Buy,
+                    before: None,
+                    after: Some((100, 2)),
+                },
+                replay::BestLevelChange {
+                    side: OrderType::Sell,
+                    before: Some((100, 10)),
+                    after: Some((110, 8)),
+                },
+            ]
+        );
+
+        // A partial fill resizes the resting order
+        let order = new_order(maker.wallet.key, 1, 100, OrderType::Sell);
+        place_order_with_makers(
+            &mut market,
+            &mut maker,
+            &mut clock,
+            std::slice::from_mut(&mut taker),
+            order,
+        )
+        .unwrap();
+        let diff = replay::BookDiff::new(&after, &market.book(), &now);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert_eq!(diff.resized, vec![(2, 2, 1)]);
+    }
+
+    #[test]
+    fn test_layout_version() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        assert_eq!(market.order_book.data[0], OrderBook::LAYOUT_VERSION);
+
+        // A market last written by an older program is stamped on its next
+        // write
+        market.order_book.data[0] = 1;
+        let order = new_order(trader.wallet.key, 10, 100, OrderType::Buy);
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        assert_eq!(market.order_book.data[0], OrderBook::LAYOUT_VERSION);
+
+        // A market written by a newer program is refused
+        market.order_book.data[0] = OrderBook::LAYOUT_VERSION + 1;
+        let order = new_order(trader.wallet.key, 10, 90, OrderType::Buy);
+        assert_eq!(
+            place_order(&mut market, &mut trader, &mut clock, order),
+            Err(OrderBookError::UnsupportedLayoutVersion.into())
+        );
+        assert_eq!(
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), clock.info()],
+                &OrderBookInstruction::GetBestBuyOrder.pack(),
+            ),
+            Err(OrderBookError::UnsupportedLayoutVersion.into())
+        );
+
+        // So is a market of an older layout of another size, before its size
+        // is checked, and whatever the version of an account of another size
+        market.order_book.data[0] = OrderBook::LAYOUT_VERSION - 1;
+        market.order_book.data.truncate(OrderBook::LEN - 8);
+        let order = new_order(trader.wallet.key, 10, 90, OrderType::Buy);
+        assert_eq!(
+            place_order(&mut market, &mut trader, &mut clock, order),
+            Err(OrderBookError::OutdatedLayoutVersion.into())
+        );
+        assert_eq!(
+            reader::with_order_book(&id(), &market.order_book.info(), |_| ()),
+            Err(OrderBookError::OutdatedLayoutVersion.into())
+        );
+        market.order_book.data[0] = OrderBook::LAYOUT_VERSION + 1;
+        assert_eq!(
+            reader::with_order_book(&id(), &market.order_book.info(), |_| ()),
+            Err(OrderBookError::UnsupportedLayoutVersion.into())
+        );
+
+        // OpenOrders accounts carry their own layout version
+        assert_eq!(trader.open_orders.data[0], OpenOrders::LAYOUT_VERSION);
+        trader.open_orders.data[0] = OpenOrders::LAYOUT_VERSION + 1;
+        assert_eq!(
+            OpenOrders::unpack(&trader.open_orders.data),
+            Err(OrderBookError::UnsupportedLayoutVersion.into())
+        );
+        // Their version is checked before their size, as a market's is
+        let cancel_all_after = |trader: &mut TestTrader| {
+            process_instruction(
+                &id(),
+                &[trader.open_orders.info(), trader.wallet.info()],
+                &OrderBookInstruction::CancelAllAfter { slot: 10 }.pack(),
+            )
+        };
+        trader.open_orders.data[0] = OpenOrders::LAYOUT_VERSION - 1;
+        trader.open_orders.data.truncate(OpenOrders::LEN - 8);
+        assert_eq!(
+            cancel_all_after(&mut trader),
+            Err(OrderBookError::OutdatedLayoutVersion.into())
+        );
+        trader.open_orders.data[0] = OpenOrders::LAYOUT_VERSION + 1;
+        assert_eq!(
+            cancel_all_after(&mut trader),
+            Err(OrderBookError::UnsupportedLayoutVersion.into())
+        );
+    }
+
+    #[test]
+    fn test_order_book_reader() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let now = clock_at(1, 1);
+        let mut trader = market.trader();
+        for (amount, price, order_type) in [
+            (10, 100, OrderType::Buy),
+            (10, 130, OrderType::Sell),
+            (10, 120, OrderType::Sell),
+        ] {
+            let order = new_order(trader.wallet.key, amount, price, order_type);
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        }
+        let pegged = Order {
+            peg: PegType::BestBid,
+            peg_ticks: 1,
+            ..new_order(trader.wallet.key, 10, 115, OrderType::Buy)
+        };
+        place_order(&mut market, &mut trader, &mut clock, pegged).unwrap();
+
+        let book = market.book();
+        let program_id = id();
+        let (best_bid, best_ask, base_mint) =
+            reader::with_order_book(&program_id, &market.order_book.info(), |view| {
+                assert_eq!(view.order_count(OrderType::Buy), 2);
+                assert_eq!(view.order(OrderType::Sell, 1).unwrap(), book.sell_orders[1]);
+                (
+                    view.best_bid(&now).unwrap(),
+                    view.best_ask(&now).unwrap(),
+                    view.base_mint(),
+                )
+            })
+            .unwrap();
+        assert_eq!(
+            best_bid,
+            book.best_order(OrderType::Buy, &now).map(|(_, p)| p)
+        );
+        assert_eq!(best_bid, Some(101));
+        assert_eq!(best_ask, Some(120));
+        assert_eq!(base_mint, book.base_mint);
+
+        // Accounts of other programs, other sizes and newer layouts are
+        // refused
+        let mut foreign = TestAccount::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            market.order_book.data.clone(),
+        );
+        assert_eq!(
+            reader::with_order_book(&program_id, &foreign.info(), |_| ()),
+            Err(ProgramError::IncorrectProgramId)
+        );
+        market.order_book.data.push(0);
+        assert_eq!(
+            reader::with_order_book(&program_id, &market.order_book.info(), |_| ()),
+            Err(OrderBookError::InvalidAccountSize.into())
+        );
+        market.order_book.data.pop();
+        market.order_book.data[0] = OrderBook::LAYOUT_VERSION + 1;
+        assert_eq!(
+            reader::with_order_book(&program_id, &market.order_book.info(), |_| ()),
+            Err(OrderBookError::UnsupportedLayoutVersion.into())
+        );
+    }
+
+    #[test]
+    fn test_reader_layout_copy() {
+        use reader::fordex_reader::{self, layout};
+        // The no_std reader's copy of the layout matches the program's
+        assert_eq!(layout::LAYOUT_VERSION, OrderBook::LAYOUT_VERSION);
+        assert_eq!(layout::MAX_ORDERS, OrderBook::MAX_ORDERS);
+        assert_eq!(layout::ORDER_BOOK_LEN, OrderBook::LEN);
+        let order_book_offsets = [
+            (
+                layout::ORDER_BOOK_IS_INITIALIZED_OFFSET,
+                ORDER_BOOK_IS_INITIALIZED_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_BASE_MINT_OFFSET,
+                ORDER_BOOK_BASE_MINT_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_QUOTE_MINT_OFFSET,
+                ORDER_BOOK_QUOTE_MINT_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_TICK_SIZE_OFFSET,
+                ORDER_BOOK_TICK_SIZE_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_LOT_SIZE_OFFSET,
+                ORDER_BOOK_LOT_SIZE_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_BUY_COUNT_OFFSET,
+                ORDER_BOOK_BUY_COUNT_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_SELL_COUNT_OFFSET,
+                ORDER_BOOK_SELL_COUNT_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_LAST_TRADE_PRICE_OFFSET,
+                ORDER_BOOK_LAST_TRADE_PRICE_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_DARK_POOL_OFFSET,
+                ORDER_BOOK_DARK_POOL_OFFSET,
+            ),
+            (layout::ORDER_BOOK_SEQ_NUM_OFFSET, ORDER_BOOK_SEQ_NUM_OFFSET),
+            (
+                layout::ORDER_BOOK_BOOK_CHECKSUM_OFFSET,
+                ORDER_BOOK_BOOK_CHECKSUM_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_BUY_ORDERS_OFFSET,
+                ORDER_BOOK_BUY_ORDERS_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_SELL_ORDERS_OFFSET,
+                ORDER_BOOK_SELL_ORDERS_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_BUY_LEVEL_COUNT_OFFSET,
+                ORDER_BOOK_BUY_LEVEL_COUNT_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_SELL_LEVEL_COUNT_OFFSET,
+                ORDER_BOOK_SELL_LEVEL_COUNT_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_BUY_LEVELS_OFFSET,
+                ORDER_BOOK_BUY_LEVELS_OFFSET,
+            ),
+            (
+                layout::ORDER_BOOK_SELL_LEVELS_OFFSET,
+                ORDER_BOOK_SELL_LEVELS_OFFSET,
+            ),
+        ];
+        for (copy, offset) in order_book_offsets {
+            assert_eq!(copy, offset);
+        }
+        let order_offsets = [
+            (layout::ORDER_TRADER_OFFSET, ORDER_TRADER_OFFSET),
+            (layout::ORDER_AMOUNT_OFFSET, ORDER_AMOUNT_OFFSET),
+            (layout::ORDER_PRICE_OFFSET, ORDER_PRICE_OFFSET),
+            (layout::ORDER_TYPE_OFFSET, ORDER_TYPE_OFFSET),
+            (
+                layout::ORDER_UNIX_TIMESTAMP_OFFSET,
+                ORDER_UNIX_TIMESTAMP_OFFSET,
+            ),
+            (layout::ORDER_ID_OFFSET, ORDER_ID_OFFSET),
+            (layout::ORDER_EXPIRES_AT_OFFSET, ORDER_EXPIRES_AT_OFFSET),
+            (layout::ORDER_PEG_OFFSET, ORDER_PEG_OFFSET),
+            (layout::ORDER_PEG_TICKS_OFFSET, ORDER_PEG_TICKS_OFFSET),
+            (
+                layout::ORDER_DECAY_START_PRICE_OFFSET,
+                ORDER_DECAY_START_PRICE_OFFSET,
+            ),
+            (layout::ORDER_DECAY_END_OFFSET, ORDER_DECAY_END_OFFSET),
+            (layout::ORDER_EXPIRY_UNIT_OFFSET, ORDER_EXPIRY_UNIT_OFFSET),
+        ];
+        for (copy, offset) in order_offsets {
+            assert_eq!(copy, offset);
+        }
+        assert_eq!(layout::ORDER_LEN, Order::LEN);
+        assert_eq!(layout::PRICE_LEVEL_LEN, PriceLevel::LEN);
+
+        // It prices a book as the program does, a Dutch auction ask and an
+        // expiring bid included
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 100);
+        let mut trader = market.trader();
+        let dutch = Order {
+            decay_start_price: 200,
+            decay_end: 200,
+            ..new_order(trader.wallet.key, 10, 120, OrderType::Sell)
+        };
+        let expiring = Order {
+            expires_at: 150,
+            ..new_order(trader.wallet.key, 10, 110, OrderType::Buy)
+        };
+        for order in [
+            dutch,
+            expiring,
+            new_order(trader.wallet.key, 10, 100, OrderType::Buy),
+        ] {
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        }
+        let book = market.book();
+        let view = fordex_reader::OrderBookView::new(&market.order_book.data).unwrap();
+        assert_eq!(view.base_mint(), book.base_mint.to_bytes());
+        let order = view.order(fordex_reader::Side::Sell, 0).unwrap();
+        assert_eq!(order.order_id(), book.sell_orders[0].order_id);
+        for unix_timestamp in [100, 150, 200] {
+            let now = clock_at(1, unix_timestamp);
+            let reader_now = fordex_reader::Now {
+                slot: 1,
+                unix_timestamp,
+            };
+            assert_eq!(
+                view.best_bid(reader_now).unwrap(),
+                book.best_order(OrderType::Buy, &now).map(|(_, p)| p)
+            );
+            assert_eq!(
+                view.best_ask(reader_now).unwrap(),
+                book.best_order(OrderType::Sell, &now).map(|(_, p)| p)
+            );
+        }
+    }
+
+    #[test]
+    fn test_serum_layouts() {
+        use serum::{SerumMarket, SerumOpenOrders};
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        for (amount, price, order_type) in [(10, 100, OrderType::Buy), (4, 120, OrderType::Sell)] {
+            let order = new_order(trader.wallet.key, amount, price, order_type);
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        }
+        let book = market.book();
+
+        let serum_market = SerumMarket::from_order_book(&market.order_book.key, &book);
+        let data = serum_market.pack();
+        assert_eq!(data.len(), SerumMarket::LEN);
+        assert_eq!(&data[..5], b"serum");
+        assert_eq!(SerumMarket::unpack(&data).unwrap(), serum_market);
+        assert_eq!(serum_market.coin_mint, book.base_mint);
+        assert_eq!(serum_market.pc_vault, book.quote_vault);
+        assert_eq!(serum_market.fordex_price(120), Some(120));
+
+        let open_orders = OpenOrders::unpack(&trader.open_orders.data).unwrap();
+        let serum_open_orders =
+            SerumOpenOrders::from_open_orders(&market.order_book.key, &open_orders, &book);
+        let data = serum_open_orders.pack();
+        assert_eq!(data.len(), SerumOpenOrders::LEN);
+        let unpacked = SerumOpenOrders::unpack(&data).unwrap();
+        assert_eq!(unpacked, serum_open_orders);
+        let orders: Vec<_> = unpacked
+            .orders
+            .iter()
+            .map(|order| (order.side, order.price_lots(), order.seq_num()))
+            .collect();
+        assert_eq!(
+            orders,
+            vec![
+                (OrderType::Buy, 100, book.buy_orders[0].order_id),
+                (OrderType::Sell, 120, book.sell_orders[0].order_id),
+            ]
+        );
+        let balances = trader.balances(&market);
+        assert_eq!(
+            unpacked.fordex_balances(),
+            OpenOrdersMarket {
+                active_epoch_end: 0,
+                ..balances
+            }
+        );
+
+        // Fordex accounts and Serum accounts of the other kind are refused
+        assert_eq!(
+            SerumOpenOrders::unpack(&trader.open_orders.data),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            SerumMarket::unpack(&data),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn test_get_depth() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        for (amount, price, order_type) in [
+            (10, 100, OrderType::Buy),
+            (5, 100, OrderType::Buy),
+            (7, 90, OrderType::Buy),
+            (4, 120, OrderType::Sell),
+            (6, 110, OrderType::Sell),
+        ] {
+            let order = new_order(trader.wallet.key, amount, price, order_type);
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        }
+        // Pegged orders float and stay out of the index
+        let pegged = Order {
+            peg: PegType::BestBid,
+            ..new_order(trader.wallet.key, 3, 105, OrderType::Buy)
+        };
+        place_order(&mut market, &mut trader, &mut clock, pegged).unwrap();
+
+        let level = |price, amount, order_count| PriceLevel {
+            price,
+            amount,
+            order_count,
+        };
+        let data = query_at(
+            &mut market.order_book,
+            &mut clock,
+            OrderBookInstruction::GetDepth {
+                max_levels: 8,
+                cursor: 0,
+            },
+        );
+        let depth = MarketDepth::unpack(&data).unwrap();
+        assert_eq!(depth.bids, vec![level(100, 15, 2), level(90, 7, 1)]);
+        assert_eq!(depth.asks, vec![level(110, 6, 1), level(120, 4, 1)]);
+        assert_eq!(depth.bids, market.book().price_levels(OrderType::Buy));
+
+        let data = query_at(
+            &mut market.order_book,
+            &mut clock,
+            OrderBookInstruction::GetDepth {
+                max_levels: 1,
+                cursor: 0,
+            },
+        );
+        let depth = MarketDepth::unpack(&data).unwrap();
+        assert_eq!(depth.bids, vec![level(100, 15, 2)]);
+        assert_eq!(depth.asks, vec![level(110, 6, 1)]);
+        assert_eq!(depth.next_cursor, Some(1));
+
+        // The next page continues both sides from the cursor
+        let data = query_at(
+            &mut market.order_book,
+            &mut clock,
+            OrderBookInstruction::GetDepth {
+                max_levels: 1,
+                cursor: 1,
+            },
+        );
+        let depth = MarketDepth::unpack(&data).unwrap();
+        assert_eq!(depth.bids, vec![level(90, 7, 1)]);
+        assert_eq!(depth.asks, vec![level(120, 4, 1)]);
+        assert!(!depth.has_more());
+
+        // Cancels and fills rewrite the index
+        let cancel = OrderBookInstruction::CancelOrder { order_id: 2 }.pack();
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                trader.wallet.info(),
+                trader.open_orders.info(),
+            ],
+            &cancel,
+        )
+        .unwrap();
+        let mut taker = market.trader();
+        let order = new_order(taker.wallet.key, 2, 110, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut trader),
+            order,
+        )
+        .unwrap();
+        let program_id = id();
+        let (bids, asks) =
+            reader::with_order_book(&program_id, &market.order_book.info(), |view| {
+                (
+                    view.levels(OrderType::Buy).collect::<Vec<_>>(),
+                    view.levels(OrderType::Sell).collect::<Vec<_>>(),
+                )
+            })
+            .unwrap();
+        assert_eq!(bids, vec![level(100, 15, 2)]);
+        assert_eq!(asks, vec![level(110, 4, 1), level(120, 4, 1)]);
+    }
+
+    #[test]
+    fn test_error_decoding() {
+        for (code, err) in OrderBookError::ALL.into_iter().enumerate() {
+            assert_eq!(err as usize, code);
+            assert_eq!(OrderBookError::from_program_error(&err.into()), Some(err));
+        }
+        assert_eq!(
+            OrderBookError::from_code(OrderBookError::ALL.len() as u32),
+            None
+        );
+        assert_eq!(
+            OrderBookError::from_program_error(&ProgramError::MissingRequiredSignature),
+            None
+        );
+
+        let logs = vec![
+            format!("Program {} invoke [1]", id()),
+            "Program log: Instruction: PlaceOrder".to_string(),
+            format!("Program {} failed: custom program error: 0x5", id()),
+        ];
+        let err = OrderBookError::from_logs(&id(), &logs).unwrap();
+        assert_eq!(err, OrderBookError::InvalidOrderSize);
+        assert!(err
+            .to_string()
+            .starts_with("Order price not a multiple of tick size"));
+        assert_eq!(
+            OrderBookError::from_logs(&Pubkey::new_unique(), &logs),
+            None
+        );
+    }
+
+    #[test]
+    fn test_market_stats() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        let order = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        let order = new_order(taker.wallet.key, 4, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            order,
+        )
+        .unwrap();
+        let order = new_order(taker.wallet.key, 5, 90, OrderType::Buy);
+        place_order(&mut market, &mut taker, &mut clock, order).unwrap();
+        let stats = |market: &mut TestMarket, clock: &mut TestAccount| {
+            let data = query_at(
+                &mut market.order_book,
+                clock,
+                OrderBookInstruction::GetMarketStats,
+            );
+            MarketStats::unpack(&data).unwrap()
+        };
+        assert_eq!(
+            stats(&mut market, &mut clock),
+            MarketStats {
+                buy_open_interest: 5,
+                sell_open_interest: 6,
+                epoch: 0,
+                unique_traders: 2,
+                ..MarketStats::default()
+            }
+        );
+
+        // Canceling releases open interest; a new epoch restarts the count
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                taker.wallet.info(),
+                taker.open_orders.info(),
+            ],
+            &OrderBookInstruction::CancelOrder { order_id: 2 }.pack(),
+        )
+        .unwrap();
+        let mut next_epoch = TestAccount::new(
+            sysvar::clock::id(),
+            sysvar::id(),
+            bincode::serialize(&Clock {
+                epoch: 1,
+                ..Clock::default()
+            })
+            .unwrap(),
+        );
+        assert_eq!(
+            stats(&mut market, &mut next_epoch),
+            MarketStats {
+                buy_open_interest: 0,
+                sell_open_interest: 6,
+                epoch: 1,
+                unique_traders: 0,
+                canceled_lifetimes: [1, 0, 0, 0, 0, 0, 0, 0],
+                ..MarketStats::default()
+            }
+        );
+        let order = new_order(maker.wallet.key, 1, 110, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut next_epoch, order).unwrap();
+        assert_eq!(stats(&mut market, &mut next_epoch).unique_traders, 1);
+
+        // Without a clock account the epoch comes from the runtime
+        install_syscall_stubs();
+        process_instruction(
+            &id(),
+            &[market.order_book.info()],
+            &OrderBookInstruction::GetMarketStats.pack(),
+        )
+        .unwrap();
+        let runtime = MarketStats::unpack(&get_return_data().unwrap().1).unwrap();
+        assert_eq!((runtime.epoch, runtime.unique_traders), (0, 0));
+
+        // Filling the rest of the first ask two minutes after it was placed
+        // counts it in the minutes bucket
+        UNIX_TIMESTAMP.with(|now| now.set(121));
+        let order = new_order(taker.wallet.key, 6, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            order,
+        )
+        .unwrap();
+        UNIX_TIMESTAMP.with(|now| now.set(0));
+        let stats = stats(&mut market, &mut clock);
+        assert_eq!(stats.filled_lifetimes, [0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(stats.canceled_lifetimes, [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(stats.fill_rate_bps(), 5_000);
+    }
+
+    #[test]
+    fn test_order_book() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(7, 1_700_000_000);
+
+        // Place a buy order and a sell order
+        let mut buyer = market.trader();
+        let mut seller = market.trader();
+        let buy = new_order(buyer.wallet.key, 100, 500, OrderType::Buy);
+        let sell = new_order(seller.wallet.key, 50, 600, OrderType::Sell);
+        place_order(&mut market, &mut buyer, &mut clock, buy).unwrap();
+        place_order(&mut market, &mut seller, &mut clock, sell).unwrap();
+
+        // Both orders are stamped with the clock's slot and time
+        let stamped = |order: Order, order_id: u64| Order {
+            slot: 7,
+            unix_timestamp: 1_700_000_000,
+            order_id,
+            ..order
+        };
+        let data = query(
+            &mut market.order_book,
+            OrderBookInstruction::GetBestBuyOrder,
+        );
+        assert_eq!(Order::unpack(&data).unwrap(), stamped(buy, 0));
+        let data = query(
+            &mut market.order_book,
+            OrderBookInstruction::GetBestSellOrder,
+        );
+        assert_eq!(Order::unpack(&data).unwrap(), stamped(sell, 1));
+
+        // Their funds are locked in the vaults
+        assert_eq!(buyer.quote_token.token_amount(), TestTrader::FUNDS - 50_000);
+        assert_eq!(buyer.balances(&market).quote_locked, 50_000);
+        assert_eq!(market.base_vault.token_amount(), 50);
+        assert_eq!(seller.balances(&market).base_locked, 50);
+    }
+
+    #[test]
+    fn test_matching_credits_open_orders() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut makers = [market.trader(), market.trader()];
+        for (maker, price) in makers.iter_mut().zip([101, 100]) {
+            let order = new_order(maker.wallet.key, 10, price, OrderType::Sell);
+            place_order(&mut market, maker, &mut clock, order).unwrap();
+        }
+        assert_eq!(
+            market.order_book.lamports,
+            2 * OrderBook::ORDER_DEPOSIT_LAMPORTS
+        );
+
+        // Fill the best ask completely and the next one partially
+        let mut taker = market.trader();
+        let order = new_order(taker.wallet.key, 15, 105, OrderType::Buy);
+        // Fail on copies, since the stubs do not roll back the token transfer
+        assert_eq!(
+            place_order_with_makers(
+                &mut market.clone(),
+                &mut taker.clone(),
+                &mut clock,
+                &mut makers[1..].to_vec(),
+                order
+            ),
+            Err(OrderBookError::MakerAccountMissing.into())
+        );
+        place_order_with_makers(&mut market, &mut taker, &mut clock, &mut makers, order).unwrap();
+
+        let book = market.book();
+        assert!(book.buy_orders.is_empty());
+        assert_eq!(book.sell_orders.len(), 1);
+        assert_eq!(book.sell_orders[0].trader, makers[0].wallet.key);
+        assert_eq!(book.sell_orders[0].amount, 5);
+
+        // The taker locked 15 at its limit price and gets the improvement back
+        assert_eq!(
+            taker.balances(&market),
+            OpenOrdersMarket {
+                market: market.order_book.key,
+                base_free: 15,
+                quote_free: 15 * 105 - 1_000 - 505,
+                active_epoch_end: 1,
+                ..OpenOrdersMarket::default()
+            }
+        );
+        assert_eq!(makers[0].balances(&market).base_locked, 5);
+        assert_eq!(makers[0].balances(&market).quote_free, 505);
+        assert_eq!(makers[0].balances(&market).order_count, 1);
+        assert_eq!(makers[1].balances(&market).quote_free, 1_000);
+        assert_eq!(makers[1].balances(&market).order_count, 0);
+        assert_eq!(
+            market.order_book.lamports,
+            OrderBook::ORDER_DEPOSIT_LAMPORTS
+        );
+
+        // Settling pays out the proceeds and the filled maker's deposit
+        settle_funds(&mut market, &mut taker).unwrap();
+        assert_eq!(taker.base_token.token_amount(), TestTrader::FUNDS + 15);
+        assert_eq!(taker.quote_token.token_amount(), TestTrader::FUNDS - 1_505);
+        assert!(OpenOrders::unpack(&taker.open_orders.data)
+            .unwrap()
+            .markets
+            .is_empty());
+        settle_funds(&mut market, &mut makers[1]).unwrap();
+        assert_eq!(
+            makers[1].quote_token.token_amount(),
+            TestTrader::FUNDS + 1_000
+        );
+        assert_eq!(makers[1].wallet.lamports, 1_000_000);
+        assert_eq!(market.quote_vault.token_amount(), 505);
+    }
+
+    #[test]
+    fn test_open_orders_across_markets() {
+        let quote_mint = Pubkey::new_unique();
+        let mut markets = [
+            TestMarket::with_quote_mint(quote_mint),
+            TestMarket::with_quote_mint(quote_mint),
+        ];
+        let mut clock = TestAccount::clock(1, 1);
+
+        // One OpenOrders account keeps separate balances for each market
+        let mut trader = markets[0].trader();
+        for (market, price) in markets.iter_mut().zip([10, 20]) {
+            let order = new_order(trader.wallet.key, 3, price, OrderType::Buy);
+            place_order(market, &mut trader, &mut clock, order).unwrap();
+        }
+        let open_orders = OpenOrders::unpack(&trader.open_orders.data).unwrap();
+        assert_eq!(open_orders.markets.len(), 2);
+        assert_eq!(trader.balances(&markets[0]).quote_locked, 30);
+        assert_eq!(trader.balances(&markets[1]).quote_locked, 60);
+        assert_eq!(trader.quote_token.token_amount(), TestTrader::FUNDS - 90);
+
+        // The account is tied to its quote mint
+        let mut other = TestMarket::new();
+        let order = new_order(trader.wallet.key, 1, 1, OrderType::Sell);
+        trader.base_token =
+            TestAccount::token_account(other.book().base_mint, trader.wallet.key, 1);
+        assert_eq!(
+            place_order(&mut other, &mut trader, &mut clock, order),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_settle_many() {
+        let (usdc, usdt) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let markets = [usdc, usdt, usdc]
+            .map(TestMarket::with_quote_mint)
+            .map(|market| (market.order_book.key, market.book()));
+        let owner = Pubkey::new_unique();
+
+        // The two USDC markets settle together
+        let instructions = client::settle_many(&id(), &owner, &markets, 2);
+        assert_eq!(instructions.len(), 2);
+        let settled = |instruction: &Instruction| -> Vec<Pubkey> {
+            instruction.accounts[8..]
+                .chunks(6)
+                .map(|group| group[0].pubkey)
+                .collect()
+        };
+        assert_eq!(settled(&instructions[0]), vec![markets[0].0, markets[2].0]);
+        assert_eq!(settled(&instructions[1]), vec![markets[1].0]);
+        assert_eq!(
+            instructions[1].accounts[1].pubkey,
+            OpenOrders::find_address(&id(), &owner, &usdt).0
+        );
+        assert_eq!(
+            instructions[0].accounts[8 + 5].pubkey,
+            get_associated_token_address(&owner, &markets[0].1.base_mint)
+        );
+        assert_eq!(
+            OrderBookInstruction::unpack(&instructions[0].data),
+            Ok(OrderBookInstruction::SettleAndWithdrawAll)
+        );
+
+        // Smaller batches split a quote mint's markets across instructions
+        let instructions = client::settle_many(&id(), &owner, &markets, 1);
+        assert_eq!(instructions.len(), 3);
+    }
+
+    #[test]
+    fn test_settle_and_withdraw_all() {
+        let quote_mint = Pubkey::new_unique();
+        let mut markets = [
+            TestMarket::with_quote_mint(quote_mint),
+            TestMarket::with_quote_mint(quote_mint),
+        ];
+        let mut clock = TestAccount::clock(1, 1);
+
+        // Sell in the first market and buy in the second
+        let mut trader = markets[0].trader();
+        trader.wallet.lamports = 1_000_000_000;
+        let sides = [(OrderType::Sell, 10), (OrderType::Buy, 20)];
+        for (market, (order_type, price)) in markets.iter_mut().zip(sides) {
+            let mut maker = market.trader();
+            let order = new_order(maker.wallet.key, 2, price, order_type.opposite());
+            place_order(market, &mut maker, &mut clock, order).unwrap();
+            let order = new_order(trader.wallet.key, 2, price, order_type);
+            place_order_with_makers(
+                market,
+                &mut trader,
+                &mut clock,
+                std::slice::from_mut(&mut maker),
+                order,
+            )
+            .unwrap();
+        }
+
+        // Withdraw both markets into newly created associated token accounts
+        let base_mints = markets.each_ref().map(|market| market.book().base_mint);
+        let mut quote_ata = TestAccount::associated_token_account(&trader.wallet.key, &quote_mint);
+        let mut base_atas =
+            base_mints.map(|mint| TestAccount::associated_token_account(&trader.wallet.key, &mint));
+        let mut mints = base_mints.map(|mint| TestAccount::new(mint, spl_token::id(), vec![]));
+        let mut vault_signers = markets.each_ref().map(|market| {
+            TestAccount::new(
+                OrderBook::find_vault_signer(&id(), &market.order_book.key).0,
+                Pubkey::default(),
+                vec![],
+            )
+        });
+        let mut quote_mint = TestAccount::new(quote_mint, spl_token::id(), vec![]);
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+        let mut associated_token_program = TestAccount::new(
+            spl_associated_token_account::id(),
+            Pubkey::default(),
+            vec![],
+        );
+        let mut rent = TestAccount::rent();
+        let mut accounts = vec![
+            trader.wallet.info(),
+            trader.open_orders.info(),
+            quote_mint.info(),
+            quote_ata.info(),
+            system_program.info(),
+            token_program.info(),
+            associated_token_program.info(),
+            rent.info(),
+        ];
+        let groups = markets
+            .iter_mut()
+            .zip(vault_signers.iter_mut())
+            .zip(mints.iter_mut().zip(base_atas.iter_mut()));
+        for ((market, vault_signer), (mint, base_ata)) in groups {
+            accounts.extend([
+                market.order_book.info(),
+                market.base_vault.info(),
+                market.quote_vault.info(),
+                vault_signer.info(),
+                mint.info(),
+                base_ata.info(),
+            ]);
+        }
+        process_instruction(
+            &id(),
+            &accounts,
+            &OrderBookInstruction::SettleAndWithdrawAll.pack(),
+        )
+        .unwrap();
+        drop(accounts);
+
+        assert_eq!(quote_ata.token_amount(), 20);
+        assert_eq!(base_atas[1].token_amount(), 2);
+        // Nothing was owed in the first market's base token
+        assert_eq!(base_atas[0].owner, system_program::id());
+        assert!(OpenOrders::unpack(&trader.open_orders.data)
+            .unwrap()
+            .markets
+            .is_empty());
+    }
+
+    #[test]
+    fn test_settle_funds_creates_associated_token_account() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        trader.wallet.lamports = 1_000_000_000;
+        let order = new_order(trader.wallet.key, 2, 10, OrderType::Buy);
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                trader.wallet.info(),
+                trader.open_orders.info(),
+            ],
+            &OrderBookInstruction::CancelOrder { order_id: 0 }.pack(),
+        )
+        .unwrap();
+
+        // Settling into a quote account that does not exist yet only works
+        // when the accounts to create it are passed
+        let book = market.book();
+        trader.quote_token =
+            TestAccount::associated_token_account(&trader.wallet.key, &book.quote_mint);
+        assert!(settle_funds(&mut market.clone(), &mut trader.clone()).is_err());
+        let mut trailing = [
+            TestAccount::new(book.base_mint, spl_token::id(), vec![]),
+            TestAccount::new(book.quote_mint, spl_token::id(), vec![]),
+            TestAccount::new(system_program::id(), Pubkey::default(), vec![]),
+            TestAccount::new(
+                spl_associated_token_account::id(),
+                Pubkey::default(),
+                vec![],
+            ),
+        ];
+        // Instruction data of only the tag settles everything
+        let settle_all = OrderBookInstruction::unpack(&[12]).unwrap();
+        settle_funds_with(&mut market, &mut trader, &mut trailing, settle_all).unwrap();
+        assert_eq!(trader.quote_token.owner, spl_token::id());
+        assert_eq!(trader.quote_token.token_amount(), 20);
+    }
+
+    #[test]
+    fn test_settle_funds_to_recipient() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        let order = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        let order = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            order,
+        )
+        .unwrap();
+
+        // The maker's proceeds go to a treasury the trading key names
+        let book = market.book();
+        let treasury = Pubkey::new_unique();
+        let mut paid_out = maker.clone();
+        paid_out.base_token = TestAccount::token_account(book.base_mint, treasury, 0);
+        paid_out.quote_token = TestAccount::token_account(book.quote_mint, treasury, 0);
+        let settle = |recipient, max_quote| OrderBookInstruction::SettleFunds {
+            max_base: u64::MAX,
+            max_quote,
+            recipient,
+        };
+        assert_eq!(
+            settle_funds_with(&mut market, &mut paid_out, &mut [], settle(None, 400)),
+            Err(ProgramError::InvalidArgument)
+        );
+        settle_funds_with(
+            &mut market,
+            &mut paid_out,
+            &mut [],
+            settle(Some(treasury), 400),
+        )
+        .unwrap();
+        assert_eq!(paid_out.quote_token.token_amount(), 400);
+        assert_eq!(paid_out.balances(&market).quote_free, 600);
+
+        // The rest is settled to the owner's own accounts
+        settle_funds(&mut market, &mut paid_out.clone()).unwrap_err();
+        maker.open_orders = paid_out.open_orders;
+        let quote_before = maker.quote_token.token_amount();
+        settle_funds(&mut market, &mut maker).unwrap();
+        assert_eq!(maker.quote_token.token_amount() - quote_before, 600);
+        assert_eq!(maker.balances(&market).quote_free, 0);
+    }
+
+    #[test]
+    fn test_withdraw_authority() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        let mut cold = TestAccount::signer(Pubkey::new_unique());
+        let set_authority =
+            |trader: &mut TestTrader, authority: &mut TestAccount, new: &mut TestAccount| {
+                let instruction = OrderBookInstruction::SetWithdrawAuthority {
+                    withdraw_authority: new.key,
+                };
+                process_instruction(
+                    &id(),
+                    &[trader.open_orders.info(), authority.info(), new.info()],
+                    &instruction.pack(),
+                )
+            };
+        let mut wallet = trader.wallet.clone();
+        set_authority(&mut trader, &mut wallet, &mut cold).unwrap();
+        let mut new_wallet = wallet.clone();
+        assert_eq!(
+            set_authority(&mut trader, &mut wallet, &mut new_wallet),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        // The trading key still places and cancels orders
+        let order = new_order(trader.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                trader.wallet.info(),
+                trader.open_orders.info(),
+            ],
+            &OrderBookInstruction::CancelOrder { order_id: 0 }.pack(),
+        )
+        .unwrap();
+        assert_eq!(trader.balances(&market).base_free, 10);
+
+        // but only the cold key withdraws
+        assert_eq!(
+            settle_funds(&mut market, &mut trader),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        let book = market.book();
+        let mut withdrawal = TestTrader {
+            wallet: cold.clone(),
+            base_token: TestAccount::token_account(book.base_mint, cold.key, 0),
+            quote_token: TestAccount::token_account(book.quote_mint, cold.key, 0),
+            ..trader.clone()
+        };
+        settle_funds(&mut market, &mut withdrawal).unwrap();
+        assert_eq!(withdrawal.base_token.token_amount(), 10);
+        assert_eq!(withdrawal.balances(&market).base_free, 0);
+    }
+
+    #[test]
+    fn test_cancel_all_after() {
+        let mut market = TestMarket::new();
+        let mut trader = market.trader();
+        let mut clock = TestAccount::clock(1, 1);
+        let order = new_order(trader.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        let mut wallet = trader.wallet.clone();
+        process_instruction(
+            &id(),
+            &[trader.open_orders.info(), wallet.info()],
+            &OrderBookInstruction::CancelAllAfter { slot: 1 }.pack(),
+        )
+        .unwrap();
+
+        // An order landing after the cutoff can be canceled by anyone, one
+        // placed before it only once it expires
+        let mut clock = TestAccount::clock(2, 2);
+        let order = new_order(trader.wallet.key, 5, 110, OrderType::Sell);
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        let mut cancel = |market: &mut TestMarket, trader: &mut TestTrader, order_id| {
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    keeper.info(),
+                    trader.open_orders.info(),
+                    TestAccount::clock(3, 3).info(),
+                ],
+                &OrderBookInstruction::CancelExpiredOrder { order_id }.pack(),
+            )
+        };
+        assert_eq!(
+            cancel(&mut market, &mut trader, 0),
+            Err(OrderBookError::OrderNotExpired.into())
+        );
+        cancel(&mut market, &mut trader, 1).unwrap();
+        let balances = trader.balances(&market);
+        assert_eq!((balances.base_locked, balances.base_free), (10, 5));
+        assert_eq!(market.book().sell_orders.len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_accounts() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        let ask = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, ask).unwrap();
+
+        // The taker's OpenOrders account passed again as a maker's, or a
+        // maker's passed twice
+        let bid = new_order(taker.wallet.key, 5, 100, OrderType::Buy);
+        for mut makers in [
+            vec![taker.clone(), maker.clone()],
+            vec![maker.clone(), maker.clone()],
+        ] {
+            assert_eq!(
+                place_order_with_makers(&mut market, &mut taker, &mut clock, &mut makers, bid),
+                Err(OrderBookError::DuplicateAccount.into())
+            );
+        }
+        place_order_with_makers(&mut market, &mut taker, &mut clock, &mut [maker], bid).unwrap();
+
+        // Funds are never settled into the vault they are paid from
+        let mut settling = taker.clone();
+        settling.base_token = market.base_vault.clone();
+        assert_eq!(
+            settle_funds(&mut market, &mut settling),
+            Err(OrderBookError::DuplicateAccount.into())
+        );
+        settle_funds(&mut market, &mut taker).unwrap();
+    }
+
+    #[test]
+    fn test_token_balance_mismatch() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        let order = new_order(trader.wallet.key, 10, 100, OrderType::Buy);
+
+        // A deposit the vault only partly receives is refused
+        TRANSFER_FEE.with(|fee| fee.set(1));
+        let result = place_order(&mut market, &mut trader, &mut clock, order);
+        TRANSFER_FEE.with(|fee| fee.set(0));
+        assert_eq!(result, Err(OrderBookError::TokenBalanceMismatch.into()));
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+    }
+
+    #[test]
+    fn test_vault_and_mint_checks() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        let order = new_order(trader.wallet.key, 10, 100, OrderType::Buy);
+
+        // A bid paid from a base token account
+        let mut wrong_token = trader.clone();
+        wrong_token.quote_token = trader.base_token.clone();
+        assert_eq!(
+            place_order(&mut market, &mut wrong_token, &mut clock, order),
+            Err(OrderBookError::MintMismatch.into())
+        );
+
+        // A bid paid into the base vault
+        let mut wrong_vault = market.clone();
+        wrong_vault.quote_vault = market.base_vault.clone();
+        assert_eq!(
+            place_order(&mut wrong_vault, &mut trader, &mut clock, order),
+            Err(OrderBookError::WrongVault.into())
+        );
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+    }
+
+    #[test]
+    fn test_order_gate() {
+        let mut market = TestMarket::new();
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let gate = Pubkey::new_unique();
+        let instruction = OrderBookInstruction::SetOrderGate { order_gate: gate };
+        process_instruction(
+            &id(),
+            &[market.order_book.info(), creator.info()],
+            &instruction.pack(),
+        )
+        .unwrap();
+        assert_eq!(market.book().order_gate, gate);
+
+        // Orders without the gate program's signature are refused
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        let order = new_order(trader.wallet.key, 10, 100, OrderType::Sell);
+        assert_eq!(
+            place_order(&mut market, &mut trader, &mut clock, order),
+            Err(OrderBookError::OrderGateRequired.into())
+        );
+
+        // The signer PDA goes right after the Config PDA, where the market
+        // has no feeds to precede the makers
+        let signer = OrderBook::find_order_gate_signer(&gate, &market.order_book.key).0;
+        let mut unsigned = TestTrader {
+            open_orders: TestAccount::new(signer, Pubkey::default(), vec![]),
+            ..trader.clone()
+        };
+        assert_eq!(
+            place_order_with_makers(
+                &mut market,
+                &mut trader,
+                &mut clock,
+                std::slice::from_mut(&mut unsigned),
+                order,
+            ),
+            Err(OrderBookError::OrderGateRequired.into())
+        );
+        let mut gate_signer = TestTrader {
+            open_orders: TestAccount::signer(signer),
+            ..trader.clone()
+        };
+        place_order_with_makers(
+            &mut market,
+            &mut trader,
+            &mut clock,
+            std::slice::from_mut(&mut gate_signer),
+            order,
+        )
+        .unwrap();
+        assert_eq!(trader.balances(&market).base_locked, 10);
+    }
+
+    #[test]
+    fn test_blocklist() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        let mut authority = TestAccount::signer(Pubkey::new_unique());
+        let blocklist_key = Blocklist::find_address(&id(), &authority.key).0;
+        let blocklist = Blocklist {
+            authority: authority.key,
+            addresses: vec![],
+        };
+        let mut blocklist_account = TestAccount::new(blocklist_key, id(), blocklist.pack());
+
+        // Only the authority lists addresses
+        let instruction = OrderBookInstruction::UpdateBlocklist {
+            address: trader.wallet.key,
+            blocked: true,
+        };
+        let mut stranger = TestAccount::signer(Pubkey::new_unique());
+        assert!(process_instruction(
+            &id(),
+            &[blocklist_account.info(), stranger.info()],
+            &instruction.pack(),
+        )
+        .is_err());
+        process_instruction(
+            &id(),
+            &[blocklist_account.info(), authority.info()],
+            &instruction.pack(),
+        )
+        .unwrap();
+        assert!(Blocklist::unpack(&blocklist_account.data)
+            .unwrap()
+            .contains(&trader.wallet.key));
+
+        // Markets without a blocklist are unaffected
+        let order = new_order(trader.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let instruction = OrderBookInstruction::SetBlocklist {
+            blocklist: blocklist_key,
+        };
+        process_instruction(
+            &id(),
+            &[market.order_book.info(), creator.info()],
+            &instruction.pack(),
+        )
+        .unwrap();
+        assert_eq!(market.book().blocklist, blocklist_key);
+
+        // Once opted in, the Blocklist goes right after the Config PDA, where
+        // the market has no feeds to precede the makers
+        assert_eq!(
+            place_order(&mut market, &mut trader, &mut clock, order),
+            Err(ProgramError::InvalidArgument)
+        );
+        let mut listed = TestTrader {
+            open_orders: blocklist_account.clone(),
+            ..trader.clone()
+        };
+        assert_eq!(
+            place_order_with_makers(
+                &mut market,
+                &mut trader,
+                &mut clock,
+                std::slice::from_mut(&mut listed),
+                order,
+            ),
+            Err(OrderBookError::AddressBlocked.into())
+        );
+
+        // Delisting lets the trader back in
+        let instruction = OrderBookInstruction::UpdateBlocklist {
+            address: trader.wallet.key,
+            blocked: false,
+        };
+        process_instruction(
+            &id(),
+            &[blocklist_account.info(), authority.info()],
+            &instruction.pack(),
+        )
+        .unwrap();
+        let mut delisted = TestTrader {
+            open_orders: blocklist_account.clone(),
+            ..trader.clone()
+        };
+        place_order_with_makers(
+            &mut market,
+            &mut trader,
+            &mut clock,
+            std::slice::from_mut(&mut delisted),
+            order,
+        )
+        .unwrap();
+        assert_eq!(trader.balances(&market).base_locked, 20);
+
+        // A listed beneficial owner is checked from the OpenOrders account,
+        // whatever the order claims
+        let fund = Pubkey::new_unique();
+        process_instruction(
+            &id(),
+            &[blocklist_account.info(), authority.info()],
+            &OrderBookInstruction::UpdateBlocklist {
+                address: fund,
+                blocked: true,
+            }
+            .pack(),
+        )
+        .unwrap();
+        let mut strategy = market.trader();
+        let mut open_orders = OpenOrders::unpack(&strategy.open_orders.data).unwrap();
+        open_orders.beneficial_owner = fund;
+        strategy.open_orders.data = open_orders.pack();
+        let order = Order {
+            beneficial_owner: strategy.wallet.key,
+            ..new_order(strategy.wallet.key, 10, 100, OrderType::Sell)
+        };
+        let mut listed = TestTrader {
+            open_orders: blocklist_account.clone(),
+            ..strategy.clone()
+        };
+        assert_eq!(
+            place_order_with_makers(
+                &mut market,
+                &mut strategy,
+                &mut clock,
+                std::slice::from_mut(&mut listed),
+                order,
+            ),
+            Err(OrderBookError::AddressBlocked.into())
+        );
+    }
+
+    #[test]
+    fn test_taker_fees_and_sweep() {
+        let mut market = TestMarket::new();
+        let fee_collector = Pubkey::new_unique();
+        let mut book = market.book();
+        book.buy_taker_fee_bps = 100;
+        book.sell_taker_fee_bps = 50;
+        book.fee_collector = fee_collector;
+        book.protocol_fee_bps = 2_000;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut clock = TestAccount::clock(1, 1);
+
+        // A taker bid pays the fee on top, a taker ask from its proceeds at
+        // the sell side's lower rate
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        for (maker_side, amount) in [(OrderType::Sell, 10), (OrderType::Buy, 5)] {
+            let order = new_order(maker.wallet.key, amount, 100, maker_side);
+            place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+            let order = new_order(taker.wallet.key, amount, 100, maker_side.opposite());
+            place_order_with_makers(
+                &mut market,
+                &mut taker,
+                &mut clock,
+                std::slice::from_mut(&mut maker),
+                order,
+            )
+            .unwrap();
+        }
+        assert_eq!(taker.quote_token.token_amount(), TestTrader::FUNDS - 1_010);
+        let balances = taker.balances(&market);
+        assert_eq!((balances.base_free, balances.quote_free), (5, 498));
+        // The maker's bid reused half of its ask's proceeds
+        let balances = maker.balances(&market);
+        assert_eq!((balances.base_free, balances.quote_free), (5, 500));
+        // The protocol takes a fifth of each order's fees, rounded down
+        let book = market.book();
+        assert_eq!((book.fees_collected, book.fees_outstanding), (10, 10));
+        assert_eq!(
+            (book.protocol_fees_collected, book.protocol_fees_outstanding),
+            (2, 2)
+        );
+
+        // Fees can only be swept to the fee collector
+        let mut vault_signer = TestAccount::new(
+            OrderBook::find_vault_signer(&id(), &market.order_book.key).0,
+            Pubkey::default(),
+            vec![],
+        );
+        let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+        let treasury = Pubkey::new_unique();
+        let mut config = TestAccount::config(treasury, false);
+        let mut sweep_with = |market: &mut TestMarket,
+                              destination: &mut TestAccount,
+                              instruction: OrderBookInstruction| {
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    market.quote_vault.info(),
+                    destination.info(),
+                    vault_signer.info(),
+                    token_program.info(),
+                    clock.info(),
+                    config.info(),
+                ],
+                &instruction.pack(),
+            )
+        };
+        let mut sweep = |market: &mut TestMarket, destination: &mut TestAccount| {
+            sweep_with(market, destination, OrderBookInstruction::SweepFees)
+        };
+        let mut intruder = TestAccount::token_account(book.quote_mint, Pubkey::new_unique(), 0);
+        assert_eq!(
+            sweep(&mut market, &mut intruder),
+            Err(ProgramError::InvalidArgument)
+        );
+        let mut destination = TestAccount::token_account(book.quote_mint, fee_collector, 0);
+        sweep(&mut market, &mut destination).unwrap();
+        assert_eq!(destination.token_amount(), 10);
+        let book = market.book();
+        assert_eq!(
+            (book.fees_collected, book.fees_swept, book.fees_outstanding),
+            (10, 10, 0)
+        );
+
+        // The protocol share goes to the Config treasury
+        let mut treasury_account = TestAccount::token_account(book.quote_mint, treasury, 0);
+        sweep_with(
+            &mut market,
+            &mut treasury_account,
+            OrderBookInstruction::SweepProtocolFees,
+        )
+        .unwrap();
+        assert_eq!(treasury_account.token_amount(), 2);
+        assert_eq!(market.book().protocol_fees_swept, 2);
+        // What remains backs the traders' free quote balances
+        assert_eq!(market.quote_vault.token_amount(), 498 + 500);
+    }
+
+    #[test]
+    fn test_pegged_orders() {
+        let mut market = TestMarket::new();
+        let mut book = market.book();
+        book.tick_size = 10;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut bidder = market.trader();
+        let mut asker = market.trader();
+        let mut pegger = market.trader();
+
+        // A pegged order without a reference price is inactive
+        let pegged = Order {
+            peg: PegType::Midpoint,
+            ..new_order(pegger.wallet.key, 10, 200, OrderType::Buy)
+        };
+        place_order(&mut market, &mut pegger, &mut clock, pegged).unwrap();
+        assert_eq!(market.book().get_best_buy_order(&clock_at(0, 1)), None);
+
+        // The midpoint rounds down to a tick for a bid and floats with the book
+        let bid = new_order(bidder.wallet.key, 10, 100, OrderType::Buy);
+        place_order(&mut market, &mut bidder, &mut clock, bid).unwrap();
+        let ask = new_order(asker.wallet.key, 10, 150, OrderType::Sell);
+        place_order(&mut market, &mut asker, &mut clock, ask).unwrap();
+        let best = market.book().get_best_buy_order(&clock_at(0, 1)).unwrap();
+        assert_eq!((best.order_id, best.price), (0, 120));
+        let ask = new_order(asker.wallet.key, 10, 130, OrderType::Sell);
+        place_order(&mut market, &mut asker, &mut clock, ask).unwrap();
+        assert_eq!(
+            market
+                .book()
+                .get_best_buy_order(&clock_at(0, 1))
+                .unwrap()
+                .price,
+            110
+        );
+
+        // Offsets are capped by the limit and never cross the fixed asks
+        let book = market.book();
+        let offset = |peg_ticks| Order {
+            peg: PegType::BestBid,
+            peg_ticks,
+            ..new_order(pegger.wallet.key, 10, 200, OrderType::Buy)
+        };
+        assert_eq!(book.effective_price(&offset(-2), &clock_at(0, 1)), Some(80));
+        assert_eq!(book.effective_price(&offset(5), &clock_at(0, 1)), Some(120));
+        let capped = Order {
+            price: 110,
+            ..offset(2)
+        };
+        assert_eq!(book.effective_price(&capped, &clock_at(0, 1)), Some(110));
+
+        // Takers fill the pegged bid at its effective price, releasing the
+        // rest of the quote it locked at its limit
+        let mut taker = market.trader();
+        let sell = new_order(taker.wallet.key, 10, 100, OrderType::Sell);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut pegger),
+            sell,
+        )
+        .unwrap();
+        assert_eq!(taker.balances(&market).quote_free, 1_100);
+        let balances = pegger.balances(&market);
+        assert_eq!(
+            (
+                balances.base_free,
+                balances.quote_free,
+                balances.quote_locked
+            ),
+            (10, 900, 0)
+        );
+        assert_eq!(
+            market
+                .book()
+                .get_best_buy_order(&clock_at(0, 1))
+                .unwrap()
+                .price,
+            100
+        );
+    }
+
+    #[test]
+    fn test_dutch_auction_listing() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 0);
+        let mut bidder = market.trader();
+        let mut seller = market.trader();
+        let dutch = |trader: &TestTrader, order_type, decay_start_price| Order {
+            decay_start_price,
+            decay_end: 100,
+            ..new_order(trader.wallet.key, 10, 100, order_type)
+        };
+
+        // Only asks decaying from above their floor are listed
+        let order = dutch(&bidder, OrderType::Buy, 200);
+        assert_eq!(
+            place_order(&mut market.clone(), &mut bidder.clone(), &mut clock, order),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        let order = dutch(&seller, OrderType::Sell, 100);
+        assert_eq!(
+            place_order(&mut market.clone(), &mut seller.clone(), &mut clock, order),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        let bid = new_order(bidder.wallet.key, 10, 120, OrderType::Buy);
+        place_order(&mut market, &mut bidder, &mut clock, bid).unwrap();
+        let ask = dutch(&seller, OrderType::Sell, 200);
+        place_order(&mut market, &mut seller, &mut clock, ask).unwrap();
+
+        // The ask decays linearly, never through the best bid
+        let book = market.book();
+        let prices: Vec<u64> = [0, 50, 75, 90, 200]
+            .into_iter()
+            .map(|now| book.get_best_sell_order(&clock_at(0, now)).unwrap().price)
+            .collect();
+        assert_eq!(prices, vec![200, 150, 125, 121, 121]);
+
+        // Takers lift it at its current price until it is exhausted
+        let mut taker = market.trader();
+        let mut clock = TestAccount::clock(2, 50);
+        let buy = new_order(taker.wallet.key, 4, 150, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut seller),
+            buy,
+        )
+        .unwrap();
+        assert_eq!(seller.balances(&market).quote_free, 600);
+        let mut clock = TestAccount::clock(3, 200);
+        let buy = new_order(taker.wallet.key, 6, 121, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut seller),
+            buy,
+        )
+        .unwrap();
+        assert!(market.book().sell_orders.is_empty());
+        let balances = seller.balances(&market);
+        assert_eq!((balances.quote_free, balances.base_locked), (1_326, 0));
+        assert_eq!(taker.balances(&market).base_free, 10);
+    }
+
+    #[test]
+    fn test_one_cancels_other_orders() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        let mut other = market.trader();
+        let bid = new_order(trader.wallet.key, 10, 90, OrderType::Buy);
+        place_order(&mut market, &mut trader, &mut clock, bid).unwrap();
+
+        // Only an unlinked resting order of the same trader can be linked
+        let linked_ask = |trader: &TestTrader, linked_order_id| Order {
+            linked_order_id: Some(linked_order_id),
+            ..new_order(trader.wallet.key, 10, 110, OrderType::Sell)
+        };
+        let order = linked_ask(&other, 0);
+        assert_eq!(
+            place_order(&mut market.clone(), &mut other, &mut clock, order),
+            Err(ProgramError::InvalidArgument)
+        );
+        let order = linked_ask(&trader, 0);
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        assert_eq!(
+            market.book().find_order(0).unwrap().linked_order_id,
+            Some(1)
+        );
+        let order = linked_ask(&trader, 0);
+        assert_eq!(
+            place_order(&mut market.clone(), &mut trader.clone(), &mut clock, order),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        // A fill on the ask cancels the bid and releases its funds
+        let lamports = trader.open_orders.lamports;
+        let buy = new_order(other.wallet.key, 5, 110, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut other,
+            &mut clock,
+            std::slice::from_mut(&mut trader),
+            buy,
+        )
+        .unwrap();
+        let book = market.book();
+        assert_eq!(book.find_order(0), None);
+        assert_eq!(book.find_order(1).unwrap().linked_order_id, None);
+        let balances = trader.balances(&market);
+        assert_eq!((balances.quote_free, balances.quote_locked), (900 + 550, 0));
+        assert_eq!(balances.order_count, 1);
+        assert_eq!(
+            trader.open_orders.lamports,
+            lamports + OrderBook::ORDER_DEPOSIT_LAMPORTS
+        );
+
+        // Canceling one order of a pair leaves the other unlinked
+        let order = Order {
+            linked_order_id: Some(1),
+            ..new_order(trader.wallet.key, 10, 80, OrderType::Buy)
+        };
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                trader.wallet.info(),
+                trader.open_orders.info(),
+            ],
+            &OrderBookInstruction::CancelOrder { order_id: 1 }.pack(),
+        )
+        .unwrap();
+        assert_eq!(market.book().find_order(3).unwrap().linked_order_id, None);
+    }
+
+    #[test]
+    fn test_trailing_stop_orders() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut holder = market.trader();
+        // A taker fills `amount` of a maker order for 10 at `price`
+        let trade =
+            |market: &mut TestMarket, clock: &mut TestAccount, price, maker_side, amount| {
+                let mut maker = market.trader();
+                let mut taker = market.trader();
+                let order = new_order(maker.wallet.key, 10, price, maker_side);
+                place_order(market, &mut maker, clock, order).unwrap();
+                let order = new_order(
+                    taker.wallet.key,
+                    amount,
+                    price,
+                    OrderType::opposite(maker_side),
+                );
+                place_order_with_makers(
+                    market,
+                    &mut taker,
+                    clock,
+                    std::slice::from_mut(&mut maker),
+                    order,
+                )
+                .unwrap();
+                maker
+            };
+
+        // A sell stop trails the last trade price by its ticks
+        let stop = Order {
+            trail_ticks: 5,
+            bounty: 1_000,
+            ..new_order(holder.wallet.key, 10, 90, OrderType::Sell)
+        };
+        assert_eq!(
+            place_order(&mut market.clone(), &mut holder.clone(), &mut clock, stop),
+            Err(ProgramError::InvalidArgument)
+        );
+        trade(&mut market, &mut clock, 100, OrderType::Sell, 10);
+        place_order(&mut market, &mut holder, &mut clock, stop).unwrap();
+        let stop_id = market.book().stop_orders[0].order_id;
+        assert_eq!(market.book().stop_orders[0].trigger_price, 95);
+        assert_eq!(holder.balances(&market).base_locked, 10);
+
+        // The trigger ratchets up with the price and never back down
+        trade(&mut market, &mut clock, 110, OrderType::Sell, 10);
+        assert_eq!(market.book().stop_orders[0].trigger_price, 105);
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        let trigger = OrderBookInstruction::TriggerStopOrder { order_id: stop_id }.pack();
+        assert_eq!(
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    keeper.info(),
+                    clock.info(),
+                    holder.open_orders.info(),
+                    market.config.info(),
+                ],
+                &trigger,
+            ),
+            Err(OrderBookError::StopNotTriggered.into())
+        );
+
+        // A trade at or below the trigger lets any keeper fire the stop as a
+        // limit order for its bounty
+        let mut bidder = trade(&mut market, &mut clock, 104, OrderType::Buy, 5);
+        assert_eq!(market.book().stop_orders[0].trigger_price, 105);
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                keeper.info(),
+                clock.info(),
+                holder.open_orders.info(),
+                market.config.info(),
+                bidder.open_orders.info(),
+            ],
+            &trigger,
+        )
+        .unwrap();
+        assert_eq!(keeper.lamports, 1_000_000 + 1_000);
+        let book = market.book();
+        assert!(book.stop_orders.is_empty());
+        assert_eq!(book.find_order(stop_id).unwrap().price, 90);
+        let balances = holder.balances(&market);
+        assert_eq!((balances.base_locked, balances.quote_free), (5, 520));
+        assert_eq!(bidder.balances(&market).base_free, 10);
+    }
+
+    #[test]
+    fn test_buy_stop_fee_reserve() {
+        let mut market = TestMarket::new();
+        let mut book = market.book();
+        book.buy_taker_fee_bps = 100;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let reserve = book.fee_reserve(1_200);
+        let mut clock = TestAccount::clock(1, 1);
+        let mut holder = market.trader();
+        let trade = |market: &mut TestMarket, clock: &mut TestAccount, price| {
+            let mut maker = market.trader();
+            let mut taker = market.trader();
+            let order = new_order(maker.wallet.key, 1, price, OrderType::Sell);
+            place_order(market, &mut maker, clock, order).unwrap();
+            let order = new_order(taker.wallet.key, 1, price, OrderType::Buy);
+            place_order_with_makers(
+                market,
+                &mut taker,
+                clock,
+                std::slice::from_mut(&mut maker),
+                order,
+            )
+            .unwrap();
+        };
+        trade(&mut market, &mut clock, 100);
+
+        // A waiting buy stop keeps its fee reserve locked, whether it is
+        // canceled or triggered
+        let stop = Order {
+            trail_ticks: 5,
+            bounty: 1_000,
+            ..new_order(holder.wallet.key, 10, 120, OrderType::Buy)
+        };
+        place_order(&mut market, &mut holder, &mut clock, stop).unwrap();
+        let balances = holder.balances(&market);
+        assert_eq!(
+            (balances.quote_locked, balances.quote_free),
+            (1_200 + reserve, 0)
+        );
+        let order_id = market.book().stop_orders[0].order_id;
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                holder.wallet.info(),
+                holder.open_orders.info(),
+            ],
+            &OrderBookInstruction::CancelOrder { order_id }.pack(),
+        )
+        .unwrap();
+        let balances = holder.balances(&market);
+        assert_eq!(
+            (balances.quote_locked, balances.quote_free),
+            (0, 1_200 + reserve)
+        );
+
+        place_order(&mut market, &mut holder, &mut clock, stop).unwrap();
+        let stop_id = market.book().stop_orders[0].order_id;
+        let mut seller = market.trader();
+        let order = new_order(seller.wallet.key, 10, 110, OrderType::Sell);
+        place_order(&mut market, &mut seller, &mut clock, order).unwrap();
+        trade(&mut market, &mut clock, 105);
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                keeper.info(),
+                clock.info(),
+                holder.open_orders.info(),
+                market.config.info(),
+                seller.open_orders.info(),
+            ],
+            &OrderBookInstruction::TriggerStopOrder { order_id: stop_id }.pack(),
+        )
+        .unwrap();
+        // The stop, funded from the canceled one's free balance, pays 1,100
+        // and an 11 fee and gets the rest of its lock and reserve back
+        let balances = holder.balances(&market);
+        assert_eq!(
+            (
+                balances.base_free,
+                balances.quote_locked,
+                balances.quote_free
+            ),
+            (10, 0, 1_200 + reserve - 1_100 - 11)
+        );
+    }
+
+    #[test]
+    fn test_price_improvement_auction() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut resting = market.trader();
+        let mut improver = market.trader();
+        let mut taker = market.trader();
+        let order = new_order(resting.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut resting, &mut clock, order).unwrap();
+
+        // A marketable order with a window waits off the book instead of
+        // matching, while a non-marketable one rests right away
+        let order = Order {
+            improvement_slots: 2,
+            bounty: 1_000,
+            ..new_order(taker.wallet.key, 10, 100, OrderType::Buy)
+        };
+        place_order(&mut market, &mut taker, &mut clock, order).unwrap();
+        let order_id = market.book().pending_orders[0].order_id;
+        assert_eq!(taker.balances(&market).base_free, 0);
+        let order = Order {
+            improvement_slots: 2,
+            ..new_order(improver.wallet.key, 10, 99, OrderType::Sell)
+        };
+        place_order(&mut market, &mut improver, &mut clock, order).unwrap();
+        assert_eq!(market.book().sell_orders.len(), 2);
+
+        // The crank waits for the window to end and then fills the order
+        // against the improved quote
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        let settle = OrderBookInstruction::SettleAuction { order_id }.pack();
+        let mut settle_at = |market: &mut TestMarket, slot, improver: &mut TestTrader| {
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    keeper.info(),
+                    TestAccount::clock(slot, 1).info(),
+                    taker.open_orders.info(),
+                    market.config.info(),
+                    improver.open_orders.info(),
+                ],
+                &settle,
+            )
+        };
+        assert_eq!(
+            settle_at(&mut market, 2, &mut improver),
+            Err(OrderBookError::AuctionNotEnded.into())
+        );
+        settle_at(&mut market, 3, &mut improver).unwrap();
+        assert_eq!(keeper.lamports, 1_000_000 + 1_000);
+        let book = market.book();
+        assert!(book.pending_orders.is_empty());
+        assert_eq!(book.sell_orders.len(), 1);
+        let balances = taker.balances(&market);
+        assert_eq!((balances.base_free, balances.quote_free), (10, 10));
+    }
+
+    #[test]
+    fn test_pending_bid_fee_reserve() {
+        let mut market = TestMarket::new();
+        let mut book = market.book();
+        book.buy_taker_fee_bps = 100;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let reserve = book.fee_reserve(1_000);
+        let mut clock = TestAccount::clock(1, 1);
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        let order = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        let bid = Order {
+            improvement_slots: 2,
+            ..new_order(taker.wallet.key, 10, 100, OrderType::Buy)
+        };
+
+        // A canceled pending bid gets its lock and fee reserve back
+        place_order(&mut market, &mut taker, &mut clock, bid).unwrap();
+        let balances = taker.balances(&market);
+        assert_eq!(balances.quote_locked, 1_000 + reserve);
+        let order_id = market.book().pending_orders[0].order_id;
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                taker.wallet.info(),
+                taker.open_orders.info(),
+            ],
+            &OrderBookInstruction::CancelOrder { order_id }.pack(),
+        )
+        .unwrap();
+        let balances = taker.balances(&market);
+        assert_eq!(
+            (balances.quote_locked, balances.quote_free),
+            (0, 1_000 + reserve)
+        );
+
+        // A settled one pays the fee out of its reserve
+        place_order(&mut market, &mut taker, &mut clock, bid).unwrap();
+        let order_id = market.book().pending_orders[0].order_id;
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                keeper.info(),
+                TestAccount::clock(3, 1).info(),
+                taker.open_orders.info(),
+                market.config.info(),
+                maker.open_orders.info(),
+            ],
+            &OrderBookInstruction::SettleAuction { order_id }.pack(),
+        )
+        .unwrap();
+        let balances = taker.balances(&market);
+        assert_eq!(
+            (
+                balances.base_free,
+                balances.quote_locked,
+                balances.quote_free
+            ),
+            (10, 0, reserve - 10)
+        );
+    }
+
+    #[test]
+    fn test_priority_auction() {
+        let mut market = TestMarket::new();
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.buy_taker_fee_bps = 100;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let reserve = book.fee_reserve(1_000);
+        let mut clock = TestAccount::clock(1, 1);
+        let mut maker = market.trader();
+        let mut early = market.trader();
+        let mut bidder = market.trader();
+        let order = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        let bid_order = Order {
+            priority_bid: 5_000,
+            ..new_order(bidder.wallet.key, 10, 100, OrderType::Buy)
+        };
+        assert_eq!(
+            place_order(&mut market, &mut bidder, &mut clock, bid_order),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        process_instruction(
+            &id(),
+            &[market.order_book.info(), creator.info()],
+            &OrderBookInstruction::SetPriorityAuction { enabled: true }.pack(),
+        )
+        .unwrap();
+
+        // Both takers of the slot wait, the later one paying its bid
+        let order = new_order(early.wallet.key, 10, 100, OrderType::Buy);
+        place_order(&mut market, &mut early, &mut clock, order).unwrap();
+        place_order(&mut market, &mut bidder, &mut clock, bid_order).unwrap();
+        let book = market.book();
+        let ids: Vec<u64> = book.pending_orders.iter().map(|o| o.order_id).collect();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(book.crank_incentive_lamports, 5_000);
+        assert_eq!(
+            book.next_priority_taker(&Clock::default()),
+            None,
+            "takers wait until their slot has passed"
+        );
+
+        // The higher bid is cleared first and takes the liquidity
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        let mut settle = |market: &mut TestMarket, taker: &mut TestTrader, order_id| {
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    keeper.info(),
+                    TestAccount::clock(2, 1).info(),
+                    taker.open_orders.info(),
+                    market.config.info(),
+                    maker.open_orders.info(),
+                ],
+                &OrderBookInstruction::SettleAuction { order_id }.pack(),
+            )
+        };
+        assert_eq!(
+            settle(&mut market, &mut early, ids[0]),
+            Err(OrderBookError::PriorityTakerWaiting.into())
+        );
+        settle(&mut market, &mut bidder, ids[1]).unwrap();
+        settle(&mut market, &mut early, ids[0]).unwrap();
+        // Both fee reserves stay locked while the takers wait and are
+        // released once they match, less the fee the filled bid paid
+        let balances = bidder.balances(&market);
+        assert_eq!(
+            (
+                balances.base_free,
+                balances.quote_locked,
+                balances.quote_free
+            ),
+            (10, 0, reserve - 10)
+        );
+        let balances = early.balances(&market);
+        assert_eq!(
+            (balances.quote_locked, balances.quote_free),
+            (1_000, reserve)
+        );
+        assert_eq!(market.book().buy_orders.len(), 1);
+    }
+
+    #[test]
+    fn test_reference_orders() {
+        let mut market = TestMarket::new();
+        let mut book = market.book();
+        book.buy_taker_fee_bps = 100;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let reserve = book.fee_reserve(1_100);
+        let mut clock = TestAccount::clock(1, 500);
+        let mut buyer = market.trader();
+        let mut seller = market.trader();
+        let mut outbid = market.trader();
+        let orders = [
+            (&mut buyer, 10, 110, OrderType::Buy),
+            (&mut seller, 6, 90, OrderType::Sell),
+            (&mut outbid, 10, 105, OrderType::Sell),
+        ];
+        for (trader, amount, price, side) in orders {
+            let order = Order {
+                settle_at: 1_000,
+                ..new_order(trader.wallet.key, amount, price, side)
+            };
+            place_order(&mut market, trader, &mut clock, order).unwrap();
+        }
+        assert_eq!(market.book().reference_orders.len(), 3);
+        assert!(market.book().sell_orders.is_empty());
+
+        // The orders wait for the PriceOracle to fix the reference price
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        let mut settle = |market: &mut TestMarket, updated_at, traders: [&mut TestTrader; 3]| {
+            let price_oracle = PriceOracle {
+                version: PriceOracle::VERSION,
+                market: market.order_book.key,
+                twap: 100,
+                updated_at,
+                ..PriceOracle::default()
+            };
+            let mut price_oracle = TestAccount::new(
+                PriceOracle::find_address(&id(), &market.order_book.key).0,
+                id(),
+                price_oracle.pack(),
+            );
+            let mut accounts = vec![
+                market.order_book.info(),
+                keeper.info(),
+                price_oracle.info(),
+                market.config.info(),
+            ];
+            accounts.extend(traders.map(|trader| trader.open_orders.info()));
+            let settle_at = 1_000;
+            process_instruction(
+                &id(),
+                &accounts,
+                &OrderBookInstruction::SettleReferenceOrders { settle_at }.pack(),
+            )
+        };
+        assert_eq!(
+            settle(&mut market, 900, [&mut buyer, &mut seller, &mut outbid]),
+            Err(OrderBookError::ReferencePriceNotFixed.into())
+        );
+
+        // Orders whose limits allow the reference price cross at it without
+        // fees, releasing a bid's fee reserve, and the rest are canceled
+        assert_eq!(buyer.balances(&market).quote_locked, 1_100 + reserve);
+        settle(&mut market, 1_100, [&mut buyer, &mut seller, &mut outbid]).unwrap();
+        assert!(market.book().reference_orders.is_empty());
+        let balances = buyer.balances(&market);
+        assert_eq!(
+            (
+                balances.base_free,
+                balances.quote_free,
+                balances.quote_locked
+            ),
+            (6, 1_100 + reserve - 600, 0)
+        );
+        assert_eq!(seller.balances(&market).quote_free, 600);
+        assert_eq!(outbid.balances(&market).base_free, 10);
+
+        // The bid filled 6 of its 10 and the rest was canceled, while the
+        // ask it crossed filled in full
+        let book = market.book();
+        let closed = book.find_closed_order(0).unwrap();
+        assert_eq!(
+            (closed.status, closed.filled_amount),
+            (OrderStatus::Canceled, 6)
+        );
+        let closed = book.find_closed_order(1).unwrap();
+        assert_eq!(
+            (closed.status, closed.filled_amount),
+            (OrderStatus::Filled, 6)
+        );
+        assert_eq!(book.order_status(2).status, OrderStatus::Canceled);
+    }
+
+    #[test]
+    fn test_avg_price_limit() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut makers = [market.trader(), market.trader()];
+        for (maker, price) in makers.iter_mut().zip([100, 110]) {
+            let order = new_order(maker.wallet.key, 10, price, OrderType::Sell);
+            place_order(&mut market, maker, &mut clock, order).unwrap();
+        }
+
+        // The bid takes the best level in full but only as much of the next
+        // as keeps its average price within the limit, and the rest is
+        // canceled even though the limit price still crosses
+        let mut taker = market.trader();
+        let order = Order {
+            avg_price_limit: 104,
+            ..new_order(taker.wallet.key, 20, 110, OrderType::Buy)
+        };
+        place_order_with_makers(&mut market, &mut taker, &mut clock, &mut makers, order).unwrap();
+        let balances = taker.balances(&market);
+        assert_eq!(
+            (
+                balances.base_free,
+                balances.quote_free,
+                balances.quote_locked
+            ),
+            (16, 2_200 - 1_000 - 660, 0)
+        );
+        assert_eq!(balances.order_count, 0);
+        let book = market.book();
+        assert!(book.buy_orders.is_empty());
+        assert_eq!(book.sell_orders[0].amount, 4);
+    }
+
+    #[test]
+    fn test_min_fill_amount() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut maker = market.trader();
+        let order = new_order(maker.wallet.key, 5, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        let mut taker = market.trader();
+        let trader = taker.wallet.key;
+        let with_min = |price, min_fill_amount| Order {
+            min_fill_amount,
+            ..new_order(trader, 10, price, OrderType::Buy)
+        };
+        assert_eq!(
+            place_order(&mut market, &mut taker, &mut clock, with_min(100, 11)),
+            Err(ProgramError::InvalidInstructionData)
+        );
+
+        // Too little liquidity cancels the whole order without filling
+        place_order(&mut market, &mut taker, &mut clock, with_min(100, 8)).unwrap();
+        let balances = taker.balances(&market);
+        assert_eq!(
+            (
+                balances.base_free,
+                balances.quote_free,
+                balances.order_count
+            ),
+            (0, 1_000, 0)
+        );
+        assert_eq!(market.book().sell_orders[0].amount, 5);
+
+        // An order that would fill nothing rests as usual
+        place_order(&mut market, &mut taker, &mut clock, with_min(90, 8)).unwrap();
+        assert_eq!(market.book().buy_orders[0].amount, 10);
+    }
+
+    #[test]
+    fn test_quote_sized_orders() {
+        let mut market = TestMarket::new();
+        let mut book = market.book();
+        book.lot_size = 10;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut makers = [market.trader(), market.trader()];
+        for (maker, (amount, price)) in makers.iter_mut().zip([(20, 100), (30, 120)]) {
+            let order = new_order(maker.wallet.key, amount, price, OrderType::Sell);
+            place_order(&mut market, maker, &mut clock, order).unwrap();
+        }
+        let mut taker = market.trader();
+        let trader = taker.wallet.key;
+        let spend = |quote_budget, price, order_type| Order {
+            quote_budget,
+            ..new_order(trader, 0, price, order_type)
+        };
+        let order = spend(500, 150, OrderType::Sell);
+        assert_eq!(
+            place_order(&mut market.clone(), &mut taker.clone(), &mut clock, order),
+            Err(ProgramError::InvalidInstructionData)
+        );
+
+        // The budget buys whole lots at each maker's price and releases what
+        // no further lot can use
+        let order = spend(500, 150, OrderType::Buy);
+        place_order_with_makers(&mut market, &mut taker, &mut clock, &mut makers, order).unwrap();
+        let balances = taker.balances(&market);
+        assert_eq!(
+            (
+                balances.base_free,
+                balances.quote_free,
+                balances.quote_locked
+            ),
+            (40, 60, 0)
+        );
+        assert_eq!(taker.quote_token.token_amount(), TestTrader::FUNDS - 500);
+        assert!(market.book().buy_orders.is_empty());
+        assert_eq!(market.book().sell_orders[0].amount, 10);
+
+        // The rest of the budget rests as the lots it buys at the limit price
+        let order = spend(1_000, 150, OrderType::Buy);
+        place_order_with_makers(&mut market, &mut taker, &mut clock, &mut makers, order).unwrap();
+        let bid = market.book().buy_orders[0];
+        assert_eq!((bid.amount, bid.price, bid.quote_budget), (50, 150, 0));
+        let balances = taker.balances(&market);
+        assert_eq!(
+            (
+                balances.base_free,
+                balances.quote_free,
+                balances.quote_locked
+            ),
+            (50, 130, 750)
+        );
+    }
+
+    #[test]
+    fn test_beneficial_owner_self_trade_prevention() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut firm = TestAccount::signer(Pubkey::new_unique());
+        let mut strategies = [market.trader(), market.trader()];
+        for strategy in strategies.iter_mut() {
+            let set_owner = OrderBookInstruction::SetBeneficialOwner {
+                beneficial_owner: firm.key,
+            }
+            .pack();
+            let mut unsigned = firm.clone();
+            unsigned.is_signer = false;
+            assert_eq!(
+                process_instruction(
+                    &id(),
+                    &[
+                        strategy.open_orders.info(),
+                        strategy.wallet.info(),
+                        unsigned.info(),
+                    ],
+                    &set_owner,
+                ),
+                Err(OrderBookError::MissingRequiredSigner.into())
+            );
+            process_instruction(
+                &id(),
+                &[
+                    strategy.open_orders.info(),
+                    strategy.wallet.info(),
+                    firm.info(),
+                ],
+                &set_owner,
+            )
+            .unwrap();
+        }
+
+        // Orders of the same beneficial owner do not match, and the taker's
+        // crossing remainder is canceled
+        let [ref mut maker, ref mut taker] = strategies;
+        let order = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, maker, &mut clock, order).unwrap();
+        assert_eq!(market.book().sell_orders[0].beneficial_owner, firm.key);
+        let order = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        place_order(&mut market, taker, &mut clock, order).unwrap();
+        let balances = taker.balances(&market);
+        assert_eq!((balances.base_free, balances.quote_free), (0, 1_000));
+        let book = market.book();
+        assert!(book.buy_orders.is_empty());
+        assert_eq!(book.sell_orders[0].amount, 10);
+
+        // Other traders still fill against the group
+        let mut outsider = market.trader();
+        let order = new_order(outsider.wallet.key, 10, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut outsider,
+            &mut clock,
+            std::slice::from_mut(maker),
+            order,
+        )
+        .unwrap();
+        assert_eq!(outsider.balances(&market).base_free, 10);
+    }
+
+    #[test]
+    fn test_oracle_sources() {
+        let expected = OraclePrice {
+            price: 12_345,
+            expo: -2,
+            conf: 7,
+            publish_time: 1_000,
+        };
+        let mut pyth = vec![0; PYTH_PRICE_LEN];
+        let fields: [(usize, &[u8]); 7] = [
+            (0, &PYTH_MAGIC.to_le_bytes()),
+            (
+                PYTH_ACCOUNT_TYPE_OFFSET,
+                &PYTH_PRICE_ACCOUNT_TYPE.to_le_bytes(),
+            ),
+            (PYTH_EXPO_OFFSET, &(-2i32).to_le_bytes()),
+            (PYTH_TIMESTAMP_OFFSET, &1_000i64.to_le_bytes()),
+            (PYTH_AGG_PRICE_OFFSET, &12_345i64.to_le_bytes()),
+            (PYTH_AGG_CONF_OFFSET, &7u64.to_le_bytes()),
+            (PYTH_AGG_STATUS_OFFSET, &PYTH_STATUS_TRADING.to_le_bytes()),
+        ];
+        for (offset, bytes) in fields {
+            pyth[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        assert_eq!(OracleSource::Pyth.read_price(&pyth), Ok(expected));
+        // A price that is not trading is rejected
+        pyth[PYTH_AGG_STATUS_OFFSET] = 0;
+        assert_eq!(
+            OracleSource::Pyth.read_price(&pyth),
+            Err(OrderBookError::InvalidOraclePrice.into())
+        );
+
+        // Switchboard's deviation is rescaled to the result's scale
+        let mut switchboard = vec![0; SWITCHBOARD_AGGREGATOR_LEN];
+        let fields: [(usize, &[u8]); 5] = [
+            (
+                SWITCHBOARD_ROUND_OPEN_TIMESTAMP_OFFSET,
+                &1_000i64.to_le_bytes(),
+            ),
+            (SWITCHBOARD_RESULT_OFFSET, &12_345i128.to_le_bytes()),
+            (SWITCHBOARD_RESULT_OFFSET + 16, &2u32.to_le_bytes()),
+            (SWITCHBOARD_STD_DEVIATION_OFFSET, &7_000i128.to_le_bytes()),
+            (SWITCHBOARD_STD_DEVIATION_OFFSET + 16, &5u32.to_le_bytes()),
+        ];
+        for (offset, bytes) in fields {
+            switchboard[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        assert_eq!(
+            OracleSource::Switchboard.read_price(&switchboard),
+            Ok(expected)
+        );
+
+        let custom = CustomOraclePrice {
+            price: 12_345,
+            expo: -2,
+            conf: 7,
+            publish_time: 1_000,
+        }
+        .pack();
+        assert_eq!(OracleSource::Custom.read_price(&custom), Ok(expected));
+        assert_eq!(
+            OracleSource::Custom.read_price(&pyth),
+            Err(OrderBookError::InvalidOraclePrice.into())
+        );
+
+        // Only the creator changes the oracle, and the book only reads the
+        // configured account
+        let mut market = TestMarket::new();
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut oracle = TestAccount::new(Pubkey::new_unique(), Pubkey::new_unique(), custom);
+        let set_oracle = |market: &mut TestMarket, signer: &mut TestAccount, oracle| {
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), signer.info()],
+                &OrderBookInstruction::SetOracle {
+                    oracle_source: OracleSource::Custom,
+                    oracle,
+                }
+                .pack(),
+            )
+        };
+        let mut intruder = TestAccount::signer(Pubkey::new_unique());
+        assert_eq!(
+            set_oracle(&mut market, &mut intruder, oracle.key),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            set_oracle(&mut market, &mut creator, Pubkey::default()),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        set_oracle(&mut market, &mut creator, oracle.key).unwrap();
+        let book = market.book();
+        assert_eq!(book.read_oracle_price(&oracle.info()), Ok(expected));
+        let mut other = TestAccount::new(Pubkey::new_unique(), oracle.owner, oracle.data.clone());
+        assert_eq!(
+            book.read_oracle_price(&other.info()),
+            Err(OrderBookError::InvalidOraclePrice.into())
+        );
+
+        // A confidence of 7 on a price of 12,345 is about 5.7 bps wide, so
+        // a limit of 5 bps refuses the price and one of 6 bps accepts it
+        let set_confidence = |market: &mut TestMarket, signer: &mut TestAccount, max_conf_bps| {
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), signer.info()],
+                &OrderBookInstruction::SetOracleConfidence { max_conf_bps }.pack(),
+            )
+        };
+        assert_eq!(
+            set_confidence(&mut market, &mut intruder, 5),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            set_confidence(&mut market, &mut creator, 10_001),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        set_confidence(&mut market, &mut creator, 5).unwrap();
+        assert_eq!(market.book().max_oracle_conf_bps, 5);
+        assert_eq!(
+            market.book().read_oracle_price(&oracle.info()),
+            Err(OrderBookError::OracleConfidenceTooWide.into())
+        );
+        set_confidence(&mut market, &mut creator, 6).unwrap();
+        assert_eq!(
+            market.book().read_oracle_price(&oracle.info()),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn test_price_oracle() {
+        install_syscall_stubs();
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut payer = TestAccount::signer(Pubkey::new_unique());
+        payer.lamports = Rent::default().minimum_balance(PriceOracle::LEN);
+        let mut price_oracle = TestAccount::new(
+            PriceOracle::find_address(&id(), &market.order_book.key).0,
+            system_program::id(),
+            vec![0; PriceOracle::LEN],
+        );
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        process_instruction(
+            &id(),
+            &[
+                price_oracle.info(),
+                market.order_book.info(),
+                payer.info(),
+                system_program.info(),
+                rent.info(),
+            ],
+            &OrderBookInstruction::InitPriceOracle.pack(),
+        )
+        .unwrap();
+        let update = |market: &mut TestMarket, price_oracle: &mut TestAccount, now| {
+            process_instruction(
+                &id(),
+                &[
+                    price_oracle.info(),
+                    market.order_book.info(),
+                    TestAccount::clock(now as Slot, now).info(),
+                ],
+                &OrderBookInstruction::UpdatePriceOracle.pack(),
+            )
+            .unwrap();
+            PriceOracle::unpack(&price_oracle.data).unwrap()
+        };
+
+        // A trade at 100 with quotes left on both sides
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        let order = new_order(maker.wallet.key, 20, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        let order = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            order,
+        )
+        .unwrap();
+        let order = new_order(taker.wallet.key, 10, 90, OrderType::Buy);
+        place_order(&mut market, &mut taker, &mut clock, order).unwrap();
+        assert_eq!(
+            update(&mut market, &mut price_oracle, 1),
+            PriceOracle {
+                version: PriceOracle::VERSION,
+                market: market.order_book.key,
+                last_trade_price: 100,
+                twap: 100,
+                best_bid: 90,
+                best_ask: 100,
+                updated_slot: 1,
+                updated_at: 1,
+            }
+        );
+
+        // Half a window at a new price moves the average halfway
+        let mut book = market.book();
+        book.last_trade_price = 110;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let updated = update(&mut market, &mut price_oracle, 1_801);
+        assert_eq!((updated.last_trade_price, updated.twap), (110, 105));
+    }
+
+    #[test]
+    fn test_book_change_events() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut maker = market.trader();
+        for price in [100, 110] {
+            let order = new_order(maker.wallet.key, 10, price, OrderType::Sell);
+            place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        }
+        let before = market.book();
+        assert_eq!(before.seq_num, 2);
+
+        // A partial fill of one level and a new bid resting behind it
+        let mut taker = market.trader();
+        let order = new_order(taker.wallet.key, 4, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            order,
+        )
+        .unwrap();
+        let order = new_order(taker.wallet.key, 5, 90, OrderType::Buy);
+        place_order(&mut market, &mut taker, &mut clock, order).unwrap();
+        let after = market.book();
+        assert_eq!(after.seq_num, 4);
+        let clock = Clock {
+            slot: 7,
+            unix_timestamp: 1,
+            ..Clock::default()
+        };
+        let event = BookChangeEvent::new(&market.order_book.key, &before, &after, &clock);
+        assert_eq!(
+            event,
+            BookChangeEvent {
+                market: market.order_book.key,
+                seq_num: 4,
+                checksum: event.checksum,
+                slot: 7,
+                added: vec![3],
+                removed: vec![],
+                levels: vec![(OrderType::Sell, 100, 6), (OrderType::Buy, 90, 5)],
+                best_bid: Some(90),
+                best_ask: Some(100),
+            }
+        );
+
+        // Cancels log the emptied level
+        let cancel = OrderBookInstruction::CancelOrder { order_id: 3 }.pack();
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                taker.wallet.info(),
+                taker.open_orders.info(),
+            ],
+            &cancel,
+        )
+        .unwrap();
+        let cancelled = market.book();
+        assert_eq!(cancelled.seq_num, 5);
+        let event = BookChangeEvent::new(&market.order_book.key, &after, &cancelled, &clock);
+        assert_eq!(
+            (event.removed, event.levels, event.best_bid),
+            (vec![3], vec![(OrderType::Buy, 90, 0)], None)
+        );
+    }
+
+    #[test]
+    fn test_trade_history() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1_000);
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        creator.lamports = Rent::default().minimum_balance(TradeHistory::space(2));
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut trade_history = TestAccount::new(
+            TradeHistory::find_address(&id(), &market.order_book.key).0,
+            system_program::id(),
+            vec![0; TradeHistory::space(2)],
+        );
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        process_instruction(
+            &id(),
+            &[
+                trade_history.info(),
+                market.order_book.info(),
+                creator.info(),
+                system_program.info(),
+                rent.info(),
+            ],
+            &OrderBookInstruction::InitTradeHistory { capacity: 2 }.pack(),
+        )
+        .unwrap();
+        assert_eq!(market.book().trade_history, trade_history.key);
+
+        // Matching requires the history account once the market keeps one
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        let trade = |market: &mut TestMarket,
+                     maker: &mut TestTrader,
+                     taker: &mut TestTrader,
+                     clock: &mut TestAccount,
+                     price| {
+            let order = new_order(maker.wallet.key, 10, price, OrderType::Sell);
+            place_order(market, maker, clock, order)?;
+            let order = new_order(taker.wallet.key, 10, price, OrderType::Buy);
+            place_order_with_makers(market, taker, clock, std::slice::from_mut(maker), order)
+        };
+        assert_eq!(
+            trade(&mut market, &mut maker, &mut taker, &mut clock, 100),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        market.trade_history = Some(trade_history);
+        for price in [100, 101, 102] {
+            trade(&mut market, &mut maker, &mut taker, &mut clock, price).unwrap();
+        }
+
+        // The ring buffer keeps the latest fills
+        let data = &market.trade_history.as_ref().unwrap().data;
+        let history = TradeHistory::unpack(data).unwrap();
+        assert_eq!(history.total, 3);
+        let record = |price| TradeRecord {
+            price,
+            amount: 10,
+            taker_side: OrderType::Buy,
+            unix_timestamp: 1_000,
+        };
+        assert_eq!(history.records(data), Ok(vec![record(101), record(102)]));
+    }
+
+    #[test]
+    fn test_candle_series() {
+        let mut market = TestMarket::new();
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        for interval in [60, 300] {
+            creator.lamports = Rent::default().minimum_balance(CandleSeries::space(4));
+            let mut series = TestAccount::new(
+                CandleSeries::find_address(&id(), &market.order_book.key, interval).0,
+                system_program::id(),
+                vec![0; CandleSeries::space(4)],
+            );
+            process_instruction(
+                &id(),
+                &[
+                    series.info(),
+                    market.order_book.info(),
+                    creator.info(),
+                    system_program.info(),
+                    rent.info(),
+                ],
+                &OrderBookInstruction::InitCandleSeries {
+                    interval,
+                    capacity: 4,
+                }
+                .pack(),
+            )
+            .unwrap();
+            market.candle_series.push(series);
+        }
+        let keys: Vec<Pubkey> = market
+            .candle_series
+            .iter()
+            .map(|series| series.key)
+            .collect();
+        assert_eq!(market.book().candle_series, keys);
+
+        // Every series aggregates the same fills
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        for (unix_timestamp, price) in [(1_000, 100), (1_010, 102), (1_100, 101)] {
+            let mut clock = TestAccount::clock(1, unix_timestamp);
+            let order = new_order(maker.wallet.key, 10, price, OrderType::Sell);
+            place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+            let order = new_order(taker.wallet.key, 10, price, OrderType::Buy);
+            place_order_with_makers(
+                &mut market,
+                &mut taker,
+                &mut clock,
+                std::slice::from_mut(&mut maker),
+                order,
+            )
+            .unwrap();
+        }
+        let candle = |start_time, open, high, low, close, volume| Candle {
+            start_time,
+            open,
+            high,
+            low,
+            close,
+            volume,
+        };
+        let candles = |series: &TestAccount| {
+            CandleSeries::unpack(&series.data)
+                .unwrap()
+                .candles(&series.data)
+                .unwrap()
+        };
+        assert_eq!(
+            candles(&market.candle_series[0]),
+            vec![
+                candle(960, 100, 102, 100, 102, 20),
+                candle(1_080, 101, 101, 101, 101, 10),
+            ]
+        );
+        assert_eq!(
+            candles(&market.candle_series[1]),
+            vec![candle(900, 100, 102, 100, 101, 30)]
+        );
+
+        // Queries return the candles starting in the requested range
+        process_instruction(
+            &id(),
+            &[market.order_book.info(), market.candle_series[0].info()],
+            &OrderBookInstruction::GetCandles {
+                start_time: 1_000,
+                end_time: 2_000,
+            }
+            .pack(),
+        )
+        .unwrap();
+        assert_eq!(
+            CandlePage::unpack(&get_return_data().unwrap().1),
+            Ok(CandlePage {
+                next_start_time: None,
+                candles: vec![candle(1_080, 101, 101, 101, 101, 10)],
+            })
+        );
+        let mut stranger = TestAccount::new(Pubkey::new_unique(), id(), vec![0; 96]);
+        assert_eq!(
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), stranger.info()],
+                &OrderBookInstruction::GetCandles {
+                    start_time: 0,
+                    end_time: 2_000,
+                }
+                .pack(),
+            ),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_dark_pool_hides_resting_orders() {
+        let mut market = TestMarket::new();
+        let mut book = market.book();
+        book.dark_pool = true;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut maker = market.trader();
+        let order = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+
+        for instruction in [
+            OrderBookInstruction::GetBestSellOrder,
+            OrderBookInstruction::ExportBook { cursor: 0 },
+        ] {
+            assert_eq!(
+                process_instruction(
+                    &id(),
+                    &[market.order_book.info(), clock.info()],
+                    &instruction.pack(),
+                ),
+                Err(ProgramError::InvalidArgument)
+            );
+        }
+
+        // Hidden orders still match
+        let mut taker = market.trader();
+        let order = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            order,
+        )
+        .unwrap();
+        assert_eq!(taker.balances(&market).base_free, 10);
+    }
+
+    #[test]
+    fn test_rounding_policy() {
+        use math::{mul_div, Rounding, RoundingPolicy};
+        assert_eq!(mul_div(5, 1, 2, Rounding::Down), 2);
+        assert_eq!(mul_div(5, 1, 2, Rounding::Up), 3);
+        assert_eq!(mul_div(5, 1, 2, Rounding::NearestEven), 2);
+        assert_eq!(mul_div(7, 1, 2, Rounding::NearestEven), 4);
+        assert_eq!(mul_div(2, 1, 3, Rounding::NearestEven), 1);
+        assert_eq!(mul_div(6, 1, 3, Rounding::Up), 2);
+        assert_eq!(mul_div(u64::MAX, 2, 1, Rounding::Down), u64::MAX);
+
+        let mut market = TestMarket::new();
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.buy_taker_fee_bps = 50;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let set_policy = |market: &mut TestMarket, signer: &mut TestAccount, policy| {
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), signer.info()],
+                &OrderBookInstruction::SetRoundingPolicy { policy }.pack(),
+            )
+        };
+        let mut clock = TestAccount::clock(1, 1);
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        assert_eq!(
+            set_policy(&mut market, &mut taker.wallet, RoundingPolicy::FavorBook),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        // A fill worth 1,300 owes a fee of 6.5 quote atoms
+        let policies = [
+            (RoundingPolicy::Truncate, 6),
+            (RoundingPolicy::FavorBook, 7),
+            (RoundingPolicy::Symmetric, 6),
+        ];
+        for (policy, fee) in policies {
+            set_policy(&mut market, &mut creator, policy).unwrap();
+            assert_eq!(market.book().rounding_policy, policy);
+            let order = new_order(maker.wallet.key, 13, 100, OrderType::Sell);
+            place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+            let before = market.book().fees_collected;
+            let order = new_order(taker.wallet.key, 13, 100, OrderType::Buy);
+            place_order_with_makers(
+                &mut market,
+                &mut taker,
+                &mut clock,
+                std::slice::from_mut(&mut maker),
+                order,
+            )
+            .unwrap();
+            assert_eq!(market.book().fees_collected - before, fee);
+            assert_eq!(taker.balances(&market).quote_locked, 0);
+        }
+
+        // Waiting orders keep the policy they reserved fees under
+        let stop = Order {
+            trail_ticks: 1,
+            ..new_order(maker.wallet.key, 5, 90, OrderType::Sell)
+        };
+        place_order(&mut market, &mut maker, &mut clock, stop).unwrap();
+        assert_eq!(
+            set_policy(&mut market, &mut creator, RoundingPolicy::Truncate),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_route_order() {
+        // Two markets of the same pair, the second with a coarser tick
+        let mut market_a = TestMarket::new();
+        let mut market_b = market_a.sibling();
+        let mut book = market_b.book();
+        book.tick_size = 2;
+        book.pack_into_slice(&mut market_b.order_book.data).unwrap();
+
+        let mut clock = TestAccount::clock(1, 1);
+        let mut bob = market_a.trader();
+        let mut carol = market_b.trader();
+        let mut alice = market_a.trader();
+        for (price, amount) in [(101, 10), (104, 10)] {
+            let order = new_order(bob.wallet.key, amount, price, OrderType::Sell);
+            place_order(&mut market_a, &mut bob, &mut clock, order).unwrap();
+        }
+        let order = new_order(carol.wallet.key, 10, 102, OrderType::Sell);
+        place_order(&mut market_b, &mut carol, &mut clock, order).unwrap();
+
+        let route = |market_a: &mut TestMarket,
+                     market_b: &mut TestMarket,
+                     makers: &mut [TestTrader; 2],
+                     alice: &mut TestTrader,
+                     clock: &mut TestAccount,
+                     min_fill_amount| {
+            let mut system_program =
+                TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+            let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+            let [bob, carol] = makers;
+            set_runtime_clock(clock);
+            let accounts = vec![
+                alice.wallet.info(),
+                system_program.info(),
+                alice.open_orders.info(),
+                alice.quote_token.info(),
+                token_program.info(),
+                market_a.config.info(),
+                market_a.order_book.info(),
+                market_a.quote_vault.info(),
+                bob.open_orders.info(),
+                market_b.order_book.info(),
+                market_b.quote_vault.info(),
+                carol.open_orders.info(),
+            ];
+            let instruction = OrderBookInstruction::RouteOrder {
+                side: OrderType::Buy,
+                amount: 25,
+                limit_price: 103,
+                min_fill_amount,
+                market_account_counts: vec![3, 3],
+            };
+            process_instruction(&id(), &accounts, &instruction.pack())
+        };
+
+        // Only 20 fill at or below 103
+        let mut makers = [bob, carol];
+        assert_eq!(
+            route(
+                &mut market_a.clone(),
+                &mut market_b.clone(),
+                &mut makers.clone(),
+                &mut alice.clone(),
+                &mut clock,
+                21
+            ),
+            Err(OrderBookError::RouteUnderfilled.into())
+        );
+        route(
+            &mut market_a,
+            &mut market_b,
+            &mut makers,
+            &mut alice,
+            &mut clock,
+            20,
+        )
+        .unwrap();
+
+        // 101 on the first market, then 102 on the second, then nothing
+        // left at or below the limit; the remainder is canceled
+        let balances_a = alice.balances(&market_a);
+        let balances_b = alice.balances(&market_b);
+        assert_eq!((balances_a.base_free, balances_a.quote_locked), (10, 0));
+        assert_eq!((balances_b.base_free, balances_b.quote_locked), (10, 0));
+        assert_eq!(balances_a.order_count + balances_b.order_count, 0);
+        assert_eq!(
+            TestTrader::FUNDS - alice.quote_token.token_amount(),
+            1_010 + 1_020 + balances_a.quote_free + balances_b.quote_free
+        );
+        assert_eq!(market_a.book().last_trade_price, 101);
+        assert_eq!(market_b.book().last_trade_price, 102);
+        assert_eq!(market_a.book().sell_orders.len(), 1);
+        assert!(market_b.book().sell_orders.is_empty());
+        assert!(market_a.book().buy_orders.is_empty());
+        assert!(market_b.book().buy_orders.is_empty());
+        let [bob, carol] = &makers;
+        assert_eq!(bob.balances(&market_a).quote_free, 1_010);
+        assert_eq!(carol.balances(&market_b).quote_free, 1_020);
+    }
+
+    #[test]
+    fn test_route_order_revisits_market() {
+        // Asks alternate between the markets: 101 and 103 on the first,
+        // 102 on the second, each charging a placement fee
+        let mut market_a = TestMarket::new();
+        let mut market_b = market_a.sibling();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut bob = market_a.trader();
+        let mut carol = market_b.trader();
+        let mut alice = market_a.trader();
+        for price in [101, 103] {
+            let order = new_order(bob.wallet.key, 10, price, OrderType::Sell);
+            place_order(&mut market_a, &mut bob, &mut clock, order).unwrap();
+        }
+        let order = new_order(carol.wallet.key, 10, 102, OrderType::Sell);
+        place_order(&mut market_b, &mut carol, &mut clock, order).unwrap();
+        for market in [&mut market_a, &mut market_b] {
+            let mut book = market.book();
+            book.placement_fee_lamports = 2_000;
+            book.pack_into_slice(&mut market.order_book.data).unwrap();
+        }
+        let (before_a, before_b) = (market_a.book(), market_b.book());
+
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+        set_runtime_clock(&clock);
+        let accounts = vec![
+            alice.wallet.info(),
+            system_program.info(),
+            alice.open_orders.info(),
+            alice.quote_token.info(),
+            token_program.info(),
+            market_a.config.info(),
+            market_a.order_book.info(),
+            market_a.quote_vault.info(),
+            bob.open_orders.info(),
+            market_b.order_book.info(),
+            market_b.quote_vault.info(),
+            carol.open_orders.info(),
+        ];
+        let instruction = OrderBookInstruction::RouteOrder {
+            side: OrderType::Buy,
+            amount: 40,
+            limit_price: 103,
+            min_fill_amount: 0,
+            market_account_counts: vec![3, 3],
+        };
+        process_instruction(&id(), &accounts, &instruction.pack()).unwrap();
+
+        // The first market is filled twice under one order id and one fee
+        let (book_a, book_b) = (market_a.book(), market_b.book());
+        assert_eq!(alice.balances(&market_a).base_free, 20);
+        assert_eq!(alice.balances(&market_b).base_free, 10);
+        assert_eq!(alice.wallet.lamports, 1_000_000 - 4_000);
+        for (before, book) in [(&before_a, &book_a), (&before_b, &book_b)] {
+            assert_eq!(book.next_order_id, before.next_order_id + 1);
+            assert_eq!(
+                book.crank_incentive_lamports,
+                before.crank_incentive_lamports + 2_000
+            );
+        }
+
+        // Both markets keep one closed entry for the route, whose unfilled
+        // remainder is canceled
+        for (before, book, filled) in [(&before_a, &book_a, 20), (&before_b, &book_b, 10)] {
+            let order_id = before.next_order_id;
+            let closed: Vec<&ClosedOrder> = book
+                .closed_orders
+                .iter()
+                .filter(|closed| closed.order_id == order_id)
+                .collect();
+            assert_eq!(closed.len(), 1);
+            assert_eq!(closed[0].status, OrderStatus::Canceled);
+            assert_eq!(closed[0].filled_amount, filled);
+        }
+        assert!(book_a.sell_orders.is_empty());
+        assert!(book_b.sell_orders.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_liquidity() {
+        let mut old = TestMarket::new();
+        let mut new = old.sibling();
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        for market in [&mut old, &mut new] {
+            let mut book = market.book();
+            book.creator = creator.key;
+            book.pack_into_slice(&mut market.order_book.data).unwrap();
+        }
+        let mut clock = TestAccount::clock(1, 1);
+        let mut alice = old.trader();
+        let mut bob = old.trader();
+        for (amount, price, order_type) in [(10, 100, OrderType::Buy), (5, 120, OrderType::Sell)] {
+            let order = new_order(alice.wallet.key, amount, price, order_type);
+            place_order(&mut old, &mut alice, &mut clock, order).unwrap();
+        }
+        let order = new_order(bob.wallet.key, 2, 120, OrderType::Buy);
+        place_order_with_makers(
+            &mut old,
+            &mut bob,
+            &mut clock,
+            std::slice::from_mut(&mut alice),
+            order,
+        )
+        .unwrap();
+        let deposits_before = alice.open_orders.lamports;
+
+        let migrate = |old: &mut TestMarket,
+                       new: &mut TestMarket,
+                       signer: &mut TestAccount,
+                       owners: &mut [&mut TestTrader],
+                       max_orders| {
+            let mut vault_signer = TestAccount::new(
+                OrderBook::find_vault_signer(&id(), &old.order_book.key).0,
+                Pubkey::default(),
+                vec![],
+            );
+            let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+            let mut accounts = vec![
+                old.order_book.info(),
+                new.order_book.info(),
+                signer.info(),
+                old.base_vault.info(),
+                old.quote_vault.info(),
+                new.base_vault.info(),
+                new.quote_vault.info(),
+                vault_signer.info(),
+                token_program.info(),
+            ];
+            accounts.extend(owners.iter_mut().map(|owner| owner.open_orders.info()));
+            process_instruction(
+                &id(),
+                &accounts,
+                &OrderBookInstruction::MigrateLiquidity { max_orders }.pack(),
+            )
+        };
+        assert_eq!(
+            migrate(&mut old, &mut new, &mut bob.wallet.clone(), &mut [], 8),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        // The first call deprecates the market and cancels one order,
+        // leaving the balances until the book is empty
+        migrate(&mut old, &mut new, &mut creator, &mut [&mut alice], 1).unwrap();
+        assert_eq!(old.book().successor, new.order_book.key);
+        assert_eq!(
+            old.book().buy_orders.len() + old.book().sell_orders.len(),
+            1
+        );
+        assert_eq!(alice.balances(&new), OpenOrdersMarket::default());
+        let order = new_order(bob.wallet.key, 1, 90, OrderType::Buy);
+        assert_eq!(
+            place_order(&mut old, &mut bob, &mut clock, order),
+            Err(OrderBookError::MarketDeprecated.into())
+        );
+
+        migrate(
+            &mut old,
+            &mut new,
+            &mut creator,
+            &mut [&mut alice, &mut bob],
+            8,
+        )
+        .unwrap();
+        assert!(old.book().sell_orders.is_empty());
+        assert_eq!(
+            alice.open_orders.lamports - deposits_before,
+            2 * OrderBook::ORDER_DEPOSIT_LAMPORTS
+        );
+        // Alice sold 2 of her 5 and keeps her bid's 1,000 plus the 240 paid
+        let balances = alice.balances(&new);
+        assert_eq!((balances.base_free, balances.quote_free), (3, 1_240));
+        assert_eq!(bob.balances(&new).base_free, 2);
+        // Her old record only keeps the maker volume she can claim rewards for
+        assert_eq!(
+            alice.balances(&old),
+            OpenOrdersMarket {
+                market: old.order_book.key,
+                active_epoch_end: 1,
+                maker_volume: 240,
+                ..OpenOrdersMarket::default()
+            }
+        );
+        assert_eq!(
+            old.base_vault.token_amount() + old.quote_vault.token_amount(),
+            0
+        );
+        assert_eq!(new.base_vault.token_amount(), 5);
+        assert_eq!(new.quote_vault.token_amount(), 1_240);
+    }
+
+    #[test]
+    fn test_transfer_free_balance() {
+        let mut from = TestMarket::new();
+        let mut to = from.sibling();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut alice = from.trader();
+        let mut bob = from.trader();
+        let order = new_order(alice.wallet.key, 5, 120, OrderType::Sell);
+        place_order(&mut from, &mut alice, &mut clock, order).unwrap();
+        let order = new_order(bob.wallet.key, 2, 120, OrderType::Buy);
+        place_order_with_makers(
+            &mut from,
+            &mut bob,
+            &mut clock,
+            std::slice::from_mut(&mut alice),
+            order,
+        )
+        .unwrap();
+
+        let transfer = |from: &mut TestMarket,
+                        to: &mut TestMarket,
+                        signer: &mut TestAccount,
+                        owner: &mut TestTrader,
+                        (base_amount, quote_amount),
+                        blocklists: &mut [TestAccount]| {
+            let mut vault_signer = TestAccount::new(
+                OrderBook::find_vault_signer(&id(), &from.order_book.key).0,
+                Pubkey::default(),
+                vec![],
+            );
+            let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+            let mut accounts = vec![
+                from.order_book.info(),
+                to.order_book.info(),
+                signer.info(),
+                owner.open_orders.info(),
+                from.base_vault.info(),
+                from.quote_vault.info(),
+                to.base_vault.info(),
+                to.quote_vault.info(),
+                vault_signer.info(),
+                token_program.info(),
+            ];
+            accounts.extend(blocklists.iter_mut().map(TestAccount::info));
+            process_instruction(
+                &id(),
+                &accounts,
+                &OrderBookInstruction::TransferFreeBalance {
+                    base_amount,
+                    quote_amount,
+                }
+                .pack(),
+            )
+        };
+        assert_eq!(
+            transfer(
+                &mut from,
+                &mut to,
+                &mut bob.wallet.clone(),
+                &mut alice,
+                (0, 100),
+                &mut [],
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        // Alice's 3 remaining base are locked in her ask, not free
+        assert_eq!(
+            transfer(
+                &mut from,
+                &mut to,
+                &mut alice.wallet.clone(),
+                &mut alice,
+                (1, 0),
+                &mut [],
+            ),
+            Err(ProgramError::InsufficientFunds)
+        );
+
+        transfer(
+            &mut from,
+            &mut to,
+            &mut alice.wallet.clone(),
+            &mut alice,
+            (0, 200),
+            &mut [],
+        )
+        .unwrap();
+        assert_eq!(alice.balances(&from).quote_free, 40);
+        assert_eq!(alice.balances(&to).quote_free, 200);
+        assert_eq!(from.quote_vault.token_amount(), 40);
+        assert_eq!(to.quote_vault.token_amount(), 200);
+
+        // Bob's whole record moves, and the emptied one is released
+        transfer(
+            &mut from,
+            &mut to,
+            &mut bob.wallet.clone(),
+            &mut bob,
+            (2, 0),
+            &mut [],
+        )
+        .unwrap();
+        assert_eq!(bob.balances(&to).base_free, 2);
+        assert_eq!(bob.balances(&from), OpenOrdersMarket::default());
+        assert_eq!(to.base_vault.token_amount(), 2);
+
+        // Base only moves between markets of the same base mint
+        let mut book = to.book();
+        book.base_mint = Pubkey::new_unique();
+        book.pack_into_slice(&mut to.order_book.data).unwrap();
+        assert_eq!(
+            transfer(
+                &mut to,
+                &mut from,
+                &mut bob.wallet.clone(),
+                &mut bob,
+                (1, 0),
+                &mut [],
+            ),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        // A blocked address cannot move its balance out of a market that
+        // enforces a blocklist
+        let authority = Pubkey::new_unique();
+        let blocklist = Blocklist {
+            authority,
+            addresses: vec![alice.wallet.key],
+        };
+        let mut blocklist = TestAccount::new(
+            Blocklist::find_address(&id(), &authority).0,
+            id(),
+            blocklist.pack(),
+        );
+        let mut book = from.book();
+        book.blocklist = blocklist.key;
+        book.pack_into_slice(&mut from.order_book.data).unwrap();
+        assert_eq!(
+            transfer(
+                &mut from,
+                &mut to,
+                &mut alice.wallet.clone(),
+                &mut alice,
+                (0, 40),
+                &mut [],
+            ),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(
+            transfer(
+                &mut from,
+                &mut to,
+                &mut alice.wallet.clone(),
+                &mut alice,
+                (0, 40),
+                std::slice::from_mut(&mut blocklist),
+            ),
+            Err(OrderBookError::AddressBlocked.into())
+        );
+    }
+
+    #[test]
+    fn test_backstop_fee_subsidy() {
+        let mut market = TestMarket::new();
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.buy_taker_fee_bps = 100;
+        book.sell_taker_fee_bps = 100;
+        book.backstop_discount_bps = 5_000;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut backstop = market.trader();
+        let mut taker = market.trader();
+
+        // Only the market creator registers backstop makers
+        let set_backstop = |market: &mut TestMarket, signer: &mut TestAccount, backstop| {
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), signer.info()],
+                &OrderBookInstruction::SetBackstopMaker {
+                    maker: taker.wallet.key,
+                    backstop,
+                }
+                .pack(),
+            )
+        };
+        let mut intruder = TestAccount::signer(Pubkey::new_unique());
+        assert_eq!(
+            set_backstop(&mut market, &mut intruder, true),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        set_backstop(&mut market, &mut creator, true).unwrap();
+        set_backstop(&mut market, &mut creator, false).unwrap();
+        assert!(market.book().backstop_makers.is_empty());
+        let mut book = market.book();
+        book.backstop_makers.push(backstop.wallet.key);
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+
+        // Anyone can fund the incentives
+        install_syscall_stubs();
+        let mut funder = TestAccount::signer(Pubkey::new_unique());
+        let mut funder_token = TestAccount::token_account(book.quote_mint, funder.key, 100);
+        let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                funder.info(),
+                funder_token.info(),
+                market.quote_vault.info(),
+                token_program.info(),
+            ],
+            &OrderBookInstruction::FundIncentives { amount: 8 }.pack(),
+        )
+        .unwrap();
+        assert_eq!(market.quote_vault.token_amount(), 8);
+        assert_eq!(market.book().incentive_balance, 8);
+
+        // Fills against the backstop maker halve the taker fee until the
+        // incentives run out, while the fee collector earns the full fee
+        let quote_held = |market: &TestMarket, taker: &TestTrader| {
+            taker.quote_token.token_amount() + taker.balances(market).quote_free
+        };
+        for paid in [1_005, 1_007] {
+            let order = new_order(backstop.wallet.key, 10, 100, OrderType::Sell);
+            place_order(&mut market, &mut backstop, &mut clock, order).unwrap();
+            let before = quote_held(&market, &taker);
+            let order = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+            place_order_with_makers(
+                &mut market,
+                &mut taker,
+                &mut clock,
+                std::slice::from_mut(&mut backstop),
+                order,
+            )
+            .unwrap();
+            assert_eq!(before - quote_held(&market, &taker), paid);
+        }
+        let book = market.book();
+        assert_eq!((book.fees_collected, book.incentive_balance), (20, 0));
+        // The vault backs the maker's proceeds, the fees and the taker's
+        // unused fee reserve
+        assert_eq!(market.quote_vault.token_amount(), 2_000 + 20 + 3);
+
+        // Other makers' fills pay the full fee
+        let mut maker = market.trader();
+        let order = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        let before = quote_held(&market, &taker);
+        let order = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            order,
+        )
+        .unwrap();
+        assert_eq!(before - quote_held(&market, &taker), 1_010);
+    }
+
+    #[test]
+    fn test_fee_epochs() {
+        let mut market = TestMarket::new();
+        let mut book = market.book();
+        book.buy_taker_fee_bps = 100;
+        book.sell_taker_fee_bps = 100;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut makers = [market.trader(), market.trader()];
+        let mut taker = market.trader();
+
+        // Rewards are funded into the current epoch
+        install_syscall_stubs();
+        let mut funder = TestAccount::signer(Pubkey::new_unique());
+        let mut funder_token = TestAccount::token_account(book.quote_mint, funder.key, 350);
+        let mut token_program = TestAccount::new(spl_token::id(), Pubkey::default(), vec![]);
+        let mut fund = |market: &mut TestMarket, amount| {
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    funder.info(),
+                    funder_token.info(),
+                    market.quote_vault.info(),
+                    token_program.info(),
+                ],
+                &OrderBookInstruction::FundEpochRewards { amount }.pack(),
+            )
+            .unwrap()
+        };
+        fund(&mut market, 300);
+        assert_eq!(market.quote_vault.token_amount(), 300);
+
+        // Fee-paying fills count towards the epoch and its makers' volume
+        for (maker, amount) in makers.iter_mut().zip([10, 20]) {
+            let order = new_order(maker.wallet.key, amount, 100, OrderType::Sell);
+            place_order(&mut market, maker, &mut clock, order).unwrap();
+        }
+        let order = new_order(taker.wallet.key, 30, 100, OrderType::Buy);
+        place_order_with_makers(&mut market, &mut taker, &mut clock, &mut makers, order).unwrap();
+        let epoch_zero = FeeEpoch {
+            epoch: 0,
+            fees: 30,
+            volume: 3_000,
+            rewards: 300,
+            rewards_claimed: 0,
+        };
+        assert_eq!(market.book().fee_epoch, epoch_zero);
+        assert_eq!(makers[1].balances(&market).maker_volume, 2_000);
+
+        // Makers claim their share by volume once the epoch closes
+        let claim = |market: &mut TestMarket, trader: &mut TestTrader, clock: &TestAccount| {
+            set_runtime_clock(clock);
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    trader.wallet.info(),
+                    trader.open_orders.info(),
+                ],
+                &OrderBookInstruction::ClaimEpochRewards.pack(),
+            )
+        };
+        assert_eq!(
+            claim(&mut market, &mut makers[0], &clock),
+            Err(OrderBookError::NoEpochRewards.into())
+        );
+        let clock = TestAccount::clock(2, OrderBook::FEE_EPOCH_SECONDS as i64);
+        claim(&mut market, &mut makers[0], &clock).unwrap();
+        assert_eq!(makers[0].balances(&market).quote_free, 1_000 + 100);
+        assert_eq!(
+            claim(&mut market, &mut makers[0], &clock),
+            Err(OrderBookError::NoEpochRewards.into())
+        );
+        let book = market.book();
+        assert_eq!(
+            book.closed_fee_epochs,
+            vec![FeeEpoch {
+                rewards_claimed: 100,
+                ..epoch_zero
+            }]
+        );
+        assert_eq!(
+            book.fee_epoch,
+            FeeEpoch {
+                epoch: 1,
+                ..FeeEpoch::default()
+            }
+        );
+
+        // Rewards of an epoch without volume roll over into the next one
+        fund(&mut market, 50);
+        let clock = TestAccount::clock(3, 2 * OrderBook::FEE_EPOCH_SECONDS as i64);
+        claim(&mut market, &mut makers[1], &clock).unwrap();
+        assert_eq!(makers[1].balances(&market).quote_free, 2_000 + 200);
+        let book = market.book();
+        assert_eq!(book.closed_fee_epochs[0].rewards_claimed, 300);
+        assert_eq!((book.fee_epoch.epoch, book.fee_epoch.rewards), (2, 50));
+    }
+
+    #[test]
+    fn test_expired_order_bounty() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1_000);
+        let mut owner = market.trader();
+        let order = Order {
+            expires_at: 2_000,
+            bounty: 5_000,
+            ..new_order(owner.wallet.key, 10, 100, OrderType::Sell)
+        };
+        place_order(&mut market, &mut owner, &mut clock, order).unwrap();
+        assert_eq!(
+            market.order_book.lamports,
+            OrderBook::ORDER_DEPOSIT_LAMPORTS + 5_000
+        );
+
+        // Expired orders no longer match or show as the best order
+        let mut expired = TestAccount::clock(2, 2_000);
+        let data = query_at(
+            &mut market.order_book,
+            &mut expired,
+            OrderBookInstruction::VerifyMarket,
+        );
+        assert_eq!(VerifyMarketReport::unpack(&data).unwrap().sell_orders, 1);
+        let mut taker = market.trader();
+        let buy = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        place_order(&mut market, &mut taker, &mut expired, buy).unwrap();
+        let book = market.book();
+        assert_eq!(book.get_best_sell_order(&clock_at(0, 2_000)), None);
+        assert_eq!(book.buy_orders.len(), 1);
+
+        // A keeper collects the bounty and the owner gets the deposit back
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        let cancel = OrderBookInstruction::CancelExpiredOrder { order_id: 0 }.pack();
+        let mut cancel_at = |order_book: &mut TestAccount, clock: &mut TestAccount| {
+            process_instruction(
+                &id(),
+                &[
+                    order_book.info(),
+                    keeper.info(),
+                    owner.open_orders.info(),
+                    clock.info(),
+                ],
+                &cancel,
+            )
+        };
+        assert_eq!(
+            cancel_at(&mut market.order_book, &mut clock),
+            Err(OrderBookError::OrderNotExpired.into())
+        );
+        cancel_at(&mut market.order_book, &mut expired).unwrap();
+        assert_eq!(keeper.lamports, 1_005_000);
+        assert_eq!(owner.wallet.lamports, 1_000_000 - 15_000);
+        assert_eq!(
+            owner.open_orders.lamports,
+            Rent::default().minimum_balance(OpenOrders::LEN) + OrderBook::ORDER_DEPOSIT_LAMPORTS
+        );
+        assert_eq!(owner.balances(&market).base_free, 10);
+        assert_eq!(owner.balances(&market).base_locked, 0);
+    }
+
+    #[test]
+    fn test_slot_expiry() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(10, 1_000);
+        let mut owner = market.trader();
+        let order = Order {
+            expires_at: 20,
+            expiry_unit: ExpiryUnit::Slot,
+            ..new_order(owner.wallet.key, 10, 100, OrderType::Sell)
+        };
+        place_order(&mut market, &mut owner, &mut clock, order).unwrap();
+        let book = market.book();
+        assert_eq!(book.sell_orders[0].expiry_unit, ExpiryUnit::Slot);
+        assert!(book.get_best_sell_order(&clock_at(19, 1_000)).is_some());
+        assert_eq!(book.get_best_sell_order(&clock_at(20, 0)), None);
+
+        // The order expires at its slot however little time has passed
+        let mut expired = TestAccount::clock(20, 1_001);
+        let mut taker = market.trader();
+        let buy = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        place_order(&mut market, &mut taker, &mut expired, buy).unwrap();
+        assert_eq!(market.book().buy_orders.len(), 1);
+
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        let cancel = OrderBookInstruction::CancelExpiredOrder { order_id: 0 }.pack();
+        let mut cancel_at = |order_book: &mut TestAccount, clock: &mut TestAccount| {
+            process_instruction(
+                &id(),
+                &[
+                    order_book.info(),
+                    keeper.info(),
+                    owner.open_orders.info(),
+                    clock.info(),
+                ],
+                &cancel,
+            )
+        };
+        assert_eq!(
+            cancel_at(&mut market.order_book, &mut clock),
+            Err(OrderBookError::OrderNotExpired.into())
+        );
+        cancel_at(&mut market.order_book, &mut expired).unwrap();
+        assert!(market.book().sell_orders.is_empty());
+        assert_eq!(owner.balances(&market).base_free, 10);
+    }
+
+    #[test]
+    fn test_prune_expired_orders() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(10, 1_000);
+        let mut alice = market.trader();
+        let mut bob = market.trader();
+        let expiring = [
+            (false, 2_000, ExpiryUnit::UnixTime, 100),
+            (true, 20, ExpiryUnit::Slot, 110),
+            (false, 0, ExpiryUnit::UnixTime, 120),
+            (true, 1_500, ExpiryUnit::UnixTime, 130),
+        ];
+        for (is_bob, expires_at, expiry_unit, price) in expiring {
+            let trader = if is_bob { &mut bob } else { &mut alice };
+            let order = Order {
+                expires_at,
+                expiry_unit,
+                bounty: if expires_at == 0 { 0 } else { 1_000 },
+                ..new_order(trader.wallet.key, 10, price, OrderType::Sell)
+            };
+            place_order(&mut market, trader, &mut clock, order).unwrap();
+        }
+        let book = market.book();
+        assert_eq!(
+            book.expiration_wheel
+                .iter()
+                .map(|entry| entry.order_id)
+                .collect::<Vec<_>>(),
+            vec![3, 0, 1]
+        );
+        assert!(book.expired_order_ids(&clock_at(19, 1_499), 8).is_empty());
+        assert_eq!(book.expired_order_ids(&clock_at(20, 2_000), 2), vec![3, 0]);
+
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        let mut prune = |market: &mut TestMarket,
+                         clock: &mut TestAccount,
+                         owners: Vec<&mut TestAccount>,
+                         max_orders| {
+            set_runtime_clock(clock);
+            let mut accounts = vec![market.order_book.info(), keeper.info()];
+            accounts.extend(owners.into_iter().map(|owner| owner.info()));
+            process_instruction(
+                &id(),
+                &accounts,
+                &OrderBookInstruction::PruneExpiredOrders { max_orders }.pack(),
+            )
+        };
+
+        // Nothing has expired yet
+        prune(&mut market, &mut clock, vec![], 8).unwrap();
+        assert_eq!(market.book().sell_orders.len(), 4);
+
+        // The owners' OpenOrders accounts must be passed
+        let mut expired = TestAccount::clock(20, 1_600);
+        assert_eq!(
+            prune(&mut market, &mut expired, vec![], 8),
+            Err(OrderBookError::MakerAccountMissing.into())
+        );
+        prune(
+            &mut market,
+            &mut expired,
+            vec![&mut alice.open_orders, &mut bob.open_orders],
+            8,
+        )
+        .unwrap();
+        let book = market.book();
+        assert_eq!(
+            book.sell_orders
+                .iter()
+                .map(|order| order.order_id)
+                .collect::<Vec<_>>(),
+            vec![0, 2]
+        );
+        assert_eq!(book.expiration_wheel.len(), 1);
+        assert_eq!(keeper.lamports, 1_002_000);
+        assert_eq!(bob.balances(&market).base_free, 20);
+        assert_eq!(bob.balances(&market).order_count, 0);
+
+        // Canceling an expiring order takes it off the wheel too
+        let cancel = OrderBookInstruction::CancelOrder { order_id: 0 }.pack();
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                alice.wallet.info(),
+                alice.open_orders.info(),
+            ],
+            &cancel,
+        )
+        .unwrap();
+        assert!(market.book().expiration_wheel.is_empty());
+    }
+
+    #[test]
+    fn test_keeper_bond_priority() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(10, 1_000);
+        let mut trader = market.trader();
+        for price in 100..100 + KeeperBond::BACKLOG_ORDERS as u64 {
+            let order = Order {
+                expires_at: 1_500,
+                bounty: 1_000,
+                ..new_order(trader.wallet.key, 1, price, OrderType::Sell)
+            };
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        }
+
+        // Bond the whole priority window
+        let mut keeper = TestAccount::signer(Pubkey::new_unique());
+        keeper.lamports = 2 * KeeperBond::FULL_PRIORITY_LAMPORTS;
+        let bond_key = KeeperBond::find_address(&id(), &keeper.key).0;
+        let mut bond = TestAccount::new(bond_key, system_program::id(), vec![0; KeeperBond::LEN]);
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        process_instruction(
+            &id(),
+            &[
+                bond.info(),
+                keeper.info(),
+                system_program.info(),
+                clock.info(),
+            ],
+            &OrderBookInstruction::BondKeeper {
+                lamports: KeeperBond::FULL_PRIORITY_LAMPORTS,
+            }
+            .pack(),
+        )
+        .unwrap();
+        assert_eq!(
+            KeeperBond::unpack(&bond.data).unwrap(),
+            KeeperBond {
+                keeper: keeper.key,
+                bonded_lamports: KeeperBond::FULL_PRIORITY_LAMPORTS,
+                bonded_at: 1_000,
+            }
+        );
+
+        // Two seconds after the backlog expired, only the bonded keeper may
+        // prune it
+        let mut expired = TestAccount::clock(20, 1_502);
+        let mut racer = TestAccount::signer(Pubkey::new_unique());
+        let prune = OrderBookInstruction::PruneExpiredOrders { max_orders: 4 }.pack();
+        set_runtime_clock(&expired);
+        assert_eq!(
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    racer.info(),
+                    trader.open_orders.info(),
+                ],
+                &prune,
+            ),
+            Err(OrderBookError::KeeperPriorityWindow.into())
+        );
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                keeper.info(),
+                bond.info(),
+                trader.open_orders.info(),
+            ],
+            &prune,
+        )
+        .unwrap();
+        assert_eq!(market.book().sell_orders.len(), 4);
+
+        // Below the backlog threshold anyone may prune again
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                racer.info(),
+                trader.open_orders.info(),
+            ],
+            &prune,
+        )
+        .unwrap();
+        assert!(market.book().sell_orders.is_empty());
+
+        // The bond stays locked for a day after bonding
+        let unbond = OrderBookInstruction::UnbondKeeper {
+            lamports: KeeperBond::FULL_PRIORITY_LAMPORTS,
+        }
+        .pack();
+        assert_eq!(
+            process_instruction(
+                &id(),
+                &[bond.info(), keeper.info(), expired.info()],
+                &unbond,
+            ),
+            Err(ProgramError::InvalidArgument)
+        );
+        let before = keeper.lamports;
+        let mut unlocked = TestAccount::clock(30, 1_000 + KeeperBond::LOCK_SECONDS);
+        process_instruction(
+            &id(),
+            &[bond.info(), keeper.info(), unlocked.info()],
+            &unbond,
+        )
+        .unwrap();
+        assert_eq!(keeper.lamports, before + KeeperBond::FULL_PRIORITY_LAMPORTS);
+        assert_eq!(KeeperBond::unpack(&bond.data).unwrap().bonded_lamports, 0);
+    }
+
+    #[test]
+    fn test_initialize_config_and_market() {
+        install_syscall_stubs();
+        let program_id = id();
+        let mut admin = TestAccount::signer(Pubkey::new_unique());
+        admin.lamports = 1_000_000_000;
+        let (program_data_key, _) =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+        let program_data = UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address: Some(admin.key),
+        };
+        let mut program_data = TestAccount::new(
+            program_data_key,
+            bpf_loader_upgradeable::id(),
+            bincode::serialize(&program_data).unwrap(),
+        );
+        let mut config = TestAccount::new(
+            Config::find_address(&program_id).0,
+            system_program::id(),
+            vec![0; Config::LEN],
+        );
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+
+        // Only the upgrade authority can create the Config
+        let init_config = OrderBookInstruction::InitializeConfig {
+            default_tick_size: 5,
+            default_lot_size: 10,
+            unique_markets: false,
+            protocol_fee_bps: 0,
+        }
+        .pack();
+        let mut intruder = TestAccount::signer(Pubkey::new_unique());
+        assert_eq!(
+            process_instruction(
+                &program_id,
+                &[
+                    config.info(),
+                    intruder.info(),
+                    program_data.info(),
+                    system_program.info(),
+                    rent.info(),
+                ],
+                &init_config,
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        process_instruction(
+            &program_id,
+            &[
+                config.info(),
+                admin.info(),
+                program_data.info(),
+                system_program.info(),
+                rent.info(),
+            ],
+            &init_config,
+        )
+        .unwrap();
+        assert_eq!(config.owner, program_id);
+
+        // Anyone can create a market, taking the default lot size
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        creator.lamports = 1_000_000_000;
+        let mut order_book =
+            TestAccount::new(Pubkey::new_unique(), program_id, vec![0; OrderBook::LEN]);
+        order_book.lamports = Rent::default().minimum_balance(OrderBook::LEN);
+        let mut base_mint = TestAccount::new(Pubkey::new_unique(), Pubkey::default(), vec![]);
+        let mut quote_mint = TestAccount::new(Pubkey::new_unique(), Pubkey::default(), vec![]);
+        let vault_signer = OrderBook::find_vault_signer(&program_id, &order_book.key).0;
+        let mut base_vault = TestAccount::token_account(base_mint.key, vault_signer, 0);
+        let mut quote_vault = TestAccount::token_account(quote_mint.key, creator.key, 0);
+        let valid_quote_vault = TestAccount::token_account(quote_mint.key, vault_signer, 0);
+        let mut registry = TestAccount::new(
+            MarketRegistryEntry::find_address(&program_id, &order_book.key).0,
+            system_program::id(),
+            vec![0; MarketRegistryEntry::LEN],
+        );
+        let init_market = OrderBookInstruction::InitializeMarket {
+            tick_size: 2,
+            lot_size: 0,
+            buy_taker_fee_bps: 0,
+            sell_taker_fee_bps: 0,
+            backstop_discount_bps: 0,
+            dark_pool: false,
+            oracle_source: OracleSource::None,
+            oracle: Pubkey::default(),
+            whole_units: false,
+        }
+        .pack();
+        let mut init_market_accounts =
+            |order_book: &mut TestAccount, quote_vault: &mut TestAccount| {
+                process_instruction(
+                    &program_id,
+                    &[
+                        order_book.info(),
+                        creator.info(),
+                        base_mint.info(),
+                        quote_mint.info(),
+                        config.info(),
+                        registry.info(),
+                        system_program.info(),
+                        rent.info(),
+                        base_vault.info(),
+                        quote_vault.info(),
+                    ],
+                    &init_market,
+                )
+            };
+
+        // The vaults must be controlled by the market's vault signer
+        assert_eq!(
+            init_market_accounts(&mut order_book, &mut quote_vault),
+            Err(ProgramError::InvalidAccountData)
+        );
+        let mut quote_vault = valid_quote_vault;
+        init_market_accounts(&mut order_book, &mut quote_vault).unwrap();
+        assert_eq!(
+            init_market_accounts(&mut order_book, &mut quote_vault),
+            Err(ProgramError::AccountAlreadyInitialized)
+        );
+
+        let book = OrderBook::unpack(&order_book.data).unwrap();
+        assert_eq!((book.tick_size, book.lot_size), (2, 10));
+        assert_eq!(book.creator, creator.key);
+        assert_eq!(book.quote_vault, quote_vault.key);
+        let entry = MarketRegistryEntry::unpack(&registry.data).unwrap();
+        assert_eq!(entry.order_book, order_book.key);
+        assert_eq!(entry.base_mint, base_mint.key);
+        let filter = OrderBook::base_mint_filter(&base_mint.key);
+        assert_eq!(
+            &order_book.data[filter.offset..filter.offset + 32],
+            filter.bytes.as_slice()
+        );
+
+        // Orders must respect the tick and lot sizes; a price is per lot
+        let mut market = TestMarket {
+            order_book,
+            config: config.clone(),
+            base_vault,
+            quote_vault,
+            trade_history: None,
+            candle_series: vec![],
+        };
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        for (amount, price, result) in [
+            (10, 3, Err(OrderBookError::InvalidOrderSize.into())),
+            (15, 4, Err(OrderBookError::InvalidOrderSize.into())),
+            (20, 4, Ok(())),
+        ] {
+            let order = new_order(trader.wallet.key, amount, price, OrderType::Buy);
+            assert_eq!(
+                place_order(&mut market, &mut trader, &mut clock, order),
+                result
+            );
+        }
+        assert_eq!(market.quote_vault.token_amount(), 8);
+    }
+
+    #[test]
+    fn test_listing_requirements() {
+        install_syscall_stubs();
+        let program_id = id();
+        let mut admin = TestAccount::signer(Pubkey::new_unique());
+        let mut config = TestAccount::config(admin.key, false);
+        let listing = ListingRequirements {
+            min_tick_size: 10,
+            min_lot_size: 100,
+            max_taker_fee_bps: 50,
+            crank_funding_lamports: 5_000_000,
+        };
+        let set_listing = OrderBookInstruction::SetListingRequirements(listing).pack();
+        let mut intruder = TestAccount::signer(Pubkey::new_unique());
+        assert_eq!(
+            process_instruction(&program_id, &[config.info(), intruder.info()], &set_listing),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        process_instruction(&program_id, &[config.info(), admin.info()], &set_listing).unwrap();
+        assert_eq!(Config::unpack(&config.data).unwrap().listing, listing);
+
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        creator.lamports = 1_000_000_000;
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        let mut base_mint = TestAccount::new(Pubkey::new_unique(), Pubkey::default(), vec![]);
+        let mut quote_mint = TestAccount::new(Pubkey::new_unique(), Pubkey::default(), vec![]);
+        let mut init_market = |tick_size, lot_size, buy_taker_fee_bps| {
+            let mut order_book =
+                TestAccount::new(Pubkey::new_unique(), program_id, vec![0; OrderBook::LEN]);
+            order_book.lamports = Rent::default().minimum_balance(OrderBook::LEN);
+            let vault_signer = OrderBook::find_vault_signer(&program_id, &order_book.key).0;
+            let mut base_vault = TestAccount::token_account(base_mint.key, vault_signer, 0);
+            let mut quote_vault = TestAccount::token_account(quote_mint.key, vault_signer, 0);
+            let mut registry = TestAccount::new(
+                MarketRegistryEntry::find_address(&program_id, &order_book.key).0,
+                system_program::id(),
+                vec![0; MarketRegistryEntry::LEN],
+            );
+            process_instruction(
+                &program_id,
+                &[
+                    order_book.info(),
+                    creator.info(),
+                    base_mint.info(),
+                    quote_mint.info(),
+                    config.info(),
+                    registry.info(),
+                    system_program.info(),
+                    rent.info(),
+                    base_vault.info(),
+                    quote_vault.info(),
+                ],
+                &OrderBookInstruction::InitializeMarket {
+                    tick_size,
+                    lot_size,
+                    buy_taker_fee_bps,
+                    sell_taker_fee_bps: 0,
+                    backstop_discount_bps: 0,
+                    dark_pool: false,
+                    oracle_source: OracleSource::None,
+                    oracle: Pubkey::default(),
+                    whole_units: false,
+                }
+                .pack(),
+            )
+            .map(|()| order_book)
+        };
+
+        // Sizes below the minimums, the defaults included, and fees above
+        // the cap are refused
+        for (tick_size, lot_size, fee_bps) in [(5, 100, 0), (10, 0, 0), (10, 100, 51)] {
+            assert_eq!(
+                init_market(tick_size, lot_size, fee_bps).err(),
+                Some(OrderBookError::ListingRequirementsNotMet.into())
+            );
+        }
+
+        // A listed market starts with the creator's crank funding
+        let order_book = init_market(10, 100, 50).unwrap();
+        let book = OrderBook::unpack(&order_book.data).unwrap();
+        assert_eq!(book.crank_incentive_lamports, 5_000_000);
+        assert_eq!(
+            order_book.lamports,
+            Rent::default().minimum_balance(OrderBook::LEN) + 5_000_000
+        );
+    }
+
+    #[test]
+    fn test_whole_unit_market() {
+        install_syscall_stubs();
+        let program_id = id();
+        let mut config = TestAccount::config(Pubkey::new_unique(), false);
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        creator.lamports = 1_000_000_000;
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        let mint = |decimals| {
+            let mint = spl_token::state::Mint {
+                decimals,
+                is_initialized: true,
+                ..spl_token::state::Mint::default()
+            };
+            let mut data = vec![0; spl_token::state::Mint::LEN];
+            spl_token::state::Mint::pack(mint, &mut data).unwrap();
+            TestAccount::new(Pubkey::new_unique(), spl_token::id(), data)
+        };
+        let mut quote_mint = TestAccount::new(Pubkey::new_unique(), Pubkey::default(), vec![]);
+        let mut init_market = |base_mint: &mut TestAccount, lot_size| {
+            let mut order_book =
+                TestAccount::new(Pubkey::new_unique(), program_id, vec![0; OrderBook::LEN]);
+            order_book.lamports = Rent::default().minimum_balance(OrderBook::LEN);
+            let vault_signer = OrderBook::find_vault_signer(&program_id, &order_book.key).0;
+            let mut base_vault = TestAccount::token_account(base_mint.key, vault_signer, 0);
+            let mut quote_vault = TestAccount::token_account(quote_mint.key, vault_signer, 0);
+            let mut registry = TestAccount::new(
+                MarketRegistryEntry::find_address(&program_id, &order_book.key).0,
+                system_program::id(),
+                vec![0; MarketRegistryEntry::LEN],
+            );
+            process_instruction(
+                &program_id,
+                &[
+                    order_book.info(),
+                    creator.info(),
+                    base_mint.info(),
+                    quote_mint.info(),
+                    config.info(),
+                    registry.info(),
+                    system_program.info(),
+                    rent.info(),
+                    base_vault.info(),
+                    quote_vault.info(),
+                ],
+                &OrderBookInstruction::InitializeMarket {
+                    tick_size: 0,
+                    lot_size,
+                    buy_taker_fee_bps: 0,
+                    sell_taker_fee_bps: 0,
+                    backstop_discount_bps: 0,
+                    dark_pool: false,
+                    oracle_source: OracleSource::None,
+                    oracle: Pubkey::default(),
+                    whole_units: true,
+                }
+                .pack(),
+            )
+            .map(|()| TestMarket {
+                order_book,
+                config: TestAccount::config(Pubkey::new_unique(), false),
+                base_vault,
+                quote_vault,
+                trade_history: None,
+                candle_series: vec![],
+            })
+        };
+
+        // Only mints without decimals trade in lots of one unit
+        assert_eq!(
+            init_market(&mut mint(6), 0).err(),
+            Some(OrderBookError::InvalidOrderSize.into())
+        );
+        let mut nft = mint(0);
+        assert_eq!(
+            init_market(&mut nft, 10).err(),
+            Some(OrderBookError::InvalidOrderSize.into())
+        );
+        let mut market = init_market(&mut nft, 0).unwrap();
+        let book = market.book();
+        assert!(book.whole_units);
+        assert_eq!(book.lot_size, 1);
+
+        // Units are listed and bid for at a price per unit
+        let mut clock = TestAccount::clock(1, 1);
+        let mut seller = market.trader();
+        let mut buyer = market.trader();
+        let ask = new_order(seller.wallet.key, 2, 40_000, OrderType::Sell);
+        place_order(&mut market, &mut seller, &mut clock, ask).unwrap();
+        let bid = new_order(buyer.wallet.key, 3, 40_000, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut buyer,
+            &mut clock,
+            std::slice::from_mut(&mut seller),
+            bid,
+        )
+        .unwrap();
+        assert_eq!(seller.balances(&market).quote_free, 80_000);
+        let balances = buyer.balances(&market);
+        assert_eq!((balances.base_free, balances.quote_locked), (2, 40_000));
+        assert_eq!(market.book().buy_orders[0].amount, 1);
+    }
+
+    #[test]
+    fn test_unique_markets() {
+        install_syscall_stubs();
+        let program_id = id();
+        let mut admin = TestAccount::signer(Pubkey::new_unique());
+        let mut config = TestAccount::config(admin.key, true);
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        creator.lamports = 1_000_000_000;
+        let mut base_mint = TestAccount::new(Pubkey::new_unique(), Pubkey::default(), vec![]);
+        let mut quote_mint = TestAccount::new(Pubkey::new_unique(), Pubkey::default(), vec![]);
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        let mut canonical = TestAccount::new(
+            CanonicalMarket::find_address(&program_id, &base_mint.key, &quote_mint.key, 1, 1).0,
+            system_program::id(),
+            vec![0; CanonicalMarket::LEN],
+        );
+
+        let mut markets = vec![];
+        for _ in 0..2 {
+            let mut order_book =
+                TestAccount::new(Pubkey::new_unique(), program_id, vec![0; OrderBook::LEN]);
+            order_book.lamports = Rent::default().minimum_balance(OrderBook::LEN);
+            let vault_signer = OrderBook::find_vault_signer(&program_id, &order_book.key).0;
+            let mut base_vault = TestAccount::token_account(base_mint.key, vault_signer, 0);
+            let mut quote_vault = TestAccount::token_account(quote_mint.key, vault_signer, 0);
+            let mut registry = TestAccount::new(
+                MarketRegistryEntry::find_address(&program_id, &order_book.key).0,
+                system_program::id(),
+                vec![0; MarketRegistryEntry::LEN],
+            );
+            let result = process_instruction(
+                &program_id,
+                &[
+                    order_book.info(),
+                    creator.info(),
+                    base_mint.info(),
+                    quote_mint.info(),
+                    config.info(),
+                    registry.info(),
+                    system_program.info(),
+                    rent.info(),
+                    base_vault.info(),
+                    quote_vault.info(),
+                    canonical.info(),
+                ],
+                &OrderBookInstruction::InitializeMarket {
+                    tick_size: 0,
+                    lot_size: 0,
+                    buy_taker_fee_bps: 0,
+                    sell_taker_fee_bps: 0,
+                    backstop_discount_bps: 0,
+                    dark_pool: false,
+                    oracle_source: OracleSource::None,
+                    oracle: Pubkey::default(),
+                    whole_units: false,
+                }
+                .pack(),
+            );
+            markets.push((result, order_book.key, registry));
+        }
+
+        // Only the first market for the combination is created
+        assert_eq!(markets[0].0, Ok(()));
+        assert_eq!(markets[1].0, Err(ProgramError::AccountAlreadyInitialized));
+        assert_eq!(canonical.owner, program_id);
+        assert_eq!(canonical.data, markets[0].1.to_bytes());
+        let registry = &mut markets[0].2;
+        assert!(
+            MarketRegistryEntry::unpack(&registry.data)
+                .unwrap()
+                .canonical
+        );
+
+        // Only the Config admin can change the canonical flag
+        let unset = OrderBookInstruction::SetCanonicalMarket { canonical: false }.pack();
+        assert_eq!(
+            process_instruction(
+                &program_id,
+                &[config.info(), creator.info(), registry.info()],
+                &unset
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        process_instruction(
+            &program_id,
+            &[config.info(), admin.info(), registry.info()],
+            &unset,
+        )
+        .unwrap();
+        assert!(
+            !MarketRegistryEntry::unpack(&registry.data)
+                .unwrap()
+                .canonical
+        );
+    }
+
+    #[test]
+    fn test_init_open_orders() {
+        install_syscall_stubs();
+        let mut owner = TestAccount::signer(Pubkey::new_unique());
+        owner.lamports = 1_000_000_000;
+        let mut quote_mint = TestAccount::new(Pubkey::new_unique(), Pubkey::default(), vec![]);
+        let mut open_orders = TestAccount::new(
+            OpenOrders::find_address(&id(), &owner.key, &quote_mint.key).0,
+            system_program::id(),
+            vec![0; OpenOrders::LEN],
+        );
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        let mut init = |open_orders: &mut TestAccount| {
+            process_instruction(
+                &id(),
+                &[
+                    open_orders.info(),
+                    owner.info(),
+                    quote_mint.info(),
+                    system_program.info(),
+                    rent.info(),
+                ],
+                &OrderBookInstruction::InitOpenOrders.pack(),
+            )
+        };
+        init(&mut open_orders).unwrap();
+        assert_eq!(
+            init(&mut open_orders),
+            Err(ProgramError::AccountAlreadyInitialized)
+        );
+        assert_eq!(open_orders.owner, id());
+        let account = OpenOrders::unpack(&open_orders.data).unwrap();
+        assert_eq!(account.owner, owner.key);
+        assert_eq!(account.quote_mint, quote_mint.key);
+        assert!(account.markets.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_and_prune_orders() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(10, 1);
+        let mut trader = market.trader();
+        for price in [90, 91] {
+            let order = new_order(trader.wallet.key, 1, price, OrderType::Buy);
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        }
+        assert_eq!(
+            trader.wallet.lamports,
+            1_000_000 - 2 * OrderBook::ORDER_DEPOSIT_LAMPORTS
+        );
+        assert_eq!(trader.balances(&market).quote_locked, 181);
+
+        // Only the owner can cancel
+        let mut other = TestAccount::signer(Pubkey::new_unique());
+        let cancel = OrderBookInstruction::CancelOrder { order_id: 0 }.pack();
+        assert_eq!(
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    other.info(),
+                    trader.open_orders.info()
+                ],
+                &cancel
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                trader.wallet.info(),
+                trader.open_orders.info(),
+            ],
+            &cancel,
+        )
+        .unwrap();
+        assert_eq!(
+            trader.wallet.lamports,
+            1_000_000 - OrderBook::ORDER_DEPOSIT_LAMPORTS
+        );
+
+        // Nobody can prune on a market that has not opted in
+        let prune = OrderBookInstruction::PruneOrder { order_id: 1 }.pack();
+        let mut later = TestAccount::clock(10 + OrderBook::MIN_PRUNE_AFTER_SLOTS, 2);
+        assert_eq!(
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    other.info(),
+                    later.info(),
+                    trader.open_orders.info()
+                ],
+                &prune
+            ),
+            Err(OrderBookError::OrderNotAbandoned.into())
+        );
+
+        // The creator opts in with an age of at least a week
+        let mut creator = TestAccount::signer(Pubkey::new_unique());
+        let mut book = market.book();
+        book.creator = creator.key;
+        book.pack_into_slice(&mut market.order_book.data).unwrap();
+        let mut set_prune_after = |market: &mut TestMarket, slots| {
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), creator.info()],
+                &OrderBookInstruction::SetPruneAfter { slots }.pack(),
+            )
+        };
+        assert_eq!(
+            set_prune_after(&mut market, OrderBook::MIN_PRUNE_AFTER_SLOTS - 1),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        set_prune_after(&mut market, OrderBook::MIN_PRUNE_AFTER_SLOTS).unwrap();
+        assert_eq!(
+            market.book().prune_after_slots,
+            OrderBook::MIN_PRUNE_AFTER_SLOTS
+        );
+
+        // Then anyone can prune once the order is abandoned
+        assert_eq!(
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    other.info(),
+                    clock.info(),
+                    trader.open_orders.info()
+                ],
+                &prune
+            ),
+            Err(OrderBookError::OrderNotAbandoned.into())
+        );
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                other.info(),
+                later.info(),
+                trader.open_orders.info(),
+            ],
+            &prune,
+        )
+        .unwrap();
+        assert_eq!(
+            other.lamports,
+            1_000_000 + OrderBook::ORDER_DEPOSIT_LAMPORTS
+        );
+        assert_eq!(market.order_book.lamports, 0);
+        assert_eq!(market.book().buy_orders, vec![]);
+
+        // Both orders' funds are free to withdraw again
+        let balances = trader.balances(&market);
+        assert_eq!((balances.quote_free, balances.quote_locked), (181, 0));
+        assert_eq!(balances.order_count, 0);
+    }
+
+    #[test]
+    fn test_cancel_orders_by_price() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(10, 1);
+        let mut trader = market.trader();
+        let mut other = market.trader();
+        for price in [90, 93, 92, 91] {
+            let order = new_order(trader.wallet.key, 1, price, OrderType::Buy);
+            place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        }
+        let order = new_order(other.wallet.key, 1, 95, OrderType::Buy);
+        place_order(&mut market, &mut other, &mut clock, order).unwrap();
+        let order = new_order(trader.wallet.key, 1, 120, OrderType::Sell);
+        place_order(&mut market, &mut trader, &mut clock, order).unwrap();
+        let mut cancel = |market: &mut TestMarket, max_count| {
+            process_instruction(
+                &id(),
+                &[
+                    market.order_book.info(),
+                    trader.wallet.info(),
+                    trader.open_orders.info(),
+                ],
+                &OrderBookInstruction::CancelOrdersByPrice {
+                    side: OrderType::Buy,
+                    limit_price: 91,
+                    max_count,
+                }
+                .pack(),
+            )
+        };
+
+        // The trader's most aggressive bids go first, up to the count
+        cancel(&mut market, 2).unwrap();
+        let resting = |market: &TestMarket| -> Vec<u64> {
+            market
+                .book()
+                .buy_orders
+                .iter()
+                .map(|order| order.price)
+                .collect()
+        };
+        assert_eq!(resting(&market), vec![90, 91, 95]);
+        cancel(&mut market, 10).unwrap();
+        assert_eq!(resting(&market), vec![90, 95]);
+        assert_eq!(market.book().sell_orders.len(), 1);
+
+        // Their funds and deposits are released
+        let balances = trader.balances(&market);
+        assert_eq!((balances.quote_free, balances.quote_locked), (276, 90));
+        assert_eq!(balances.order_count, 2);
+        assert_eq!(
+            trader.wallet.lamports,
+            1_000_000 - 2 * OrderBook::ORDER_DEPOSIT_LAMPORTS
+        );
+    }
+
+    #[test]
+    fn test_order_statuses() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(10, 1);
+        let mut maker = market.trader();
+        for price in [100, 101] {
+            let ask = new_order(maker.wallet.key, 10, price, OrderType::Sell);
+            place_order(&mut market, &mut maker, &mut clock, ask).unwrap();
+        }
+        let bid = new_order(maker.wallet.key, 5, 90, OrderType::Buy);
+        place_order(&mut market, &mut maker, &mut clock, bid).unwrap();
+        let mut taker = market.trader();
+        let buy = new_order(taker.wallet.key, 15, 101, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            buy,
+        )
+        .unwrap();
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                maker.wallet.info(),
+                maker.open_orders.info(),
+            ],
+            &OrderBookInstruction::CancelOrder { order_id: 2 }.pack(),
+        )
+        .unwrap();
+
+        let data = query(
+            &mut market.order_book,
+            OrderBookInstruction::GetOrderStatuses {
+                order_ids: vec![0, 1, 2, 3, 99],
+            },
+        );
+        let statuses: Vec<(OrderStatus, u64)> = OrderStatusReport::unpack(&data)
+            .unwrap()
+            .statuses
+            .iter()
+            .map(|entry| (entry.status, entry.filled_amount))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (OrderStatus::Filled, 10),
+                (OrderStatus::PartiallyFilled, 5),
+                (OrderStatus::Canceled, 0),
+                (OrderStatus::Filled, 15),
+                (OrderStatus::Unknown, 0),
+            ]
+        );
+
+        // The book remembers only the most recent closed orders
+        let mut book = market.book();
+        for order_id in 100..100 + OrderBook::MAX_CLOSED_ORDERS as u64 {
+            let order = Order {
+                order_id,
+                ..new_order(maker.wallet.key, 1, 90, OrderType::Buy)
+            };
+            book.record_closed(&order, OrderStatus::Canceled);
+        }
+        assert_eq!(book.order_status(0).status, OrderStatus::Unknown);
+        assert_eq!(book.order_status(1).status, OrderStatus::PartiallyFilled);
+        let mut data = vec![0; OrderBook::LEN];
+        book.pack_into_slice(&mut data).unwrap();
+        assert_eq!(OrderBook::unpack(&data).unwrap(), book);
+    }
+
+    #[test]
+    fn test_fill_receipts() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(10, 1_000);
+        let mut maker = market.trader();
+        let ask = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, ask).unwrap();
+        let mut taker = market.trader();
+        let bid = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            bid,
+        )
+        .unwrap();
+
+        let rent_exempt = Rent::default().minimum_balance(FillReceipt::LEN);
+        let mut fill_receipt = TestAccount::new(
+            FillReceipt::find_address(&id(), &market.order_book.key, 1).0,
+            system_program::id(),
+            vec![0; FillReceipt::LEN],
+        );
+        let mut system_program = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        let mut write = |fill_receipt: &mut TestAccount, owner: &mut TestAccount, order_id| {
+            process_instruction(
+                &id(),
+                &[
+                    fill_receipt.info(),
+                    market.order_book.info(),
+                    owner.info(),
+                    system_program.info(),
+                    rent.info(),
+                    clock.info(),
+                ],
+                &OrderBookInstruction::WriteFillReceipt { order_id }.pack(),
+            )
+        };
+        // Only the owner of a filled order can write its receipt
+        assert_eq!(
+            write(&mut fill_receipt, &mut maker.wallet, 1),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            write(&mut fill_receipt, &mut taker.wallet, 99),
+            Err(OrderBookError::OrderNotFound.into())
+        );
+        taker.wallet.lamports = rent_exempt;
+        write(&mut fill_receipt, &mut taker.wallet, 1).unwrap();
+        assert_eq!(taker.wallet.lamports, 0);
+        let receipt = FillReceipt::unpack(&fill_receipt.data).unwrap();
+        assert_eq!(
+            (receipt.owner, receipt.side, receipt.filled_amount),
+            (taker.wallet.key, OrderType::Buy, 10)
+        );
+        assert_eq!(receipt.average_price, 100);
+        assert_eq!(receipt.recorded_at, 1_000);
+        assert_eq!(
+            write(&mut fill_receipt, &mut taker.wallet, 1),
+            Err(ProgramError::AccountAlreadyInitialized)
+        );
+
+        // Closing it returns the rent to the owner
+        let mut close = |owner: &mut TestAccount| {
+            process_instruction(
+                &id(),
+                &[fill_receipt.info(), owner.info()],
+                &OrderBookInstruction::CloseFillReceipt.pack(),
+            )
+        };
+        assert_eq!(
+            close(&mut maker.wallet),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        close(&mut taker.wallet).unwrap();
+        assert_eq!(taker.wallet.lamports, rent_exempt);
+        assert_eq!(fill_receipt.lamports, 0);
+    }
+
+    #[test]
+    fn test_decode_log_events() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(10, 1);
+        let mut maker = market.trader();
+        let ask = new_order(maker.wallet.key, 10, 100, OrderType::Sell);
+        let bid = new_order(maker.wallet.key, 5, 90, OrderType::Buy);
+        let mut taker = market.trader();
+        let buy = new_order(taker.wallet.key, 10, 100, OrderType::Buy);
+        LOGGED_DATA.with(|cell| cell.borrow_mut().clear());
+        place_order(&mut market, &mut maker, &mut clock, ask).unwrap();
+        place_order(&mut market, &mut maker, &mut clock, bid).unwrap();
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            buy,
+        )
+        .unwrap();
+        process_instruction(
+            &id(),
+            &[
+                market.order_book.info(),
+                maker.wallet.info(),
+                maker.open_orders.info(),
+            ],
+            &OrderBookInstruction::CancelOrder { order_id: 1 }.pack(),
+        )
+        .unwrap();
+
+        // Data logged by other programs is skipped
+        let other_program = Pubkey::new_unique();
+        let mut logs = vec![format!("Program {} invoke [1]", id())];
+        logs.extend(LOGGED_DATA.with(|cell| cell.borrow().clone()));
+        logs.push(format!("Program {} invoke [2]", other_program));
+        logs.push("Program data: AAAA".to_string());
+        logs.push(format!("Program {} success", other_program));
+        logs.push(format!("Program {} success", id()));
+        let market_key = market.order_book.key;
+        let events = client::decode_log_events(&id(), &logs).unwrap();
+        let summary: Vec<(&str, u64)> = events
+            .iter()
+            .map(|event| match event {
+                FordexEvent::Place(place) => ("place", place.order_id),
+                FordexEvent::Fill { fill, .. } => ("fill", fill.maker_order_id),
+                FordexEvent::Out(out) => ("out", out.order_id),
+                FordexEvent::Cancel(cancel) => ("cancel", cancel.order_id),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("place", 0),
+                ("place", 1),
+                ("fill", 0),
+                ("out", 0),
+                ("cancel", 1)
+            ]
+        );
+        assert_eq!(
+            events[4],
+            FordexEvent::Cancel(CancelEvent {
+                market: market_key,
+                order_id: 1,
+                trader: maker.wallet.key,
+                side: OrderType::Buy,
+                price: 90,
+                canceled_amount: 5,
+            })
+        );
+        for event in &events {
+            assert_eq!(FordexEvent::unpack(&event.pack()).unwrap(), *event);
+        }
+        assert!(client::decode_log_data("AAAA").is_err());
+    }
+
+    #[test]
+    fn test_order_tags() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(10, 1);
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+
+        // One OpenOrders account quotes for two strategies
+        for (price, tag) in [(100, 7), (101, 8)] {
+            let ask = Order {
+                tag,
+                ..new_order(maker.wallet.key, 5, price, OrderType::Sell)
+            };
+            place_order(&mut market, &mut maker, &mut clock, ask).unwrap();
+        }
+        assert_eq!(market.book().find_order(1).unwrap().tag, 8);
+
+        // Every fill echoes the tags of both orders
+        LOGGED_DATA.with(|cell| cell.borrow_mut().clear());
+        let buy = Order {
+            tag: u64::from_le_bytes(*b"arb-desk"),
+            ..new_order(taker.wallet.key, 10, 101, OrderType::Buy)
+        };
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            buy,
+        )
+        .unwrap();
+        let mut logs = vec![format!("Program {} invoke [1]", id())];
+        logs.extend(LOGGED_DATA.with(|cell| cell.borrow().clone()));
+        let tags: Vec<(u64, [u8; 8])> = client::decode_log_events(&id(), &logs)
+            .unwrap()
+            .iter()
+            .filter_map(|event| match event {
+                FordexEvent::Fill { fill, .. } => {
+                    Some((fill.maker_tag, fill.taker_tag.to_le_bytes()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(tags, vec![(7, *b"arb-desk"), (8, *b"arb-desk")]);
+    }
+
+    #[test]
+    fn test_rejects_mis_sized_accounts() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        market.order_book.data.push(0);
+        assert_eq!(
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), clock.info()],
+                &OrderBookInstruction::GetBestBuyOrder.pack(),
+            ),
+            Err(OrderBookError::InvalidAccountSize.into())
+        );
+
+        market.order_book.data.pop();
+        trader.open_orders.data.truncate(OpenOrders::LEN - 1);
+        let order = new_order(trader.wallet.key, 1, 1, OrderType::Buy);
+        assert_eq!(
+            place_order(&mut market, &mut trader, &mut clock, order),
+            Err(OrderBookError::InvalidAccountSize.into())
+        );
+    }
+
+    #[test]
+    fn test_place_order_checks_accounts() {
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut trader = market.trader();
+        trader.wallet.is_signer = false;
+        let order = new_order(trader.wallet.key, 1, 1, OrderType::Buy);
+        assert_eq!(
+            place_order(&mut market, &mut trader, &mut clock, order),
+            Err(OrderBookError::MissingRequiredSigner.into())
+        );
+
+        trader.wallet.is_signer = true;
+        market.order_book.is_writable = false;
+        assert_eq!(
+            place_order(&mut market, &mut trader, &mut clock, order),
+            Err(OrderBookError::AccountNotWritable.into())
+        );
+
+        market.order_book.is_writable = true;
+        assert_eq!(
+            process_instruction(
+                &id(),
+                &[market.order_book.info(), trader.wallet.info()],
+                &OrderBookInstruction::PlaceOrder(order).pack(),
+            ),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_eq!(market.book().buy_orders.len(), 0);
+    }
+
+    #[test]
+    fn test_read_only_flows_lock_book_read_only() {
+        let read_only = [
+            OrderBookInstruction::GetBestBuyOrder,
+            OrderBookInstruction::VerifyMarket,
+            OrderBookInstruction::GetMarketStats,
+            OrderBookInstruction::GetInventory,
+            OrderBookInstruction::UpdatePriceOracle,
+            OrderBookInstruction::TransferFreeBalance {
+                base_amount: 0,
+                quote_amount: 0,
+            },
+            OrderBookInstruction::SettleFunds {
+                max_base: u64::MAX,
+                max_quote: u64::MAX,
+                recipient: None,
+            },
+        ];
+        for instruction in read_only {
+            assert!(
+                instruction
+                    .expected_accounts()
+                    .iter()
+                    .all(|spec| spec.name != "order_book" || !spec.writable),
+                "{:?}",
+                instruction
+            );
+        }
+
+        // Settlement runs with the order book locked read-only
+        let mut market = TestMarket::new();
+        let mut clock = TestAccount::clock(1, 1);
+        let mut maker = market.trader();
+        let mut taker = market.trader();
+        let order = new_order(maker.wallet.key, 5, 100, OrderType::Sell);
+        place_order(&mut market, &mut maker, &mut clock, order).unwrap();
+        let order = new_order(taker.wallet.key, 5, 100, OrderType::Buy);
+        place_order_with_makers(
+            &mut market,
+            &mut taker,
+            &mut clock,
+            std::slice::from_mut(&mut maker),
+            order,
+        )
+        .unwrap();
+        market.order_book.is_writable = false;
+        let quote_before = maker.quote_token.token_amount();
+        settle_funds(&mut market, &mut maker).unwrap();
+        assert_eq!(maker.quote_token.token_amount() - quote_before, 500);
+    }
 }
-}
\ No newline at end of file
@@ -0,0 +1,493 @@
+// Deterministic seeded markets for local development
+//
+// `seed_market` creates the mints, vaults and accounts of a market on a
+// local cluster, initializes it and rests the orders of a Fixture on its
+// book, so front-end and bot developers get a working market in one command.
+// Every keypair derives from a fixed name, so a fixture seeds the same
+// addresses on every run. The program must be deployed upgradeable with
+// `authority()` as its upgrade authority, which the `fordex-localnet` binary
+// arranges when it starts solana-test-validator.
+//
+// A fixture is a text file of one entry per line, `#` starting a comment:
+//
+//     market tick_size=1 lot_size=10 base_decimals=6 quote_decimals=6
+//     trader alice base=1000000 quote=1000000
+//     bid alice price=99 amount=500
+//     ask alice price=101 amount=500
+//
+// The market line may also set `buy_fee_bps` and `sell_fee_bps`. Traders
+// are funded with their `base` and `quote` before the bids and asks are
+// placed in file order. The resting book must not cross.
+
+use crate::{
+    client::{self, InitializeMarketAccounts, MarketParams, MatchingAccounts, PlaceOrderAccounts},
+    state::{Config, ExpiryUnit, OpenOrders, Order, OrderBook, OrderType, PegType},
+};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    rpc_client::RpcClient,
+};
+use solana_program::{
+    bpf_loader_upgradeable, hash::hashv, instruction::Instruction, program_error::ProgramError,
+    program_pack::Pack, pubkey::Pubkey, system_instruction,
+};
+use solana_sdk::{
+    signature::{keypair_from_seed, Keypair},
+    signer::Signer,
+    transaction::Transaction,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+
+// Define a trader of a fixture and the tokens it is funded with
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixtureTrader {
+    pub name: String,
+    pub base: u64,
+    pub quote: u64,
+}
+
+// Define an order a fixture rests on the book
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixtureOrder {
+    pub trader: String,
+    pub side: OrderType,
+    pub price: u64,
+    pub amount: u64,
+}
+
+// Define the market and book a fixture seeds
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fixture {
+    pub params: MarketParams,
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+    pub traders: Vec<FixtureTrader>,
+    pub orders: Vec<FixtureOrder>,
+}
+
+impl Fixture {
+    // Parse a fixture, naming the line of the first invalid entry
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut fixture = Fixture::default();
+        let mut has_market = false;
+        for (index, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let Some(kind) = words.next() else {
+                continue;
+            };
+            fixture
+                .parse_entry(kind, words.collect(), &mut has_market)
+                .map_err(|err| format!("line {}: {}", index + 1, err))?;
+        }
+        if !has_market {
+            return Err("fixture has no market entry".to_string());
+        }
+        let best_bid = fixture.prices(OrderType::Buy).max();
+        let best_ask = fixture.prices(OrderType::Sell).min();
+        if let (Some(bid), Some(ask)) = (best_bid, best_ask) {
+            if bid >= ask {
+                return Err(format!("bid at {} crosses ask at {}", bid, ask));
+            }
+        }
+        Ok(fixture)
+    }
+
+    fn parse_entry(
+        &mut self,
+        kind: &str,
+        words: Vec<&str>,
+        has_market: &mut bool,
+    ) -> Result<(), String> {
+        match kind {
+            "market" => {
+                let fields = Fields::parse(&words)?;
+                self.params.tick_size = fields.get("tick_size")?;
+                self.params.lot_size = fields.get("lot_size")?;
+                self.params.buy_taker_fee_bps = fields.get_or("buy_fee_bps", 0)?;
+                self.params.sell_taker_fee_bps = fields.get_or("sell_fee_bps", 0)?;
+                self.base_decimals = fields.get("base_decimals")?;
+                self.quote_decimals = fields.get("quote_decimals")?;
+                *has_market = true;
+            }
+            "trader" => {
+                let (name, words) = words.split_first().ok_or("trader has no name")?;
+                if self.traders.iter().any(|trader| &trader.name == name) {
+                    return Err(format!("trader {} is defined twice", name));
+                }
+                let fields = Fields::parse(words)?;
+                self.traders.push(FixtureTrader {
+                    name: name.to_string(),
+                    base: fields.get("base")?,
+                    quote: fields.get("quote")?,
+                });
+            }
+            "bid" | "ask" => {
+                let (trader, words) = words.split_first().ok_or("order has no trader")?;
+                if !self.traders.iter().any(|known| &known.name == trader) {
+                    return Err(format!("unknown trader {}", trader));
+                }
+                let fields = Fields::parse(words)?;
+                self.orders.push(FixtureOrder {
+                    trader: trader.to_string(),
+                    side: if kind == "bid" {
+                        OrderType::Buy
+                    } else {
+                        OrderType::Sell
+                    },
+                    price: fields.get("price")?,
+                    amount: fields.get("amount")?,
+                });
+            }
+            _ => return Err(format!("unknown entry {}", kind)),
+        }
+        Ok(())
+    }
+
+    fn prices(&self, side: OrderType) -> impl Iterator<Item = u64> + '_ {
+        self.orders
+            .iter()
+            .filter(move |order| order.side == side)
+            .map(|order| order.price)
+    }
+}
+
+// The `key=value` fields of a fixture entry
+struct Fields<'a>(Vec<(&'a str, &'a str)>);
+
+impl<'a> Fields<'a> {
+    fn parse(words: &[&'a str]) -> Result<Self, String> {
+        words
+            .iter()
+            .map(|word| {
+                word.split_once('=')
+                    .ok_or_else(|| format!("expected key=value, found {}", word))
+            })
+            .collect::<Result<_, _>>()
+            .map(Fields)
+    }
+
+    fn get<T: std::str::FromStr>(&self, key: &str) -> Result<T, String> {
+        let (_, value) = self
+            .0
+            .iter()
+            .find(|(field, _)| *field == key)
+            .ok_or_else(|| format!("missing {}", key))?;
+        value
+            .parse()
+            .map_err(|_| format!("invalid {} {}", key, value))
+    }
+
+    fn get_or<T: std::str::FromStr>(&self, key: &str, default: T) -> Result<T, String> {
+        match self.0.iter().any(|(field, _)| *field == key) {
+            true => self.get(key),
+            false => Ok(default),
+        }
+    }
+}
+
+// The keypair a fixture uses for `name`, the same on every run
+pub fn keypair(name: &str) -> Keypair {
+    let seed = hashv(&[b"fordex-localnet", name.as_bytes()]);
+    keypair_from_seed(seed.as_ref()).expect("a hash is a valid keypair seed")
+}
+
+// The keypair of the fixture trader `name`
+pub fn trader_keypair(name: &str) -> Keypair {
+    keypair(&format!("trader/{}", name))
+}
+
+// The program's upgrade authority, which also pays for and creates the
+// market
+pub fn authority() -> Keypair {
+    keypair("authority")
+}
+
+// Define the accounts of a trader a fixture seeded
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeededTrader {
+    pub name: String,
+    pub wallet: Pubkey,
+    pub open_orders: Pubkey,
+    pub base_token: Pubkey,
+    pub quote_token: Pubkey,
+}
+
+// Define the accounts of a market a fixture seeded
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeededMarket {
+    pub order_book: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub base_vault: Pubkey,
+    pub quote_vault: Pubkey,
+    pub traders: Vec<SeededTrader>,
+}
+
+// Lamports each fixture keypair is airdropped for fees and rent
+pub const AIRDROP_LAMPORTS: u64 = 100_000_000_000;
+
+// Create, initialize and seed the market of `fixture`
+#[allow(clippy::result_large_err)]
+pub fn seed_market(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey,
+    fixture: &Fixture,
+) -> ClientResult<SeededMarket> {
+    let authority = authority();
+    airdrop(rpc_client, &authority.pubkey())?;
+
+    let config = Config::find_address(program_id).0;
+    if rpc_client
+        .get_account_with_commitment(&config, rpc_client.commitment())?
+        .value
+        .is_none()
+    {
+        let (program_data, _) =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+        send(
+            rpc_client,
+            &authority,
+            &[client::initialize_config(
+                program_id,
+                &authority.pubkey(),
+                &program_data,
+                fixture.params.tick_size,
+                fixture.params.lot_size,
+                false,
+                0,
+            )],
+            &[],
+        )?;
+    }
+
+    // Mints, the order book account and the vaults of its vault signer
+    let base_mint = keypair("base_mint");
+    let quote_mint = keypair("quote_mint");
+    let order_book = keypair("order_book");
+    let mut instructions = vec![];
+    for (mint, decimals) in [
+        (&base_mint, fixture.base_decimals),
+        (&quote_mint, fixture.quote_decimals),
+    ] {
+        instructions.push(system_instruction::create_account(
+            &authority.pubkey(),
+            &mint.pubkey(),
+            rpc_client.get_minimum_balance_for_rent_exemption(spl_token::state::Mint::LEN)?,
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ));
+        instructions.push(
+            spl_token::instruction::initialize_mint2(
+                &spl_token::id(),
+                &mint.pubkey(),
+                &authority.pubkey(),
+                None,
+                decimals,
+            )
+            .map_err(program_error)?,
+        );
+    }
+    instructions.push(system_instruction::create_account(
+        &authority.pubkey(),
+        &order_book.pubkey(),
+        rpc_client.get_minimum_balance_for_rent_exemption(OrderBook::LEN)?,
+        OrderBook::LEN as u64,
+        program_id,
+    ));
+    send(
+        rpc_client,
+        &authority,
+        &instructions,
+        &[&base_mint, &quote_mint, &order_book],
+    )?;
+
+    let (vault_signer, _) = OrderBook::find_vault_signer(program_id, &order_book.pubkey());
+    let base_vault = get_associated_token_address(&vault_signer, &base_mint.pubkey());
+    let quote_vault = get_associated_token_address(&vault_signer, &quote_mint.pubkey());
+    let accounts = InitializeMarketAccounts {
+        order_book: order_book.pubkey(),
+        creator: authority.pubkey(),
+        base_mint: base_mint.pubkey(),
+        quote_mint: quote_mint.pubkey(),
+        base_vault,
+        quote_vault,
+        canonical: None,
+    };
+    send(
+        rpc_client,
+        &authority,
+        &[
+            create_associated_token_account(
+                &authority.pubkey(),
+                &vault_signer,
+                &base_mint.pubkey(),
+                &spl_token::id(),
+            ),
+            create_associated_token_account(
+                &authority.pubkey(),
+                &vault_signer,
+                &quote_mint.pubkey(),
+                &spl_token::id(),
+            ),
+            client::initialize_market(program_id, &accounts, &fixture.params),
+        ],
+        &[],
+    )?;
+
+    // Fund each trader and create its OpenOrders account
+    let mut traders = vec![];
+    for trader in &fixture.traders {
+        let wallet = trader_keypair(&trader.name);
+        airdrop(rpc_client, &wallet.pubkey())?;
+        let seeded = SeededTrader {
+            name: trader.name.clone(),
+            wallet: wallet.pubkey(),
+            open_orders: OpenOrders::find_address(
+                program_id,
+                &wallet.pubkey(),
+                &quote_mint.pubkey(),
+            )
+            .0,
+            base_token: get_associated_token_address(&wallet.pubkey(), &base_mint.pubkey()),
+            quote_token: get_associated_token_address(&wallet.pubkey(), &quote_mint.pubkey()),
+        };
+        let mut instructions = vec![];
+        for (mint, token, amount) in [
+            (&base_mint, seeded.base_token, trader.base),
+            (&quote_mint, seeded.quote_token, trader.quote),
+        ] {
+            instructions.push(create_associated_token_account(
+                &wallet.pubkey(),
+                &wallet.pubkey(),
+                &mint.pubkey(),
+                &spl_token::id(),
+            ));
+            instructions.push(
+                spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    &mint.pubkey(),
+                    &token,
+                    &authority.pubkey(),
+                    &[],
+                    amount,
+                )
+                .map_err(program_error)?,
+            );
+        }
+        instructions.push(client::init_open_orders(
+            program_id,
+            &wallet.pubkey(),
+            &quote_mint.pubkey(),
+        ));
+        send(rpc_client, &authority, &instructions, &[&wallet])?;
+        traders.push(seeded);
+    }
+
+    for order in &fixture.orders {
+        let wallet = trader_keypair(&order.trader);
+        let trader = traders
+            .iter()
+            .find(|trader| trader.wallet == wallet.pubkey())
+            .expect("fixture orders name known traders");
+        let (trader_token, vault) = match order.side {
+            OrderType::Buy => (trader.quote_token, quote_vault),
+            OrderType::Sell => (trader.base_token, base_vault),
+        };
+        let accounts = PlaceOrderAccounts {
+            order_book: order_book.pubkey(),
+            trader: wallet.pubkey(),
+            open_orders: trader.open_orders,
+            trader_token,
+            vault,
+            order_gate_signer: None,
+            blocklist: None,
+            matching: MatchingAccounts::default(),
+        };
+        send(
+            rpc_client,
+            &authority,
+            &[client::place_order(
+                program_id,
+                &accounts,
+                fixture_order(wallet.pubkey(), order),
+            )],
+            &[&wallet],
+        )?;
+    }
+
+    Ok(SeededMarket {
+        order_book: order_book.pubkey(),
+        base_mint: base_mint.pubkey(),
+        quote_mint: quote_mint.pubkey(),
+        base_vault,
+        quote_vault,
+        traders,
+    })
+}
+
+// A plain limit order; the program stamps the slot, time and id
+fn fixture_order(trader: Pubkey, order: &FixtureOrder) -> Order {
+    Order {
+        trader,
+        amount: order.amount,
+        price: order.price,
+        order_type: order.side,
+        slot: 0,
+        unix_timestamp: 0,
+        order_id: 0,
+        expires_at: 0,
+        bounty: 0,
+        peg: PegType::None,
+        peg_ticks: 0,
+        linked_order_id: None,
+        trail_ticks: 0,
+        trigger_price: 0,
+        improvement_slots: 0,
+        avg_price_limit: 0,
+        min_fill_amount: 0,
+        beneficial_owner: trader,
+        decay_start_price: 0,
+        decay_end: 0,
+        quote_budget: 0,
+        expiry_unit: ExpiryUnit::UnixTime,
+        filled_amount: 0,
+        filled_quote: 0,
+        fees_paid: 0,
+        tag: 0,
+        priority_bid: 0,
+        settle_at: 0,
+    }
+}
+
+fn program_error(err: ProgramError) -> ClientError {
+    ClientErrorKind::Custom(err.to_string()).into()
+}
+
+#[allow(clippy::result_large_err)]
+fn airdrop(rpc_client: &RpcClient, to: &Pubkey) -> ClientResult<()> {
+    let signature = rpc_client.request_airdrop(to, AIRDROP_LAMPORTS)?;
+    rpc_client.poll_for_signature(&signature)
+}
+
+// Send `instructions` paid for and signed by the authority and `signers`
+#[allow(clippy::result_large_err)]
+fn send(
+    rpc_client: &RpcClient,
+    authority: &Keypair,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> ClientResult<()> {
+    let mut all_signers = vec![authority];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&authority.pubkey()),
+        &all_signers,
+        rpc_client.get_latest_blockhash()?,
+    );
+    rpc_client.send_and_confirm_transaction(&transaction)?;
+    Ok(())
+}
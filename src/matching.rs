@@ -0,0 +1,446 @@
+// Matching of incoming orders against the resting orders of a book
+
+use crate::state::{
+    FillEvent, OpenOrdersMarket, Order, OrderBook, OrderStatus, OrderType, PegType,
+};
+use solana_program::{
+    clock::{Clock, UnixTimestamp},
+    msg,
+    program_error::ProgramError,
+};
+
+impl OrderBook {
+    // Amount an incoming order would fill against the book right away
+    pub fn fillable_amount(&self, order: &Order, now: &Clock) -> u64 {
+        let mut probe = order.to_owned();
+        self.clone().match_order(&mut probe, now);
+        order.amount - probe.amount
+    }
+
+    // Whether an incoming order would fill against the book right away
+    pub fn is_marketable(&self, order: &Order, now: &Clock) -> bool {
+        let Some(price) = self.effective_price(order, now) else {
+            return false;
+        };
+        self.best_order(order.order_type.opposite(), now)
+            .is_some_and(|(_, best)| match order.order_type {
+                OrderType::Buy => best <= price,
+                OrderType::Sell => best >= price,
+            })
+    }
+
+    // Best live fixed-price bid and ask, the references of pegged orders
+    fn peg_references(&self, now: &Clock) -> (Option<u64>, Option<u64>) {
+        let fixed = |order_type| {
+            self.orders(order_type)
+                .iter()
+                .filter(move |order| order.peg == PegType::None && !order.is_expired(now))
+                .map(move |order| order.current_price(now, self.tick_size))
+        };
+        (fixed(OrderType::Buy).max(), fixed(OrderType::Sell).min())
+    }
+
+    // Price an order currently trades at. A pegged order floats `peg_ticks`
+    // from its reference, rounded to a tick away from the other side, capped
+    // at its limit and kept off the best fixed order of the other side. It is
+    // inactive, returning None, while its reference is missing. A Dutch
+    // auction ask trades at its decayed price, kept off the best fixed bid
+    // like a pegged ask so that it never crosses the book.
+    pub fn effective_price(&self, order: &Order, now: &Clock) -> Option<u64> {
+        self.effective_price_with(order, self.peg_references(now), now)
+    }
+
+    fn effective_price_with(
+        &self,
+        order: &Order,
+        references: (Option<u64>, Option<u64>),
+        now: &Clock,
+    ) -> Option<u64> {
+        effective_price(order, references, self.tick_size, now)
+    }
+
+    // Index and price of the best live order on a side: highest bid or
+    // lowest ask, earliest placement on ties
+    pub(crate) fn best_order(&self, order_type: OrderType, now: &Clock) -> Option<(usize, u64)> {
+        let references = self.peg_references(now);
+        let orders = self
+            .orders(order_type)
+            .iter()
+            .enumerate()
+            .filter(|(_, order)| !order.is_expired(now))
+            .filter_map(|(index, order)| {
+                Some((index, self.effective_price_with(order, references, now)?))
+            });
+        match order_type {
+            OrderType::Buy => orders.rev().max_by_key(|(_, price)| *price),
+            OrderType::Sell => orders.min_by_key(|(_, price)| *price),
+        }
+    }
+
+    // Get the best buy order (highest price, earliest placement on ties),
+    // priced at its effective price
+    pub fn get_best_buy_order(&self, now: &Clock) -> Option<Order> {
+        self.best_order(OrderType::Buy, now)
+            .map(|(index, price)| Order {
+                price,
+                ..self.buy_orders[index]
+            })
+    }
+
+    // Get the best sell order (lowest price, earliest placement on ties),
+    // priced at its effective price
+    pub fn get_best_sell_order(&self, now: &Clock) -> Option<Order> {
+        self.best_order(OrderType::Sell, now)
+            .map(|(index, price)| Order {
+                price,
+                ..self.sell_orders[index]
+            })
+    }
+
+    // Match an incoming order against the opposite side of the book in
+    // price-time priority, reducing `taker.amount` by the filled amount.
+    // Expired orders are skipped and pegged orders trade at their effective
+    // prices. Returns the fills and the maker orders that were filled
+    // completely and left the book. Makes no syscalls, so proofs in
+    // `proofs.rs` can run it directly.
+    pub(crate) fn match_order(
+        &mut self,
+        taker: &mut Order,
+        now: &Clock,
+    ) -> (Vec<FillEvent>, Vec<Order>) {
+        let mut fills = Vec::new();
+        let mut filled_makers = Vec::new();
+        let maker_side = taker.order_type.opposite();
+        let Some(taker_price) = self.effective_price(taker, now) else {
+            return (fills, filled_makers);
+        };
+        let lot_size = self.lot_size as u128;
+        let whole_units = self.whole_units;
+        let mut filled = 0u128;
+        let mut notional = 0u128;
+        let mut matched = 0u64;
+        let mut budget = taker.quote_budget;
+        while taker.amount > 0 {
+            let Some((index, price)) = self.best_order(maker_side, now) else {
+                break;
+            };
+            // Whole lots the rest of a quote-sized order's budget buys
+            let affordable = (taker.quote_budget > 0).then(|| self.base_amount(price, budget));
+            let makers = self.orders_mut(maker_side);
+            let maker = &mut makers[index];
+            let crosses = match taker.order_type {
+                OrderType::Buy => price <= taker_price,
+                OrderType::Sell => price >= taker_price,
+            };
+            if !crosses || maker.beneficial_owner == taker.beneficial_owner {
+                break;
+            }
+            let mut amount = maker.amount.min(taker.amount);
+            if let Some(affordable) = affordable {
+                amount = amount.min(affordable);
+                if amount == 0 {
+                    break;
+                }
+                budget -= (price as u128 * amount as u128 / lot_size) as u64;
+            }
+            if taker.avg_price_limit > 0 {
+                // Largest amount at `price` that keeps the average fill price
+                // within the limit, in whole lots
+                let limit = taker.avg_price_limit as u128;
+                let headroom = match taker.order_type {
+                    OrderType::Buy if price as u128 > limit => {
+                        Some((limit * filled - notional) / (price as u128 - limit))
+                    }
+                    OrderType::Sell if (price as u128) < limit => {
+                        Some((notional - limit * filled) / (limit - price as u128))
+                    }
+                    _ => None,
+                };
+                if let Some(headroom) = headroom {
+                    let headroom = headroom - headroom % lot_size;
+                    amount = amount.min(headroom.min(u64::MAX as u128) as u64);
+                }
+                if amount == 0 {
+                    break;
+                }
+                filled += amount as u128;
+                notional += price as u128 * amount as u128;
+            }
+            // Quote value of the fill, as `quote_amount` prices it
+            let quote = if whole_units {
+                price as u128 * amount as u128
+            } else {
+                price as u128 * amount as u128 / lot_size
+            }
+            .min(u64::MAX as u128) as u64;
+            maker.amount -= amount;
+            maker.filled_amount += amount;
+            maker.filled_quote = maker.filled_quote.saturating_add(quote);
+            taker.amount -= amount;
+            taker.filled_amount += amount;
+            taker.filled_quote = taker.filled_quote.saturating_add(quote);
+            matched += amount;
+            fills.push(FillEvent {
+                maker: maker.trader,
+                maker_order_id: maker.order_id,
+                taker: taker.trader,
+                taker_order_id: taker.order_id,
+                taker_side: taker.order_type,
+                price,
+                amount,
+                maker_remaining: maker.amount,
+                taker_fee_bps: 0,
+                taker_fee: 0,
+                fee_subsidy: 0,
+                maker_tag: maker.tag,
+                taker_tag: taker.tag,
+            });
+            if maker.amount == 0 {
+                let filled = makers.remove(index);
+                self.record_closed(&filled, OrderStatus::Filled);
+                filled_makers.push(filled);
+            }
+        }
+        let open_interest = self.open_interest_mut(maker_side);
+        *open_interest = open_interest.saturating_sub(matched);
+        (fills, filled_makers)
+    }
+
+    // Match the crossed portion of the book: the later placed of the best
+    // bid and best ask takes from the other at its resting price, until the
+    // book no longer crosses, the two share a beneficial owner or
+    // `max_fills` fills were made. Returns the fills and the orders of
+    // either side that were filled completely and left the book.
+    pub(crate) fn uncross(
+        &mut self,
+        now: &Clock,
+        max_fills: usize,
+    ) -> (Vec<FillEvent>, Vec<Order>) {
+        let mut fills = Vec::new();
+        let mut filled_orders = Vec::new();
+        while fills.len() < max_fills {
+            let (Some((bid_index, bid_price)), Some((ask_index, ask_price))) = (
+                self.best_order(OrderType::Buy, now),
+                self.best_order(OrderType::Sell, now),
+            ) else {
+                break;
+            };
+            let (bid, ask) = (self.buy_orders[bid_index], self.sell_orders[ask_index]);
+            if bid_price < ask_price || bid.beneficial_owner == ask.beneficial_owner {
+                break;
+            }
+            let (taker, maker, price) = if bid.order_id > ask.order_id {
+                (bid, ask, ask_price)
+            } else {
+                (ask, bid, bid_price)
+            };
+            let amount = bid.amount.min(ask.amount);
+            let quote = self.quote_amount(price, amount).unwrap_or(u64::MAX);
+            fills.push(FillEvent {
+                maker: maker.trader,
+                maker_order_id: maker.order_id,
+                taker: taker.trader,
+                taker_order_id: taker.order_id,
+                taker_side: taker.order_type,
+                price,
+                amount,
+                maker_remaining: maker.amount - amount,
+                taker_fee_bps: 0,
+                taker_fee: 0,
+                fee_subsidy: 0,
+                maker_tag: maker.tag,
+                taker_tag: taker.tag,
+            });
+            for (side, index) in [(OrderType::Buy, bid_index), (OrderType::Sell, ask_index)] {
+                let orders = self.orders_mut(side);
+                orders[index].amount -= amount;
+                orders[index].filled_amount += amount;
+                orders[index].filled_quote = orders[index].filled_quote.saturating_add(quote);
+                if orders[index].amount == 0 {
+                    let filled = orders.remove(index);
+                    self.record_closed(&filled, OrderStatus::Filled);
+                    filled_orders.push(filled);
+                }
+                let open_interest = self.open_interest_mut(side);
+                *open_interest = open_interest.saturating_sub(amount);
+            }
+        }
+        (fills, filled_orders)
+    }
+
+    // Cross the orders trading at settlement at `settle_at` at the reference
+    // `price`, or cancel them all when no reference was fixed. Bids and asks
+    // whose limits allow the price trade in order of placement, the later
+    // placed of each pair taking, and never against an order of the same
+    // beneficial owner. Returns the fills and every order of the batch, all
+    // of which leave the book with whatever amount they did not fill.
+    pub(crate) fn match_reference_orders(
+        &mut self,
+        settle_at: UnixTimestamp,
+        price: Option<u64>,
+    ) -> (Vec<FillEvent>, Vec<Order>) {
+        let (mut batch, waiting): (Vec<Order>, Vec<Order>) =
+            std::mem::take(&mut self.reference_orders)
+                .into_iter()
+                .partition(|order| order.settle_at == settle_at);
+        self.reference_orders = waiting;
+        batch.sort_by_key(|order| order.order_id);
+        let mut fills = Vec::new();
+        if let Some(price) = price {
+            let trades = |order: &Order| match order.order_type {
+                OrderType::Buy => order.price >= price,
+                OrderType::Sell => order.price <= price,
+            };
+            for bid_index in 0..batch.len() {
+                for ask_index in 0..batch.len() {
+                    let (bid, ask) = (batch[bid_index], batch[ask_index]);
+                    if bid.order_type != OrderType::Buy
+                        || ask.order_type != OrderType::Sell
+                        || bid.amount == 0
+                        || ask.amount == 0
+                        || !trades(&bid)
+                        || !trades(&ask)
+                        || bid.beneficial_owner == ask.beneficial_owner
+                    {
+                        continue;
+                    }
+                    let (taker, maker) = if bid.order_id > ask.order_id {
+                        (bid, ask)
+                    } else {
+                        (ask, bid)
+                    };
+                    let amount = bid.amount.min(ask.amount);
+                    let quote = self.quote_amount(price, amount).unwrap_or(u64::MAX);
+                    fills.push(FillEvent {
+                        maker: maker.trader,
+                        maker_order_id: maker.order_id,
+                        taker: taker.trader,
+                        taker_order_id: taker.order_id,
+                        taker_side: taker.order_type,
+                        price,
+                        amount,
+                        maker_remaining: maker.amount - amount,
+                        taker_fee_bps: 0,
+                        taker_fee: 0,
+                        fee_subsidy: 0,
+                        maker_tag: maker.tag,
+                        taker_tag: taker.tag,
+                    });
+                    for index in [bid_index, ask_index] {
+                        batch[index].amount -= amount;
+                        batch[index].filled_amount += amount;
+                        batch[index].filled_quote = batch[index].filled_quote.saturating_add(quote);
+                    }
+                }
+            }
+        }
+        // An order left with an amount had the rest canceled, however much
+        // of it filled
+        for order in &batch {
+            let status = match order.amount {
+                0 => OrderStatus::Filled,
+                _ => OrderStatus::Canceled,
+            };
+            self.record_closed(order, status);
+        }
+        (fills, batch)
+    }
+}
+
+// Price `order` trades at given the best fixed bid and ask of its book. See
+// `OrderBook::effective_price`.
+pub(crate) fn effective_price(
+    order: &Order,
+    (best_bid, best_ask): (Option<u64>, Option<u64>),
+    tick_size: u64,
+    now: &Clock,
+) -> Option<u64> {
+    let reference = match order.peg {
+        PegType::None if order.is_dutch() => {
+            let floor = best_bid.map_or(0, |bid| bid + tick_size);
+            return Some(order.current_price(now, tick_size).max(floor));
+        }
+        PegType::None => return Some(order.price),
+        PegType::BestBid => best_bid? as i128,
+        PegType::BestAsk => best_ask? as i128,
+        PegType::Midpoint => (best_bid? as i128 + best_ask? as i128) / 2,
+    };
+    let tick = tick_size as i128;
+    let pegged = reference + order.peg_ticks as i128 * tick;
+    let price = match order.order_type {
+        OrderType::Buy => {
+            let ceiling = best_ask.map_or(i128::MAX, |ask| ask as i128 - tick);
+            (pegged.div_euclid(tick) * tick)
+                .min(order.price as i128)
+                .min(ceiling)
+        }
+        OrderType::Sell => {
+            let floor = best_bid.map_or(0, |bid| bid as i128 + tick);
+            ((pegged + tick - 1).div_euclid(tick) * tick)
+                .max(order.price as i128)
+                .max(floor)
+        }
+    };
+    u64::try_from(price).ok().filter(|price| *price > 0)
+}
+
+// Match a taker order against the book, releasing the funds of whatever its
+// minimum fill amount, average price limit or self-trade prevention cancels.
+// Matching is skipped when the book would fill less than the minimum, and a
+// remainder that stopped matching short of its limit price does not rest
+// crossing the book. The remainder of a quote-sized order is sized from its
+// unspent budget.
+pub(crate) fn match_taker_order(
+    order_book: &mut OrderBook,
+    balances: &mut OpenOrdersMarket,
+    order: &mut Order,
+    now: &Clock,
+) -> Result<(Vec<FillEvent>, Vec<Order>), ProgramError> {
+    if order.min_fill_amount > 0 {
+        let fillable = order_book.fillable_amount(order, now);
+        if fillable > 0 && fillable < order.min_fill_amount {
+            balances.unlock(order.order_type, order_book.locked_funds(order)?);
+            msg!(
+                "Only {} of the minimum fill amount {} is available, canceling the order",
+                fillable,
+                order.min_fill_amount
+            );
+            order.amount = 0;
+            order_book.record_closed(order, OrderStatus::Canceled);
+            return Ok((vec![], vec![]));
+        }
+    }
+    let matched = order_book.match_order(order, now);
+    if order.quote_budget > 0 {
+        // Size the rest of a quote-sized order in whole lots at its limit
+        // price, releasing the part of the budget they leave over
+        let mut spent = 0;
+        for fill in &matched.0 {
+            spent += order_book.quote_amount(fill.price, fill.amount)?;
+        }
+        let remaining = order.quote_budget - spent;
+        order.amount = order_book.base_amount(order.price, remaining);
+        balances.unlock(
+            OrderType::Buy,
+            remaining - order_book.quote_amount(order.price, order.amount)?,
+        );
+    }
+    if order.amount > 0 && order_book.is_marketable(order, now) {
+        balances.unlock(order.order_type, order_book.locked_funds(order)?);
+        msg!(
+            "Matching stopped at the average price limit or a self-trade, canceling the remaining {}",
+            order.amount
+        );
+        order.amount = 0;
+        order_book.record_closed(order, OrderStatus::Canceled);
+    } else if order.amount == 0 {
+        // Filled completely, or left a quote-sized order's budget too small
+        // for another lot at its limit
+        let status = match order.filled_amount {
+            0 => OrderStatus::Canceled,
+            _ => OrderStatus::Filled,
+        };
+        order_book.record_closed(order, status);
+    }
+    Ok(matched)
+}
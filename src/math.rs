@@ -0,0 +1,81 @@
+// Integer math of the fee calculations and the rounding they apply
+
+use solana_program::{msg, program_error::ProgramError};
+
+// Define the direction a division that does not come out even rounds in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rounding {
+    Down,
+    Up,
+    // To the nearest integer, halves to the even one, so that rounding
+    // errors cancel out on average
+    NearestEven,
+}
+
+// Define how a market rounds the fees it charges
+//
+// `Truncate` rounds every fee down, in the payer's favor, as markets always
+// have. `FavorBook` rounds taker fees and the protocol's share of them up
+// and backstop subsidies down, so the dust of every fill accrues to the fee
+// collector and the protocol rather than to traders. `Symmetric` rounds all
+// of them to the nearest atom, so dust accrues to neither side on average.
+//
+// Notional amounts always round down: the quote a fill pays is one amount
+// credited to one side and debited from the other, so its rounding creates
+// no dust.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RoundingPolicy {
+    #[default]
+    Truncate,
+    FavorBook,
+    Symmetric,
+}
+
+impl RoundingPolicy {
+    pub fn from_u8(tag: u8) -> Result<Self, ProgramError> {
+        match tag {
+            0 => Ok(RoundingPolicy::Truncate),
+            1 => Ok(RoundingPolicy::FavorBook),
+            2 => Ok(RoundingPolicy::Symmetric),
+            _ => {
+                msg!("Invalid rounding policy");
+                Err(ProgramError::InvalidInstructionData)
+            }
+        }
+    }
+
+    // Rounding of amounts traders pay the book, the taker fees
+    pub fn charge(self) -> Rounding {
+        match self {
+            RoundingPolicy::Truncate => Rounding::Down,
+            RoundingPolicy::FavorBook => Rounding::Up,
+            RoundingPolicy::Symmetric => Rounding::NearestEven,
+        }
+    }
+
+    // Rounding of amounts the book pays or gives up, the fee subsidies
+    pub fn rebate(self) -> Rounding {
+        match self {
+            RoundingPolicy::Truncate | RoundingPolicy::FavorBook => Rounding::Down,
+            RoundingPolicy::Symmetric => Rounding::NearestEven,
+        }
+    }
+}
+
+// `value * numerator / denominator` computed in 128 bits, rounded as given
+// and saturated to u64. The denominator must not be zero.
+pub fn mul_div(value: u64, numerator: u64, denominator: u64, rounding: Rounding) -> u64 {
+    let product = value as u128 * numerator as u128;
+    let denominator = denominator as u128;
+    let (quotient, remainder) = (product / denominator, product % denominator);
+    let round_up = match rounding {
+        Rounding::Down => false,
+        Rounding::Up => remainder > 0,
+        Rounding::NearestEven => {
+            let twice = remainder * 2;
+            twice > denominator || (twice == denominator && quotient % 2 == 1)
+        }
+    };
+    (quotient + round_up as u128).min(u64::MAX as u128) as u64
+}
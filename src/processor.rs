@@ -0,0 +1,5591 @@
+// Instruction handlers and the accounts and CPIs they work with
+
+use crate::{
+    error::OrderBookError,
+    instruction::OrderBookInstruction,
+    matching::match_taker_order,
+    math::RoundingPolicy,
+    reader::with_order_book,
+    state::{
+        check_account_size, AddressBlockedEvent, Blocklist, BlocklistUpdatedEvent, BookChangeEvent,
+        Candle, CandlePage, CandleSeries, CanonicalMarket, Config, ExpiryUnit, FeeSweptEvent,
+        FillEvent, FillPreview, FillReceipt, FordexEvent, InventoryReport, KeeperBond,
+        LaunchAuction, ListingRequirements, MarketDepth, MarketRegistryEntry, MarketStats,
+        OpenOrders, OracleSource, Order, OrderBook, OrderPage, OrderStatus, OrderStatusReport,
+        OrderType, PegType, PriceLevel, PriceOracle, ProgramVersion, SealedBid, TradeHistory,
+        TradeRecord, VerifyMarketReport,
+    },
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::{Clock, Slot, UnixTimestamp},
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed, set_return_data},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction, system_program,
+    sysvar::Sysvar,
+};
+use spl_associated_token_account::get_associated_token_address;
+use spl_associated_token_account::instruction::create_associated_token_account;
+use std::slice::Iter;
+
+// Move lamports out of a program-owned account
+pub(crate) fn transfer_lamports(
+    from: &AccountInfo,
+    to: &AccountInfo,
+    lamports: u64,
+) -> ProgramResult {
+    let from_lamports = from
+        .lamports()
+        .checked_sub(lamports)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    let to_lamports = to
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **from.try_borrow_mut_lamports()? = from_lamports;
+    **to.try_borrow_mut_lamports()? = to_lamports;
+    Ok(())
+}
+
+// Load an OpenOrders account, checking it is owned by this program and holds
+// balances for markets quoted in `quote_mint`
+fn load_open_orders(
+    program_id: &Pubkey,
+    open_orders_info: &AccountInfo,
+    quote_mint: &Pubkey,
+) -> Result<OpenOrders, ProgramError> {
+    if open_orders_info.owner != program_id {
+        msg!("OpenOrders account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    OpenOrders::check_layout(open_orders_info)?;
+    let open_orders = OpenOrders::unpack(&open_orders_info.try_borrow_data()?)?;
+    if !open_orders.is_initialized {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if &open_orders.quote_mint != quote_mint {
+        msg!(
+            "OpenOrders account is for quote mint {}, not {}",
+            open_orders.quote_mint,
+            quote_mint
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(open_orders)
+}
+
+// Create a program-owned PDA funded by `payer`
+fn create_pda_account<'a>(
+    program_id: &Pubkey,
+    payer_info: &AccountInfo<'a>,
+    pda_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    rent: &Rent,
+    space: usize,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_info.key,
+            pda_info.key,
+            rent.minimum_balance(space),
+            space as u64,
+            program_id,
+        ),
+        &[
+            payer_info.clone(),
+            pda_info.clone(),
+            system_program_info.clone(),
+        ],
+        &[seeds],
+    )
+}
+
+// Transfer `amount` tokens through the token program, checking both balances
+// moved by exactly `amount` so the CPI cannot leave the vaults out of step
+// with what the market accounted for
+fn transfer_tokens<'a>(
+    token_program_info: &AccountInfo<'a>,
+    source_info: &AccountInfo<'a>,
+    destination_info: &AccountInfo<'a>,
+    authority_info: &AccountInfo<'a>,
+    amount: u64,
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let source_before = token_balance(source_info)?;
+    let destination_before = token_balance(destination_info)?;
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            token_program_info.key,
+            source_info.key,
+            destination_info.key,
+            authority_info.key,
+            &[],
+            amount,
+        )?,
+        &[
+            source_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            token_program_info.clone(),
+        ],
+        signers_seeds,
+    )?;
+    if source_before.checked_sub(token_balance(source_info)?) != Some(amount)
+        || token_balance(destination_info)?.checked_sub(destination_before) != Some(amount)
+    {
+        msg!(
+            "Transfer of {} tokens from {} to {} moved a different amount",
+            amount,
+            source_info.key,
+            destination_info.key
+        );
+        return Err(OrderBookError::TokenBalanceMismatch.into());
+    }
+    Ok(())
+}
+
+// Check an account is the market vault `vault`
+fn check_vault(vault_info: &AccountInfo, vault: &Pubkey) -> ProgramResult {
+    if vault_info.key != vault {
+        msg!(
+            "Expected the market vault {}, got {}",
+            vault,
+            vault_info.key
+        );
+        return Err(OrderBookError::WrongVault.into());
+    }
+    Ok(())
+}
+
+// Check a token account passed for the market holds `mint`
+fn check_token_mint(token_info: &AccountInfo, mint: &Pubkey) -> ProgramResult {
+    let holds_mint = token_info.owner == &spl_token::id()
+        && spl_token::state::Account::unpack(&token_info.try_borrow_data()?)
+            .is_ok_and(|account| &account.mint == mint);
+    if !holds_mint {
+        msg!("Token account {} must hold mint {}", token_info.key, mint);
+        return Err(OrderBookError::MintMismatch.into());
+    }
+    Ok(())
+}
+
+// Token balance of a token account
+fn token_balance(token_info: &AccountInfo) -> Result<u64, ProgramError> {
+    Ok(spl_token::state::Account::unpack(&token_info.try_borrow_data()?)?.amount)
+}
+
+// Read a sysvar from the next account if the caller passed it, otherwise
+// from the runtime, so clients may leave out a trailing sysvar account
+fn next_sysvar<S: Sysvar>(account_info_iter: &mut Iter<AccountInfo>) -> Result<S, ProgramError> {
+    match account_info_iter.next() {
+        Some(sysvar_info) => S::from_account_info(sysvar_info),
+        None => S::get(),
+    }
+}
+
+// Advance the market's sequence number and log what changed on the book
+// since `before`
+fn log_book_changes(
+    before: &OrderBook,
+    order_book: &mut OrderBook,
+    market: &Pubkey,
+) -> ProgramResult {
+    let clock = Clock::get()?;
+    order_book.seq_num += 1;
+    let event = BookChangeEvent::new(market, before, order_book, &clock);
+    order_book.book_checksum = event.checksum;
+    msg!("BookChange: {:?}", event);
+    order_book.record_lifetimes(before, &event.removed, clock.unix_timestamp);
+    for order_event in FordexEvent::order_events(&event, before, order_book) {
+        order_event.log();
+    }
+    Ok(())
+}
+
+// Process the PlaceOrder instruction
+fn process_place_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order: Order,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let trader_info = next_account_info(account_info_iter)?;
+    let clock_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let trader_token_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    // The remaining accounts are the Config PDA, the gate signer of a gated
+    // market, the market's TradeHistory account, if it keeps one, and the
+    // OpenOrders accounts of the makers this order fills, which are credited
+    // with the proceeds
+    let maker_infos = split_unpaused_config(program_id, account_info_iter.as_slice())?;
+
+    if order_book_info.owner != program_id {
+        msg!("Order book account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if trader_info.key != &order.trader || !trader_info.is_signer {
+        msg!("Order trader must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if system_program_info.key != &system_program::id() {
+        msg!("Expected the system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if token_program_info.key != &spl_token::id() {
+        msg!("Expected the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if (order.amount == 0 && order.quote_budget == 0) || order.price == 0 {
+        msg!("Order amount and price must be non-zero");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if order.quote_budget > 0
+        && (order.order_type != OrderType::Buy
+            || order.amount > 0
+            || order.is_waiting()
+            || order.min_fill_amount > 0)
+    {
+        msg!("A quote budget sizes a bid that matches on placement and leaves its amount zero");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if order.bounty > 0 && order.expires_at == 0 && !order.is_waiting() {
+        msg!("A bounty requires an order expiry or an order waiting off the book");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if order.is_stop() && order.improvement_slots > 0 {
+        msg!("A trailing stop cannot have a price improvement window");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if order.min_fill_amount > order.amount {
+        msg!("Minimum fill amount cannot exceed the order amount");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if order.peg_ticks != 0 && order.peg == PegType::None {
+        msg!("A peg offset requires a pegged order");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if order.is_dutch()
+        && (order.order_type != OrderType::Sell
+            || order.peg != PegType::None
+            || order.is_waiting()
+            || order.decay_start_price <= order.price)
+    {
+        msg!("A Dutch auction is a fixed-price ask decaying from above its floor price");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if order.is_reference()
+        && (order.is_stop()
+            || order.improvement_slots > 0
+            || order.peg != PegType::None
+            || order.linked_order_id.is_some()
+            || order.avg_price_limit > 0
+            || order.min_fill_amount > 0)
+    {
+        msg!("An order trading at settlement is a plain limit order");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    // Stamp the order with the placement slot, time and id
+    let clock = Clock::from_account_info(clock_info)?;
+    if order.is_expired(&clock) {
+        msg!("Order expiry {} is in the past", order.expires_at);
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if order.is_dutch() && order.decay_end <= clock.unix_timestamp {
+        msg!("Dutch auction decay end {} is in the past", order.decay_end);
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if order.is_reference() && order.settle_at <= clock.unix_timestamp {
+        msg!("Settlement time {} is in the past", order.settle_at);
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let mut order_book = OrderBook::from_account_info(order_book_info)?;
+    if !order_book.is_initialized {
+        msg!("Market is not initialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    let before = order_book.clone();
+    order_book.check_open()?;
+    let maker_infos = split_order_gate(order_book_info, &order_book, maker_infos)?;
+    order_book.check_features(&order)?;
+    if order.priority_bid > 0 && !order_book.priority_auction {
+        msg!("Priority bids require a market in priority auction mode");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if order_book.priority_auction && order.quote_budget > 0 {
+        msg!("A quote budget cannot wait for the priority auction");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if !order.price.is_multiple_of(order_book.tick_size)
+        || !order.decay_start_price.is_multiple_of(order_book.tick_size)
+        || !order.amount.is_multiple_of(order_book.lot_size)
+    {
+        msg!(
+            "Price must be a multiple of {} and amount a multiple of {}",
+            order_book.tick_size,
+            order_book.lot_size
+        );
+        return Err(OrderBookError::InvalidOrderSize.into());
+    }
+    if let Some(linked_id) = order.linked_order_id {
+        let linked = order_book
+            .find_order(linked_id)
+            .ok_or(OrderBookError::OrderNotFound)?;
+        if linked.trader != order.trader || linked.linked_order_id.is_some() {
+            msg!("Orders can only be linked to an unlinked order of the same trader");
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+    let (vault, mint) = match order.order_type {
+        OrderType::Buy => (order_book.quote_vault, order_book.quote_mint),
+        OrderType::Sell => (order_book.base_vault, order_book.base_mint),
+    };
+    check_vault(vault_info, &vault)?;
+    check_token_mint(trader_token_info, &mint)?;
+    let mut open_orders = vec![(
+        open_orders_info,
+        load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?,
+    )];
+    if open_orders[0].1.owner != order.trader {
+        msg!("OpenOrders account does not belong to the trader");
+        return Err(ProgramError::InvalidArgument);
+    }
+    // The beneficial owner is the OpenOrders account's, not the one in the
+    // instruction data
+    let maker_infos = split_blocklist(
+        program_id,
+        order_book_info,
+        &order_book,
+        maker_infos,
+        &[&order.trader, &open_orders[0].1.beneficial_owner],
+    )?;
+    let mut order = Order {
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+        order_id: order_book.next_order_id,
+        filled_amount: 0,
+        filled_quote: 0,
+        fees_paid: 0,
+        beneficial_owner: open_orders[0].1.beneficial_owner,
+        ..order
+    };
+    order_book.next_order_id += 1;
+    if order.quote_budget > 0 {
+        // Matching sizes the fills of a quote-sized bid, up to the base its
+        // budget would buy at one tick
+        order.amount = order_book.base_amount(order_book.tick_size, order.quote_budget);
+        if order.amount == 0 {
+            msg!("Quote budget does not buy a lot");
+            return Err(OrderBookError::InvalidOrderSize.into());
+        }
+    }
+    if let Some(linked) = order
+        .linked_order_id
+        .and_then(|linked_id| order_book.find_order_mut(linked_id))
+    {
+        linked.linked_order_id = Some(order.order_id);
+    }
+
+    if order_book.dark_pool {
+        msg!("Order placed: order_id={}", order.order_id);
+    } else {
+        msg!(
+            "Order placed: order_id={} trader={} type={:?} price={} amount={} slot={} unix_timestamp={} expires_at={} bounty={} peg={:?} peg_ticks={} linked_order_id={:?}",
+            order.order_id,
+            order.trader,
+            order.order_type,
+            order.price,
+            order.amount,
+            order.slot,
+            order.unix_timestamp,
+            order.expires_at,
+            order.bounty,
+            order.peg,
+            order.peg_ticks,
+            order.linked_order_id
+        );
+    }
+
+    // Lock the order's funds, drawing on the free balance before the
+    // trader's token account. A bid also reserves the taker fee on its full
+    // size; whatever matching does not use is released afterwards, or once
+    // an order waiting off the book is matched.
+    let order_funds = match order.quote_budget {
+        0 => order_book.locked_funds(&order)?,
+        quote_budget => quote_budget,
+    };
+    let fee_reserve = match order.order_type {
+        OrderType::Buy => order_book.fee_reserve(order_funds),
+        OrderType::Sell => 0,
+    };
+    let locked = order_funds
+        .checked_add(fee_reserve)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let balances = open_orders[0].1.market_mut(order_book_info.key)?;
+    order_book.record_placement(balances, clock.slot)?;
+    order_book.record_trader(balances, clock.epoch);
+    let free = match order.order_type {
+        OrderType::Buy => &mut balances.quote_free,
+        OrderType::Sell => &mut balances.base_free,
+    };
+    let from_free = locked.min(*free);
+    *free -= from_free;
+    balances.lock(order.order_type, locked);
+    if locked > from_free {
+        transfer_tokens(
+            token_program_info,
+            trader_token_info,
+            vault_info,
+            trader_info,
+            locked - from_free,
+            &[],
+        )?;
+    }
+
+    // Match against the book and rest any remainder. Trailing stops, orders
+    // trading at settlement and marketable orders with a price improvement
+    // window wait off the book instead, as do all marketable orders of a
+    // priority auction until their slot has passed.
+    if !order_book.is_marketable(&order, &clock) {
+        order.improvement_slots = 0;
+    } else if order_book.priority_auction && !order.is_stop() && !order.is_reference() {
+        order.improvement_slots = order.improvement_slots.max(1);
+    }
+    let (mut fills, filled_makers) = if order.is_stop() {
+        order_book.add_stop_order(order)?;
+        (vec![], vec![])
+    } else if order.is_reference() {
+        order_book.add_reference_order(order)?;
+        (vec![], vec![])
+    } else if order.improvement_slots > 0 {
+        order_book.add_pending_order(order)?;
+        (vec![], vec![])
+    } else {
+        match_taker_order(
+            &mut order_book,
+            open_orders[0].1.market_mut(order_book_info.key)?,
+            &mut order,
+            &clock,
+        )?
+    };
+    if order.amount > 0 {
+        if !order.is_waiting() {
+            order_book.add_order(Order {
+                quote_budget: 0,
+                ..order
+            })?;
+        }
+        open_orders[0]
+            .1
+            .market_mut(order_book_info.key)?
+            .order_count += 1;
+        invoke(
+            &system_instruction::transfer(
+                trader_info.key,
+                order_book_info.key,
+                order.locked_lamports(),
+            ),
+            &[
+                trader_info.clone(),
+                order_book_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+    }
+    // Charge the market's placement fee, and the priority bid of a taker
+    // waiting for the priority auction, into the crank incentives
+    let priority_bid = match order_book.priority_auction && order.improvement_slots > 0 {
+        true => order.priority_bid,
+        false => 0,
+    };
+    let placement_fee = order_book
+        .placement_fee_lamports
+        .checked_add(priority_bid)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    if placement_fee > 0 {
+        invoke(
+            &system_instruction::transfer(trader_info.key, order_book_info.key, placement_fee),
+            &[
+                trader_info.clone(),
+                order_book_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+        order_book.crank_incentive_lamports = order_book
+            .crank_incentive_lamports
+            .checked_add(placement_fee)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+
+    // An order waiting off the book keeps its fee reserve locked until it is
+    // matched or canceled, which release it
+    let fee_reserve = match order.is_waiting() {
+        true => 0,
+        false => fee_reserve,
+    };
+    settle_fills(
+        program_id,
+        order_book_info,
+        &mut order_book,
+        &mut open_orders,
+        maker_infos,
+        &order,
+        &mut fills,
+        &filled_makers,
+        fee_reserve,
+    )?;
+
+    for (info, open_orders) in &open_orders {
+        open_orders.pack_into_account(info)?;
+    }
+    log_book_changes(&before, &mut order_book, order_book_info.key)?;
+    order_book.pack_into_account(order_book_info)?;
+    set_return_data(&FillPreview::new(&order_book, &order, &fills)?.pack());
+
+    Ok(())
+}
+
+// Define one market of a RouteOrder with its group of accounts
+struct RoutedMarket<'a, 'b> {
+    order_book_info: &'b AccountInfo<'a>,
+    vault_info: &'b AccountInfo<'a>,
+    maker_infos: &'b [AccountInfo<'a>],
+    before: OrderBook,
+    order_book: OrderBook,
+    // The order id the route holds at the market once it first fills there
+    order_id: Option<u64>,
+}
+
+// Process the RouteOrder instruction
+fn process_route_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    side: OrderType,
+    amount: u64,
+    limit_price: u64,
+    min_fill_amount: u64,
+    market_account_counts: Vec<u8>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let trader_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let trader_token_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    // The remaining accounts are the Config PDA and then the accounts of
+    // each market in turn: its order book, its vault for the order's side,
+    // its feeds and the OpenOrders accounts of the makers filled there
+    let mut market_accounts = split_unpaused_config(program_id, account_info_iter.as_slice())?;
+
+    if !trader_info.is_signer {
+        msg!("Order trader must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if system_program_info.key != &system_program::id() {
+        msg!("Expected the system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if token_program_info.key != &spl_token::id() {
+        msg!("Expected the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if amount == 0 || limit_price == 0 {
+        msg!("Order amount and price must be non-zero");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if min_fill_amount > amount {
+        msg!("Minimum fill amount cannot exceed the order amount");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if market_account_counts.is_empty()
+        || market_account_counts.len() > OrderBookInstruction::MAX_ROUTE_MARKETS
+    {
+        msg!(
+            "An order is routed across 1 to {} markets",
+            OrderBookInstruction::MAX_ROUTE_MARKETS
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    // Load the markets, which must trade the same pair in the same lots
+    let mut markets: Vec<RoutedMarket> = Vec::with_capacity(market_account_counts.len());
+    for count in market_account_counts {
+        let count = count as usize;
+        if count < 2 || count > market_accounts.len() {
+            msg!("Each routed market needs its order book and vault accounts");
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let (group, rest) = market_accounts.split_at(count);
+        market_accounts = rest;
+        let order_book_info = &group[0];
+        let order_book = load_order_book(program_id, order_book_info)?;
+        order_book.check_open()?;
+        if order_book.blocklist != Pubkey::default() {
+            msg!(
+                "Market {} enforces a blocklist and is not routed to",
+                order_book_info.key
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+        if order_book.order_gate != Pubkey::default() {
+            msg!(
+                "Market {} only takes orders through its gate program",
+                order_book_info.key
+            );
+            return Err(OrderBookError::OrderGateRequired.into());
+        }
+        if order_book.priority_auction {
+            msg!(
+                "Market {} orders its takers by priority auction",
+                order_book_info.key
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+        if markets
+            .iter()
+            .any(|market| market.order_book_info.key == order_book_info.key)
+        {
+            msg!("Market {} is routed to more than once", order_book_info.key);
+            return Err(ProgramError::InvalidArgument);
+        }
+        if let Some(first) = markets.first() {
+            let first = &first.order_book;
+            if order_book.base_mint != first.base_mint
+                || order_book.quote_mint != first.quote_mint
+                || order_book.lot_size != first.lot_size
+                || order_book.whole_units != first.whole_units
+            {
+                msg!("Routed markets must trade the same pair in the same lot size");
+                return Err(ProgramError::InvalidArgument);
+            }
+        }
+        let (vault, mint) = match side {
+            OrderType::Buy => (order_book.quote_vault, order_book.quote_mint),
+            OrderType::Sell => (order_book.base_vault, order_book.base_mint),
+        };
+        check_vault(&group[1], &vault)?;
+        check_token_mint(trader_token_info, &mint)?;
+        markets.push(RoutedMarket {
+            order_book_info,
+            vault_info: &group[1],
+            maker_infos: &group[2..],
+            before: order_book.clone(),
+            order_book,
+            order_id: None,
+        });
+    }
+    let lot_size = markets[0].order_book.lot_size;
+    if !amount.is_multiple_of(lot_size) {
+        msg!("Amount must be a multiple of {}", lot_size);
+        return Err(OrderBookError::InvalidOrderSize.into());
+    }
+    let mut open_orders = vec![(
+        open_orders_info,
+        load_open_orders(
+            program_id,
+            open_orders_info,
+            &markets[0].order_book.quote_mint,
+        )?,
+    )];
+    if &open_orders[0].1.owner != trader_info.key {
+        msg!("OpenOrders account does not belong to the trader");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let clock = Clock::get()?;
+
+    // Fill from the market quoting the best price, taking its liquidity
+    // down to the next best market's price before comparing again. A market
+    // that fills nothing, say at a self-trade, leaves the route.
+    let mut remaining = amount;
+    let mut excluded = vec![false; markets.len()];
+    while remaining > 0 {
+        let mut quotes: Vec<(usize, u64)> = markets
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !excluded[*index])
+            .filter_map(|(index, market)| {
+                let (_, price) = market.order_book.best_order(side.opposite(), &clock)?;
+                Some((index, price))
+            })
+            .filter(|(_, price)| match side {
+                OrderType::Buy => *price <= limit_price,
+                OrderType::Sell => *price >= limit_price,
+            })
+            .collect();
+        match side {
+            OrderType::Buy => quotes.sort_by_key(|(_, price)| *price),
+            OrderType::Sell => quotes.sort_by_key(|(_, price)| std::cmp::Reverse(*price)),
+        }
+        let Some(&(index, _)) = quotes.first() else {
+            break;
+        };
+        let bound = quotes.get(1).map_or(limit_price, |(_, price)| *price);
+        let market = &mut markets[index];
+        let order_book = &mut market.order_book;
+        // The market's best price lies on its tick, so rounding the bound
+        // toward the order's side keeps it marketable
+        let tick_size = order_book.tick_size;
+        let price = match side {
+            OrderType::Buy => bound - bound % tick_size,
+            OrderType::Sell => bound
+                .div_ceil(tick_size)
+                .checked_mul(tick_size)
+                .ok_or(ProgramError::ArithmeticOverflow)?,
+        };
+        // A market the route returns to carries on with the order it holds
+        // there, reopening its closed entry, rather than taking a new order
+        // id and placement fee
+        let first_visit = market.order_id.is_none();
+        let order_id = *market.order_id.get_or_insert_with(|| {
+            order_book.next_order_id += 1;
+            order_book.next_order_id - 1
+        });
+        let reopened = if first_visit {
+            None
+        } else {
+            order_book.reopen_closed(order_id)
+        };
+        let mut order = Order {
+            trader: *trader_info.key,
+            amount: remaining,
+            price,
+            order_type: side,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+            order_id,
+            expires_at: 0,
+            bounty: 0,
+            peg: PegType::None,
+            peg_ticks: 0,
+            linked_order_id: None,
+            trail_ticks: 0,
+            trigger_price: 0,
+            improvement_slots: 0,
+            avg_price_limit: 0,
+            min_fill_amount: 0,
+            beneficial_owner: open_orders[0].1.beneficial_owner,
+            decay_start_price: 0,
+            decay_end: 0,
+            quote_budget: 0,
+            expiry_unit: ExpiryUnit::UnixTime,
+            filled_amount: reopened.map_or(0, |closed| closed.filled_amount),
+            filled_quote: reopened.map_or(0, |closed| closed.filled_quote),
+            fees_paid: reopened.map_or(0, |closed| closed.fees_paid),
+            tag: 0,
+            priority_bid: 0,
+            settle_at: 0,
+        };
+        let filled_before = order.filled_amount;
+
+        // Lock the order's funds and fee reserve as PlaceOrder does, drawing
+        // on the free balance before the trader's token account
+        let order_funds = order_book.locked_funds(&order)?;
+        let fee_reserve = match side {
+            OrderType::Buy => order_book.fee_reserve(order_funds),
+            OrderType::Sell => 0,
+        };
+        let locked = order_funds
+            .checked_add(fee_reserve)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        let balances = open_orders[0].1.market_mut(market.order_book_info.key)?;
+        if first_visit {
+            order_book.record_placement(balances, clock.slot)?;
+        }
+        order_book.record_trader(balances, clock.epoch);
+        let free = match side {
+            OrderType::Buy => &mut balances.quote_free,
+            OrderType::Sell => &mut balances.base_free,
+        };
+        let from_free = locked.min(*free);
+        *free -= from_free;
+        balances.lock(side, locked);
+        if locked > from_free {
+            transfer_tokens(
+                token_program_info,
+                trader_token_info,
+                market.vault_info,
+                trader_info,
+                locked - from_free,
+                &[],
+            )?;
+        }
+
+        // Match, then cancel whatever stopped at the bound like an
+        // immediate-or-cancel order
+        let (mut fills, filled_makers) =
+            match_taker_order(order_book, balances, &mut order, &clock)?;
+        if order.amount > 0 {
+            balances.unlock(side, order_book.locked_funds(&order)?);
+            order.amount = 0;
+            order_book.record_closed(&order, OrderStatus::Canceled);
+        }
+        if first_visit && order_book.placement_fee_lamports > 0 {
+            invoke(
+                &system_instruction::transfer(
+                    trader_info.key,
+                    market.order_book_info.key,
+                    order_book.placement_fee_lamports,
+                ),
+                &[
+                    trader_info.clone(),
+                    market.order_book_info.clone(),
+                    system_program_info.clone(),
+                ],
+            )?;
+            order_book.crank_incentive_lamports = order_book
+                .crank_incentive_lamports
+                .checked_add(order_book.placement_fee_lamports)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
+        settle_fills(
+            program_id,
+            market.order_book_info,
+            order_book,
+            &mut open_orders,
+            market.maker_infos,
+            &order,
+            &mut fills,
+            &filled_makers,
+            fee_reserve,
+        )?;
+        let filled = order.filled_amount - filled_before;
+        msg!(
+            "Routed order_id={} market={} price={} filled={}",
+            order.order_id,
+            market.order_book_info.key,
+            price,
+            filled
+        );
+        if filled == 0 {
+            excluded[index] = true;
+        }
+        remaining -= filled;
+    }
+
+    let filled = amount - remaining;
+    if filled < min_fill_amount {
+        msg!(
+            "Only {} of the minimum fill amount {} filled across the routed markets",
+            filled,
+            min_fill_amount
+        );
+        return Err(OrderBookError::RouteUnderfilled.into());
+    }
+    for (info, open_orders) in &open_orders {
+        open_orders.pack_into_account(info)?;
+    }
+    for market in markets
+        .iter_mut()
+        .filter(|market| market.order_id.is_some())
+    {
+        log_book_changes(
+            &market.before,
+            &mut market.order_book,
+            market.order_book_info.key,
+        )?;
+        market
+            .order_book
+            .pack_into_account(market.order_book_info)?;
+    }
+    msg!("Route filled {} of {}", filled, amount);
+
+    Ok(())
+}
+
+// Charge fees on and credit both sides of the fills of a taker order, whose
+// owner's OpenOrders account is the first in `open_orders`, then cancel the
+// orders linked to filled orders and credit the deposits of maker orders that
+// left the book
+#[allow(clippy::too_many_arguments)]
+fn settle_fills<'a, 'b>(
+    program_id: &Pubkey,
+    order_book_info: &'b AccountInfo<'a>,
+    order_book: &mut OrderBook,
+    open_orders: &mut Vec<(&'b AccountInfo<'a>, OpenOrders)>,
+    maker_infos: &'b [AccountInfo<'a>],
+    order: &Order,
+    fills: &mut [FillEvent],
+    filled_makers: &[Order],
+    fee_reserve: u64,
+) -> ProgramResult {
+    let (trade_history_info, candle_infos, maker_infos) =
+        split_market_feeds(order_book, maker_infos)?;
+    // A maker's OpenOrders account is passed once and never as the taker's.
+    // Makers loaded by an earlier pass of a route are reused, not reloaded.
+    check_distinct_accounts(std::iter::once(open_orders[0].0).chain(maker_infos.iter()))?;
+    order_book.roll_fee_epoch(order.unix_timestamp);
+    // Charge the taker fee, less any backstop subsidy, and credit both sides
+    // of every fill
+    let mut fees = 0;
+    let mut taker_fees = 0;
+    for fill in fills.iter_mut() {
+        let quote = order_book.quote_amount(fill.price, fill.amount)?;
+        let fee = order_book.taker_fee(order.order_type, quote);
+        fill.fee_subsidy = order_book.fee_subsidy(&fill.maker, fee);
+        order_book.incentive_balance -= fill.fee_subsidy;
+        fill.taker_fee_bps = order_book.taker_fee_bps(order.order_type);
+        fill.taker_fee = fee - fill.fee_subsidy;
+        fees += fee;
+        taker_fees += fill.taker_fee;
+        msg!("Fill: {:?}", fill);
+        FordexEvent::Fill {
+            market: *order_book_info.key,
+            fill: *fill,
+        }
+        .log();
+        // A quote-sized bid locked its budget rather than its limit price
+        let locked_quote = match order.quote_budget {
+            0 => order_book.quote_amount(order.price, fill.amount)?,
+            _ => quote,
+        };
+        let taker_quote = match order.order_type {
+            OrderType::Buy => quote,
+            OrderType::Sell => quote - fill.taker_fee,
+        };
+        open_orders[0].1.market_mut(order_book_info.key)?.fill(
+            order.order_type,
+            fill.amount,
+            taker_quote,
+            locked_quote,
+        );
+        let maker = find_open_orders(
+            program_id,
+            open_orders,
+            maker_infos,
+            &fill.maker,
+            &order_book.quote_mint,
+        )?;
+        // A pegged maker bid locked funds at its limit rather than the fill
+        // price
+        let maker_price = order_book
+            .find_order(fill.maker_order_id)
+            .or_else(|| {
+                filled_makers
+                    .iter()
+                    .find(|maker| maker.order_id == fill.maker_order_id)
+            })
+            .map_or(fill.price, |maker| maker.price);
+        let maker_locked_quote = order_book.quote_amount(maker_price, fill.amount)?;
+        let balances = open_orders[maker].1.market_mut(order_book_info.key)?;
+        balances.fill(
+            order.order_type.opposite(),
+            fill.amount,
+            quote,
+            maker_locked_quote,
+        );
+        if fill.maker_remaining == 0 {
+            balances.order_count -= 1;
+        }
+        order_book.record_maker_volume(balances, quote);
+    }
+    order_book.accrue_fees(fees);
+    order_book.add_fees_paid(order.order_id, taker_fees);
+    if fee_reserve > 0 {
+        let balances = open_orders[0].1.market_mut(order_book_info.key)?;
+        balances.quote_locked -= fee_reserve;
+        balances.quote_free += fee_reserve - taker_fees;
+    }
+
+    // Cancel the orders linked one-cancels-other to the orders that filled
+    let mut filled_orders: Vec<Order> = fills
+        .iter()
+        .filter_map(|fill| {
+            order_book
+                .find_order(fill.maker_order_id)
+                .or_else(|| {
+                    filled_makers
+                        .iter()
+                        .find(|maker| maker.order_id == fill.maker_order_id)
+                })
+                .copied()
+        })
+        .collect();
+    if !fills.is_empty() {
+        filled_orders.push(*order);
+    }
+    cancel_linked_orders(
+        program_id,
+        order_book_info,
+        order_book,
+        open_orders,
+        maker_infos,
+        &filled_orders,
+    )?;
+
+    // Credit the deposits and bounties of maker orders that left the book
+    for maker in filled_makers {
+        let (maker_info, _) = open_orders
+            .iter()
+            .find(|(_, open_orders)| open_orders.owner == maker.trader)
+            .ok_or(OrderBookError::MakerAccountMissing)?;
+        transfer_lamports(order_book_info, maker_info, maker.locked_lamports())?;
+    }
+    record_fills(
+        order_book,
+        trade_history_info,
+        candle_infos,
+        fills,
+        order.unix_timestamp,
+    )
+}
+
+// Cancel the orders linked one-cancels-other to orders that filled,
+// releasing their funds and deposits to their owners' OpenOrders accounts
+fn cancel_linked_orders<'a, 'b>(
+    program_id: &Pubkey,
+    order_book_info: &'b AccountInfo<'a>,
+    order_book: &mut OrderBook,
+    open_orders: &mut Vec<(&'b AccountInfo<'a>, OpenOrders)>,
+    owner_infos: &'b [AccountInfo<'a>],
+    filled_orders: &[Order],
+) -> ProgramResult {
+    for filled in filled_orders {
+        let Some(linked) = order_book.cancel_linked_order(filled) else {
+            continue;
+        };
+        let owner = find_open_orders(
+            program_id,
+            open_orders,
+            owner_infos,
+            &linked.trader,
+            &order_book.quote_mint,
+        )?;
+        let balances = open_orders[owner].1.market_mut(order_book_info.key)?;
+        balances.unlock(linked.order_type, order_book.reserved_funds(&linked)?);
+        balances.order_count -= 1;
+        transfer_lamports(
+            order_book_info,
+            open_orders[owner].0,
+            linked.locked_lamports(),
+        )?;
+        msg!("Linked order canceled: {:?}", linked);
+    }
+    Ok(())
+}
+
+// Record fills made at `unix_timestamp` as the market's last trade and in
+// its TradeHistory and CandleSeries accounts
+fn record_fills(
+    order_book: &mut OrderBook,
+    trade_history_info: Option<&AccountInfo>,
+    candle_infos: &[AccountInfo],
+    fills: &[FillEvent],
+    unix_timestamp: UnixTimestamp,
+) -> ProgramResult {
+    for fill in fills {
+        order_book.record_trade(fill.price);
+    }
+    if let Some(trade_history_info) = trade_history_info {
+        let mut data = trade_history_info.try_borrow_mut_data()?;
+        let mut history = TradeHistory::unpack(&data)?;
+        for fill in fills {
+            let record = TradeRecord {
+                price: fill.price,
+                amount: fill.amount,
+                taker_side: fill.taker_side,
+                unix_timestamp,
+            };
+            history.append(&mut data, &record);
+        }
+    }
+    for candle_info in candle_infos {
+        let mut data = candle_info.try_borrow_mut_data()?;
+        let mut series = CandleSeries::unpack(&data)?;
+        for fill in fills {
+            series.record(&mut data, fill.price, fill.amount, unix_timestamp)?;
+        }
+    }
+    Ok(())
+}
+
+// Split the market's TradeHistory and CandleSeries accounts, which come first
+// in that order when the market keeps them, off the trailing accounts of an
+// instruction that matches orders
+#[allow(clippy::type_complexity)]
+fn split_market_feeds<'a, 'b>(
+    order_book: &OrderBook,
+    accounts: &'b [AccountInfo<'a>],
+) -> Result<
+    (
+        Option<&'b AccountInfo<'a>>,
+        &'b [AccountInfo<'a>],
+        &'b [AccountInfo<'a>],
+    ),
+    ProgramError,
+> {
+    let (trade_history_info, accounts) = if order_book.trade_history == Pubkey::default() {
+        (None, accounts)
+    } else {
+        match accounts.split_first() {
+            Some((info, rest)) if info.key == &order_book.trade_history => (Some(info), rest),
+            _ => {
+                msg!(
+                    "Expected the market's TradeHistory account {}",
+                    order_book.trade_history
+                );
+                return Err(ProgramError::NotEnoughAccountKeys);
+            }
+        }
+    };
+    let count = order_book.candle_series.len();
+    let candle_infos = accounts.get(..count).unwrap_or_default();
+    if candle_infos.len() < count
+        || candle_infos
+            .iter()
+            .zip(&order_book.candle_series)
+            .any(|(info, key)| info.key != key)
+    {
+        msg!("Expected the market's CandleSeries accounts in order");
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    Ok((trade_history_info, candle_infos, &accounts[count..]))
+}
+
+// Index of the OpenOrders account belonging to `owner`, loading it from
+// `maker_infos` on first use
+fn find_open_orders<'a, 'b>(
+    program_id: &Pubkey,
+    open_orders: &mut Vec<(&'b AccountInfo<'a>, OpenOrders)>,
+    maker_infos: &'b [AccountInfo<'a>],
+    owner: &Pubkey,
+    quote_mint: &Pubkey,
+) -> Result<usize, ProgramError> {
+    if let Some(index) = open_orders
+        .iter()
+        .position(|(_, open_orders)| &open_orders.owner == owner)
+    {
+        return Ok(index);
+    }
+    for maker_info in maker_infos {
+        if open_orders
+            .iter()
+            .any(|(info, _)| info.key == maker_info.key)
+        {
+            continue;
+        }
+        let maker_open_orders = load_open_orders(program_id, maker_info, quote_mint)?;
+        if &maker_open_orders.owner == owner {
+            open_orders.push((maker_info, maker_open_orders));
+            return Ok(open_orders.len() - 1);
+        }
+    }
+    msg!("OpenOrders account of maker {} is required", owner);
+    Err(OrderBookError::MakerAccountMissing.into())
+}
+
+// Return the funds locked by an order removed from the book unfilled to the
+// owner's OpenOrders account
+fn release_order(
+    program_id: &Pubkey,
+    order_book_info: &AccountInfo,
+    order_book: &OrderBook,
+    open_orders_info: &AccountInfo,
+    order: &Order,
+) -> ProgramResult {
+    let mut open_orders = load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?;
+    if open_orders.owner != order.trader {
+        msg!("Expected the OpenOrders account of {}", order.trader);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let balances = open_orders.market_mut(order_book_info.key)?;
+    balances.unlock(order.order_type, order_book.reserved_funds(order)?);
+    balances.order_count -= 1;
+    open_orders.pack_into_account(open_orders_info)
+}
+
+// Process the CancelOrder instruction
+fn process_cancel_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order_id: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let trader_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+
+    if order_book_info.owner != program_id {
+        msg!("Order book account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut order_book = OrderBook::from_account_info(order_book_info)?;
+    let order = *order_book
+        .find_order(order_id)
+        .ok_or(OrderBookError::OrderNotFound)?;
+    if trader_info.key != &order.trader || !trader_info.is_signer {
+        msg!("Order owner must sign the cancellation");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let before = order_book.clone();
+    order_book.remove_order(order_id);
+    log_book_changes(&before, &mut order_book, order_book_info.key)?;
+    order_book.pack_into_account(order_book_info)?;
+    release_order(
+        program_id,
+        order_book_info,
+        &order_book,
+        open_orders_info,
+        &order,
+    )?;
+    transfer_lamports(order_book_info, trader_info, order.locked_lamports())?;
+
+    msg!("Order canceled: {:?}", order);
+
+    Ok(())
+}
+
+// Process the CancelOrdersByPrice instruction
+fn process_cancel_orders_by_price(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    side: OrderType,
+    limit_price: u64,
+    max_count: u16,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let trader_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+
+    if !trader_info.is_signer {
+        msg!("Order owner must sign the cancellation");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    let mut open_orders = load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?;
+    if &open_orders.owner != trader_info.key {
+        msg!("Expected the OpenOrders account of {}", trader_info.key);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let before = order_book.clone();
+    let mut canceled = vec![];
+    for order_id in order_book
+        .orders_through(trader_info.key, side, limit_price)
+        .into_iter()
+        .take(max_count as usize)
+    {
+        if let Some(order) = order_book.remove_order(order_id) {
+            canceled.push(order);
+        }
+    }
+    if canceled.is_empty() {
+        msg!("No orders to cancel at or through {}", limit_price);
+        return Ok(());
+    }
+    log_book_changes(&before, &mut order_book, order_book_info.key)?;
+    order_book.pack_into_account(order_book_info)?;
+
+    let balances = open_orders.market_mut(order_book_info.key)?;
+    let mut lamports = 0u64;
+    for order in &canceled {
+        balances.unlock(side, order_book.reserved_funds(order)?);
+        balances.order_count -= 1;
+        lamports = lamports
+            .checked_add(order.locked_lamports())
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+    open_orders.pack_into_account(open_orders_info)?;
+    transfer_lamports(order_book_info, trader_info, lamports)?;
+
+    msg!(
+        "Orders canceled at or through {}: {:?}",
+        limit_price,
+        canceled
+            .iter()
+            .map(|order| order.order_id)
+            .collect::<Vec<_>>()
+    );
+
+    Ok(())
+}
+
+// Process the PruneOrder instruction
+fn process_prune_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order_id: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let pruner_info = next_account_info(account_info_iter)?;
+    let clock_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+
+    if order_book_info.owner != program_id {
+        msg!("Order book account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let clock = Clock::from_account_info(clock_info)?;
+    let mut order_book = OrderBook::from_account_info(order_book_info)?;
+    let order = *order_book
+        .find_order(order_id)
+        .ok_or(OrderBookError::OrderNotFound)?;
+    if order_book.prune_after_slots == 0 {
+        msg!("Market does not let resting orders be pruned");
+        return Err(OrderBookError::OrderNotAbandoned.into());
+    }
+    if clock.slot < order.slot.saturating_add(order_book.prune_after_slots) {
+        msg!("Order {} is not abandoned yet", order_id);
+        return Err(OrderBookError::OrderNotAbandoned.into());
+    }
+
+    let before = order_book.clone();
+    order_book.remove_order(order_id);
+    log_book_changes(&before, &mut order_book, order_book_info.key)?;
+    order_book.pack_into_account(order_book_info)?;
+    release_order(
+        program_id,
+        order_book_info,
+        &order_book,
+        open_orders_info,
+        &order,
+    )?;
+    transfer_lamports(order_book_info, pruner_info, order.locked_lamports())?;
+
+    msg!("Order pruned by {}: {:?}", pruner_info.key, order);
+
+    Ok(())
+}
+
+// Process the CancelExpiredOrder instruction
+fn process_cancel_expired_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order_id: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let keeper_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+
+    if order_book_info.owner != program_id {
+        msg!("Order book account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    let mut order_book = OrderBook::from_account_info(order_book_info)?;
+    let order = *order_book
+        .find_order(order_id)
+        .ok_or(OrderBookError::OrderNotFound)?;
+    let open_orders = load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?;
+    let stale = open_orders.owner == order.trader && open_orders.is_past_cutoff(&order);
+    if !order.is_expired(&clock) && !stale {
+        msg!(
+            "Order {} has not expired nor was placed after its owner's cutoff",
+            order_id
+        );
+        return Err(OrderBookError::OrderNotExpired.into());
+    }
+
+    let before = order_book.clone();
+    order_book.remove_order(order_id);
+    let reward = order_book.crank_reward();
+    order_book.crank_incentive_lamports -= reward;
+    log_book_changes(&before, &mut order_book, order_book_info.key)?;
+    order_book.pack_into_account(order_book_info)?;
+    release_order(
+        program_id,
+        order_book_info,
+        &order_book,
+        open_orders_info,
+        &order,
+    )?;
+    transfer_lamports(order_book_info, keeper_info, order.bounty + reward)?;
+    transfer_lamports(
+        order_book_info,
+        open_orders_info,
+        OrderBook::ORDER_DEPOSIT_LAMPORTS,
+    )?;
+
+    msg!(
+        "Expired or stale order canceled by {} for a bounty of {} and a reward of {}: {:?}",
+        keeper_info.key,
+        order.bounty,
+        reward,
+        order
+    );
+
+    Ok(())
+}
+
+// Process the PruneExpiredOrders instruction
+fn process_prune_expired_orders(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_orders: u8,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let keeper_info = next_account_info(account_info_iter)?;
+    // The remaining accounts are the keeper's KeeperBond, if it has one, and
+    // the OpenOrders accounts of the owners of the expired orders
+    let (bonded_lamports, owner_infos) =
+        split_keeper_bond(program_id, keeper_info, account_info_iter.as_slice())?;
+
+    let clock = Clock::get()?;
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    let expired = order_book.expired_entries(&clock).collect::<Vec<_>>();
+    if expired.is_empty() {
+        msg!("No resting order has expired");
+        return Ok(());
+    }
+    // During a backlog, bonded keepers get the freshly expired orders first
+    let claim_delay = match expired.len() >= KeeperBond::BACKLOG_ORDERS {
+        true => KeeperBond::claim_delay(bonded_lamports),
+        false => 0,
+    };
+    let order_ids = expired
+        .iter()
+        .filter(|entry| entry.expired_for(&clock) >= claim_delay)
+        .take(max_orders as usize)
+        .map(|entry| entry.order_id)
+        .collect::<Vec<_>>();
+    if order_ids.is_empty() {
+        msg!(
+            "Expired orders are reserved for {} slots after expiring to keepers bonding more than {} lamports",
+            claim_delay,
+            bonded_lamports
+        );
+        return Err(OrderBookError::KeeperPriorityWindow.into());
+    }
+
+    let before = order_book.clone();
+    let mut open_orders = vec![];
+    let mut payout = 0u64;
+    for order_id in &order_ids {
+        let order = order_book
+            .remove_order(*order_id)
+            .ok_or(OrderBookError::OrderNotFound)?;
+        let owner = find_open_orders(
+            program_id,
+            &mut open_orders,
+            owner_infos,
+            &order.trader,
+            &order_book.quote_mint,
+        )?;
+        let balances = open_orders[owner].1.market_mut(order_book_info.key)?;
+        balances.unlock(order.order_type, order_book.reserved_funds(&order)?);
+        balances.order_count -= 1;
+        transfer_lamports(
+            order_book_info,
+            open_orders[owner].0,
+            OrderBook::ORDER_DEPOSIT_LAMPORTS,
+        )?;
+        let reward = order_book.crank_reward();
+        order_book.crank_incentive_lamports -= reward;
+        payout = payout
+            .checked_add(order.bounty + reward)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+    for (info, open_orders) in &open_orders {
+        open_orders.pack_into_account(info)?;
+    }
+    log_book_changes(&before, &mut order_book, order_book_info.key)?;
+    order_book.pack_into_account(order_book_info)?;
+    transfer_lamports(order_book_info, keeper_info, payout)?;
+
+    msg!(
+        "Expired orders pruned by {} for {} lamports: {:?}",
+        keeper_info.key,
+        payout,
+        order_ids
+    );
+
+    Ok(())
+}
+
+// Process the TriggerStopOrder instruction
+fn process_trigger_stop_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order_id: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let keeper_info = next_account_info(account_info_iter)?;
+    let clock_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+    // The remaining accounts are the Config PDA, the market's TradeHistory
+    // account, if it keeps one, and the OpenOrders accounts of the makers the
+    // triggered order fills
+    let maker_infos = split_unpaused_config(program_id, account_info_iter.as_slice())?;
+
+    let clock = Clock::from_account_info(clock_info)?;
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    let index = order_book
+        .stop_orders
+        .iter()
+        .position(|stop| stop.order_id == order_id)
+        .ok_or(OrderBookError::OrderNotFound)?;
+    let stop = order_book.stop_orders[index];
+    if stop.is_expired(&clock) {
+        msg!("Stop order {} has expired", order_id);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if !order_book.is_triggered(&stop) {
+        msg!(
+            "Last trade price {} has not reached the trigger price {}",
+            order_book.last_trade_price,
+            stop.trigger_price
+        );
+        return Err(OrderBookError::StopNotTriggered.into());
+    }
+    order_book.stop_orders.remove(index);
+    execute_waiting_order(
+        program_id,
+        order_book_info,
+        keeper_info,
+        &clock,
+        open_orders_info,
+        maker_infos,
+        &mut order_book,
+        stop,
+    )?;
+
+    msg!(
+        "Stop order triggered by {} at last trade price {}: {:?}",
+        keeper_info.key,
+        order_book.last_trade_price,
+        stop
+    );
+
+    Ok(())
+}
+
+// Process the SettleAuction instruction
+fn process_settle_auction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order_id: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let keeper_info = next_account_info(account_info_iter)?;
+    let clock_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+    // The remaining accounts are the Config PDA, the market's TradeHistory
+    // account, if it keeps one, and the OpenOrders accounts of the makers the
+    // order fills
+    let maker_infos = split_unpaused_config(program_id, account_info_iter.as_slice())?;
+
+    let clock = Clock::from_account_info(clock_info)?;
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    let index = order_book
+        .pending_orders
+        .iter()
+        .position(|pending| pending.order_id == order_id)
+        .ok_or(OrderBookError::OrderNotFound)?;
+    let pending = order_book.pending_orders[index];
+    if pending.is_expired(&clock) {
+        msg!("Order {} has expired", order_id);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let end_slot = pending.slot.saturating_add(pending.improvement_slots);
+    if clock.slot < end_slot {
+        msg!(
+            "Price improvement window of order {} ends at slot {}",
+            order_id,
+            end_slot
+        );
+        return Err(OrderBookError::AuctionNotEnded.into());
+    }
+    if order_book.priority_auction {
+        let next = order_book
+            .next_priority_taker(&clock)
+            .map(|next| next.order_id);
+        if next != Some(order_id) {
+            msg!("Order {:?} takes priority over order {}", next, order_id);
+            return Err(OrderBookError::PriorityTakerWaiting.into());
+        }
+    }
+
+    order_book.pending_orders.remove(index);
+    execute_waiting_order(
+        program_id,
+        order_book_info,
+        keeper_info,
+        &clock,
+        open_orders_info,
+        maker_infos,
+        &mut order_book,
+        pending,
+    )?;
+
+    msg!("Auction settled by {}: {:?}", keeper_info.key, pending);
+
+    Ok(())
+}
+
+// Process the SettleReferenceOrders instruction
+fn process_settle_reference_orders(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    settle_at: UnixTimestamp,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let keeper_info = next_account_info(account_info_iter)?;
+    let price_oracle_info = next_account_info(account_info_iter)?;
+    // The remaining accounts are the Config PDA and the OpenOrders accounts
+    // of the owners of the orders trading at `settle_at`
+    let owner_infos = split_unpaused_config(program_id, account_info_iter.as_slice())?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if price_oracle_info.owner != program_id
+        || price_oracle_info.key != &PriceOracle::find_address(program_id, order_book_info.key).0
+    {
+        msg!("Expected the PriceOracle PDA of the market");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    check_account_size(price_oracle_info, PriceOracle::LEN)?;
+    let price_oracle = PriceOracle::unpack(&price_oracle_info.try_borrow_data()?)?;
+    if price_oracle.updated_at < settle_at {
+        msg!(
+            "Reference price for {} is not fixed; the PriceOracle was last updated at {}",
+            settle_at,
+            price_oracle.updated_at
+        );
+        return Err(OrderBookError::ReferencePriceNotFixed.into());
+    }
+    // Orders whose reference was missed are canceled without trading
+    let reference_end = settle_at.saturating_add(OrderBook::REFERENCE_WINDOW);
+    let price = Some(price_oracle.twap)
+        .filter(|twap| *twap > 0 && price_oracle.updated_at <= reference_end);
+
+    let before = order_book.clone();
+    let (fills, batch) = order_book.match_reference_orders(settle_at, price);
+    if batch.is_empty() {
+        msg!("No order trades at settlement at {}", settle_at);
+        return Err(OrderBookError::OrderNotFound.into());
+    }
+
+    // Settle both sides of every fill at the reference price, without fees
+    let mut open_orders = vec![];
+    for fill in &fills {
+        msg!("Fill: {:?}", fill);
+        FordexEvent::Fill {
+            market: *order_book_info.key,
+            fill: *fill,
+        }
+        .log();
+        let quote = order_book.quote_amount(fill.price, fill.amount)?;
+        for order_id in [fill.taker_order_id, fill.maker_order_id] {
+            let order = batch
+                .iter()
+                .find(|order| order.order_id == order_id)
+                .ok_or(OrderBookError::OrderNotFound)?;
+            let owner = find_open_orders(
+                program_id,
+                &mut open_orders,
+                owner_infos,
+                &order.trader,
+                &order_book.quote_mint,
+            )?;
+            // A bid locked funds at its limit rather than the reference price
+            let locked_quote = order_book.quote_amount(order.price, fill.amount)?;
+            open_orders[owner].1.market_mut(order_book_info.key)?.fill(
+                order.order_type,
+                fill.amount,
+                quote,
+                locked_quote,
+            );
+        }
+    }
+
+    // Release what the orders did not fill, along with a bid's fee reserve,
+    // and pay their deposits back and their bounties to the keeper
+    let mut bounties = 0u64;
+    for order in &batch {
+        let owner = find_open_orders(
+            program_id,
+            &mut open_orders,
+            owner_infos,
+            &order.trader,
+            &order_book.quote_mint,
+        )?;
+        let placed = Order {
+            amount: order.amount + order.filled_amount,
+            ..*order
+        };
+        let fee_reserve = order_book.reserved_funds(&placed)? - order_book.locked_funds(&placed)?;
+        let balances = open_orders[owner].1.market_mut(order_book_info.key)?;
+        balances.unlock(
+            order.order_type,
+            order_book.locked_funds(order)? + fee_reserve,
+        );
+        balances.order_count -= 1;
+        transfer_lamports(
+            order_book_info,
+            open_orders[owner].0,
+            OrderBook::ORDER_DEPOSIT_LAMPORTS,
+        )?;
+        bounties += order.bounty;
+    }
+    transfer_lamports(order_book_info, keeper_info, bounties)?;
+
+    for (info, open_orders) in &open_orders {
+        open_orders.pack_into_account(info)?;
+    }
+    log_book_changes(&before, &mut order_book, order_book_info.key)?;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!(
+        "Orders trading at {} settled by {} at reference price {:?} with {} fills",
+        settle_at,
+        keeper_info.key,
+        price,
+        fills.len()
+    );
+
+    Ok(())
+}
+
+// Match an order taken off the stop or pending list as a plain limit order
+// entering the book now, paying its bounty to the keeper. Its funds and fee
+// reserve are already locked and its deposit is already held by the book.
+#[allow(clippy::too_many_arguments)]
+fn execute_waiting_order<'a>(
+    program_id: &Pubkey,
+    order_book_info: &AccountInfo<'a>,
+    keeper_info: &AccountInfo<'a>,
+    clock: &Clock,
+    open_orders_info: &AccountInfo<'a>,
+    maker_infos: &[AccountInfo<'a>],
+    order_book: &mut OrderBook,
+    waiting: Order,
+) -> ProgramResult {
+    let before = order_book.clone();
+    let mut open_orders = vec![(
+        open_orders_info,
+        load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?,
+    )];
+    if open_orders[0].1.owner != waiting.trader {
+        msg!("Expected the OpenOrders account of {}", waiting.trader);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut order = Order {
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+        bounty: 0,
+        trail_ticks: 0,
+        trigger_price: 0,
+        improvement_slots: 0,
+        ..waiting
+    };
+    transfer_lamports(order_book_info, keeper_info, waiting.bounty)?;
+    let fee_reserve = order_book.reserved_funds(&waiting)? - order_book.locked_funds(&waiting)?;
+    let (mut fills, filled_makers) = match_taker_order(
+        order_book,
+        open_orders[0].1.market_mut(order_book_info.key)?,
+        &mut order,
+        clock,
+    )?;
+    if order.amount > 0 {
+        order_book.add_order(order)?;
+    } else {
+        open_orders[0]
+            .1
+            .market_mut(order_book_info.key)?
+            .order_count -= 1;
+        transfer_lamports(order_book_info, open_orders_info, order.locked_lamports())?;
+    }
+    settle_fills(
+        program_id,
+        order_book_info,
+        order_book,
+        &mut open_orders,
+        maker_infos,
+        &order,
+        &mut fills,
+        &filled_makers,
+        fee_reserve,
+    )?;
+
+    for (info, open_orders) in &open_orders {
+        open_orders.pack_into_account(info)?;
+    }
+    log_book_changes(&before, order_book, order_book_info.key)?;
+    order_book.pack_into_account(order_book_info)
+}
+
+// Process the SetBeneficialOwner instruction
+fn process_set_beneficial_owner(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    beneficial_owner: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let beneficial_owner_info = next_account_info(account_info_iter)?;
+
+    if open_orders_info.owner != program_id {
+        msg!("OpenOrders account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    OpenOrders::check_layout(open_orders_info)?;
+    let mut open_orders = OpenOrders::unpack(&open_orders_info.try_borrow_data()?)?;
+    if !open_orders.is_initialized {
+        msg!("OpenOrders account is not initialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if !owner_info.is_signer || owner_info.key != &open_orders.owner {
+        msg!("OpenOrders owner must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if !beneficial_owner_info.is_signer || beneficial_owner_info.key != &beneficial_owner {
+        msg!("Beneficial owner must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    open_orders.beneficial_owner = beneficial_owner;
+    open_orders.pack_into_account(open_orders_info)?;
+
+    msg!(
+        "Beneficial owner of {} set to {}",
+        open_orders_info.key,
+        beneficial_owner
+    );
+
+    Ok(())
+}
+
+// Process the SetWithdrawAuthority instruction
+fn process_set_withdraw_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    withdraw_authority: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let authority_info = next_account_info(account_info_iter)?;
+    let new_authority_info = next_account_info(account_info_iter)?;
+
+    if open_orders_info.owner != program_id {
+        msg!("OpenOrders account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    OpenOrders::check_layout(open_orders_info)?;
+    let mut open_orders = OpenOrders::unpack(&open_orders_info.try_borrow_data()?)?;
+    if !open_orders.is_initialized {
+        msg!("OpenOrders account is not initialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if !authority_info.is_signer || authority_info.key != &open_orders.withdraw_authority {
+        msg!("OpenOrders withdraw authority must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if !new_authority_info.is_signer || new_authority_info.key != &withdraw_authority {
+        msg!("New withdraw authority must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    open_orders.withdraw_authority = withdraw_authority;
+    open_orders.pack_into_account(open_orders_info)?;
+
+    msg!(
+        "Withdraw authority of {} set to {}",
+        open_orders_info.key,
+        withdraw_authority
+    );
+
+    Ok(())
+}
+
+// Process the CancelAllAfter instruction
+fn process_cancel_all_after(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    slot: Slot,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+
+    if open_orders_info.owner != program_id {
+        msg!("OpenOrders account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    OpenOrders::check_layout(open_orders_info)?;
+    let mut open_orders = OpenOrders::unpack(&open_orders_info.try_borrow_data()?)?;
+    if !open_orders.is_initialized {
+        msg!("OpenOrders account is not initialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if !owner_info.is_signer || owner_info.key != &open_orders.owner {
+        msg!("OpenOrders owner must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    open_orders.cancel_after_slot = slot;
+    open_orders.pack_into_account(open_orders_info)?;
+
+    msg!(
+        "Orders of {} placed after slot {} are cancelable",
+        open_orders_info.key,
+        slot
+    );
+
+    Ok(())
+}
+
+// Process the InitOpenOrders instruction
+fn process_init_open_orders(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let quote_mint_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    if !owner_info.is_signer {
+        msg!("OpenOrders owner must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let (open_orders_key, bump) =
+        OpenOrders::find_address(program_id, owner_info.key, quote_mint_info.key);
+    if open_orders_info.key != &open_orders_key {
+        msg!("Expected the OpenOrders PDA {}", open_orders_key);
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if open_orders_info.owner == program_id {
+        msg!("OpenOrders account already exists");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent: Rent = next_sysvar(account_info_iter)?;
+    create_pda_account(
+        program_id,
+        owner_info,
+        open_orders_info,
+        system_program_info,
+        &rent,
+        OpenOrders::LEN,
+        &[
+            OpenOrders::SEED,
+            owner_info.key.as_ref(),
+            quote_mint_info.key.as_ref(),
+            &[bump],
+        ],
+    )?;
+    let open_orders = OpenOrders {
+        is_initialized: true,
+        owner: *owner_info.key,
+        quote_mint: *quote_mint_info.key,
+        markets: vec![],
+        beneficial_owner: *owner_info.key,
+        withdraw_authority: *owner_info.key,
+        cancel_after_slot: 0,
+    };
+    open_orders.pack_into_account(open_orders_info)?;
+
+    msg!(
+        "OpenOrders initialized: owner={} quote_mint={}",
+        open_orders.owner,
+        open_orders.quote_mint
+    );
+
+    Ok(())
+}
+
+// Process the SettleFunds instruction
+fn process_settle_funds(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_amounts: (u64, u64),
+    recipient: Option<Pubkey>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let base_vault_info = next_account_info(account_info_iter)?;
+    let quote_vault_info = next_account_info(account_info_iter)?;
+    let owner_base_info = next_account_info(account_info_iter)?;
+    let owner_quote_info = next_account_info(account_info_iter)?;
+    let vault_signer_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let rent_info = next_account_info(account_info_iter)?;
+    // Followed by the market's Blocklist if it has one, and optionally by the
+    // base mint, quote mint, system program and associated token program, to
+    // create missing destination accounts
+
+    if token_program_info.key != &spl_token::id() {
+        msg!("Expected the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let order_book = load_order_book(program_id, order_book_info)?;
+    let mut open_orders = load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?;
+    if owner_info.key != &open_orders.withdraw_authority || !owner_info.is_signer {
+        msg!("OpenOrders withdraw authority must sign the settlement");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let ata_infos = split_blocklist(
+        program_id,
+        order_book_info,
+        &order_book,
+        account_info_iter.as_slice(),
+        &[
+            &open_orders.owner,
+            owner_info.key,
+            &recipient.unwrap_or(*owner_info.key),
+        ],
+    )?;
+    if recipient.is_some() && !ata_infos.is_empty() {
+        msg!("Token accounts are only created for the owner");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if let [base_mint_info, quote_mint_info, system_program_info, associated_token_program_info, ..] =
+        ata_infos
+    {
+        if base_mint_info.key != &order_book.base_mint
+            || quote_mint_info.key != &order_book.quote_mint
+        {
+            msg!("Expected the market mints");
+            return Err(ProgramError::InvalidArgument);
+        }
+        create_settlement_accounts(
+            &open_orders,
+            order_book_info,
+            owner_info,
+            [
+                (owner_base_info, base_mint_info),
+                (owner_quote_info, quote_mint_info),
+            ],
+            system_program_info,
+            token_program_info,
+            associated_token_program_info,
+        )?;
+    }
+
+    settle_market(
+        &mut open_orders,
+        order_book_info,
+        &order_book,
+        base_vault_info,
+        quote_vault_info,
+        owner_base_info,
+        owner_quote_info,
+        vault_signer_info,
+        token_program_info,
+        &recipient.unwrap_or(*owner_info.key),
+        max_amounts,
+    )?;
+    open_orders.markets.retain(|record| !record.is_empty());
+    open_orders.pack_into_account(open_orders_info)?;
+    withdraw_refunds(open_orders_info, owner_info, rent_info)?;
+
+    Ok(())
+}
+
+// Process the SettleAndWithdrawAll instruction
+fn process_settle_and_withdraw_all(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let quote_mint_info = next_account_info(account_info_iter)?;
+    let owner_quote_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let associated_token_program_info = next_account_info(account_info_iter)?;
+    let rent_info = next_account_info(account_info_iter)?;
+    // The remaining accounts come in groups of six per market: the order
+    // book, base vault, quote vault, vault signer, base mint and the owner's
+    // base associated token account
+    let market_infos = account_info_iter.as_slice();
+
+    if token_program_info.key != &spl_token::id() {
+        msg!("Expected the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !market_infos.len().is_multiple_of(6) {
+        msg!("Expected six accounts per market");
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    let mut open_orders = load_open_orders(program_id, open_orders_info, quote_mint_info.key)?;
+    if owner_info.key != &open_orders.withdraw_authority || !owner_info.is_signer {
+        msg!("OpenOrders withdraw authority must sign the settlement");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    for market_infos in market_infos.chunks_exact(6) {
+        let [order_book_info, base_vault_info, quote_vault_info, vault_signer_info, base_mint_info, owner_base_info] =
+            market_infos
+        else {
+            unreachable!();
+        };
+        let order_book = load_order_book(program_id, order_book_info)?;
+        if base_mint_info.key != &order_book.base_mint {
+            msg!("Expected the base mint {}", order_book.base_mint);
+            return Err(ProgramError::InvalidArgument);
+        }
+        if order_book.blocklist != Pubkey::default() {
+            msg!(
+                "Market {} enforces a blocklist; settle it with SettleFunds",
+                order_book_info.key
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        create_settlement_accounts(
+            &open_orders,
+            order_book_info,
+            owner_info,
+            [
+                (owner_base_info, base_mint_info),
+                (owner_quote_info, quote_mint_info),
+            ],
+            system_program_info,
+            token_program_info,
+            associated_token_program_info,
+        )?;
+        settle_market(
+            &mut open_orders,
+            order_book_info,
+            &order_book,
+            base_vault_info,
+            quote_vault_info,
+            owner_base_info,
+            owner_quote_info,
+            vault_signer_info,
+            token_program_info,
+            owner_info.key,
+            (u64::MAX, u64::MAX),
+        )?;
+    }
+    open_orders.markets.retain(|record| !record.is_empty());
+    open_orders.pack_into_account(open_orders_info)?;
+    withdraw_refunds(open_orders_info, owner_info, rent_info)?;
+
+    Ok(())
+}
+
+// Process the SweepFees instruction
+fn process_sweep_fees(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let quote_vault_info = next_account_info(account_info_iter)?;
+    let destination_info = next_account_info(account_info_iter)?;
+    let vault_signer_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let clock_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    let amount = std::mem::take(&mut order_book.fees_outstanding);
+    order_book.fees_swept += amount;
+    let fee_collector = order_book.fee_collector;
+    sweep_fees(
+        order_book_info,
+        &order_book,
+        quote_vault_info,
+        destination_info,
+        vault_signer_info,
+        token_program_info,
+        &fee_collector,
+        amount,
+    )?;
+    order_book.pack_into_account(order_book_info)?;
+
+    let event = FeeSweptEvent {
+        market: *order_book_info.key,
+        fee_collector,
+        destination: *destination_info.key,
+        amount,
+        total_swept: order_book.fees_swept,
+        slot: Clock::from_account_info(clock_info)?.slot,
+    };
+    msg!("FeeSwept: {:?}", event);
+
+    Ok(())
+}
+
+// Process the SweepProtocolFees instruction
+fn process_sweep_protocol_fees(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let quote_vault_info = next_account_info(account_info_iter)?;
+    let destination_info = next_account_info(account_info_iter)?;
+    let vault_signer_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let clock_info = next_account_info(account_info_iter)?;
+    let config_info = next_account_info(account_info_iter)?;
+
+    let config = load_config(program_id, config_info)?;
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    let amount = std::mem::take(&mut order_book.protocol_fees_outstanding);
+    order_book.protocol_fees_swept += amount;
+    sweep_fees(
+        order_book_info,
+        &order_book,
+        quote_vault_info,
+        destination_info,
+        vault_signer_info,
+        token_program_info,
+        &config.treasury,
+        amount,
+    )?;
+    order_book.pack_into_account(order_book_info)?;
+
+    let event = FeeSweptEvent {
+        market: *order_book_info.key,
+        fee_collector: config.treasury,
+        destination: *destination_info.key,
+        amount,
+        total_swept: order_book.protocol_fees_swept,
+        slot: Clock::from_account_info(clock_info)?.slot,
+    };
+    msg!("FeeSwept: {:?}", event);
+
+    Ok(())
+}
+
+// Transfer swept fees from a market's quote vault to a token account of
+// `recipient`
+#[allow(clippy::too_many_arguments)]
+fn sweep_fees<'a>(
+    order_book_info: &AccountInfo<'a>,
+    order_book: &OrderBook,
+    quote_vault_info: &AccountInfo<'a>,
+    destination_info: &AccountInfo<'a>,
+    vault_signer_info: &AccountInfo<'a>,
+    token_program_info: &AccountInfo<'a>,
+    recipient: &Pubkey,
+    amount: u64,
+) -> ProgramResult {
+    if token_program_info.key != &spl_token::id() {
+        msg!("Expected the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    check_vault(quote_vault_info, &order_book.quote_vault)?;
+    check_token_mint(destination_info, &order_book.quote_mint)?;
+    if destination_info.owner != &spl_token::id()
+        || &spl_token::state::Account::unpack(&destination_info.try_borrow_data()?)?.owner
+            != recipient
+    {
+        msg!("Fees can only be swept to a token account of {}", recipient);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if amount == 0 {
+        return Ok(());
+    }
+    transfer_tokens(
+        token_program_info,
+        quote_vault_info,
+        destination_info,
+        vault_signer_info,
+        amount,
+        &[&[
+            OrderBook::VAULT_SIGNER_SEED,
+            order_book_info.key.as_ref(),
+            &[order_book.vault_signer_bump],
+        ]],
+    )
+}
+
+// Process the SetBackstopMaker instruction
+fn process_set_backstop_maker(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    maker: Pubkey,
+    backstop: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can set backstop makers");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let registered = order_book.backstop_makers.contains(&maker);
+    if backstop && !registered {
+        if order_book.backstop_makers.len() >= OrderBook::MAX_BACKSTOP_MAKERS {
+            msg!(
+                "Market already has {} backstop makers",
+                OrderBook::MAX_BACKSTOP_MAKERS
+            );
+            return Err(OrderBookError::BackstopMakersFull.into());
+        }
+        order_book.backstop_makers.push(maker);
+    } else if !backstop {
+        order_book.backstop_makers.retain(|key| key != &maker);
+    }
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!("Backstop maker {}: {}", maker, backstop);
+
+    Ok(())
+}
+
+// Check that an oracle source other than none comes with an oracle account
+fn check_oracle(oracle_source: OracleSource, oracle: &Pubkey) -> ProgramResult {
+    if oracle_source != OracleSource::None && oracle == &Pubkey::default() {
+        msg!("A {:?} oracle requires an oracle account", oracle_source);
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    Ok(())
+}
+
+// Process the SetOracle instruction
+fn process_set_oracle(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    oracle_source: OracleSource,
+    oracle: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can set the oracle");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_oracle(oracle_source, &oracle)?;
+    order_book.oracle_source = oracle_source;
+    order_book.oracle = oracle;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!("Oracle set to {:?} {}", oracle_source, oracle);
+
+    Ok(())
+}
+
+// Process the SetMarketFeatures instruction
+fn process_set_market_features(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    disabled_features: u8,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can set the market features");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    order_book.disabled_features = disabled_features;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!(
+        "Disabled market features set to {:#010b}",
+        disabled_features
+    );
+
+    Ok(())
+}
+
+// Process the SetPlacementFee instruction
+fn process_set_placement_fee(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    lamports: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can set the placement fee");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if lamports > OrderBook::MAX_PLACEMENT_FEE_LAMPORTS {
+        msg!(
+            "Placement fee may not exceed {} lamports",
+            OrderBook::MAX_PLACEMENT_FEE_LAMPORTS
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    order_book.placement_fee_lamports = lamports;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!("Placement fee set to {} lamports", lamports);
+
+    Ok(())
+}
+
+// Process the SetPruneAfter instruction
+fn process_set_prune_after(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    slots: Slot,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can set the pruning age");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if slots != 0 && slots < OrderBook::MIN_PRUNE_AFTER_SLOTS {
+        msg!(
+            "Orders may only be pruned after at least {} slots",
+            OrderBook::MIN_PRUNE_AFTER_SLOTS
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    order_book.prune_after_slots = slots;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!("Orders may be pruned after {} slots", slots);
+
+    Ok(())
+}
+
+// Process the SetOracleConfidence instruction
+fn process_set_oracle_confidence(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_conf_bps: u16,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can set the oracle confidence limit");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if max_conf_bps as u64 > OrderBook::FEE_BPS_DENOMINATOR {
+        msg!(
+            "Oracle confidence limit may not exceed {} bps",
+            OrderBook::FEE_BPS_DENOMINATOR
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    order_book.max_oracle_conf_bps = max_conf_bps;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!("Oracle confidence limited to {} bps", max_conf_bps);
+
+    Ok(())
+}
+
+// Process the SetOrderRateLimit instruction
+fn process_set_order_rate_limit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_orders_per_slot: u16,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can set the order rate limit");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    order_book.max_orders_per_slot = max_orders_per_slot;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!(
+        "Orders per OpenOrders account per slot limited to {}",
+        max_orders_per_slot
+    );
+
+    Ok(())
+}
+
+// Process the SetPriorityAuction instruction
+fn process_set_priority_auction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    enabled: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can set the priority auction mode");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    order_book.priority_auction = enabled;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!("Priority auction mode set to {}", enabled);
+
+    Ok(())
+}
+
+// Process the SetOrderGate instruction
+fn process_set_order_gate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order_gate: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can set the order gate");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    order_book.order_gate = order_gate;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!("Order gate set to {}", order_gate);
+
+    Ok(())
+}
+
+// Process the InitBlocklist instruction
+fn process_init_blocklist(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let blocklist_info = next_account_info(account_info_iter)?;
+    let authority_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    if !authority_info.is_signer {
+        msg!("Blocklist authority must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let (blocklist_key, bump) = Blocklist::find_address(program_id, authority_info.key);
+    if blocklist_info.key != &blocklist_key {
+        msg!("Expected the Blocklist PDA {}", blocklist_key);
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if blocklist_info.owner == program_id {
+        msg!("Blocklist account already exists");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent: Rent = next_sysvar(account_info_iter)?;
+    create_pda_account(
+        program_id,
+        authority_info,
+        blocklist_info,
+        system_program_info,
+        &rent,
+        Blocklist::LEN,
+        &[Blocklist::SEED, authority_info.key.as_ref(), &[bump]],
+    )?;
+    let blocklist = Blocklist {
+        authority: *authority_info.key,
+        addresses: vec![],
+    };
+    blocklist_info
+        .try_borrow_mut_data()?
+        .copy_from_slice(&blocklist.pack());
+
+    msg!("Blocklist initialized for authority {}", authority_info.key);
+
+    Ok(())
+}
+
+// Process the UpdateBlocklist instruction
+fn process_update_blocklist(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    address: &Pubkey,
+    blocked: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let blocklist_info = next_account_info(account_info_iter)?;
+    let authority_info = next_account_info(account_info_iter)?;
+
+    if blocklist_info.owner != program_id {
+        msg!("Blocklist account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut blocklist = Blocklist::unpack(&blocklist_info.try_borrow_data()?)?;
+    if !authority_info.is_signer || authority_info.key != &blocklist.authority {
+        msg!("Only the blocklist authority can update it");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if blocklist.set_blocked(address, blocked)? {
+        blocklist_info
+            .try_borrow_mut_data()?
+            .copy_from_slice(&blocklist.pack());
+        let event = BlocklistUpdatedEvent {
+            blocklist: *blocklist_info.key,
+            authority: blocklist.authority,
+            address: *address,
+            blocked,
+        };
+        msg!("BlocklistUpdated: {:?}", event);
+    }
+
+    Ok(())
+}
+
+// Process the BondKeeper instruction
+fn process_bond_keeper(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    lamports: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let bond_info = next_account_info(account_info_iter)?;
+    let keeper_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    if !keeper_info.is_signer {
+        msg!("Keeper must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if system_program_info.key != &system_program::id() {
+        msg!("Expected the system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if lamports == 0 {
+        msg!("Bond amount must be non-zero");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let (bond_key, bump) = KeeperBond::find_address(program_id, keeper_info.key);
+    if bond_info.key != &bond_key {
+        msg!("Expected the KeeperBond PDA {}", bond_key);
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    let mut bond = if bond_info.owner == program_id {
+        KeeperBond::unpack(&bond_info.try_borrow_data()?)?
+    } else {
+        let rent: Rent = next_sysvar(account_info_iter)?;
+        create_pda_account(
+            program_id,
+            keeper_info,
+            bond_info,
+            system_program_info,
+            &rent,
+            KeeperBond::LEN,
+            &[KeeperBond::SEED, keeper_info.key.as_ref(), &[bump]],
+        )?;
+        KeeperBond {
+            keeper: *keeper_info.key,
+            ..KeeperBond::default()
+        }
+    };
+    invoke(
+        &system_instruction::transfer(keeper_info.key, bond_info.key, lamports),
+        &[
+            keeper_info.clone(),
+            bond_info.clone(),
+            system_program_info.clone(),
+        ],
+    )?;
+    bond.bonded_lamports = bond
+        .bonded_lamports
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    bond.bonded_at = clock.unix_timestamp;
+    bond_info
+        .try_borrow_mut_data()?
+        .copy_from_slice(&bond.pack());
+
+    msg!(
+        "Keeper {} bonds {} lamports, locked until {}",
+        keeper_info.key,
+        bond.bonded_lamports,
+        bond.unlocks_at()
+    );
+
+    Ok(())
+}
+
+// Process the UnbondKeeper instruction
+fn process_unbond_keeper(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    lamports: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let bond_info = next_account_info(account_info_iter)?;
+    let keeper_info = next_account_info(account_info_iter)?;
+
+    if bond_info.owner != program_id {
+        msg!("KeeperBond account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut bond = KeeperBond::unpack(&bond_info.try_borrow_data()?)?;
+    if !keeper_info.is_signer || keeper_info.key != &bond.keeper {
+        msg!("Only the bonded keeper can unbond");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    if clock.unix_timestamp < bond.unlocks_at() {
+        msg!("Keeper bond is locked until {}", bond.unlocks_at());
+        return Err(ProgramError::InvalidArgument);
+    }
+    if lamports > bond.bonded_lamports {
+        msg!(
+            "Cannot unbond {} lamports, {} are bonded",
+            lamports,
+            bond.bonded_lamports
+        );
+        return Err(ProgramError::InsufficientFunds);
+    }
+    bond.bonded_lamports -= lamports;
+    bond_info
+        .try_borrow_mut_data()?
+        .copy_from_slice(&bond.pack());
+    transfer_lamports(bond_info, keeper_info, lamports)?;
+
+    msg!(
+        "Keeper {} unbonds {} lamports, {} remain bonded",
+        keeper_info.key,
+        lamports,
+        bond.bonded_lamports
+    );
+
+    Ok(())
+}
+
+// Process the DryRun instruction
+fn process_dry_run(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    process_instruction(program_id, accounts, instruction_data)?;
+    msg!("Dry run succeeded, rolling it back");
+    Err(OrderBookError::DryRunComplete.into())
+}
+
+// Process the SetBlocklist instruction
+fn process_set_blocklist(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    blocklist: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can set the blocklist");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    order_book.blocklist = blocklist;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!("Blocklist set to {}", blocklist);
+
+    Ok(())
+}
+
+// Process the SetRoundingPolicy instruction
+fn process_set_rounding_policy(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    policy: RoundingPolicy,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can set the rounding policy");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // Waiting bids reserved their taker fees under the current policy
+    if !order_book.stop_orders.is_empty()
+        || !order_book.pending_orders.is_empty()
+        || !order_book.reference_orders.is_empty()
+    {
+        msg!("The rounding policy cannot change while orders wait off the book");
+        return Err(ProgramError::InvalidArgument);
+    }
+    order_book.rounding_policy = policy;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!("Rounding policy set to {:?}", policy);
+
+    Ok(())
+}
+
+// Process the MigrateLiquidity instruction
+fn process_migrate_liquidity(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_orders: u8,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let successor_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+    let base_vault_info = next_account_info(account_info_iter)?;
+    let quote_vault_info = next_account_info(account_info_iter)?;
+    let successor_base_vault_info = next_account_info(account_info_iter)?;
+    let successor_quote_vault_info = next_account_info(account_info_iter)?;
+    let vault_signer_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    // The remaining accounts are the OpenOrders accounts of the owners of
+    // the orders to cancel and of the balances to move
+    let owner_infos = account_info_iter.as_slice();
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    let successor = load_order_book(program_id, successor_info)?;
+    if !creator_info.is_signer
+        || creator_info.key != &order_book.creator
+        || creator_info.key != &successor.creator
+    {
+        msg!("Only the creator of both markets can migrate liquidity");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if order_book_info.key == successor_info.key
+        || order_book.base_mint != successor.base_mint
+        || order_book.quote_mint != successor.quote_mint
+    {
+        msg!("Liquidity can only migrate to another market of the same pair");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if order_book.successor != Pubkey::default() && &order_book.successor != successor_info.key {
+        msg!("Market is deprecated in favor of {}", order_book.successor);
+        return Err(ProgramError::InvalidArgument);
+    }
+    successor.check_open()?;
+    check_vault(base_vault_info, &order_book.base_vault)?;
+    check_vault(quote_vault_info, &order_book.quote_vault)?;
+    check_vault(successor_base_vault_info, &successor.base_vault)?;
+    check_vault(successor_quote_vault_info, &successor.quote_vault)?;
+    if token_program_info.key != &spl_token::id() {
+        msg!("Expected the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if order_book.successor == Pubkey::default() {
+        order_book.successor = *successor_info.key;
+        msg!(
+            "Market {} deprecated in favor of {}",
+            order_book_info.key,
+            successor_info.key
+        );
+    }
+
+    // Cancel orders, releasing their funds and deposits to their owners
+    let before = order_book.clone();
+    let mut open_orders: Vec<(&AccountInfo, OpenOrders)> = vec![];
+    let order_ids: Vec<u64> = order_book
+        .buy_orders
+        .iter()
+        .chain(&order_book.sell_orders)
+        .chain(&order_book.stop_orders)
+        .chain(&order_book.pending_orders)
+        .chain(&order_book.reference_orders)
+        .map(|order| order.order_id)
+        .take(max_orders as usize)
+        .collect();
+    for order_id in order_ids {
+        let Some(order) = order_book.remove_order(order_id) else {
+            continue;
+        };
+        let owner = find_open_orders(
+            program_id,
+            &mut open_orders,
+            owner_infos,
+            &order.trader,
+            &order_book.quote_mint,
+        )?;
+        let balances = open_orders[owner].1.market_mut(order_book_info.key)?;
+        balances.unlock(order.order_type, order_book.reserved_funds(&order)?);
+        balances.order_count -= 1;
+        transfer_lamports(
+            order_book_info,
+            open_orders[owner].0,
+            order.locked_lamports(),
+        )?;
+        msg!("Order canceled: {:?}", order);
+    }
+
+    // Move the free balances once the last order is gone
+    let remaining = order_book.buy_orders.len()
+        + order_book.sell_orders.len()
+        + order_book.stop_orders.len()
+        + order_book.pending_orders.len()
+        + order_book.reference_orders.len();
+    let (mut base_moved, mut quote_moved) = (0, 0);
+    if remaining == 0 {
+        for owner_info in owner_infos {
+            if !open_orders
+                .iter()
+                .any(|(info, _)| info.key == owner_info.key)
+            {
+                let owner_open_orders =
+                    load_open_orders(program_id, owner_info, &order_book.quote_mint)?;
+                open_orders.push((owner_info, owner_open_orders));
+            }
+        }
+        for (_, owner_open_orders) in open_orders.iter_mut() {
+            let Some(balances) = owner_open_orders
+                .markets
+                .iter_mut()
+                .find(|record| &record.market == order_book_info.key)
+            else {
+                continue;
+            };
+            let base_free = std::mem::take(&mut balances.base_free);
+            let quote_free = std::mem::take(&mut balances.quote_free);
+            owner_open_orders
+                .markets
+                .retain(|record| !record.is_empty());
+            let balances = owner_open_orders.market_mut(successor_info.key)?;
+            balances.base_free += base_free;
+            balances.quote_free += quote_free;
+            base_moved += base_free;
+            quote_moved += quote_free;
+            msg!(
+                "Balances migrated: owner={} base={} quote={}",
+                owner_open_orders.owner,
+                base_free,
+                quote_free
+            );
+        }
+    } else {
+        msg!("{} orders remain to cancel before balances move", remaining);
+    }
+
+    let seeds: &[&[u8]] = &[
+        OrderBook::VAULT_SIGNER_SEED,
+        order_book_info.key.as_ref(),
+        &[order_book.vault_signer_bump],
+    ];
+    let transfers = [
+        (base_moved, base_vault_info, successor_base_vault_info),
+        (quote_moved, quote_vault_info, successor_quote_vault_info),
+    ];
+    for (amount, vault_info, destination_info) in transfers {
+        if amount == 0 {
+            continue;
+        }
+        transfer_tokens(
+            token_program_info,
+            vault_info,
+            destination_info,
+            vault_signer_info,
+            amount,
+            &[seeds],
+        )?;
+    }
+
+    for (info, open_orders) in &open_orders {
+        open_orders.pack_into_account(info)?;
+    }
+    log_book_changes(&before, &mut order_book, order_book_info.key)?;
+    order_book.pack_into_account(order_book_info)?;
+
+    Ok(())
+}
+
+// Process the TransferFreeBalance instruction
+fn process_transfer_free_balance(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    (base_amount, quote_amount): (u64, u64),
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let destination_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let base_vault_info = next_account_info(account_info_iter)?;
+    let quote_vault_info = next_account_info(account_info_iter)?;
+    let destination_base_vault_info = next_account_info(account_info_iter)?;
+    let destination_quote_vault_info = next_account_info(account_info_iter)?;
+    let vault_signer_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    // Followed by the Blocklist of each market that enforces one, the
+    // source market's first
+
+    let order_book = load_order_book(program_id, order_book_info)?;
+    let destination = load_order_book(program_id, destination_info)?;
+    if order_book_info.key == destination_info.key
+        || order_book.quote_mint != destination.quote_mint
+    {
+        msg!("Balances can only move to another market of the same quote mint");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if base_amount > 0 && order_book.base_mint != destination.base_mint {
+        msg!("Base balances can only move to a market of the same base mint");
+        return Err(ProgramError::InvalidArgument);
+    }
+    destination.check_open()?;
+    check_vault(base_vault_info, &order_book.base_vault)?;
+    check_vault(quote_vault_info, &order_book.quote_vault)?;
+    check_vault(destination_base_vault_info, &destination.base_vault)?;
+    check_vault(destination_quote_vault_info, &destination.quote_vault)?;
+    if token_program_info.key != &spl_token::id() {
+        msg!("Expected the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut open_orders = load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?;
+    if owner_info.key != &open_orders.owner || !owner_info.is_signer {
+        msg!("OpenOrders owner must sign the transfer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // A blocked address could otherwise move its balance to a market
+    // without a blocklist and settle it there
+    let addresses = [
+        &open_orders.owner,
+        &open_orders.beneficial_owner,
+        &open_orders.withdraw_authority,
+    ];
+    let blocklist_infos = split_blocklist(
+        program_id,
+        order_book_info,
+        &order_book,
+        account_info_iter.as_slice(),
+        &addresses,
+    )?;
+    split_blocklist(
+        program_id,
+        destination_info,
+        &destination,
+        blocklist_infos,
+        &addresses,
+    )?;
+
+    let balances = open_orders
+        .markets
+        .iter_mut()
+        .find(|record| &record.market == order_book_info.key)
+        .filter(|record| record.base_free >= base_amount && record.quote_free >= quote_amount)
+        .ok_or_else(|| {
+            msg!("Free balances on {} are too low", order_book_info.key);
+            ProgramError::InsufficientFunds
+        })?;
+    balances.base_free -= base_amount;
+    balances.quote_free -= quote_amount;
+    open_orders.markets.retain(|record| !record.is_empty());
+    let balances = open_orders.market_mut(destination_info.key)?;
+    balances.base_free += base_amount;
+    balances.quote_free += quote_amount;
+
+    let seeds: &[&[u8]] = &[
+        OrderBook::VAULT_SIGNER_SEED,
+        order_book_info.key.as_ref(),
+        &[order_book.vault_signer_bump],
+    ];
+    let transfers = [
+        (base_amount, base_vault_info, destination_base_vault_info),
+        (quote_amount, quote_vault_info, destination_quote_vault_info),
+    ];
+    for (amount, vault_info, destination_vault_info) in transfers {
+        if amount == 0 {
+            continue;
+        }
+        transfer_tokens(
+            token_program_info,
+            vault_info,
+            destination_vault_info,
+            vault_signer_info,
+            amount,
+            &[seeds],
+        )?;
+    }
+    open_orders.pack_into_account(open_orders_info)?;
+    msg!(
+        "Balances transferred: owner={} from={} to={} base={} quote={}",
+        open_orders.owner,
+        order_book_info.key,
+        destination_info.key,
+        base_amount,
+        quote_amount
+    );
+
+    Ok(())
+}
+
+// Process the HealBook instruction
+fn process_heal_book(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_fills: u16,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    // The remaining accounts are the Config PDA, the market's feeds, if it
+    // keeps them, and the OpenOrders accounts of the owners of the crossed
+    // orders
+    let clock = Clock::get()?;
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    let before = order_book.clone();
+    let matching_infos = split_unpaused_config(program_id, account_info_iter.as_slice())?;
+    let (trade_history_info, candle_infos, owner_infos) =
+        split_market_feeds(&order_book, matching_infos)?;
+
+    let (fills, filled_orders) = order_book.uncross(&clock, max_fills as usize);
+    if fills.is_empty() {
+        msg!("Order book is not crossed");
+        return Ok(());
+    }
+
+    // Settle both sides of every fill, without fees
+    let mut open_orders = vec![];
+    let mut healed_orders = vec![];
+    for fill in &fills {
+        msg!("Fill: {:?}", fill);
+        FordexEvent::Fill {
+            market: *order_book_info.key,
+            fill: *fill,
+        }
+        .log();
+        let quote = order_book.quote_amount(fill.price, fill.amount)?;
+        for order_id in [fill.taker_order_id, fill.maker_order_id] {
+            let order = *order_book
+                .find_order(order_id)
+                .or_else(|| {
+                    filled_orders
+                        .iter()
+                        .find(|order| order.order_id == order_id)
+                })
+                .ok_or(OrderBookError::OrderNotFound)?;
+            let owner = find_open_orders(
+                program_id,
+                &mut open_orders,
+                owner_infos,
+                &order.trader,
+                &order_book.quote_mint,
+            )?;
+            // A bid locked funds at its limit rather than the fill price
+            let locked_quote = order_book.quote_amount(order.price, fill.amount)?;
+            open_orders[owner].1.market_mut(order_book_info.key)?.fill(
+                order.order_type,
+                fill.amount,
+                quote,
+                locked_quote,
+            );
+            if healed_orders
+                .iter()
+                .all(|healed: &Order| healed.order_id != order_id)
+            {
+                healed_orders.push(order);
+            }
+        }
+    }
+    cancel_linked_orders(
+        program_id,
+        order_book_info,
+        &mut order_book,
+        &mut open_orders,
+        owner_infos,
+        &healed_orders,
+    )?;
+
+    // Credit the deposits and bounties of the orders that left the book
+    for filled in &filled_orders {
+        let owner = find_open_orders(
+            program_id,
+            &mut open_orders,
+            owner_infos,
+            &filled.trader,
+            &order_book.quote_mint,
+        )?;
+        open_orders[owner]
+            .1
+            .market_mut(order_book_info.key)?
+            .order_count -= 1;
+        transfer_lamports(
+            order_book_info,
+            open_orders[owner].0,
+            filled.locked_lamports(),
+        )?;
+    }
+    record_fills(
+        &mut order_book,
+        trade_history_info,
+        candle_infos,
+        &fills,
+        clock.unix_timestamp,
+    )?;
+
+    for (info, open_orders) in &open_orders {
+        open_orders.pack_into_account(info)?;
+    }
+    log_book_changes(&before, &mut order_book, order_book_info.key)?;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!(
+        "Book healed with {} fills; crossed: {}",
+        fills.len(),
+        VerifyMarketReport::verify(&order_book, &clock).crossed
+    );
+
+    Ok(())
+}
+
+// Load a market's LaunchAuction PDA
+fn load_launch_auction(
+    program_id: &Pubkey,
+    launch_auction_info: &AccountInfo,
+    order_book_info: &AccountInfo,
+) -> Result<LaunchAuction, ProgramError> {
+    if launch_auction_info.owner != program_id {
+        msg!("LaunchAuction account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    check_account_size(launch_auction_info, LaunchAuction::LEN)?;
+    let auction = LaunchAuction::unpack(&launch_auction_info.try_borrow_data()?)?;
+    if &auction.market != order_book_info.key {
+        msg!("LaunchAuction belongs to market {}", auction.market);
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(auction)
+}
+
+// Process the InitLaunchAuction instruction
+fn process_init_launch_auction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    supply: u64,
+    reserve_price: u64,
+    bid_end: UnixTimestamp,
+    reveal_end: UnixTimestamp,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let launch_auction_info = next_account_info(account_info_iter)?;
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+    let creator_base_info = next_account_info(account_info_iter)?;
+    let base_vault_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    let order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can launch an auction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if supply == 0
+        || !supply.is_multiple_of(order_book.lot_size)
+        || !reserve_price.is_multiple_of(order_book.tick_size)
+    {
+        msg!(
+            "Supply must be a non-zero multiple of {} and the reserve price a multiple of {}",
+            order_book.lot_size,
+            order_book.tick_size
+        );
+        return Err(OrderBookError::InvalidOrderSize.into());
+    }
+    if reveal_end <= bid_end {
+        msg!("The reveal window must end after bidding closes");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    check_vault(base_vault_info, &order_book.base_vault)?;
+    check_token_mint(creator_base_info, &order_book.base_mint)?;
+    if token_program_info.key != &spl_token::id() {
+        msg!("Expected the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let (launch_auction_key, bump) = LaunchAuction::find_address(program_id, order_book_info.key);
+    if launch_auction_info.key != &launch_auction_key {
+        msg!("Expected the LaunchAuction PDA {}", launch_auction_key);
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if launch_auction_info.owner == program_id {
+        msg!("LaunchAuction account already exists");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent: Rent = next_sysvar(account_info_iter)?;
+    create_pda_account(
+        program_id,
+        creator_info,
+        launch_auction_info,
+        system_program_info,
+        &rent,
+        LaunchAuction::LEN,
+        &[LaunchAuction::SEED, order_book_info.key.as_ref(), &[bump]],
+    )?;
+    transfer_tokens(
+        token_program_info,
+        creator_base_info,
+        base_vault_info,
+        creator_info,
+        supply,
+        &[],
+    )?;
+    let auction = LaunchAuction {
+        market: *order_book_info.key,
+        seller: *creator_info.key,
+        supply,
+        reserve_price,
+        bid_end,
+        reveal_end,
+        ..LaunchAuction::default()
+    };
+    launch_auction_info
+        .try_borrow_mut_data()?
+        .copy_from_slice(&auction.pack());
+
+    msg!(
+        "LaunchAuction initialized: market={} supply={} reserve_price={} bid_end={} reveal_end={}",
+        order_book_info.key,
+        supply,
+        reserve_price,
+        bid_end,
+        reveal_end
+    );
+
+    Ok(())
+}
+
+// Process the CommitLaunchBid instruction
+fn process_commit_launch_bid(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    quantity: u64,
+    deposit: u64,
+    commitment: [u8; 32],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let launch_auction_info = next_account_info(account_info_iter)?;
+    let order_book_info = next_account_info(account_info_iter)?;
+    let bidder_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let bidder_quote_info = next_account_info(account_info_iter)?;
+    let quote_vault_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+
+    let order_book = load_order_book(program_id, order_book_info)?;
+    let mut auction = load_launch_auction(program_id, launch_auction_info, order_book_info)?;
+    if !bidder_info.is_signer {
+        msg!("Bidder must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    if clock.unix_timestamp >= auction.bid_end {
+        msg!("Bidding closed at {}", auction.bid_end);
+        return Err(OrderBookError::WrongAuctionPhase.into());
+    }
+    if quantity == 0 || !quantity.is_multiple_of(order_book.lot_size) || deposit == 0 {
+        msg!(
+            "Bid quantity must be a non-zero multiple of {} backed by a deposit",
+            order_book.lot_size
+        );
+        return Err(OrderBookError::InvalidOrderSize.into());
+    }
+    if auction.bids.len() >= LaunchAuction::MAX_BIDS {
+        msg!("Launch auction accepts no more bids");
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    check_vault(quote_vault_info, &order_book.quote_vault)?;
+    check_token_mint(bidder_quote_info, &order_book.quote_mint)?;
+    if token_program_info.key != &spl_token::id() {
+        msg!("Expected the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut open_orders = load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?;
+    if &open_orders.owner != bidder_info.key {
+        msg!("OpenOrders account does not belong to the bidder");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // Lock the deposit, drawing on the free balance before the bidder's
+    // token account
+    let balances = open_orders.market_mut(order_book_info.key)?;
+    let from_free = deposit.min(balances.quote_free);
+    balances.quote_free -= from_free;
+    balances.lock(OrderType::Buy, deposit);
+    if deposit > from_free {
+        transfer_tokens(
+            token_program_info,
+            bidder_quote_info,
+            quote_vault_info,
+            bidder_info,
+            deposit - from_free,
+            &[],
+        )?;
+    }
+    auction.bids.push(SealedBid {
+        bidder: *bidder_info.key,
+        commitment,
+        quantity,
+        deposit,
+        ..SealedBid::default()
+    });
+    open_orders.pack_into_account(open_orders_info)?;
+    launch_auction_info
+        .try_borrow_mut_data()?
+        .copy_from_slice(&auction.pack());
+
+    msg!(
+        "Launch bid committed: bidder={} quantity={} deposit={}",
+        bidder_info.key,
+        quantity,
+        deposit
+    );
+
+    Ok(())
+}
+
+// Process the RevealLaunchBid instruction
+fn process_reveal_launch_bid(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    price: u64,
+    salt: [u8; 32],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let launch_auction_info = next_account_info(account_info_iter)?;
+    let order_book_info = next_account_info(account_info_iter)?;
+    let bidder_info = next_account_info(account_info_iter)?;
+
+    let order_book = load_order_book(program_id, order_book_info)?;
+    let mut auction = load_launch_auction(program_id, launch_auction_info, order_book_info)?;
+    if !bidder_info.is_signer {
+        msg!("Bidder must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    if clock.unix_timestamp < auction.bid_end || clock.unix_timestamp >= auction.reveal_end {
+        msg!(
+            "Bids are revealed from {} until {}",
+            auction.bid_end,
+            auction.reveal_end
+        );
+        return Err(OrderBookError::WrongAuctionPhase.into());
+    }
+    if price == 0 || price < auction.reserve_price {
+        msg!(
+            "Bid price is below the reserve price {}",
+            auction.reserve_price
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if !price.is_multiple_of(order_book.tick_size) {
+        msg!("Bid price must be a multiple of {}", order_book.tick_size);
+        return Err(OrderBookError::InvalidOrderSize.into());
+    }
+    let commitment = LaunchAuction::commitment(bidder_info.key, price, &salt);
+    let bid = auction
+        .bids
+        .iter_mut()
+        .find(|bid| {
+            &bid.bidder == bidder_info.key && bid.commitment == commitment && !bid.is_revealed()
+        })
+        .ok_or_else(|| {
+            msg!(
+                "No sealed bid of {} matches the revealed price",
+                bidder_info.key
+            );
+            ProgramError::InvalidArgument
+        })?;
+    let cost = order_book.quote_amount(price, bid.quantity)?;
+    if cost > bid.deposit {
+        msg!("Bid costs {} but deposited only {}", cost, bid.deposit);
+        return Err(ProgramError::InsufficientFunds);
+    }
+    bid.price = price;
+    launch_auction_info
+        .try_borrow_mut_data()?
+        .copy_from_slice(&auction.pack());
+
+    msg!(
+        "Launch bid revealed: bidder={} price={}",
+        bidder_info.key,
+        price
+    );
+
+    Ok(())
+}
+
+// Process the ClearLaunchAuction instruction
+fn process_clear_launch_auction(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let launch_auction_info = next_account_info(account_info_iter)?;
+    let order_book_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+
+    let order_book = load_order_book(program_id, order_book_info)?;
+    let mut auction = load_launch_auction(program_id, launch_auction_info, order_book_info)?;
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    if auction.cleared || clock.unix_timestamp < auction.reveal_end {
+        msg!(
+            "Launch auction clears once after its reveal window ends at {}",
+            auction.reveal_end
+        );
+        return Err(OrderBookError::WrongAuctionPhase.into());
+    }
+    let mut open_orders = load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?;
+    if open_orders.owner != auction.seller {
+        msg!(
+            "Expected the OpenOrders account of the seller {}",
+            auction.seller
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    auction.clear(order_book.lot_size);
+    let mut proceeds = 0u64;
+    for bid in &auction.bids {
+        proceeds = proceeds
+            .checked_add(order_book.quote_amount(auction.clearing_price, bid.allocation)?)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+    let balances = open_orders.market_mut(order_book_info.key)?;
+    balances.quote_free += proceeds;
+    balances.base_free += auction.supply - auction.sold;
+    open_orders.pack_into_account(open_orders_info)?;
+    launch_auction_info
+        .try_borrow_mut_data()?
+        .copy_from_slice(&auction.pack());
+
+    msg!(
+        "Launch auction cleared: market={} clearing_price={} sold={} proceeds={}",
+        order_book_info.key,
+        auction.clearing_price,
+        auction.sold,
+        proceeds
+    );
+
+    Ok(())
+}
+
+// Process the SettleLaunchBids instruction
+fn process_settle_launch_bids(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let launch_auction_info = next_account_info(account_info_iter)?;
+    let order_book_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+
+    let order_book = load_order_book(program_id, order_book_info)?;
+    let mut auction = load_launch_auction(program_id, launch_auction_info, order_book_info)?;
+    if !auction.cleared {
+        msg!("Launch auction has not cleared yet");
+        return Err(OrderBookError::WrongAuctionPhase.into());
+    }
+    let mut open_orders = load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?;
+    let bidder = open_orders.owner;
+    let balances = open_orders.market_mut(order_book_info.key)?;
+    let mut settled = 0;
+    for bid in auction
+        .bids
+        .iter_mut()
+        .filter(|bid| bid.bidder == bidder && !bid.settled)
+    {
+        let payment = order_book.quote_amount(auction.clearing_price, bid.allocation)?;
+        balances.quote_locked -= bid.deposit;
+        balances.quote_free += bid.deposit - payment;
+        balances.base_free += bid.allocation;
+        bid.settled = true;
+        settled += 1;
+    }
+    open_orders.pack_into_account(open_orders_info)?;
+    launch_auction_info
+        .try_borrow_mut_data()?
+        .copy_from_slice(&auction.pack());
+
+    msg!("Launch bids settled: bidder={} bids={}", bidder, settled);
+
+    Ok(())
+}
+
+// Process the InitPriceOracle instruction
+fn process_init_price_oracle(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let price_oracle_info = next_account_info(account_info_iter)?;
+    let order_book_info = next_account_info(account_info_iter)?;
+    let payer_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    if !payer_info.is_signer {
+        msg!("Payer must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    load_order_book(program_id, order_book_info)?;
+    let (price_oracle_key, bump) = PriceOracle::find_address(program_id, order_book_info.key);
+    if price_oracle_info.key != &price_oracle_key {
+        msg!("Expected the PriceOracle PDA {}", price_oracle_key);
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if price_oracle_info.owner == program_id {
+        msg!("PriceOracle account already exists");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent: Rent = next_sysvar(account_info_iter)?;
+    create_pda_account(
+        program_id,
+        payer_info,
+        price_oracle_info,
+        system_program_info,
+        &rent,
+        PriceOracle::LEN,
+        &[PriceOracle::SEED, order_book_info.key.as_ref(), &[bump]],
+    )?;
+    let price_oracle = PriceOracle {
+        version: PriceOracle::VERSION,
+        market: *order_book_info.key,
+        ..PriceOracle::default()
+    };
+    price_oracle_info
+        .try_borrow_mut_data()?
+        .copy_from_slice(&price_oracle.pack());
+
+    msg!("PriceOracle initialized for market {}", order_book_info.key);
+
+    Ok(())
+}
+
+// Process the WriteFillReceipt instruction
+fn process_write_fill_receipt(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order_id: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let fill_receipt_info = next_account_info(account_info_iter)?;
+    let order_book_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    let order_book = load_order_book(program_id, order_book_info)?;
+    let closed = *order_book
+        .find_closed_order(order_id)
+        .filter(|closed| closed.status == OrderStatus::Filled)
+        .ok_or_else(|| {
+            msg!(
+                "Order {} is not a filled order the market remembers",
+                order_id
+            );
+            OrderBookError::OrderNotFound
+        })?;
+    if !owner_info.is_signer || owner_info.key != &closed.trader {
+        msg!("Only the order owner can write its fill receipt");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let (fill_receipt_key, bump) =
+        FillReceipt::find_address(program_id, order_book_info.key, order_id);
+    if fill_receipt_info.key != &fill_receipt_key {
+        msg!("Expected the FillReceipt PDA {}", fill_receipt_key);
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if fill_receipt_info.owner == program_id {
+        msg!("FillReceipt account already exists");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent: Rent = next_sysvar(account_info_iter)?;
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    create_pda_account(
+        program_id,
+        owner_info,
+        fill_receipt_info,
+        system_program_info,
+        &rent,
+        FillReceipt::LEN,
+        &[
+            FillReceipt::SEED,
+            order_book_info.key.as_ref(),
+            &order_id.to_le_bytes(),
+            &[bump],
+        ],
+    )?;
+    let receipt = FillReceipt::new(
+        *order_book_info.key,
+        &closed,
+        order_book.lot_size,
+        clock.unix_timestamp,
+    );
+    fill_receipt_info
+        .try_borrow_mut_data()?
+        .copy_from_slice(&receipt.pack());
+
+    msg!("Fill receipt written: {:?}", receipt);
+
+    Ok(())
+}
+
+// Process the CloseFillReceipt instruction
+fn process_close_fill_receipt(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let fill_receipt_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+
+    if fill_receipt_info.owner != program_id {
+        msg!("FillReceipt account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    check_account_size(fill_receipt_info, FillReceipt::LEN)?;
+    let receipt = FillReceipt::unpack(&fill_receipt_info.try_borrow_data()?)?;
+    if !owner_info.is_signer || owner_info.key != &receipt.owner {
+        msg!("Only the receipt owner can close it");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    fill_receipt_info.try_borrow_mut_data()?.fill(0);
+    transfer_lamports(fill_receipt_info, owner_info, fill_receipt_info.lamports())?;
+
+    msg!("Fill receipt of order {} closed", receipt.order_id);
+
+    Ok(())
+}
+
+// Process the InitTradeHistory instruction
+fn process_init_trade_history(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    capacity: u32,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let trade_history_info = next_account_info(account_info_iter)?;
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can create the trade history");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if capacity == 0 || capacity > TradeHistory::MAX_CAPACITY {
+        msg!(
+            "Trade history capacity must be between 1 and {}",
+            TradeHistory::MAX_CAPACITY
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let (trade_history_key, bump) = TradeHistory::find_address(program_id, order_book_info.key);
+    if trade_history_info.key != &trade_history_key {
+        msg!("Expected the TradeHistory PDA {}", trade_history_key);
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if trade_history_info.owner == program_id {
+        msg!("TradeHistory account already exists");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent: Rent = next_sysvar(account_info_iter)?;
+    create_pda_account(
+        program_id,
+        creator_info,
+        trade_history_info,
+        system_program_info,
+        &rent,
+        TradeHistory::space(capacity),
+        &[TradeHistory::SEED, order_book_info.key.as_ref(), &[bump]],
+    )?;
+    let history = TradeHistory {
+        market: *order_book_info.key,
+        capacity,
+        total: 0,
+    };
+    trade_history_info.try_borrow_mut_data()?[..TradeHistory::HEADER_LEN]
+        .copy_from_slice(&history.pack());
+    order_book.trade_history = trade_history_key;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!(
+        "TradeHistory initialized for market {} with capacity {}",
+        order_book_info.key,
+        capacity
+    );
+
+    Ok(())
+}
+
+// Process the InitCandleSeries instruction
+fn process_init_candle_series(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    interval: u32,
+    capacity: u32,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let candle_series_info = next_account_info(account_info_iter)?;
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !creator_info.is_signer || creator_info.key != &order_book.creator {
+        msg!("Only the market creator can create candle series");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if interval == 0 || capacity == 0 || capacity > CandleSeries::MAX_CAPACITY {
+        msg!(
+            "Candle interval must be non-zero and capacity between 1 and {}",
+            CandleSeries::MAX_CAPACITY
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if order_book.candle_series.len() >= OrderBook::MAX_CANDLE_SERIES {
+        msg!(
+            "Market already has {} candle series",
+            OrderBook::MAX_CANDLE_SERIES
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+    let (candle_series_key, bump) =
+        CandleSeries::find_address(program_id, order_book_info.key, interval);
+    if candle_series_info.key != &candle_series_key {
+        msg!("Expected the CandleSeries PDA {}", candle_series_key);
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if candle_series_info.owner == program_id {
+        msg!("CandleSeries account already exists");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent: Rent = next_sysvar(account_info_iter)?;
+    create_pda_account(
+        program_id,
+        creator_info,
+        candle_series_info,
+        system_program_info,
+        &rent,
+        CandleSeries::space(capacity),
+        &[
+            CandleSeries::SEED,
+            order_book_info.key.as_ref(),
+            &interval.to_le_bytes(),
+            &[bump],
+        ],
+    )?;
+    let series = CandleSeries {
+        market: *order_book_info.key,
+        interval,
+        capacity,
+        total: 0,
+    };
+    candle_series_info.try_borrow_mut_data()?[..CandleSeries::HEADER_LEN]
+        .copy_from_slice(&series.pack());
+    order_book.candle_series.push(candle_series_key);
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!(
+        "CandleSeries initialized for market {} with interval {} and capacity {}",
+        order_book_info.key,
+        interval,
+        capacity
+    );
+
+    Ok(())
+}
+
+// Process the UpdatePriceOracle instruction
+fn process_update_price_oracle(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let price_oracle_info = next_account_info(account_info_iter)?;
+    let order_book_info = next_account_info(account_info_iter)?;
+
+    let order_book = load_order_book(program_id, order_book_info)?;
+    if price_oracle_info.owner != program_id
+        || price_oracle_info.key != &PriceOracle::find_address(program_id, order_book_info.key).0
+    {
+        msg!("Expected the PriceOracle PDA of the market");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    check_account_size(price_oracle_info, PriceOracle::LEN)?;
+    let mut price_oracle = PriceOracle::unpack(&price_oracle_info.try_borrow_data()?)?;
+    price_oracle.update(&order_book, &clock);
+    price_oracle_info
+        .try_borrow_mut_data()?
+        .copy_from_slice(&price_oracle.pack());
+
+    msg!("PriceOracle updated: {:?}", price_oracle);
+
+    Ok(())
+}
+
+// Process the FundIncentives instruction
+fn process_fund_incentives(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let funder_info = next_account_info(account_info_iter)?;
+    let funder_token_info = next_account_info(account_info_iter)?;
+    let quote_vault_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !funder_info.is_signer {
+        msg!("Funder must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_vault(quote_vault_info, &order_book.quote_vault)?;
+    check_token_mint(funder_token_info, &order_book.quote_mint)?;
+    if token_program_info.key != &spl_token::id() {
+        msg!("Expected the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    order_book.incentive_balance = order_book
+        .incentive_balance
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    transfer_tokens(
+        token_program_info,
+        funder_token_info,
+        quote_vault_info,
+        funder_info,
+        amount,
+        &[],
+    )?;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!(
+        "Incentives funded: amount={} balance={}",
+        amount,
+        order_book.incentive_balance
+    );
+
+    Ok(())
+}
+
+// Process the FundEpochRewards instruction
+fn process_fund_epoch_rewards(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let funder_info = next_account_info(account_info_iter)?;
+    let funder_token_info = next_account_info(account_info_iter)?;
+    let quote_vault_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    if !funder_info.is_signer {
+        msg!("Funder must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_vault(quote_vault_info, &order_book.quote_vault)?;
+    check_token_mint(funder_token_info, &order_book.quote_mint)?;
+    if token_program_info.key != &spl_token::id() {
+        msg!("Expected the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    order_book.roll_fee_epoch(Clock::get()?.unix_timestamp);
+    order_book.fee_epoch.rewards = order_book
+        .fee_epoch
+        .rewards
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    transfer_tokens(
+        token_program_info,
+        funder_token_info,
+        quote_vault_info,
+        funder_info,
+        amount,
+        &[],
+    )?;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!(
+        "Epoch rewards funded: epoch={} amount={} rewards={}",
+        order_book.fee_epoch.epoch,
+        amount,
+        order_book.fee_epoch.rewards
+    );
+
+    Ok(())
+}
+
+// Process the ClaimEpochRewards instruction
+fn process_claim_epoch_rewards(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let clock: Clock = next_sysvar(account_info_iter)?;
+
+    let mut order_book = load_order_book(program_id, order_book_info)?;
+    let mut open_orders = load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?;
+    if !owner_info.is_signer || &open_orders.owner != owner_info.key {
+        msg!("OpenOrders owner must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    order_book.roll_fee_epoch(clock.unix_timestamp);
+    let Some(balances) = open_orders
+        .markets
+        .iter_mut()
+        .find(|record| &record.market == order_book_info.key)
+    else {
+        msg!("OpenOrders account holds no balances in this market");
+        return Err(OrderBookError::NoEpochRewards.into());
+    };
+    order_book.roll_maker_volume(balances);
+    if balances.unclaimed_maker_volume == 0 {
+        msg!("No maker volume in a closed fee epoch to claim rewards for");
+        return Err(OrderBookError::NoEpochRewards.into());
+    }
+    let epoch = balances.unclaimed_epoch;
+    let amount = order_book.claim_epoch_rewards(balances);
+    open_orders.markets.retain(|record| !record.is_empty());
+    open_orders.pack_into_account(open_orders_info)?;
+    order_book.pack_into_account(order_book_info)?;
+
+    msg!("Epoch rewards claimed: epoch={} amount={}", epoch, amount);
+
+    Ok(())
+}
+
+// Load the program's Config PDA
+fn load_config(program_id: &Pubkey, config_info: &AccountInfo) -> Result<Config, ProgramError> {
+    if config_info.key != &Config::find_address(program_id).0 || config_info.owner != program_id {
+        msg!("Expected the Config PDA");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    check_account_size(config_info, Config::LEN)?;
+    Config::unpack(&config_info.try_borrow_data()?)
+}
+
+// Split the Config PDA, which comes first, off the trailing accounts of an
+// instruction that matches orders, failing while the protocol is paused
+fn split_unpaused_config<'a, 'b>(
+    program_id: &Pubkey,
+    accounts: &'b [AccountInfo<'a>],
+) -> Result<&'b [AccountInfo<'a>], ProgramError> {
+    let (config_info, accounts) = accounts
+        .split_first()
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    if load_config(program_id, config_info)?.paused {
+        msg!("The protocol is paused");
+        return Err(OrderBookError::ProtocolPaused.into());
+    }
+    Ok(accounts)
+}
+
+// Split off the signer PDA of the gate program a gated market requires of
+// PlaceOrder, which only a CPI from that program can sign
+fn split_order_gate<'a, 'b>(
+    order_book_info: &AccountInfo<'a>,
+    order_book: &OrderBook,
+    accounts: &'b [AccountInfo<'a>],
+) -> Result<&'b [AccountInfo<'a>], ProgramError> {
+    if order_book.order_gate == Pubkey::default() {
+        return Ok(accounts);
+    }
+    let signer = OrderBook::find_order_gate_signer(&order_book.order_gate, order_book_info.key).0;
+    match accounts.split_first() {
+        Some((info, rest)) if info.key == &signer && info.is_signer => Ok(rest),
+        _ => {
+            msg!(
+                "Orders on this market must be placed through its gate program {}",
+                order_book.order_gate
+            );
+            Err(OrderBookError::OrderGateRequired.into())
+        }
+    }
+}
+
+// Split off the Blocklist a market opted into and check none of `addresses`
+// is listed on it
+fn split_blocklist<'a, 'b>(
+    program_id: &Pubkey,
+    order_book_info: &AccountInfo<'a>,
+    order_book: &OrderBook,
+    accounts: &'b [AccountInfo<'a>],
+    addresses: &[&Pubkey],
+) -> Result<&'b [AccountInfo<'a>], ProgramError> {
+    if order_book.blocklist == Pubkey::default() {
+        return Ok(accounts);
+    }
+    let Some((blocklist_info, rest)) = accounts
+        .split_first()
+        .filter(|(info, _)| info.key == &order_book.blocklist && info.owner == program_id)
+    else {
+        msg!("Expected the market's Blocklist {}", order_book.blocklist);
+        return Err(ProgramError::InvalidArgument);
+    };
+    let blocklist = Blocklist::unpack(&blocklist_info.try_borrow_data()?)?;
+    if let Some(address) = addresses.iter().find(|address| blocklist.contains(address)) {
+        let event = AddressBlockedEvent {
+            market: *order_book_info.key,
+            blocklist: *blocklist_info.key,
+            address: **address,
+        };
+        msg!("AddressBlocked: {:?}", event);
+        return Err(OrderBookError::AddressBlocked.into());
+    }
+    Ok(rest)
+}
+
+// Split the keeper's KeeperBond off the front of the remaining accounts
+// when passed there, returning the lamports it bonds
+fn split_keeper_bond<'a, 'b>(
+    program_id: &Pubkey,
+    keeper_info: &AccountInfo<'a>,
+    accounts: &'b [AccountInfo<'a>],
+) -> Result<(u64, &'b [AccountInfo<'a>]), ProgramError> {
+    let bond_key = KeeperBond::find_address(program_id, keeper_info.key).0;
+    match accounts.split_first() {
+        Some((bond_info, rest)) if bond_info.key == &bond_key && bond_info.owner == program_id => {
+            let bond = KeeperBond::unpack(&bond_info.try_borrow_data()?)?;
+            Ok((bond.bonded_lamports, rest))
+        }
+        _ => Ok((0, accounts)),
+    }
+}
+
+// Check a new market's sizes and taker fees against the Config's listing
+// requirements
+fn check_listing_requirements(
+    listing: &ListingRequirements,
+    tick_size: u64,
+    lot_size: u64,
+    whole_units: bool,
+    max_taker_fee_bps: u16,
+) -> ProgramResult {
+    if tick_size < listing.min_tick_size || (!whole_units && lot_size < listing.min_lot_size) {
+        msg!(
+            "Tick size {} and lot size {} must be at least {} and {}",
+            tick_size,
+            lot_size,
+            listing.min_tick_size,
+            listing.min_lot_size
+        );
+        return Err(OrderBookError::ListingRequirementsNotMet.into());
+    }
+    if listing.max_taker_fee_bps > 0 && max_taker_fee_bps > listing.max_taker_fee_bps {
+        msg!(
+            "Taker fee of {} bps exceeds the cap of {} bps",
+            max_taker_fee_bps,
+            listing.max_taker_fee_bps
+        );
+        return Err(OrderBookError::ListingRequirementsNotMet.into());
+    }
+    Ok(())
+}
+
+// Load an initialized order book owned by this program
+fn load_order_book(
+    program_id: &Pubkey,
+    order_book_info: &AccountInfo,
+) -> Result<OrderBook, ProgramError> {
+    if order_book_info.owner != program_id {
+        msg!("Order book account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let order_book = OrderBook::from_account_info(order_book_info)?;
+    if !order_book.is_initialized {
+        msg!("Market is not initialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(order_book)
+}
+
+// Take up to `max_base` and `max_quote` of the free balances a market holds
+// in an OpenOrders account and transfer them from the market vaults to token
+// accounts of `recipient`
+#[allow(clippy::too_many_arguments)]
+fn settle_market<'a>(
+    open_orders: &mut OpenOrders,
+    order_book_info: &AccountInfo<'a>,
+    order_book: &OrderBook,
+    base_vault_info: &AccountInfo<'a>,
+    quote_vault_info: &AccountInfo<'a>,
+    owner_base_info: &AccountInfo<'a>,
+    owner_quote_info: &AccountInfo<'a>,
+    vault_signer_info: &AccountInfo<'a>,
+    token_program_info: &AccountInfo<'a>,
+    recipient: &Pubkey,
+    (max_base, max_quote): (u64, u64),
+) -> ProgramResult {
+    check_vault(base_vault_info, &order_book.base_vault)?;
+    check_vault(quote_vault_info, &order_book.quote_vault)?;
+    if order_book.quote_mint != open_orders.quote_mint {
+        msg!(
+            "Market {} is not quoted in the OpenOrders quote mint",
+            order_book_info.key
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let (base_free, quote_free) = match open_orders
+        .markets
+        .iter_mut()
+        .find(|record| &record.market == order_book_info.key)
+    {
+        Some(balances) => {
+            let base_free = balances.base_free.min(max_base);
+            let quote_free = balances.quote_free.min(max_quote);
+            balances.base_free -= base_free;
+            balances.quote_free -= quote_free;
+            (base_free, quote_free)
+        }
+        None => (0, 0),
+    };
+
+    let seeds: &[&[u8]] = &[
+        OrderBook::VAULT_SIGNER_SEED,
+        order_book_info.key.as_ref(),
+        &[order_book.vault_signer_bump],
+    ];
+    let transfers = [
+        (
+            base_free,
+            base_vault_info,
+            owner_base_info,
+            &order_book.base_mint,
+        ),
+        (
+            quote_free,
+            quote_vault_info,
+            owner_quote_info,
+            &order_book.quote_mint,
+        ),
+    ];
+    for (amount, vault_info, destination_info, mint) in transfers {
+        if amount == 0 {
+            continue;
+        }
+        check_token_mint(destination_info, mint)?;
+        if destination_info.owner != &spl_token::id()
+            || &spl_token::state::Account::unpack(&destination_info.try_borrow_data()?)?.owner
+                != recipient
+        {
+            msg!(
+                "Funds can only be settled to token accounts of {}",
+                recipient
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+        transfer_tokens(
+            token_program_info,
+            vault_info,
+            destination_info,
+            vault_signer_info,
+            amount,
+            &[seeds],
+        )?;
+    }
+
+    msg!(
+        "Funds settled: market={} owner={} recipient={} base={} quote={}",
+        order_book_info.key,
+        open_orders.owner,
+        recipient,
+        base_free,
+        quote_free
+    );
+
+    Ok(())
+}
+
+// Pay out the order deposits credited to an OpenOrders account, keeping it
+// rent exempt
+fn withdraw_refunds(
+    open_orders_info: &AccountInfo,
+    owner_info: &AccountInfo,
+    rent_info: &AccountInfo,
+) -> ProgramResult {
+    let rent = Rent::from_account_info(rent_info)?;
+    let refunds = open_orders_info
+        .lamports()
+        .saturating_sub(rent.minimum_balance(open_orders_info.data_len()));
+    transfer_lamports(open_orders_info, owner_info, refunds)?;
+    msg!(
+        "Deposits withdrawn: owner={} lamports={}",
+        owner_info.key,
+        refunds
+    );
+    Ok(())
+}
+
+// Create the owner's associated token accounts that settling a market pays
+// into, skipping sides with no free balance
+fn create_settlement_accounts<'a>(
+    open_orders: &OpenOrders,
+    order_book_info: &AccountInfo<'a>,
+    owner_info: &AccountInfo<'a>,
+    destinations: [(&AccountInfo<'a>, &AccountInfo<'a>); 2],
+    system_program_info: &AccountInfo<'a>,
+    token_program_info: &AccountInfo<'a>,
+    associated_token_program_info: &AccountInfo<'a>,
+) -> ProgramResult {
+    let (base_free, quote_free) = open_orders
+        .market(order_book_info.key)
+        .map_or((0, 0), |record| (record.base_free, record.quote_free));
+    for (amount, (token_account_info, mint_info)) in
+        [base_free, quote_free].into_iter().zip(destinations)
+    {
+        if amount > 0 {
+            create_associated_token_account_if_missing(
+                owner_info,
+                token_account_info,
+                mint_info,
+                system_program_info,
+                token_program_info,
+                associated_token_program_info,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Create the owner's associated token account for `mint`, paid by the owner,
+// unless it already exists
+fn create_associated_token_account_if_missing<'a>(
+    owner_info: &AccountInfo<'a>,
+    token_account_info: &AccountInfo<'a>,
+    mint_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    token_program_info: &AccountInfo<'a>,
+    associated_token_program_info: &AccountInfo<'a>,
+) -> ProgramResult {
+    let address = get_associated_token_address(owner_info.key, mint_info.key);
+    if token_account_info.key != &address {
+        msg!("Expected the associated token account {}", address);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if token_account_info.owner == &spl_token::id() {
+        return Ok(());
+    }
+    invoke(
+        &create_associated_token_account(
+            owner_info.key,
+            owner_info.key,
+            mint_info.key,
+            &spl_token::id(),
+        ),
+        &[
+            owner_info.clone(),
+            token_account_info.clone(),
+            mint_info.clone(),
+            system_program_info.clone(),
+            token_program_info.clone(),
+            associated_token_program_info.clone(),
+        ],
+    )
+}
+
+// Process the InitializeConfig instruction
+fn process_initialize_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    default_tick_size: u64,
+    default_lot_size: u64,
+    unique_markets: bool,
+    protocol_fee_bps: u16,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let config_info = next_account_info(account_info_iter)?;
+    let admin_info = next_account_info(account_info_iter)?;
+    let program_data_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    let (config_key, bump) = Config::find_address(program_id);
+    if config_info.key != &config_key {
+        msg!("Expected the Config PDA {}", config_key);
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if default_tick_size == 0 || default_lot_size == 0 {
+        msg!("Default tick and lot sizes must be non-zero");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if protocol_fee_bps as u64 > OrderBook::FEE_BPS_DENOMINATOR {
+        msg!("Protocol fee share must not exceed 100%");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    // Only the upgrade authority may set protocol defaults
+    let (program_data_key, _) =
+        Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    if program_data_info.key != &program_data_key {
+        msg!("Expected the program data account {}", program_data_key);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let upgrade_authority = match bincode::deserialize(&program_data_info.try_borrow_data()?) {
+        Ok(UpgradeableLoaderState::ProgramData {
+            upgrade_authority_address,
+            ..
+        }) => upgrade_authority_address,
+        _ => return Err(ProgramError::InvalidAccountData),
+    };
+    if upgrade_authority != Some(*admin_info.key) || !admin_info.is_signer {
+        msg!("Config must be initialized by the upgrade authority");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let rent: Rent = next_sysvar(account_info_iter)?;
+    create_pda_account(
+        program_id,
+        admin_info,
+        config_info,
+        system_program_info,
+        &rent,
+        Config::LEN,
+        &[Config::SEED, &[bump]],
+    )?;
+    let config = Config {
+        is_initialized: true,
+        admin: *admin_info.key,
+        default_tick_size,
+        default_lot_size,
+        unique_markets,
+        protocol_fee_bps,
+        treasury: *admin_info.key,
+        ..Config::default()
+    };
+    config_info.try_borrow_mut_data()?[..Config::LEN].copy_from_slice(&config.pack());
+
+    msg!("Config initialized: {:?}", config);
+
+    Ok(())
+}
+
+// Process the InitializeMarket instruction
+#[allow(clippy::too_many_arguments)]
+fn process_initialize_market(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    tick_size: u64,
+    lot_size: u64,
+    buy_taker_fee_bps: u16,
+    sell_taker_fee_bps: u16,
+    backstop_discount_bps: u16,
+    dark_pool: bool,
+    oracle_source: OracleSource,
+    oracle: Pubkey,
+    whole_units: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let creator_info = next_account_info(account_info_iter)?;
+    let base_mint_info = next_account_info(account_info_iter)?;
+    let quote_mint_info = next_account_info(account_info_iter)?;
+    let config_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let rent_info = next_account_info(account_info_iter)?;
+    let base_vault_info = next_account_info(account_info_iter)?;
+    let quote_vault_info = next_account_info(account_info_iter)?;
+
+    if order_book_info.owner != program_id {
+        msg!("Order book account is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !creator_info.is_signer {
+        msg!("Market creator must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if base_mint_info.key == quote_mint_info.key {
+        msg!("Base and quote mints must differ");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if buy_taker_fee_bps.max(sell_taker_fee_bps) as u64 > OrderBook::FEE_BPS_DENOMINATOR {
+        msg!("Taker fees must not exceed 100%");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if backstop_discount_bps as u64 > OrderBook::FEE_BPS_DENOMINATOR {
+        msg!("Backstop discount must not exceed 100%");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    check_oracle(oracle_source, &oracle)?;
+    let rent = Rent::from_account_info(rent_info)?;
+    if !rent.is_exempt(order_book_info.lamports(), order_book_info.data_len()) {
+        msg!("Order book account must be rent exempt");
+        return Err(ProgramError::AccountNotRentExempt);
+    }
+
+    // Zero sizes take the protocol defaults
+    let config = load_config(program_id, config_info)?;
+    if !config.is_initialized {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    let tick_size = if tick_size == 0 {
+        config.default_tick_size
+    } else {
+        tick_size
+    };
+    let lot_size = match lot_size {
+        0 if whole_units => 1,
+        0 => config.default_lot_size,
+        lot_size => lot_size,
+    };
+    check_listing_requirements(
+        &config.listing,
+        tick_size,
+        lot_size,
+        whole_units,
+        buy_taker_fee_bps.max(sell_taker_fee_bps),
+    )?;
+    if whole_units {
+        // Indivisible assets trade in lots of one unit of a mint without
+        // decimals
+        if base_mint_info.owner != &spl_token::id() {
+            msg!("Base mint {} is not a token mint", base_mint_info.key);
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let base_mint = spl_token::state::Mint::unpack(&base_mint_info.try_borrow_data()?)?;
+        if base_mint.decimals != 0 || lot_size != 1 {
+            msg!("A whole-unit market needs a base mint without decimals and a lot size of 1");
+            return Err(OrderBookError::InvalidOrderSize.into());
+        }
+    }
+
+    let mut order_book = OrderBook::from_account_info(order_book_info)?;
+    if order_book.is_initialized {
+        msg!("Market is already initialized");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    // The vaults must be token accounts for the market mints that only the
+    // vault signer can move funds out of
+    let (vault_signer, vault_signer_bump) =
+        OrderBook::find_vault_signer(program_id, order_book_info.key);
+    let vaults = [
+        (base_vault_info, base_mint_info.key),
+        (quote_vault_info, quote_mint_info.key),
+    ];
+    for (vault_info, mint) in vaults {
+        if vault_info.owner != &spl_token::id() {
+            msg!("Vault {} is not a token account", vault_info.key);
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let vault = spl_token::state::Account::unpack(&vault_info.try_borrow_data()?)?;
+        if &vault.mint != mint
+            || vault.owner != vault_signer
+            || vault.delegate.is_some()
+            || vault.close_authority.is_some()
+        {
+            msg!(
+                "Vault {} must hold mint {} and be owned only by the vault signer {}",
+                vault_info.key,
+                mint,
+                vault_signer
+            );
+            return Err(ProgramError::InvalidAccountData);
+        }
+    }
+
+    order_book.is_initialized = true;
+    order_book.base_mint = *base_mint_info.key;
+    order_book.quote_mint = *quote_mint_info.key;
+    order_book.creator = *creator_info.key;
+    order_book.tick_size = tick_size;
+    order_book.lot_size = lot_size;
+    order_book.base_vault = *base_vault_info.key;
+    order_book.quote_vault = *quote_vault_info.key;
+    order_book.vault_signer_bump = vault_signer_bump;
+    order_book.fee_collector = *creator_info.key;
+    order_book.buy_taker_fee_bps = buy_taker_fee_bps;
+    order_book.sell_taker_fee_bps = sell_taker_fee_bps;
+    order_book.backstop_discount_bps = backstop_discount_bps;
+    order_book.dark_pool = dark_pool;
+    order_book.whole_units = whole_units;
+    order_book.oracle_source = oracle_source;
+    order_book.oracle = oracle;
+    order_book.protocol_fee_bps = config.protocol_fee_bps;
+    if config.listing.crank_funding_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(
+                creator_info.key,
+                order_book_info.key,
+                config.listing.crank_funding_lamports,
+            ),
+            &[
+                creator_info.clone(),
+                order_book_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+        order_book.crank_incentive_lamports = config.listing.crank_funding_lamports;
+    }
+    order_book.pack_into_account(order_book_info)?;
+
+    // Register the market
+    let (registry_key, bump) = MarketRegistryEntry::find_address(program_id, order_book_info.key);
+    if registry_info.key != &registry_key {
+        msg!("Expected the registry entry PDA {}", registry_key);
+        return Err(ProgramError::InvalidSeeds);
+    }
+    create_pda_account(
+        program_id,
+        creator_info,
+        registry_info,
+        system_program_info,
+        &rent,
+        MarketRegistryEntry::LEN,
+        &[
+            MarketRegistryEntry::SEED,
+            order_book_info.key.as_ref(),
+            &[bump],
+        ],
+    )?;
+    let entry = MarketRegistryEntry {
+        order_book: *order_book_info.key,
+        base_mint: order_book.base_mint,
+        quote_mint: order_book.quote_mint,
+        creator: order_book.creator,
+        canonical: config.unique_markets,
+    };
+    registry_info.try_borrow_mut_data()?[..MarketRegistryEntry::LEN].copy_from_slice(&entry.pack());
+
+    // Claim the market combination; fails if another market already has it
+    if config.unique_markets {
+        let canonical_info = next_account_info(account_info_iter)?;
+        let (canonical_key, bump) = CanonicalMarket::find_address(
+            program_id,
+            &order_book.base_mint,
+            &order_book.quote_mint,
+            tick_size,
+            lot_size,
+        );
+        if canonical_info.key != &canonical_key {
+            msg!("Expected the canonical market PDA {}", canonical_key);
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if canonical_info.owner == program_id {
+            msg!("A market already exists for this mint pair, tick and lot size");
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        create_pda_account(
+            program_id,
+            creator_info,
+            canonical_info,
+            system_program_info,
+            &rent,
+            CanonicalMarket::LEN,
+            &[
+                CanonicalMarket::SEED,
+                order_book.base_mint.as_ref(),
+                order_book.quote_mint.as_ref(),
+                &tick_size.to_le_bytes(),
+                &lot_size.to_le_bytes(),
+                &[bump],
+            ],
+        )?;
+        canonical_info.try_borrow_mut_data()?[..CanonicalMarket::LEN]
+            .copy_from_slice(order_book_info.key.as_ref());
+    }
+
+    msg!(
+        "Market initialized: order_book={} base_mint={} quote_mint={} creator={} tick_size={} lot_size={} buy_taker_fee_bps={} sell_taker_fee_bps={} dark_pool={} whole_units={}",
+        order_book_info.key,
+        order_book.base_mint,
+        order_book.quote_mint,
+        order_book.creator,
+        tick_size,
+        lot_size,
+        buy_taker_fee_bps,
+        sell_taker_fee_bps,
+        dark_pool,
+        whole_units
+    );
+
+    Ok(())
+}
+
+// Process the SetCanonicalMarket instruction
+fn process_set_canonical_market(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    canonical: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let config_info = next_account_info(account_info_iter)?;
+    let admin_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+
+    let config = load_config(program_id, config_info)?;
+    if admin_info.key != &config.admin || !admin_info.is_signer {
+        msg!("Only the Config admin may set canonical markets");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if registry_info.owner != program_id {
+        msg!("Registry entry is not owned by this program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    check_account_size(registry_info, MarketRegistryEntry::LEN)?;
+    let mut entry = MarketRegistryEntry::unpack(&registry_info.try_borrow_data()?)?;
+    if registry_info.key != &MarketRegistryEntry::find_address(program_id, &entry.order_book).0 {
+        msg!("Expected a registry entry PDA");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    entry.canonical = canonical;
+    registry_info.try_borrow_mut_data()?[..MarketRegistryEntry::LEN].copy_from_slice(&entry.pack());
+
+    msg!("Market {} canonical: {}", entry.order_book, canonical);
+
+    Ok(())
+}
+
+// Process the SetGuardians instruction
+fn process_set_guardians(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    pause_threshold: u8,
+    guardians: Vec<Pubkey>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let config_info = next_account_info(account_info_iter)?;
+    let admin_info = next_account_info(account_info_iter)?;
+
+    let mut config = load_config(program_id, config_info)?;
+    if admin_info.key != &config.admin || !admin_info.is_signer {
+        msg!("Only the Config admin may set the guardians");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if guardians.len() > Config::MAX_GUARDIANS {
+        msg!("At most {} guardians may be set", Config::MAX_GUARDIANS);
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if (1..guardians.len()).any(|i| guardians[..i].contains(&guardians[i])) {
+        msg!("Guardians must be distinct");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if pause_threshold as usize > guardians.len() || (pause_threshold == 0 && !guardians.is_empty())
+    {
+        msg!("Pause threshold must be between one and the number of guardians");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    config.pause_threshold = pause_threshold;
+    config.guardians = guardians;
+    config_info.try_borrow_mut_data()?[..Config::LEN].copy_from_slice(&config.pack());
+
+    msg!(
+        "Guardians set, {} of {} may pause: {:?}",
+        pause_threshold,
+        config.guardians.len(),
+        config.guardians
+    );
+
+    Ok(())
+}
+
+// Process the SetListingRequirements instruction
+fn process_set_listing_requirements(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    listing: ListingRequirements,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let config_info = next_account_info(account_info_iter)?;
+    let admin_info = next_account_info(account_info_iter)?;
+
+    let mut config = load_config(program_id, config_info)?;
+    if admin_info.key != &config.admin || !admin_info.is_signer {
+        msg!("Only the Config admin may set the listing requirements");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if listing.max_taker_fee_bps as u64 > OrderBook::FEE_BPS_DENOMINATOR {
+        msg!("Taker fee cap must not exceed 100%");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    config.listing = listing;
+    config_info.try_borrow_mut_data()?[..Config::LEN].copy_from_slice(&config.pack());
+
+    msg!("Listing requirements set: {:?}", listing);
+
+    Ok(())
+}
+
+// Process the SetPaused instruction
+fn process_set_paused(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    paused: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let config_info = next_account_info(account_info_iter)?;
+    // The remaining accounts are the signing guardians
+    let guardian_infos = account_info_iter.as_slice();
+
+    let mut config = load_config(program_id, config_info)?;
+    let signed = config
+        .guardians
+        .iter()
+        .filter(|guardian| {
+            guardian_infos
+                .iter()
+                .any(|info| info.key == *guardian && info.is_signer)
+        })
+        .count();
+    if config.pause_threshold == 0 || signed < config.pause_threshold as usize {
+        msg!(
+            "{} of {} required guardians signed",
+            signed,
+            config.pause_threshold
+        );
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    config.paused = paused;
+    config_info.try_borrow_mut_data()?[..Config::LEN].copy_from_slice(&config.pack());
+
+    msg!("Protocol paused: {}", paused);
+
+    Ok(())
+}
+
+// Process the GetBestBuyOrder instruction
+fn process_get_best_buy_order(accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    let order_book = OrderBook::from_account_info(order_book_info)?;
+
+    if order_book.dark_pool {
+        msg!("Dark pool markets do not display resting orders");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // Get the best buy order from the order book
+    let best_buy_order = order_book
+        .get_best_buy_order(&clock)
+        .ok_or(ProgramError::InvalidAccountData)?;
+
+    msg!("Best buy order: {:?}", best_buy_order);
+    set_return_data(&best_buy_order.pack());
+
+    Ok(())
+}
+
+// Process the GetBestSellOrder instruction
+fn process_get_best_sell_order(accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    let order_book = OrderBook::from_account_info(order_book_info)?;
+
+    if order_book.dark_pool {
+        msg!("Dark pool markets do not display resting orders");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // Get the best sell order from the order book
+    let best_sell_order = order_book
+        .get_best_sell_order(&clock)
+        .ok_or(ProgramError::InvalidAccountData)?;
+
+    msg!("Best sell order: {:?}", best_sell_order);
+    set_return_data(&best_sell_order.pack());
+
+    Ok(())
+}
+
+// Process the ExportBook instruction
+fn process_export_book(accounts: &[AccountInfo], cursor: u32) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let order_book = OrderBook::from_account_info(order_book_info)?;
+    if order_book.dark_pool {
+        msg!("Dark pool markets do not display resting orders");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // Walk buy orders then sell orders from the cursor
+    let total = order_book.buy_orders.len() + order_book.sell_orders.len();
+    let page = OrderPage::new(
+        order_book
+            .buy_orders
+            .iter()
+            .chain(order_book.sell_orders.iter())
+            .copied(),
+        cursor,
+    );
+    let start = (cursor as usize).min(total);
+    let end = start + page.items.len();
+
+    msg!(
+        "Exported orders {}..{} of {}, next cursor: {:?}",
+        start,
+        end,
+        total,
+        page.next_cursor
+    );
+    set_return_data(&page.pack());
+
+    Ok(())
+}
+
+// Process the GetDepth instruction
+fn process_get_depth(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_levels: u8,
+    cursor: u32,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+
+    // Read the level index in place rather than unpacking every order
+    let depth = with_order_book(program_id, order_book_info, |view| {
+        if view.dark_pool() {
+            msg!("Dark pool markets do not display resting orders");
+            return Err(ProgramError::InvalidArgument);
+        }
+        let max_levels = (max_levels as usize).min(MarketDepth::MAX_LEVELS);
+        let mut bids = view.levels(OrderType::Buy).skip(cursor as usize);
+        let mut asks = view.levels(OrderType::Sell).skip(cursor as usize);
+        let depth_bids: Vec<PriceLevel> = bids.by_ref().take(max_levels).collect();
+        let depth_asks: Vec<PriceLevel> = asks.by_ref().take(max_levels).collect();
+        let more = bids.next().is_some() || asks.next().is_some();
+        let end = cursor as usize + depth_bids.len().max(depth_asks.len());
+        Ok(MarketDepth {
+            next_cursor: more.then_some(end as u32),
+            bids: depth_bids,
+            asks: depth_asks,
+        })
+    })??;
+
+    msg!(
+        "Returned {} bid and {} ask levels, next cursor: {:?}",
+        depth.bids.len(),
+        depth.asks.len(),
+        depth.next_cursor
+    );
+    set_return_data(&depth.pack());
+
+    Ok(())
+}
+
+// Process the GetOpenOrders instruction
+fn process_get_open_orders(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    trader: &Pubkey,
+    cursor: u32,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let order_book = load_order_book(program_id, order_book_info)?;
+    if order_book.dark_pool {
+        msg!("Dark pool markets do not display resting orders");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // Walk the resting orders, then the orders waiting off the book
+    let page = OrderPage::new(
+        order_book
+            .buy_orders
+            .iter()
+            .chain(order_book.sell_orders.iter())
+            .chain(order_book.stop_orders.iter())
+            .chain(order_book.pending_orders.iter())
+            .chain(order_book.reference_orders.iter())
+            .filter(|order| order.trader == *trader)
+            .copied(),
+        cursor,
+    );
+
+    msg!(
+        "Returned {} orders of {}, next cursor: {:?}",
+        page.items.len(),
+        trader,
+        page.next_cursor
+    );
+    set_return_data(&page.pack());
+
+    Ok(())
+}
+
+// Process the GetInventory instruction
+fn process_get_inventory(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let open_orders_info = next_account_info(account_info_iter)?;
+    let order_book = load_order_book(program_id, order_book_info)?;
+    if order_book.dark_pool {
+        msg!("Dark pool markets do not display resting orders");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let open_orders = load_open_orders(program_id, open_orders_info, &order_book.quote_mint)?;
+
+    let report = InventoryReport::new(&order_book, order_book_info.key, &open_orders)?;
+    msg!("Inventory: {:?}", report);
+    set_return_data(&report.pack());
+
+    Ok(())
+}
+
+// Process the GetCandles instruction
+fn process_get_candles(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    start_time: UnixTimestamp,
+    end_time: UnixTimestamp,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let candle_series_info = next_account_info(account_info_iter)?;
+
+    let order_book = load_order_book(program_id, order_book_info)?;
+    if !order_book.candle_series.contains(candle_series_info.key) {
+        msg!("Expected one of the market's CandleSeries accounts");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let data = candle_series_info.try_borrow_data()?;
+    let series = CandleSeries::unpack(&data)?;
+    let mut candles = series
+        .candles(&data)?
+        .into_iter()
+        .filter(|candle| candle.start_time >= start_time && candle.start_time < end_time);
+    let page_candles: Vec<Candle> = candles.by_ref().take(CandlePage::MAX_CANDLES).collect();
+    let page = CandlePage {
+        next_start_time: candles.next().map(|candle| candle.start_time),
+        candles: page_candles,
+    };
+
+    msg!(
+        "Returned {} candles of interval {}, next start time: {:?}",
+        page.candles.len(),
+        series.interval,
+        page.next_start_time
+    );
+    set_return_data(&page.pack());
+
+    Ok(())
+}
+
+// Process the VerifyMarket instruction
+fn process_verify_market(accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    let order_book = OrderBook::from_account_info(order_book_info)?;
+
+    let report = VerifyMarketReport::verify(&order_book, &clock);
+    msg!("VerifyMarket report: {:?}", report);
+    if report.crossed {
+        msg!("Order book is crossed; HealBook matches the crossed orders");
+    }
+    set_return_data(&report.pack());
+
+    Ok(())
+}
+
+// Process the GetMarketStats instruction
+fn process_get_market_stats(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let clock: Clock = next_sysvar(account_info_iter)?;
+    let order_book = load_order_book(program_id, order_book_info)?;
+
+    let stats = MarketStats::new(&order_book, clock.epoch);
+    msg!("Market stats: {:?}", stats);
+    set_return_data(&stats.pack());
+
+    Ok(())
+}
+
+// Process the GetOrderStatuses instruction
+fn process_get_order_statuses(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order_ids: &[u64],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let order_book_info = next_account_info(account_info_iter)?;
+    let order_book = load_order_book(program_id, order_book_info)?;
+    if order_ids.len() > OrderStatusReport::MAX_ORDERS {
+        msg!(
+            "At most {} order ids may be queried at once",
+            OrderStatusReport::MAX_ORDERS
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    let report = OrderStatusReport::new(&order_book, order_ids);
+    msg!("Order statuses: {:?}", report.statuses);
+    set_return_data(&report.pack());
+
+    Ok(())
+}
+
+// Process the GetVersion instruction
+fn process_get_version() -> ProgramResult {
+    let version = ProgramVersion::current();
+    msg!("Program version: {:?}", version);
+    set_return_data(&version.pack());
+
+    Ok(())
+}
+
+// Check the leading accounts against the instruction's expected accounts
+fn check_accounts(instruction: &OrderBookInstruction, accounts: &[AccountInfo]) -> ProgramResult {
+    let expected = instruction.expected_accounts();
+    if accounts.len() < expected.len() {
+        msg!(
+            "Missing account {}: expected at least {} accounts, got {}",
+            expected[accounts.len()].name,
+            expected.len(),
+            accounts.len()
+        );
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    for (spec, account) in expected.iter().zip(accounts) {
+        if spec.signer && !account.is_signer {
+            msg!("Account {} must sign: {}", spec.name, account.key);
+            return Err(OrderBookError::MissingRequiredSigner.into());
+        }
+        if spec.writable && !account.is_writable {
+            msg!("Account {} must be writable: {}", spec.name, account.key);
+            return Err(OrderBookError::AccountNotWritable.into());
+        }
+    }
+    // Every writable role holds its own account, so no balance is read from
+    // one role and written back through another
+    check_distinct_accounts(
+        expected
+            .iter()
+            .zip(accounts)
+            .filter(|(spec, _)| spec.writable)
+            .map(|(_, account)| account),
+    )
+}
+
+// Check no account appears twice among `accounts`
+fn check_distinct_accounts<'b, 'a: 'b>(
+    accounts: impl Iterator<Item = &'b AccountInfo<'a>>,
+) -> ProgramResult {
+    let mut keys: Vec<&Pubkey> = vec![];
+    for account in accounts {
+        if keys.contains(&account.key) {
+            msg!("Account {} is passed in two distinct roles", account.key);
+            return Err(OrderBookError::DuplicateAccount.into());
+        }
+        keys.push(account.key);
+    }
+    Ok(())
+}
+
+// Define the instruction processor function
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    if instruction_data.is_empty() {
+        msg!("No instruction data provided");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    // Parse the instruction data
+    let instruction = match OrderBookInstruction::unpack(instruction_data) {
+        Ok(instruction) => instruction,
+        Err(err) => {
+            msg!("Failed to unpack instruction data: {:?}", err);
+            return Err(err);
+        }
+    };
+
+    check_accounts(&instruction, accounts)?;
+
+    #[cfg(feature = "metrics")]
+    let metering = ComputeMetering::start(&instruction);
+
+    // Route the instruction to the appropriate handler
+    let result = match instruction {
+        OrderBookInstruction::PlaceOrder(order) => {
+            msg!("Instruction: PlaceOrder");
+            process_place_order(program_id, accounts, order)
+        }
+        OrderBookInstruction::GetBestBuyOrder => {
+            msg!("Instruction: GetBestBuyOrder");
+            process_get_best_buy_order(accounts)
+        }
+        OrderBookInstruction::GetBestSellOrder => {
+            msg!("Instruction: GetBestSellOrder");
+            process_get_best_sell_order(accounts)
+        }
+        OrderBookInstruction::ExportBook { cursor } => {
+            msg!("Instruction: ExportBook");
+            process_export_book(accounts, cursor)
+        }
+        OrderBookInstruction::VerifyMarket => {
+            msg!("Instruction: VerifyMarket");
+            process_verify_market(accounts)
+        }
+        OrderBookInstruction::CancelOrder { order_id } => {
+            msg!("Instruction: CancelOrder");
+            process_cancel_order(program_id, accounts, order_id)
+        }
+        OrderBookInstruction::CancelOrdersByPrice {
+            side,
+            limit_price,
+            max_count,
+        } => {
+            msg!("Instruction: CancelOrdersByPrice");
+            process_cancel_orders_by_price(program_id, accounts, side, limit_price, max_count)
+        }
+        OrderBookInstruction::PruneOrder { order_id } => {
+            msg!("Instruction: PruneOrder");
+            process_prune_order(program_id, accounts, order_id)
+        }
+        OrderBookInstruction::CancelExpiredOrder { order_id } => {
+            msg!("Instruction: CancelExpiredOrder");
+            process_cancel_expired_order(program_id, accounts, order_id)
+        }
+        OrderBookInstruction::InitializeConfig {
+            default_tick_size,
+            default_lot_size,
+            unique_markets,
+            protocol_fee_bps,
+        } => {
+            msg!("Instruction: InitializeConfig");
+            process_initialize_config(
+                program_id,
+                accounts,
+                default_tick_size,
+                default_lot_size,
+                unique_markets,
+                protocol_fee_bps,
+            )
+        }
+        OrderBookInstruction::InitializeMarket {
+            tick_size,
+            lot_size,
+            buy_taker_fee_bps,
+            sell_taker_fee_bps,
+            backstop_discount_bps,
+            dark_pool,
+            oracle_source,
+            oracle,
+            whole_units,
+        } => {
+            msg!("Instruction: InitializeMarket");
+            process_initialize_market(
+                program_id,
+                accounts,
+                tick_size,
+                lot_size,
+                buy_taker_fee_bps,
+                sell_taker_fee_bps,
+                backstop_discount_bps,
+                dark_pool,
+                oracle_source,
+                oracle,
+                whole_units,
+            )
+        }
+        OrderBookInstruction::SetCanonicalMarket { canonical } => {
+            msg!("Instruction: SetCanonicalMarket");
+            process_set_canonical_market(program_id, accounts, canonical)
+        }
+        OrderBookInstruction::SetGuardians {
+            pause_threshold,
+            guardians,
+        } => {
+            msg!("Instruction: SetGuardians");
+            process_set_guardians(program_id, accounts, pause_threshold, guardians)
+        }
+        OrderBookInstruction::SetPaused { paused } => {
+            msg!("Instruction: SetPaused");
+            process_set_paused(program_id, accounts, paused)
+        }
+        OrderBookInstruction::PruneExpiredOrders { max_orders } => {
+            msg!("Instruction: PruneExpiredOrders");
+            process_prune_expired_orders(program_id, accounts, max_orders)
+        }
+        OrderBookInstruction::GetDepth { max_levels, cursor } => {
+            msg!("Instruction: GetDepth");
+            process_get_depth(program_id, accounts, max_levels, cursor)
+        }
+        OrderBookInstruction::InitOpenOrders => {
+            msg!("Instruction: InitOpenOrders");
+            process_init_open_orders(program_id, accounts)
+        }
+        OrderBookInstruction::SettleFunds {
+            max_base,
+            max_quote,
+            recipient,
+        } => {
+            msg!("Instruction: SettleFunds");
+            process_settle_funds(program_id, accounts, (max_base, max_quote), recipient)
+        }
+        OrderBookInstruction::SettleAndWithdrawAll => {
+            msg!("Instruction: SettleAndWithdrawAll");
+            process_settle_and_withdraw_all(program_id, accounts)
+        }
+        OrderBookInstruction::SweepFees => {
+            msg!("Instruction: SweepFees");
+            process_sweep_fees(program_id, accounts)
+        }
+        OrderBookInstruction::SweepProtocolFees => {
+            msg!("Instruction: SweepProtocolFees");
+            process_sweep_protocol_fees(program_id, accounts)
+        }
+        OrderBookInstruction::SetBackstopMaker { maker, backstop } => {
+            msg!("Instruction: SetBackstopMaker");
+            process_set_backstop_maker(program_id, accounts, maker, backstop)
+        }
+        OrderBookInstruction::FundIncentives { amount } => {
+            msg!("Instruction: FundIncentives");
+            process_fund_incentives(program_id, accounts, amount)
+        }
+        OrderBookInstruction::TriggerStopOrder { order_id } => {
+            msg!("Instruction: TriggerStopOrder");
+            process_trigger_stop_order(program_id, accounts, order_id)
+        }
+        OrderBookInstruction::SettleAuction { order_id } => {
+            msg!("Instruction: SettleAuction");
+            process_settle_auction(program_id, accounts, order_id)
+        }
+        OrderBookInstruction::SetBeneficialOwner { beneficial_owner } => {
+            msg!("Instruction: SetBeneficialOwner");
+            process_set_beneficial_owner(program_id, accounts, beneficial_owner)
+        }
+        OrderBookInstruction::GetOpenOrders { trader, cursor } => {
+            msg!("Instruction: GetOpenOrders");
+            process_get_open_orders(program_id, accounts, &trader, cursor)
+        }
+        OrderBookInstruction::SettleReferenceOrders { settle_at } => {
+            msg!("Instruction: SettleReferenceOrders");
+            process_settle_reference_orders(program_id, accounts, settle_at)
+        }
+        OrderBookInstruction::CancelAllAfter { slot } => {
+            msg!("Instruction: CancelAllAfter");
+            process_cancel_all_after(program_id, accounts, slot)
+        }
+        OrderBookInstruction::SetPriorityAuction { enabled } => {
+            msg!("Instruction: SetPriorityAuction");
+            process_set_priority_auction(program_id, accounts, enabled)
+        }
+        OrderBookInstruction::InitBlocklist => {
+            msg!("Instruction: InitBlocklist");
+            process_init_blocklist(program_id, accounts)
+        }
+        OrderBookInstruction::UpdateBlocklist { address, blocked } => {
+            msg!("Instruction: UpdateBlocklist");
+            process_update_blocklist(program_id, accounts, &address, blocked)
+        }
+        OrderBookInstruction::SetBlocklist { blocklist } => {
+            msg!("Instruction: SetBlocklist");
+            process_set_blocklist(program_id, accounts, blocklist)
+        }
+        OrderBookInstruction::BondKeeper { lamports } => {
+            msg!("Instruction: BondKeeper");
+            process_bond_keeper(program_id, accounts, lamports)
+        }
+        OrderBookInstruction::UnbondKeeper { lamports } => {
+            msg!("Instruction: UnbondKeeper");
+            process_unbond_keeper(program_id, accounts, lamports)
+        }
+        OrderBookInstruction::SetListingRequirements(listing) => {
+            msg!("Instruction: SetListingRequirements");
+            process_set_listing_requirements(program_id, accounts, listing)
+        }
+        OrderBookInstruction::GetInventory => {
+            msg!("Instruction: GetInventory");
+            process_get_inventory(program_id, accounts)
+        }
+        OrderBookInstruction::TransferFreeBalance {
+            base_amount,
+            quote_amount,
+        } => {
+            msg!("Instruction: TransferFreeBalance");
+            process_transfer_free_balance(program_id, accounts, (base_amount, quote_amount))
+        }
+        OrderBookInstruction::DryRun(_) => {
+            msg!("Instruction: DryRun");
+            process_dry_run(program_id, accounts, &instruction_data[1..])
+        }
+        OrderBookInstruction::SetOrderGate { order_gate } => {
+            msg!("Instruction: SetOrderGate");
+            process_set_order_gate(program_id, accounts, order_gate)
+        }
+        OrderBookInstruction::SetWithdrawAuthority { withdraw_authority } => {
+            msg!("Instruction: SetWithdrawAuthority");
+            process_set_withdraw_authority(program_id, accounts, withdraw_authority)
+        }
+        OrderBookInstruction::SetOracle {
+            oracle_source,
+            oracle,
+        } => {
+            msg!("Instruction: SetOracle");
+            process_set_oracle(program_id, accounts, oracle_source, oracle)
+        }
+        OrderBookInstruction::InitPriceOracle => {
+            msg!("Instruction: InitPriceOracle");
+            process_init_price_oracle(program_id, accounts)
+        }
+        OrderBookInstruction::UpdatePriceOracle => {
+            msg!("Instruction: UpdatePriceOracle");
+            process_update_price_oracle(program_id, accounts)
+        }
+        OrderBookInstruction::InitTradeHistory { capacity } => {
+            msg!("Instruction: InitTradeHistory");
+            process_init_trade_history(program_id, accounts, capacity)
+        }
+        OrderBookInstruction::InitCandleSeries { interval, capacity } => {
+            msg!("Instruction: InitCandleSeries");
+            process_init_candle_series(program_id, accounts, interval, capacity)
+        }
+        OrderBookInstruction::GetCandles {
+            start_time,
+            end_time,
+        } => {
+            msg!("Instruction: GetCandles");
+            process_get_candles(program_id, accounts, start_time, end_time)
+        }
+        OrderBookInstruction::GetMarketStats => {
+            msg!("Instruction: GetMarketStats");
+            process_get_market_stats(program_id, accounts)
+        }
+        OrderBookInstruction::GetOrderStatuses { order_ids } => {
+            msg!("Instruction: GetOrderStatuses");
+            process_get_order_statuses(program_id, accounts, &order_ids)
+        }
+        OrderBookInstruction::WriteFillReceipt { order_id } => {
+            msg!("Instruction: WriteFillReceipt");
+            process_write_fill_receipt(program_id, accounts, order_id)
+        }
+        OrderBookInstruction::CloseFillReceipt => {
+            msg!("Instruction: CloseFillReceipt");
+            process_close_fill_receipt(program_id, accounts)
+        }
+        OrderBookInstruction::GetVersion => {
+            msg!("Instruction: GetVersion");
+            process_get_version()
+        }
+        OrderBookInstruction::SetPlacementFee { lamports } => {
+            msg!("Instruction: SetPlacementFee");
+            process_set_placement_fee(program_id, accounts, lamports)
+        }
+        OrderBookInstruction::SetPruneAfter { slots } => {
+            msg!("Instruction: SetPruneAfter");
+            process_set_prune_after(program_id, accounts, slots)
+        }
+        OrderBookInstruction::SetOrderRateLimit {
+            max_orders_per_slot,
+        } => {
+            msg!("Instruction: SetOrderRateLimit");
+            process_set_order_rate_limit(program_id, accounts, max_orders_per_slot)
+        }
+        OrderBookInstruction::SetOracleConfidence { max_conf_bps } => {
+            msg!("Instruction: SetOracleConfidence");
+            process_set_oracle_confidence(program_id, accounts, max_conf_bps)
+        }
+        OrderBookInstruction::SetRoundingPolicy { policy } => {
+            msg!("Instruction: SetRoundingPolicy");
+            process_set_rounding_policy(program_id, accounts, policy)
+        }
+        OrderBookInstruction::RouteOrder {
+            side,
+            amount,
+            limit_price,
+            min_fill_amount,
+            market_account_counts,
+        } => {
+            msg!("Instruction: RouteOrder");
+            process_route_order(
+                program_id,
+                accounts,
+                side,
+                amount,
+                limit_price,
+                min_fill_amount,
+                market_account_counts,
+            )
+        }
+        OrderBookInstruction::MigrateLiquidity { max_orders } => {
+            msg!("Instruction: MigrateLiquidity");
+            process_migrate_liquidity(program_id, accounts, max_orders)
+        }
+        OrderBookInstruction::FundEpochRewards { amount } => {
+            msg!("Instruction: FundEpochRewards");
+            process_fund_epoch_rewards(program_id, accounts, amount)
+        }
+        OrderBookInstruction::ClaimEpochRewards => {
+            msg!("Instruction: ClaimEpochRewards");
+            process_claim_epoch_rewards(program_id, accounts)
+        }
+        OrderBookInstruction::SetMarketFeatures { disabled_features } => {
+            msg!("Instruction: SetMarketFeatures");
+            process_set_market_features(program_id, accounts, disabled_features)
+        }
+        OrderBookInstruction::HealBook { max_fills } => {
+            msg!("Instruction: HealBook");
+            process_heal_book(program_id, accounts, max_fills)
+        }
+        OrderBookInstruction::InitLaunchAuction {
+            supply,
+            reserve_price,
+            bid_end,
+            reveal_end,
+        } => {
+            msg!("Instruction: InitLaunchAuction");
+            process_init_launch_auction(
+                program_id,
+                accounts,
+                supply,
+                reserve_price,
+                bid_end,
+                reveal_end,
+            )
+        }
+        OrderBookInstruction::CommitLaunchBid {
+            quantity,
+            deposit,
+            commitment,
+        } => {
+            msg!("Instruction: CommitLaunchBid");
+            process_commit_launch_bid(program_id, accounts, quantity, deposit, commitment)
+        }
+        OrderBookInstruction::RevealLaunchBid { price, salt } => {
+            msg!("Instruction: RevealLaunchBid");
+            process_reveal_launch_bid(program_id, accounts, price, salt)
+        }
+        OrderBookInstruction::ClearLaunchAuction => {
+            msg!("Instruction: ClearLaunchAuction");
+            process_clear_launch_auction(program_id, accounts)
+        }
+        OrderBookInstruction::SettleLaunchBids => {
+            msg!("Instruction: SettleLaunchBids");
+            process_settle_launch_bids(program_id, accounts)
+        }
+    };
+
+    #[cfg(feature = "metrics")]
+    metering.finish(&result);
+
+    result
+}
+
+// Record the compute units left when a handler starts, to log what it
+// consumed once it returns
+#[cfg(feature = "metrics")]
+struct ComputeMetering {
+    instruction: String,
+    units_at_entry: u64,
+}
+
+#[cfg(feature = "metrics")]
+impl ComputeMetering {
+    fn start(instruction: &OrderBookInstruction) -> Self {
+        // The variant name, without its fields
+        let mut name = format!("{:?}", instruction);
+        name.truncate(name.find([' ', '(', '{']).unwrap_or(name.len()));
+        ComputeMetering {
+            instruction: name,
+            units_at_entry: solana_program::compute_units::sol_remaining_compute_units(),
+        }
+    }
+
+    // Log the compute units as a single key=value line operators can grep
+    // from transaction logs
+    fn finish(self, result: &ProgramResult) {
+        let units_at_exit = solana_program::compute_units::sol_remaining_compute_units();
+        msg!(
+            "ComputeUnits: instruction={} entry={} exit={} consumed={} ok={}",
+            self.instruction,
+            self.units_at_entry,
+            units_at_exit,
+            self.units_at_entry.saturating_sub(units_at_exit),
+            result.is_ok()
+        );
+    }
+}
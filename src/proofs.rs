@@ -0,0 +1,147 @@
+// Kani proof harnesses for the matching and balance accounting invariants
+//
+// Compiled only under `cargo kani`, which sets `cfg(kani)`. The harnesses
+// cover the syscall-free core: `OrderBook::match_order` and the
+// `OpenOrdersMarket` balance updates that settle its fills.
+
+use crate::state::{ExpiryUnit, OpenOrdersMarket, Order, OrderBook, OrderType, PegType};
+use solana_program::{clock::Clock, pubkey::Pubkey};
+
+fn any_balances() -> OpenOrdersMarket {
+    OpenOrdersMarket {
+        market: Pubkey::default(),
+        base_free: kani::any(),
+        base_locked: kani::any(),
+        quote_free: kani::any(),
+        quote_locked: kani::any(),
+        order_count: kani::any(),
+        active_epoch_end: 0,
+        ..OpenOrdersMarket::default()
+    }
+}
+
+// A fixed-price limit order of its own trader with any price and amount
+fn any_order(order_type: OrderType, order_id: u64) -> Order {
+    let trader = Pubkey::new_from_array([order_id as u8; 32]);
+    Order {
+        trader,
+        amount: kani::any(),
+        price: kani::any(),
+        order_type,
+        slot: 0,
+        unix_timestamp: 0,
+        order_id,
+        expires_at: 0,
+        bounty: 0,
+        peg: PegType::None,
+        peg_ticks: 0,
+        linked_order_id: None,
+        trail_ticks: 0,
+        trigger_price: 0,
+        improvement_slots: 0,
+        avg_price_limit: 0,
+        min_fill_amount: 0,
+        beneficial_owner: trader,
+        decay_start_price: 0,
+        decay_end: 0,
+        quote_budget: 0,
+        expiry_unit: ExpiryUnit::UnixTime,
+        filled_amount: 0,
+        filled_quote: 0,
+        fees_paid: 0,
+        tag: 0,
+        priority_bid: 0,
+        settle_at: 0,
+    }
+}
+
+// Total base and quote held, free or locked, across `balances`
+fn totals(balances: &[&OpenOrdersMarket]) -> (u128, u128) {
+    balances.iter().fold((0, 0), |(base, quote), balances| {
+        (
+            base + balances.base_free as u128 + balances.base_locked as u128,
+            quote + balances.quote_free as u128 + balances.quote_locked as u128,
+        )
+    })
+}
+
+// Settling a fill between a taker bid and a maker ask moves base to the
+// taker and quote to the maker, conserving both, and never underflows the
+// locked balances that back it
+#[kani::proof]
+fn fill_conserves_balances() {
+    let mut taker = any_balances();
+    let mut maker = any_balances();
+    let amount: u64 = kani::any();
+    let quote: u64 = kani::any();
+    let locked_quote: u64 = kani::any();
+    kani::assume(quote <= locked_quote && locked_quote <= taker.quote_locked);
+    kani::assume(amount <= maker.base_locked);
+    kani::assume(taker.quote_free.checked_add(locked_quote).is_some());
+    kani::assume(taker.base_free.checked_add(amount).is_some());
+    kani::assume(maker.quote_free.checked_add(quote).is_some());
+
+    let before = totals(&[&taker, &maker]);
+    taker.fill(OrderType::Buy, amount, quote, locked_quote);
+    maker.fill(OrderType::Sell, amount, quote, 0);
+    assert_eq!(totals(&[&taker, &maker]), before);
+}
+
+// Releasing funds an order locked returns them to the free balance
+#[kani::proof]
+fn unlock_returns_locked_funds() {
+    let mut balances = any_balances();
+    let order_type = if kani::any() {
+        OrderType::Buy
+    } else {
+        OrderType::Sell
+    };
+    let amount: u64 = kani::any();
+    let (locked, free) = match order_type {
+        OrderType::Buy => (balances.quote_locked, balances.quote_free),
+        OrderType::Sell => (balances.base_locked, balances.base_free),
+    };
+    kani::assume(amount <= locked && free.checked_add(amount).is_some());
+
+    let before = totals(&[&balances]);
+    balances.unlock(order_type, amount);
+    assert_eq!(totals(&[&balances]), before);
+}
+
+// Matching a taker bid against two resting asks takes from the makers
+// exactly what it fills, only at prices within the taker's limit, and
+// removes exactly the makers it fills completely
+#[kani::proof]
+#[kani::unwind(4)]
+fn match_order_conserves_amounts() {
+    let mut order_book = OrderBook {
+        tick_size: 1,
+        lot_size: 1,
+        ..OrderBook::default()
+    };
+    order_book.sell_orders = vec![any_order(OrderType::Sell, 1), any_order(OrderType::Sell, 2)];
+    for maker in &order_book.sell_orders {
+        kani::assume(maker.amount > 0 && maker.price > 0);
+    }
+    let mut taker = any_order(OrderType::Buy, 3);
+    kani::assume(taker.amount > 0 && taker.price > 0);
+    let resting = |order_book: &OrderBook| -> u128 {
+        order_book
+            .sell_orders
+            .iter()
+            .map(|order| order.amount as u128)
+            .sum()
+    };
+
+    let resting_before = resting(&order_book);
+    let taker_before = taker.amount;
+    let (fills, filled_makers) = order_book.match_order(&mut taker, &Clock::default());
+    let filled: u128 = fills.iter().map(|fill| fill.amount as u128).sum();
+    assert_eq!((taker_before - taker.amount) as u128, filled);
+    assert_eq!(resting_before - resting(&order_book), filled);
+    assert!(fills
+        .iter()
+        .all(|fill| fill.amount > 0 && fill.price <= taker.price));
+    assert!(filled_makers.iter().all(|maker| maker.amount == 0));
+    assert_eq!(order_book.sell_orders.len() + filled_makers.len(), 2);
+}
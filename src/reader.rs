@@ -0,0 +1,185 @@
+// Read-only access to order book accounts for other on-chain programs
+//
+// The reading itself lives in the `fordex-reader` crate, re-exported here as
+// `fordex_reader`. It is `no_std`, links neither std nor solana-program and
+// reads the order book through its own copy of the layout, so a program
+// that only needs the best bid and ask (a lending protocol pricing
+// collateral, say) can depend on it alone. This module adapts it to
+// `solana_program` types for programs that link this crate with the
+// `no-entrypoint` feature: keys as `Pubkey`, `Clock` for the time orders
+// are read at, `ProgramError` for errors, whole `Order`s, and
+// `with_order_book` to check an `AccountInfo` before reading it.
+
+pub use fordex_reader;
+
+use crate::{
+    error::OrderBookError,
+    state::{Order, OrderBook, OrderType, PriceLevel},
+};
+use fordex_reader::{Now, ReaderError, Side};
+use solana_program::{
+    account_info::AccountInfo, clock::Clock, msg, program_error::ProgramError, pubkey::Pubkey,
+};
+
+fn side(order_type: OrderType) -> Side {
+    match order_type {
+        OrderType::Buy => Side::Buy,
+        OrderType::Sell => Side::Sell,
+    }
+}
+
+fn now(clock: &Clock) -> Now {
+    Now {
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    }
+}
+
+// Log a reader error and map it to the error the program returns for the
+// same account
+fn program_error(err: ReaderError) -> ProgramError {
+    match err {
+        ReaderError::UnsupportedLayoutVersion => {
+            msg!("Order book has a newer layout version than this reader");
+            OrderBookError::UnsupportedLayoutVersion.into()
+        }
+        ReaderError::OutdatedLayoutVersion => {
+            msg!("Order book has an older layout of another size");
+            OrderBookError::OutdatedLayoutVersion.into()
+        }
+        ReaderError::AccountDataTooSmall => {
+            msg!("Order book account is too small");
+            ProgramError::AccountDataTooSmall
+        }
+        ReaderError::UninitializedAccount => {
+            msg!("Market is not initialized");
+            ProgramError::UninitializedAccount
+        }
+        ReaderError::InvalidAccountData => {
+            msg!("Order book account holds an invalid count or order");
+            ProgramError::InvalidAccountData
+        }
+        ReaderError::OrderNotFound => OrderBookError::OrderNotFound.into(),
+    }
+}
+
+// Define a borrowed view of an initialized order book account's data
+#[derive(Clone, Copy, Debug)]
+pub struct OrderBookView<'a> {
+    view: fordex_reader::OrderBookView<'a>,
+    data: &'a [u8],
+}
+
+impl<'a> OrderBookView<'a> {
+    // Check the data holds an initialized order book of a supported layout
+    pub fn new(data: &'a [u8]) -> Result<Self, ProgramError> {
+        let view = fordex_reader::OrderBookView::new(data).map_err(program_error)?;
+        Ok(OrderBookView { view, data })
+    }
+
+    // Layout version the account was last written with
+    pub fn layout_version(&self) -> u8 {
+        self.view.layout_version()
+    }
+
+    pub fn base_mint(&self) -> Pubkey {
+        Pubkey::new_from_array(self.view.base_mint())
+    }
+
+    pub fn quote_mint(&self) -> Pubkey {
+        Pubkey::new_from_array(self.view.quote_mint())
+    }
+
+    pub fn tick_size(&self) -> u64 {
+        self.view.tick_size()
+    }
+
+    pub fn lot_size(&self) -> u64 {
+        self.view.lot_size()
+    }
+
+    pub fn last_trade_price(&self) -> u64 {
+        self.view.last_trade_price()
+    }
+
+    pub fn seq_num(&self) -> u64 {
+        self.view.seq_num()
+    }
+
+    pub fn book_checksum(&self) -> u64 {
+        self.view.book_checksum()
+    }
+
+    pub fn dark_pool(&self) -> bool {
+        self.view.dark_pool()
+    }
+
+    // Number of resting orders on a side
+    pub fn order_count(&self, side: OrderType) -> usize {
+        self.view.order_count(self::side(side))
+    }
+
+    // Unpack the resting order at `index` of a side
+    pub fn order(&self, side: OrderType, index: usize) -> Result<Order, ProgramError> {
+        if index >= self.order_count(side) {
+            return Err(OrderBookError::OrderNotFound.into());
+        }
+        Order::unpack(&self.data[OrderBook::order_offset(side, index)..])
+    }
+
+    // Unpack the resting orders of a side one at a time, in book order
+    pub fn orders(
+        &self,
+        side: OrderType,
+    ) -> impl Iterator<Item = Result<Order, ProgramError>> + 'a {
+        let view = *self;
+        (0..self.order_count(side)).map(move |index| view.order(side, index))
+    }
+
+    // Number of price levels in the index of a side
+    pub fn level_count(&self, side: OrderType) -> usize {
+        self.view.level_count(self::side(side))
+    }
+
+    // Read the price levels of a side from the index, best first. See
+    // `OrderBook::price_levels` for the orders they aggregate.
+    pub fn levels(&self, side: OrderType) -> impl Iterator<Item = PriceLevel> + 'a {
+        self.view.levels(self::side(side)).map(|level| PriceLevel {
+            price: level.price,
+            amount: level.amount,
+            order_count: level.order_count,
+        })
+    }
+
+    // Best live bid: the highest price a resting buy order trades at, pegged
+    // orders and Dutch auctions priced as the matcher prices them. A dark
+    // pool market reports no best orders.
+    pub fn best_bid(&self, now: &Clock) -> Result<Option<u64>, ProgramError> {
+        self.view.best_bid(self::now(now)).map_err(program_error)
+    }
+
+    // Best live ask, the lowest price a resting sell order trades at
+    pub fn best_ask(&self, now: &Clock) -> Result<Option<u64>, ProgramError> {
+        self.view.best_ask(self::now(now)).map_err(program_error)
+    }
+}
+
+// Check `order_book_info` is an order book of the Fordex program deployed at
+// `program_id` and read it through an OrderBookView
+pub fn with_order_book<R>(
+    program_id: &Pubkey,
+    order_book_info: &AccountInfo,
+    read: impl FnOnce(&OrderBookView) -> R,
+) -> Result<R, ProgramError> {
+    if order_book_info.owner != program_id {
+        msg!("Order book account is not owned by the Fordex program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let data = order_book_info.try_borrow_data()?;
+    let view = OrderBookView::new(&data)?;
+    if data.len() != OrderBook::LEN {
+        msg!("Order book account has the wrong size");
+        return Err(OrderBookError::InvalidAccountSize.into());
+    }
+    Ok(read(&view))
+}
@@ -0,0 +1,258 @@
+// Off-chain reconstruction of a market's book from its logged events
+//
+// Every instruction that changes a market's resting orders logs a
+// BookChangeEvent and every fill a FillEvent. Replaying a market's events in
+// order from a snapshot rebuilds its L2 book, resting order ids and trade
+// tape exactly, so maker strategies can be backtested against historical
+// markets without an archive of account states.
+//
+// `BookDiff` compares two decoded snapshots of a market's account, for
+// monitoring tools and for checking a replayed market against the account
+// fetched at the same sequence number.
+
+use crate::state::{BookChangeEvent, FillEvent, Order, OrderBook, OrderType};
+use solana_program::{
+    clock::{Clock, Slot},
+    pubkey::Pubkey,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+// Define an event a market logged, in the order the program logged it
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MarketEvent {
+    BookChange(BookChangeEvent),
+    Fill(FillEvent),
+}
+
+// Define the reasons a sequence of events cannot be replayed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplayError {
+    // The event belongs to another market
+    WrongMarket { event_market: Pubkey },
+    // Events between the replayed sequence number and the event's are missing
+    SequenceGap { expected: u64, found: u64 },
+    // The event removes an order that is not resting on the replayed book
+    UnknownOrder { order_id: u64 },
+    // The event's checksum does not follow from the replayed checksum, so
+    // the replica diverged from the market or started from a wrong snapshot
+    ChecksumMismatch { seq_num: u64 },
+}
+
+// Define the state of a market rebuilt from its events
+//
+// `bids` and `asks` map each price level to its total resting amount.
+// `seq_num`, `checksum` and `slot` are those of the last book change
+// applied, and `fills` the fills in the order they executed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayedMarket {
+    pub market: Pubkey,
+    pub seq_num: u64,
+    pub checksum: u64,
+    pub slot: Slot,
+    pub bids: BTreeMap<u64, u64>,
+    pub asks: BTreeMap<u64, u64>,
+    pub resting_orders: BTreeSet<u64>,
+    pub fills: Vec<FillEvent>,
+}
+
+impl ReplayedMarket {
+    // Start from an empty book, before the market's first change
+    pub fn new(market: Pubkey) -> Self {
+        ReplayedMarket {
+            market,
+            ..ReplayedMarket::default()
+        }
+    }
+
+    // Start from a snapshot of the order book account at `now`
+    pub fn from_order_book(market: Pubkey, order_book: &OrderBook, now: &Clock) -> Self {
+        let mut replayed = ReplayedMarket::new(market);
+        replayed.seq_num = order_book.seq_num;
+        replayed.checksum = order_book.book_checksum;
+        for (side, price, amount) in order_book.levels(now) {
+            replayed.side_mut(side).insert(price, amount);
+        }
+        replayed.resting_orders = order_book
+            .buy_orders
+            .iter()
+            .chain(order_book.sell_orders.iter())
+            .map(|order| order.order_id)
+            .collect();
+        replayed
+    }
+
+    // Replay `events` in order from an empty book
+    pub fn replay<'a>(
+        market: Pubkey,
+        events: impl IntoIterator<Item = &'a MarketEvent>,
+    ) -> Result<Self, ReplayError> {
+        let mut replayed = ReplayedMarket::new(market);
+        for event in events {
+            replayed.apply(event)?;
+        }
+        Ok(replayed)
+    }
+
+    // Apply the next event of the market
+    pub fn apply(&mut self, event: &MarketEvent) -> Result<(), ReplayError> {
+        match event {
+            MarketEvent::BookChange(change) => self.apply_book_change(change),
+            MarketEvent::Fill(fill) => {
+                self.fills.push(*fill);
+                Ok(())
+            }
+        }
+    }
+
+    fn apply_book_change(&mut self, change: &BookChangeEvent) -> Result<(), ReplayError> {
+        if change.market != self.market {
+            return Err(ReplayError::WrongMarket {
+                event_market: change.market,
+            });
+        }
+        if change.seq_num != self.seq_num + 1 {
+            return Err(ReplayError::SequenceGap {
+                expected: self.seq_num + 1,
+                found: change.seq_num,
+            });
+        }
+        if change.next_checksum(self.checksum) != change.checksum {
+            return Err(ReplayError::ChecksumMismatch {
+                seq_num: change.seq_num,
+            });
+        }
+        if let Some(&order_id) = change
+            .removed
+            .iter()
+            .find(|order_id| !self.resting_orders.contains(order_id))
+        {
+            return Err(ReplayError::UnknownOrder { order_id });
+        }
+        for order_id in &change.removed {
+            self.resting_orders.remove(order_id);
+        }
+        self.resting_orders.extend(change.added.iter().copied());
+        for &(side, price, amount) in &change.levels {
+            if amount == 0 {
+                self.side_mut(side).remove(&price);
+            } else {
+                self.side_mut(side).insert(price, amount);
+            }
+        }
+        self.seq_num = change.seq_num;
+        self.checksum = change.checksum;
+        self.slot = change.slot;
+        Ok(())
+    }
+
+    fn side_mut(&mut self, side: OrderType) -> &mut BTreeMap<u64, u64> {
+        match side {
+            OrderType::Buy => &mut self.bids,
+            OrderType::Sell => &mut self.asks,
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<u64> {
+        self.bids.keys().next_back().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<u64> {
+        self.asks.keys().next().copied()
+    }
+
+    // Total amount the replayed fills traded
+    pub fn base_volume(&self) -> u64 {
+        self.fills.iter().map(|fill| fill.amount).sum()
+    }
+}
+
+// Define the best level of one side before and after, as its price and total
+// resting amount
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BestLevelChange {
+    pub side: OrderType,
+    pub before: Option<(u64, u64)>,
+    pub after: Option<(u64, u64)>,
+}
+
+// Define the differences between two snapshots of a market's order book
+//
+// `added` and `removed` are the resting orders only one snapshot holds, and
+// `resized` the order id, old and new amount of orders both hold with a
+// different amount. `best_levels` has an entry for each side whose best
+// price or amount changed. Expired orders stay on the account until they
+// are pruned, so they count as resting orders but not toward best levels.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BookDiff {
+    pub seq_nums: (u64, u64),
+    pub added: Vec<Order>,
+    pub removed: Vec<Order>,
+    pub resized: Vec<(u64, u64, u64)>,
+    pub best_levels: Vec<BestLevelChange>,
+}
+
+impl BookDiff {
+    // Diff the snapshot `before` against the later snapshot `after`, pricing
+    // levels at `now`
+    pub fn new(before: &OrderBook, after: &OrderBook, now: &Clock) -> Self {
+        let resting = |book: &OrderBook| -> BTreeMap<u64, Order> {
+            book.buy_orders
+                .iter()
+                .chain(book.sell_orders.iter())
+                .map(|order| (order.order_id, *order))
+                .collect()
+        };
+        let (old_orders, new_orders) = (resting(before), resting(after));
+        let mut diff = BookDiff {
+            seq_nums: (before.seq_num, after.seq_num),
+            ..BookDiff::default()
+        };
+        for (order_id, order) in &old_orders {
+            match new_orders.get(order_id) {
+                None => diff.removed.push(*order),
+                Some(new_order) if new_order.amount != order.amount => {
+                    diff.resized
+                        .push((*order_id, order.amount, new_order.amount));
+                }
+                Some(_) => {}
+            }
+        }
+        diff.added = new_orders
+            .iter()
+            .filter(|(order_id, _)| !old_orders.contains_key(order_id))
+            .map(|(_, order)| *order)
+            .collect();
+        for side in [OrderType::Buy, OrderType::Sell] {
+            let change = BestLevelChange {
+                side,
+                before: best_level(before, side, now),
+                after: best_level(after, side, now),
+            };
+            if change.before != change.after {
+                diff.best_levels.push(change);
+            }
+        }
+        diff
+    }
+
+    // Whether the snapshots hold the same resting orders
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.resized.is_empty()
+    }
+}
+
+// Price and total amount of the best level of a side of the book
+fn best_level(order_book: &OrderBook, side: OrderType, now: &Clock) -> Option<(u64, u64)> {
+    let levels = order_book
+        .levels(now)
+        .into_iter()
+        .filter(|level| level.0 == side)
+        .map(|(_, price, amount)| (price, amount));
+    match side {
+        OrderType::Buy => levels.max_by_key(|level| level.0),
+        OrderType::Sell => levels.min_by_key(|level| level.0),
+    }
+}
@@ -0,0 +1,376 @@
+// Serum DEX v3 account layouts, for tooling migrating from Serum markets
+//
+// OpenBook v1, the community fork of Serum, kept these layouts unchanged, so
+// the same decoders read both. `SerumMarket` and `SerumOpenOrders` unpack
+// accounts of those programs into Fordex terms, and build the same layouts
+// from a Fordex market and OpenOrders account, so market makers can feed
+// Fordex accounts to their existing Serum decoding stacks while they move
+// over. Serum's request and event queues and its bid and ask slabs have no
+// Fordex counterpart and are not adapted.
+
+use crate::state::{OpenOrders, OpenOrdersMarket, OrderBook, OrderType};
+use arrayref::array_ref;
+use solana_program::{msg, program_error::ProgramError, pubkey::Pubkey};
+
+// Every Serum account starts and ends with these paddings
+pub const SERUM_HEAD_PADDING: &[u8; 5] = b"serum";
+pub const SERUM_TAIL_PADDING: &[u8; 7] = b"padding";
+
+// Bits of a Serum account's flags
+pub const SERUM_FLAG_INITIALIZED: u64 = 1;
+pub const SERUM_FLAG_MARKET: u64 = 1 << 1;
+pub const SERUM_FLAG_OPEN_ORDERS: u64 = 1 << 2;
+pub const SERUM_FLAG_DISABLED: u64 = 1 << 7;
+
+// Field offsets of a Serum market account, past its head padding.
+// Permissioned markets append authorities after these fields.
+pub const SERUM_MARKET_FLAGS_OFFSET: usize = 5;
+pub const SERUM_MARKET_OWN_ADDRESS_OFFSET: usize = 13;
+pub const SERUM_MARKET_VAULT_SIGNER_NONCE_OFFSET: usize = 45;
+pub const SERUM_MARKET_COIN_MINT_OFFSET: usize = 53;
+pub const SERUM_MARKET_PC_MINT_OFFSET: usize = 85;
+pub const SERUM_MARKET_COIN_VAULT_OFFSET: usize = 117;
+pub const SERUM_MARKET_COIN_DEPOSITS_TOTAL_OFFSET: usize = 149;
+pub const SERUM_MARKET_COIN_FEES_ACCRUED_OFFSET: usize = 157;
+pub const SERUM_MARKET_PC_VAULT_OFFSET: usize = 165;
+pub const SERUM_MARKET_PC_DEPOSITS_TOTAL_OFFSET: usize = 197;
+pub const SERUM_MARKET_PC_FEES_ACCRUED_OFFSET: usize = 205;
+pub const SERUM_MARKET_PC_DUST_THRESHOLD_OFFSET: usize = 213;
+pub const SERUM_MARKET_REQUEST_QUEUE_OFFSET: usize = 221;
+pub const SERUM_MARKET_EVENT_QUEUE_OFFSET: usize = 253;
+pub const SERUM_MARKET_BIDS_OFFSET: usize = 285;
+pub const SERUM_MARKET_ASKS_OFFSET: usize = 317;
+pub const SERUM_MARKET_COIN_LOT_SIZE_OFFSET: usize = 349;
+pub const SERUM_MARKET_PC_LOT_SIZE_OFFSET: usize = 357;
+pub const SERUM_MARKET_FEE_RATE_BPS_OFFSET: usize = 365;
+pub const SERUM_MARKET_REFERRER_REBATES_OFFSET: usize = 373;
+
+// Field offsets of a Serum OpenOrders account, past its head padding
+pub const SERUM_OPEN_ORDERS_FLAGS_OFFSET: usize = 5;
+pub const SERUM_OPEN_ORDERS_MARKET_OFFSET: usize = 13;
+pub const SERUM_OPEN_ORDERS_OWNER_OFFSET: usize = 45;
+pub const SERUM_OPEN_ORDERS_COIN_FREE_OFFSET: usize = 77;
+pub const SERUM_OPEN_ORDERS_COIN_TOTAL_OFFSET: usize = 85;
+pub const SERUM_OPEN_ORDERS_PC_FREE_OFFSET: usize = 93;
+pub const SERUM_OPEN_ORDERS_PC_TOTAL_OFFSET: usize = 101;
+pub const SERUM_OPEN_ORDERS_FREE_SLOT_BITS_OFFSET: usize = 109;
+pub const SERUM_OPEN_ORDERS_IS_BID_BITS_OFFSET: usize = 125;
+pub const SERUM_OPEN_ORDERS_ORDERS_OFFSET: usize = 141;
+pub const SERUM_OPEN_ORDERS_CLIENT_IDS_OFFSET: usize = 2189;
+pub const SERUM_OPEN_ORDERS_REFERRER_REBATES_OFFSET: usize = 3213;
+
+fn check_serum_account(data: &[u8], len: usize, flags: u64) -> Result<(), ProgramError> {
+    if data.len() < len
+        || &data[..5] != SERUM_HEAD_PADDING
+        || &data[data.len() - 7..] != SERUM_TAIL_PADDING
+    {
+        msg!("Account does not hold a Serum account layout");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let account_flags = u64::from_le_bytes(*array_ref![data, 5, 8]);
+    if account_flags & flags != flags {
+        msg!(
+            "Serum account has flags {:#x}, expected {:#x}",
+            account_flags,
+            flags
+        );
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+// Define a Serum market account
+//
+// Serum prices count `pc_lot_size` quote atoms per lot of `coin_lot_size`
+// base atoms, while Fordex prices count quote atoms per lot, so the price of
+// a market quoting per lot (`whole_units` off) is `price_lots * pc_lot_size`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SerumMarket {
+    pub account_flags: u64,
+    pub own_address: Pubkey,
+    pub vault_signer_nonce: u64,
+    pub coin_mint: Pubkey,
+    pub pc_mint: Pubkey,
+    pub coin_vault: Pubkey,
+    pub coin_deposits_total: u64,
+    pub coin_fees_accrued: u64,
+    pub pc_vault: Pubkey,
+    pub pc_deposits_total: u64,
+    pub pc_fees_accrued: u64,
+    pub pc_dust_threshold: u64,
+    pub request_queue: Pubkey,
+    pub event_queue: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    pub fee_rate_bps: u64,
+    pub referrer_rebates_accrued: u64,
+}
+
+impl SerumMarket {
+    pub const LEN: usize = 388;
+
+    // Describe a Fordex market in the Serum layout. Its bids and asks both
+    // point at the order book, which holds both sides, and its prices count
+    // in the market's own price units.
+    pub fn from_order_book(key: &Pubkey, order_book: &OrderBook) -> Self {
+        SerumMarket {
+            account_flags: SERUM_FLAG_INITIALIZED | SERUM_FLAG_MARKET,
+            own_address: *key,
+            vault_signer_nonce: order_book.vault_signer_bump as u64,
+            coin_mint: order_book.base_mint,
+            pc_mint: order_book.quote_mint,
+            coin_vault: order_book.base_vault,
+            pc_vault: order_book.quote_vault,
+            pc_fees_accrued: order_book.fees_outstanding,
+            bids: *key,
+            asks: *key,
+            coin_lot_size: order_book.lot_size,
+            // A whole-unit price counts quote atoms per base atom, so a lot
+            // costs `lot_size` price units
+            pc_lot_size: match order_book.whole_units {
+                true => order_book.lot_size,
+                false => 1,
+            },
+            fee_rate_bps: order_book.buy_taker_fee_bps as u64,
+            ..SerumMarket::default()
+        }
+    }
+
+    // Fordex price, in quote atoms per lot, of a Serum price in lots
+    pub fn fordex_price(&self, price_lots: u64) -> Option<u64> {
+        price_lots.checked_mul(self.pc_lot_size)
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.account_flags & SERUM_FLAG_DISABLED != 0
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(SERUM_HEAD_PADDING);
+        buf.extend_from_slice(&self.account_flags.to_le_bytes());
+        buf.extend_from_slice(self.own_address.as_ref());
+        buf.extend_from_slice(&self.vault_signer_nonce.to_le_bytes());
+        buf.extend_from_slice(self.coin_mint.as_ref());
+        buf.extend_from_slice(self.pc_mint.as_ref());
+        buf.extend_from_slice(self.coin_vault.as_ref());
+        buf.extend_from_slice(&self.coin_deposits_total.to_le_bytes());
+        buf.extend_from_slice(&self.coin_fees_accrued.to_le_bytes());
+        buf.extend_from_slice(self.pc_vault.as_ref());
+        buf.extend_from_slice(&self.pc_deposits_total.to_le_bytes());
+        buf.extend_from_slice(&self.pc_fees_accrued.to_le_bytes());
+        buf.extend_from_slice(&self.pc_dust_threshold.to_le_bytes());
+        buf.extend_from_slice(self.request_queue.as_ref());
+        buf.extend_from_slice(self.event_queue.as_ref());
+        buf.extend_from_slice(self.bids.as_ref());
+        buf.extend_from_slice(self.asks.as_ref());
+        buf.extend_from_slice(&self.coin_lot_size.to_le_bytes());
+        buf.extend_from_slice(&self.pc_lot_size.to_le_bytes());
+        buf.extend_from_slice(&self.fee_rate_bps.to_le_bytes());
+        buf.extend_from_slice(&self.referrer_rebates_accrued.to_le_bytes());
+        buf.extend_from_slice(SERUM_TAIL_PADDING);
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        check_serum_account(data, Self::LEN, SERUM_FLAG_INITIALIZED | SERUM_FLAG_MARKET)?;
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        let pubkey_at = |offset: usize| Pubkey::new_from_array(*array_ref![data, offset, 32]);
+        Ok(SerumMarket {
+            account_flags: u64_at(SERUM_MARKET_FLAGS_OFFSET),
+            own_address: pubkey_at(SERUM_MARKET_OWN_ADDRESS_OFFSET),
+            vault_signer_nonce: u64_at(SERUM_MARKET_VAULT_SIGNER_NONCE_OFFSET),
+            coin_mint: pubkey_at(SERUM_MARKET_COIN_MINT_OFFSET),
+            pc_mint: pubkey_at(SERUM_MARKET_PC_MINT_OFFSET),
+            coin_vault: pubkey_at(SERUM_MARKET_COIN_VAULT_OFFSET),
+            coin_deposits_total: u64_at(SERUM_MARKET_COIN_DEPOSITS_TOTAL_OFFSET),
+            coin_fees_accrued: u64_at(SERUM_MARKET_COIN_FEES_ACCRUED_OFFSET),
+            pc_vault: pubkey_at(SERUM_MARKET_PC_VAULT_OFFSET),
+            pc_deposits_total: u64_at(SERUM_MARKET_PC_DEPOSITS_TOTAL_OFFSET),
+            pc_fees_accrued: u64_at(SERUM_MARKET_PC_FEES_ACCRUED_OFFSET),
+            pc_dust_threshold: u64_at(SERUM_MARKET_PC_DUST_THRESHOLD_OFFSET),
+            request_queue: pubkey_at(SERUM_MARKET_REQUEST_QUEUE_OFFSET),
+            event_queue: pubkey_at(SERUM_MARKET_EVENT_QUEUE_OFFSET),
+            bids: pubkey_at(SERUM_MARKET_BIDS_OFFSET),
+            asks: pubkey_at(SERUM_MARKET_ASKS_OFFSET),
+            coin_lot_size: u64_at(SERUM_MARKET_COIN_LOT_SIZE_OFFSET),
+            pc_lot_size: u64_at(SERUM_MARKET_PC_LOT_SIZE_OFFSET),
+            fee_rate_bps: u64_at(SERUM_MARKET_FEE_RATE_BPS_OFFSET),
+            referrer_rebates_accrued: u64_at(SERUM_MARKET_REFERRER_REBATES_OFFSET),
+        })
+    }
+}
+
+// Define an order held in one of the slots of a Serum OpenOrders account.
+// Its id carries the price in lots in the upper 64 bits and the sequence
+// number, inverted for bids, in the lower 64.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SerumOrder {
+    pub slot: u8,
+    pub order_id: u128,
+    pub side: OrderType,
+    pub client_order_id: u64,
+}
+
+impl SerumOrder {
+    pub fn price_lots(&self) -> u64 {
+        (self.order_id >> 64) as u64
+    }
+
+    pub fn seq_num(&self) -> u64 {
+        match self.side {
+            OrderType::Buy => !(self.order_id as u64),
+            OrderType::Sell => self.order_id as u64,
+        }
+    }
+}
+
+// Define a Serum OpenOrders account, listing only its occupied order slots
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SerumOpenOrders {
+    pub account_flags: u64,
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub native_coin_free: u64,
+    pub native_coin_total: u64,
+    pub native_pc_free: u64,
+    pub native_pc_total: u64,
+    pub orders: Vec<SerumOrder>,
+    pub referrer_rebates_accrued: u64,
+}
+
+impl SerumOpenOrders {
+    pub const LEN: usize = 3228;
+    // Number of order slots of an account
+    pub const MAX_ORDERS: usize = 128;
+
+    // Describe the balances and resting orders of a Fordex OpenOrders
+    // account on `market` in the Serum layout. Orders past the slots of the
+    // layout are left out.
+    pub fn from_open_orders(
+        market: &Pubkey,
+        open_orders: &OpenOrders,
+        order_book: &OrderBook,
+    ) -> Self {
+        let balances = open_orders.market(market).copied().unwrap_or_default();
+        let orders = order_book
+            .buy_orders
+            .iter()
+            .chain(&order_book.sell_orders)
+            .filter(|order| order.trader == open_orders.owner)
+            .take(Self::MAX_ORDERS)
+            .enumerate()
+            .map(|(slot, order)| {
+                let seq_num = match order.order_type {
+                    OrderType::Buy => !order.order_id,
+                    OrderType::Sell => order.order_id,
+                };
+                SerumOrder {
+                    slot: slot as u8,
+                    order_id: (order.price as u128) << 64 | seq_num as u128,
+                    side: order.order_type,
+                    client_order_id: order.tag,
+                }
+            })
+            .collect();
+        SerumOpenOrders {
+            account_flags: SERUM_FLAG_INITIALIZED | SERUM_FLAG_OPEN_ORDERS,
+            market: *market,
+            owner: open_orders.owner,
+            native_coin_free: balances.base_free,
+            native_coin_total: balances.base_free + balances.base_locked,
+            native_pc_free: balances.quote_free,
+            native_pc_total: balances.quote_free + balances.quote_locked,
+            orders,
+            referrer_rebates_accrued: 0,
+        }
+    }
+
+    // The account's balances as Fordex OpenOrders balances, the part of each
+    // total that is not free being locked in orders
+    pub fn fordex_balances(&self) -> OpenOrdersMarket {
+        OpenOrdersMarket {
+            market: self.market,
+            base_free: self.native_coin_free,
+            base_locked: self.native_coin_total.saturating_sub(self.native_coin_free),
+            quote_free: self.native_pc_free,
+            quote_locked: self.native_pc_total.saturating_sub(self.native_pc_free),
+            order_count: self.orders.len() as u32,
+            ..OpenOrdersMarket::default()
+        }
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut free_slot_bits = u128::MAX;
+        let mut is_bid_bits = 0u128;
+        let mut order_ids = [0u128; Self::MAX_ORDERS];
+        let mut client_order_ids = [0u64; Self::MAX_ORDERS];
+        for order in &self.orders {
+            let slot = order.slot as usize % Self::MAX_ORDERS;
+            free_slot_bits &= !(1 << slot);
+            if order.side == OrderType::Buy {
+                is_bid_bits |= 1 << slot;
+            }
+            order_ids[slot] = order.order_id;
+            client_order_ids[slot] = order.client_order_id;
+        }
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(SERUM_HEAD_PADDING);
+        buf.extend_from_slice(&self.account_flags.to_le_bytes());
+        buf.extend_from_slice(self.market.as_ref());
+        buf.extend_from_slice(self.owner.as_ref());
+        buf.extend_from_slice(&self.native_coin_free.to_le_bytes());
+        buf.extend_from_slice(&self.native_coin_total.to_le_bytes());
+        buf.extend_from_slice(&self.native_pc_free.to_le_bytes());
+        buf.extend_from_slice(&self.native_pc_total.to_le_bytes());
+        buf.extend_from_slice(&free_slot_bits.to_le_bytes());
+        buf.extend_from_slice(&is_bid_bits.to_le_bytes());
+        for order_id in order_ids {
+            buf.extend_from_slice(&order_id.to_le_bytes());
+        }
+        for client_order_id in client_order_ids {
+            buf.extend_from_slice(&client_order_id.to_le_bytes());
+        }
+        buf.extend_from_slice(&self.referrer_rebates_accrued.to_le_bytes());
+        buf.extend_from_slice(SERUM_TAIL_PADDING);
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        check_serum_account(
+            data,
+            Self::LEN,
+            SERUM_FLAG_INITIALIZED | SERUM_FLAG_OPEN_ORDERS,
+        )?;
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        let u128_at = |offset: usize| u128::from_le_bytes(*array_ref![data, offset, 16]);
+        let pubkey_at = |offset: usize| Pubkey::new_from_array(*array_ref![data, offset, 32]);
+        let free_slot_bits = u128_at(SERUM_OPEN_ORDERS_FREE_SLOT_BITS_OFFSET);
+        let is_bid_bits = u128_at(SERUM_OPEN_ORDERS_IS_BID_BITS_OFFSET);
+        let orders = (0..Self::MAX_ORDERS)
+            .filter(|slot| free_slot_bits & (1 << slot) == 0)
+            .map(|slot| SerumOrder {
+                slot: slot as u8,
+                order_id: u128_at(SERUM_OPEN_ORDERS_ORDERS_OFFSET + slot * 16),
+                side: match is_bid_bits & (1 << slot) {
+                    0 => OrderType::Sell,
+                    _ => OrderType::Buy,
+                },
+                client_order_id: u64_at(SERUM_OPEN_ORDERS_CLIENT_IDS_OFFSET + slot * 8),
+            })
+            .collect();
+        Ok(SerumOpenOrders {
+            account_flags: u64_at(SERUM_OPEN_ORDERS_FLAGS_OFFSET),
+            market: pubkey_at(SERUM_OPEN_ORDERS_MARKET_OFFSET),
+            owner: pubkey_at(SERUM_OPEN_ORDERS_OWNER_OFFSET),
+            native_coin_free: u64_at(SERUM_OPEN_ORDERS_COIN_FREE_OFFSET),
+            native_coin_total: u64_at(SERUM_OPEN_ORDERS_COIN_TOTAL_OFFSET),
+            native_pc_free: u64_at(SERUM_OPEN_ORDERS_PC_FREE_OFFSET),
+            native_pc_total: u64_at(SERUM_OPEN_ORDERS_PC_TOTAL_OFFSET),
+            orders,
+            referrer_rebates_accrued: u64_at(SERUM_OPEN_ORDERS_REFERRER_REBATES_OFFSET),
+        })
+    }
+}
@@ -0,0 +1,216 @@
+// The LaunchAuction PDA selling a market's base token in a sealed-bid
+// uniform-price auction
+
+use arrayref::array_ref;
+use solana_program::{
+    clock::UnixTimestamp, hash::hashv, msg, program_error::ProgramError, pubkey::Pubkey,
+};
+
+// Byte offsets of the fields of the launch auction account
+pub const LAUNCH_AUCTION_MARKET_OFFSET: usize = 0;
+pub const LAUNCH_AUCTION_SELLER_OFFSET: usize = 32;
+pub const LAUNCH_AUCTION_SUPPLY_OFFSET: usize = 64;
+pub const LAUNCH_AUCTION_RESERVE_PRICE_OFFSET: usize = 72;
+pub const LAUNCH_AUCTION_BID_END_OFFSET: usize = 80;
+pub const LAUNCH_AUCTION_REVEAL_END_OFFSET: usize = 88;
+pub const LAUNCH_AUCTION_CLEARING_PRICE_OFFSET: usize = 96;
+pub const LAUNCH_AUCTION_SOLD_OFFSET: usize = 104;
+pub const LAUNCH_AUCTION_CLEARED_OFFSET: usize = 112;
+pub const LAUNCH_AUCTION_BID_COUNT_OFFSET: usize = 113;
+pub const LAUNCH_AUCTION_BIDS_OFFSET: usize = 117;
+
+// Define a bid committed to a launch auction
+//
+// The quantity is public while the price stays hidden behind `commitment`
+// until the bidder reveals it; `price` is zero until then. `deposit` is the
+// quote locked in the bidder's OpenOrders account to pay for the bid, and
+// `allocation` the base it won once the auction cleared.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SealedBid {
+    pub bidder: Pubkey,
+    pub commitment: [u8; 32],
+    pub quantity: u64,
+    pub deposit: u64,
+    pub price: u64,
+    pub allocation: u64,
+    pub settled: bool,
+}
+
+impl SealedBid {
+    pub const LEN: usize = 97;
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.bidder.to_bytes());
+        buf.extend_from_slice(&self.commitment);
+        buf.extend_from_slice(&self.quantity.to_le_bytes());
+        buf.extend_from_slice(&self.deposit.to_le_bytes());
+        buf.extend_from_slice(&self.price.to_le_bytes());
+        buf.extend_from_slice(&self.allocation.to_le_bytes());
+        buf.push(self.settled as u8);
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Sealed bid too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        Ok(SealedBid {
+            bidder: Pubkey::new_from_array(*array_ref![data, 0, 32]),
+            commitment: *array_ref![data, 32, 32],
+            quantity: u64_at(64),
+            deposit: u64_at(72),
+            price: u64_at(80),
+            allocation: u64_at(88),
+            settled: data[96] != 0,
+        })
+    }
+
+    pub fn is_revealed(&self) -> bool {
+        self.price > 0
+    }
+}
+
+// Define the LaunchAuction PDA of a market, a liquidity bootstrapping sale
+// of `supply` base atoms the seller deposited into the market's base vault
+//
+// Bidders commit sealed bids until `bid_end` and reveal their prices until
+// `reveal_end`, after which anyone may clear the auction. Clearing fills the
+// revealed bids from the highest price down and sells every winner at one
+// `clearing_price`, that of the lowest winning bid. Bids at the clearing
+// price share what remains of the supply pro-rata to their quantity, in
+// whole lots. Bids below the reserve price cannot be revealed, and bids that
+// were never revealed win nothing.
+//
+// Proceeds and unsold supply are credited to the seller's OpenOrders account
+// on clearing, and each bidder's allocation and change when its bids are
+// settled, all withdrawn with SettleFunds like trading balances.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LaunchAuction {
+    pub market: Pubkey,
+    pub seller: Pubkey,
+    pub supply: u64,
+    pub reserve_price: u64,
+    pub bid_end: UnixTimestamp,
+    pub reveal_end: UnixTimestamp,
+    pub clearing_price: u64,
+    pub sold: u64,
+    pub cleared: bool,
+    pub bids: Vec<SealedBid>,
+}
+
+impl LaunchAuction {
+    // Maximum number of bids an auction accepts
+    pub const MAX_BIDS: usize = 64;
+    pub const LEN: usize = LAUNCH_AUCTION_BIDS_OFFSET + Self::MAX_BIDS * SealedBid::LEN;
+    pub const SEED: &'static [u8] = b"launch_auction";
+
+    // Address of the LaunchAuction PDA for an order book
+    pub fn find_address(program_id: &Pubkey, order_book: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED, order_book.as_ref()], program_id)
+    }
+
+    // Commitment to a bid price, salted so that prices cannot be guessed
+    // from the commitments of other bidders
+    pub fn commitment(bidder: &Pubkey, price: u64, salt: &[u8; 32]) -> [u8; 32] {
+        hashv(&[bidder.as_ref(), &price.to_le_bytes(), salt]).to_bytes()
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.market.to_bytes());
+        buf.extend_from_slice(&self.seller.to_bytes());
+        buf.extend_from_slice(&self.supply.to_le_bytes());
+        buf.extend_from_slice(&self.reserve_price.to_le_bytes());
+        buf.extend_from_slice(&self.bid_end.to_le_bytes());
+        buf.extend_from_slice(&self.reveal_end.to_le_bytes());
+        buf.extend_from_slice(&self.clearing_price.to_le_bytes());
+        buf.extend_from_slice(&self.sold.to_le_bytes());
+        buf.push(self.cleared as u8);
+        buf.extend_from_slice(&(self.bids.len() as u32).to_le_bytes());
+        for bid in &self.bids {
+            buf.extend_from_slice(&bid.pack());
+        }
+        buf.resize(Self::LEN, 0);
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Launch auction account is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        let count =
+            u32::from_le_bytes(*array_ref![data, LAUNCH_AUCTION_BID_COUNT_OFFSET, 4]) as usize;
+        if count > Self::MAX_BIDS {
+            msg!("Launch auction holds an invalid bid count");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(LaunchAuction {
+            market: Pubkey::new_from_array(*array_ref![data, LAUNCH_AUCTION_MARKET_OFFSET, 32]),
+            seller: Pubkey::new_from_array(*array_ref![data, LAUNCH_AUCTION_SELLER_OFFSET, 32]),
+            supply: u64_at(LAUNCH_AUCTION_SUPPLY_OFFSET),
+            reserve_price: u64_at(LAUNCH_AUCTION_RESERVE_PRICE_OFFSET),
+            bid_end: u64_at(LAUNCH_AUCTION_BID_END_OFFSET) as UnixTimestamp,
+            reveal_end: u64_at(LAUNCH_AUCTION_REVEAL_END_OFFSET) as UnixTimestamp,
+            clearing_price: u64_at(LAUNCH_AUCTION_CLEARING_PRICE_OFFSET),
+            sold: u64_at(LAUNCH_AUCTION_SOLD_OFFSET),
+            cleared: data[LAUNCH_AUCTION_CLEARED_OFFSET] != 0,
+            bids: (0..count)
+                .map(|i| {
+                    SealedBid::unpack(&data[LAUNCH_AUCTION_BIDS_OFFSET + i * SealedBid::LEN..])
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    // Compute the clearing price and every bid's allocation, in multiples of
+    // `lot_size`
+    pub fn clear(&mut self, lot_size: u64) {
+        let mut ranked: Vec<(u64, usize)> = self
+            .bids
+            .iter()
+            .enumerate()
+            .filter(|(_, bid)| bid.is_revealed())
+            .map(|(index, bid)| (bid.price, index))
+            .collect();
+        ranked.sort_by_key(|(price, _)| std::cmp::Reverse(*price));
+        let mut remaining = self.supply;
+        let mut clearing_price = 0;
+        for level in ranked.chunk_by(|a, b| a.0 == b.0) {
+            if remaining == 0 {
+                break;
+            }
+            clearing_price = level[0].0;
+            let demand: u64 = level
+                .iter()
+                .map(|(_, index)| self.bids[*index].quantity)
+                .sum();
+            if demand <= remaining {
+                for (_, index) in level {
+                    self.bids[*index].allocation = self.bids[*index].quantity;
+                }
+                remaining -= demand;
+                continue;
+            }
+            // The marginal level shares the rest pro-rata, rounded down to
+            // whole lots
+            let available = remaining;
+            for (_, index) in level {
+                let share = (self.bids[*index].quantity as u128 * available as u128
+                    / demand as u128) as u64;
+                let allocation = share - share % lot_size;
+                self.bids[*index].allocation = allocation;
+                remaining -= allocation;
+            }
+        }
+        self.clearing_price = clearing_price;
+        self.sold = self.supply - remaining;
+        self.cleared = true;
+    }
+}
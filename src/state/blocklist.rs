@@ -0,0 +1,85 @@
+// The Blocklist PDA of addresses barred from markets that opt into it
+
+use crate::error::OrderBookError;
+use arrayref::array_ref;
+use solana_program::{msg, program_error::ProgramError, pubkey::Pubkey};
+
+// Define a Blocklist PDA, the addresses its `authority` bars from trading
+// and withdrawing on the markets that opted into it
+//
+// The authority creates it with InitBlocklist and adds or removes addresses
+// with UpdateBlocklist. A market creator opts the market into one with
+// SetBlocklist; markets without a blocklist are unaffected. PlaceOrder then
+// refuses orders of a listed trader or beneficial owner, and SettleFunds
+// refuses to settle an OpenOrders account of a listed owner or to pay a
+// listed recipient.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Blocklist {
+    pub authority: Pubkey,
+    pub addresses: Vec<Pubkey>,
+}
+
+impl Blocklist {
+    // Maximum number of listed addresses
+    pub const MAX_ADDRESSES: usize = 64;
+    pub const LEN: usize = 36 + Self::MAX_ADDRESSES * 32;
+    pub const SEED: &'static [u8] = b"blocklist";
+
+    // Address of the Blocklist PDA of an authority
+    pub fn find_address(program_id: &Pubkey, authority: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED, authority.as_ref()], program_id)
+    }
+
+    pub fn contains(&self, address: &Pubkey) -> bool {
+        self.addresses.contains(address)
+    }
+
+    // List or delist an address, returning whether the list changed
+    pub fn set_blocked(&mut self, address: &Pubkey, blocked: bool) -> Result<bool, ProgramError> {
+        let index = self.addresses.iter().position(|listed| listed == address);
+        match (index, blocked) {
+            (None, true) => {
+                if self.addresses.len() >= Self::MAX_ADDRESSES {
+                    msg!("Blocklist holds its maximum number of addresses");
+                    return Err(OrderBookError::BlocklistFull.into());
+                }
+                self.addresses.push(*address);
+                Ok(true)
+            }
+            (Some(index), false) => {
+                self.addresses.swap_remove(index);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = vec![0; Self::LEN];
+        buf[..32].copy_from_slice(self.authority.as_ref());
+        buf[32..36].copy_from_slice(&(self.addresses.len() as u32).to_le_bytes());
+        for (i, address) in self.addresses.iter().enumerate() {
+            buf[36 + i * 32..68 + i * 32].copy_from_slice(address.as_ref());
+        }
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Blocklist account is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let count = u32::from_le_bytes(*array_ref![data, 32, 4]) as usize;
+        if count > Self::MAX_ADDRESSES {
+            msg!("Blocklist holds an invalid address count");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(Blocklist {
+            authority: Pubkey::new_from_array(*array_ref![data, 0, 32]),
+            addresses: (0..count)
+                .map(|i| Pubkey::new_from_array(*array_ref![data, 36 + i * 32, 32]))
+                .collect(),
+        })
+    }
+}
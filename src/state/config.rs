@@ -0,0 +1,217 @@
+// Protocol configuration and market registration PDAs
+
+use arrayref::array_ref;
+use solana_program::{msg, program_error::ProgramError, pubkey::Pubkey};
+
+// Define the protocol-level configuration stored in the Config PDA
+//
+// `protocol_fee_bps` is the protocol's share of taker fees, in basis points
+// of the fee. Markets copy it when they are created and accrue the share for
+// `treasury` separately from their own fee collector.
+//
+// While `paused` is set no market places or matches orders, while cancels,
+// settlement and withdrawals keep working. Pausing or resuming takes the
+// signatures of `pause_threshold` of the `guardians`, which the admin sets
+// with SetGuardians.
+//
+// `listing` holds the requirements InitializeMarket enforces on every new
+// market, set by the admin with SetListingRequirements.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Config {
+    pub is_initialized: bool,
+    pub admin: Pubkey,
+    pub default_tick_size: u64,
+    pub default_lot_size: u64,
+    pub unique_markets: bool,
+    pub protocol_fee_bps: u16,
+    pub treasury: Pubkey,
+    pub paused: bool,
+    pub pause_threshold: u8,
+    pub guardians: Vec<Pubkey>,
+    pub listing: ListingRequirements,
+}
+
+impl Config {
+    // Maximum number of guardians that may pause the protocol
+    pub const MAX_GUARDIANS: usize = 5;
+    pub const LEN: usize = 87 + Self::MAX_GUARDIANS * 32 + ListingRequirements::LEN;
+    pub const SEED: &'static [u8] = b"config";
+
+    // Address of the Config PDA
+    pub fn find_address(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED], program_id)
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.push(self.is_initialized as u8);
+        buf.extend_from_slice(&self.admin.to_bytes());
+        buf.extend_from_slice(&self.default_tick_size.to_le_bytes());
+        buf.extend_from_slice(&self.default_lot_size.to_le_bytes());
+        buf.push(self.unique_markets as u8);
+        buf.extend_from_slice(&self.protocol_fee_bps.to_le_bytes());
+        buf.extend_from_slice(&self.treasury.to_bytes());
+        buf.push(self.paused as u8);
+        buf.push(self.pause_threshold);
+        buf.push(self.guardians.len() as u8);
+        for guardian in &self.guardians {
+            buf.extend_from_slice(&guardian.to_bytes());
+        }
+        buf.resize(87 + Self::MAX_GUARDIANS * 32, 0);
+        buf.extend_from_slice(&self.listing.pack());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Config account is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        Ok(Config {
+            is_initialized: data[0] != 0,
+            admin: Pubkey::new_from_array(*array_ref![data, 1, 32]),
+            default_tick_size: u64::from_le_bytes(*array_ref![data, 33, 8]),
+            default_lot_size: u64::from_le_bytes(*array_ref![data, 41, 8]),
+            unique_markets: data[49] != 0,
+            protocol_fee_bps: u16::from_le_bytes(*array_ref![data, 50, 2]),
+            treasury: Pubkey::new_from_array(*array_ref![data, 52, 32]),
+            paused: data[84] != 0,
+            pause_threshold: data[85],
+            guardians: (0..(data[86] as usize).min(Self::MAX_GUARDIANS))
+                .map(|i| Pubkey::new_from_array(*array_ref![data, 87 + i * 32, 32]))
+                .collect(),
+            listing: ListingRequirements::unpack(&data[87 + Self::MAX_GUARDIANS * 32..])?,
+        })
+    }
+}
+
+// Define the requirements a new market must meet to be listed
+//
+// A market's tick and lot sizes, after the Config defaults fill in zero
+// sizes, may not be below `min_tick_size` and `min_lot_size`. Whole-unit
+// markets always trade in lots of one and are exempt from the lot minimum.
+// Neither taker fee may exceed `max_taker_fee_bps`, unless it is zero, which
+// leaves fees uncapped. The creator funds the market's crank incentives with
+// `crank_funding_lamports` on creation, so keepers are paid to maintain it
+// from the start.
+//
+// All zero, the default, lists any market.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ListingRequirements {
+    pub min_tick_size: u64,
+    pub min_lot_size: u64,
+    pub max_taker_fee_bps: u16,
+    pub crank_funding_lamports: u64,
+}
+
+impl ListingRequirements {
+    pub const LEN: usize = 26;
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.min_tick_size.to_le_bytes());
+        buf.extend_from_slice(&self.min_lot_size.to_le_bytes());
+        buf.extend_from_slice(&self.max_taker_fee_bps.to_le_bytes());
+        buf.extend_from_slice(&self.crank_funding_lamports.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Listing requirements too short");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        Ok(ListingRequirements {
+            min_tick_size: u64::from_le_bytes(*array_ref![data, 0, 8]),
+            min_lot_size: u64::from_le_bytes(*array_ref![data, 8, 8]),
+            max_taker_fee_bps: u16::from_le_bytes(*array_ref![data, 16, 2]),
+            crank_funding_lamports: u64::from_le_bytes(*array_ref![data, 18, 8]),
+        })
+    }
+}
+
+// Define the registry entry PDA created for every market, so markets can be
+// discovered by mint without scanning order book accounts
+//
+// `canonical` marks the market liquidity should gather in for its mint pair.
+// It is set automatically when the Config enforces unique markets, and the
+// Config admin may change it with SetCanonicalMarket.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MarketRegistryEntry {
+    pub order_book: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub creator: Pubkey,
+    pub canonical: bool,
+}
+
+impl MarketRegistryEntry {
+    pub const LEN: usize = 129;
+    pub const SEED: &'static [u8] = b"registry";
+
+    // Address of the registry entry for an order book
+    pub fn find_address(program_id: &Pubkey, order_book: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED, order_book.as_ref()], program_id)
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.order_book.to_bytes());
+        buf.extend_from_slice(&self.base_mint.to_bytes());
+        buf.extend_from_slice(&self.quote_mint.to_bytes());
+        buf.extend_from_slice(&self.creator.to_bytes());
+        buf.push(self.canonical as u8);
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Registry entry is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        Ok(MarketRegistryEntry {
+            order_book: Pubkey::new_from_array(*array_ref![data, 0, 32]),
+            base_mint: Pubkey::new_from_array(*array_ref![data, 32, 32]),
+            quote_mint: Pubkey::new_from_array(*array_ref![data, 64, 32]),
+            creator: Pubkey::new_from_array(*array_ref![data, 96, 32]),
+            canonical: data[128] != 0,
+        })
+    }
+}
+
+// Define the PDA claiming a (base, quote, tick, lot) combination for a single
+// market when the Config enforces unique markets. Creating a second market
+// with the same combination fails because the PDA already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CanonicalMarket {
+    pub order_book: Pubkey,
+}
+
+impl CanonicalMarket {
+    pub const LEN: usize = 32;
+    pub const SEED: &'static [u8] = b"canonical";
+
+    // Address of the canonical market claim for a market combination
+    pub fn find_address(
+        program_id: &Pubkey,
+        base_mint: &Pubkey,
+        quote_mint: &Pubkey,
+        tick_size: u64,
+        lot_size: u64,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                Self::SEED,
+                base_mint.as_ref(),
+                quote_mint.as_ref(),
+                &tick_size.to_le_bytes(),
+                &lot_size.to_le_bytes(),
+            ],
+            program_id,
+        )
+    }
+}
@@ -0,0 +1,466 @@
+// Events logged by instructions that fill or change orders
+
+use crate::state::{OrderBook, OrderStatus, OrderType};
+use arrayref::array_ref;
+use solana_program::{
+    clock::{Clock, Slot},
+    hash::hashv,
+    log::sol_log_data,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+// Define a fill between an incoming taker order and a resting maker order
+//
+// Fills execute at the maker's price. `maker_remaining` is the maker order's
+// amount left after the fill; zero means the maker order left the book.
+// `taker_fee` is the quote amount the taker paid the market on top of a bid
+// or had deducted from the proceeds of an ask, charged at `taker_fee_bps`,
+// the market's rate for the taker's side. `fee_subsidy` is the part of the
+// fee paid from the market's incentives when the maker is a backstop maker.
+// `maker_tag` and `taker_tag` are the tags of the two orders.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FillEvent {
+    pub maker: Pubkey,
+    pub maker_order_id: u64,
+    pub taker: Pubkey,
+    pub taker_order_id: u64,
+    pub taker_side: OrderType,
+    pub price: u64,
+    pub amount: u64,
+    pub maker_remaining: u64,
+    pub taker_fee_bps: u16,
+    pub taker_fee: u64,
+    pub fee_subsidy: u64,
+    pub maker_tag: u64,
+    pub taker_tag: u64,
+}
+
+// Define the change summary logged by every instruction that changes a
+// market's resting orders, so indexers can maintain its L2 book from logs
+//
+// `seq_num` is the market's sequence number after the change; a consumer
+// that sees a gap resynchronizes from the account. `added` and `removed` are
+// the ids of resting orders that entered and left the book, and `levels` the
+// side, price and new total amount of every price level whose total changed,
+// zero once the level is empty. Dark pool markets only log the sequence
+// number, checksum and slot.
+//
+// `checksum` is the market's book checksum after the change, the previous
+// one folded with this change by `next_checksum`. A replica that applied the
+// same changes from the same starting point holds the same checksum, so
+// comparing it against an event or the account's `book_checksum` detects
+// divergence without comparing whole books.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BookChangeEvent {
+    pub market: Pubkey,
+    pub seq_num: u64,
+    pub checksum: u64,
+    pub slot: Slot,
+    pub added: Vec<u64>,
+    pub removed: Vec<u64>,
+    pub levels: Vec<(OrderType, u64, u64)>,
+    pub best_bid: Option<u64>,
+    pub best_ask: Option<u64>,
+}
+
+impl BookChangeEvent {
+    // Summarize the changes from `before` to `after`, which carries the new
+    // sequence number
+    pub fn new(market: &Pubkey, before: &OrderBook, after: &OrderBook, clock: &Clock) -> Self {
+        let mut event = BookChangeEvent {
+            market: *market,
+            seq_num: after.seq_num,
+            slot: clock.slot,
+            ..BookChangeEvent::default()
+        };
+        if after.dark_pool {
+            event.checksum = event.next_checksum(before.book_checksum);
+            return event;
+        }
+        let resting = |book: &OrderBook| -> Vec<u64> {
+            book.buy_orders
+                .iter()
+                .chain(book.sell_orders.iter())
+                .map(|order| order.order_id)
+                .collect()
+        };
+        let (old_ids, new_ids) = (resting(before), resting(after));
+        event.added = new_ids
+            .iter()
+            .filter(|id| !old_ids.contains(id))
+            .copied()
+            .collect();
+        event.removed = old_ids
+            .iter()
+            .filter(|id| !new_ids.contains(id))
+            .copied()
+            .collect();
+        let old_levels = before.levels(clock);
+        let new_levels = after.levels(clock);
+        let total = |levels: &[(OrderType, u64, u64)], side: OrderType, price: u64| {
+            levels
+                .iter()
+                .find(|level| level.0 == side && level.1 == price)
+                .map_or(0, |level| level.2)
+        };
+        for &(side, price, _) in old_levels.iter().chain(new_levels.iter()) {
+            let amount = total(&new_levels, side, price);
+            if amount != total(&old_levels, side, price)
+                && !event
+                    .levels
+                    .iter()
+                    .any(|level| level.0 == side && level.1 == price)
+            {
+                event.levels.push((side, price, amount));
+            }
+        }
+        let best = |side| after.best_order(side, clock).map(|(_, price)| price);
+        event.best_bid = best(OrderType::Buy);
+        event.best_ask = best(OrderType::Sell);
+        event.checksum = event.next_checksum(before.book_checksum);
+        event
+    }
+
+    // Fold the change's sequence number, added and removed order ids and
+    // level totals into `checksum`, the book checksum before the change
+    pub fn next_checksum(&self, checksum: u64) -> u64 {
+        // Each list is prefixed with its length, so an id cannot shift from
+        // one list to the next without changing the hash
+        let ids = |ids: &[u64]| -> Vec<u8> {
+            (ids.len() as u32)
+                .to_le_bytes()
+                .into_iter()
+                .chain(ids.iter().flat_map(|id| id.to_le_bytes()))
+                .collect()
+        };
+        let levels: Vec<u8> = self
+            .levels
+            .iter()
+            .flat_map(|&(side, price, amount)| {
+                std::iter::once(side as u8)
+                    .chain(price.to_le_bytes())
+                    .chain(amount.to_le_bytes())
+            })
+            .collect();
+        let hash = hashv(&[
+            &checksum.to_le_bytes(),
+            &self.seq_num.to_le_bytes(),
+            &ids(&self.added),
+            &ids(&self.removed),
+            &levels,
+        ]);
+        u64::from_le_bytes(*array_ref![hash.as_ref(), 0, 8])
+    }
+}
+
+// Define the event logged the first time an upgraded program writes a market
+// last written with an older account layout
+//
+// Markets last written before layout versions were recorded report
+// `old_version` 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayoutUpgradedEvent {
+    pub market: Pubkey,
+    pub old_version: u8,
+    pub new_version: u8,
+}
+
+// Define the event logged when a market's outstanding fees are swept
+//
+// `total_swept` is the market's cumulative swept amount including this sweep,
+// so the sequence of events reconciles against `fees_swept`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeeSweptEvent {
+    pub market: Pubkey,
+    pub fee_collector: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub total_swept: u64,
+    pub slot: Slot,
+}
+
+// Define the event logged when a Blocklist lists or delists an address
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlocklistUpdatedEvent {
+    pub blocklist: Pubkey,
+    pub authority: Pubkey,
+    pub address: Pubkey,
+    pub blocked: bool,
+}
+
+// Define the event logged when a market refuses an instruction because an
+// address it involves is on the market's blocklist
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AddressBlockedEvent {
+    pub market: Pubkey,
+    pub blocklist: Pubkey,
+    pub address: Pubkey,
+}
+
+// Define an order that started resting on the book
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlaceEvent {
+    pub market: Pubkey,
+    pub order_id: u64,
+    pub trader: Pubkey,
+    pub side: OrderType,
+    pub price: u64,
+    pub amount: u64,
+}
+
+// Define a resting order that left the book filled completely
+//
+// `filled_amount` is everything the order filled over its life.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OutEvent {
+    pub market: Pubkey,
+    pub order_id: u64,
+    pub trader: Pubkey,
+    pub side: OrderType,
+    pub price: u64,
+    pub filled_amount: u64,
+}
+
+// Define a resting order that left the book with an amount left, whether its
+// owner canceled it or it expired or was pruned
+//
+// `canceled_amount` is the amount it still rested with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CancelEvent {
+    pub market: Pubkey,
+    pub order_id: u64,
+    pub trader: Pubkey,
+    pub side: OrderType,
+    pub price: u64,
+    pub canceled_amount: u64,
+}
+
+// Define the events the program logs with sol_log_data for trade feeds
+//
+// Each event is logged as one `Program data:` field holding a tag byte and
+// the event, so a consumer of the program's logs decodes them without
+// parsing the human readable messages. Dark pool markets only log fills.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FordexEvent {
+    Fill { market: Pubkey, fill: FillEvent },
+    Out(OutEvent),
+    Place(PlaceEvent),
+    Cancel(CancelEvent),
+}
+
+impl FordexEvent {
+    // The order events of a change to the book from `before` to `after`
+    pub fn order_events(
+        change: &BookChangeEvent,
+        before: &OrderBook,
+        after: &OrderBook,
+    ) -> Vec<FordexEvent> {
+        let mut events = vec![];
+        for order_id in &change.removed {
+            let Some(order) = before.find_order(*order_id) else {
+                continue;
+            };
+            let closed = after.find_closed_order(*order_id);
+            let event = match closed.map(|closed| (closed.status, closed.filled_amount)) {
+                Some((OrderStatus::Filled, filled_amount)) => FordexEvent::Out(OutEvent {
+                    market: change.market,
+                    order_id: order.order_id,
+                    trader: order.trader,
+                    side: order.order_type,
+                    price: order.price,
+                    filled_amount,
+                }),
+                status => {
+                    let filled_now = status.map_or(0, |(_, filled_amount)| {
+                        filled_amount.saturating_sub(order.filled_amount)
+                    });
+                    FordexEvent::Cancel(CancelEvent {
+                        market: change.market,
+                        order_id: order.order_id,
+                        trader: order.trader,
+                        side: order.order_type,
+                        price: order.price,
+                        canceled_amount: order.amount.saturating_sub(filled_now),
+                    })
+                }
+            };
+            events.push(event);
+        }
+        for order_id in &change.added {
+            if let Some(order) = after.find_order(*order_id) {
+                events.push(FordexEvent::Place(PlaceEvent {
+                    market: change.market,
+                    order_id: order.order_id,
+                    trader: order.trader,
+                    side: order.order_type,
+                    price: order.price,
+                    amount: order.amount,
+                }));
+            }
+        }
+        events
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        let mut order_event = |tag: u8,
+                               market: &Pubkey,
+                               order_id: u64,
+                               trader: &Pubkey,
+                               side,
+                               price: u64,
+                               amount: u64| {
+            buf.push(tag);
+            buf.extend_from_slice(&market.to_bytes());
+            buf.extend_from_slice(&order_id.to_le_bytes());
+            buf.extend_from_slice(&trader.to_bytes());
+            buf.push(side as u8);
+            buf.extend_from_slice(&price.to_le_bytes());
+            buf.extend_from_slice(&amount.to_le_bytes());
+        };
+        match self {
+            FordexEvent::Fill { market, fill } => {
+                buf.push(0);
+                buf.extend_from_slice(&market.to_bytes());
+                buf.extend_from_slice(&fill.maker.to_bytes());
+                buf.extend_from_slice(&fill.maker_order_id.to_le_bytes());
+                buf.extend_from_slice(&fill.taker.to_bytes());
+                buf.extend_from_slice(&fill.taker_order_id.to_le_bytes());
+                buf.push(fill.taker_side as u8);
+                buf.extend_from_slice(&fill.price.to_le_bytes());
+                buf.extend_from_slice(&fill.amount.to_le_bytes());
+                buf.extend_from_slice(&fill.maker_remaining.to_le_bytes());
+                buf.extend_from_slice(&fill.taker_fee_bps.to_le_bytes());
+                buf.extend_from_slice(&fill.taker_fee.to_le_bytes());
+                buf.extend_from_slice(&fill.fee_subsidy.to_le_bytes());
+                buf.extend_from_slice(&fill.maker_tag.to_le_bytes());
+                buf.extend_from_slice(&fill.taker_tag.to_le_bytes());
+            }
+            FordexEvent::Out(out) => order_event(
+                1,
+                &out.market,
+                out.order_id,
+                &out.trader,
+                out.side,
+                out.price,
+                out.filled_amount,
+            ),
+            FordexEvent::Place(place) => order_event(
+                2,
+                &place.market,
+                place.order_id,
+                &place.trader,
+                place.side,
+                place.price,
+                place.amount,
+            ),
+            FordexEvent::Cancel(cancel) => order_event(
+                3,
+                &cancel.market,
+                cancel.order_id,
+                &cancel.trader,
+                cancel.side,
+                cancel.price,
+                cancel.canceled_amount,
+            ),
+        }
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let (&tag, rest) = data.split_first().ok_or(ProgramError::InvalidAccountData)?;
+        let len = match tag {
+            0 => 171,
+            1..=3 => 89,
+            _ => {
+                msg!("Unknown event tag {}", tag);
+                return Err(ProgramError::InvalidAccountData);
+            }
+        };
+        if rest.len() != len {
+            msg!("Event has an unexpected length");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![rest, offset, 8]);
+        let pubkey_at = |offset: usize| Pubkey::new_from_array(*array_ref![rest, offset, 32]);
+        let side_at = |offset: usize| match rest[offset] {
+            0 => Ok(OrderType::Buy),
+            1 => Ok(OrderType::Sell),
+            _ => {
+                msg!("Event holds an invalid side");
+                Err(ProgramError::InvalidAccountData)
+            }
+        };
+        let market = pubkey_at(0);
+        if tag == 0 {
+            return Ok(FordexEvent::Fill {
+                market,
+                fill: FillEvent {
+                    maker: pubkey_at(32),
+                    maker_order_id: u64_at(64),
+                    taker: pubkey_at(72),
+                    taker_order_id: u64_at(104),
+                    taker_side: side_at(112)?,
+                    price: u64_at(113),
+                    amount: u64_at(121),
+                    maker_remaining: u64_at(129),
+                    taker_fee_bps: u16::from_le_bytes(*array_ref![rest, 137, 2]),
+                    taker_fee: u64_at(139),
+                    fee_subsidy: u64_at(147),
+                    maker_tag: u64_at(155),
+                    taker_tag: u64_at(163),
+                },
+            });
+        }
+        let (order_id, trader, side, price, amount) = (
+            u64_at(32),
+            pubkey_at(40),
+            side_at(72)?,
+            u64_at(73),
+            u64_at(81),
+        );
+        Ok(match tag {
+            1 => FordexEvent::Out(OutEvent {
+                market,
+                order_id,
+                trader,
+                side,
+                price,
+                filled_amount: amount,
+            }),
+            2 => FordexEvent::Place(PlaceEvent {
+                market,
+                order_id,
+                trader,
+                side,
+                price,
+                amount,
+            }),
+            _ => FordexEvent::Cancel(CancelEvent {
+                market,
+                order_id,
+                trader,
+                side,
+                price,
+                canceled_amount: amount,
+            }),
+        })
+    }
+
+    // Log the event as program data
+    pub fn log(&self) {
+        sol_log_data(&[&self.pack()]);
+    }
+}
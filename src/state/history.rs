@@ -0,0 +1,281 @@
+// Trade history and candle PDAs fed from a market's fills
+
+use crate::{error::OrderBookError, state::OrderType};
+use arrayref::array_ref;
+use solana_program::{
+    clock::UnixTimestamp, entrypoint::ProgramResult, msg, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+// Define the header of the TradeHistory PDA a market may keep, a ring buffer
+// of its most recent fills for clients that do not run an indexer
+//
+// The header is followed by `capacity` TradeRecord slots. `total` counts
+// every fill appended, so fill `n` is stored in slot `n % capacity`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TradeHistory {
+    pub market: Pubkey,
+    pub capacity: u32,
+    pub total: u64,
+}
+
+// Define one fill recorded in a TradeHistory account
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TradeRecord {
+    pub price: u64,
+    pub amount: u64,
+    pub taker_side: OrderType,
+    pub unix_timestamp: UnixTimestamp,
+}
+
+impl TradeRecord {
+    pub const LEN: usize = 25;
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.price.to_le_bytes());
+        buf.extend_from_slice(&self.amount.to_le_bytes());
+        buf.push(self.taker_side as u8);
+        buf.extend_from_slice(&self.unix_timestamp.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Trade record too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(TradeRecord {
+            price: u64::from_le_bytes(*array_ref![data, 0, 8]),
+            amount: u64::from_le_bytes(*array_ref![data, 8, 8]),
+            taker_side: match data[16] {
+                0 => OrderType::Buy,
+                _ => OrderType::Sell,
+            },
+            unix_timestamp: i64::from_le_bytes(*array_ref![data, 17, 8]),
+        })
+    }
+}
+
+impl TradeHistory {
+    pub const HEADER_LEN: usize = 44;
+    // Largest capacity whose account can still be created by the program
+    pub const MAX_CAPACITY: u32 = 400;
+    pub const SEED: &'static [u8] = b"trade_history";
+
+    // Address of the TradeHistory PDA for an order book
+    pub fn find_address(program_id: &Pubkey, order_book: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED, order_book.as_ref()], program_id)
+    }
+
+    // Account size for a history of `capacity` fills
+    pub fn space(capacity: u32) -> usize {
+        Self::HEADER_LEN + capacity as usize * TradeRecord::LEN
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::HEADER_LEN);
+        buf.extend_from_slice(&self.market.to_bytes());
+        buf.extend_from_slice(&self.capacity.to_le_bytes());
+        buf.extend_from_slice(&self.total.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::HEADER_LEN {
+            msg!("Trade history account is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let history = TradeHistory {
+            market: Pubkey::new_from_array(*array_ref![data, 0, 32]),
+            capacity: u32::from_le_bytes(*array_ref![data, 32, 4]),
+            total: u64::from_le_bytes(*array_ref![data, 36, 8]),
+        };
+        if history.capacity == 0 {
+            msg!("Trade history account holds an invalid capacity");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if data.len() != Self::space(history.capacity) {
+            msg!("Trade history account size does not match its capacity");
+            return Err(OrderBookError::InvalidAccountSize.into());
+        }
+        Ok(history)
+    }
+
+    // Write a fill into the next slot of the account data, overwriting the
+    // oldest once the buffer is full
+    pub fn append(&mut self, data: &mut [u8], record: &TradeRecord) {
+        let start = Self::space((self.total % self.capacity as u64) as u32);
+        data[start..start + TradeRecord::LEN].copy_from_slice(&record.pack());
+        self.total += 1;
+        data[..Self::HEADER_LEN].copy_from_slice(&self.pack());
+    }
+
+    // The recorded fills, oldest first
+    pub fn records(&self, data: &[u8]) -> Result<Vec<TradeRecord>, ProgramError> {
+        let stored = self.total.min(self.capacity as u64);
+        (self.total - stored..self.total)
+            .map(|n| TradeRecord::unpack(&data[Self::space((n % self.capacity as u64) as u32)..]))
+            .collect()
+    }
+}
+
+// Define the header of a CandleSeries PDA, a ring buffer of a market's
+// candles for one interval
+//
+// The header is followed by `capacity` Candle slots. `total` counts every
+// candle started, so candle `n` is stored in slot `n % capacity`. Candles
+// start at multiples of `interval` seconds and only exist for intervals
+// with fills.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CandleSeries {
+    pub market: Pubkey,
+    pub interval: u32,
+    pub capacity: u32,
+    pub total: u64,
+}
+
+// Define one candle of a CandleSeries; `volume` is in base atoms
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Candle {
+    pub start_time: UnixTimestamp,
+    pub open: u64,
+    pub high: u64,
+    pub low: u64,
+    pub close: u64,
+    pub volume: u64,
+}
+
+impl Candle {
+    pub const LEN: usize = 48;
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.start_time.to_le_bytes());
+        for value in [self.open, self.high, self.low, self.close, self.volume] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Candle data too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        Ok(Candle {
+            start_time: i64::from_le_bytes(*array_ref![data, 0, 8]),
+            open: u64_at(8),
+            high: u64_at(16),
+            low: u64_at(24),
+            close: u64_at(32),
+            volume: u64_at(40),
+        })
+    }
+}
+
+impl CandleSeries {
+    pub const HEADER_LEN: usize = 48;
+    // Largest capacity whose account can still be created by the program
+    pub const MAX_CAPACITY: u32 = 200;
+    pub const SEED: &'static [u8] = b"candles";
+
+    // Address of the CandleSeries PDA for an order book and interval
+    pub fn find_address(program_id: &Pubkey, order_book: &Pubkey, interval: u32) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[Self::SEED, order_book.as_ref(), &interval.to_le_bytes()],
+            program_id,
+        )
+    }
+
+    // Account size for a series of `capacity` candles
+    pub fn space(capacity: u32) -> usize {
+        Self::HEADER_LEN + capacity as usize * Candle::LEN
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::HEADER_LEN);
+        buf.extend_from_slice(&self.market.to_bytes());
+        buf.extend_from_slice(&self.interval.to_le_bytes());
+        buf.extend_from_slice(&self.capacity.to_le_bytes());
+        buf.extend_from_slice(&self.total.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::HEADER_LEN {
+            msg!("Candle series account is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let series = CandleSeries {
+            market: Pubkey::new_from_array(*array_ref![data, 0, 32]),
+            interval: u32::from_le_bytes(*array_ref![data, 32, 4]),
+            capacity: u32::from_le_bytes(*array_ref![data, 36, 4]),
+            total: u64::from_le_bytes(*array_ref![data, 40, 8]),
+        };
+        if series.interval == 0 || series.capacity == 0 {
+            msg!("Candle series account holds an invalid interval or capacity");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if data.len() != Self::space(series.capacity) {
+            msg!("Candle series account size does not match its capacity");
+            return Err(OrderBookError::InvalidAccountSize.into());
+        }
+        Ok(series)
+    }
+
+    fn slot_offset(&self, n: u64) -> usize {
+        Self::space((n % self.capacity as u64) as u32)
+    }
+
+    // Fold a fill into the candle for its interval in the account data,
+    // starting a new candle, and overwriting the oldest once the buffer is
+    // full, when the interval changed
+    pub fn record(
+        &mut self,
+        data: &mut [u8],
+        price: u64,
+        amount: u64,
+        now: UnixTimestamp,
+    ) -> ProgramResult {
+        let start_time = now - now.rem_euclid(self.interval as i64);
+        if self.total > 0 {
+            let offset = self.slot_offset(self.total - 1);
+            let mut candle = Candle::unpack(&data[offset..])?;
+            if candle.start_time >= start_time {
+                candle.high = candle.high.max(price);
+                candle.low = candle.low.min(price);
+                candle.close = price;
+                candle.volume = candle.volume.saturating_add(amount);
+                data[offset..offset + Candle::LEN].copy_from_slice(&candle.pack());
+                return Ok(());
+            }
+        }
+        let candle = Candle {
+            start_time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: amount,
+        };
+        let offset = self.slot_offset(self.total);
+        data[offset..offset + Candle::LEN].copy_from_slice(&candle.pack());
+        self.total += 1;
+        data[..Self::HEADER_LEN].copy_from_slice(&self.pack());
+        Ok(())
+    }
+
+    // The stored candles, oldest first
+    pub fn candles(&self, data: &[u8]) -> Result<Vec<Candle>, ProgramError> {
+        let stored = self.total.min(self.capacity as u64);
+        (self.total - stored..self.total)
+            .map(|n| Candle::unpack(&data[self.slot_offset(n)..]))
+            .collect()
+    }
+}
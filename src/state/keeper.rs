@@ -0,0 +1,82 @@
+// The KeeperBond PDA of a keeper bonding lamports for crank priority
+
+use arrayref::array_ref;
+use solana_program::{
+    clock::{Slot, UnixTimestamp},
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+// Define a KeeperBond PDA, the lamports `keeper` bonded with BondKeeper,
+// held by the PDA above its rent exemption
+//
+// The bonds form an optional keeper registry. While a market's backlog of
+// expired orders reaches `BACKLOG_ORDERS`, PruneExpiredOrders reserves each
+// expired order for `PRIORITY_WINDOW_SLOTS` after it expired to the keepers
+// bonding `FULL_PRIORITY_LAMPORTS`; smaller bonds wait part of the window in
+// proportion and keepers without a bond all of it. Keepers then stop racing
+// each other for the same orders during a backlog, and those that lose the
+// race learn so in simulation.
+//
+// Bonded lamports stay locked for `LOCK_SECONDS` after the last bond, after
+// which UnbondKeeper returns them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeeperBond {
+    pub keeper: Pubkey,
+    pub bonded_lamports: u64,
+    pub bonded_at: UnixTimestamp,
+}
+
+impl KeeperBond {
+    pub const LEN: usize = 48;
+    pub const SEED: &'static [u8] = b"keeper_bond";
+    // Expired orders on a market from which bonded keepers get priority
+    pub const BACKLOG_ORDERS: usize = 8;
+    // Slots after an order expired during which bonded keepers get priority
+    // (about ten seconds)
+    pub const PRIORITY_WINDOW_SLOTS: Slot = 25;
+    // Bond giving a keeper the whole priority window (10 SOL)
+    pub const FULL_PRIORITY_LAMPORTS: u64 = 10_000_000_000;
+    // Time bonded lamports stay locked after the last bond (a day)
+    pub const LOCK_SECONDS: UnixTimestamp = 86_400;
+
+    // Address of the KeeperBond PDA of a keeper
+    pub fn find_address(program_id: &Pubkey, keeper: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED, keeper.as_ref()], program_id)
+    }
+
+    // Slots after an order expired before a keeper bonding `bonded_lamports`
+    // may prune it during a backlog
+    pub fn claim_delay(bonded_lamports: u64) -> Slot {
+        let weight = bonded_lamports.min(Self::FULL_PRIORITY_LAMPORTS) as u128;
+        let waived =
+            Self::PRIORITY_WINDOW_SLOTS as u128 * weight / Self::FULL_PRIORITY_LAMPORTS as u128;
+        Self::PRIORITY_WINDOW_SLOTS - waived as Slot
+    }
+
+    pub fn unlocks_at(&self) -> UnixTimestamp {
+        self.bonded_at.saturating_add(Self::LOCK_SECONDS)
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(self.keeper.as_ref());
+        buf.extend_from_slice(&self.bonded_lamports.to_le_bytes());
+        buf.extend_from_slice(&self.bonded_at.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("KeeperBond account is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        Ok(KeeperBond {
+            keeper: Pubkey::new_from_array(*array_ref![data, 0, 32]),
+            bonded_lamports: u64::from_le_bytes(*array_ref![data, 32, 8]),
+            bonded_at: i64::from_le_bytes(*array_ref![data, 40, 8]),
+        })
+    }
+}
@@ -0,0 +1,46 @@
+// Accounts, events and query responses of the order book program
+
+mod auction;
+mod blocklist;
+mod config;
+mod events;
+mod history;
+mod keeper;
+mod open_orders;
+mod oracle;
+mod order;
+mod order_book;
+mod query;
+mod receipt;
+
+pub use auction::*;
+pub use blocklist::*;
+pub use config::*;
+pub use events::*;
+pub use history::*;
+pub use keeper::*;
+pub use open_orders::*;
+pub use oracle::*;
+pub use order::*;
+pub use order_book::*;
+pub use query::*;
+pub use receipt::*;
+
+use crate::error::OrderBookError;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, msg};
+
+// Check an account holds exactly the bytes of its layout before reading it at
+// fixed offsets, so a truncated or oversized account is rejected rather than
+// misread
+pub(crate) fn check_account_size(account_info: &AccountInfo, expected: usize) -> ProgramResult {
+    if account_info.data_len() != expected {
+        msg!(
+            "Account {} holds {} bytes, expected {}",
+            account_info.key,
+            account_info.data_len(),
+            expected
+        );
+        return Err(OrderBookError::InvalidAccountSize.into());
+    }
+    Ok(())
+}
@@ -0,0 +1,342 @@
+// OpenOrders accounts holding traders' balances
+
+use crate::{
+    error::OrderBookError,
+    state::{check_account_size, Order, OrderType},
+};
+use arrayref::array_ref;
+use solana_program::{
+    account_info::AccountInfo,
+    clock::{Epoch, Slot},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+// Define the balances one OpenOrders account holds for a single market
+//
+// Locked balances back the owner's resting orders and free balances can be
+// withdrawn with SettleFunds. `order_count` is the number of the owner's
+// orders resting in the market.
+//
+// `maker_volume` is the owner's maker volume in the market's fee epoch
+// `fee_epoch`. Once that epoch closes the volume moves to
+// `unclaimed_maker_volume`, of epoch `unclaimed_epoch`, until the owner
+// claims its share of the epoch's rewards.
+//
+// On a market that limits orders per slot, `slot_order_count` counts the
+// orders placed in `last_order_slot`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OpenOrdersMarket {
+    pub market: Pubkey,
+    pub base_free: u64,
+    pub base_locked: u64,
+    pub quote_free: u64,
+    pub quote_locked: u64,
+    pub order_count: u32,
+    pub active_epoch_end: Epoch,
+    pub fee_epoch: u64,
+    pub maker_volume: u64,
+    pub unclaimed_epoch: u64,
+    pub unclaimed_maker_volume: u64,
+    pub last_order_slot: Slot,
+    pub slot_order_count: u16,
+}
+
+impl OpenOrdersMarket {
+    pub const LEN: usize = 118;
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.market.to_bytes());
+        buf.extend_from_slice(&self.base_free.to_le_bytes());
+        buf.extend_from_slice(&self.base_locked.to_le_bytes());
+        buf.extend_from_slice(&self.quote_free.to_le_bytes());
+        buf.extend_from_slice(&self.quote_locked.to_le_bytes());
+        buf.extend_from_slice(&self.order_count.to_le_bytes());
+        buf.extend_from_slice(&self.active_epoch_end.to_le_bytes());
+        buf.extend_from_slice(&self.fee_epoch.to_le_bytes());
+        buf.extend_from_slice(&self.maker_volume.to_le_bytes());
+        buf.extend_from_slice(&self.unclaimed_epoch.to_le_bytes());
+        buf.extend_from_slice(&self.unclaimed_maker_volume.to_le_bytes());
+        buf.extend_from_slice(&self.last_order_slot.to_le_bytes());
+        buf.extend_from_slice(&self.slot_order_count.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("OpenOrders market record too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        Ok(OpenOrdersMarket {
+            market: Pubkey::new_from_array(*array_ref![data, 0, 32]),
+            base_free: u64_at(32),
+            base_locked: u64_at(40),
+            quote_free: u64_at(48),
+            quote_locked: u64_at(56),
+            order_count: u32::from_le_bytes(*array_ref![data, 64, 4]),
+            active_epoch_end: u64_at(68),
+            fee_epoch: u64_at(76),
+            maker_volume: u64_at(84),
+            unclaimed_epoch: u64_at(92),
+            unclaimed_maker_volume: u64_at(100),
+            last_order_slot: u64_at(108),
+            slot_order_count: u16::from_le_bytes(*array_ref![data, 116, 2]),
+        })
+    }
+
+    // Whether the record holds nothing and its slot can be reused
+    pub fn is_empty(&self) -> bool {
+        self.base_free == 0
+            && self.base_locked == 0
+            && self.quote_free == 0
+            && self.quote_locked == 0
+            && self.order_count == 0
+            && self.maker_volume == 0
+            && self.unclaimed_maker_volume == 0
+    }
+
+    // Lock funds for an order: quote for a bid, base for an ask
+    pub(crate) fn lock(&mut self, order_type: OrderType, amount: u64) {
+        match order_type {
+            OrderType::Buy => self.quote_locked += amount,
+            OrderType::Sell => self.base_locked += amount,
+        }
+    }
+
+    // Release funds locked by an order that left the book unfilled
+    pub(crate) fn unlock(&mut self, order_type: OrderType, amount: u64) {
+        match order_type {
+            OrderType::Buy => {
+                self.quote_locked -= amount;
+                self.quote_free += amount;
+            }
+            OrderType::Sell => {
+                self.base_locked -= amount;
+                self.base_free += amount;
+            }
+        }
+    }
+
+    // Settle one side of a fill of `amount` base atoms for `quote` quote
+    // atoms. A bid releases `locked_quote`, which exceeds `quote` when a taker
+    // bid fills below its limit price.
+    pub(crate) fn fill(
+        &mut self,
+        order_type: OrderType,
+        amount: u64,
+        quote: u64,
+        locked_quote: u64,
+    ) {
+        match order_type {
+            OrderType::Buy => {
+                self.quote_locked -= locked_quote;
+                self.quote_free += locked_quote - quote;
+                self.base_free += amount;
+            }
+            OrderType::Sell => {
+                self.base_locked -= amount;
+                self.quote_free += quote;
+            }
+        }
+    }
+}
+
+// Define the OpenOrders account holding a trader's balances in every market
+// quoted in one mint
+//
+// The account is a PDA of the owner and quote mint, so an active trader
+// needs one account per quote currency rather than one per market. Each
+// market gets a sub-record on first use, which SettleFunds releases once it
+// is empty, so up to `MAX_MARKETS` markets can be traded at a time.
+//
+// `beneficial_owner` starts as the owner. Accounts of different owners set
+// to the same beneficial owner with SetBeneficialOwner, such as one per
+// trading strategy, never trade against each other.
+//
+// `withdraw_authority` also starts as the owner. Only it signs SettleFunds
+// and SettleAndWithdrawAll, so an owner that hands it to a cold key with
+// SetWithdrawAuthority keeps a trading key that can place and cancel
+// orders but not move funds out.
+//
+// A non-zero `cancel_after_slot`, set by the owner with CancelAllAfter, is a
+// cutoff for every market the account trades: any of the owner's orders
+// placed in a later slot, such as a stale duplicate of a transaction that
+// landed late, can be canceled by anyone with CancelExpiredOrder. The owner
+// lifts it by setting it back to zero.
+//
+// A market record's `active_epoch_end` is one past the last epoch it placed
+// an order in, or zero before its first, and counts it once per epoch in the
+// market's unique traders.
+//
+// As in the order book, the first byte holds the layout version the account
+// was last written with, or zero while it is uninitialized, and accounts
+// written with a newer layout than `LAYOUT_VERSION` are refused.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OpenOrders {
+    pub is_initialized: bool,
+    pub owner: Pubkey,
+    pub quote_mint: Pubkey,
+    pub markets: Vec<OpenOrdersMarket>,
+    pub beneficial_owner: Pubkey,
+    pub withdraw_authority: Pubkey,
+    pub cancel_after_slot: Slot,
+}
+
+impl OpenOrders {
+    // Version of the account layout, bumped whenever it changes
+    pub const LAYOUT_VERSION: u8 = 5;
+    // Maximum number of markets with balances or resting orders at a time
+    pub const MAX_MARKETS: usize = 8;
+    // Header, the fixed-size market records, the beneficial owner, the
+    // withdraw authority and the cancel cutoff
+    pub const BENEFICIAL_OWNER_OFFSET: usize = 66 + Self::MAX_MARKETS * OpenOrdersMarket::LEN;
+    pub const WITHDRAW_AUTHORITY_OFFSET: usize = Self::BENEFICIAL_OWNER_OFFSET + 32;
+    pub const CANCEL_AFTER_SLOT_OFFSET: usize = Self::WITHDRAW_AUTHORITY_OFFSET + 32;
+    pub const LEN: usize = Self::CANCEL_AFTER_SLOT_OFFSET + 8;
+    pub const SEED: &'static [u8] = b"open_orders";
+
+    // Address of the OpenOrders PDA for an owner and quote mint
+    pub fn find_address(program_id: &Pubkey, owner: &Pubkey, quote_mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[Self::SEED, owner.as_ref(), quote_mint.as_ref()],
+            program_id,
+        )
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.push(match self.is_initialized {
+            true => Self::LAYOUT_VERSION,
+            false => 0,
+        });
+        buf.extend_from_slice(&self.owner.to_bytes());
+        buf.extend_from_slice(&self.quote_mint.to_bytes());
+        buf.push(self.markets.len() as u8);
+        for market in &self.markets {
+            buf.extend_from_slice(&market.pack());
+        }
+        buf.resize(Self::BENEFICIAL_OWNER_OFFSET, 0);
+        buf.extend_from_slice(&self.beneficial_owner.to_bytes());
+        buf.extend_from_slice(&self.withdraw_authority.to_bytes());
+        buf.extend_from_slice(&self.cancel_after_slot.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("OpenOrders account is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        if data[0] > Self::LAYOUT_VERSION {
+            msg!(
+                "OpenOrders account has layout version {}, newer than this program's {}",
+                data[0],
+                Self::LAYOUT_VERSION
+            );
+            return Err(OrderBookError::UnsupportedLayoutVersion.into());
+        }
+        let count = data[65] as usize;
+        if count > Self::MAX_MARKETS {
+            msg!("OpenOrders account holds an invalid market count");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(OpenOrders {
+            is_initialized: data[0] != 0,
+            owner: Pubkey::new_from_array(*array_ref![data, 1, 32]),
+            quote_mint: Pubkey::new_from_array(*array_ref![data, 33, 32]),
+            markets: (0..count)
+                .map(|i| OpenOrdersMarket::unpack(&data[66 + i * OpenOrdersMarket::LEN..]))
+                .collect::<Result<_, _>>()?,
+            beneficial_owner: Pubkey::new_from_array(*array_ref![
+                data,
+                Self::BENEFICIAL_OWNER_OFFSET,
+                32
+            ]),
+            withdraw_authority: Pubkey::new_from_array(*array_ref![
+                data,
+                Self::WITHDRAW_AUTHORITY_OFFSET,
+                32
+            ]),
+            cancel_after_slot: u64::from_le_bytes(*array_ref![
+                data,
+                Self::CANCEL_AFTER_SLOT_OFFSET,
+                8
+            ]),
+        })
+    }
+
+    // Check the account's layout version, then its size
+    pub(crate) fn check_layout(account_info: &AccountInfo) -> ProgramResult {
+        let version = account_info
+            .try_borrow_data()?
+            .first()
+            .copied()
+            .unwrap_or_default();
+        if version > Self::LAYOUT_VERSION {
+            msg!(
+                "OpenOrders account has layout version {}, newer than this program's {}",
+                version,
+                Self::LAYOUT_VERSION
+            );
+            return Err(OrderBookError::UnsupportedLayoutVersion.into());
+        }
+        if version != 0 && version < Self::LAYOUT_VERSION && account_info.data_len() != Self::LEN {
+            msg!(
+                "OpenOrders account has layout version {} of {} bytes, which this program's version {} cannot read",
+                version,
+                account_info.data_len(),
+                Self::LAYOUT_VERSION
+            );
+            return Err(OrderBookError::OutdatedLayoutVersion.into());
+        }
+        check_account_size(account_info, Self::LEN)
+    }
+
+    // Store the OpenOrders account into the account data
+    pub fn pack_into_account(&self, account_info: &AccountInfo) -> ProgramResult {
+        account_info.try_borrow_mut_data()?[..Self::LEN].copy_from_slice(&self.pack());
+        Ok(())
+    }
+
+    // Whether an order of the owner was placed after the cancel cutoff
+    pub fn is_past_cutoff(&self, order: &Order) -> bool {
+        self.cancel_after_slot > 0 && order.slot > self.cancel_after_slot
+    }
+
+    // The balances held for a market, if any
+    pub fn market(&self, market: &Pubkey) -> Option<&OpenOrdersMarket> {
+        self.markets.iter().find(|record| &record.market == market)
+    }
+
+    // The balances held for a market, adding a record on first use
+    pub(crate) fn market_mut(
+        &mut self,
+        market: &Pubkey,
+    ) -> Result<&mut OpenOrdersMarket, ProgramError> {
+        let index = match self
+            .markets
+            .iter()
+            .position(|record| &record.market == market)
+        {
+            Some(index) => index,
+            None if self.markets.len() < Self::MAX_MARKETS => {
+                self.markets.push(OpenOrdersMarket {
+                    market: *market,
+                    ..OpenOrdersMarket::default()
+                });
+                self.markets.len() - 1
+            }
+            None => {
+                msg!("OpenOrders account has no room for market {}", market);
+                return Err(OrderBookError::OpenOrdersFull.into());
+            }
+        };
+        Ok(&mut self.markets[index])
+    }
+}
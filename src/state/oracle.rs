@@ -0,0 +1,265 @@
+// External oracle formats and the PriceOracle PDA markets publish
+
+use crate::{
+    error::OrderBookError,
+    state::{OrderBook, OrderType},
+};
+use arrayref::array_ref;
+use solana_program::{
+    clock::{Clock, Slot, UnixTimestamp},
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+// Define the oracle account formats a market can read prices from
+//
+// `Custom` accounts hold a `CustomOraclePrice` for programs that publish
+// their own prices.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OracleSource {
+    #[default]
+    None,
+    Pyth,
+    Switchboard,
+    Custom,
+}
+
+// Field offsets of a Pyth v2 price account
+pub const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+pub const PYTH_PRICE_ACCOUNT_TYPE: u32 = 3;
+pub const PYTH_STATUS_TRADING: u32 = 1;
+pub const PYTH_ACCOUNT_TYPE_OFFSET: usize = 8;
+pub const PYTH_EXPO_OFFSET: usize = 20;
+pub const PYTH_TIMESTAMP_OFFSET: usize = 96;
+pub const PYTH_AGG_PRICE_OFFSET: usize = 208;
+pub const PYTH_AGG_CONF_OFFSET: usize = 216;
+pub const PYTH_AGG_STATUS_OFFSET: usize = 224;
+pub const PYTH_PRICE_LEN: usize = 240;
+
+// Field offsets of a Switchboard v2 aggregator account, within its latest
+// confirmed round. Decimals are an i128 mantissa and a u32 scale.
+pub const SWITCHBOARD_ROUND_OPEN_TIMESTAMP_OFFSET: usize = 358;
+pub const SWITCHBOARD_RESULT_OFFSET: usize = 366;
+pub const SWITCHBOARD_STD_DEVIATION_OFFSET: usize = 386;
+pub const SWITCHBOARD_AGGREGATOR_LEN: usize = 406;
+
+impl OracleSource {
+    pub fn from_u8(tag: u8) -> Result<Self, ProgramError> {
+        match tag {
+            0 => Ok(OracleSource::None),
+            1 => Ok(OracleSource::Pyth),
+            2 => Ok(OracleSource::Switchboard),
+            3 => Ok(OracleSource::Custom),
+            _ => {
+                msg!("Invalid oracle source");
+                Err(ProgramError::InvalidInstructionData)
+            }
+        }
+    }
+
+    // Read the price held in an oracle account of this format
+    pub fn read_price(self, data: &[u8]) -> Result<OraclePrice, ProgramError> {
+        let u32_at = |offset: usize| u32::from_le_bytes(*array_ref![data, offset, 4]);
+        let i64_at = |offset: usize| i64::from_le_bytes(*array_ref![data, offset, 8]);
+        let price = match self {
+            OracleSource::None => None,
+            OracleSource::Pyth => (data.len() >= PYTH_PRICE_LEN
+                && u32_at(0) == PYTH_MAGIC
+                && u32_at(PYTH_ACCOUNT_TYPE_OFFSET) == PYTH_PRICE_ACCOUNT_TYPE
+                && u32_at(PYTH_AGG_STATUS_OFFSET) == PYTH_STATUS_TRADING)
+                .then(|| OraclePrice {
+                    price: i64_at(PYTH_AGG_PRICE_OFFSET),
+                    expo: i32::from_le_bytes(*array_ref![data, PYTH_EXPO_OFFSET, 4]),
+                    conf: u64::from_le_bytes(*array_ref![data, PYTH_AGG_CONF_OFFSET, 8]),
+                    publish_time: i64_at(PYTH_TIMESTAMP_OFFSET),
+                }),
+            OracleSource::Switchboard if data.len() >= SWITCHBOARD_AGGREGATOR_LEN => {
+                let decimal = |offset: usize| {
+                    (
+                        i128::from_le_bytes(*array_ref![data, offset, 16]),
+                        u32_at(offset + 16),
+                    )
+                };
+                let (mantissa, scale) = decimal(SWITCHBOARD_RESULT_OFFSET);
+                let (deviation, deviation_scale) = decimal(SWITCHBOARD_STD_DEVIATION_OFFSET);
+                // Express the deviation at the result's scale
+                let conf = match deviation_scale.checked_sub(scale) {
+                    Some(shift) => 10i128
+                        .checked_pow(shift)
+                        .map_or(Some(0), |divisor| deviation.checked_div(divisor)),
+                    None => 10i128
+                        .checked_pow(scale - deviation_scale)
+                        .and_then(|factor| deviation.checked_mul(factor)),
+                };
+                match (i64::try_from(mantissa), i32::try_from(scale), conf) {
+                    (Ok(price), Ok(scale), Some(conf)) => Some(OraclePrice {
+                        price,
+                        expo: -scale,
+                        conf: u64::try_from(conf.unsigned_abs()).unwrap_or(u64::MAX),
+                        publish_time: i64_at(SWITCHBOARD_ROUND_OPEN_TIMESTAMP_OFFSET),
+                    }),
+                    _ => None,
+                }
+            }
+            OracleSource::Switchboard => None,
+            OracleSource::Custom => {
+                CustomOraclePrice::unpack(data)
+                    .ok()
+                    .map(|custom| OraclePrice {
+                        price: custom.price,
+                        expo: custom.expo,
+                        conf: custom.conf,
+                        publish_time: custom.publish_time,
+                    })
+            }
+        };
+        price.filter(|price| price.price > 0).ok_or_else(|| {
+            msg!("Oracle account holds no usable {:?} price", self);
+            OrderBookError::InvalidOraclePrice.into()
+        })
+    }
+}
+
+// Define a price read from an oracle, worth `price * 10^expo` quote units
+// per base unit, with confidence interval `conf` at the same exponent
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OraclePrice {
+    pub price: i64,
+    pub expo: i32,
+    pub conf: u64,
+    pub publish_time: UnixTimestamp,
+}
+
+// Define the documented layout of a custom oracle account
+//
+// The account starts with `MAGIC` so that other account types are not read
+// as prices by mistake, followed by the price fields in little-endian order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CustomOraclePrice {
+    pub price: i64,
+    pub expo: i32,
+    pub conf: u64,
+    pub publish_time: UnixTimestamp,
+}
+
+impl CustomOraclePrice {
+    pub const MAGIC: [u8; 4] = *b"FDXO";
+    pub const LEN: usize = 32;
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&Self::MAGIC);
+        buf.extend_from_slice(&self.price.to_le_bytes());
+        buf.extend_from_slice(&self.expo.to_le_bytes());
+        buf.extend_from_slice(&self.conf.to_le_bytes());
+        buf.extend_from_slice(&self.publish_time.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN || data[..4] != Self::MAGIC {
+            msg!("Not a custom oracle price account");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(CustomOraclePrice {
+            price: i64::from_le_bytes(*array_ref![data, 4, 8]),
+            expo: i32::from_le_bytes(*array_ref![data, 12, 4]),
+            conf: u64::from_le_bytes(*array_ref![data, 16, 8]),
+            publish_time: i64::from_le_bytes(*array_ref![data, 24, 8]),
+        })
+    }
+}
+
+// Define the PriceOracle PDA published for a market, so other programs can
+// read its prices without understanding the order book layout
+//
+// The layout is stable: fields are only ever appended, into the reserved
+// zero bytes at the end of the account, and `version` counts the appends.
+// `twap` averages the last trade price over about `TWAP_WINDOW` seconds of
+// updates. `best_bid` and `best_ask` are zero when that side is empty or the
+// market is a dark pool.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceOracle {
+    pub version: u8,
+    pub market: Pubkey,
+    pub last_trade_price: u64,
+    pub twap: u64,
+    pub best_bid: u64,
+    pub best_ask: u64,
+    pub updated_slot: Slot,
+    pub updated_at: UnixTimestamp,
+}
+
+impl PriceOracle {
+    pub const VERSION: u8 = 1;
+    // Account size, including the bytes reserved for appended fields
+    pub const LEN: usize = 128;
+    pub const SEED: &'static [u8] = b"price_oracle";
+    pub const TWAP_WINDOW: UnixTimestamp = 3_600;
+
+    // Address of the PriceOracle PDA for an order book
+    pub fn find_address(program_id: &Pubkey, order_book: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED, order_book.as_ref()], program_id)
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.push(self.version);
+        buf.extend_from_slice(&self.market.to_bytes());
+        buf.extend_from_slice(&self.last_trade_price.to_le_bytes());
+        buf.extend_from_slice(&self.twap.to_le_bytes());
+        buf.extend_from_slice(&self.best_bid.to_le_bytes());
+        buf.extend_from_slice(&self.best_ask.to_le_bytes());
+        buf.extend_from_slice(&self.updated_slot.to_le_bytes());
+        buf.extend_from_slice(&self.updated_at.to_le_bytes());
+        buf.resize(Self::LEN, 0);
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Price oracle account is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        Ok(PriceOracle {
+            version: data[0],
+            market: Pubkey::new_from_array(*array_ref![data, 1, 32]),
+            last_trade_price: u64_at(33),
+            twap: u64_at(41),
+            best_bid: u64_at(49),
+            best_ask: u64_at(57),
+            updated_slot: u64_at(65),
+            updated_at: i64::from_le_bytes(*array_ref![data, 73, 8]),
+        })
+    }
+
+    // Refresh the prices from the order book at the given time
+    pub fn update(&mut self, order_book: &OrderBook, clock: &Clock) {
+        let price = order_book.last_trade_price;
+        let elapsed = clock.unix_timestamp.saturating_sub(self.updated_at);
+        self.twap = if self.twap == 0 || elapsed >= Self::TWAP_WINDOW {
+            price
+        } else {
+            let weight = elapsed.max(0) as i128;
+            let twap = self.twap as i128;
+            (twap + (price as i128 - twap) * weight / Self::TWAP_WINDOW as i128) as u64
+        };
+        self.last_trade_price = price;
+        let best = |side| match order_book.dark_pool {
+            true => 0,
+            false => order_book
+                .best_order(side, clock)
+                .map_or(0, |(_, price)| price),
+        };
+        self.best_bid = best(OrderType::Buy);
+        self.best_ask = best(OrderType::Sell);
+        self.updated_slot = clock.slot;
+        self.updated_at = clock.unix_timestamp;
+    }
+}
@@ -0,0 +1,615 @@
+// Orders and their packed layout
+
+use crate::state::OrderBook;
+use arrayref::array_ref;
+use solana_program::{
+    clock::{Clock, Slot, UnixTimestamp, DEFAULT_MS_PER_SLOT},
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+// Byte offsets of the fields of a packed order
+pub const ORDER_TRADER_OFFSET: usize = 0;
+pub const ORDER_AMOUNT_OFFSET: usize = 32;
+pub const ORDER_PRICE_OFFSET: usize = 40;
+pub const ORDER_TYPE_OFFSET: usize = 48;
+pub const ORDER_SLOT_OFFSET: usize = 49;
+pub const ORDER_UNIX_TIMESTAMP_OFFSET: usize = 57;
+pub const ORDER_ID_OFFSET: usize = 65;
+pub const ORDER_EXPIRES_AT_OFFSET: usize = 73;
+pub const ORDER_BOUNTY_OFFSET: usize = 81;
+pub const ORDER_PEG_OFFSET: usize = 89;
+pub const ORDER_PEG_TICKS_OFFSET: usize = 90;
+pub const ORDER_LINKED_ORDER_ID_OFFSET: usize = 98;
+pub const ORDER_TRAIL_TICKS_OFFSET: usize = 107;
+pub const ORDER_TRIGGER_PRICE_OFFSET: usize = 115;
+pub const ORDER_IMPROVEMENT_SLOTS_OFFSET: usize = 123;
+pub const ORDER_AVG_PRICE_LIMIT_OFFSET: usize = 131;
+pub const ORDER_MIN_FILL_AMOUNT_OFFSET: usize = 139;
+pub const ORDER_BENEFICIAL_OWNER_OFFSET: usize = 147;
+pub const ORDER_DECAY_START_PRICE_OFFSET: usize = 179;
+pub const ORDER_DECAY_END_OFFSET: usize = 187;
+pub const ORDER_QUOTE_BUDGET_OFFSET: usize = 195;
+pub const ORDER_EXPIRY_UNIT_OFFSET: usize = 203;
+pub const ORDER_FILLED_AMOUNT_OFFSET: usize = 204;
+pub const ORDER_FILLED_QUOTE_OFFSET: usize = 212;
+pub const ORDER_FEES_PAID_OFFSET: usize = 220;
+pub const ORDER_TAG_OFFSET: usize = 228;
+pub const ORDER_PRIORITY_BID_OFFSET: usize = 236;
+pub const ORDER_SETTLE_AT_OFFSET: usize = 244;
+
+// Offsets at which a PlaceOrder payload may end: after `order_type`, the
+// last field the first clients sent, or after any later field
+const ORDER_PAYLOAD_ENDS: [usize; 25] = [
+    ORDER_SLOT_OFFSET,
+    ORDER_UNIX_TIMESTAMP_OFFSET,
+    ORDER_ID_OFFSET,
+    ORDER_EXPIRES_AT_OFFSET,
+    ORDER_BOUNTY_OFFSET,
+    ORDER_PEG_OFFSET,
+    ORDER_PEG_TICKS_OFFSET,
+    ORDER_LINKED_ORDER_ID_OFFSET,
+    ORDER_TRAIL_TICKS_OFFSET,
+    ORDER_TRIGGER_PRICE_OFFSET,
+    ORDER_IMPROVEMENT_SLOTS_OFFSET,
+    ORDER_AVG_PRICE_LIMIT_OFFSET,
+    ORDER_MIN_FILL_AMOUNT_OFFSET,
+    ORDER_BENEFICIAL_OWNER_OFFSET,
+    ORDER_DECAY_START_PRICE_OFFSET,
+    ORDER_DECAY_END_OFFSET,
+    ORDER_QUOTE_BUDGET_OFFSET,
+    ORDER_EXPIRY_UNIT_OFFSET,
+    ORDER_FILLED_AMOUNT_OFFSET,
+    ORDER_FILLED_QUOTE_OFFSET,
+    ORDER_FEES_PAID_OFFSET,
+    ORDER_TAG_OFFSET,
+    ORDER_PRIORITY_BID_OFFSET,
+    ORDER_SETTLE_AT_OFFSET,
+    Order::LEN,
+];
+
+// Define the fields of an order
+//
+// `slot` and `unix_timestamp` record when the order was placed and
+// `order_id` identifies it for cancellation. `filled_amount` and
+// `filled_quote` count the base and quote it has filled so far, and
+// `fees_paid` the taker fees it paid on them. They are stamped by the
+// program, along with the `beneficial_owner` of the trader's OpenOrders
+// account; any values supplied by the client in the PlaceOrder instruction
+// are overwritten.
+//
+// A non-zero `expires_at` makes the order good-til-date: it stops matching
+// at that unix time, or at that slot when `expiry_unit` is `Slot`, and any
+// keeper may then cancel it with CancelExpiredOrder and collect the `bounty`
+// lamports the owner attached.
+//
+// A pegged order's price floats `peg_ticks` ticks from the reference price
+// chosen by `peg`, and `price` is its limit: the highest price a pegged bid
+// pays or the lowest a pegged ask accepts. See `OrderBook::effective_price`.
+//
+// Two orders of a trader can be linked one-cancels-other by placing the
+// second with `linked_order_id` set to the first, which must be resting and
+// unlinked. A fill on either order cancels the other; canceling either
+// without a fill leaves the other resting unlinked.
+//
+// A non-zero `trail_ticks` makes the order a trailing stop. It waits off the
+// book with `trigger_price` trailing the best last trade price by that many
+// ticks, and becomes a limit order at `price` once TriggerStopOrder fires it.
+//
+// A marketable order with a non-zero `improvement_slots` is held off the book
+// for that many slots, during which makers can post quotes better than the
+// book's. SettleAuction then matches it against the book including them.
+//
+// A non-zero `avg_price_limit` bounds the average price the order fills at
+// when it takes: the highest for a bid, the lowest for an ask. Matching stops
+// once another fill would break it, and the unfilled remainder is canceled
+// rather than resting at a price the book still crosses. Matching likewise
+// stops, canceling the remainder, at a maker order of the same beneficial
+// owner.
+//
+// A non-zero `min_fill_amount` is the least a marketable order must fill
+// when it takes. If the book would fill less, the whole order is canceled
+// without matching; an order that would fill nothing rests as usual.
+//
+// A non-zero `decay_start_price` lists a sell order as a Dutch auction. Its
+// ask starts at that price when placed and decays linearly to its `price`
+// floor at `decay_end`, rounded up to a tick, then rests at the floor. It
+// never decays through the best bid, and takers lift it at its current price
+// until its amount is exhausted. See `OrderBook::effective_price`.
+//
+// A bid with a non-zero `quote_budget` is sized in quote rather than base:
+// it spends up to that many quote atoms before fees, and matching buys as
+// many whole lots as the remaining budget affords at each fill price. Its
+// `amount` is left zero for the program to fill in. Whatever remains of the
+// budget rests as the whole lots it buys at the order's limit price.
+//
+// `tag` is an opaque value the client attaches to the order, echoed in the
+// fill events on either side of it, so a firm running several strategies
+// through one OpenOrders account can attribute its fills to them.
+//
+// `priority_bid` is the lamports a taker pays a market in priority auction
+// mode to be matched ahead of the other takers of its slot. See
+// `OrderBook::priority_auction`.
+//
+// A non-zero `settle_at` makes the order trade at settlement: rather than
+// matching the book, it waits for the market's reference price at that unix
+// time, and SettleReferenceOrders then crosses it with the opposite orders
+// of the same `settle_at` at that price. `price` is its limit, beyond which
+// it does not trade. See `OrderBook::match_reference_orders`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Order {
+    pub trader: Pubkey,
+    pub amount: u64,
+    pub price: u64,
+    pub order_type: OrderType,
+    pub slot: Slot,
+    pub unix_timestamp: UnixTimestamp,
+    pub order_id: u64,
+    pub expires_at: UnixTimestamp,
+    pub bounty: u64,
+    pub peg: PegType,
+    pub peg_ticks: i64,
+    pub linked_order_id: Option<u64>,
+    pub trail_ticks: u64,
+    pub trigger_price: u64,
+    pub improvement_slots: u64,
+    pub avg_price_limit: u64,
+    pub min_fill_amount: u64,
+    pub beneficial_owner: Pubkey,
+    pub decay_start_price: u64,
+    pub decay_end: UnixTimestamp,
+    pub quote_budget: u64,
+    pub expiry_unit: ExpiryUnit,
+    pub filled_amount: u64,
+    pub filled_quote: u64,
+    pub fees_paid: u64,
+    pub tag: u64,
+    pub priority_bid: u64,
+    pub settle_at: UnixTimestamp,
+}
+
+impl Order {
+    pub const LEN: usize = 252;
+
+    // Pack the order data into a byte array
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.trader.to_bytes());
+        buf.extend_from_slice(&self.amount.to_le_bytes());
+        buf.extend_from_slice(&self.price.to_le_bytes());
+        buf.push(self.order_type as u8);
+        buf.extend_from_slice(&self.slot.to_le_bytes());
+        buf.extend_from_slice(&self.unix_timestamp.to_le_bytes());
+        buf.extend_from_slice(&self.order_id.to_le_bytes());
+        buf.extend_from_slice(&self.expires_at.to_le_bytes());
+        buf.extend_from_slice(&self.bounty.to_le_bytes());
+        buf.push(self.peg as u8);
+        buf.extend_from_slice(&self.peg_ticks.to_le_bytes());
+        buf.push(self.linked_order_id.is_some() as u8);
+        buf.extend_from_slice(&self.linked_order_id.unwrap_or_default().to_le_bytes());
+        buf.extend_from_slice(&self.trail_ticks.to_le_bytes());
+        buf.extend_from_slice(&self.trigger_price.to_le_bytes());
+        buf.extend_from_slice(&self.improvement_slots.to_le_bytes());
+        buf.extend_from_slice(&self.avg_price_limit.to_le_bytes());
+        buf.extend_from_slice(&self.min_fill_amount.to_le_bytes());
+        buf.extend_from_slice(&self.beneficial_owner.to_bytes());
+        buf.extend_from_slice(&self.decay_start_price.to_le_bytes());
+        buf.extend_from_slice(&self.decay_end.to_le_bytes());
+        buf.extend_from_slice(&self.quote_budget.to_le_bytes());
+        buf.push(self.expiry_unit as u8);
+        buf.extend_from_slice(&self.filled_amount.to_le_bytes());
+        buf.extend_from_slice(&self.filled_quote.to_le_bytes());
+        buf.extend_from_slice(&self.fees_paid.to_le_bytes());
+        buf.extend_from_slice(&self.tag.to_le_bytes());
+        buf.extend_from_slice(&self.priority_bid.to_le_bytes());
+        buf.extend_from_slice(&self.settle_at.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Order data too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let trader = Pubkey::new_from_array(*array_ref![data, ORDER_TRADER_OFFSET, 32]);
+        let amount = u64::from_le_bytes(*array_ref![data, ORDER_AMOUNT_OFFSET, 8]);
+        let price = u64::from_le_bytes(*array_ref![data, ORDER_PRICE_OFFSET, 8]);
+        let order_type = match data.get(ORDER_TYPE_OFFSET) {
+            Some(0) => OrderType::Buy,
+            Some(1) => OrderType::Sell,
+            _ => {
+                msg!("Invalid order type");
+                return Err(ProgramError::InvalidAccountData);
+            }
+        };
+        let slot = u64::from_le_bytes(*array_ref![data, ORDER_SLOT_OFFSET, 8]);
+        let unix_timestamp = i64::from_le_bytes(*array_ref![data, ORDER_UNIX_TIMESTAMP_OFFSET, 8]);
+        let order_id = u64::from_le_bytes(*array_ref![data, ORDER_ID_OFFSET, 8]);
+        let expires_at = i64::from_le_bytes(*array_ref![data, ORDER_EXPIRES_AT_OFFSET, 8]);
+        let bounty = u64::from_le_bytes(*array_ref![data, ORDER_BOUNTY_OFFSET, 8]);
+        let peg = match data[ORDER_PEG_OFFSET] {
+            0 => PegType::None,
+            1 => PegType::BestBid,
+            2 => PegType::BestAsk,
+            3 => PegType::Midpoint,
+            _ => {
+                msg!("Invalid peg type");
+                return Err(ProgramError::InvalidAccountData);
+            }
+        };
+        let peg_ticks = i64::from_le_bytes(*array_ref![data, ORDER_PEG_TICKS_OFFSET, 8]);
+        let linked_order_id = (data[ORDER_LINKED_ORDER_ID_OFFSET] != 0)
+            .then(|| u64::from_le_bytes(*array_ref![data, ORDER_LINKED_ORDER_ID_OFFSET + 1, 8]));
+        let trail_ticks = u64::from_le_bytes(*array_ref![data, ORDER_TRAIL_TICKS_OFFSET, 8]);
+        let trigger_price = u64::from_le_bytes(*array_ref![data, ORDER_TRIGGER_PRICE_OFFSET, 8]);
+        let improvement_slots =
+            u64::from_le_bytes(*array_ref![data, ORDER_IMPROVEMENT_SLOTS_OFFSET, 8]);
+        let avg_price_limit =
+            u64::from_le_bytes(*array_ref![data, ORDER_AVG_PRICE_LIMIT_OFFSET, 8]);
+        let min_fill_amount =
+            u64::from_le_bytes(*array_ref![data, ORDER_MIN_FILL_AMOUNT_OFFSET, 8]);
+        let beneficial_owner =
+            Pubkey::new_from_array(*array_ref![data, ORDER_BENEFICIAL_OWNER_OFFSET, 32]);
+        let decay_start_price =
+            u64::from_le_bytes(*array_ref![data, ORDER_DECAY_START_PRICE_OFFSET, 8]);
+        let decay_end = i64::from_le_bytes(*array_ref![data, ORDER_DECAY_END_OFFSET, 8]);
+        let quote_budget = u64::from_le_bytes(*array_ref![data, ORDER_QUOTE_BUDGET_OFFSET, 8]);
+        let filled_amount = u64::from_le_bytes(*array_ref![data, ORDER_FILLED_AMOUNT_OFFSET, 8]);
+        let filled_quote = u64::from_le_bytes(*array_ref![data, ORDER_FILLED_QUOTE_OFFSET, 8]);
+        let fees_paid = u64::from_le_bytes(*array_ref![data, ORDER_FEES_PAID_OFFSET, 8]);
+        let tag = u64::from_le_bytes(*array_ref![data, ORDER_TAG_OFFSET, 8]);
+        let priority_bid = u64::from_le_bytes(*array_ref![data, ORDER_PRIORITY_BID_OFFSET, 8]);
+        let settle_at = i64::from_le_bytes(*array_ref![data, ORDER_SETTLE_AT_OFFSET, 8]);
+        let expiry_unit = match data[ORDER_EXPIRY_UNIT_OFFSET] {
+            0 => ExpiryUnit::UnixTime,
+            1 => ExpiryUnit::Slot,
+            _ => {
+                msg!("Invalid expiry unit");
+                return Err(ProgramError::InvalidAccountData);
+            }
+        };
+        Ok(Order {
+            trader,
+            amount,
+            price,
+            order_type,
+            slot,
+            unix_timestamp,
+            order_id,
+            expires_at,
+            bounty,
+            peg,
+            peg_ticks,
+            linked_order_id,
+            trail_ticks,
+            trigger_price,
+            improvement_slots,
+            avg_price_limit,
+            min_fill_amount,
+            beneficial_owner,
+            decay_start_price,
+            decay_end,
+            quote_budget,
+            expiry_unit,
+            filled_amount,
+            filled_quote,
+            fees_paid,
+            tag,
+            priority_bid,
+            settle_at,
+        })
+    }
+
+    // Unpack the order of a PlaceOrder instruction. Clients built before
+    // later fields were appended send a shorter payload, which may end after
+    // any field from `order_type` on; the fields it leaves out take their
+    // defaults, all zero. Bytes past `LEN` were appended by a newer version
+    // and are ignored.
+    pub fn unpack_payload(data: &[u8]) -> Result<Self, ProgramError> {
+        let len = data.len().min(Self::LEN);
+        if !ORDER_PAYLOAD_ENDS.contains(&len) {
+            msg!(
+                "PlaceOrder data of {} bytes ends inside an order field",
+                len
+            );
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        let mut padded = [0; Self::LEN];
+        padded[..len].copy_from_slice(&data[..len]);
+        Self::unpack(&padded)
+    }
+
+    // Whether a good-til-date order has expired at the given time
+    pub fn is_expired(&self, now: &Clock) -> bool {
+        self.expires_at != 0
+            && match self.expiry_unit {
+                ExpiryUnit::UnixTime => now.unix_timestamp >= self.expires_at,
+                ExpiryUnit::Slot => now.slot >= self.expires_at as Slot,
+            }
+    }
+
+    // Whether the order is a trailing stop waiting to be triggered
+    pub fn is_stop(&self) -> bool {
+        self.trail_ticks > 0
+    }
+
+    // Whether the order waits off the book, as a trailing stop or in a price
+    // improvement window
+    pub fn is_waiting(&self) -> bool {
+        self.is_stop() || self.improvement_slots > 0 || self.is_reference()
+    }
+
+    // Whether the order trades at settlement, at a reference price fixed
+    // later
+    pub fn is_reference(&self) -> bool {
+        self.settle_at != 0
+    }
+
+    // Whether the order is a Dutch auction listing
+    pub fn is_dutch(&self) -> bool {
+        self.decay_start_price > 0
+    }
+
+    // Price a fixed-price order is listed at. A Dutch auction ask
+    // decays linearly from its start price at placement to its floor at
+    // `decay_end`, rounded up to a multiple of `tick_size`.
+    pub fn current_price(&self, now: &Clock, tick_size: u64) -> u64 {
+        if !self.is_dutch() || now.unix_timestamp >= self.decay_end {
+            return self.price;
+        }
+        let elapsed = now
+            .unix_timestamp
+            .saturating_sub(self.unix_timestamp)
+            .max(0) as u128;
+        let duration = (self.decay_end - self.unix_timestamp).max(1) as u128;
+        let range = self.decay_start_price.saturating_sub(self.price) as u128;
+        let decayed = self.decay_start_price - (range * elapsed / duration) as u64;
+        decayed.div_ceil(tick_size) * tick_size
+    }
+
+    // Lamports the order holds in the order book account while resting
+    pub fn locked_lamports(&self) -> u64 {
+        OrderBook::ORDER_DEPOSIT_LAMPORTS + self.bounty
+    }
+}
+
+// Define the reference prices a pegged order can float against
+//
+// References are taken from the best live orders with fixed prices, so
+// pegged orders never peg to each other.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PegType {
+    None,
+    BestBid,
+    BestAsk,
+    Midpoint,
+}
+
+// Define the units an order's `expires_at` counts in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExpiryUnit {
+    #[default]
+    UnixTime,
+    Slot,
+}
+
+// Define the lifecycle states an order id can be queried in
+//
+// An order is `Open` until it first fills and `PartiallyFilled` while the
+// rest of it waits on or off the book. It is `Filled` once it filled
+// completely and `Canceled` once removed with an amount left, however it
+// filled before. Ids the market never issued, and orders that closed too
+// long ago to be remembered, are `Unknown`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OrderStatus {
+    Unknown,
+    Open,
+    PartiallyFilled,
+    Filled,
+    Canceled,
+}
+
+impl OrderStatus {
+    pub fn from_u8(tag: u8) -> Result<Self, ProgramError> {
+        match tag {
+            0 => Ok(OrderStatus::Unknown),
+            1 => Ok(OrderStatus::Open),
+            2 => Ok(OrderStatus::PartiallyFilled),
+            3 => Ok(OrderStatus::Filled),
+            4 => Ok(OrderStatus::Canceled),
+            _ => {
+                msg!("Invalid order status");
+                Err(ProgramError::InvalidAccountData)
+            }
+        }
+    }
+}
+
+// Define an order that left the book or never rested, as the book remembers
+// it
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClosedOrder {
+    pub order_id: u64,
+    pub trader: Pubkey,
+    pub side: OrderType,
+    pub status: OrderStatus,
+    pub filled_amount: u64,
+    pub filled_quote: u64,
+    pub fees_paid: u64,
+}
+
+impl ClosedOrder {
+    pub const LEN: usize = 66;
+
+    pub fn new(order: &Order, status: OrderStatus) -> Self {
+        ClosedOrder {
+            order_id: order.order_id,
+            trader: order.trader,
+            side: order.order_type,
+            status,
+            filled_amount: order.filled_amount,
+            filled_quote: order.filled_quote,
+            fees_paid: order.fees_paid,
+        }
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.order_id.to_le_bytes());
+        buf.extend_from_slice(&self.trader.to_bytes());
+        buf.push(self.side as u8);
+        buf.push(self.status as u8);
+        buf.extend_from_slice(&self.filled_amount.to_le_bytes());
+        buf.extend_from_slice(&self.filled_quote.to_le_bytes());
+        buf.extend_from_slice(&self.fees_paid.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Closed order too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        Ok(ClosedOrder {
+            order_id: u64_at(0),
+            trader: Pubkey::new_from_array(*array_ref![data, 8, 32]),
+            side: match data[40] {
+                0 => OrderType::Buy,
+                _ => OrderType::Sell,
+            },
+            status: OrderStatus::from_u8(data[41])?,
+            filled_amount: u64_at(42),
+            filled_quote: u64_at(50),
+            fees_paid: u64_at(58),
+        })
+    }
+}
+
+// Define a resting order's entry in the book's expiration wheel
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExpiryEntry {
+    pub expiry_unit: ExpiryUnit,
+    pub expires_at: UnixTimestamp,
+    pub order_id: u64,
+}
+
+impl ExpiryEntry {
+    pub const LEN: usize = 17;
+
+    // The entry of an order that expires, or None for a good-till-canceled
+    // order
+    pub fn new(order: &Order) -> Option<Self> {
+        (order.expires_at != 0).then_some(ExpiryEntry {
+            expiry_unit: order.expiry_unit,
+            expires_at: order.expires_at,
+            order_id: order.order_id,
+        })
+    }
+
+    // Key the wheel is sorted by: each unit's bucket in expiry order, orders
+    // expiring together by id
+    pub fn key(&self) -> (u8, UnixTimestamp, u64) {
+        (self.expiry_unit as u8, self.expires_at, self.order_id)
+    }
+
+    pub fn is_expired(&self, now: &Clock) -> bool {
+        match self.expiry_unit {
+            ExpiryUnit::UnixTime => now.unix_timestamp >= self.expires_at,
+            ExpiryUnit::Slot => now.slot >= self.expires_at as Slot,
+        }
+    }
+
+    // Slots since the order expired at `now`, converting a time expiry at
+    // the default slot duration
+    pub fn expired_for(&self, now: &Clock) -> Slot {
+        match self.expiry_unit {
+            ExpiryUnit::UnixTime => {
+                let seconds = now.unix_timestamp.saturating_sub(self.expires_at).max(0) as u64;
+                seconds * 1_000 / DEFAULT_MS_PER_SLOT
+            }
+            ExpiryUnit::Slot => now.slot.saturating_sub(self.expires_at as Slot),
+        }
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.push(self.expiry_unit as u8);
+        buf.extend_from_slice(&self.expires_at.to_le_bytes());
+        buf.extend_from_slice(&self.order_id.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Expiry entry too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(ExpiryEntry {
+            expiry_unit: match data[0] {
+                0 => ExpiryUnit::UnixTime,
+                _ => ExpiryUnit::Slot,
+            },
+            expires_at: i64::from_le_bytes(*array_ref![data, 1, 8]),
+            order_id: u64::from_le_bytes(*array_ref![data, 9, 8]),
+        })
+    }
+}
+
+// Define the status of one order id and the base it filled
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderStatusEntry {
+    pub order_id: u64,
+    pub status: OrderStatus,
+    pub filled_amount: u64,
+}
+
+impl OrderStatusEntry {
+    pub const LEN: usize = 17;
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.order_id.to_le_bytes());
+        buf.push(self.status as u8);
+        buf.extend_from_slice(&self.filled_amount.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Order status entry too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(OrderStatusEntry {
+            order_id: u64::from_le_bytes(*array_ref![data, 0, 8]),
+            status: OrderStatus::from_u8(data[8])?,
+            filled_amount: u64::from_le_bytes(*array_ref![data, 9, 8]),
+        })
+    }
+}
+
+// Define the two types of orders (buy and sell)
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OrderType {
+    Buy,
+    Sell,
+}
+
+impl OrderType {
+    // The side of the book an incoming order of this type matches against
+    pub fn opposite(self) -> Self {
+        match self {
+            OrderType::Buy => OrderType::Sell,
+            OrderType::Sell => OrderType::Buy,
+        }
+    }
+}
@@ -0,0 +1,1655 @@
+// The order book account
+
+use crate::{
+    error::OrderBookError,
+    math::{mul_div, Rounding, RoundingPolicy},
+    state::{
+        check_account_size, ClosedOrder, ExpiryEntry, ExpiryUnit, LayoutUpgradedEvent,
+        OpenOrdersMarket, OraclePrice, OracleSource, Order, OrderStatus, OrderStatusEntry,
+        OrderType, PegType, ORDER_TRADER_OFFSET,
+    },
+};
+use arrayref::array_ref;
+use solana_program::{
+    account_info::AccountInfo,
+    clock::{Clock, Epoch, Slot, UnixTimestamp},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+// Byte offsets of the fields of the order book account
+pub const ORDER_BOOK_IS_INITIALIZED_OFFSET: usize = 0;
+pub const ORDER_BOOK_BASE_MINT_OFFSET: usize = 1;
+pub const ORDER_BOOK_QUOTE_MINT_OFFSET: usize = 33;
+pub const ORDER_BOOK_CREATOR_OFFSET: usize = 65;
+pub const ORDER_BOOK_TICK_SIZE_OFFSET: usize = 97;
+pub const ORDER_BOOK_LOT_SIZE_OFFSET: usize = 105;
+pub const ORDER_BOOK_BUY_COUNT_OFFSET: usize = 113;
+pub const ORDER_BOOK_SELL_COUNT_OFFSET: usize = 117;
+pub const ORDER_BOOK_NEXT_ORDER_ID_OFFSET: usize = 121;
+pub const ORDER_BOOK_BASE_VAULT_OFFSET: usize = 129;
+pub const ORDER_BOOK_QUOTE_VAULT_OFFSET: usize = 161;
+pub const ORDER_BOOK_VAULT_SIGNER_BUMP_OFFSET: usize = 193;
+pub const ORDER_BOOK_FEE_COLLECTOR_OFFSET: usize = 194;
+pub const ORDER_BOOK_BUY_TAKER_FEE_BPS_OFFSET: usize = 226;
+pub const ORDER_BOOK_SELL_TAKER_FEE_BPS_OFFSET: usize = 228;
+pub const ORDER_BOOK_FEES_COLLECTED_OFFSET: usize = 230;
+pub const ORDER_BOOK_FEES_SWEPT_OFFSET: usize = 238;
+pub const ORDER_BOOK_FEES_OUTSTANDING_OFFSET: usize = 246;
+pub const ORDER_BOOK_PROTOCOL_FEE_BPS_OFFSET: usize = 254;
+pub const ORDER_BOOK_PROTOCOL_FEES_COLLECTED_OFFSET: usize = 256;
+pub const ORDER_BOOK_PROTOCOL_FEES_SWEPT_OFFSET: usize = 264;
+pub const ORDER_BOOK_PROTOCOL_FEES_OUTSTANDING_OFFSET: usize = 272;
+pub const ORDER_BOOK_BACKSTOP_DISCOUNT_BPS_OFFSET: usize = 280;
+pub const ORDER_BOOK_INCENTIVE_BALANCE_OFFSET: usize = 282;
+pub const ORDER_BOOK_BACKSTOP_MAKER_COUNT_OFFSET: usize = 290;
+pub const ORDER_BOOK_BACKSTOP_MAKERS_OFFSET: usize = 291;
+pub const ORDER_BOOK_LAST_TRADE_PRICE_OFFSET: usize =
+    ORDER_BOOK_BACKSTOP_MAKERS_OFFSET + OrderBook::MAX_BACKSTOP_MAKERS * 32;
+pub const ORDER_BOOK_STOP_COUNT_OFFSET: usize = ORDER_BOOK_LAST_TRADE_PRICE_OFFSET + 8;
+pub const ORDER_BOOK_DARK_POOL_OFFSET: usize = ORDER_BOOK_STOP_COUNT_OFFSET + 4;
+pub const ORDER_BOOK_PENDING_COUNT_OFFSET: usize = ORDER_BOOK_DARK_POOL_OFFSET + 1;
+pub const ORDER_BOOK_ORACLE_SOURCE_OFFSET: usize = ORDER_BOOK_PENDING_COUNT_OFFSET + 4;
+pub const ORDER_BOOK_ORACLE_OFFSET: usize = ORDER_BOOK_ORACLE_SOURCE_OFFSET + 1;
+pub const ORDER_BOOK_SEQ_NUM_OFFSET: usize = ORDER_BOOK_ORACLE_OFFSET + 32;
+pub const ORDER_BOOK_TRADE_HISTORY_OFFSET: usize = ORDER_BOOK_SEQ_NUM_OFFSET + 8;
+pub const ORDER_BOOK_CANDLE_SERIES_COUNT_OFFSET: usize = ORDER_BOOK_TRADE_HISTORY_OFFSET + 32;
+pub const ORDER_BOOK_CANDLE_SERIES_OFFSET: usize = ORDER_BOOK_CANDLE_SERIES_COUNT_OFFSET + 1;
+pub const ORDER_BOOK_BUY_OPEN_INTEREST_OFFSET: usize =
+    ORDER_BOOK_CANDLE_SERIES_OFFSET + OrderBook::MAX_CANDLE_SERIES * 32;
+pub const ORDER_BOOK_SELL_OPEN_INTEREST_OFFSET: usize = ORDER_BOOK_BUY_OPEN_INTEREST_OFFSET + 8;
+pub const ORDER_BOOK_STATS_EPOCH_OFFSET: usize = ORDER_BOOK_SELL_OPEN_INTEREST_OFFSET + 8;
+pub const ORDER_BOOK_UNIQUE_TRADERS_OFFSET: usize = ORDER_BOOK_STATS_EPOCH_OFFSET + 8;
+pub const ORDER_BOOK_DISABLED_FEATURES_OFFSET: usize = ORDER_BOOK_UNIQUE_TRADERS_OFFSET + 4;
+pub const ORDER_BOOK_WHOLE_UNITS_OFFSET: usize = ORDER_BOOK_DISABLED_FEATURES_OFFSET + 1;
+pub const ORDER_BOOK_PLACEMENT_FEE_LAMPORTS_OFFSET: usize = ORDER_BOOK_WHOLE_UNITS_OFFSET + 1;
+pub const ORDER_BOOK_CRANK_INCENTIVE_LAMPORTS_OFFSET: usize =
+    ORDER_BOOK_PLACEMENT_FEE_LAMPORTS_OFFSET + 8;
+pub const ORDER_BOOK_ROUNDING_POLICY_OFFSET: usize = ORDER_BOOK_CRANK_INCENTIVE_LAMPORTS_OFFSET + 8;
+pub const ORDER_BOOK_SUCCESSOR_OFFSET: usize = ORDER_BOOK_ROUNDING_POLICY_OFFSET + 1;
+pub const ORDER_BOOK_FEE_EPOCH_OFFSET: usize = ORDER_BOOK_SUCCESSOR_OFFSET + 32;
+pub const ORDER_BOOK_CLOSED_FEE_EPOCH_COUNT_OFFSET: usize =
+    ORDER_BOOK_FEE_EPOCH_OFFSET + FeeEpoch::LEN;
+pub const ORDER_BOOK_CLOSED_FEE_EPOCHS_OFFSET: usize = ORDER_BOOK_CLOSED_FEE_EPOCH_COUNT_OFFSET + 1;
+pub const ORDER_BOOK_ORDER_GATE_OFFSET: usize =
+    ORDER_BOOK_CLOSED_FEE_EPOCHS_OFFSET + OrderBook::MAX_CLOSED_FEE_EPOCHS * FeeEpoch::LEN;
+pub const ORDER_BOOK_PRIORITY_AUCTION_OFFSET: usize = ORDER_BOOK_ORDER_GATE_OFFSET + 32;
+pub const ORDER_BOOK_REFERENCE_COUNT_OFFSET: usize = ORDER_BOOK_PRIORITY_AUCTION_OFFSET + 1;
+pub const ORDER_BOOK_BLOCKLIST_OFFSET: usize = ORDER_BOOK_REFERENCE_COUNT_OFFSET + 4;
+pub const ORDER_BOOK_FILLED_LIFETIMES_OFFSET: usize = ORDER_BOOK_BLOCKLIST_OFFSET + 32;
+pub const ORDER_BOOK_CANCELED_LIFETIMES_OFFSET: usize =
+    ORDER_BOOK_FILLED_LIFETIMES_OFFSET + OrderBook::LIFETIME_BUCKETS * 4;
+pub const ORDER_BOOK_BOOK_CHECKSUM_OFFSET: usize =
+    ORDER_BOOK_CANCELED_LIFETIMES_OFFSET + OrderBook::LIFETIME_BUCKETS * 4;
+pub const ORDER_BOOK_PRUNE_AFTER_SLOTS_OFFSET: usize = ORDER_BOOK_BOOK_CHECKSUM_OFFSET + 8;
+pub const ORDER_BOOK_MAX_ORDERS_PER_SLOT_OFFSET: usize = ORDER_BOOK_PRUNE_AFTER_SLOTS_OFFSET + 8;
+pub const ORDER_BOOK_MAX_ORACLE_CONF_BPS_OFFSET: usize = ORDER_BOOK_MAX_ORDERS_PER_SLOT_OFFSET + 2;
+pub const ORDER_BOOK_BUY_ORDERS_OFFSET: usize = ORDER_BOOK_MAX_ORACLE_CONF_BPS_OFFSET + 2;
+pub const ORDER_BOOK_SELL_ORDERS_OFFSET: usize =
+    ORDER_BOOK_BUY_ORDERS_OFFSET + OrderBook::MAX_ORDERS * Order::LEN;
+pub const ORDER_BOOK_STOP_ORDERS_OFFSET: usize =
+    ORDER_BOOK_SELL_ORDERS_OFFSET + OrderBook::MAX_ORDERS * Order::LEN;
+pub const ORDER_BOOK_PENDING_ORDERS_OFFSET: usize =
+    ORDER_BOOK_STOP_ORDERS_OFFSET + OrderBook::MAX_STOP_ORDERS * Order::LEN;
+pub const ORDER_BOOK_REFERENCE_ORDERS_OFFSET: usize =
+    ORDER_BOOK_PENDING_ORDERS_OFFSET + OrderBook::MAX_PENDING_ORDERS * Order::LEN;
+pub const ORDER_BOOK_CLOSED_COUNT_OFFSET: usize =
+    ORDER_BOOK_REFERENCE_ORDERS_OFFSET + OrderBook::MAX_REFERENCE_ORDERS * Order::LEN;
+pub const ORDER_BOOK_CLOSED_ORDERS_OFFSET: usize = ORDER_BOOK_CLOSED_COUNT_OFFSET + 4;
+pub const ORDER_BOOK_EXPIRY_COUNT_OFFSET: usize =
+    ORDER_BOOK_CLOSED_ORDERS_OFFSET + OrderBook::MAX_CLOSED_ORDERS * ClosedOrder::LEN;
+pub const ORDER_BOOK_EXPIRATION_WHEEL_OFFSET: usize = ORDER_BOOK_EXPIRY_COUNT_OFFSET + 4;
+pub const ORDER_BOOK_BUY_LEVEL_COUNT_OFFSET: usize =
+    ORDER_BOOK_EXPIRATION_WHEEL_OFFSET + 2 * OrderBook::MAX_ORDERS * ExpiryEntry::LEN;
+pub const ORDER_BOOK_SELL_LEVEL_COUNT_OFFSET: usize = ORDER_BOOK_BUY_LEVEL_COUNT_OFFSET + 4;
+pub const ORDER_BOOK_BUY_LEVELS_OFFSET: usize = ORDER_BOOK_SELL_LEVEL_COUNT_OFFSET + 4;
+pub const ORDER_BOOK_SELL_LEVELS_OFFSET: usize =
+    ORDER_BOOK_BUY_LEVELS_OFFSET + OrderBook::MAX_ORDERS * PriceLevel::LEN;
+
+// Define one aggregated price level of a side of the book
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceLevel {
+    pub price: u64,
+    pub amount: u64,
+    pub order_count: u32,
+}
+
+impl PriceLevel {
+    pub const LEN: usize = 20;
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.price.to_le_bytes());
+        buf.extend_from_slice(&self.amount.to_le_bytes());
+        buf.extend_from_slice(&self.order_count.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Price level too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(PriceLevel {
+            price: u64::from_le_bytes(*array_ref![data, 0, 8]),
+            amount: u64::from_le_bytes(*array_ref![data, 8, 8]),
+            order_count: u32::from_le_bytes(*array_ref![data, 16, 4]),
+        })
+    }
+}
+
+// Define the accounting of one fee epoch of a market
+//
+// `fees` are the taker fees collected during the epoch and `volume` the
+// quote volume of its fee-paying fills, which is also the maker volume
+// `rewards` are shared by. `rewards_claimed` is the part makers claimed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeeEpoch {
+    pub epoch: u64,
+    pub fees: u64,
+    pub volume: u64,
+    pub rewards: u64,
+    pub rewards_claimed: u64,
+}
+
+impl FeeEpoch {
+    pub const LEN: usize = 40;
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.epoch.to_le_bytes());
+        buf.extend_from_slice(&self.fees.to_le_bytes());
+        buf.extend_from_slice(&self.volume.to_le_bytes());
+        buf.extend_from_slice(&self.rewards.to_le_bytes());
+        buf.extend_from_slice(&self.rewards_claimed.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Fee epoch too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        Ok(FeeEpoch {
+            epoch: u64_at(0),
+            fees: u64_at(8),
+            volume: u64_at(16),
+            rewards: u64_at(24),
+            rewards_claimed: u64_at(32),
+        })
+    }
+
+    // Rewards no maker has claimed yet
+    pub fn unclaimed_rewards(&self) -> u64 {
+        self.rewards - self.rewards_claimed
+    }
+}
+
+// A getProgramAccounts memcmp filter matching `bytes` at `offset` in the
+// account data
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemcmpFilter {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+}
+
+// Define the account data for the order book
+//
+// Each order book account is one market for a base/quote mint pair. Prices
+// must be multiples of `tick_size` and amounts multiples of `lot_size`; a
+// price is the number of quote atoms paid per `lot_size` base atoms.
+//
+// The market's tokens are held in `base_vault` and `quote_vault`, token
+// accounts owned by the vault signer PDA. Traders' shares of the vaults are
+// recorded in their OpenOrders accounts.
+//
+// Every resting order holds `ORDER_DEPOSIT_LAMPORTS` in the order book
+// account. The deposit is refunded to the owner when the order is canceled,
+// credited to the owner's OpenOrders account when the order fills, and paid
+// with its bounty to whoever prunes it with PruneOrder once it has rested for
+// `prune_after_slots`. Pruning takes a live order from an owner who may
+// still want it, so markets opt into it: the creator sets
+// `prune_after_slots` with SetPruneAfter, to zero to turn pruning off again
+// or to at least `MIN_PRUNE_AFTER_SLOTS`, and it is zero until then.
+//
+// Takers pay `buy_taker_fee_bps` or `sell_taker_fee_bps` of the quote amount
+// of every fill, depending on the side they take. Fees stay in
+// the quote vault until swept to the `fee_collector`; `fees_collected` and
+// `fees_swept` are cumulative and `fees_outstanding` is their difference.
+// `protocol_fee_bps` of each fee is the protocol's share, copied from the
+// Config at creation and tracked in the `protocol_fees_*` fields instead.
+//
+// Fills against the market's `backstop_makers` charge the taker
+// `backstop_discount_bps` less of the fee. The discount is paid from
+// `incentive_balance`, quote tokens deposited into the quote vault with
+// FundIncentives, so the fee collector still earns the full fee; once the
+// incentives run out takers pay the full fee again.
+//
+// `last_trade_price` is the price of the market's latest fill. Trailing stops
+// wait in `stop_orders`, and every fill ratchets their trigger prices.
+// Orders in their price improvement window wait in `pending_orders`.
+//
+// A `dark_pool` market keeps its resting orders out of the best order and
+// ExportBook queries and out of placement logs; fills are logged as usual.
+// The account data itself stays readable, so this hides orders from casual
+// observers rather than encrypting them.
+//
+// `oracle` is the account the market reads external prices from, in the
+// format given by `oracle_source`. See `OrderBook::read_oracle_price`. A
+// point estimate alone can be far off in a volatile or thin market, so the
+// creator may cap the oracle's confidence interval relative to its price at
+// `max_oracle_conf_bps` with SetOracleConfidence. Prices whose interval is
+// wider are refused; zero accepts any interval.
+//
+// `seq_num` counts the instructions that changed the resting orders, each of
+// which logs a BookChangeEvent. `book_checksum` is the rolling checksum of
+// those changes, carried by each event; see `BookChangeEvent::checksum`.
+//
+// `trade_history` is the market's TradeHistory PDA, or the default key while
+// the market keeps none. `candle_series` are its CandleSeries PDAs.
+//
+// The market stats region tracks the open interest, the base resting on each
+// side of the book, and `unique_traders`, the number of OpenOrders accounts
+// that placed an order during `stats_epoch`.
+//
+// `disabled_features` is a bitmask of the `OrderBook::FEATURE_*` order
+// capabilities new orders may not use on the market, so they can be rolled
+// out market by market. Orders already placed are unaffected.
+//
+// A `whole_units` market trades an indivisible asset, such as an NFT or a
+// semi-fungible token, whose base mint has no decimals. Its lot size is one
+// base atom, so amounts count whole units and prices are quote atoms per
+// unit.
+//
+// Every PlaceOrder pays the market's `placement_fee_lamports`, set by the
+// creator with SetPlacementFee, to fund keepers on markets whose trading fees
+// are too low to. The fees accrue in `crank_incentive_lamports`, held by the
+// order book account, and each expired order a keeper cancels earns it up to
+// one placement fee from them on top of the order's bounty.
+//
+// A market may cap the orders each OpenOrders account places in it per slot
+// at `max_orders_per_slot`, set by the creator with SetOrderRateLimit, to
+// blunt quote stuffing. Zero leaves placements unlimited.
+//
+// `rounding_policy`, set by the creator with SetRoundingPolicy, decides
+// which way the market's fee math rounds. See `RoundingPolicy`.
+//
+// A deprecated market names its `successor`, the market of the same pair
+// MigrateLiquidity moves its liquidity to. It takes no new orders once set.
+//
+// Fees, maker rewards and volume are accounted in fee epochs of
+// `FEE_EPOCH_SECONDS`, numbered from the Unix epoch. `fee_epoch` is the
+// current one and `closed_fee_epochs` the last `MAX_CLOSED_FEE_EPOCHS` that
+// saw trading, oldest first. FundEpochRewards adds quote tokens to the
+// current epoch's rewards, and once it closes each maker claims a share
+// proportional to its maker volume with ClaimEpochRewards. Rewards of an
+// epoch without volume, or left unclaimed when it is dropped from
+// `closed_fee_epochs`, roll over into the next epoch.
+//
+// A market with an `order_gate`, set by the creator with SetOrderGate, only
+// takes PlaceOrder through CPI from that program: the gate program's
+// signer PDA for the market, see `OrderBook::find_order_gate_signer`, must
+// sign. Venues put their own compliance or fee checks in front of the market
+// this way. RouteOrder does not route through gated markets.
+//
+// In a market in `priority_auction` mode, set by the creator with
+// SetPriorityAuction, takers of the same slot are matched in the order of
+// their `priority_bid` rather than of arrival. Marketable orders wait in
+// `pending_orders` until their slot has passed, paying the bid into the
+// crank incentives up front, and SettleAuction clears them earliest slot
+// first, highest bid first within a slot, then by order id. See
+// `OrderBook::next_priority_taker`.
+//
+// Orders trading at settlement wait in `reference_orders` until their
+// `settle_at`. The reference price is the TWAP of the market's PriceOracle
+// as updated within `REFERENCE_WINDOW` seconds after it; SettleReferenceOrders
+// crosses the orders at that price, and cancels them instead once the window
+// has passed without an update. Reference fills pay no taker fees and stay
+// out of the last trade price and the market feeds, which the reference
+// price is derived from.
+//
+// A market with a `blocklist`, set by the creator with SetBlocklist, takes
+// the Blocklist account in PlaceOrder and SettleFunds and refuses the
+// addresses listed on it; see `Blocklist`. RouteOrder and
+// SettleAndWithdrawAll do not touch such markets.
+//
+// `filled_lifetimes` and `canceled_lifetimes` count the orders that left the
+// book filled or canceled, expired and pruned orders included, bucketed by
+// how long they lived, with the bucket bounds in `LIFETIME_BUCKET_BOUNDS`.
+// The counters saturate and never reset; GetMarketStats returns them.
+//
+// The first byte holds the layout version the account was last written
+// with, or zero while it is uninitialized. Accounts written with a newer
+// layout than the program's `LAYOUT_VERSION` are refused, so a program
+// rolled back during a staggered upgrade cannot corrupt them. The version is
+// checked before the account's size, so an account of an older layout of a
+// different size is refused with OutdatedLayoutVersion rather than misread;
+// accounts are never resized in place. A layout change that keeps `LEN`
+// must keep the older fields' offsets, and such accounts are upgraded when
+// next written.
+//
+// `closed_orders` remembers the last `MAX_CLOSED_ORDERS` orders that left
+// the book or never rested, oldest first, with whether they filled or were
+// canceled and what they filled, for the GetOrderStatuses query and fill
+// receipts.
+//
+// `expiration_wheel` indexes the resting orders that expire, bucketed by
+// expiry unit and time and kept in expiry order, so PruneExpiredOrders
+// finds the expired orders without scanning the book. Orders enter it when
+// they rest and leave it when they close.
+//
+// The account data also holds an index of each side's price levels, best
+// first, aggregating the fixed-price orders by limit price. It is rewritten
+// from the orders whenever the book is stored, so GetDepth and on-chain
+// readers get the book's depth without walking its orders. Pegged orders
+// and Dutch auctions, whose prices move, are left out, and expired orders
+// count until they are canceled.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderBook {
+    pub is_initialized: bool,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub creator: Pubkey,
+    pub tick_size: u64,
+    pub lot_size: u64,
+    pub next_order_id: u64,
+    pub base_vault: Pubkey,
+    pub quote_vault: Pubkey,
+    pub vault_signer_bump: u8,
+    pub fee_collector: Pubkey,
+    pub buy_taker_fee_bps: u16,
+    pub sell_taker_fee_bps: u16,
+    pub fees_collected: u64,
+    pub fees_swept: u64,
+    pub fees_outstanding: u64,
+    pub protocol_fee_bps: u16,
+    pub protocol_fees_collected: u64,
+    pub protocol_fees_swept: u64,
+    pub protocol_fees_outstanding: u64,
+    pub backstop_discount_bps: u16,
+    pub incentive_balance: u64,
+    pub backstop_makers: Vec<Pubkey>,
+    pub last_trade_price: u64,
+    pub dark_pool: bool,
+    pub oracle_source: OracleSource,
+    pub oracle: Pubkey,
+    pub seq_num: u64,
+    pub trade_history: Pubkey,
+    pub candle_series: Vec<Pubkey>,
+    pub buy_open_interest: u64,
+    pub sell_open_interest: u64,
+    pub stats_epoch: Epoch,
+    pub unique_traders: u32,
+    pub disabled_features: u8,
+    pub whole_units: bool,
+    pub placement_fee_lamports: u64,
+    pub crank_incentive_lamports: u64,
+    pub rounding_policy: RoundingPolicy,
+    pub successor: Pubkey,
+    pub fee_epoch: FeeEpoch,
+    pub closed_fee_epochs: Vec<FeeEpoch>,
+    pub order_gate: Pubkey,
+    pub priority_auction: bool,
+    pub blocklist: Pubkey,
+    pub filled_lifetimes: [u32; OrderBook::LIFETIME_BUCKETS],
+    pub canceled_lifetimes: [u32; OrderBook::LIFETIME_BUCKETS],
+    pub book_checksum: u64,
+    pub prune_after_slots: Slot,
+    pub max_orders_per_slot: u16,
+    pub max_oracle_conf_bps: u16,
+    pub buy_orders: Vec<Order>,
+    pub sell_orders: Vec<Order>,
+    pub stop_orders: Vec<Order>,
+    pub pending_orders: Vec<Order>,
+    pub reference_orders: Vec<Order>,
+    pub closed_orders: Vec<ClosedOrder>,
+    pub expiration_wheel: Vec<ExpiryEntry>,
+}
+
+impl OrderBook {
+    // Version of the account layout, bumped whenever it changes, along with
+    // the copy in the fordex-reader crate's `layout`
+    pub const LAYOUT_VERSION: u8 = 22;
+    // Maximum number of resting orders on each side of the book
+    pub const MAX_ORDERS: usize = 64;
+    // Maximum number of backstop makers per market
+    pub const MAX_BACKSTOP_MAKERS: usize = 4;
+    // Maximum number of trailing stops waiting to be triggered
+    pub const MAX_STOP_ORDERS: usize = 16;
+    // Maximum number of orders in their price improvement window
+    pub const MAX_PENDING_ORDERS: usize = 8;
+    // Maximum number of orders waiting to trade at settlement
+    pub const MAX_REFERENCE_ORDERS: usize = 16;
+    // Time after an order's `settle_at` within which the PriceOracle must be
+    // updated to fix its reference price
+    pub const REFERENCE_WINDOW: UnixTimestamp = 300;
+    // Maximum number of candle intervals per market
+    pub const MAX_CANDLE_SERIES: usize = 3;
+    // Number of closed orders whose status the book remembers
+    pub const MAX_CLOSED_ORDERS: usize = 64;
+    // Upper bounds in seconds of all but the last order lifetime bucket,
+    // from under a second to a week and over
+    pub const LIFETIME_BUCKET_BOUNDS: [UnixTimestamp; 7] = [1, 10, 60, 600, 3_600, 86_400, 604_800];
+    pub const LIFETIME_BUCKETS: usize = Self::LIFETIME_BUCKET_BOUNDS.len() + 1;
+    // Number of closed fee epochs whose rewards can still be claimed
+    pub const MAX_CLOSED_FEE_EPOCHS: usize = 4;
+    // Length of a fee epoch (a week)
+    pub const FEE_EPOCH_SECONDS: u64 = 604_800;
+    // Trailing stop orders
+    pub const FEATURE_STOPS: u8 = 1 << 0;
+    // Pegged orders
+    pub const FEATURE_PEGS: u8 = 1 << 1;
+    // Price improvement windows
+    pub const FEATURE_AUCTIONS: u8 = 1 << 2;
+    // One-cancels-other linked orders
+    pub const FEATURE_LINKED_ORDERS: u8 = 1 << 3;
+    // Dutch auction listings
+    pub const FEATURE_DUTCH_AUCTIONS: u8 = 1 << 4;
+    // Orders trading at settlement
+    pub const FEATURE_REFERENCE_ORDERS: u8 = 1 << 5;
+    // Lamports held per resting order to make book stuffing costly
+    pub const ORDER_DEPOSIT_LAMPORTS: u64 = 10_000;
+    // Largest placement fee a market may charge
+    pub const MAX_PLACEMENT_FEE_LAMPORTS: u64 = 1_000_000;
+    // Shortest age after which a market may let anyone prune a resting order
+    // (about a week)
+    pub const MIN_PRUNE_AFTER_SLOTS: Slot = 1_512_000;
+    // Header followed by the fixed-size order slots for each side, the
+    // closed order statuses, the expiration wheel and the price level index
+    pub const LEN: usize = ORDER_BOOK_SELL_LEVELS_OFFSET + Self::MAX_ORDERS * PriceLevel::LEN;
+    pub const VAULT_SIGNER_SEED: &'static [u8] = b"vault_signer";
+    pub const ORDER_GATE_SEED: &'static [u8] = b"order_gate";
+    // Fee rates are in basis points of the quote amount
+    pub const FEE_BPS_DENOMINATOR: u64 = 10_000;
+
+    // Total resting amount at each price level, by side and price
+    pub fn levels(&self, now: &Clock) -> Vec<(OrderType, u64, u64)> {
+        let mut levels: Vec<(OrderType, u64, u64)> = Vec::new();
+        for order in self.buy_orders.iter().chain(self.sell_orders.iter()) {
+            if order.is_expired(now) {
+                continue;
+            }
+            let Some(price) = self.effective_price(order, now) else {
+                continue;
+            };
+            match levels
+                .iter_mut()
+                .find(|(side, level, _)| *side == order.order_type && *level == price)
+            {
+                Some((_, _, amount)) => *amount += order.amount,
+                None => levels.push((order.order_type, price, order.amount)),
+            }
+        }
+        levels
+    }
+
+    // Fixed-price resting orders of a side aggregated by limit price, best
+    // first, as the account's price level index holds them
+    pub fn price_levels(&self, side: OrderType) -> Vec<PriceLevel> {
+        let mut levels: Vec<PriceLevel> = Vec::new();
+        let fixed = self
+            .orders(side)
+            .iter()
+            .filter(|order| order.peg == PegType::None && !order.is_dutch());
+        for order in fixed {
+            let index = levels.partition_point(|level| match side {
+                OrderType::Buy => level.price > order.price,
+                OrderType::Sell => level.price < order.price,
+            });
+            match levels.get_mut(index) {
+                Some(level) if level.price == order.price => {
+                    level.amount = level.amount.saturating_add(order.amount);
+                    level.order_count += 1;
+                }
+                _ => levels.insert(
+                    index,
+                    PriceLevel {
+                        price: order.price,
+                        amount: order.amount,
+                        order_count: 1,
+                    },
+                ),
+            }
+        }
+        levels
+    }
+
+    // Read the current price from the market's oracle account, refusing it
+    // when its confidence interval is wider than `max_oracle_conf_bps` of
+    // the price
+    pub fn read_oracle_price(
+        &self,
+        oracle_info: &AccountInfo,
+    ) -> Result<OraclePrice, ProgramError> {
+        if self.oracle_source == OracleSource::None || oracle_info.key != &self.oracle {
+            msg!("Expected the market oracle {}", self.oracle);
+            return Err(OrderBookError::InvalidOraclePrice.into());
+        }
+        let price = self
+            .oracle_source
+            .read_price(&oracle_info.try_borrow_data()?)?;
+        if self.max_oracle_conf_bps > 0
+            && price.conf as u128 * Self::FEE_BPS_DENOMINATOR as u128
+                > price.price as u128 * self.max_oracle_conf_bps as u128
+        {
+            msg!(
+                "Oracle confidence {} is wider than {} bps of its price {}",
+                price.conf,
+                self.max_oracle_conf_bps,
+                price.price
+            );
+            return Err(OrderBookError::OracleConfidenceTooWide.into());
+        }
+        Ok(price)
+    }
+
+    // Address of the PDA that owns the market's token vaults
+    pub fn find_vault_signer(program_id: &Pubkey, order_book: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::VAULT_SIGNER_SEED, order_book.as_ref()], program_id)
+    }
+
+    // Address of the PDA a market's gate program signs its PlaceOrder CPIs
+    // with
+    pub fn find_order_gate_signer(order_gate: &Pubkey, order_book: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::ORDER_GATE_SEED, order_book.as_ref()], order_gate)
+    }
+
+    // Quote atoms paid for `amount` base atoms at `price`; whole units are
+    // priced per unit
+    pub fn quote_amount(&self, price: u64, amount: u64) -> Result<u64, ProgramError> {
+        if self.whole_units {
+            return price
+                .checked_mul(amount)
+                .ok_or(ProgramError::ArithmeticOverflow);
+        }
+        let quote = price as u128 * amount as u128 / self.lot_size as u128;
+        u64::try_from(quote).map_err(|_| ProgramError::ArithmeticOverflow)
+    }
+
+    // Base atoms, in whole lots, that `quote` quote atoms buy at `price`
+    pub fn base_amount(&self, price: u64, quote: u64) -> u64 {
+        let lot_size = self.lot_size as u128;
+        let amount = quote as u128 * lot_size / price as u128;
+        (amount - amount % lot_size).min(u64::MAX as u128) as u64
+    }
+
+    // Taker fee rate for takers on the given side
+    pub fn taker_fee_bps(&self, taker_side: OrderType) -> u16 {
+        match taker_side {
+            OrderType::Buy => self.buy_taker_fee_bps,
+            OrderType::Sell => self.sell_taker_fee_bps,
+        }
+    }
+
+    // Taker fee charged on a fill worth `quote` quote atoms, rounded as the
+    // market's rounding policy charges
+    pub fn taker_fee(&self, taker_side: OrderType, quote: u64) -> u64 {
+        mul_div(
+            quote,
+            self.taker_fee_bps(taker_side) as u64,
+            Self::FEE_BPS_DENOMINATOR,
+            self.rounding_policy.charge(),
+        )
+    }
+
+    // Taker fee a bid reserves on `quote` quote atoms of its size. Fees
+    // rounded up or to the nearest atom per fill can exceed the fee on the
+    // whole size by an atom for each maker a bid can fill against, so those
+    // policies reserve that much more.
+    pub fn fee_reserve(&self, quote: u64) -> u64 {
+        let rounding_slack = match self.rounding_policy.charge() {
+            Rounding::Down => 0,
+            Rounding::Up | Rounding::NearestEven => Self::MAX_ORDERS as u64,
+        };
+        self.taker_fee(OrderType::Buy, quote)
+            .saturating_add(rounding_slack)
+    }
+
+    // Lamports a keeper earns from the crank incentives for canceling an
+    // expired order, limited to the remaining incentives
+    pub fn crank_reward(&self) -> u64 {
+        self.placement_fee_lamports
+            .min(self.crank_incentive_lamports)
+    }
+
+    // Part of a fill's taker fee paid from the incentives when the maker is a
+    // backstop maker, limited to the remaining incentive balance
+    pub fn fee_subsidy(&self, maker: &Pubkey, taker_fee: u64) -> u64 {
+        if !self.backstop_makers.contains(maker) {
+            return 0;
+        }
+        let discount = mul_div(
+            taker_fee,
+            self.backstop_discount_bps as u64,
+            Self::FEE_BPS_DENOMINATOR,
+            self.rounding_policy.rebate(),
+        );
+        discount.min(self.incentive_balance)
+    }
+
+    // Split collected taker fees between the fee collector and the protocol
+    pub(crate) fn accrue_fees(&mut self, fees: u64) {
+        let protocol_fees = mul_div(
+            fees,
+            self.protocol_fee_bps as u64,
+            Self::FEE_BPS_DENOMINATOR,
+            self.rounding_policy.charge(),
+        );
+        self.protocol_fees_collected += protocol_fees;
+        self.protocol_fees_outstanding += protocol_fees;
+        self.fees_collected += fees - protocol_fees;
+        self.fees_outstanding += fees - protocol_fees;
+        self.fee_epoch.fees += fees;
+    }
+
+    // Number of the fee epoch a Unix timestamp falls in
+    pub fn fee_epoch_at(unix_timestamp: UnixTimestamp) -> u64 {
+        unix_timestamp.max(0) as u64 / Self::FEE_EPOCH_SECONDS
+    }
+
+    // Close the current fee epoch once `unix_timestamp` is past it, carrying
+    // the rewards nobody can claim into the new one
+    pub(crate) fn roll_fee_epoch(&mut self, unix_timestamp: UnixTimestamp) {
+        let epoch = Self::fee_epoch_at(unix_timestamp);
+        if epoch <= self.fee_epoch.epoch {
+            return;
+        }
+        let mut closed = self.fee_epoch;
+        let mut carried = 0;
+        if closed.volume == 0 {
+            carried += closed.rewards;
+            closed.rewards = 0;
+        }
+        if closed.fees != 0 || closed.volume != 0 {
+            self.closed_fee_epochs.push(closed);
+        }
+        if self.closed_fee_epochs.len() > Self::MAX_CLOSED_FEE_EPOCHS {
+            carried += self.closed_fee_epochs.remove(0).unclaimed_rewards();
+        }
+        self.fee_epoch = FeeEpoch {
+            epoch,
+            rewards: carried,
+            ..FeeEpoch::default()
+        };
+    }
+
+    // Count a maker fill's quote volume towards the current fee epoch
+    pub(crate) fn record_maker_volume(&mut self, balances: &mut OpenOrdersMarket, quote: u64) {
+        self.roll_maker_volume(balances);
+        balances.fee_epoch = self.fee_epoch.epoch;
+        balances.maker_volume = balances.maker_volume.saturating_add(quote);
+        self.fee_epoch.volume = self.fee_epoch.volume.saturating_add(quote);
+    }
+
+    // Move a maker's volume of a closed fee epoch to its unclaimed volume,
+    // first claiming the rewards of the unclaimed volume it replaces
+    pub(crate) fn roll_maker_volume(&mut self, balances: &mut OpenOrdersMarket) {
+        if balances.maker_volume == 0 || balances.fee_epoch >= self.fee_epoch.epoch {
+            return;
+        }
+        self.claim_epoch_rewards(balances);
+        balances.unclaimed_epoch = balances.fee_epoch;
+        balances.unclaimed_maker_volume = std::mem::take(&mut balances.maker_volume);
+    }
+
+    // Credit a maker's share of the rewards of its unclaimed fee epoch to its
+    // free quote balance, returning the share. Nothing is paid once the epoch
+    // was dropped from the closed fee epochs.
+    pub(crate) fn claim_epoch_rewards(&mut self, balances: &mut OpenOrdersMarket) -> u64 {
+        let volume = std::mem::take(&mut balances.unclaimed_maker_volume);
+        let Some(epoch) = self
+            .closed_fee_epochs
+            .iter_mut()
+            .find(|epoch| epoch.epoch == balances.unclaimed_epoch)
+        else {
+            return 0;
+        };
+        let share = mul_div(epoch.rewards, volume, epoch.volume, Rounding::Down)
+            .min(epoch.unclaimed_rewards());
+        epoch.rewards_claimed += share;
+        balances.quote_free += share;
+        share
+    }
+
+    // Tokens a resting order locks: quote for a bid, base for an ask
+    pub fn locked_funds(&self, order: &Order) -> Result<u64, ProgramError> {
+        match order.order_type {
+            OrderType::Buy => self.quote_amount(order.price, order.amount),
+            OrderType::Sell => Ok(order.amount),
+        }
+    }
+
+    // Tokens an order holds in its owner's OpenOrders account. A bid waiting
+    // off the book also holds the taker fee reserve it needs once matched.
+    pub fn reserved_funds(&self, order: &Order) -> Result<u64, ProgramError> {
+        let locked = self.locked_funds(order)?;
+        match order.order_type {
+            OrderType::Buy if order.is_waiting() => locked
+                .checked_add(self.fee_reserve(locked))
+                .ok_or(ProgramError::ArithmeticOverflow),
+            _ => Ok(locked),
+        }
+    }
+
+    // Record a fill price as the last trade and ratchet the trigger prices of
+    // the trailing stops: a sell stop's rises with the price and a buy stop's
+    // falls with it
+    pub(crate) fn record_trade(&mut self, price: u64) {
+        self.last_trade_price = price;
+        for stop in &mut self.stop_orders {
+            let trail = stop.trail_ticks.saturating_mul(self.tick_size);
+            stop.trigger_price = match stop.order_type {
+                OrderType::Sell => stop.trigger_price.max(price.saturating_sub(trail)),
+                OrderType::Buy => stop.trigger_price.min(price.saturating_add(trail)),
+            };
+        }
+    }
+
+    // Whether the last trade reached a trailing stop's trigger price
+    pub fn is_triggered(&self, stop: &Order) -> bool {
+        self.last_trade_price > 0
+            && match stop.order_type {
+                OrderType::Sell => self.last_trade_price <= stop.trigger_price,
+                OrderType::Buy => self.last_trade_price >= stop.trigger_price,
+            }
+    }
+
+    // Byte offset of the order at `index` on the given side of the book
+    pub fn order_offset(order_type: OrderType, index: usize) -> usize {
+        let side_offset = match order_type {
+            OrderType::Buy => ORDER_BOOK_BUY_ORDERS_OFFSET,
+            OrderType::Sell => ORDER_BOOK_SELL_ORDERS_OFFSET,
+        };
+        side_offset + index * Order::LEN
+    }
+
+    // Filter for order books trading the given base mint
+    pub fn base_mint_filter(base_mint: &Pubkey) -> MemcmpFilter {
+        MemcmpFilter {
+            offset: ORDER_BOOK_BASE_MINT_OFFSET,
+            bytes: base_mint.to_bytes().to_vec(),
+        }
+    }
+
+    // Filter for order books trading the given quote mint
+    pub fn quote_mint_filter(quote_mint: &Pubkey) -> MemcmpFilter {
+        MemcmpFilter {
+            offset: ORDER_BOOK_QUOTE_MINT_OFFSET,
+            bytes: quote_mint.to_bytes().to_vec(),
+        }
+    }
+
+    // Filter for order books whose order at `index` on the given side was
+    // placed by `trader`
+    pub fn trader_filter(order_type: OrderType, index: usize, trader: &Pubkey) -> MemcmpFilter {
+        MemcmpFilter {
+            offset: Self::order_offset(order_type, index) + ORDER_TRADER_OFFSET,
+            bytes: trader.to_bytes().to_vec(),
+        }
+    }
+
+    // Load the order book from the account data
+    pub fn from_account_info(account_info: &AccountInfo) -> Result<Self, ProgramError> {
+        Self::check_layout(account_info)?;
+        Self::unpack(&account_info.try_borrow_data()?)
+    }
+
+    // Check the account's layout version, then its size
+    pub(crate) fn check_layout(account_info: &AccountInfo) -> ProgramResult {
+        let version = account_info
+            .try_borrow_data()?
+            .get(ORDER_BOOK_IS_INITIALIZED_OFFSET)
+            .copied()
+            .unwrap_or_default();
+        if version > Self::LAYOUT_VERSION {
+            msg!(
+                "Order book has layout version {}, newer than this program's {}",
+                version,
+                Self::LAYOUT_VERSION
+            );
+            return Err(OrderBookError::UnsupportedLayoutVersion.into());
+        }
+        if version != 0 && version < Self::LAYOUT_VERSION && account_info.data_len() != Self::LEN {
+            msg!(
+                "Order book has layout version {} of {} bytes, which this program's version {} cannot read",
+                version,
+                account_info.data_len(),
+                Self::LAYOUT_VERSION
+            );
+            return Err(OrderBookError::OutdatedLayoutVersion.into());
+        }
+        check_account_size(account_info, Self::LEN)
+    }
+
+    // Store the order book into the account data, logging a
+    // LayoutUpgradedEvent when it was last written with an older layout
+    pub fn pack_into_account(&self, account_info: &AccountInfo) -> ProgramResult {
+        let mut data = account_info.try_borrow_mut_data()?;
+        let old_version = data
+            .get(ORDER_BOOK_IS_INITIALIZED_OFFSET)
+            .copied()
+            .unwrap_or_default();
+        if self.is_initialized && old_version != 0 && old_version < Self::LAYOUT_VERSION {
+            let event = LayoutUpgradedEvent {
+                market: *account_info.key,
+                old_version,
+                new_version: Self::LAYOUT_VERSION,
+            };
+            msg!("LayoutUpgraded: {:?}", event);
+        }
+        self.pack_into_slice(&mut data)
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Order book account is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let layout_version = data[ORDER_BOOK_IS_INITIALIZED_OFFSET];
+        if layout_version > Self::LAYOUT_VERSION {
+            msg!(
+                "Order book has layout version {}, newer than this program's {}",
+                layout_version,
+                Self::LAYOUT_VERSION
+            );
+            return Err(OrderBookError::UnsupportedLayoutVersion.into());
+        }
+        let buy_count =
+            u32::from_le_bytes(*array_ref![data, ORDER_BOOK_BUY_COUNT_OFFSET, 4]) as usize;
+        let sell_count =
+            u32::from_le_bytes(*array_ref![data, ORDER_BOOK_SELL_COUNT_OFFSET, 4]) as usize;
+        if buy_count > Self::MAX_ORDERS || sell_count > Self::MAX_ORDERS {
+            msg!("Order book account holds an invalid order count");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let backstop_count = data[ORDER_BOOK_BACKSTOP_MAKER_COUNT_OFFSET] as usize;
+        if backstop_count > Self::MAX_BACKSTOP_MAKERS {
+            msg!("Order book account holds an invalid backstop maker count");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let candle_series_count = data[ORDER_BOOK_CANDLE_SERIES_COUNT_OFFSET] as usize;
+        if candle_series_count > Self::MAX_CANDLE_SERIES {
+            msg!("Order book account holds an invalid candle series count");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let stop_count =
+            u32::from_le_bytes(*array_ref![data, ORDER_BOOK_STOP_COUNT_OFFSET, 4]) as usize;
+        let pending_count =
+            u32::from_le_bytes(*array_ref![data, ORDER_BOOK_PENDING_COUNT_OFFSET, 4]) as usize;
+        let reference_count =
+            u32::from_le_bytes(*array_ref![data, ORDER_BOOK_REFERENCE_COUNT_OFFSET, 4]) as usize;
+        if stop_count > Self::MAX_STOP_ORDERS
+            || pending_count > Self::MAX_PENDING_ORDERS
+            || reference_count > Self::MAX_REFERENCE_ORDERS
+        {
+            msg!("Order book account holds an invalid waiting order count");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let closed_count =
+            u32::from_le_bytes(*array_ref![data, ORDER_BOOK_CLOSED_COUNT_OFFSET, 4]) as usize;
+        if closed_count > Self::MAX_CLOSED_ORDERS {
+            msg!("Order book account holds an invalid closed order count");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let expiry_count =
+            u32::from_le_bytes(*array_ref![data, ORDER_BOOK_EXPIRY_COUNT_OFFSET, 4]) as usize;
+        if expiry_count > 2 * Self::MAX_ORDERS {
+            msg!("Order book account holds an invalid expiry count");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let closed_fee_epoch_count = data[ORDER_BOOK_CLOSED_FEE_EPOCH_COUNT_OFFSET] as usize;
+        if closed_fee_epoch_count > Self::MAX_CLOSED_FEE_EPOCHS {
+            msg!("Order book account holds an invalid closed fee epoch count");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let waiting = |offset: usize, count: usize| {
+            (0..count)
+                .map(|i| Order::unpack(&data[offset + i * Order::LEN..]))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let side = |order_type: OrderType, count: usize| {
+            (0..count)
+                .map(|i| Order::unpack(&data[Self::order_offset(order_type, i)..]))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let pubkey = |offset: usize| Pubkey::new_from_array(*array_ref![data, offset, 32]);
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        let lifetimes = |offset: usize| {
+            std::array::from_fn(|i| u32::from_le_bytes(*array_ref![data, offset + i * 4, 4]))
+        };
+        Ok(OrderBook {
+            is_initialized: data[ORDER_BOOK_IS_INITIALIZED_OFFSET] != 0,
+            base_mint: pubkey(ORDER_BOOK_BASE_MINT_OFFSET),
+            quote_mint: pubkey(ORDER_BOOK_QUOTE_MINT_OFFSET),
+            creator: pubkey(ORDER_BOOK_CREATOR_OFFSET),
+            tick_size: u64_at(ORDER_BOOK_TICK_SIZE_OFFSET),
+            lot_size: u64_at(ORDER_BOOK_LOT_SIZE_OFFSET),
+            next_order_id: u64_at(ORDER_BOOK_NEXT_ORDER_ID_OFFSET),
+            base_vault: pubkey(ORDER_BOOK_BASE_VAULT_OFFSET),
+            quote_vault: pubkey(ORDER_BOOK_QUOTE_VAULT_OFFSET),
+            vault_signer_bump: data[ORDER_BOOK_VAULT_SIGNER_BUMP_OFFSET],
+            fee_collector: pubkey(ORDER_BOOK_FEE_COLLECTOR_OFFSET),
+            buy_taker_fee_bps: u16::from_le_bytes(*array_ref![
+                data,
+                ORDER_BOOK_BUY_TAKER_FEE_BPS_OFFSET,
+                2
+            ]),
+            sell_taker_fee_bps: u16::from_le_bytes(*array_ref![
+                data,
+                ORDER_BOOK_SELL_TAKER_FEE_BPS_OFFSET,
+                2
+            ]),
+            fees_collected: u64_at(ORDER_BOOK_FEES_COLLECTED_OFFSET),
+            fees_swept: u64_at(ORDER_BOOK_FEES_SWEPT_OFFSET),
+            fees_outstanding: u64_at(ORDER_BOOK_FEES_OUTSTANDING_OFFSET),
+            protocol_fee_bps: u16::from_le_bytes(*array_ref![
+                data,
+                ORDER_BOOK_PROTOCOL_FEE_BPS_OFFSET,
+                2
+            ]),
+            protocol_fees_collected: u64_at(ORDER_BOOK_PROTOCOL_FEES_COLLECTED_OFFSET),
+            protocol_fees_swept: u64_at(ORDER_BOOK_PROTOCOL_FEES_SWEPT_OFFSET),
+            protocol_fees_outstanding: u64_at(ORDER_BOOK_PROTOCOL_FEES_OUTSTANDING_OFFSET),
+            backstop_discount_bps: u16::from_le_bytes(*array_ref![
+                data,
+                ORDER_BOOK_BACKSTOP_DISCOUNT_BPS_OFFSET,
+                2
+            ]),
+            incentive_balance: u64_at(ORDER_BOOK_INCENTIVE_BALANCE_OFFSET),
+            backstop_makers: (0..backstop_count)
+                .map(|i| pubkey(ORDER_BOOK_BACKSTOP_MAKERS_OFFSET + i * 32))
+                .collect(),
+            last_trade_price: u64_at(ORDER_BOOK_LAST_TRADE_PRICE_OFFSET),
+            dark_pool: data[ORDER_BOOK_DARK_POOL_OFFSET] != 0,
+            oracle_source: OracleSource::from_u8(data[ORDER_BOOK_ORACLE_SOURCE_OFFSET])?,
+            oracle: pubkey(ORDER_BOOK_ORACLE_OFFSET),
+            seq_num: u64_at(ORDER_BOOK_SEQ_NUM_OFFSET),
+            trade_history: pubkey(ORDER_BOOK_TRADE_HISTORY_OFFSET),
+            candle_series: (0..candle_series_count)
+                .map(|i| pubkey(ORDER_BOOK_CANDLE_SERIES_OFFSET + i * 32))
+                .collect(),
+            buy_open_interest: u64_at(ORDER_BOOK_BUY_OPEN_INTEREST_OFFSET),
+            sell_open_interest: u64_at(ORDER_BOOK_SELL_OPEN_INTEREST_OFFSET),
+            stats_epoch: u64_at(ORDER_BOOK_STATS_EPOCH_OFFSET),
+            unique_traders: u32::from_le_bytes(*array_ref![
+                data,
+                ORDER_BOOK_UNIQUE_TRADERS_OFFSET,
+                4
+            ]),
+            disabled_features: data[ORDER_BOOK_DISABLED_FEATURES_OFFSET],
+            whole_units: data[ORDER_BOOK_WHOLE_UNITS_OFFSET] != 0,
+            placement_fee_lamports: u64_at(ORDER_BOOK_PLACEMENT_FEE_LAMPORTS_OFFSET),
+            crank_incentive_lamports: u64_at(ORDER_BOOK_CRANK_INCENTIVE_LAMPORTS_OFFSET),
+            rounding_policy: RoundingPolicy::from_u8(data[ORDER_BOOK_ROUNDING_POLICY_OFFSET])?,
+            successor: pubkey(ORDER_BOOK_SUCCESSOR_OFFSET),
+            fee_epoch: FeeEpoch::unpack(&data[ORDER_BOOK_FEE_EPOCH_OFFSET..])?,
+            order_gate: pubkey(ORDER_BOOK_ORDER_GATE_OFFSET),
+            priority_auction: data[ORDER_BOOK_PRIORITY_AUCTION_OFFSET] != 0,
+            blocklist: pubkey(ORDER_BOOK_BLOCKLIST_OFFSET),
+            filled_lifetimes: lifetimes(ORDER_BOOK_FILLED_LIFETIMES_OFFSET),
+            canceled_lifetimes: lifetimes(ORDER_BOOK_CANCELED_LIFETIMES_OFFSET),
+            book_checksum: u64_at(ORDER_BOOK_BOOK_CHECKSUM_OFFSET),
+            prune_after_slots: u64_at(ORDER_BOOK_PRUNE_AFTER_SLOTS_OFFSET),
+            max_orders_per_slot: u16::from_le_bytes(*array_ref![
+                data,
+                ORDER_BOOK_MAX_ORDERS_PER_SLOT_OFFSET,
+                2
+            ]),
+            max_oracle_conf_bps: u16::from_le_bytes(*array_ref![
+                data,
+                ORDER_BOOK_MAX_ORACLE_CONF_BPS_OFFSET,
+                2
+            ]),
+            closed_fee_epochs: (0..closed_fee_epoch_count)
+                .map(|i| {
+                    FeeEpoch::unpack(
+                        &data[ORDER_BOOK_CLOSED_FEE_EPOCHS_OFFSET + i * FeeEpoch::LEN..],
+                    )
+                })
+                .collect::<Result<_, _>>()?,
+            buy_orders: side(OrderType::Buy, buy_count)?,
+            sell_orders: side(OrderType::Sell, sell_count)?,
+            stop_orders: waiting(ORDER_BOOK_STOP_ORDERS_OFFSET, stop_count)?,
+            pending_orders: waiting(ORDER_BOOK_PENDING_ORDERS_OFFSET, pending_count)?,
+            reference_orders: waiting(ORDER_BOOK_REFERENCE_ORDERS_OFFSET, reference_count)?,
+            closed_orders: (0..closed_count)
+                .map(|i| {
+                    ClosedOrder::unpack(
+                        &data[ORDER_BOOK_CLOSED_ORDERS_OFFSET + i * ClosedOrder::LEN..],
+                    )
+                })
+                .collect::<Result<_, _>>()?,
+            expiration_wheel: (0..expiry_count)
+                .map(|i| {
+                    ExpiryEntry::unpack(
+                        &data[ORDER_BOOK_EXPIRATION_WHEEL_OFFSET + i * ExpiryEntry::LEN..],
+                    )
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn pack_into_slice(&self, dst: &mut [u8]) -> ProgramResult {
+        if dst.len() < Self::LEN {
+            msg!("Order book account is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let counts = [
+            (ORDER_BOOK_BUY_COUNT_OFFSET, self.buy_orders.len()),
+            (ORDER_BOOK_SELL_COUNT_OFFSET, self.sell_orders.len()),
+            (ORDER_BOOK_STOP_COUNT_OFFSET, self.stop_orders.len()),
+            (ORDER_BOOK_PENDING_COUNT_OFFSET, self.pending_orders.len()),
+            (
+                ORDER_BOOK_REFERENCE_COUNT_OFFSET,
+                self.reference_orders.len(),
+            ),
+            (ORDER_BOOK_CLOSED_COUNT_OFFSET, self.closed_orders.len()),
+            (ORDER_BOOK_EXPIRY_COUNT_OFFSET, self.expiration_wheel.len()),
+        ];
+        for (offset, count) in counts {
+            dst[offset..offset + 4].copy_from_slice(&(count as u32).to_le_bytes());
+        }
+        dst[ORDER_BOOK_IS_INITIALIZED_OFFSET] = match self.is_initialized {
+            true => Self::LAYOUT_VERSION,
+            false => 0,
+        };
+        dst[ORDER_BOOK_VAULT_SIGNER_BUMP_OFFSET] = self.vault_signer_bump;
+        dst[ORDER_BOOK_DARK_POOL_OFFSET] = self.dark_pool as u8;
+        dst[ORDER_BOOK_ORACLE_SOURCE_OFFSET] = self.oracle_source as u8;
+        dst[ORDER_BOOK_BACKSTOP_MAKER_COUNT_OFFSET] = self.backstop_makers.len() as u8;
+        for (i, maker) in self.backstop_makers.iter().enumerate() {
+            let offset = ORDER_BOOK_BACKSTOP_MAKERS_OFFSET + i * 32;
+            dst[offset..offset + 32].copy_from_slice(maker.as_ref());
+        }
+        dst[ORDER_BOOK_CANDLE_SERIES_COUNT_OFFSET] = self.candle_series.len() as u8;
+        dst[ORDER_BOOK_DISABLED_FEATURES_OFFSET] = self.disabled_features;
+        dst[ORDER_BOOK_WHOLE_UNITS_OFFSET] = self.whole_units as u8;
+        dst[ORDER_BOOK_ROUNDING_POLICY_OFFSET] = self.rounding_policy as u8;
+        dst[ORDER_BOOK_PRIORITY_AUCTION_OFFSET] = self.priority_auction as u8;
+        dst[ORDER_BOOK_CLOSED_FEE_EPOCH_COUNT_OFFSET] = self.closed_fee_epochs.len() as u8;
+        let fee_epochs = std::iter::once((ORDER_BOOK_FEE_EPOCH_OFFSET, &self.fee_epoch)).chain(
+            self.closed_fee_epochs.iter().enumerate().map(|(i, epoch)| {
+                (
+                    ORDER_BOOK_CLOSED_FEE_EPOCHS_OFFSET + i * FeeEpoch::LEN,
+                    epoch,
+                )
+            }),
+        );
+        for (offset, epoch) in fee_epochs {
+            dst[offset..offset + FeeEpoch::LEN].copy_from_slice(&epoch.pack());
+        }
+        for (i, series) in self.candle_series.iter().enumerate() {
+            let offset = ORDER_BOOK_CANDLE_SERIES_OFFSET + i * 32;
+            dst[offset..offset + 32].copy_from_slice(series.as_ref());
+        }
+        let lifetimes = [
+            (ORDER_BOOK_FILLED_LIFETIMES_OFFSET, &self.filled_lifetimes),
+            (
+                ORDER_BOOK_CANCELED_LIFETIMES_OFFSET,
+                &self.canceled_lifetimes,
+            ),
+        ];
+        for (offset, counts) in lifetimes {
+            for (i, count) in counts.iter().enumerate() {
+                dst[offset + i * 4..offset + i * 4 + 4].copy_from_slice(&count.to_le_bytes());
+            }
+        }
+        let fields: [(usize, &[u8]); 37] = [
+            (ORDER_BOOK_BASE_MINT_OFFSET, self.base_mint.as_ref()),
+            (
+                ORDER_BOOK_BUY_OPEN_INTEREST_OFFSET,
+                &self.buy_open_interest.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_SELL_OPEN_INTEREST_OFFSET,
+                &self.sell_open_interest.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_STATS_EPOCH_OFFSET,
+                &self.stats_epoch.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_UNIQUE_TRADERS_OFFSET,
+                &self.unique_traders.to_le_bytes(),
+            ),
+            (ORDER_BOOK_ORACLE_OFFSET, self.oracle.as_ref()),
+            (ORDER_BOOK_SEQ_NUM_OFFSET, &self.seq_num.to_le_bytes()),
+            (
+                ORDER_BOOK_BOOK_CHECKSUM_OFFSET,
+                &self.book_checksum.to_le_bytes(),
+            ),
+            (ORDER_BOOK_TRADE_HISTORY_OFFSET, self.trade_history.as_ref()),
+            (ORDER_BOOK_QUOTE_MINT_OFFSET, self.quote_mint.as_ref()),
+            (ORDER_BOOK_CREATOR_OFFSET, self.creator.as_ref()),
+            (ORDER_BOOK_TICK_SIZE_OFFSET, &self.tick_size.to_le_bytes()),
+            (ORDER_BOOK_LOT_SIZE_OFFSET, &self.lot_size.to_le_bytes()),
+            (
+                ORDER_BOOK_NEXT_ORDER_ID_OFFSET,
+                &self.next_order_id.to_le_bytes(),
+            ),
+            (ORDER_BOOK_BASE_VAULT_OFFSET, self.base_vault.as_ref()),
+            (ORDER_BOOK_QUOTE_VAULT_OFFSET, self.quote_vault.as_ref()),
+            (ORDER_BOOK_FEE_COLLECTOR_OFFSET, self.fee_collector.as_ref()),
+            (ORDER_BOOK_SUCCESSOR_OFFSET, self.successor.as_ref()),
+            (ORDER_BOOK_ORDER_GATE_OFFSET, self.order_gate.as_ref()),
+            (ORDER_BOOK_BLOCKLIST_OFFSET, self.blocklist.as_ref()),
+            (
+                ORDER_BOOK_BUY_TAKER_FEE_BPS_OFFSET,
+                &self.buy_taker_fee_bps.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_SELL_TAKER_FEE_BPS_OFFSET,
+                &self.sell_taker_fee_bps.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_FEES_COLLECTED_OFFSET,
+                &self.fees_collected.to_le_bytes(),
+            ),
+            (ORDER_BOOK_FEES_SWEPT_OFFSET, &self.fees_swept.to_le_bytes()),
+            (
+                ORDER_BOOK_FEES_OUTSTANDING_OFFSET,
+                &self.fees_outstanding.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_PROTOCOL_FEE_BPS_OFFSET,
+                &self.protocol_fee_bps.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_PROTOCOL_FEES_COLLECTED_OFFSET,
+                &self.protocol_fees_collected.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_PROTOCOL_FEES_SWEPT_OFFSET,
+                &self.protocol_fees_swept.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_PROTOCOL_FEES_OUTSTANDING_OFFSET,
+                &self.protocol_fees_outstanding.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_BACKSTOP_DISCOUNT_BPS_OFFSET,
+                &self.backstop_discount_bps.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_INCENTIVE_BALANCE_OFFSET,
+                &self.incentive_balance.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_LAST_TRADE_PRICE_OFFSET,
+                &self.last_trade_price.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_PLACEMENT_FEE_LAMPORTS_OFFSET,
+                &self.placement_fee_lamports.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_PRUNE_AFTER_SLOTS_OFFSET,
+                &self.prune_after_slots.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_MAX_ORDERS_PER_SLOT_OFFSET,
+                &self.max_orders_per_slot.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_MAX_ORACLE_CONF_BPS_OFFSET,
+                &self.max_oracle_conf_bps.to_le_bytes(),
+            ),
+            (
+                ORDER_BOOK_CRANK_INCENTIVE_LAMPORTS_OFFSET,
+                &self.crank_incentive_lamports.to_le_bytes(),
+            ),
+        ];
+        for (offset, bytes) in fields {
+            dst[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        let sides = [
+            (OrderType::Buy, &self.buy_orders),
+            (OrderType::Sell, &self.sell_orders),
+        ];
+        for (order_type, orders) in sides {
+            for (i, order) in orders.iter().enumerate() {
+                let start = Self::order_offset(order_type, i);
+                dst[start..start + Order::LEN].copy_from_slice(&order.pack());
+            }
+        }
+        let waiting = [
+            (ORDER_BOOK_STOP_ORDERS_OFFSET, &self.stop_orders),
+            (ORDER_BOOK_PENDING_ORDERS_OFFSET, &self.pending_orders),
+            (ORDER_BOOK_REFERENCE_ORDERS_OFFSET, &self.reference_orders),
+        ];
+        for (offset, orders) in waiting {
+            for (i, order) in orders.iter().enumerate() {
+                let start = offset + i * Order::LEN;
+                dst[start..start + Order::LEN].copy_from_slice(&order.pack());
+            }
+        }
+        for (i, entry) in self.closed_orders.iter().enumerate() {
+            let start = ORDER_BOOK_CLOSED_ORDERS_OFFSET + i * ClosedOrder::LEN;
+            dst[start..start + ClosedOrder::LEN].copy_from_slice(&entry.pack());
+        }
+        for (i, entry) in self.expiration_wheel.iter().enumerate() {
+            let start = ORDER_BOOK_EXPIRATION_WHEEL_OFFSET + i * ExpiryEntry::LEN;
+            dst[start..start + ExpiryEntry::LEN].copy_from_slice(&entry.pack());
+        }
+        let level_index = [
+            (
+                OrderType::Buy,
+                ORDER_BOOK_BUY_LEVEL_COUNT_OFFSET,
+                ORDER_BOOK_BUY_LEVELS_OFFSET,
+            ),
+            (
+                OrderType::Sell,
+                ORDER_BOOK_SELL_LEVEL_COUNT_OFFSET,
+                ORDER_BOOK_SELL_LEVELS_OFFSET,
+            ),
+        ];
+        for (side, count_offset, levels_offset) in level_index {
+            let levels = self.price_levels(side);
+            dst[count_offset..count_offset + 4]
+                .copy_from_slice(&(levels.len() as u32).to_le_bytes());
+            for (i, level) in levels.iter().enumerate() {
+                let start = levels_offset + i * PriceLevel::LEN;
+                dst[start..start + PriceLevel::LEN].copy_from_slice(&level.pack());
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn orders(&self, order_type: OrderType) -> &Vec<Order> {
+        match order_type {
+            OrderType::Buy => &self.buy_orders,
+            OrderType::Sell => &self.sell_orders,
+        }
+    }
+
+    pub(crate) fn orders_mut(&mut self, order_type: OrderType) -> &mut Vec<Order> {
+        match order_type {
+            OrderType::Buy => &mut self.buy_orders,
+            OrderType::Sell => &mut self.sell_orders,
+        }
+    }
+
+    // Check the market still takes new orders, which a deprecated market
+    // naming its successor does not
+    pub(crate) fn check_open(&self) -> ProgramResult {
+        if self.successor != Pubkey::default() {
+            msg!("Market is deprecated in favor of {}", self.successor);
+            return Err(OrderBookError::MarketDeprecated.into());
+        }
+        Ok(())
+    }
+
+    // Check the market allows the capabilities an incoming order uses
+    pub(crate) fn check_features(&self, order: &Order) -> ProgramResult {
+        let uses = [
+            (Self::FEATURE_STOPS, order.is_stop(), "Trailing stops"),
+            (
+                Self::FEATURE_PEGS,
+                order.peg != PegType::None,
+                "Pegged orders",
+            ),
+            (
+                Self::FEATURE_AUCTIONS,
+                order.improvement_slots > 0,
+                "Price improvement windows",
+            ),
+            (
+                Self::FEATURE_LINKED_ORDERS,
+                order.linked_order_id.is_some(),
+                "Linked orders",
+            ),
+            (
+                Self::FEATURE_DUTCH_AUCTIONS,
+                order.is_dutch(),
+                "Dutch auction listings",
+            ),
+            (
+                Self::FEATURE_REFERENCE_ORDERS,
+                order.is_reference(),
+                "Orders trading at settlement",
+            ),
+        ];
+        for (feature, used, name) in uses {
+            if used && self.disabled_features & feature != 0 {
+                msg!("{} are disabled on this market", name);
+                return Err(OrderBookError::FeatureDisabled.into());
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn open_interest_mut(&mut self, order_type: OrderType) -> &mut u64 {
+        match order_type {
+            OrderType::Buy => &mut self.buy_open_interest,
+            OrderType::Sell => &mut self.sell_open_interest,
+        }
+    }
+
+    // Add an order to the order book
+    pub(crate) fn add_order(&mut self, order: Order) -> ProgramResult {
+        let orders = self.orders_mut(order.order_type);
+        if orders.len() >= Self::MAX_ORDERS {
+            msg!("Order book side is full");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        orders.push(order);
+        let open_interest = self.open_interest_mut(order.order_type);
+        *open_interest = open_interest.saturating_add(order.amount);
+        if let Some(entry) = ExpiryEntry::new(&order) {
+            let index = self
+                .expiration_wheel
+                .partition_point(|other| other.key() < entry.key());
+            self.expiration_wheel.insert(index, entry);
+        }
+        Ok(())
+    }
+
+    // Count an OpenOrders market record placing an order in `epoch` towards
+    // the epoch's unique traders, restarting the count in a new epoch
+    pub(crate) fn record_trader(&mut self, balances: &mut OpenOrdersMarket, epoch: Epoch) {
+        if self.stats_epoch != epoch {
+            self.stats_epoch = epoch;
+            self.unique_traders = 0;
+        }
+        if balances.active_epoch_end != epoch + 1 {
+            balances.active_epoch_end = epoch + 1;
+            self.unique_traders += 1;
+        }
+    }
+
+    // Count an order an OpenOrders market record places in `slot`, refusing
+    // it once the record has placed `max_orders_per_slot` in the slot
+    pub(crate) fn record_placement(
+        &self,
+        balances: &mut OpenOrdersMarket,
+        slot: Slot,
+    ) -> ProgramResult {
+        if self.max_orders_per_slot == 0 {
+            return Ok(());
+        }
+        if balances.last_order_slot != slot {
+            balances.last_order_slot = slot;
+            balances.slot_order_count = 0;
+        }
+        if balances.slot_order_count >= self.max_orders_per_slot {
+            msg!(
+                "OpenOrders account already placed {} orders in slot {}",
+                balances.slot_order_count,
+                slot
+            );
+            return Err(OrderBookError::OrderRateLimited.into());
+        }
+        balances.slot_order_count += 1;
+        Ok(())
+    }
+
+    // Add a trailing stop, trailing its trigger price from the last trade
+    pub(crate) fn add_stop_order(&mut self, mut order: Order) -> ProgramResult {
+        if self.last_trade_price == 0 {
+            msg!("Trailing stops need a last trade price to trail");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if self.stop_orders.len() >= Self::MAX_STOP_ORDERS {
+            msg!("Stop orders are full");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let trail = order
+            .trail_ticks
+            .checked_mul(self.tick_size)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        order.trigger_price = match order.order_type {
+            OrderType::Sell => self.last_trade_price.saturating_sub(trail),
+            OrderType::Buy => self.last_trade_price.saturating_add(trail),
+        };
+        self.stop_orders.push(order);
+        Ok(())
+    }
+
+    // Hold a marketable order for its price improvement window
+    pub(crate) fn add_pending_order(&mut self, order: Order) -> ProgramResult {
+        if self.pending_orders.len() >= Self::MAX_PENDING_ORDERS {
+            msg!("Pending orders are full");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        self.pending_orders.push(order);
+        Ok(())
+    }
+
+    // Hold an order trading at settlement until its reference price is fixed
+    pub(crate) fn add_reference_order(&mut self, order: Order) -> ProgramResult {
+        if self.reference_orders.len() >= Self::MAX_REFERENCE_ORDERS {
+            msg!("Orders trading at settlement are full");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        self.reference_orders.push(order);
+        Ok(())
+    }
+
+    // The waiting taker a market in priority auction mode clears next: of
+    // the unexpired pending orders whose window has ended, the earliest
+    // slot's highest bid, ties going to the lower order id
+    pub fn next_priority_taker(&self, now: &Clock) -> Option<&Order> {
+        self.pending_orders
+            .iter()
+            .filter(|order| {
+                !order.is_expired(now)
+                    && order.slot.saturating_add(order.improvement_slots) <= now.slot
+            })
+            .min_by_key(|order| {
+                (
+                    order.slot,
+                    std::cmp::Reverse(order.priority_bid),
+                    order.order_id,
+                )
+            })
+    }
+
+    // Find a resting or waiting order by id
+    pub fn find_order(&self, order_id: u64) -> Option<&Order> {
+        self.buy_orders
+            .iter()
+            .chain(self.sell_orders.iter())
+            .chain(self.stop_orders.iter())
+            .chain(self.pending_orders.iter())
+            .chain(self.reference_orders.iter())
+            .find(|order| order.order_id == order_id)
+    }
+
+    pub(crate) fn find_order_mut(&mut self, order_id: u64) -> Option<&mut Order> {
+        self.buy_orders
+            .iter_mut()
+            .chain(self.sell_orders.iter_mut())
+            .chain(self.stop_orders.iter_mut())
+            .chain(self.pending_orders.iter_mut())
+            .chain(self.reference_orders.iter_mut())
+            .find(|order| order.order_id == order_id)
+    }
+
+    // Ids of `trader`'s resting orders on `side` whose limit price is at or
+    // through `limit_price`, bids at or above it and asks at or below it,
+    // most aggressive first
+    pub fn orders_through(&self, trader: &Pubkey, side: OrderType, limit_price: u64) -> Vec<u64> {
+        let mut orders: Vec<&Order> = self
+            .orders(side)
+            .iter()
+            .filter(|order| &order.trader == trader)
+            .filter(|order| match side {
+                OrderType::Buy => order.price >= limit_price,
+                OrderType::Sell => order.price <= limit_price,
+            })
+            .collect();
+        orders.sort_by(|a, b| {
+            match side {
+                OrderType::Buy => b.price.cmp(&a.price),
+                OrderType::Sell => a.price.cmp(&b.price),
+            }
+            .then(a.order_id.cmp(&b.order_id))
+        });
+        orders.iter().map(|order| order.order_id).collect()
+    }
+
+    // Remove a resting or waiting order by id, unlinking the order linked to
+    // it
+    pub(crate) fn remove_order(&mut self, order_id: u64) -> Option<Order> {
+        let (list, order) = [
+            &mut self.buy_orders,
+            &mut self.sell_orders,
+            &mut self.stop_orders,
+            &mut self.pending_orders,
+            &mut self.reference_orders,
+        ]
+        .into_iter()
+        .enumerate()
+        .find_map(|(list, orders)| {
+            let index = orders.iter().position(|order| order.order_id == order_id)?;
+            Some((list, orders.remove(index)))
+        })?;
+        // Only the first two lists rest on the book
+        if list < 2 {
+            let open_interest = self.open_interest_mut(order.order_type);
+            *open_interest = open_interest.saturating_sub(order.amount);
+        }
+        if let Some(linked) = order
+            .linked_order_id
+            .and_then(|linked_id| self.find_order_mut(linked_id))
+        {
+            linked.linked_order_id = None;
+        }
+        self.record_closed(&order, OrderStatus::Canceled);
+        Some(order)
+    }
+
+    // Remember the final status of an order leaving the book, forgetting the
+    // oldest once `MAX_CLOSED_ORDERS` are remembered
+    pub(crate) fn record_closed(&mut self, order: &Order, status: OrderStatus) {
+        if let Some(entry) = ExpiryEntry::new(order) {
+            if let Ok(index) = self
+                .expiration_wheel
+                .binary_search_by_key(&entry.key(), ExpiryEntry::key)
+            {
+                self.expiration_wheel.remove(index);
+            }
+        }
+        if self.closed_orders.len() == Self::MAX_CLOSED_ORDERS {
+            self.closed_orders.remove(0);
+        }
+        self.closed_orders.push(ClosedOrder::new(order, status));
+    }
+
+    // Count the orders of `before` that have since left the book towards the
+    // lifetime histograms, as filled or canceled by their closed status
+    pub(crate) fn record_lifetimes(
+        &mut self,
+        before: &OrderBook,
+        removed: &[u64],
+        now: UnixTimestamp,
+    ) {
+        for order_id in removed {
+            let Some(order) = before.find_order(*order_id) else {
+                continue;
+            };
+            let lifetime = now.saturating_sub(order.unix_timestamp);
+            let bucket = Self::LIFETIME_BUCKET_BOUNDS.partition_point(|bound| *bound <= lifetime);
+            let counts = match self
+                .find_closed_order(*order_id)
+                .map(|closed| closed.status)
+            {
+                Some(OrderStatus::Filled) => &mut self.filled_lifetimes,
+                _ => &mut self.canceled_lifetimes,
+            };
+            counts[bucket] = counts[bucket].saturating_add(1);
+        }
+    }
+
+    // Ids of up to `limit` resting orders expired at `now`, read from the
+    // front of each unit's expiry buckets in turn, soonest expiring first
+    pub fn expired_order_ids(&self, now: &Clock, limit: usize) -> Vec<u64> {
+        self.expired_entries(now)
+            .take(limit)
+            .map(|entry| entry.order_id)
+            .collect()
+    }
+
+    // The expiry entries of the resting orders expired at `now`, in the
+    // order `expired_order_ids` reads them
+    pub fn expired_entries<'a>(&'a self, now: &'a Clock) -> impl Iterator<Item = &'a ExpiryEntry> {
+        [ExpiryUnit::UnixTime, ExpiryUnit::Slot]
+            .into_iter()
+            .flat_map(move |unit| {
+                let start = self
+                    .expiration_wheel
+                    .partition_point(|entry| (entry.expiry_unit as u8) < unit as u8);
+                self.expiration_wheel[start..]
+                    .iter()
+                    .take_while(move |entry| entry.expiry_unit == unit && entry.is_expired(now))
+            })
+    }
+
+    // The remembered order `order_id` closed as, the latest if its id was
+    // recorded twice
+    pub fn find_closed_order(&self, order_id: u64) -> Option<&ClosedOrder> {
+        self.closed_orders
+            .iter()
+            .rev()
+            .find(|closed| closed.order_id == order_id)
+    }
+
+    // Take back the latest closed entry of an order that carries on trading
+    pub(crate) fn reopen_closed(&mut self, order_id: u64) -> Option<ClosedOrder> {
+        let index = self
+            .closed_orders
+            .iter()
+            .rposition(|closed| closed.order_id == order_id)?;
+        Some(self.closed_orders.remove(index))
+    }
+
+    // Add the taker fees an order paid to it, resting or closed
+    pub(crate) fn add_fees_paid(&mut self, order_id: u64, fees: u64) {
+        if let Some(order) = self.find_order_mut(order_id) {
+            order.fees_paid += fees;
+        } else if let Some(closed) = self
+            .closed_orders
+            .iter_mut()
+            .rev()
+            .find(|closed| closed.order_id == order_id)
+        {
+            closed.fees_paid += fees;
+        }
+    }
+
+    // Status of an order id, from the book while the order rests or waits
+    // and from the remembered closed orders after
+    pub fn order_status(&self, order_id: u64) -> OrderStatusEntry {
+        if let Some(order) = self.find_order(order_id) {
+            return OrderStatusEntry {
+                order_id,
+                status: match order.filled_amount {
+                    0 => OrderStatus::Open,
+                    _ => OrderStatus::PartiallyFilled,
+                },
+                filled_amount: order.filled_amount,
+            };
+        }
+        let (status, filled_amount) = self
+            .find_closed_order(order_id)
+            .map_or((OrderStatus::Unknown, 0), |closed| {
+                (closed.status, closed.filled_amount)
+            });
+        OrderStatusEntry {
+            order_id,
+            status,
+            filled_amount,
+        }
+    }
+
+    // Remove the order linked one-cancels-other to an order that filled
+    pub(crate) fn cancel_linked_order(&mut self, filled: &Order) -> Option<Order> {
+        self.remove_order(filled.linked_order_id?)
+    }
+}
@@ -0,0 +1,692 @@
+// Return data of the query instructions
+
+use crate::{
+    instruction::OrderBookInstruction,
+    state::{
+        Candle, FillEvent, OpenOrders, Order, OrderBook, OrderStatusEntry, OrderType, PriceLevel,
+        PriceOracle,
+    },
+};
+use arrayref::array_ref;
+use solana_program::{
+    clock::{Clock, Epoch, UnixTimestamp},
+    msg,
+    program::MAX_RETURN_DATA,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+// Define one page of the GetCandles query response
+//
+// `next_start_time` is the start time to pass to the next GetCandles call
+// when the range held more candles than fit in the return data, or `None`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CandlePage {
+    pub next_start_time: Option<UnixTimestamp>,
+    pub candles: Vec<Candle>,
+}
+
+impl CandlePage {
+    // Number of candles that fit in the return data
+    pub const MAX_CANDLES: usize = (MAX_RETURN_DATA - 8) / Candle::LEN;
+
+    // Pack the page into a byte array, with `i64::MIN` marking the last page
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(8 + self.candles.len() * Candle::LEN);
+        buf.extend_from_slice(&self.next_start_time.unwrap_or(i64::MIN).to_le_bytes());
+        for candle in &self.candles {
+            buf.extend_from_slice(&candle.pack());
+        }
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < 8 || !(data.len() - 8).is_multiple_of(Candle::LEN) {
+            msg!("Invalid GetCandles page length");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let next_start_time = match i64::from_le_bytes(*array_ref![data, 0, 8]) {
+            i64::MIN => None,
+            start_time => Some(start_time),
+        };
+        let candles = data[8..]
+            .chunks(Candle::LEN)
+            .map(Candle::unpack)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(CandlePage {
+            next_start_time,
+            candles,
+        })
+    }
+}
+
+// Define a response item of a chunked query
+pub trait PageItem: Sized {
+    const LEN: usize;
+
+    fn pack(&self) -> Vec<u8>;
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError>;
+}
+
+impl PageItem for Order {
+    const LEN: usize = Order::LEN;
+
+    fn pack(&self) -> Vec<u8> {
+        Order::pack(self)
+    }
+
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        Order::unpack(data)
+    }
+}
+
+// Define one page of a chunked query response
+//
+// Queries whose response can outgrow the return data take a `cursor`
+// counting the items already returned and answer with one page of at most
+// `MAX_ITEMS` items. `next_cursor` is the cursor to pass to the next call, or
+// `None` once the last item has been returned.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Page<T> {
+    pub next_cursor: Option<u32>,
+    pub items: Vec<T>,
+}
+
+// Pages of the ExportBook and GetOpenOrders queries
+pub type OrderPage = Page<Order>;
+
+impl<T: PageItem> Page<T> {
+    // Number of items that fit in the return data after the cursor
+    pub const MAX_ITEMS: usize = (MAX_RETURN_DATA - 4) / T::LEN;
+
+    // Take the page of `items` starting at `cursor`
+    pub fn new(items: impl Iterator<Item = T>, cursor: u32) -> Self {
+        let mut items = items.skip(cursor as usize);
+        let page: Vec<T> = items.by_ref().take(Self::MAX_ITEMS).collect();
+        let end = cursor as usize + page.len();
+        Page {
+            next_cursor: items.next().map(|_| end as u32),
+            items: page,
+        }
+    }
+
+    pub fn has_more(&self) -> bool {
+        self.next_cursor.is_some()
+    }
+
+    // Pack the page into a byte array, with `u32::MAX` marking the last page
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4 + self.items.len() * T::LEN);
+        buf.extend_from_slice(&self.next_cursor.unwrap_or(u32::MAX).to_le_bytes());
+        for item in &self.items {
+            buf.extend_from_slice(&item.pack());
+        }
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < 4 || !(data.len() - 4).is_multiple_of(T::LEN) {
+            msg!("Invalid query page length");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(Page {
+            next_cursor: unpack_cursor(data),
+            items: data[4..]
+                .chunks(T::LEN)
+                .map(T::unpack)
+                .collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+}
+
+// Read the leading cursor of a chunked response
+fn unpack_cursor(data: &[u8]) -> Option<u32> {
+    match u32::from_le_bytes(*array_ref![data, 0, 4]) {
+        u32::MAX => None,
+        cursor => Some(cursor),
+    }
+}
+
+// Define one page of the GetDepth query response: price levels of each
+// side, best first, read from the order book's price level index
+//
+// The cursor counts the levels of each side already returned, so both sides
+// are paged together; `next_cursor` is `None` once neither side has more.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MarketDepth {
+    pub next_cursor: Option<u32>,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+impl MarketDepth {
+    // Number of levels of each side that fit in the return data
+    pub const MAX_LEVELS: usize = (MAX_RETURN_DATA - 5) / (2 * PriceLevel::LEN);
+
+    pub fn has_more(&self) -> bool {
+        self.next_cursor.is_some()
+    }
+
+    // Pack the depth into a byte array, the cursor followed by the bids
+    // prefixed by their count
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(5 + (self.bids.len() + self.asks.len()) * PriceLevel::LEN);
+        buf.extend_from_slice(&self.next_cursor.unwrap_or(u32::MAX).to_le_bytes());
+        buf.push(self.bids.len() as u8);
+        for level in self.bids.iter().chain(self.asks.iter()) {
+            buf.extend_from_slice(&level.pack());
+        }
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < 5
+            || !(data.len() - 5).is_multiple_of(PriceLevel::LEN)
+            || (data.len() - 5) / PriceLevel::LEN < data[4] as usize
+        {
+            msg!("Invalid GetDepth response length");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut levels = data[5..]
+            .chunks(PriceLevel::LEN)
+            .map(PriceLevel::unpack)
+            .collect::<Result<Vec<_>, _>>()?;
+        let asks = levels.split_off(data[4] as usize);
+        Ok(MarketDepth {
+            next_cursor: unpack_cursor(data),
+            bids: levels,
+            asks,
+        })
+    }
+}
+
+// Define the GetOrderStatuses query response, one entry per queried order
+// id in the order they were queried
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderStatusReport {
+    pub statuses: Vec<OrderStatusEntry>,
+}
+
+impl OrderStatusReport {
+    // Number of order ids one query may ask about
+    pub const MAX_ORDERS: usize = MAX_RETURN_DATA / OrderStatusEntry::LEN;
+
+    pub fn new(order_book: &OrderBook, order_ids: &[u64]) -> Self {
+        OrderStatusReport {
+            statuses: order_ids
+                .iter()
+                .map(|order_id| order_book.order_status(*order_id))
+                .collect(),
+        }
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.statuses.len() * OrderStatusEntry::LEN);
+        for entry in &self.statuses {
+            buf.extend_from_slice(&entry.pack());
+        }
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if !data.len().is_multiple_of(OrderStatusEntry::LEN) {
+            msg!("Invalid GetOrderStatuses response length");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(OrderStatusReport {
+            statuses: data
+                .chunks(OrderStatusEntry::LEN)
+                .map(OrderStatusEntry::unpack)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+// Define the VerifyMarket query response
+//
+// `violations` is a bitmask of the `VerifyMarketReport::*` invariant flags
+// that failed; zero means the book is consistent. `crossed` reports whether
+// the best bid is at or above the best ask, which matching on placement
+// should never leave behind. `seq_num` and `book_checksum` are the market's,
+// for replicas to check themselves against.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VerifyMarketReport {
+    pub buy_orders: u32,
+    pub sell_orders: u32,
+    pub crossed: bool,
+    pub violations: u32,
+    pub seq_num: u64,
+    pub book_checksum: u64,
+}
+
+impl VerifyMarketReport {
+    pub const LEN: usize = 29;
+
+    // An order is resting on the wrong side of the book
+    pub const SIDE_MISMATCH: u32 = 1 << 0;
+    // Orders on a side are not in placement order
+    pub const TIME_PRIORITY: u32 = 1 << 1;
+    // The best bid is at or above the best ask
+    pub const CROSSED: u32 = 1 << 2;
+
+    // Check the invariants of an order book, ignoring orders expired at `now`
+    // when looking for a crossed book
+    pub fn verify(order_book: &OrderBook, now: &Clock) -> Self {
+        let mut violations = 0;
+        let sides = [
+            (OrderType::Buy, &order_book.buy_orders),
+            (OrderType::Sell, &order_book.sell_orders),
+        ];
+        for (order_type, orders) in sides {
+            if orders.iter().any(|order| order.order_type != order_type) {
+                violations |= Self::SIDE_MISMATCH;
+            }
+            if orders.windows(2).any(|pair| pair[0].slot > pair[1].slot) {
+                violations |= Self::TIME_PRIORITY;
+            }
+        }
+        let crossed = match (
+            order_book.get_best_buy_order(now),
+            order_book.get_best_sell_order(now),
+        ) {
+            (Some(bid), Some(ask)) => bid.price >= ask.price,
+            _ => false,
+        };
+        if crossed {
+            violations |= Self::CROSSED;
+        }
+        VerifyMarketReport {
+            buy_orders: order_book.buy_orders.len() as u32,
+            sell_orders: order_book.sell_orders.len() as u32,
+            crossed,
+            violations,
+            seq_num: order_book.seq_num,
+            book_checksum: order_book.book_checksum,
+        }
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.buy_orders.to_le_bytes());
+        buf.extend_from_slice(&self.sell_orders.to_le_bytes());
+        buf.push(self.crossed as u8);
+        buf.extend_from_slice(&self.violations.to_le_bytes());
+        buf.extend_from_slice(&self.seq_num.to_le_bytes());
+        buf.extend_from_slice(&self.book_checksum.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("VerifyMarket report too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(VerifyMarketReport {
+            buy_orders: u32::from_le_bytes(*array_ref![data, 0, 4]),
+            sell_orders: u32::from_le_bytes(*array_ref![data, 4, 4]),
+            crossed: data[8] != 0,
+            violations: u32::from_le_bytes(*array_ref![data, 9, 4]),
+            seq_num: u64::from_le_bytes(*array_ref![data, 13, 8]),
+            book_checksum: u64::from_le_bytes(*array_ref![data, 21, 8]),
+        })
+    }
+}
+
+// Define the GetMarketStats query response
+//
+// `unique_traders` counts the OpenOrders accounts that placed an order on the
+// market during `epoch`, the current epoch. `filled_lifetimes` and
+// `canceled_lifetimes` are the market's order lifetime histograms, bucketed
+// by `OrderBook::LIFETIME_BUCKET_BOUNDS`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MarketStats {
+    pub buy_open_interest: u64,
+    pub sell_open_interest: u64,
+    pub epoch: Epoch,
+    pub unique_traders: u32,
+    pub filled_lifetimes: [u32; OrderBook::LIFETIME_BUCKETS],
+    pub canceled_lifetimes: [u32; OrderBook::LIFETIME_BUCKETS],
+}
+
+impl MarketStats {
+    pub const LEN: usize = 28 + 2 * OrderBook::LIFETIME_BUCKETS * 4;
+
+    pub fn new(order_book: &OrderBook, epoch: Epoch) -> Self {
+        MarketStats {
+            buy_open_interest: order_book.buy_open_interest,
+            sell_open_interest: order_book.sell_open_interest,
+            epoch,
+            unique_traders: if order_book.stats_epoch == epoch {
+                order_book.unique_traders
+            } else {
+                0
+            },
+            filled_lifetimes: order_book.filled_lifetimes,
+            canceled_lifetimes: order_book.canceled_lifetimes,
+        }
+    }
+
+    // Orders that left the book filled per ten thousand that left it, the
+    // inverse of the market's quote-to-trade ratio
+    pub fn fill_rate_bps(&self) -> u64 {
+        let filled: u64 = self
+            .filled_lifetimes
+            .iter()
+            .map(|&count| count as u64)
+            .sum();
+        let canceled: u64 = self
+            .canceled_lifetimes
+            .iter()
+            .map(|&count| count as u64)
+            .sum();
+        (filled * 10_000)
+            .checked_div(filled + canceled)
+            .unwrap_or(0)
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.buy_open_interest.to_le_bytes());
+        buf.extend_from_slice(&self.sell_open_interest.to_le_bytes());
+        buf.extend_from_slice(&self.epoch.to_le_bytes());
+        buf.extend_from_slice(&self.unique_traders.to_le_bytes());
+        for count in self.filled_lifetimes.iter().chain(&self.canceled_lifetimes) {
+            buf.extend_from_slice(&count.to_le_bytes());
+        }
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("GetMarketStats response too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        let u32_at = |offset: usize| u32::from_le_bytes(*array_ref![data, offset, 4]);
+        Ok(MarketStats {
+            buy_open_interest: u64_at(0),
+            sell_open_interest: u64_at(8),
+            epoch: u64_at(16),
+            unique_traders: u32_at(24),
+            filled_lifetimes: std::array::from_fn(|i| u32_at(28 + i * 4)),
+            canceled_lifetimes: std::array::from_fn(|i| {
+                u32_at(28 + (OrderBook::LIFETIME_BUCKETS + i) * 4)
+            }),
+        })
+    }
+}
+
+// Define the GetInventory query response, an OpenOrders account's exposure
+// on one market
+//
+// The bid and ask totals sum the owner's orders on each side, resting or
+// waiting off the book, with `bid_quote` and `ask_quote` their value at
+// their limit prices. The balances are the account's record for the
+// market: locked backing the orders, free awaiting SettleFunds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InventoryReport {
+    pub owner: Pubkey,
+    pub bid_count: u32,
+    pub bid_base: u64,
+    pub bid_quote: u64,
+    pub ask_count: u32,
+    pub ask_base: u64,
+    pub ask_quote: u64,
+    pub base_locked: u64,
+    pub quote_locked: u64,
+    pub base_free: u64,
+    pub quote_free: u64,
+}
+
+impl InventoryReport {
+    pub const LEN: usize = 104;
+
+    pub fn new(
+        order_book: &OrderBook,
+        market: &Pubkey,
+        open_orders: &OpenOrders,
+    ) -> Result<Self, ProgramError> {
+        let balances = open_orders.market(market).copied().unwrap_or_default();
+        let mut report = InventoryReport {
+            owner: open_orders.owner,
+            base_locked: balances.base_locked,
+            quote_locked: balances.quote_locked,
+            base_free: balances.base_free,
+            quote_free: balances.quote_free,
+            ..InventoryReport::default()
+        };
+        let orders = order_book
+            .buy_orders
+            .iter()
+            .chain(&order_book.sell_orders)
+            .chain(&order_book.stop_orders)
+            .chain(&order_book.pending_orders)
+            .chain(&order_book.reference_orders)
+            .filter(|order| order.trader == open_orders.owner);
+        for order in orders {
+            let quote = order_book.quote_amount(order.price, order.amount)?;
+            let (count, base, total_quote) = match order.order_type {
+                OrderType::Buy => (
+                    &mut report.bid_count,
+                    &mut report.bid_base,
+                    &mut report.bid_quote,
+                ),
+                OrderType::Sell => (
+                    &mut report.ask_count,
+                    &mut report.ask_base,
+                    &mut report.ask_quote,
+                ),
+            };
+            *count += 1;
+            *base = base
+                .checked_add(order.amount)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            *total_quote = total_quote
+                .checked_add(quote)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
+        Ok(report)
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(self.owner.as_ref());
+        buf.extend_from_slice(&self.bid_count.to_le_bytes());
+        buf.extend_from_slice(&self.bid_base.to_le_bytes());
+        buf.extend_from_slice(&self.bid_quote.to_le_bytes());
+        buf.extend_from_slice(&self.ask_count.to_le_bytes());
+        buf.extend_from_slice(&self.ask_base.to_le_bytes());
+        buf.extend_from_slice(&self.ask_quote.to_le_bytes());
+        buf.extend_from_slice(&self.base_locked.to_le_bytes());
+        buf.extend_from_slice(&self.quote_locked.to_le_bytes());
+        buf.extend_from_slice(&self.base_free.to_le_bytes());
+        buf.extend_from_slice(&self.quote_free.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("GetInventory response too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let u32_at = |offset: usize| u32::from_le_bytes(*array_ref![data, offset, 4]);
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        Ok(InventoryReport {
+            owner: Pubkey::new_from_array(*array_ref![data, 0, 32]),
+            bid_count: u32_at(32),
+            bid_base: u64_at(36),
+            bid_quote: u64_at(44),
+            ask_count: u32_at(52),
+            ask_base: u64_at(56),
+            ask_quote: u64_at(64),
+            base_locked: u64_at(72),
+            quote_locked: u64_at(80),
+            base_free: u64_at(88),
+            quote_free: u64_at(96),
+        })
+    }
+}
+
+// Define the outcome of a PlaceOrder, set as its return data so clients can
+// simulate an order to preview its fills
+//
+// `filled_quote` is the quote value of the fills before fees and
+// `taker_fee` the fee the taker pays on them. `resting_amount` is what is left
+// of the order on the book or waiting off it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FillPreview {
+    pub order_id: u64,
+    pub fill_count: u32,
+    pub filled_amount: u64,
+    pub filled_quote: u64,
+    pub taker_fee: u64,
+    pub resting_amount: u64,
+}
+
+impl FillPreview {
+    pub const LEN: usize = 44;
+
+    pub fn new(
+        order_book: &OrderBook,
+        order: &Order,
+        fills: &[FillEvent],
+    ) -> Result<Self, ProgramError> {
+        let mut preview = FillPreview {
+            order_id: order.order_id,
+            fill_count: fills.len() as u32,
+            resting_amount: order.amount,
+            ..FillPreview::default()
+        };
+        for fill in fills {
+            preview.filled_amount += fill.amount;
+            preview.filled_quote += order_book.quote_amount(fill.price, fill.amount)?;
+            preview.taker_fee += fill.taker_fee;
+        }
+        Ok(preview)
+    }
+
+    // Average fill price in quote units per lot, or 0 without fills
+    pub fn average_price(&self, lot_size: u64) -> u64 {
+        if self.filled_amount == 0 {
+            return 0;
+        }
+        (self.filled_quote as u128 * lot_size as u128 / self.filled_amount as u128) as u64
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.order_id.to_le_bytes());
+        buf.extend_from_slice(&self.fill_count.to_le_bytes());
+        buf.extend_from_slice(&self.filled_amount.to_le_bytes());
+        buf.extend_from_slice(&self.filled_quote.to_le_bytes());
+        buf.extend_from_slice(&self.taker_fee.to_le_bytes());
+        buf.extend_from_slice(&self.resting_amount.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("PlaceOrder return data too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        Ok(FillPreview {
+            order_id: u64_at(0),
+            fill_count: u32::from_le_bytes(*array_ref![data, 8, 4]),
+            filled_amount: u64_at(12),
+            filled_quote: u64_at(20),
+            taker_fee: u64_at(28),
+            resting_amount: u64_at(36),
+        })
+    }
+}
+
+// Define the GetVersion query response
+//
+// `major`, `minor` and `patch` are the semver of the deployed program and
+// `latest_tag` the highest instruction tag it understands. The layout
+// versions change whenever the corresponding account layout does, and
+// `features` is a bitmask of the `ProgramVersion::*` feature flags the
+// program was built with.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProgramVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+    pub latest_tag: u8,
+    pub order_book_layout: u8,
+    pub open_orders_layout: u8,
+    pub price_oracle_layout: u8,
+    pub features: u32,
+}
+
+impl ProgramVersion {
+    pub const LEN: usize = 14;
+
+    // Built with serde support for the account and instruction types
+    pub const FEATURE_SERDE: u32 = 1 << 0;
+    // Built with per-instruction compute unit logs
+    pub const FEATURE_METRICS: u32 = 1 << 1;
+
+    // Version of this program build
+    pub fn current() -> Self {
+        let semver = |part: &str| part.parse().unwrap_or_default();
+        let mut features = 0;
+        if cfg!(feature = "serde") {
+            features |= Self::FEATURE_SERDE;
+        }
+        if cfg!(feature = "metrics") {
+            features |= Self::FEATURE_METRICS;
+        }
+        ProgramVersion {
+            major: semver(env!("CARGO_PKG_VERSION_MAJOR")),
+            minor: semver(env!("CARGO_PKG_VERSION_MINOR")),
+            patch: semver(env!("CARGO_PKG_VERSION_PATCH")),
+            latest_tag: OrderBookInstruction::LATEST_TAG,
+            order_book_layout: OrderBook::LAYOUT_VERSION,
+            open_orders_layout: OpenOrders::LAYOUT_VERSION,
+            price_oracle_layout: PriceOracle::VERSION,
+            features,
+        }
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.major.to_le_bytes());
+        buf.extend_from_slice(&self.minor.to_le_bytes());
+        buf.extend_from_slice(&self.patch.to_le_bytes());
+        buf.push(self.latest_tag);
+        buf.push(self.order_book_layout);
+        buf.push(self.open_orders_layout);
+        buf.push(self.price_oracle_layout);
+        buf.extend_from_slice(&self.features.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("GetVersion response too short");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let u16_at = |offset: usize| u16::from_le_bytes(*array_ref![data, offset, 2]);
+        Ok(ProgramVersion {
+            major: u16_at(0),
+            minor: u16_at(2),
+            patch: u16_at(4),
+            latest_tag: data[6],
+            order_book_layout: data[7],
+            open_orders_layout: data[8],
+            price_oracle_layout: data[9],
+            features: u32::from_le_bytes(*array_ref![data, 10, 4]),
+        })
+    }
+}
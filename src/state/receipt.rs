@@ -0,0 +1,107 @@
+// The FillReceipt PDA an owner may keep for each of its completed orders
+
+use crate::state::{ClosedOrder, OrderType};
+use arrayref::array_ref;
+use solana_program::{clock::UnixTimestamp, msg, program_error::ProgramError, pubkey::Pubkey};
+
+// Define the FillReceipt PDA of a completed order, an on-chain record of what
+// it traded that outlives the transaction logs
+//
+// The owner writes it with WriteFillReceipt while the order book still
+// remembers the order among its closed orders, paying its rent, and closes
+// it with CloseFillReceipt to recover the rent. `average_price` is in quote
+// atoms per lot and `fees_paid` the taker fees the order paid, in quote
+// atoms. `recorded_at` is when the receipt was written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FillReceipt {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub order_id: u64,
+    pub side: OrderType,
+    pub filled_amount: u64,
+    pub filled_quote: u64,
+    pub average_price: u64,
+    pub fees_paid: u64,
+    pub recorded_at: UnixTimestamp,
+}
+
+impl FillReceipt {
+    pub const LEN: usize = 113;
+    pub const SEED: &'static [u8] = b"fill_receipt";
+
+    // Address of the FillReceipt PDA for an order of a market
+    pub fn find_address(program_id: &Pubkey, order_book: &Pubkey, order_id: u64) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[Self::SEED, order_book.as_ref(), &order_id.to_le_bytes()],
+            program_id,
+        )
+    }
+
+    // Receipt of an order that closed filled on a market with `lot_size`
+    pub fn new(
+        market: Pubkey,
+        closed: &ClosedOrder,
+        lot_size: u64,
+        recorded_at: UnixTimestamp,
+    ) -> Self {
+        let average_price = match closed.filled_amount {
+            0 => 0,
+            filled_amount => {
+                (closed.filled_quote as u128 * lot_size as u128 / filled_amount as u128) as u64
+            }
+        };
+        FillReceipt {
+            market,
+            owner: closed.trader,
+            order_id: closed.order_id,
+            side: closed.side,
+            filled_amount: closed.filled_amount,
+            filled_quote: closed.filled_quote,
+            average_price,
+            fees_paid: closed.fees_paid,
+            recorded_at,
+        }
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.market.to_bytes());
+        buf.extend_from_slice(&self.owner.to_bytes());
+        buf.extend_from_slice(&self.order_id.to_le_bytes());
+        buf.push(self.side as u8);
+        buf.extend_from_slice(&self.filled_amount.to_le_bytes());
+        buf.extend_from_slice(&self.filled_quote.to_le_bytes());
+        buf.extend_from_slice(&self.average_price.to_le_bytes());
+        buf.extend_from_slice(&self.fees_paid.to_le_bytes());
+        buf.extend_from_slice(&self.recorded_at.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            msg!("Fill receipt account is too small");
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(*array_ref![data, offset, 8]);
+        let side = match data[72] {
+            0 => OrderType::Buy,
+            1 => OrderType::Sell,
+            _ => {
+                msg!("Fill receipt holds an invalid side");
+                return Err(ProgramError::InvalidAccountData);
+            }
+        };
+        Ok(FillReceipt {
+            market: Pubkey::new_from_array(*array_ref![data, 0, 32]),
+            owner: Pubkey::new_from_array(*array_ref![data, 32, 32]),
+            order_id: u64_at(64),
+            side,
+            filled_amount: u64_at(73),
+            filled_quote: u64_at(81),
+            average_price: u64_at(89),
+            fees_paid: u64_at(97),
+            recorded_at: u64_at(105) as UnixTimestamp,
+        })
+    }
+}